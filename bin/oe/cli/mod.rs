@@ -258,6 +258,10 @@ usage! {
             "--identity=[NAME]",
             "Specify your node's name.",
 
+            ARG arg_release_track: (Option<String>) = None, or |c: &Config| c.parity.as_ref()?.release_track.clone(),
+            "--release-track=[TRACK]",
+            "Override the release track this node reports in its version string and accepts updates from. TRACK can be one of: stable, beta, nightly or a custom track name.",
+
             ARG arg_base_path: (Option<String>) = None, or |c: &Config| c.parity.as_ref()?.base_path.clone(),
             "-d, --base-path=[PATH]",
             "Specify the base data storage path.",
@@ -318,6 +322,10 @@ usage! {
             "--reserved-only",
             "Connect only to reserved nodes.",
 
+            FLAG flag_observer_mode: (bool) = false, or |c: &Config| c.network.as_ref()?.observer_mode,
+            "--observer-mode",
+            "Follow the chain and serve requests without ever propagating transactions, blocks or consensus messages. Sealing blocks is refused.",
+
             FLAG flag_no_ancient_blocks: (bool) = false, or |_| None,
             "--no-ancient-blocks",
             "Disable downloading old blocks after snapshot restoration or warp sync. Not recommended.",
@@ -326,6 +334,10 @@ usage! {
             "--warp-barrier=[NUM]",
             "When warp enabled never attempt regular sync before warping to block NUM.",
 
+            ARG arg_serve_from_block: (Option<u64>) = None, or |c: &Config| c.network.as_ref()?.serve_from_block,
+            "--serve-from-block=[NUM]",
+            "Serve block headers, bodies and receipts to peers only from block NUM on, e.g. when older blocks were pruned. The floor is advertised to OpenEthereum peers.",
+
             ARG arg_port: (u16) = 30303u16, or |c: &Config| c.network.as_ref()?.port,
             "--port=[PORT]",
             "Override the port on which the node should listen.",
@@ -334,6 +346,10 @@ usage! {
             "--interface=[IP]",
             "Network interfaces. Valid values are 'all', 'local' or the ip of the interface you want OpenEthereum to listen to.",
 
+            ARG arg_source_address: (Option<String>) = None, or |c: &Config| c.network.as_ref()?.source_address.clone(),
+            "--source-address=[IP]",
+            "Make outbound connections and discovery packets from the local address IP. Also advertised as the public address if NAT does not find one.",
+
             ARG arg_min_peers: (Option<u16>) = None, or |c: &Config| c.network.as_ref()?.min_peers,
             "--min-peers=[NUM]",
             "Try to maintain at least NUM peers.",
@@ -358,6 +374,22 @@ usage! {
             "--max-pending-peers=[NUM]",
             "Allow up to NUM pending connections.",
 
+            ARG arg_max_peer_share: (u32) = 100u32, or |c: &Config| c.network.as_ref()?.max_peer_share,
+            "--max-peer-share=[PERCENT]",
+            "Allow inbound peers of one IP prefix or one client to take at most PERCENT of the peer slots once --min-peers are connected. 100 turns the limit off.",
+
+            ARG arg_max_packet_captures: (u16) = 0u16, or |c: &Config| c.network.as_ref()?.max_packet_captures,
+            "--max-packet-captures=[NUM]",
+            "Allow up to NUM peers to have their packets captured to a file at the same time, see parity_startPeerCapture. 0 disables capturing.",
+
+            ARG arg_consensus_propagation: (String) = "all", or |c: &Config| c.network.as_ref()?.consensus_propagation.clone(),
+            "--consensus-propagation=[PEERS]",
+            "Peers proposed blocks and consensus messages are sent to. Must be one of: all - a selection of all peers; validators - only the nodes of the current validators given with --validator-nodes.",
+
+            ARG arg_validator_nodes: (Option<String>) = None, or |c: &Config| c.network.as_ref()?.validator_nodes.as_ref().map(|vec| vec.join(",")),
+            "--validator-nodes=[NODES]",
+            "Nodes of the validators, for --consensus-propagation=validators. NODES should be comma-delimited ADDRESS=ENODE pairs of a validator address and the enode of its node.",
+
             ARG arg_network_id: (Option<u64>) = None, or |c: &Config| c.network.as_ref()?.id,
             "--network-id=[INDEX]",
             "Override the network identifier from the chain we are on.",
@@ -366,6 +398,10 @@ usage! {
             "--bootnodes=[NODES]",
             "Override the bootnodes from our chain. NODES should be comma-delimited enodes.",
 
+            ARG arg_dns_discovery: (Option<String>) = None, or |c: &Config| c.network.as_ref()?.dns_discovery.as_ref().map(|vec| vec.join(",")),
+            "--dns-discovery=[URLS]",
+            "Discover nodes from EIP-1459 node trees in addition to the discovery protocol. URLS should be comma-delimited enrtree://KEY@DOMAIN URLs.",
+
             ARG arg_node_key: (Option<String>) = None, or |c: &Config| c.network.as_ref()?.node_key.clone(),
             "--node-key=[KEY]",
             "Specify node secret key, either as 64-character hex string or input to SHA3 operation.",
@@ -487,6 +523,10 @@ usage! {
             "--metrics-interface=[IP]",
             "Specify the hostname portion of the metrics server, IP should be an interface's IP address, or all (all interfaces) or local.",
 
+            ARG arg_metrics_profiler_interval: (Option<u64>) = None, or |c: &Config| c.metrics.as_ref()?.profiler_interval,
+            "--metrics-profiler-interval=[MS]",
+            "Sample the stacks of the IO and verifier threads every MS milliseconds. The samples of the last minute are served as folded stacks for flamegraph tools at /profile of the metrics server, the functions found running most often are logged on every request.",
+
         ["API and Console Options – IPC"]
             FLAG flag_no_ipc: (bool) = false, or |c: &Config| c.ipc.as_ref()?.disable,
             "--no-ipc",
@@ -722,6 +762,10 @@ usage! {
             "--new-transactions-stats-period=[N]",
             "Specify number of blocks for which new transactions will be returned in a result of `parity_newTransactionsStats` RPC call. Setting this parameter to 0 will return only transactions imported during the current block. (default: 0)",
 
+            ARG arg_new_transactions_stats_retention: (Option<u64>) = None, or |c: &Config| c.mining.as_ref()?.new_transactions_stats_retention,
+            "--new-transactions-stats-retention=[SECS]",
+            "Specify number of seconds for which new transactions will be returned in a result of `parity_newTransactionsStats` RPC call. Overrides --new-transactions-stats-period.",
+
         ["Internal Options"]
             FLAG flag_can_restart: (bool) = false, or |_| None,
             "--can-restart",
@@ -736,6 +780,10 @@ usage! {
             "--json-logging",
             "Use json format in output.",
 
+            FLAG flag_async_logging: (bool) = false, or |c: &Config| c.misc.as_ref()?.async_logging,
+            "--async-logging",
+            "Write log lines on a dedicated thread. Lines are dropped while its queue is full.",
+
             FLAG flag_version: (bool) = false, or |_| None,
             "-v, --version",
             "Show information about version.",
@@ -851,6 +899,7 @@ struct Operating {
     db_path: Option<String>,
     keys_path: Option<String>,
     identity: Option<String>,
+    release_track: Option<String>,
     no_persistent_txqueue: Option<bool>,
 }
 
@@ -875,20 +924,28 @@ struct Ui {
 struct Network {
     warp: Option<bool>,
     warp_barrier: Option<u64>,
+    serve_from_block: Option<u64>,
     port: Option<u16>,
     interface: Option<String>,
+    source_address: Option<String>,
     min_peers: Option<u16>,
     max_peers: Option<u16>,
     snapshot_peers: Option<u16>,
     max_pending_peers: Option<u16>,
+    max_peer_share: Option<u32>,
+    max_packet_captures: Option<u16>,
+    consensus_propagation: Option<String>,
+    validator_nodes: Option<Vec<String>>,
     nat: Option<String>,
     allow_ips: Option<String>,
     id: Option<u64>,
     bootnodes: Option<Vec<String>>,
+    dns_discovery: Option<Vec<String>>,
     discovery: Option<bool>,
     node_key: Option<String>,
     reserved_peers: Option<String>,
     reserved_only: Option<bool>,
+    observer_mode: Option<bool>,
 }
 
 #[derive(Default, Debug, PartialEq, Deserialize)]
@@ -937,6 +994,7 @@ struct Metrics {
     prefix: Option<String>,
     port: Option<u16>,
     interface: Option<String>,
+    profiler_interval: Option<u64>,
 }
 
 #[derive(Default, Debug, PartialEq, Deserialize)]
@@ -999,6 +1057,7 @@ struct Mining {
     infinite_pending_block: Option<bool>,
     max_round_blocks_to_import: Option<usize>,
     new_transactions_stats_period: Option<u64>,
+    new_transactions_stats_retention: Option<u64>,
 }
 
 #[derive(Default, Debug, PartialEq, Deserialize)]
@@ -1044,6 +1103,7 @@ struct Misc {
     ports_shift: Option<u16>,
     unsafe_expose: Option<bool>,
     json: Option<bool>,
+    async_logging: Option<bool>,
 }
 
 #[cfg(test)]
@@ -1283,6 +1343,7 @@ mod tests {
                 arg_db_path: Some("$HOME/.parity/chains".into()),
                 arg_keys_path: "$HOME/.parity/keys".into(),
                 arg_identity: "".into(),
+                arg_release_track: None,
                 flag_no_persistent_txqueue: false,
 
                 // -- Convenience Options
@@ -1303,20 +1364,28 @@ mod tests {
                 flag_no_warp: false,
                 arg_port: 30303u16,
                 arg_interface: "all".into(),
+                arg_source_address: None,
                 arg_min_peers: Some(25u16),
                 arg_max_peers: Some(50u16),
                 arg_max_pending_peers: 64u16,
+                arg_max_peer_share: 100u32,
+                arg_max_packet_captures: 0u16,
+                arg_consensus_propagation: "all".into(),
+                arg_validator_nodes: Some("".into()),
                 arg_snapshot_peers: 0u16,
                 arg_allow_ips: "all".into(),
                 arg_nat: "any".into(),
                 arg_network_id: Some(1),
                 arg_bootnodes: Some("".into()),
+                arg_dns_discovery: None,
                 flag_no_discovery: false,
                 arg_node_key: None,
                 arg_reserved_peers: Some("./path_to_file".into()),
                 flag_reserved_only: false,
+                flag_observer_mode: false,
                 flag_no_ancient_blocks: false,
                 arg_warp_barrier: None,
+                arg_serve_from_block: None,
 
                 // -- API and Console Options
                 // RPC
@@ -1355,6 +1424,7 @@ mod tests {
                 arg_metrics_prefix: "".into(),
                 arg_metrics_port: 3000u16,
                 arg_metrics_interface: "local".into(),
+                arg_metrics_profiler_interval: None,
 
                 // SECRETSTORE
                 flag_no_secretstore: false,
@@ -1409,6 +1479,7 @@ mod tests {
                 flag_infinite_pending_block: false,
                 arg_max_round_blocks_to_import: 1usize,
                 arg_new_transactions_stats_period: 0u64,
+                arg_new_transactions_stats_retention: None,
 
                 flag_stratum: false,
                 arg_stratum_interface: "local".to_owned(),
@@ -1454,7 +1525,8 @@ mod tests {
                 arg_log_file: Some("/var/log/openethereum.log".into()),
                 flag_no_color: false,
                 flag_no_config: false,
-                flag_json_logging: false
+                flag_json_logging: false,
+                flag_async_logging: false,
             }
         );
     }
@@ -1495,6 +1567,7 @@ mod tests {
                     db_path: None,
                     keys_path: None,
                     identity: None,
+                    release_track: None,
                     no_persistent_txqueue: None,
                 }),
                 account: Some(Account {
@@ -1508,20 +1581,28 @@ mod tests {
                 network: Some(Network {
                     warp: Some(false),
                     warp_barrier: None,
+                    serve_from_block: None,
                     port: None,
                     interface: None,
+                    source_address: None,
                     min_peers: Some(10),
                     max_peers: Some(20),
                     max_pending_peers: Some(30),
+                    max_peer_share: None,
+                    max_packet_captures: None,
+                    consensus_propagation: None,
+                    validator_nodes: None,
                     snapshot_peers: Some(40),
                     allow_ips: Some("public".into()),
                     nat: Some("any".into()),
                     id: None,
                     bootnodes: None,
+                    dns_discovery: None,
                     discovery: Some(true),
                     node_key: None,
                     reserved_peers: Some("./path/to/reserved_peers".into()),
                     reserved_only: Some(true),
+                    observer_mode: None,
                 }),
                 websockets: Some(Ws {
                     disable: Some(true),
@@ -1558,6 +1639,7 @@ mod tests {
                     prefix: Some("oe".to_string()),
                     interface: Some("local".to_string()),
                     port: Some(4000),
+                    profiler_interval: None,
                 }),
                 secretstore: Some(SecretStore {
                     disable: None,
@@ -1614,6 +1696,7 @@ mod tests {
                     infinite_pending_block: None,
                     max_round_blocks_to_import: None,
                     new_transactions_stats_period: None,
+                    new_transactions_stats_retention: None,
                 }),
                 footprint: Some(Footprint {
                     tracing: Some("on".into()),
@@ -1641,7 +1724,8 @@ mod tests {
                     color: Some(true),
                     ports_shift: Some(0),
                     unsafe_expose: Some(false),
-                    json: None
+                    json: None,
+                    async_logging: None,
                 }),
                 stratum: None,
             }
@@ -23,7 +23,7 @@ use crate::{
     hash::keccak,
     metrics::MetricsConfiguration,
     miner::pool,
-    sync::{self, validate_node_url, NetworkConfiguration},
+    sync::{self, validate_node_url, ConsensusPropagation, NetworkConfiguration},
 };
 use ansi_term::Colour;
 
@@ -34,15 +34,15 @@ use ethcore::{
     snapshot::SnapshotConfiguration,
     verification::queue::VerifierSettings,
 };
-use ethereum_types::{Address, H256, U256};
+use ethereum_types::{Address, H256, H512, U256};
 
-use parity_version::{version_data, version_short};
+use parity_version::{client_version, version_data, ReleaseTrack};
 use std::{
     cmp,
-    collections::{BTreeMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     io::Read,
     iter::FromIterator,
-    net::{SocketAddr, ToSocketAddrs},
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
     num::NonZeroU32,
     path::PathBuf,
     time::Duration,
@@ -417,6 +417,7 @@ impl Configuration {
                 vm_type,
                 warp_sync,
                 warp_barrier: self.args.arg_warp_barrier,
+                serve_from_block: self.args.arg_serve_from_block,
                 experimental_rpcs,
                 net_settings: self.network_settings()?,
                 secretstore_conf,
@@ -424,7 +425,14 @@ impl Configuration {
                 custom_bootnodes: self.args.arg_bootnodes.is_some(),
                 check_seal: !self.args.flag_no_seal_check,
                 download_old_blocks: !self.args.flag_no_ancient_blocks,
+                consensus_propagation: self.consensus_propagation()?,
+                validator_nodes: self.validator_nodes()?,
+                observer_mode: self.args.flag_observer_mode,
                 new_transactions_stats_period: self.args.arg_new_transactions_stats_period,
+                new_transactions_stats_retention: self
+                    .args
+                    .arg_new_transactions_stats_retention
+                    .map(Duration::from_secs),
                 verifier_settings,
                 no_persistent_txqueue: self.args.flag_no_persistent_txqueue,
                 max_round_blocks_to_import: self.args.arg_max_round_blocks_to_import,
@@ -491,6 +499,15 @@ impl Configuration {
         }
     }
 
+    /// Release track overriding the compiled one, if configured.
+    pub fn release_track(&self) -> Result<Option<ReleaseTrack>, String> {
+        self.args
+            .arg_release_track
+            .as_ref()
+            .map(|track| track.parse())
+            .transpose()
+    }
+
     /// returns logger config
     pub fn logger_config(&self) -> LogConfig {
         LogConfig {
@@ -502,6 +519,7 @@ impl Configuration {
                 .as_ref()
                 .map(|log_file| replace_home(&self.directories().base, log_file)),
             json: self.args.flag_json_logging,
+            async_writer: self.args.flag_async_logging,
         }
     }
 
@@ -536,6 +554,78 @@ impl Configuration {
         self.args.arg_max_pending_peers as u32
     }
 
+    fn max_peer_share(&self) -> Result<u32, String> {
+        match self.args.arg_max_peer_share {
+            share @ 1..=100 => Ok(share),
+            share => Err(format!(
+                "Invalid max peer share: {share}, must be a percentage between 1 and 100"
+            )),
+        }
+    }
+
+    fn source_address(&self) -> Result<Option<SocketAddr>, String> {
+        self.args
+            .arg_source_address
+            .as_ref()
+            .map(|ip| {
+                ip.parse::<IpAddr>()
+                    .map(|ip| SocketAddr::new(ip, 0))
+                    .map_err(|_| format!("Invalid source address: {ip}"))
+            })
+            .transpose()
+    }
+
+    fn dns_discovery_urls(&self) -> Result<Vec<String>, String> {
+        match self.args.arg_dns_discovery {
+            Some(ref urls) if !urls.is_empty() => urls
+                .split(',')
+                .map(|url| match url.starts_with("enrtree://") {
+                    true => Ok(url.to_owned()),
+                    false => Err(format!("Invalid DNS discovery URL: {url}")),
+                })
+                .collect(),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn max_packet_captures(&self) -> usize {
+        self.args.arg_max_packet_captures as usize
+    }
+
+    fn consensus_propagation(&self) -> Result<ConsensusPropagation, String> {
+        match self.args.arg_consensus_propagation.as_str() {
+            "all" => Ok(ConsensusPropagation::All),
+            "validators" => Ok(ConsensusPropagation::Validators),
+            other => Err(format!("Invalid consensus propagation: {other}")),
+        }
+    }
+
+    fn validator_nodes(&self) -> Result<HashMap<Address, H512>, String> {
+        let nodes = match self.args.arg_validator_nodes {
+            Some(ref nodes) if !nodes.is_empty() => nodes,
+            _ => return Ok(HashMap::new()),
+        };
+        nodes
+            .split(',')
+            .map(|pair| {
+                let (address, enode) = pair.split_once('=').ok_or_else(|| {
+                    format!("Invalid validator node, expected ADDRESS=ENODE: {pair}")
+                })?;
+                let address = to_address(Some(address.to_owned()))?;
+                if validate_node_url(enode).is_some() {
+                    return Err(format!("Invalid enode of validator {address:?}: {enode}"));
+                }
+                let node_id = enode
+                    .trim_start_matches("enode://")
+                    .split('@')
+                    .next()
+                    .and_then(|id| id.parse().ok())
+                    .ok_or_else(|| format!("Invalid enode of validator {address:?}: {enode}"))?;
+                Ok((address, node_id))
+            })
+            .collect()
+    }
+
     fn snapshot_peers(&self) -> u32 {
         self.args.arg_snapshot_peers as u32
     }
@@ -609,6 +699,8 @@ impl Configuration {
             tx_queue_strategy: to_queue_strategy(&self.args.arg_tx_queue_strategy)?,
             tx_queue_no_unfamiliar_locals: self.args.flag_tx_queue_no_unfamiliar_locals,
             refuse_service_transactions: self.args.flag_refuse_service_transactions,
+            // blocks sealed in observer mode would never be propagated
+            refuse_sealing: self.args.flag_observer_mode,
 
             pool_limits: self.pool_limits()?,
             pool_verification_options: self.pool_verification_options()?,
@@ -808,9 +900,11 @@ impl Configuration {
         let mut ret = NetworkConfiguration::new();
         ret.nat_enabled = self.args.arg_nat == "any" || self.args.arg_nat == "upnp";
         ret.boot_nodes = to_bootnodes(&self.args.arg_bootnodes)?;
+        ret.dns_discovery_urls = self.dns_discovery_urls()?;
         let (listen, public) = self.net_addresses()?;
         ret.listen_address = Some(format!("{listen}"));
         ret.public_address = public.map(|p| format!("{p}"));
+        ret.outbound_source_address = self.source_address()?;
         ret.use_secret = match self.args.arg_node_key.as_ref().map(|s| {
             s.parse::<Secret>()
                 .or_else(|_| Secret::import_key(keccak(s).as_bytes()))
@@ -826,20 +920,16 @@ impl Configuration {
         ret.snapshot_peers = self.snapshot_peers();
         ret.ip_filter = self.ip_filter()?;
         ret.max_pending_peers = self.max_pending_peers();
+        ret.max_peer_share = self.max_peer_share()?;
+        ret.max_packet_captures = self.max_packet_captures();
         let mut net_path = PathBuf::from(self.directories().base);
         net_path.push("network");
         ret.config_path = Some(net_path.to_str().unwrap().to_owned());
         ret.reserved_nodes = self.init_reserved_nodes()?;
         ret.allow_non_reserved = !self.args.flag_reserved_only;
-        ret.client_version = {
-            let mut client_version = version_short();
-            if !self.args.arg_identity.is_empty() {
-                // Insert name after the "Parity-Ethereum/" at the beginning of version string.
-                let idx = client_version.find('/').unwrap_or(client_version.len());
-                client_version.insert_str(idx, &format!("/{}", self.args.arg_identity));
-            }
-            client_version
-        };
+        ret.client_version = client_version(
+            Some(self.args.arg_identity.as_str()).filter(|identity| !identity.is_empty()),
+        );
         Ok(ret)
     }
 
@@ -964,6 +1054,10 @@ impl Configuration {
             prefix: self.metrics_prefix(),
             interface: self.metrics_interface(),
             port: self.args.arg_ports_shift + self.args.arg_metrics_port,
+            profiler_interval: self
+                .args
+                .arg_metrics_profiler_interval
+                .map(Duration::from_millis),
         };
         Ok(conf)
     }
@@ -1501,6 +1595,7 @@ mod tests {
                     mode: None,
                     file: None,
                     json: false,
+                    async_writer: false,
                 },
             )
         );
@@ -1538,6 +1633,7 @@ mod tests {
                 mode: None,
                 file: None,
                 json: false,
+                async_writer: false,
             },
             miner_options: Default::default(),
             gas_price_percentile: 50,
@@ -1549,6 +1645,7 @@ mod tests {
             network_id: None,
             warp_sync: true,
             warp_barrier: None,
+            serve_from_block: None,
             acc_conf: Default::default(),
             gas_pricer_conf: Default::default(),
             miner_extras: Default::default(),
@@ -1566,7 +1663,11 @@ mod tests {
             stratum: None,
             check_seal: true,
             download_old_blocks: true,
+            consensus_propagation: ConsensusPropagation::All,
+            validator_nodes: HashMap::new(),
+            observer_mode: false,
             new_transactions_stats_period: 0,
+            new_transactions_stats_retention: None,
             verifier_settings: Default::default(),
             no_persistent_txqueue: false,
             max_round_blocks_to_import: 1,
@@ -1606,6 +1707,95 @@ mod tests {
         assert!(conf.miner_options().is_err());
     }
 
+    #[test]
+    fn should_parse_consensus_propagation() {
+        let conf = parse(&["openethereum", "--consensus-propagation", "validators"]);
+        assert_eq!(
+            conf.consensus_propagation(),
+            Ok(ConsensusPropagation::Validators)
+        );
+
+        let conf = parse(&["openethereum", "--consensus-propagation", "some"]);
+        assert!(conf.consensus_propagation().is_err());
+    }
+
+    #[test]
+    fn should_parse_dns_discovery_urls() {
+        let conf = parse(&["openethereum"]);
+        assert!(conf.net_config().unwrap().dns_discovery_urls.is_empty());
+
+        let urls = "enrtree://AKA3AM6LPBYEUDMVNU3BSVQJ5AD45Y7YPOHJLEF6W26QOE4VTUDPE@all.mainnet.ethdisco.net,enrtree://AKA3AM6LPBYEUDMVNU3BSVQJ5AD45Y7YPOHJLEF6W26QOE4VTUDPE@les.mainnet.ethdisco.net";
+        let conf = parse(&["openethereum", "--dns-discovery", urls]);
+        assert_eq!(
+            conf.net_config().unwrap().dns_discovery_urls,
+            urls.split(',').map(Into::into).collect::<Vec<String>>()
+        );
+
+        let conf = parse(&[
+            "openethereum",
+            "--dns-discovery",
+            "all.mainnet.ethdisco.net",
+        ]);
+        assert!(conf.net_config().is_err());
+    }
+
+    #[test]
+    fn should_parse_source_address() {
+        let conf = parse(&["openethereum"]);
+        assert_eq!(conf.net_config().unwrap().outbound_source_address, None);
+
+        let conf = parse(&["openethereum", "--source-address", "10.0.0.2"]);
+        assert_eq!(
+            conf.net_config().unwrap().outbound_source_address,
+            Some("10.0.0.2:0".parse().unwrap())
+        );
+
+        let conf = parse(&["openethereum", "--source-address", "10.0.0.2:30303"]);
+        assert!(conf.net_config().is_err());
+    }
+
+    #[test]
+    fn should_parse_max_peer_share() {
+        let conf = parse(&["openethereum"]);
+        assert_eq!(conf.net_config().unwrap().max_peer_share, 100);
+
+        let conf = parse(&["openethereum", "--max-peer-share", "40"]);
+        assert_eq!(conf.net_config().unwrap().max_peer_share, 40);
+
+        let conf = parse(&["openethereum", "--max-peer-share", "0"]);
+        assert!(conf.net_config().is_err());
+    }
+
+    #[test]
+    fn should_parse_validator_nodes() {
+        let enode = "enode://6f8a80d14311c39f35f516fa664deaaaa13e85b2f7493f37f6144d86991ec012937307647bd3b9a82abe2974e1407241d54947bbb39763a4cac9f77166ad92a0@127.0.0.1:30303";
+        let nodes = format!("0x00000000000000000000000000000000000000aa={enode}");
+        let conf = parse(&["openethereum", "--validator-nodes", &nodes]);
+        let expected: H512 = "6f8a80d14311c39f35f516fa664deaaaa13e85b2f7493f37f6144d86991ec012937307647bd3b9a82abe2974e1407241d54947bbb39763a4cac9f77166ad92a0".parse().unwrap();
+        assert_eq!(
+            conf.validator_nodes(),
+            Ok(vec![(Address::from_low_u64_be(0xaa), expected)]
+                .into_iter()
+                .collect())
+        );
+
+        let conf = parse(&["openethereum", "--validator-nodes", enode]);
+        assert!(conf.validator_nodes().is_err());
+    }
+
+    #[test]
+    fn observer_mode_refuses_sealing() {
+        let conf = parse(&["openethereum"]);
+        assert!(!conf.miner_options().unwrap().refuse_sealing);
+
+        let conf = parse(&["openethereum", "--observer-mode"]);
+        assert!(conf.miner_options().unwrap().refuse_sealing);
+        match conf.into_command().unwrap().cmd {
+            Cmd::Run(c) => assert!(c.observer_mode),
+            _ => panic!("Should be Cmd::Run"),
+        }
+    }
+
     #[test]
     fn should_parse_network_settings() {
         // given
@@ -1816,6 +2006,16 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_release_track_arg() {
+        let conf = Configuration::parse_cli(&["openethereum", "--release-track", "beta"]).unwrap();
+        assert_eq!(conf.release_track(), Ok(Some(ReleaseTrack::Beta)));
+        let conf = Configuration::parse_cli(&["openethereum"]).unwrap();
+        assert_eq!(conf.release_track(), Ok(None));
+        let conf = Configuration::parse_cli(&["openethereum", "--release-track", "a/b"]).unwrap();
+        assert!(conf.release_track().is_err());
+    }
+
     #[test]
     fn should_apply_ports_shift() {
         // given
@@ -223,19 +223,27 @@ pub fn default_network_config() -> crate::sync::NetworkConfiguration {
         net_config_path: None,
         listen_address: Some("0.0.0.0:30303".into()),
         public_address: None,
+        bind_interface: None,
+        outbound_source_address: None,
         udp_port: None,
         nat_enabled: true,
         discovery_enabled: true,
         boot_nodes: Vec::new(),
+        dns_discovery_urls: Vec::new(),
         use_secret: None,
         max_peers: 50,
         min_peers: 25,
+        max_peer_share: 100,
         snapshot_peers: 0,
         max_pending_peers: 64,
         ip_filter: IpFilter::default(),
         reserved_nodes: Vec::new(),
         allow_non_reserved: true,
         client_version: ::parity_version::version_short(),
+        peer_ban_duration: 24 * 60 * 60,
+        instance_label: String::new(),
+        reachability_probe_interval: None,
+        max_packet_captures: 0,
     }
 }
 
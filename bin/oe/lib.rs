@@ -237,5 +237,9 @@ fn execute(command: Execute, logger: Arc<RotatingLogger>) -> Result<ExecutionAct
 /// On error, returns what to print on stderr.
 // FIXME: totally independent logging capability, see https://github.com/openethereum/openethereum/issues/10252
 pub fn start(conf: Configuration, logger: Arc<RotatingLogger>) -> Result<ExecutionAction, String> {
+    if let Some(track) = conf.release_track()? {
+        parity_version::set_release_track(track)
+            .map_err(|track| format!("Release track {track} must be set before it is used"))?;
+    }
     execute(conf.into_command()?, logger)
 }
@@ -0,0 +1,303 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Writes log lines on a dedicated thread, in the order they were logged.
+
+use crate::rotating::RotatingLogger;
+use parking_lot::{Mutex, RwLock};
+use rlog::Level;
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+/// Number of lines written before the outputs are flushed.
+const MAX_BATCH: usize = 64;
+
+/// A formatted log line.
+pub struct Line {
+    /// Level of the logged record.
+    pub level: Level,
+    /// Line for the console, possibly coloured.
+    pub console: String,
+    /// Line without colour codes for the file and the in-memory logs.
+    pub plain: String,
+}
+
+/// Destinations of the log lines.
+pub struct Outputs {
+    /// Console output.
+    pub console: Box<dyn Write + Send>,
+    /// Log file, if any.
+    pub file: Option<Box<dyn Write + Send>>,
+    /// In-memory logs.
+    pub logger: Arc<RotatingLogger>,
+    /// Duplicate INFO and more severe lines to stdout.
+    pub duplicate_to_stdout: bool,
+}
+
+impl Outputs {
+    // Returns true if the line must be flushed right away.
+    fn write(&mut self, line: Line) -> bool {
+        // ignore errors - there's nothing we can do
+        if let Some(file) = self.file.as_mut() {
+            let _ = file.write_all(line.plain.as_bytes());
+            let _ = file.write_all(b"\n");
+        }
+        if self.duplicate_to_stdout && line.level <= Level::Info {
+            println!("{}", line.console);
+        }
+        let _ = self.console.write_all(line.console.as_bytes());
+        let _ = self.console.write_all(b"\n");
+        self.logger.append(line.plain);
+        line.level <= Level::Warn
+    }
+
+    fn flush(&mut self) {
+        if let Some(file) = self.file.as_mut() {
+            let _ = file.flush();
+        }
+        let _ = self.console.flush();
+    }
+}
+
+/// Queues log lines for a writer thread. Lines are dropped while the queue is full.
+pub struct AsyncWriter {
+    sender: RwLock<Option<SyncSender<Line>>>,
+    dropped: AtomicUsize,
+    thread: Mutex<Option<WriterThread>>,
+}
+
+/// The writer thread, not spawned until `start` so that it is not lost on `fork`.
+enum WriterThread {
+    Pending(Receiver<Line>, Outputs),
+    Running(JoinHandle<()>),
+}
+
+impl AsyncWriter {
+    /// Create the writer with a queue of `capacity` lines. The lines are queued until `start`.
+    pub fn new(capacity: usize, outputs: Outputs) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        AsyncWriter {
+            sender: RwLock::new(Some(sender)),
+            dropped: AtomicUsize::new(0),
+            thread: Mutex::new(Some(WriterThread::Pending(receiver, outputs))),
+        }
+    }
+
+    /// Spawn the writer thread, if not spawned yet.
+    pub fn start(&self) {
+        let mut thread = self.thread.lock();
+        if let Some(WriterThread::Pending(receiver, outputs)) = thread.take() {
+            let handle = thread::Builder::new()
+                .name("log-writer".into())
+                .spawn(move || run(receiver, outputs))
+                .expect("Error creating log writer thread");
+            *thread = Some(WriterThread::Running(handle));
+        }
+    }
+
+    /// Queue a line. After `shutdown` the line is written to stderr directly.
+    pub fn write(&self, line: Line) {
+        match self.sender.read().as_ref() {
+            Some(sender) => match sender.try_send(line) {
+                Ok(()) => (),
+                Err(TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(TrySendError::Disconnected(line)) => eprintln!("{}", line.console),
+            },
+            None => eprintln!("{}", line.console),
+        }
+    }
+
+    /// Number of lines dropped because the queue was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Write all queued lines and stop the writer thread. The lines of a writer which was never
+    /// started are written on the calling thread.
+    pub fn shutdown(&self) {
+        self.sender.write().take();
+        match self.thread.lock().take() {
+            Some(WriterThread::Running(handle)) => {
+                let _ = handle.join();
+            }
+            Some(WriterThread::Pending(receiver, outputs)) => run(receiver, outputs),
+            None => (),
+        }
+    }
+}
+
+fn run(receiver: Receiver<Line>, mut outputs: Outputs) {
+    while let Ok(line) = receiver.recv() {
+        let mut urgent = outputs.write(line);
+        let mut written = 1;
+        while !urgent && written < MAX_BATCH {
+            match receiver.try_recv() {
+                Ok(line) => urgent = outputs.write(line),
+                Err(_) => break,
+            }
+            written += 1;
+        }
+        outputs.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AsyncWriter, Line, Outputs};
+    use crate::rotating::RotatingLogger;
+    use parking_lot::Mutex;
+    use rlog::Level;
+    use std::{collections::HashMap, io, sync::Arc, thread, time::Duration};
+
+    #[derive(Clone, Default)]
+    struct Capture {
+        data: Arc<Mutex<Vec<u8>>>,
+        delay: Option<Duration>,
+    }
+
+    impl io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if let Some(delay) = self.delay {
+                thread::sleep(delay);
+            }
+            // write in small pieces so that unsynchronised writers would interleave
+            let len = buf.len().min(7);
+            self.data.lock().extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn writer(capacity: usize, console: Capture) -> (AsyncWriter, Arc<RotatingLogger>) {
+        let logger = Arc::new(RotatingLogger::new("test".into()));
+        let outputs = Outputs {
+            console: Box::new(console),
+            file: None,
+            logger: logger.clone(),
+            duplicate_to_stdout: false,
+        };
+        let writer = AsyncWriter::new(capacity, outputs);
+        writer.start();
+        (writer, logger)
+    }
+
+    fn hammer(writer: &Arc<AsyncWriter>, threads: usize, lines: usize) {
+        let handles: Vec<_> = (0..threads)
+            .map(|t| {
+                let writer = writer.clone();
+                thread::spawn(move || {
+                    for i in 0..lines {
+                        let text = format!("thread {t} line {i} {}", "x".repeat(40));
+                        writer.write(Line {
+                            level: Level::Trace,
+                            console: text.clone(),
+                            plain: text,
+                        });
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn lines_of_many_threads_are_not_torn() {
+        let console = Capture::default();
+        let (writer, logger) = writer(100_000, console.clone());
+        let writer = Arc::new(writer);
+
+        hammer(&writer, 8, 1000);
+        writer.shutdown();
+
+        let output = String::from_utf8(console.data.lock().clone()).unwrap();
+        let mut next_line = HashMap::new();
+        for line in output.lines() {
+            let parts: Vec<_> = line.split(' ').collect();
+            assert_eq!(parts.len(), 5, "torn line: {line}");
+            assert_eq!(parts[4], "x".repeat(40), "torn line: {line}");
+            let thread: usize = parts[1].parse().unwrap();
+            let number: usize = parts[3].parse().unwrap();
+            let expected = next_line.entry(thread).or_insert(0);
+            assert_eq!(number, *expected, "out of order: {line}");
+            *expected += 1;
+        }
+        assert_eq!(next_line.len(), 8);
+        assert!(next_line.values().all(|&n| n == 1000));
+        assert_eq!(writer.dropped(), 0);
+        assert_eq!(logger.logs()[0], output.lines().last().unwrap().to_string());
+    }
+
+    #[test]
+    fn overflowing_lines_are_dropped_and_counted() {
+        let console = Capture {
+            delay: Some(Duration::from_millis(1)),
+            ..Default::default()
+        };
+        let (writer, _) = writer(1, console.clone());
+        let writer = Arc::new(writer);
+
+        hammer(&writer, 8, 100);
+        writer.shutdown();
+
+        let written = String::from_utf8(console.data.lock().clone())
+            .unwrap()
+            .lines()
+            .count();
+        assert!(writer.dropped() > 0);
+        assert_eq!(written + writer.dropped(), 800);
+    }
+
+    #[test]
+    fn lines_queued_before_start_are_written() {
+        let console = Capture::default();
+        let logger = Arc::new(RotatingLogger::new("test".into()));
+        let writer = AsyncWriter::new(
+            10,
+            Outputs {
+                console: Box::new(console.clone()),
+                file: None,
+                logger,
+                duplicate_to_stdout: false,
+            },
+        );
+        let line = |text: &str| Line {
+            level: Level::Info,
+            console: text.into(),
+            plain: text.into(),
+        };
+        writer.write(line("before"));
+        assert!(console.data.lock().is_empty());
+
+        writer.start();
+        writer.write(line("after"));
+        writer.shutdown();
+        assert_eq!(console.data.lock().as_slice(), b"before\nafter\n");
+    }
+}
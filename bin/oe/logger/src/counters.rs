@@ -0,0 +1,322 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Number of log records by target and level, in total and over the last hour.
+
+use rlog::Level;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Targets counted separately, the records of further targets are counted as `OTHER`.
+const MAX_TARGETS: usize = 32;
+/// Key of the records of the targets beyond `MAX_TARGETS`.
+pub const OTHER: &str = "other";
+/// Minute buckets kept, covering the last hour.
+const BUCKETS: usize = 60;
+/// Number of log levels.
+const LEVELS: usize = 5;
+/// Minute of a bucket which was never written.
+const UNUSED: u64 = u64::MAX;
+
+/// Number of log records by level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LevelCounts {
+    pub error: u64,
+    pub warn: u64,
+    pub info: u64,
+    pub debug: u64,
+    pub trace: u64,
+}
+
+impl LevelCounts {
+    fn from_array(counts: [u64; LEVELS]) -> Self {
+        let [error, warn, info, debug, trace] = counts;
+        LevelCounts {
+            error,
+            warn,
+            info,
+            debug,
+            trace,
+        }
+    }
+
+    /// Number of records of `level`.
+    pub fn get(&self, level: Level) -> u64 {
+        match level {
+            Level::Error => self.error,
+            Level::Warn => self.warn,
+            Level::Info => self.info,
+            Level::Debug => self.debug,
+            Level::Trace => self.trace,
+        }
+    }
+
+    /// Number of records of all levels.
+    pub fn total(&self) -> u64 {
+        self.error + self.warn + self.info + self.debug + self.trace
+    }
+}
+
+/// Records of a single minute.
+struct Bucket {
+    minute: AtomicU64,
+    counts: [AtomicU64; LEVELS],
+}
+
+/// Records of a single target.
+struct Slot {
+    target: OnceLock<String>,
+    totals: [AtomicU64; LEVELS],
+    buckets: [Bucket; BUCKETS],
+}
+
+impl Slot {
+    fn new() -> Self {
+        Slot {
+            target: OnceLock::new(),
+            totals: Default::default(),
+            buckets: std::array::from_fn(|_| Bucket {
+                minute: AtomicU64::new(UNUSED),
+                counts: Default::default(),
+            }),
+        }
+    }
+
+    fn totals(&self) -> [u64; LEVELS] {
+        std::array::from_fn(|level| self.totals[level].load(Ordering::Relaxed))
+    }
+
+    fn counts(&self, now: u64, minutes: u64) -> [u64; LEVELS] {
+        let mut counts = [0; LEVELS];
+        for bucket in &self.buckets {
+            let minute = bucket.minute.load(Ordering::Relaxed);
+            if minute <= now && now - minute < minutes {
+                for (count, bucket_count) in counts.iter_mut().zip(&bucket.counts) {
+                    *count += bucket_count.load(Ordering::Relaxed);
+                }
+            }
+        }
+        counts
+    }
+}
+
+/// Counts log records by the first path segment of their target. Counting a record takes a few
+/// relaxed atomic operations, no locks. Records counted while their minute bucket is recycled
+/// may get lost.
+pub struct EventCounters {
+    /// `MAX_TARGETS` slots claimed by the targets in the order they log, followed by `OTHER`.
+    slots: Vec<Slot>,
+}
+
+impl EventCounters {
+    pub fn new() -> Self {
+        let slots: Vec<_> = (0..=MAX_TARGETS).map(|_| Slot::new()).collect();
+        slots[MAX_TARGETS].target.get_or_init(|| OTHER.to_owned());
+        EventCounters { slots }
+    }
+
+    /// Count a record of `target` and `level` at the current time.
+    pub fn record(&self, target: &str, level: Level) {
+        self.record_at(current_minute(), target, level);
+    }
+
+    /// Count a record of `target` and `level` logged in `minute`.
+    pub fn record_at(&self, minute: u64, target: &str, level: Level) {
+        let slot = self.slot(target);
+        let level = level as usize - 1;
+        slot.totals[level].fetch_add(1, Ordering::Relaxed);
+
+        let bucket = &slot.buckets[(minute % BUCKETS as u64) as usize];
+        let bucket_minute = bucket.minute.load(Ordering::Relaxed);
+        if bucket_minute != minute
+            && bucket
+                .minute
+                .compare_exchange(bucket_minute, minute, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            // the bucket held the counts of an hour ago
+            for count in &bucket.counts {
+                count.store(0, Ordering::Relaxed);
+            }
+        }
+        bucket.counts[level].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of records per target logged since the counters were created.
+    pub fn totals(&self) -> Vec<(String, LevelCounts)> {
+        self.collect(Slot::totals)
+    }
+
+    /// Number of records per target logged within `window` before `now`, a minute, up to an hour.
+    pub fn counts_at(&self, now: u64, window: Duration) -> Vec<(String, LevelCounts)> {
+        let minutes = window.as_secs().div_ceil(60).clamp(1, BUCKETS as u64);
+        self.collect(|slot| slot.counts(now, minutes))
+    }
+
+    /// Number of records per target logged within `window` before now.
+    pub fn counts(&self, window: Duration) -> Vec<(String, LevelCounts)> {
+        self.counts_at(current_minute(), window)
+    }
+
+    fn collect<F>(&self, counts: F) -> Vec<(String, LevelCounts)>
+    where
+        F: Fn(&Slot) -> [u64; LEVELS],
+    {
+        let mut result: Vec<_> = self
+            .slots
+            .iter()
+            .filter_map(|slot| {
+                let counts = LevelCounts::from_array(counts(slot));
+                match slot.target.get() {
+                    Some(target) if counts.total() > 0 => Some((target.clone(), counts)),
+                    _ => None,
+                }
+            })
+            .collect();
+        result.sort_by(|a, b| a.0.cmp(&b.0));
+        result
+    }
+
+    /// Slot of the first path segment of `target`. A target claims the first unclaimed slot,
+    /// racing targets settle on the same order of slots.
+    fn slot(&self, target: &str) -> &Slot {
+        let key = target.split("::").next().unwrap_or(target);
+        self.slots[..MAX_TARGETS]
+            .iter()
+            .find(|slot| slot.target.get_or_init(|| key.to_owned()) == key)
+            .unwrap_or(&self.slots[MAX_TARGETS])
+    }
+}
+
+impl Default for EventCounters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn current_minute() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 60
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    fn counts(error: u64, warn: u64, info: u64) -> LevelCounts {
+        LevelCounts {
+            error,
+            warn,
+            info,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn counts_records_by_target_and_level() {
+        let counters = EventCounters::new();
+        let start = 1_000;
+        counters.record_at(start, "sync", Level::Error);
+        counters.record_at(start, "sync::chain", Level::Warn);
+        counters.record_at(start + 1, "sync", Level::Warn);
+        counters.record_at(start + 1, "network", Level::Info);
+        counters.record_at(start + 30, "network", Level::Error);
+
+        assert_eq!(
+            counters.counts_at(start + 30, HOUR),
+            vec![
+                ("network".to_owned(), counts(1, 0, 1)),
+                ("sync".to_owned(), counts(1, 2, 0)),
+            ]
+        );
+        // the current minute only
+        assert_eq!(
+            counters.counts_at(start + 30, Duration::from_secs(1)),
+            vec![("network".to_owned(), counts(1, 0, 0))]
+        );
+        // partial minutes count as whole ones
+        assert_eq!(
+            counters.counts_at(start + 30, Duration::from_secs(29 * 60 + 1)),
+            vec![
+                ("network".to_owned(), counts(1, 0, 1)),
+                ("sync".to_owned(), counts(0, 1, 0)),
+            ]
+        );
+        assert_eq!(
+            counters.counts_at(start + 30, HOUR)[0].1.get(Level::Info),
+            1
+        );
+        assert_eq!(counters.totals(), counters.counts_at(start + 30, HOUR));
+    }
+
+    #[test]
+    fn forgets_records_older_than_an_hour() {
+        let counters = EventCounters::new();
+        let start = 1_000;
+        counters.record_at(start, "sync", Level::Error);
+        counters.record_at(start + 59, "sync", Level::Warn);
+
+        assert_eq!(
+            counters.counts_at(start + 59, HOUR),
+            vec![("sync".to_owned(), counts(1, 1, 0))]
+        );
+        assert_eq!(
+            counters.counts_at(start + 60, HOUR),
+            vec![("sync".to_owned(), counts(0, 1, 0))]
+        );
+
+        // the bucket of `start` is reused an hour later
+        counters.record_at(start + 60, "sync", Level::Info);
+        assert_eq!(
+            counters.counts_at(start + 60, HOUR),
+            vec![("sync".to_owned(), counts(0, 1, 1))]
+        );
+        assert_eq!(
+            counters.counts_at(start + 60, Duration::from_secs(60)),
+            vec![("sync".to_owned(), counts(0, 0, 1))]
+        );
+        assert!(counters.counts_at(start + 200, HOUR).is_empty());
+        assert_eq!(
+            counters.totals(),
+            vec![("sync".to_owned(), counts(1, 1, 1))]
+        );
+    }
+
+    #[test]
+    fn caps_the_number_of_targets() {
+        let counters = EventCounters::new();
+        for i in 0..MAX_TARGETS + 2 {
+            counters.record_at(0, &format!("target{i}::module"), Level::Error);
+        }
+        counters.record_at(0, "target0", Level::Warn);
+
+        let totals = counters.totals();
+        assert_eq!(totals.len(), MAX_TARGETS + 1);
+        let total = |target: &str| totals.iter().find(|(t, _)| t == target).unwrap().1;
+        assert_eq!(total(OTHER), counts(2, 0, 0));
+        assert_eq!(total("target0"), counts(1, 1, 0));
+    }
+}
@@ -28,20 +28,26 @@ extern crate time;
 #[macro_use]
 extern crate lazy_static;
 
+mod async_writer;
+mod counters;
 mod rotating;
 
 use ansi_term::Colour;
+use async_writer::{AsyncWriter, Line, Outputs};
 use chrono::SecondsFormat;
+use counters::EventCounters;
 use env_logger::{Builder as LogBuilder, Formatter};
 use parking_lot::Mutex;
 use regex::Regex;
 use std::{
     env, fs,
-    io::{IsTerminal, Write},
+    io::{self, IsTerminal, Write},
     sync::{Arc, Weak},
     thread,
+    time::Duration,
 };
 
+pub use counters::{LevelCounts, OTHER as OTHER_EVENT_TARGET};
 pub use rotating::{init_log, RotatingLogger};
 
 #[derive(Debug, PartialEq, Clone)]
@@ -50,6 +56,9 @@ pub struct Config {
     pub color: bool,
     pub file: Option<String>,
     pub json: bool,
+    /// Write the log lines on a dedicated thread, dropping lines while its queue is full.
+    /// The thread is spawned by `start_log_writer`.
+    pub async_writer: bool,
 }
 
 impl Default for Config {
@@ -59,12 +68,18 @@ impl Default for Config {
             color: true,
             file: None,
             json: true,
+            async_writer: false,
         }
     }
 }
 
+/// Number of log lines the asynchronous writer queues before dropping lines.
+const ASYNC_QUEUE_SIZE: usize = 16 * 1024;
+
 lazy_static! {
     static ref ROTATING_LOGGER: Mutex<Weak<RotatingLogger>> = Mutex::new(Default::default());
+    static ref ASYNC_WRITER: Mutex<Option<Arc<AsyncWriter>>> = Mutex::new(None);
+    static ref EVENT_COUNTERS: EventCounters = EventCounters::new();
 }
 
 /// Escapes multiline message string for json output, e.g. call stacks
@@ -105,7 +120,7 @@ pub fn setup_log(config: &Config) -> Result<Arc<RotatingLogger>, String> {
     let logger = logs.clone();
     let mut open_options = fs::OpenOptions::new();
 
-    let maybe_file = match config.file.as_ref() {
+    let mut maybe_file = match config.file.as_ref() {
         Some(f) => Some(
             open_options
                 .append(true)
@@ -116,7 +131,26 @@ pub fn setup_log(config: &Config) -> Result<Arc<RotatingLogger>, String> {
         None => None,
     };
 
+    let duplicate_to_stdout = !isatty && std::io::stdout().is_terminal();
+    let writer = match config.async_writer {
+        true => Some(Arc::new(AsyncWriter::new(
+            ASYNC_QUEUE_SIZE,
+            Outputs {
+                console: Box::new(io::BufWriter::new(io::stderr())),
+                file: maybe_file
+                    .take()
+                    .map(|file| Box::new(io::BufWriter::new(file)) as Box<dyn Write + Send>),
+                logger: logs.clone(),
+                duplicate_to_stdout,
+            },
+        ))),
+        false => None,
+    };
+    let async_writer = writer.clone();
+
     let format = move |buf: &mut Formatter, record: &Record| {
+        EVENT_COUNTERS.record(record.target(), record.level());
+
         let with_color = if max_level() <= LevelFilter::Info && !enable_json {
             let utc_time = chrono::Utc::now();
             let timestamp = utc_time.format("%Y-%m-%d %H:%M:%S %Z").to_string();
@@ -164,13 +198,22 @@ pub fn setup_log(config: &Config) -> Result<Arc<RotatingLogger>, String> {
             false => removed_color.clone(),
         };
 
+        if let Some(writer) = async_writer.as_ref() {
+            writer.write(Line {
+                level: record.level(),
+                console: ret,
+                plain: removed_color,
+            });
+            return Ok(());
+        }
+
         if let Some(mut file) = maybe_file.as_ref() {
             // ignore errors - there's nothing we can do
             let _ = file.write_all(removed_color.as_bytes());
             let _ = file.write_all(b"\n");
         }
         logger.append(removed_color);
-        if !isatty && record.level() <= Level::Info && std::io::stdout().is_terminal() {
+        if duplicate_to_stdout && record.level() <= Level::Info {
             // duplicate INFO/WARN output to console
             println!("{ret}");
         }
@@ -179,19 +222,59 @@ pub fn setup_log(config: &Config) -> Result<Arc<RotatingLogger>, String> {
     };
 
     builder.format(format);
-    builder
-        .try_init()
-        .map(|_| {
+    match builder.try_init() {
+        Ok(()) => {
             *ROTATING_LOGGER.lock() = Arc::downgrade(&logs);
-            logs
-        })
+            *ASYNC_WRITER.lock() = writer;
+            Ok(logs)
+        }
         // couldn't create new logger - try to fall back on previous logger.
-        .or_else(|err| {
+        Err(err) => {
+            if let Some(writer) = writer {
+                writer.shutdown();
+            }
             ROTATING_LOGGER
                 .lock()
                 .upgrade()
                 .ok_or_else(|| format!("{err:?}"))
-        })
+        }
+    }
+}
+
+/// Starts the thread of the asynchronous writer, the log lines are queued until then. Call it
+/// after forking, threads do not survive `fork`.
+pub fn start_log_writer() {
+    if let Some(writer) = ASYNC_WRITER.lock().as_ref() {
+        writer.start();
+    }
+}
+
+/// Writes the log lines queued by the asynchronous writer and stops it. Lines logged afterwards
+/// are written to stderr directly.
+pub fn shutdown_log() {
+    let writer = ASYNC_WRITER.lock().take();
+    if let Some(writer) = writer {
+        writer.shutdown();
+    }
+}
+
+/// Number of log lines dropped by the asynchronous writer because its queue was full.
+pub fn dropped_log_lines() -> usize {
+    ASYNC_WRITER
+        .lock()
+        .as_ref()
+        .map_or(0, |writer| writer.dropped())
+}
+
+/// Number of log records per top-level target and level logged within `window`, up to the last
+/// hour. Targets beyond the first few dozen are counted as `OTHER_EVENT_TARGET`.
+pub fn event_counts(window: Duration) -> Vec<(String, LevelCounts)> {
+    EVENT_COUNTERS.counts(window)
+}
+
+/// Number of log records per top-level target and level logged since the start.
+pub fn event_totals() -> Vec<(String, LevelCounts)> {
+    EVENT_COUNTERS.totals()
 }
 
 fn kill_color(s: &str) -> String {
@@ -43,7 +43,7 @@ use std::{
 use ansi_term::Colour;
 use ctrlc::CtrlC;
 use daemonize::Daemonize;
-use ethcore_logger::setup_log;
+use ethcore_logger::{setup_log, shutdown_log, start_log_writer};
 use fdlimit::raise_fd_limit;
 use openethereum::{start, ExecutionAction};
 use parking_lot::{Condvar, Mutex};
@@ -84,12 +84,15 @@ fn main() -> Result<(), i32> {
                     "{}",
                     Colour::Red.paint(format!("Daemonization failed: {e}"))
                 );
+                shutdown_log();
                 return Err(1);
             }
         }
     } else {
         None
     };
+    // the log writer thread is started after forking, threads do not survive `fork`
+    start_log_writer();
 
     // increase max number of open files
     let _ = raise_fd_limit();
@@ -156,15 +159,18 @@ fn main() -> Result<(), i32> {
                 client.shutdown();
 
                 if lock.panicking {
+                    shutdown_log();
                     return Err(1);
                 }
             }
         },
         Err(err) => {
             eprintln!("{err}");
+            shutdown_log();
             return Err(1);
         }
     };
 
+    shutdown_log();
     Ok(())
 }
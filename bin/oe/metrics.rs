@@ -3,12 +3,19 @@ use hyper::{
     service::{make_service_fn, service_fn},
     Body, Method, Request, Response, Server, StatusCode,
 };
-use parking_lot::Mutex;
 use stats::{
+    profiler::{Profiler, ProfilerConfig},
     prometheus::{self, Encoder},
-    PrometheusMetrics, PrometheusRegistry,
+    Metrics, PrometheusMetrics, PrometheusRegistry,
 };
-use std::{convert::Infallible, sync::Arc, time::Instant};
+use std::{
+    convert::Infallible,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Number of functions logged on every request of the profile.
+const PROFILE_TOP_FUNCTIONS: usize = 20;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct MetricsConfiguration {
@@ -20,6 +27,8 @@ pub struct MetricsConfiguration {
     pub interface: String,
     /// The network port (default is 3000).
     pub port: u16,
+    /// Sampling interval of the profiler served at /profile, not sampled if `None`.
+    pub profiler_interval: Option<Duration>,
 }
 
 impl Default for MetricsConfiguration {
@@ -29,41 +38,63 @@ impl Default for MetricsConfiguration {
             prefix: "".into(),
             interface: "127.0.0.1".into(),
             port: 3000,
+            profiler_interval: None,
         }
     }
 }
 
-struct State {
-    rpc_apis: Arc<rpc_apis::FullDependencies>,
+/// Errors and warnings logged by each top-level log target, and the dropped log lines.
+struct LogEvents;
+
+impl PrometheusMetrics for LogEvents {
+    fn prometheus_metrics(&self, r: &mut PrometheusRegistry) {
+        let totals = ethcore_logger::event_totals();
+        let count = |level: fn(&ethcore_logger::LevelCounts) -> u64| {
+            totals
+                .iter()
+                .map(|(target, counts)| (target.as_str(), level(counts) as i64))
+                .collect::<Vec<_>>()
+        };
+        let _ = r.register_labeled_counter(
+            "log_errors",
+            "Number of errors logged, by target",
+            "target",
+            &count(|counts| counts.error),
+        );
+        let _ = r.register_labeled_counter(
+            "log_warnings",
+            "Number of warnings logged, by target",
+            "target",
+            &count(|counts| counts.warn),
+        );
+        let _ = r.register_counter(
+            "log_dropped_lines",
+            "Number of log lines dropped while the queue of the log writer was full",
+            ethcore_logger::dropped_log_lines() as i64,
+        );
+    }
 }
 
 async fn handle_request(
     req: Request<Body>,
-    conf: Arc<MetricsConfiguration>,
-    state: Arc<Mutex<State>>,
+    metrics: Metrics,
+    profiler: Option<Arc<Profiler>>,
 ) -> Result<Response<Body>, Infallible> {
     let (parts, _body) = req.into_parts();
 
     match (parts.method, parts.uri.path()) {
         (Method::GET, "/metrics") => {
             let start = Instant::now();
-            let mut reg = PrometheusRegistry::new(conf.prefix.clone());
-
-            let state = state.lock();
-
-            state.rpc_apis.client.prometheus_metrics(&mut reg);
-            state.rpc_apis.sync.prometheus_metrics(&mut reg);
-
-            let elapsed = start.elapsed();
-            reg.register_gauge(
-                "metrics_time",
-                "Time to perform rpc metrics",
-                elapsed.as_millis() as i64,
-            );
+            let metric_families = metrics.gather_with(|reg| {
+                let _ = reg.register_gauge(
+                    "metrics_time",
+                    "Time to perform rpc metrics",
+                    start.elapsed().as_millis() as i64,
+                );
+            });
 
             let mut buffer = vec![];
             let encoder = prometheus::TextEncoder::new();
-            let metric_families = reg.registry().gather();
             encoder
                 .encode(&metric_families, &mut buffer)
                 .expect("all source of metrics are static; qed");
@@ -71,6 +102,11 @@ async fn handle_request(
             let text = String::from_utf8(buffer).expect("metrics encoding is ASCII; qed");
             Ok(Response::new(Body::from(text)))
         }
+        (Method::GET, "/profile") if profiler.is_some() => {
+            let profiler = profiler.expect("checked by the match guard; qed");
+            profiler.log_top_functions(PROFILE_TOP_FUNCTIONS);
+            Ok(Response::new(Body::from(profiler.folded())))
+        }
         (_, _) => {
             let mut res = Response::new(Body::from("not found"));
             *res.status_mut() = StatusCode::NOT_FOUND;
@@ -83,13 +119,36 @@ async fn handle_request(
 pub fn start_prometheus_metrics(
     conf: &MetricsConfiguration,
     deps: &rpc::Dependencies<rpc_apis::FullDependencies>,
+    stratum: Option<Arc<dyn PrometheusMetrics + Send + Sync>>,
 ) -> Result<(), String> {
     if !conf.enabled {
         return Ok(());
     }
 
+    // Legacy metric sources are collected on every scrape.
+    let metrics = Metrics::new(conf.prefix.clone())
+        .map_err(|e| format!("Invalid metrics prefix: {e}"))?;
+    metrics.register_legacy(deps.apis.client.clone());
+    metrics.register_legacy(deps.apis.sync.clone());
+    if let Some(stratum) = stratum {
+        metrics.register_legacy(stratum);
+    }
+    metrics.register_legacy(Arc::new(LogEvents));
+
+    let profiler = match conf.profiler_interval {
+        Some(interval) => {
+            let config = ProfilerConfig {
+                interval,
+                ..Default::default()
+            };
+            let profiler =
+                Profiler::start(config).map_err(|e| format!("Failed to start profiler: {e}"))?;
+            Some(Arc::new(profiler))
+        }
+        None => None,
+    };
+
     let conf = conf.clone();
-    let apis = deps.apis.clone();
 
     // Spawn in a separate thread with its own tokio runtime
     std::thread::spawn(move || {
@@ -112,17 +171,13 @@ pub fn start_prometheus_metrics(
                 }
             };
 
-            let state = State { rpc_apis: apis };
-            let state = Arc::new(Mutex::new(state));
-            let conf = Arc::new(conf);
-
             let make_svc = make_service_fn(move |_conn| {
-                let state = state.clone();
-                let conf = conf.clone();
+                let metrics = metrics.clone();
+                let profiler = profiler.clone();
 
                 async move {
                     Ok::<_, Infallible>(service_fn(move |req| {
-                        handle_request(req, conf.clone(), state.clone())
+                        handle_request(req, metrics.clone(), profiler.clone())
                     }))
                 }
             });
@@ -17,16 +17,20 @@
 use std::sync::{mpsc, Arc};
 
 use crate::{
-    sync::{self, ConnectionFilter, NetworkConfiguration, Params, SyncConfig},
+    sync::{
+        self, ConnectionFilter, NetworkConfiguration, Params, SealedBlockRejectedHandler,
+        SyncConfig,
+    },
     types::BlockNumber,
 };
 use ethcore::{client::BlockChainClient, snapshot::SnapshotService};
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap};
 
 pub use crate::sync::{EthSync, ManageNetwork, SyncProvider};
 pub use ethcore::client::ChainNotify;
 use ethcore_logger::Config as LogConfig;
-use ethereum_types::H256;
+use ethereum_types::{Address, H256, H512};
+use parity_runtime::Executor;
 
 pub type SyncModules = (
     Arc<dyn SyncProvider>,
@@ -44,6 +48,9 @@ pub fn sync(
     snapshot_service: Arc<dyn SnapshotService>,
     _log_settings: &LogConfig,
     connection_filter: Option<Arc<dyn ConnectionFilter>>,
+    executor: Executor,
+    validator_nodes: HashMap<Address, H512>,
+    sealed_block_rejected: SealedBlockRejectedHandler,
 ) -> Result<SyncModules, sync::Error> {
     let eth_sync = EthSync::new(
         Params {
@@ -52,9 +59,12 @@ pub fn sync(
             forks,
             snapshot_service,
             network_config,
+            executor,
+            validator_nodes,
         },
         connection_filter,
     )?;
+    eth_sync.set_sealed_block_rejected_handler(Some(sealed_block_rejected));
 
     Ok((
         eth_sync.clone() as Arc<dyn SyncProvider>,
@@ -16,6 +16,7 @@
 
 use std::{
     any::Any,
+    collections::HashMap,
     str::FromStr,
     sync::{atomic, Arc, Weak},
     thread,
@@ -42,19 +43,23 @@ use crate::{
 use ansi_term::Colour;
 use dir::{DatabaseDirectories, Directories};
 use ethcore::{
-    client::{BlockChainClient, BlockInfo, Client, DatabaseCompactionProfile, Mode, VMType},
+    client::{
+        traits::ForceUpdateSealing, BlockChainClient, BlockInfo, Client, DatabaseCompactionProfile,
+        EngineClient, Mode, VMType,
+    },
     miner::{self, stratum, Miner, MinerOptions, MinerService},
     snapshot::{self, SnapshotConfiguration},
     verification::queue::VerifierSettings,
 };
 use ethcore_logger::{Config as LogConfig, RotatingLogger};
 use ethcore_service::ClientService;
-use ethereum_types::{H256, U64};
+use ethereum_types::{Address, H256, H512, U64};
 use journaldb::Algorithm;
 use node_filter::NodeFilter;
 use parity_rpc::{informant, is_major_importing, NetworkSettings};
 use parity_runtime::Runtime;
 use parity_version::version_short;
+use stats::PrometheusMetrics;
 
 // How often we attempt to take a snapshot: only snapshot on blocknumbers that are multiples of this.
 const SNAPSHOT_PERIOD: u64 = 20000;
@@ -85,6 +90,7 @@ pub struct RunCmd {
     pub network_id: Option<u64>,
     pub warp_sync: bool,
     pub warp_barrier: Option<u64>,
+    pub serve_from_block: Option<u64>,
     pub acc_conf: AccountsConfig,
     pub gas_pricer_conf: GasPricerConfig,
     pub miner_extras: MinerExtras,
@@ -103,7 +109,11 @@ pub struct RunCmd {
     pub check_seal: bool,
     pub allow_missing_blocks: bool,
     pub download_old_blocks: bool,
+    pub consensus_propagation: sync::ConsensusPropagation,
+    pub validator_nodes: HashMap<Address, H512>,
+    pub observer_mode: bool,
     pub new_transactions_stats_period: u64,
+    pub new_transactions_stats_retention: Option<Duration>,
     pub verifier_settings: VerifierSettings,
     pub no_persistent_txqueue: bool,
     pub max_round_blocks_to_import: usize,
@@ -250,8 +260,13 @@ pub fn execute(cmd: RunCmd, logger: Arc<RotatingLogger>) -> Result<RunningClient
         _ => sync::WarpSync::Disabled,
     };
     sync_config.download_old_blocks = cmd.download_old_blocks;
+    sync_config.serve_from_block = cmd.serve_from_block;
+    sync_config.consensus_propagation = cmd.consensus_propagation;
+    sync_config.observer_mode = cmd.observer_mode;
     sync_config.eip1559_transition = spec.params().eip1559_transition;
+    sync_config.max_block_difficulty = Some(spec.engine.maximum_block_difficulty());
     sync_config.new_transactions_stats_period = cmd.new_transactions_stats_period;
+    sync_config.new_transactions_stats_retention = cmd.new_transactions_stats_retention;
 
     let passwords = passwords_from_files(&cmd.acc_conf.password_files)?;
 
@@ -431,10 +446,33 @@ pub fn execute(cmd: RunCmd, logger: Arc<RotatingLogger>) -> Result<RunningClient
     let external_miner = Arc::new(ExternalMiner::default());
 
     // start stratum
-    if let Some(ref stratum_config) = cmd.stratum {
-        stratum::Stratum::register(stratum_config, miner.clone(), Arc::downgrade(&client))
-            .map_err(|e| format!("Stratum start error: {e:?}"))?;
-    }
+    let stratum: Option<Arc<dyn PrometheusMetrics + Send + Sync>> = match cmd.stratum {
+        Some(ref stratum_config) => Some(
+            stratum::Stratum::register(stratum_config, miner.clone(), Arc::downgrade(&client))
+                .map_err(|e| format!("Stratum start error: {e:?}"))?,
+        ),
+        None => None,
+    };
+
+    // A block sealed by this node was rejected, e.g. for its timestamp: drop the work built on
+    // it and seal again on the best block. Sync calls this with its lock held and sealing
+    // notifies sync of the new block, so the reseal runs on a thread of its own.
+    let sealed_block_rejected: sync::SealedBlockRejectedHandler = {
+        let client = Arc::downgrade(&client);
+        let miner = miner.clone();
+        Arc::new(move |_, _: &str| {
+            let (client, miner) = (client.clone(), miner.clone());
+            let spawned = thread::Builder::new().name("reseal".into()).spawn(move || {
+                if let Some(client) = client.upgrade() {
+                    miner.clear();
+                    client.update_sealing(ForceUpdateSealing::Yes);
+                }
+            });
+            if let Err(e) = spawned {
+                warn!("Error starting to seal again after a rejected block: {e:?}");
+            }
+        })
+    };
 
     // create sync object
     let (sync_provider, manage_network, chain_notify, priority_tasks, new_transaction_hashes) =
@@ -448,6 +486,9 @@ pub fn execute(cmd: RunCmd, logger: Arc<RotatingLogger>) -> Result<RunningClient
             connection_filter
                 .clone()
                 .map(|f| f as Arc<dyn crate::sync::ConnectionFilter + 'static>),
+            runtime.executor(),
+            cmd.validator_nodes.clone(),
+            sealed_block_rejected,
         )
         .map_err(|e| format!("Sync error: {e}"))?;
 
@@ -522,7 +563,7 @@ pub fn execute(cmd: RunCmd, logger: Arc<RotatingLogger>) -> Result<RunningClient
     let ipc_server = rpc::new_ipc(cmd.ipc_conf, &dependencies)?;
 
     // start the prometheus metrics server
-    start_prometheus_metrics(&cmd.metrics_conf, &dependencies)?;
+    start_prometheus_metrics(&cmd.metrics_conf, &dependencies, stratum)?;
 
     let http_server = rpc::new_http(
         "HTTP JSON-RPC",
@@ -20,6 +20,7 @@
 
 extern crate futures;
 extern crate parity_runtime;
+extern crate parking_lot;
 extern crate serde_json;
 
 extern crate log;
@@ -29,16 +30,34 @@ extern crate fake_fetch;
 
 pub extern crate fetch;
 
-use std::{cmp, fmt, io, str};
+use std::{
+    cmp, fmt, io, str,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use fetch::{Client as FetchClient, Fetch};
-use futures::{FutureExt, StreamExt, TryFutureExt};
-use log::warn;
-use parity_runtime::Executor;
+use futures::{FutureExt, StreamExt};
+use log::{debug, warn};
+use parity_runtime::{Clock, Executor, SystemClock};
+use parking_lot::Mutex;
 use serde_json::Value;
 
+/// Failed fetches in a row after which the circuit breaker opens.
+const FAILURES_TO_OPEN: u32 = 5;
+/// Wait before the first request while the circuit breaker is open.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(60);
+/// Longest wait between requests while the circuit breaker is open.
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+/// Time a price update may take, including redirects and reading the response.
+const FETCH_DEADLINE: Duration = Duration::from_secs(30);
+/// Time after which a probe of the API without a result no longer holds back the next one.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+/// Largest price API response accepted.
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
 /// Current ETH price information.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PriceInfo {
     /// Current ETH price in USD.
     pub ethusd: f32,
@@ -55,6 +74,23 @@ pub enum Error {
     Fetch(fetch::Error),
     /// IO error when reading API response.
     Io(io::Error),
+    /// The API failed repeatedly, no request is made until the backoff elapses.
+    CircuitOpen,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::StatusCode(status) => write!(f, "unexpected status: {status}"),
+            Error::UnexpectedResponse(Some(ref body)) => {
+                write!(f, "unexpected response: {body}")
+            }
+            Error::UnexpectedResponse(None) => write!(f, "unexpected response"),
+            Error::Fetch(ref err) => write!(f, "fetch failed: {err}"),
+            Error::Io(ref err) => write!(f, "I/O error: {err}"),
+            Error::CircuitOpen => write!(f, "backed off after repeated failures"),
+        }
+    }
 }
 
 impl From<io::Error> for Error {
@@ -69,11 +105,135 @@ impl From<fetch::Error> for Error {
     }
 }
 
+/// Health of the price API as seen by the client.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Status {
+    /// Time of the last successful price update.
+    pub last_success: Option<Instant>,
+    /// The last error encountered.
+    pub last_error: Option<String>,
+    /// Number of failed fetches since the last success.
+    pub consecutive_failures: u32,
+    /// Whether requests are suspended after repeated failures.
+    pub circuit_open: bool,
+    /// ETH price in USD of the last successful update.
+    pub last_price: Option<f32>,
+    /// ETH price in USD of the successful update before the last one.
+    pub previous_price: Option<f32>,
+    /// Change in percent from the previous to the last price, unknown if the previous price
+    /// was zero.
+    pub price_change_pct: Option<f32>,
+}
+
+/// Called with the previous and the new price when the price moves more than a threshold.
+pub type PriceObserver = Arc<dyn Fn(PriceInfo, PriceInfo) + Send + Sync>;
+
+/// Observer with the change in percent above which it is called.
+struct ThresholdObserver {
+    threshold_pct: f32,
+    observer: PriceObserver,
+}
+
+/// Circuit breaker state shared with the pending fetches.
+#[derive(Default)]
+struct Health {
+    status: Status,
+    /// Time the next request is allowed while the circuit breaker is open.
+    retry_at: Option<Instant>,
+    /// Wait before the next request after a failure while the circuit breaker is open.
+    backoff: Duration,
+    /// Start of the single request in flight to probe the API while the circuit breaker is open.
+    probing: Option<Instant>,
+}
+
+impl Health {
+    fn permit(&mut self, now: Instant) -> bool {
+        match self.retry_at {
+            None => true,
+            Some(retry_at) if now >= retry_at && !self.probing(now) => {
+                self.probing = Some(now);
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    /// Whether a probe is in flight which did not time out yet.
+    fn probing(&self, now: Instant) -> bool {
+        self.probing
+            .is_some_and(|started| now < started + PROBE_TIMEOUT)
+    }
+
+    /// Record a successful update to `ethusd`, returns the previous price if any.
+    fn succeeded(&mut self, now: Instant, ethusd: f32) -> Option<f32> {
+        let previous = self.status.last_price.replace(ethusd);
+        self.status.previous_price = previous;
+        self.status.price_change_pct = previous.and_then(|previous| change_pct(previous, ethusd));
+        self.status.last_success = Some(now);
+        self.status.consecutive_failures = 0;
+        self.status.circuit_open = false;
+        self.retry_at = None;
+        self.probing = None;
+        previous
+    }
+
+    fn failed(&mut self, now: Instant, err: &Error) {
+        self.status.last_error = Some(err.to_string());
+        self.status.consecutive_failures += 1;
+        self.probing = None;
+        if self.retry_at.is_some() {
+            self.backoff = cmp::min(self.backoff * 2, MAX_BACKOFF);
+        } else if self.status.consecutive_failures >= FAILURES_TO_OPEN {
+            self.backoff = INITIAL_BACKOFF;
+        } else {
+            return;
+        }
+        self.retry_at = Some(now + self.backoff);
+        self.status.circuit_open = true;
+    }
+}
+
+// Change in percent, none from a price of zero.
+fn change_pct(old: f32, new: f32) -> Option<f32> {
+    if old == 0.0 {
+        None
+    } else {
+        Some((new - old) / old * 100.0)
+    }
+}
+
+/// Call the observers whose threshold the move from `old` to `new` exceeds. Each one runs in
+/// its own task, a panicking observer affects neither the others nor the next updates.
+fn notify(pool: &Executor, observers: &Mutex<Vec<ThresholdObserver>>, old: f32, new: f32) {
+    let change = match change_pct(old, new) {
+        Some(change) => change.abs(),
+        None => return,
+    };
+    let triggered: Vec<PriceObserver> = observers
+        .lock()
+        .iter()
+        .filter(|o| change > o.threshold_pct)
+        .map(|o| o.observer.clone())
+        .collect();
+    for observer in triggered {
+        let task = async move {
+            let call = || observer(PriceInfo { ethusd: old }, PriceInfo { ethusd: new });
+            if let Err(e) = panic_hook::catch_scoped("price_observer", call) {
+                warn!("ETH price observer failed on a move from {old} to {new}: {e}");
+            }
+        };
+        pool.spawn_03(task.boxed());
+    }
+}
+
 /// A client to get the current ETH price using an external API.
 pub struct Client<F = FetchClient> {
     pool: Executor,
     api_endpoint: String,
     fetch: F,
+    health: Arc<Mutex<Health>>,
+    observers: Arc<Mutex<Vec<ThresholdObserver>>>,
+    clock: Arc<dyn Clock>,
 }
 
 impl<F> fmt::Debug for Client<F> {
@@ -97,16 +257,56 @@ impl<F: Fetch> Client<F> {
             pool,
             api_endpoint,
             fetch,
+            health: Default::default(),
+            observers: Default::default(),
+            clock: Arc::new(SystemClock),
         }
     }
 
+    /// Use `clock` as the clock of the circuit breaker.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Health of the price API.
+    pub fn status(&self) -> Status {
+        self.health.lock().status.clone()
+    }
+
+    /// Call `observer` with the previous and the new price whenever a successful update moves
+    /// the price by more than `threshold_pct` percent. Observers are added to the ones already set.
+    pub fn set_threshold_observer(&self, threshold_pct: f32, observer: PriceObserver) {
+        self.observers.lock().push(ThresholdObserver {
+            threshold_pct,
+            observer,
+        });
+    }
+
     /// Gets the current ETH price and calls `set_price` with the result.
-    pub fn get<G: FnOnce(PriceInfo) + Sync + Send + 'static>(&self, set_price: G) {
+    /// Fails with `Error::CircuitOpen` without making a request while the API is backed off.
+    pub fn get<G: FnOnce(PriceInfo) + Sync + Send + 'static>(
+        &self,
+        set_price: G,
+    ) -> Result<(), Error> {
+        if !self.health.lock().permit(self.clock.now()) {
+            debug!("Price API backed off after repeated failures, skipping update");
+            return Err(Error::CircuitOpen);
+        }
+
         let fetch = self.fetch.clone();
         let api_endpoint = self.api_endpoint.clone();
+        let health = self.health.clone();
+        let observers = self.observers.clone();
+        let pool = self.pool.clone();
+        let clock = self.clock.clone();
+        // the fetch enforces its deadline against the time of the operating system
+        let abort = fetch::Abort::default()
+            .with_max_size(MAX_RESPONSE_SIZE)
+            .with_deadline(Instant::now() + FETCH_DEADLINE);
         let future = async move {
             let response = fetch
-                .get(&api_endpoint, fetch::Abort::default())
+                .get(&api_endpoint, abort)
                 .await
                 .map_err(Error::Fetch)?;
             if !response.is_success() {
@@ -135,16 +335,26 @@ impl<F: Fetch> Client<F> {
             match ethusd {
                 Some(ethusd) => {
                     set_price(PriceInfo { ethusd });
-                    Ok(())
+                    Ok(ethusd)
                 }
                 None => Err(Error::UnexpectedResponse(body_str.map(From::from))),
             }
         }
-        .map_err(|err: Error| {
-            warn!("Failed to auto-update latest ETH price: {err:?}");
+        .map(move |result: Result<f32, Error>| match result {
+            Ok(ethusd) => {
+                let previous = health.lock().succeeded(clock.now(), ethusd);
+                if let Some(previous) = previous {
+                    notify(&pool, &observers, previous, ethusd);
+                }
+            }
+            Err(err) => {
+                warn!("Failed to auto-update latest ETH price: {err}");
+                health.lock().failed(clock.now(), &err);
+            }
         });
 
-        self.pool.spawn_03(future.map(|_| ()).boxed())
+        self.pool.spawn_03(future.boxed());
+        Ok(())
     }
 }
 
@@ -152,11 +362,122 @@ impl<F: Fetch> Client<F> {
 mod test {
     use super::*;
     use fake_fetch::FakeFetch;
-    use parity_runtime::{Executor, Runtime};
-    use std::sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    };
+    use fetch::{Abort, Request};
+    use parity_runtime::{Executor, Runtime, TestClock};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    const PRICE_RESPONSE: &str = r#"{ "status": "1", "result": { "ethusd": "209.55" } }"#;
+
+    /// Fetch backed by one of two fake fetchers, depending on whether the API is up.
+    #[derive(Clone)]
+    struct FlakyFetch {
+        up: Arc<AtomicBool>,
+        ok: FakeFetch<()>,
+        not_found: FakeFetch<()>,
+    }
+
+    impl FlakyFetch {
+        fn backend(&self) -> &FakeFetch<()> {
+            match self.up.load(Ordering::SeqCst) {
+                true => &self.ok,
+                false => &self.not_found,
+            }
+        }
+
+        fn requests(&self) -> usize {
+            self.ok.requests() + self.not_found.requests()
+        }
+    }
+
+    impl Fetch for FlakyFetch {
+        type Result = <FakeFetch<()> as Fetch>::Result;
+
+        fn fetch(&self, request: Request, abort: Abort) -> Self::Result {
+            self.backend().fetch(request, abort)
+        }
+
+        fn get(&self, url: &str, abort: Abort) -> Self::Result {
+            self.backend().get(url, abort)
+        }
+
+        fn post(&self, url: &str, abort: Abort) -> Self::Result {
+            self.backend().post(url, abort)
+        }
+    }
+
+    /// Fetch responding with the price currently set.
+    #[derive(Clone, Default)]
+    struct PriceFetch {
+        ethusd: Arc<Mutex<String>>,
+    }
+
+    impl PriceFetch {
+        fn backend(&self) -> FakeFetch<()> {
+            let response = format!(
+                r#"{{ "status": "1", "result": {{ "ethusd": "{}" }} }}"#,
+                self.ethusd.lock()
+            );
+            FakeFetch::new(Some(())).with_body(&response)
+        }
+    }
+
+    impl Fetch for PriceFetch {
+        type Result = <FakeFetch<()> as Fetch>::Result;
+
+        fn fetch(&self, request: Request, abort: Abort) -> Self::Result {
+            self.backend().fetch(request, abort)
+        }
+
+        fn get(&self, url: &str, abort: Abort) -> Self::Result {
+            self.backend().get(url, abort)
+        }
+
+        fn post(&self, url: &str, abort: Abort) -> Self::Result {
+            self.backend().post(url, abort)
+        }
+    }
+
+    type Moves = Arc<Mutex<Vec<(PriceInfo, PriceInfo)>>>;
+
+    fn price_moves() -> (Client<PriceFetch>, PriceFetch, Moves) {
+        let fetch = PriceFetch::default();
+        // observers are spawned from the update, which the synchronous executor cannot nest
+        let price_info = Client::new(
+            fetch.clone(),
+            Executor::new_thread_per_future(),
+            "https://fake.endpoint".to_owned(),
+        );
+        let moves = Arc::new(Mutex::new(Vec::new()));
+        let record = moves.clone();
+        price_info.set_threshold_observer(
+            10.0,
+            Arc::new(move |old, new| record.lock().push((old, new))),
+        );
+        (price_info, fetch, moves)
+    }
+
+    fn wait_until<C: Fn() -> bool>(condition: C) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    // Assert the condition still holds after the spawned observers had time to run.
+    fn assert_holds<C: Fn() -> bool>(condition: C) {
+        let deadline = Instant::now() + Duration::from_millis(200);
+        while Instant::now() < deadline {
+            assert!(condition());
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn update(price_info: &Client<PriceFetch>, fetch: &PriceFetch, ethusd: f32) {
+        *fetch.ethusd.lock() = ethusd.to_string();
+        price_info.get(|_| {}).unwrap();
+        wait_until(|| price_info.status().last_price == Some(ethusd));
+    }
 
     fn price_info_ok(response: &str, executor: Executor) -> Client<FakeFetch<String>> {
         Client::new(
@@ -193,10 +514,12 @@ mod test {
         let price_info = price_info_ok(response, runtime.executor());
 
         // when
-        price_info.get(|price| {
-            // then
-            assert_eq!(price.ethusd, 209.55);
-        });
+        price_info
+            .get(|price| {
+                // then
+                assert_eq!(price.ethusd, 209.55);
+            })
+            .unwrap();
     }
 
     #[test]
@@ -211,9 +534,11 @@ mod test {
 
         // when
         let bb = b.clone();
-        price_info.get(move |_| {
-            bb.store(true, Ordering::SeqCst);
-        });
+        price_info
+            .get(move |_| {
+                bb.store(true, Ordering::SeqCst);
+            })
+            .unwrap();
 
         // then
         assert!(!b.load(Ordering::SeqCst));
@@ -229,11 +554,215 @@ mod test {
 
         // when
         let bb = b.clone();
-        price_info.get(move |_| {
-            bb.store(true, Ordering::SeqCst);
-        });
+        price_info
+            .get(move |_| {
+                bb.store(true, Ordering::SeqCst);
+            })
+            .unwrap();
 
         // then
         assert!(!b.load(Ordering::SeqCst));
     }
+
+    #[test]
+    fn should_back_off_while_api_is_down() {
+        // given
+        let fetch = FlakyFetch {
+            up: Arc::new(AtomicBool::new(false)),
+            ok: FakeFetch::new(Some(())).with_body(PRICE_RESPONSE),
+            not_found: FakeFetch::new(None),
+        };
+        let clock = TestClock::new();
+        let start = clock.now();
+        let price_info = Client::new(
+            fetch.clone(),
+            Executor::new_sync(),
+            "https://fake.endpoint".to_owned(),
+        )
+        .with_clock(Arc::new(clock.clone()));
+
+        // when
+        for _ in 0..FAILURES_TO_OPEN {
+            price_info.get(|_| {}).unwrap();
+        }
+
+        // then
+        let status = price_info.status();
+        assert!(status.circuit_open);
+        assert_eq!(status.consecutive_failures, FAILURES_TO_OPEN);
+        assert!(status.last_error.is_some());
+        assert_eq!(status.last_success, None);
+        assert!(matches!(price_info.get(|_| {}), Err(Error::CircuitOpen)));
+        assert_eq!(fetch.requests(), FAILURES_TO_OPEN as usize);
+
+        // when
+        fetch.up.store(true, Ordering::SeqCst);
+        clock.advance(INITIAL_BACKOFF);
+        let updated = Arc::new(AtomicBool::new(false));
+        let set = updated.clone();
+        price_info
+            .get(move |price| {
+                assert_eq!(price.ethusd, 209.55);
+                set.store(true, Ordering::SeqCst);
+            })
+            .unwrap();
+
+        // then
+        assert!(updated.load(Ordering::SeqCst));
+        assert_eq!(fetch.requests(), FAILURES_TO_OPEN as usize + 1);
+        let status = price_info.status();
+        assert!(!status.circuit_open);
+        assert_eq!(status.consecutive_failures, 0);
+        assert_eq!(status.last_success, Some(start + INITIAL_BACKOFF));
+        price_info.get(|_| {}).unwrap();
+        assert_eq!(fetch.requests(), FAILURES_TO_OPEN as usize + 2);
+    }
+
+    #[test]
+    fn should_wait_doubled_backoff_after_failed_probe() {
+        // given
+        let fetch = FakeFetch::new(None::<()>);
+        let clock = TestClock::new();
+        let price_info = Client::new(
+            fetch.clone(),
+            Executor::new_sync(),
+            "https://fake.endpoint".to_owned(),
+        )
+        .with_clock(Arc::new(clock.clone()));
+        for _ in 0..FAILURES_TO_OPEN {
+            price_info.get(|_| {}).unwrap();
+        }
+
+        // when
+        clock.advance(INITIAL_BACKOFF);
+        price_info.get(|_| {}).unwrap();
+
+        // then
+        assert_eq!(fetch.requests(), FAILURES_TO_OPEN as usize + 1);
+        clock.advance(INITIAL_BACKOFF);
+        assert!(matches!(price_info.get(|_| {}), Err(Error::CircuitOpen)));
+        clock.advance(INITIAL_BACKOFF);
+        price_info.get(|_| {}).unwrap();
+        assert_eq!(fetch.requests(), FAILURES_TO_OPEN as usize + 2);
+        assert!(price_info.status().circuit_open);
+    }
+
+    #[test]
+    fn should_not_notify_moves_within_threshold() {
+        // given
+        let (price_info, fetch, moves) = price_moves();
+
+        // when
+        update(&price_info, &fetch, 200.0);
+        update(&price_info, &fetch, 210.0);
+
+        // then
+        assert_holds(|| moves.lock().is_empty());
+        let status = price_info.status();
+        assert_eq!(status.previous_price, Some(200.0));
+        assert_eq!(status.last_price, Some(210.0));
+        assert_eq!(status.price_change_pct, Some(5.0));
+    }
+
+    #[test]
+    fn should_notify_moves_beyond_threshold_once() {
+        // given
+        let (price_info, fetch, moves) = price_moves();
+        let calls = Arc::new(Mutex::new(0));
+        let count = calls.clone();
+        price_info.set_threshold_observer(50.0, Arc::new(move |_, _| *count.lock() += 1));
+
+        // when
+        update(&price_info, &fetch, 200.0);
+        update(&price_info, &fetch, 150.0);
+
+        // then
+        wait_until(|| !moves.lock().is_empty());
+        assert_eq!(
+            *moves.lock(),
+            vec![(PriceInfo { ethusd: 200.0 }, PriceInfo { ethusd: 150.0 })]
+        );
+        assert_holds(|| *calls.lock() == 0);
+        assert_eq!(price_info.status().price_change_pct, Some(-25.0));
+    }
+
+    #[test]
+    fn should_not_notify_moves_from_zero() {
+        // given
+        let (price_info, fetch, moves) = price_moves();
+
+        // when
+        update(&price_info, &fetch, 0.0);
+        update(&price_info, &fetch, 100.0);
+
+        // then
+        assert_holds(|| moves.lock().is_empty());
+        assert_eq!(price_info.status().price_change_pct, None);
+    }
+
+    #[test]
+    fn should_keep_updating_after_observer_panics() {
+        // given
+        // the global hook of the client ends the process
+        static GLOBAL: Mutex<Vec<String>> = parking_lot::const_mutex(Vec::new());
+        panic_hook::set_with(|msg| GLOBAL.lock().push(msg.to_owned()));
+        let (price_info, fetch, moves) = price_moves();
+        price_info.set_threshold_observer(1.0, Arc::new(|_, _| panic!("observer failed")));
+
+        // when
+        update(&price_info, &fetch, 100.0);
+        update(&price_info, &fetch, 200.0);
+        update(&price_info, &fetch, 400.0);
+
+        // then
+        wait_until(|| moves.lock().len() == 2);
+        let status = price_info.status();
+        assert_eq!(status.last_price, Some(400.0));
+        assert_eq!(status.consecutive_failures, 0);
+        assert!(!GLOBAL
+            .lock()
+            .iter()
+            .any(|msg| msg.contains("observer failed")));
+    }
+
+    #[test]
+    fn should_double_backoff_after_failed_probe() {
+        let mut health = Health::default();
+        let start = Instant::now();
+        let err = Error::StatusCode("Not Found");
+        for _ in 0..FAILURES_TO_OPEN {
+            assert!(health.permit(start));
+            health.failed(start, &err);
+        }
+        assert!(!health.permit(start + INITIAL_BACKOFF - Duration::from_secs(1)));
+
+        // a single probe at a time
+        let probe = start + INITIAL_BACKOFF;
+        assert!(health.permit(probe));
+        assert!(!health.permit(probe));
+        health.failed(probe, &err);
+
+        assert!(!health.permit(probe + INITIAL_BACKOFF));
+        assert!(health.permit(probe + INITIAL_BACKOFF * 2));
+        health.failed(probe, &err);
+        assert_eq!(health.retry_at, Some(probe + INITIAL_BACKOFF * 4));
+    }
+
+    #[test]
+    fn should_probe_again_after_probe_timeout() {
+        let mut health = Health::default();
+        let start = Instant::now();
+        let err = Error::StatusCode("Not Found");
+        for _ in 0..FAILURES_TO_OPEN {
+            assert!(health.permit(start));
+            health.failed(start, &err);
+        }
+
+        // the probe never completes
+        let probe = start + INITIAL_BACKOFF;
+        assert!(health.permit(probe));
+        assert!(!health.permit(probe + PROBE_TIMEOUT - Duration::from_secs(1)));
+        assert!(health.permit(probe + PROBE_TIMEOUT));
+        assert!(!health.permit(probe + PROBE_TIMEOUT));
+    }
 }
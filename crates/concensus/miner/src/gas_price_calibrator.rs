@@ -61,7 +61,7 @@ impl GasPriceCalibrator {
             let usd_per_tx = self.options.usd_per_tx;
             trace!(target: "miner", "Getting price info");
 
-            self.price_info.get(move |price: PriceInfo| {
+            let result = self.price_info.get(move |price: PriceInfo| {
 				trace!(target: "miner", "Price info arrived: {price:?}");
 				let usd_per_eth = price.ethusd;
 				let wei_per_usd: f32 = 1.0e18 / usd_per_eth;
@@ -70,6 +70,9 @@ impl GasPriceCalibrator {
 				info!(target: "miner", "Updated conversion rate to Ξ1 = {} ({} wei/gas)", Colour::White.bold().paint(format!("US${usd_per_eth:.2}")), Colour::Yellow.bold().paint(format!("{wei_per_gas}")));
 				set_price(U256::from(wei_per_gas as u64));
 			});
+            if let Err(err) = result {
+                trace!(target: "miner", "Price info not requested: {err:?}");
+            }
 
             self.next_calibration = Instant::now() + self.options.recalibration_period;
         }
@@ -18,14 +18,16 @@
 
 extern crate ethash;
 extern crate fetch;
+extern crate futures03;
 extern crate hyper;
 extern crate parity_runtime;
 extern crate url;
 
 use self::{
     ethash::SeedHashCompute,
-    fetch::{Client as FetchClient, ClientCompatExt, Method, Request},
-    hyper::header::{self, HeaderValue},
+    fetch::{Client as FetchClient, Fetch, Method, Request},
+    futures03::FutureExt,
+    hyper::header::HeaderValue,
     parity_runtime::Executor,
     url::Url,
 };
@@ -33,8 +35,6 @@ use self::{
 use ethereum_types::{H256, U256};
 use parking_lot::Mutex;
 
-use futures::Future;
-
 /// Trait for notifying about new mining work
 pub trait NotifyWork: Send + Sync {
     /// Fired when new mining job available
@@ -83,22 +83,16 @@ impl NotifyWork for WorkPoster {
 
         for u in &self.urls {
             let u = u.clone();
-            self.executor.spawn_01(
-                self.client
-                    .fetch_compat(
-                        Request::new(u.clone(), Method::POST)
-                            .with_header(
-                                header::CONTENT_TYPE,
-                                HeaderValue::from_static("application/json"),
-                            )
-                            .with_body(body.clone()),
-                        Default::default(),
-                    )
-                    .map_err(move |e| {
-                        warn!("Error sending HTTP notification to {u} : {e}, retrying");
-                    })
-                    .map(|_| ()),
+            let request = self.client.fetch(
+                Request::new(u.clone(), Method::POST)
+                    .with_body(body.clone(), HeaderValue::from_static("application/json")),
+                Default::default(),
             );
+            self.executor.spawn_03(request.map(move |result| {
+                if let Err(e) = result {
+                    warn!("Error sending HTTP notification to {u} : {e}, retrying");
+                }
+            }));
         }
     }
 }
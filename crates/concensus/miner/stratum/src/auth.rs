@@ -0,0 +1,47 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Authorization of the workers with a secret shared by all of them.
+
+use crate::traits::{AuthResult, WorkerAuthorizer};
+use ethereum_types::H256;
+use hash::keccak;
+use std::net::SocketAddr;
+
+/// Authorizes workers whose password hashes to the secret, or any worker without a secret
+pub struct SecretAuthorizer {
+    secret: Option<H256>,
+}
+
+impl SecretAuthorizer {
+    /// Authorizer checking passwords against the keccak hash `secret`
+    pub fn new(secret: Option<H256>) -> Self {
+        SecretAuthorizer { secret }
+    }
+}
+
+impl WorkerAuthorizer for SecretAuthorizer {
+    fn authorize(&self, _worker_id: &str, password: &str, _addr: SocketAddr) -> AuthResult {
+        match self.secret {
+            Some(secret) if keccak(password) != secret => AuthResult::Deny {
+                reason: "Invalid secret".into(),
+            },
+            _ => AuthResult::Allow {
+                difficulty_override: None,
+            },
+        }
+    }
+}
@@ -0,0 +1,372 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Configuration of the stratum server
+
+use crate::transport::ConnectionLimits;
+use ethereum_types::H256;
+use std::{fmt, net::SocketAddr, path::PathBuf, time::Duration};
+
+/// Settings of a stratum server, created with `StratumConfig::builder`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StratumConfig {
+    listen_addrs: Vec<SocketAddr>,
+    secret: Option<H256>,
+    initial_difficulty: Option<f64>,
+    idle_timeout: Option<Duration>,
+    limits: ConnectionLimits,
+}
+
+impl StratumConfig {
+    /// Configure a server, at least one listen address has to be given.
+    pub fn builder() -> StratumConfigBuilder {
+        StratumConfigBuilder {
+            listen_addrs: Vec::new(),
+            secret: None,
+            initial_difficulty: None,
+            idle_timeout: None,
+            limits: ConnectionLimits::default(),
+            admin_socket: None,
+            tls_certificate: None,
+            tls_private_key: None,
+        }
+    }
+
+    /// Addresses listened on, workers connected to any of them get the pushed work
+    pub fn listen_addrs(&self) -> &[SocketAddr] {
+        &self.listen_addrs
+    }
+
+    /// Workers are authorized if their password hashes to the secret, any worker if not set
+    pub fn secret(&self) -> Option<H256> {
+        self.secret
+    }
+
+    /// Share difficulty of the workers the authorizer sets none for
+    pub fn initial_difficulty(&self) -> Option<f64> {
+        self.initial_difficulty
+    }
+
+    /// Time without requests after which a worker gets no more work
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Limits of each connection
+    pub fn limits(&self) -> &ConnectionLimits {
+        &self.limits
+    }
+}
+
+/// Builder of a `StratumConfig`. Settings left out keep the behaviour of `Stratum::start`.
+#[derive(Debug, Clone)]
+pub struct StratumConfigBuilder {
+    listen_addrs: Vec<SocketAddr>,
+    secret: Option<H256>,
+    initial_difficulty: Option<f64>,
+    idle_timeout: Option<Duration>,
+    limits: ConnectionLimits,
+    admin_socket: Option<PathBuf>,
+    tls_certificate: Option<PathBuf>,
+    tls_private_key: Option<PathBuf>,
+}
+
+impl StratumConfigBuilder {
+    /// Listen on `addr`, in addition to the addresses given before.
+    pub fn with_listen_addr(mut self, addr: SocketAddr) -> Self {
+        self.listen_addrs.push(addr);
+        self
+    }
+
+    /// Authorize only the workers whose password hashes to `secret`.
+    pub fn with_secret(mut self, secret: Option<H256>) -> Self {
+        self.secret = secret;
+        self
+    }
+
+    /// Send `difficulty` ahead of each job to the workers the authorizer sets none for.
+    pub fn with_initial_difficulty(mut self, difficulty: f64) -> Self {
+        self.initial_difficulty = Some(difficulty);
+        self
+    }
+
+    /// Stop pushing work to the workers which sent no request for `timeout`.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Drop connections sending a request larger than `size` bytes.
+    pub fn with_max_frame_size(mut self, size: usize) -> Self {
+        self.limits.max_request_size = size;
+        self
+    }
+
+    /// Allow `requests_per_second` requests per connection on average, `burst` of them at once.
+    pub fn with_rate_limit(mut self, requests_per_second: u32, burst: u32) -> Self {
+        self.limits.requests_per_second = requests_per_second;
+        self.limits.burst = burst;
+        self
+    }
+
+    /// Drop connections after `violations` requests refused by the rate limit.
+    pub fn with_max_rate_violations(mut self, violations: u32) -> Self {
+        self.limits.max_violations = violations;
+        self
+    }
+
+    /// Refuse submits while `submits` of a connection are not answered yet.
+    pub fn with_max_inflight_submits(mut self, submits: usize) -> Self {
+        self.limits.max_inflight_submits = submits;
+        self
+    }
+
+    /// Replace all connection limits at once.
+    pub fn with_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Accept administrative commands on the unix socket at `path`.
+    /// Not supported by the server yet, `build` fails with `ConfigError::Unsupported`.
+    pub fn with_admin_socket(mut self, path: PathBuf) -> Self {
+        self.admin_socket = Some(path);
+        self
+    }
+
+    /// Secure the connections with the certificate chain at `path`, requires a private key.
+    /// Not supported by the server yet, `build` fails with `ConfigError::Unsupported`.
+    pub fn with_tls_certificate(mut self, path: PathBuf) -> Self {
+        self.tls_certificate = Some(path);
+        self
+    }
+
+    /// Private key of the certificate given with `with_tls_certificate`.
+    pub fn with_tls_private_key(mut self, path: PathBuf) -> Self {
+        self.tls_private_key = Some(path);
+        self
+    }
+
+    /// Check the settings and create the configuration.
+    pub fn build(self) -> Result<StratumConfig, ConfigError> {
+        if self.listen_addrs.is_empty() {
+            return Err(ConfigError::NoListenAddress);
+        }
+        for (index, addr) in self.listen_addrs.iter().enumerate() {
+            if self.listen_addrs[..index].contains(addr) {
+                return Err(ConfigError::DuplicateListenAddress(*addr));
+            }
+        }
+        if let Some(difficulty) = self.initial_difficulty {
+            if !difficulty.is_finite() || difficulty <= 0.0 {
+                return Err(ConfigError::InvalidDifficulty(difficulty));
+            }
+        }
+        if self.idle_timeout == Some(Duration::from_secs(0)) {
+            return Err(ConfigError::ZeroIdleTimeout);
+        }
+        if self.limits.max_request_size == 0 {
+            return Err(ConfigError::ZeroMaxFrameSize);
+        }
+        if self.limits.requests_per_second == 0 {
+            return Err(ConfigError::ZeroRateLimit);
+        }
+        if self.limits.max_inflight_submits == 0 {
+            return Err(ConfigError::ZeroInflightSubmits);
+        }
+        match self.admin_socket {
+            Some(ref path) if path.as_os_str().is_empty() => {
+                return Err(ConfigError::EmptyAdminSocketPath)
+            }
+            Some(_) => return Err(ConfigError::Unsupported("Admin socket")),
+            None => {}
+        }
+        match (self.tls_certificate, self.tls_private_key) {
+            (None, None) => {}
+            (Some(_), Some(_)) => return Err(ConfigError::Unsupported("TLS")),
+            (Some(_), None) => return Err(ConfigError::TlsCertificateWithoutKey),
+            (None, Some(_)) => return Err(ConfigError::TlsKeyWithoutCertificate),
+        }
+
+        Ok(StratumConfig {
+            listen_addrs: self.listen_addrs,
+            secret: self.secret,
+            initial_difficulty: self.initial_difficulty,
+            idle_timeout: self.idle_timeout,
+            limits: self.limits,
+        })
+    }
+}
+
+/// Invalid stratum server settings
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// No address to listen on
+    NoListenAddress,
+    /// The same address is listened on twice
+    DuplicateListenAddress(SocketAddr),
+    /// Share difficulty which is not a positive number
+    InvalidDifficulty(f64),
+    /// Idle timeout of zero, every worker would be dropped right away
+    ZeroIdleTimeout,
+    /// Frame size limit of zero, no request would fit
+    ZeroMaxFrameSize,
+    /// Rate limit of zero requests per second, the connections would stall after the burst
+    ZeroRateLimit,
+    /// In-flight submit limit of zero, no share would be accepted
+    ZeroInflightSubmits,
+    /// Admin socket with an empty path
+    EmptyAdminSocketPath,
+    /// TLS certificate given without its private key
+    TlsCertificateWithoutKey,
+    /// TLS private key given without its certificate
+    TlsKeyWithoutCertificate,
+    /// Setting this server does not implement yet
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::NoListenAddress => write!(f, "No listen address is given"),
+            ConfigError::DuplicateListenAddress(addr) => {
+                write!(f, "Listen address {addr} is given more than once")
+            }
+            ConfigError::InvalidDifficulty(difficulty) => {
+                write!(
+                    f,
+                    "Share difficulty must be a positive number, got {difficulty}"
+                )
+            }
+            ConfigError::ZeroIdleTimeout => write!(f, "Idle timeout must be positive"),
+            ConfigError::ZeroMaxFrameSize => write!(f, "Maximum frame size must be positive"),
+            ConfigError::ZeroRateLimit => {
+                write!(f, "Rate limit must allow at least one request per second")
+            }
+            ConfigError::ZeroInflightSubmits => {
+                write!(f, "Limit of submits in flight must be positive")
+            }
+            ConfigError::EmptyAdminSocketPath => write!(f, "Admin socket path is empty"),
+            ConfigError::TlsCertificateWithoutKey => {
+                write!(f, "TLS certificate is given without a private key")
+            }
+            ConfigError::TlsKeyWithoutCertificate => {
+                write!(f, "TLS private key is given without a certificate")
+            }
+            ConfigError::Unsupported(setting) => {
+                write!(f, "{setting} is not supported by the stratum server")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder() -> StratumConfigBuilder {
+        StratumConfig::builder().with_listen_addr("127.0.0.1:8008".parse().unwrap())
+    }
+
+    #[test]
+    fn defaults_match_legacy_start() {
+        let config = builder().build().unwrap();
+        assert_eq!(config.listen_addrs(), &["127.0.0.1:8008".parse().unwrap()]);
+        assert_eq!(config.secret(), None);
+        assert_eq!(config.initial_difficulty(), None);
+        assert_eq!(config.idle_timeout(), None);
+        assert_eq!(config.limits(), &ConnectionLimits::default());
+    }
+
+    #[test]
+    fn builds_given_settings() {
+        let config = builder()
+            .with_listen_addr("[::1]:8008".parse().unwrap())
+            .with_secret(Some(H256::from_low_u64_be(1)))
+            .with_initial_difficulty(0.5)
+            .with_idle_timeout(Duration::from_secs(30))
+            .with_max_frame_size(1024)
+            .with_rate_limit(5, 2)
+            .build()
+            .unwrap();
+        assert_eq!(config.listen_addrs().len(), 2);
+        assert_eq!(config.secret(), Some(H256::from_low_u64_be(1)));
+        assert_eq!(config.initial_difficulty(), Some(0.5));
+        assert_eq!(config.idle_timeout(), Some(Duration::from_secs(30)));
+        assert_eq!(config.limits().max_request_size, 1024);
+        assert_eq!(config.limits().requests_per_second, 5);
+        assert_eq!(config.limits().burst, 2);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let invalid = [
+            (StratumConfig::builder(), ConfigError::NoListenAddress),
+            (
+                builder().with_listen_addr("127.0.0.1:8008".parse().unwrap()),
+                ConfigError::DuplicateListenAddress("127.0.0.1:8008".parse().unwrap()),
+            ),
+            (
+                builder().with_initial_difficulty(0.0),
+                ConfigError::InvalidDifficulty(0.0),
+            ),
+            (
+                builder().with_initial_difficulty(f64::INFINITY),
+                ConfigError::InvalidDifficulty(f64::INFINITY),
+            ),
+            (
+                builder().with_idle_timeout(Duration::from_secs(0)),
+                ConfigError::ZeroIdleTimeout,
+            ),
+            (
+                builder().with_max_frame_size(0),
+                ConfigError::ZeroMaxFrameSize,
+            ),
+            (builder().with_rate_limit(0, 10), ConfigError::ZeroRateLimit),
+            (
+                builder().with_max_inflight_submits(0),
+                ConfigError::ZeroInflightSubmits,
+            ),
+            (
+                builder().with_admin_socket(PathBuf::new()),
+                ConfigError::EmptyAdminSocketPath,
+            ),
+            (
+                builder().with_tls_certificate("cert.pem".into()),
+                ConfigError::TlsCertificateWithoutKey,
+            ),
+            (
+                builder().with_tls_private_key("key.pem".into()),
+                ConfigError::TlsKeyWithoutCertificate,
+            ),
+            (
+                builder().with_admin_socket("stratum.sock".into()),
+                ConfigError::Unsupported("Admin socket"),
+            ),
+            (
+                builder()
+                    .with_tls_certificate("cert.pem".into())
+                    .with_tls_private_key("key.pem".into()),
+                ConfigError::Unsupported("TLS"),
+            ),
+        ];
+        for (builder, error) in invalid {
+            assert_eq!(builder.build(), Err(error));
+        }
+    }
+}
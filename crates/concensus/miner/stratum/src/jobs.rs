@@ -0,0 +1,167 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Jobs pushed to the workers, used to tell whether a share was found for current work.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Jobs remembered after they were superseded
+const MAX_JOB_HISTORY: usize = 16;
+
+/// Time a superseded job is still accepted by default
+pub const DEFAULT_STALE_GRACE: Duration = Duration::from_secs(10);
+
+/// Job a submitted share refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    /// Share for the job pushed last
+    Current,
+    /// Share for a job superseded within the grace window
+    Stale,
+    /// Share for a job which was never pushed, or superseded longer than the grace window ago
+    Unknown,
+}
+
+/// Bounded history of the jobs pushed, with monotonically increasing ids
+pub struct JobHistory {
+    /// Pushed jobs (id - push time), oldest first
+    jobs: VecDeque<(u64, Instant)>,
+    /// Id of the next job pushed
+    next_id: u64,
+    /// Time a superseded job is still accepted
+    stale_grace: Duration,
+}
+
+impl Default for JobHistory {
+    fn default() -> Self {
+        JobHistory {
+            jobs: VecDeque::with_capacity(MAX_JOB_HISTORY),
+            next_id: 1,
+            stale_grace: DEFAULT_STALE_GRACE,
+        }
+    }
+}
+
+impl JobHistory {
+    /// Set the time a superseded job is still accepted
+    pub fn set_stale_grace(&mut self, stale_grace: Duration) {
+        self.stale_grace = stale_grace;
+    }
+
+    /// Record a job pushed at `now`, returns its id as sent to the workers
+    pub fn push(&mut self, now: Instant) -> String {
+        let id = self.next_id;
+        self.next_id += 1;
+        if self.jobs.len() == MAX_JOB_HISTORY {
+            self.jobs.pop_front();
+        }
+        self.jobs.push_back((id, now));
+        format_job_id(id)
+    }
+
+    /// Classify the job id of a share submitted at `now`
+    pub fn classify(&self, job_id: &str, now: Instant) -> JobStatus {
+        let id = match parse_job_id(job_id) {
+            Some(id) => id,
+            None => return JobStatus::Unknown,
+        };
+        let position = match self.jobs.iter().position(|&(job, _)| job == id) {
+            Some(position) => position,
+            None => return JobStatus::Unknown,
+        };
+        // a job is superseded once the next one is pushed
+        match self.jobs.get(position + 1) {
+            None => JobStatus::Current,
+            Some(&(_, superseded))
+                if now.saturating_duration_since(superseded) <= self.stale_grace =>
+            {
+                JobStatus::Stale
+            }
+            Some(_) => JobStatus::Unknown,
+        }
+    }
+}
+
+fn format_job_id(id: u64) -> String {
+    format!("0x{id:x}")
+}
+
+fn parse_job_id(job_id: &str) -> Option<u64> {
+    let hex = job_id.strip_prefix("0x").unwrap_or(job_id);
+    u64::from_str_radix(hex, 16).ok()
+}
+
+/// Prefix the params of a `mining.notify` with the job id, if the params are a JSON array
+pub fn with_job_id(payload: &str, job_id: &str) -> Option<String> {
+    let rest = payload.trim_start().strip_prefix('[')?;
+    Some(if rest.trim_start().starts_with(']') {
+        format!("[\"{job_id}\"{rest}")
+    } else {
+        format!("[\"{job_id}\", {rest}")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_shares_by_job() {
+        let mut history = JobHistory::default();
+        history.set_stale_grace(Duration::from_secs(5));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        let first = history.push(at(0));
+        let second = history.push(at(10));
+        assert_eq!(first, "0x1");
+        assert_eq!(second, "0x2");
+
+        assert_eq!(history.classify(&second, at(11)), JobStatus::Current);
+        assert_eq!(history.classify(&first, at(15)), JobStatus::Stale);
+        assert_eq!(history.classify(&first, at(16)), JobStatus::Unknown);
+        assert_eq!(history.classify("0x2a", at(11)), JobStatus::Unknown);
+        assert_eq!(history.classify("job1", at(11)), JobStatus::Unknown);
+    }
+
+    #[test]
+    fn forgets_old_jobs() {
+        let mut history = JobHistory::default();
+        history.set_stale_grace(Duration::from_secs(3600));
+        let now = Instant::now();
+
+        let first = history.push(now);
+        for _ in 0..MAX_JOB_HISTORY - 1 {
+            history.push(now);
+        }
+        assert_eq!(history.classify(&first, now), JobStatus::Stale);
+        history.push(now);
+        assert_eq!(history.classify(&first, now), JobStatus::Unknown);
+    }
+
+    #[test]
+    fn prefixes_array_params() {
+        assert_eq!(
+            with_job_id(r#"["0x1234", "0x5678"]"#, "0x7"),
+            Some(r#"["0x7", "0x1234", "0x5678"]"#.to_owned())
+        );
+        assert_eq!(with_job_id(" [ ]", "0x7"), Some(r#"["0x7" ]"#.to_owned()));
+        assert_eq!(with_job_id(r#"{ "job": 1 }"#, "0x7"), None);
+    }
+}
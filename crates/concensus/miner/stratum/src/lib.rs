@@ -19,9 +19,12 @@
 extern crate ethereum_types;
 extern crate futures;
 extern crate jsonrpc_core;
-extern crate jsonrpc_tcp_server;
 extern crate keccak_hash as hash;
+extern crate parity_runtime;
 extern crate parking_lot;
+extern crate serde_json;
+extern crate stats;
+extern crate tokio;
 
 #[macro_use]
 extern crate log;
@@ -29,23 +32,36 @@ extern crate log;
 #[cfg(test)]
 extern crate env_logger;
 
+mod auth;
+mod config;
+mod jobs;
 mod traits;
+mod transport;
 
-pub use traits::{Error, JobDispatcher, PushWorkHandler, ServiceConfiguration};
+pub use auth::SecretAuthorizer;
+pub use config::{ConfigError, StratumConfig, StratumConfigBuilder};
+pub use jobs::{JobStatus, DEFAULT_STALE_GRACE};
+pub use traits::{
+    AuthResult, Error, JobDispatcher, PushWorkHandler, SubmitError, WorkerAuthorizer,
+};
+pub use transport::ConnectionLimits;
 
-use jsonrpc_core::{to_value, Compatibility, IoDelegate, MetaIoHandler, Metadata, Params, Value};
-use jsonrpc_tcp_server::{
-    Dispatcher, MetaExtractor, PushMessageError, RequestContext, Server as JsonRpcServer,
-    ServerBuilder as JsonRpcServerBuilder,
+use jobs::JobHistory;
+use jsonrpc_core::{
+    to_value, Compatibility, ErrorCode, IoDelegate, MetaIoHandler, Metadata, Params, Value,
 };
 use std::sync::Arc;
+use transport::{Dispatcher, Enforcement, PushMessageError, Server as TcpServer};
 
 use ethereum_types::H256;
-use hash::keccak;
+use parity_runtime::{Clock, SystemClock};
 use parking_lot::RwLock;
+use stats::{PrometheusMetrics, PrometheusRegistry};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime},
 };
 
 type RpcResult = Result<jsonrpc_core::Value, jsonrpc_core::Error>;
@@ -57,7 +73,7 @@ pub struct Stratum {
     /// RPC server
     ///
     /// It is an `Option` so it can be easily closed and released during `drop` phase
-    rpc_server: Option<JsonRpcServer>,
+    rpc_server: Option<TcpServer>,
     /// stratum protocol implementation
     ///
     /// It is owned by a container and rpc server
@@ -69,38 +85,109 @@ pub struct Stratum {
 }
 
 impl Stratum {
+    /// Start the server listening on every address of `addrs`, authorizing the workers whose
+    /// password hashes to `secret` if given
+    #[deprecated(note = "configure the server with `StratumConfig` and use `start_with_config`")]
     pub fn start(
-        addr: &SocketAddr,
+        addrs: &[SocketAddr],
         dispatcher: Arc<dyn JobDispatcher>,
         secret: Option<H256>,
+        limits: ConnectionLimits,
+    ) -> Result<Arc<Stratum>, Error> {
+        let config = addrs
+            .iter()
+            .fold(StratumConfig::builder(), |builder, addr| {
+                builder.with_listen_addr(*addr)
+            })
+            .with_secret(secret)
+            .with_limits(limits)
+            .build()?;
+        Self::start_with_config(&config, dispatcher)
+    }
+
+    /// Start the server as configured, authorizing the workers whose password hashes to the
+    /// secret of `config` if given
+    pub fn start_with_config(
+        config: &StratumConfig,
+        dispatcher: Arc<dyn JobDispatcher>,
+    ) -> Result<Arc<Stratum>, Error> {
+        Self::start_with_authorizer(
+            config,
+            dispatcher,
+            Arc::new(SecretAuthorizer::new(config.secret())),
+        )
+    }
+
+    /// Start the server as configured, authorizing the workers with `authorizer` instead of the
+    /// secret of `config`. Workers connected to any of the addresses get the pushed work.
+    pub fn start_with_authorizer(
+        config: &StratumConfig,
+        dispatcher: Arc<dyn JobDispatcher>,
+        authorizer: Arc<dyn WorkerAuthorizer>,
+    ) -> Result<Arc<Stratum>, Error> {
+        Self::start_with_clock(config, dispatcher, authorizer, Arc::new(SystemClock))
+    }
+
+    /// Start the server like `start_with_authorizer`, taking the time of job and worker
+    /// activity from `clock`
+    pub fn start_with_clock(
+        config: &StratumConfig,
+        dispatcher: Arc<dyn JobDispatcher>,
+        authorizer: Arc<dyn WorkerAuthorizer>,
+        clock: Arc<dyn Clock>,
     ) -> Result<Arc<Stratum>, Error> {
         let implementation = Arc::new(StratumImpl {
             subscribers: RwLock::default(),
             job_queue: RwLock::default(),
             dispatcher,
             workers: Arc::new(RwLock::default()),
-            secret,
+            worker_stats: RwLock::default(),
+            authorizer,
             notify_counter: RwLock::new(NOTIFY_COUNTER_INITIAL),
+            jobs: RwLock::default(),
+            counters: Counters::default(),
+            idle_timeout: RwLock::new(config.idle_timeout()),
+            initial_difficulty: config.initial_difficulty(),
+            last_active: RwLock::default(),
+            clock: clock.clone(),
         });
 
         let mut delegate = IoDelegate::<StratumImpl, SocketMetadata>::new(implementation.clone());
         delegate.add_method_with_meta("mining.subscribe", |s: &StratumImpl, p, m| {
+            s.seen(m.addr());
             futures::future::ready(s.subscribe(p, m))
         });
         delegate.add_method_with_meta("mining.authorize", |s: &StratumImpl, p, m| {
-            futures::future::ready(s.authorize(p, m))
+            let addr = *m.addr();
+            let result = s.authorize(p, m);
+            s.seen(&addr);
+            futures::future::ready(result)
         });
         delegate.add_method_with_meta("mining.submit", |s: &StratumImpl, p, m| {
+            s.seen(m.addr());
             futures::future::ready(s.submit(p, m))
         });
+        delegate.add_method_with_meta("mining.ping", |s: &StratumImpl, _p, m| {
+            s.seen(m.addr());
+            futures::future::ready(Ok(Value::String("pong".into())))
+        });
         let mut handler = MetaIoHandler::<SocketMetadata>::with_compatibility(Compatibility::Both);
         handler.extend_with(delegate);
 
-        let server_builder = JsonRpcServerBuilder::new(handler);
-        let tcp_dispatcher = server_builder.dispatcher();
-        let server_builder =
-            server_builder.session_meta_extractor(PeerMetaExtractor::new(tcp_dispatcher.clone()));
-        let server = server_builder.start(addr)?;
+        let tcp_dispatcher = Dispatcher::default();
+        let enforcement_stats = implementation.clone();
+        let disconnected = implementation.clone();
+        let server = TcpServer::start(
+            config.listen_addrs(),
+            handler,
+            tcp_dispatcher.clone(),
+            *config.limits(),
+            Arc::new(move |addr: &SocketAddr, action| {
+                enforcement_stats.record_enforcement(addr, action)
+            }),
+            Arc::new(move |addr: &SocketAddr| disconnected.disconnected(addr)),
+            clock,
+        )?;
 
         let stratum = Arc::new(Stratum {
             rpc_server: Some(server),
@@ -110,6 +197,105 @@ impl Stratum {
 
         Ok(stratum)
     }
+
+    /// Addresses listened on, with the ports assigned to the ones requested with port 0
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        self.rpc_server
+            .as_ref()
+            .map(|server| server.local_addrs())
+            .unwrap_or_default()
+    }
+
+    /// Shares submitted so far and last activity, by worker
+    pub fn worker_stats(&self) -> HashMap<String, WorkerStats> {
+        self.implementation.worker_stats.read().clone()
+    }
+
+    /// Set the time shares for a superseded job are still reported as `JobStatus::Stale`
+    pub fn set_stale_share_grace(&self, grace: Duration) {
+        self.implementation.jobs.write().set_stale_grace(grace);
+    }
+
+    /// Stop pushing work to the workers which sent no request for `timeout`, they have to
+    /// authorize again to get work. `None`, the default, keeps idle workers.
+    pub fn set_idle_timeout(&self, timeout: Option<Duration>) {
+        *self.implementation.idle_timeout.write() = timeout;
+    }
+}
+
+impl PrometheusMetrics for Stratum {
+    fn prometheus_metrics(&self, r: &mut PrometheusRegistry) {
+        let implementation = &self.implementation;
+        let counters = &implementation.counters;
+        let count = |counter: &AtomicU64| counter.load(Ordering::Relaxed) as i64;
+
+        let _ = r.register_gauge(
+            "stratum_subscribers",
+            "Number of subscribed stratum clients",
+            implementation.subscribers.read().len() as i64,
+        );
+        let _ = r.register_gauge(
+            "stratum_workers",
+            "Number of authorized stratum workers",
+            implementation.workers.read().len() as i64,
+        );
+        let _ = r.register_counter(
+            "stratum_jobs_broadcast",
+            "Number of jobs pushed to the stratum workers",
+            count(&counters.jobs_broadcast),
+        );
+        let _ = r.register_labeled_counter(
+            "stratum_push_failures",
+            "Number of jobs which could not be pushed to a worker",
+            "kind",
+            &[
+                ("no_such_peer", count(&counters.push_no_such_peer)),
+                ("transport", count(&counters.push_transport)),
+            ],
+        );
+        let _ = r.register_counter(
+            "stratum_shares_accepted",
+            "Number of shares accepted",
+            count(&counters.shares_accepted),
+        );
+        let _ = r.register_counter(
+            "stratum_shares_rejected",
+            "Number of shares rejected",
+            count(&counters.shares_rejected),
+        );
+        let _ = r.register_counter(
+            "stratum_authorization_failures",
+            "Number of denied authorization requests",
+            count(&counters.authorization_failures),
+        );
+        let _ = r.register_counter(
+            "stratum_idle_workers_dropped",
+            "Number of workers dropped for sending no request within the idle timeout",
+            count(&counters.idle_workers_dropped),
+        );
+
+        let worker_stats = implementation.worker_stats.read();
+        let accepted = worker_stats
+            .iter()
+            .map(|(worker, stats)| (worker.as_str(), stats.accepted as i64))
+            .collect::<Vec<_>>();
+        let rejected = worker_stats
+            .iter()
+            .map(|(worker, stats)| (worker.as_str(), stats.rejected.values().sum::<u64>() as i64))
+            .collect::<Vec<_>>();
+        let _ = r.register_labeled_counter(
+            "stratum_worker_shares_accepted",
+            "Number of shares accepted, by worker",
+            "worker",
+            &accepted,
+        );
+        let _ = r.register_labeled_counter(
+            "stratum_worker_shares_rejected",
+            "Number of shares rejected, by worker",
+            "worker",
+            &rejected,
+        );
+    }
 }
 
 impl PushWorkHandler for Stratum {
@@ -128,6 +314,46 @@ impl Drop for Stratum {
     }
 }
 
+/// Shares submitted by a worker
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct WorkerStats {
+    /// Number of accepted shares
+    pub accepted: u64,
+    /// Number of rejected shares, by stratum error code
+    pub rejected: BTreeMap<i64, u64>,
+    /// Time of the last request received from the worker, pings included
+    pub last_seen: Option<SystemTime>,
+    /// Number of connections dropped for a request above the size limit
+    pub oversized_requests: u64,
+    /// Number of requests refused by the rate limit
+    pub rate_limited: u64,
+    /// Number of connections dropped for repeated rate limit violations
+    pub rate_limit_drops: u64,
+    /// Number of submits refused because too many were in flight
+    pub submits_over_limit: u64,
+}
+
+/// Totals of the stratum server, maintained as events happen
+#[derive(Default)]
+struct Counters {
+    jobs_broadcast: AtomicU64,
+    push_no_such_peer: AtomicU64,
+    push_transport: AtomicU64,
+    shares_accepted: AtomicU64,
+    shares_rejected: AtomicU64,
+    authorization_failures: AtomicU64,
+    idle_workers_dropped: AtomicU64,
+}
+
+/// Worker authorized on a connection
+#[derive(Debug, Clone, PartialEq)]
+struct Worker {
+    /// Worker id given on authorization
+    id: String,
+    /// Share difficulty set by the authorizer, sent along with each job
+    difficulty: Option<f64>,
+}
+
 struct StratumImpl {
     /// Subscribed clients
     subscribers: RwLock<Vec<SocketAddr>>,
@@ -135,12 +361,26 @@ struct StratumImpl {
     job_queue: RwLock<HashSet<SocketAddr>>,
     /// Payload manager
     dispatcher: Arc<dyn JobDispatcher>,
-    /// Authorized workers (socket - worker_id)
-    workers: Arc<RwLock<HashMap<SocketAddr, String>>>,
-    /// Secret if any
-    secret: Option<H256>,
+    /// Authorized workers (socket - worker)
+    workers: Arc<RwLock<HashMap<SocketAddr, Worker>>>,
+    /// Submitted shares (worker_id - stats)
+    worker_stats: RwLock<HashMap<String, WorkerStats>>,
+    /// Decides which workers may authorize
+    authorizer: Arc<dyn WorkerAuthorizer>,
     /// Dispatch notify counter
     notify_counter: RwLock<u32>,
+    /// Jobs pushed to the workers
+    jobs: RwLock<JobHistory>,
+    /// Totals exported as metrics
+    counters: Counters,
+    /// Time without requests after which a worker gets no more work
+    idle_timeout: RwLock<Option<Duration>>,
+    /// Share difficulty of the workers the authorizer sets none for
+    initial_difficulty: Option<f64>,
+    /// Time of the last request of each authorized worker
+    last_active: RwLock<HashMap<SocketAddr, Instant>>,
+    /// Source of the time of jobs and worker activity
+    clock: Arc<dyn Clock>,
 }
 
 impl StratumImpl {
@@ -167,56 +407,175 @@ impl StratumImpl {
 
     /// rpc method `mining.authorize`
     fn authorize(&self, params: Params, meta: SocketMetadata) -> RpcResult {
-        params
-            .parse::<(String, String)>()
-            .map(|(worker_id, secret)| {
-                if let Some(valid_secret) = self.secret {
-                    let hash = keccak(secret);
-                    if hash != valid_secret {
-                        return to_value(false);
-                    }
-                }
+        let (worker_id, password) = params.parse::<(String, String)>()?;
+        let addr = *meta.addr();
+        let result = panic_hook::catch_scoped("stratum_authorizer", || {
+            self.authorizer.authorize(&worker_id, &password, addr)
+        })
+        .unwrap_or_else(|e| {
+            warn!(target: "stratum", "Authorizer failed on worker #{worker_id}: {e}");
+            AuthResult::Deny {
+                reason: "Authorization failed".into(),
+            }
+        });
+        match result {
+            AuthResult::Allow {
+                difficulty_override,
+            } => {
                 trace!(target: "stratum", "New worker #{worker_id} registered");
-                self.workers.write().insert(*meta.addr(), worker_id);
-                to_value(true)
-            })
-            .map(|v| v.expect("Only true/false is returned and it's always serializable; qed"))
+                self.workers.write().insert(
+                    addr,
+                    Worker {
+                        id: worker_id,
+                        difficulty: difficulty_override.or(self.initial_difficulty),
+                    },
+                );
+                Ok(to_value(true)
+                    .expect("Only true/false is returned and it's always serializable; qed"))
+            }
+            AuthResult::Deny { reason } => {
+                debug!(target: "stratum", "Worker #{worker_id} from {addr} denied: {reason}");
+                self.counters
+                    .authorization_failures
+                    .fetch_add(1, Ordering::Relaxed);
+                Err(jsonrpc_core::Error {
+                    code: ErrorCode::ServerError(SubmitError::Unauthorized.code()),
+                    message: reason,
+                    data: None,
+                })
+            }
+        }
     }
 
     /// rpc method `mining.submit`
     fn submit(&self, params: Params, meta: SocketMetadata) -> RpcResult {
-        Ok(match params {
-            Params::Array(vals) => {
-                // first two elements are service messages (worker_id & job_id)
-                match self.dispatcher.submit(
-                    vals.iter()
-                        .skip(2)
-                        .filter_map(|val| match *val {
-                            Value::String(ref s) => Some(s.to_owned()),
-                            _ => None,
-                        })
-                        .collect::<Vec<String>>(),
-                ) {
-                    Ok(()) => {
-                        self.update_peers(
-                            &meta
-                                .tcp_dispatcher
-                                .expect("tcp_dispatcher is always initialized; qed"),
-                        );
-                        to_value(true)
-                    }
-                    Err(submit_err) => {
-                        warn!("Error while submitting share: {submit_err:?}");
-                        to_value(false)
-                    }
-                }
-            }
+        let vals = match params {
+            Params::Array(vals) => vals,
             _ => {
                 trace!(target: "stratum", "Invalid submit work format {params:?}");
-                to_value(false)
+                return Ok(to_value(false)
+                    .expect("Only true/false is returned and it's always serializable; qed"));
+            }
+        };
+        // first two elements are service messages (worker_id & job_id)
+        let job = match vals.get(1) {
+            Some(Value::String(job_id)) => self.jobs.read().classify(job_id, self.clock.now()),
+            _ => JobStatus::Unknown,
+        };
+        trace!(target: "stratum", "Share for job {:?}: {job:?}", vals.get(1));
+        let result = self.dispatcher.submit_share(
+            vals.iter()
+                .skip(2)
+                .filter_map(|val| match *val {
+                    Value::String(ref s) => Some(s.to_owned()),
+                    _ => None,
+                })
+                .collect::<Vec<String>>(),
+            job,
+        );
+        self.record_submit(meta.addr(), &result);
+
+        match result {
+            Ok(()) => {
+                self.update_peers(
+                    &meta
+                        .tcp_dispatcher
+                        .expect("tcp_dispatcher is always initialized; qed"),
+                );
+                Ok(to_value(true)
+                    .expect("Only true/false is returned and it's always serializable; qed"))
+            }
+            Err(submit_err) => {
+                warn!(target: "stratum", "Share rejected: {submit_err:?}");
+                Err(jsonrpc_core::Error {
+                    code: ErrorCode::ServerError(submit_err.code()),
+                    message: submit_err.message().to_owned(),
+                    data: None,
+                })
+            }
+        }
+    }
+
+    /// Worker id of the peer connected from `addr`, or the address if it did not authorize
+    fn worker_id(&self, addr: &SocketAddr) -> String {
+        self.workers
+            .read()
+            .get(addr)
+            .map(|worker| worker.id.clone())
+            .unwrap_or_else(|| addr.to_string())
+    }
+
+    /// Note a request from the worker connected from `addr`. Connections which did not authorize
+    /// are not tracked.
+    fn seen(&self, addr: &SocketAddr) {
+        let worker = match self.workers.read().get(addr) {
+            Some(worker) => worker.id.clone(),
+            None => return,
+        };
+        self.last_active.write().insert(*addr, self.clock.now());
+        self.worker_stats
+            .write()
+            .entry(worker)
+            .or_default()
+            .last_seen = Some(self.clock.system_now());
+    }
+
+    /// Stop tracking the activity of the connection from `addr` once it is closed
+    fn disconnected(&self, addr: &SocketAddr) {
+        self.last_active.write().remove(addr);
+    }
+
+    /// Workers of `workers` which sent no request within the idle timeout
+    fn idle_workers(&self, workers: &HashMap<SocketAddr, Worker>) -> HashSet<SocketAddr> {
+        let timeout = match *self.idle_timeout.read() {
+            Some(timeout) => timeout,
+            None => return HashSet::new(),
+        };
+        let now = self.clock.now();
+        let last_active = self.last_active.read();
+        workers
+            .keys()
+            .filter(|addr| {
+                last_active
+                    .get(addr)
+                    .is_some_and(|last| now.saturating_duration_since(*last) >= timeout)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Account a submitted share to the worker connected from `addr`
+    fn record_submit(&self, addr: &SocketAddr, result: &Result<(), SubmitError>) {
+        let worker = self.worker_id(addr);
+        let mut worker_stats = self.worker_stats.write();
+        let stats = worker_stats.entry(worker).or_default();
+        match *result {
+            Ok(()) => {
+                stats.accepted += 1;
+                self.counters
+                    .shares_accepted
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+            Err(ref e) => {
+                *stats.rejected.entry(e.code()).or_insert(0) += 1;
+                self.counters
+                    .shares_rejected
+                    .fetch_add(1, Ordering::Relaxed);
             }
         }
-        .expect("Only true/false is returned and it's always serializable; qed"))
+    }
+
+    /// Account an action taken against the connection from `addr` for exceeding its limits
+    fn record_enforcement(&self, addr: &SocketAddr, action: Enforcement) {
+        let worker = self.worker_id(addr);
+        let mut worker_stats = self.worker_stats.write();
+        let stats = worker_stats.entry(worker).or_default();
+        match action {
+            Enforcement::OversizedRequest => stats.oversized_requests += 1,
+            Enforcement::RateLimited => stats.rate_limited += 1,
+            Enforcement::RateLimitDrop => stats.rate_limit_drops += 1,
+            Enforcement::TooManySubmits => stats.submits_over_limit += 1,
+        }
     }
 
     /// Helper method
@@ -227,8 +586,9 @@ impl StratumImpl {
     }
 
     fn push_work_all(&self, payload: String, tcp_dispatcher: &Dispatcher) {
-        let hup_peers = {
+        let (hup_peers, idle_peers) = {
             let workers = self.workers.read();
+            let idle_peers = self.idle_workers(&workers);
             let next_request_id = {
                 let mut counter = self.notify_counter.write();
                 if *counter == ::std::u32::MAX {
@@ -238,32 +598,54 @@ impl StratumImpl {
                 }
                 *counter
             };
+            let job_id = self.jobs.write().push(self.clock.now());
+            let payload = jobs::with_job_id(&payload, &job_id).unwrap_or(payload);
 
             let mut hup_peers = HashSet::new();
             let workers_msg = format!(
                 "{{ \"id\": {next_request_id}, \"method\": \"mining.notify\", \"params\": {payload} }}"
             );
             trace!(target: "stratum", "pushing work for {} workers (payload: '{}')", workers.len(), &workers_msg);
-            for (addr, _) in workers.iter() {
-                trace!(target: "stratum", "pusing work to {addr}");
-                match tcp_dispatcher.push_message(addr, workers_msg.clone()) {
-                    Err(PushMessageError::NoSuchPeer) => {
-                        trace!(target: "stratum", "Worker no longer connected: {addr}");
-                        hup_peers.insert(*addr);
-                    }
-                    Err(e) => {
-                        warn!(target: "stratum", "Unexpected transport error: {e:?}");
-                    }
-                    Ok(_) => {}
+            for (addr, worker) in workers.iter() {
+                if idle_peers.contains(addr) {
+                    continue;
                 }
+                trace!(target: "stratum", "pusing work to {addr}");
+                // workers with a difficulty of their own get it ahead of each job
+                let pushed = worker
+                    .difficulty
+                    .map_or(Ok(()), |difficulty| {
+                        let message = format!(
+                            "{{ \"id\": null, \"method\": \"mining.set_difficulty\", \"params\": [{difficulty}] }}"
+                        );
+                        tcp_dispatcher.push_message(addr, message)
+                    })
+                    .and_then(|()| tcp_dispatcher.push_message(addr, workers_msg.clone()));
+                let failures = match pushed {
+                    Ok(()) => continue,
+                    Err(PushMessageError::NoSuchPeer) => &self.counters.push_no_such_peer,
+                    Err(PushMessageError::Transport) => &self.counters.push_transport,
+                };
+                failures.fetch_add(1, Ordering::Relaxed);
+                trace!(target: "stratum", "Worker no longer connected: {addr}");
+                hup_peers.insert(*addr);
             }
-            hup_peers
+            self.counters.jobs_broadcast.fetch_add(1, Ordering::Relaxed);
+            (hup_peers, idle_peers)
         };
 
-        if !hup_peers.is_empty() {
+        if !idle_peers.is_empty() {
+            debug!(target: "stratum", "Dropping {} idle workers", idle_peers.len());
+            self.counters
+                .idle_workers_dropped
+                .fetch_add(idle_peers.len() as u64, Ordering::Relaxed);
+        }
+        if !hup_peers.is_empty() || !idle_peers.is_empty() {
             let mut workers = self.workers.write();
-            for hup_peer in hup_peers {
-                workers.remove(&hup_peer);
+            let mut last_active = self.last_active.write();
+            for peer in hup_peers.iter().chain(&idle_peers) {
+                workers.remove(peer);
+                last_active.remove(peer);
             }
         }
     }
@@ -295,31 +677,17 @@ impl SocketMetadata {
 
 impl Metadata for SocketMetadata {}
 
-pub struct PeerMetaExtractor {
-    tcp_dispatcher: Dispatcher,
-}
-
-impl PeerMetaExtractor {
-    fn new(tcp_dispatcher: Dispatcher) -> Self {
-        PeerMetaExtractor { tcp_dispatcher }
-    }
-}
-
-impl MetaExtractor<SocketMetadata> for PeerMetaExtractor {
-    fn extract(&self, context: &RequestContext) -> SocketMetadata {
-        SocketMetadata {
-            addr: context.peer_addr,
-            tcp_dispatcher: Some(self.tcp_dispatcher.clone()),
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::{net::SocketAddr, sync::Arc};
+    use hash::keccak;
+    use parity_runtime::TestClock;
+    use std::{
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
     use tokio::{
-        io::{AsyncReadExt, AsyncWriteExt},
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
         net::TcpStream,
         time,
     };
@@ -332,6 +700,10 @@ mod tests {
         }
     }
 
+    fn config(addr: SocketAddr) -> StratumConfigBuilder {
+        StratumConfig::builder().with_listen_addr(addr)
+    }
+
     fn dummy_request(addr: &SocketAddr, data: &str) -> Vec<u8> {
         let rt = tokio::runtime::Builder::new_current_thread()
             .enable_all()
@@ -358,20 +730,22 @@ mod tests {
 
     #[test]
     fn can_be_started() {
-        let stratum = Stratum::start(
-            &"127.0.0.1:19980".parse().unwrap(),
+        let stratum = Stratum::start_with_config(
+            &config("127.0.0.1:19980".parse().unwrap()).build().unwrap(),
             Arc::new(VoidManager),
-            None,
         );
         assert!(stratum.is_ok());
     }
 
     #[test]
+    #[allow(deprecated)]
     fn records_subscriber() {
         let _ = ::env_logger::try_init();
 
+        // the legacy entry point still starts a working server
         let addr = "127.0.0.1:19985".parse().unwrap();
-        let stratum = Stratum::start(&addr, Arc::new(VoidManager), None).unwrap();
+        let stratum =
+            Stratum::start(&[addr], Arc::new(VoidManager), None, Default::default()).unwrap();
         let request = r#"{"jsonrpc": "2.0", "method": "mining.subscribe", "params": [], "id": 1}"#;
         dummy_request(&addr, request);
         assert_eq!(1, stratum.implementation.subscribers.read().len());
@@ -418,8 +792,9 @@ mod tests {
     #[test]
     fn receives_initial_payload() {
         let addr = "127.0.0.1:19975".parse().unwrap();
-        let _stratum = Stratum::start(&addr, DummyManager::new(), None)
-            .expect("There should be no error starting stratum");
+        let _stratum =
+            Stratum::start_with_config(&config(addr).build().unwrap(), DummyManager::new())
+                .expect("There should be no error starting stratum");
         let request = r#"{"jsonrpc": "2.0", "method": "mining.subscribe", "params": [], "id": 2}"#;
 
         let response = String::from_utf8(dummy_request(&addr, request)).unwrap();
@@ -433,10 +808,9 @@ mod tests {
     #[test]
     fn can_authorize() {
         let addr = "127.0.0.1:19970".parse().unwrap();
-        let stratum = Stratum::start(
-            &addr,
+        let stratum = Stratum::start_with_config(
+            &config(addr).build().unwrap(),
             Arc::new(DummyManager::build().of_initial(r#"["dummy autorize payload"]"#)),
-            None,
         )
         .expect("There should be no error starting stratum");
 
@@ -450,15 +824,131 @@ mod tests {
         assert_eq!(1, stratum.implementation.workers.read().len());
     }
 
+    struct RigAuthorizer;
+
+    impl WorkerAuthorizer for RigAuthorizer {
+        fn authorize(&self, worker_id: &str, password: &str, _addr: SocketAddr) -> AuthResult {
+            match (worker_id, password) {
+                ("rig1", "x") => AuthResult::Allow {
+                    difficulty_override: Some(2.5),
+                },
+                ("panic", _) => panic!("authorizer failure"),
+                _ => AuthResult::Deny {
+                    reason: format!("Worker {worker_id} is revoked"),
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn authorizes_with_external_authorizer() {
+        // the global hook of the client ends the process
+        static GLOBAL: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        panic_hook::set_with(|msg| GLOBAL.lock().unwrap().push(msg.to_owned()));
+        let addr = "127.0.0.1:19930".parse().unwrap();
+        let stratum = Stratum::start_with_authorizer(
+            &config(addr).build().unwrap(),
+            Arc::new(VoidManager),
+            Arc::new(RigAuthorizer),
+        )
+        .expect("There should be no error starting stratum");
+
+        let allowed =
+            r#"{"jsonrpc": "2.0", "method": "mining.authorize", "params": ["rig1", "x"], "id": 1}"#;
+        let response = String::from_utf8(dummy_request(&addr, allowed)).unwrap();
+        assert_eq!(
+            terminated_str(r#"{"jsonrpc":"2.0","result":true,"id":1}"#),
+            response
+        );
+
+        let denied =
+            r#"{"jsonrpc": "2.0", "method": "mining.authorize", "params": ["rig2", "x"], "id": 2}"#;
+        let response = String::from_utf8(dummy_request(&addr, denied)).unwrap();
+        assert_eq!(
+            terminated_str(
+                r#"{"jsonrpc":"2.0","error":{"code":24,"message":"Worker rig2 is revoked"},"id":2}"#
+            ),
+            response
+        );
+
+        let panicking = r#"{"jsonrpc": "2.0", "method": "mining.authorize", "params": ["panic", "x"], "id": 3}"#;
+        let response = String::from_utf8(dummy_request(&addr, panicking)).unwrap();
+        assert_eq!(
+            terminated_str(
+                r#"{"jsonrpc":"2.0","error":{"code":24,"message":"Authorization failed"},"id":3}"#
+            ),
+            response
+        );
+        assert!(!GLOBAL
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|msg| msg.contains("authorizer failure")));
+
+        let workers = stratum.implementation.workers.read();
+        assert_eq!(
+            workers.values().cloned().collect::<Vec<_>>(),
+            vec![Worker {
+                id: "rig1".into(),
+                difficulty: Some(2.5),
+            }]
+        );
+    }
+
+    #[test]
+    fn initial_difficulty_applies_without_override() {
+        let addr = "127.0.0.1:19925".parse().unwrap();
+        let config = config(addr).with_initial_difficulty(0.5).build().unwrap();
+        let stratum = Stratum::start_with_authorizer(
+            &config,
+            Arc::new(VoidManager),
+            Arc::new(SecretAuthorizer::new(None)),
+        )
+        .expect("There should be no error starting stratum");
+
+        let request = r#"{"jsonrpc": "2.0", "method": "mining.authorize", "params": ["miner1", ""], "id": 1}"#;
+        dummy_request(&addr, request);
+        assert_eq!(
+            stratum
+                .implementation
+                .workers
+                .read()
+                .values()
+                .cloned()
+                .collect::<Vec<_>>(),
+            vec![Worker {
+                id: "miner1".into(),
+                difficulty: Some(0.5),
+            }]
+        );
+        drop(stratum);
+
+        let stratum =
+            Stratum::start_with_authorizer(&config, Arc::new(VoidManager), Arc::new(RigAuthorizer))
+                .expect("There should be no error starting stratum");
+        let request =
+            r#"{"jsonrpc": "2.0", "method": "mining.authorize", "params": ["rig1", "x"], "id": 1}"#;
+        dummy_request(&addr, request);
+        assert_eq!(
+            stratum
+                .implementation
+                .workers
+                .read()
+                .values()
+                .map(|worker| worker.difficulty)
+                .collect::<Vec<_>>(),
+            vec![Some(2.5)]
+        );
+    }
+
     #[test]
     fn can_push_work() {
         let _ = ::env_logger::try_init();
 
         let addr = "127.0.0.1:19995".parse().unwrap();
-        let stratum = Stratum::start(
-            &addr,
+        let stratum = Stratum::start_with_config(
+            &config(addr).build().unwrap(),
             Arc::new(DummyManager::build().of_initial(r#"["dummy autorize payload"]"#)),
-            None,
         )
         .expect("There should be no error starting stratum");
 
@@ -507,10 +997,610 @@ mod tests {
 			response);
     }
 
+    #[test]
+    fn pushes_work_to_workers_of_all_listeners() {
+        if std::net::TcpListener::bind("[::1]:0").is_err() {
+            // no IPv6 loopback to listen on
+            return;
+        }
+        let requested: Vec<SocketAddr> =
+            vec!["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()];
+        let config = config(requested[0])
+            .with_listen_addr(requested[1])
+            .build()
+            .unwrap();
+        let stratum = Stratum::start_with_config(&config, Arc::new(VoidManager))
+            .expect("There should be no error starting stratum");
+
+        let bound = stratum.local_addrs().to_vec();
+        assert_eq!(bound.len(), 2);
+        for (bound, requested) in bound.iter().zip(&requested) {
+            assert_eq!(bound.ip(), requested.ip());
+            assert_ne!(bound.port(), 0);
+        }
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Tokio Runtime should be created with no errors");
+
+        let responses = rt.block_on(async {
+            let mut streams = Vec::new();
+            for (i, addr) in bound.iter().enumerate() {
+                let request = format!(
+                    "{{\"jsonrpc\": \"2.0\", \"method\": \"mining.authorize\", \"params\": [\"miner{i}\", \"\"], \"id\": 1}}\n"
+                );
+                let auth_response = "{\"jsonrpc\":\"2.0\",\"result\":true,\"id\":1}\n";
+                let mut stream = TcpStream::connect(addr).await.expect("Failed to connect");
+                stream
+                    .write_all(request.as_bytes())
+                    .await
+                    .expect("Failed to write auth request");
+                let mut read_buf = vec![0u8; auth_response.len()];
+                stream
+                    .read_exact(&mut read_buf)
+                    .await
+                    .expect("Failed to read auth response");
+                assert_eq!(String::from_utf8(read_buf).unwrap(), auth_response);
+                streams.push(stream);
+            }
+
+            stratum.push_work_all(r#"{ "00040008", "100500" }"#.to_owned());
+            time::sleep(time::Duration::from_millis(100)).await;
+
+            let mut responses = Vec::new();
+            for mut stream in streams {
+                stream.shutdown().await.expect("Failed to shutdown write");
+                let mut read_buf = Vec::with_capacity(2048);
+                stream
+                    .read_to_end(&mut read_buf)
+                    .await
+                    .expect("Failed to read work");
+                responses.push(String::from_utf8(read_buf).expect("Response should be utf-8"));
+            }
+            responses
+        });
+
+        let work =
+            "{ \"id\": 17, \"method\": \"mining.notify\", \"params\": { \"00040008\", \"100500\" } }\n";
+        assert_eq!(responses, vec![work, work]);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn listening_on_no_address_is_an_error() {
+        assert!(Stratum::start(&[], Arc::new(VoidManager), None, Default::default()).is_err());
+    }
+
+    struct RejectingManager {
+        result: RwLock<Result<(), SubmitError>>,
+    }
+
+    impl JobDispatcher for RejectingManager {
+        fn submit_share(&self, _payload: Vec<String>, _job: JobStatus) -> Result<(), SubmitError> {
+            self.result.read().clone()
+        }
+    }
+
+    #[test]
+    fn submit_reports_stratum_error_codes() {
+        let addr = "127.0.0.1:19965".parse().unwrap();
+        let dispatcher = Arc::new(RejectingManager {
+            result: RwLock::new(Ok(())),
+        });
+        let stratum =
+            Stratum::start_with_config(&config(addr).build().unwrap(), dispatcher.clone())
+                .expect("There should be no error starting stratum");
+        let request = r#"{"jsonrpc": "2.0", "method": "mining.submit", "params": ["miner1", "job1", "0x01", "0x02", "0x03"], "id": 1}"#;
+
+        let rejects = [
+            (SubmitError::Stale, 21, "Job not found"),
+            (SubmitError::Duplicate, 22, "Duplicate share"),
+            (SubmitError::LowDifficulty, 23, "Low difficulty share"),
+            (SubmitError::Unauthorized, 24, "Unauthorized worker"),
+            (SubmitError::InvalidSolution, 20, "Invalid solution"),
+            (SubmitError::Other("Bad seal".into()), 20, "Bad seal"),
+        ];
+        for (err, code, message) in rejects.iter() {
+            *dispatcher.result.write() = Err(err.clone());
+            let response = String::from_utf8(dummy_request(&addr, request)).unwrap();
+            assert_eq!(
+                format!(
+                    "{{\"jsonrpc\":\"2.0\",\"error\":{{\"code\":{code},\"message\":\"{message}\"}},\"id\":1}}\n"
+                ),
+                response
+            );
+        }
+
+        *dispatcher.result.write() = Ok(());
+        let response = String::from_utf8(dummy_request(&addr, request)).unwrap();
+        assert_eq!(
+            terminated_str(r#"{"jsonrpc":"2.0","result":true,"id":1}"#),
+            response
+        );
+
+        let stats = stratum.worker_stats();
+        let rejected: u64 = stats.values().flat_map(|s| s.rejected.values()).sum();
+        let accepted: u64 = stats.values().map(|s| s.accepted).sum();
+        assert_eq!(rejected, 6);
+        assert_eq!(accepted, 1);
+        // every request comes from a connection of its own, so count over all of them
+        let invalid: u64 = stats.values().filter_map(|s| s.rejected.get(&20)).sum();
+        assert_eq!(invalid, 2);
+    }
+
+    struct LegacyManager {
+        result: RwLock<Result<(), Error>>,
+    }
+
+    impl JobDispatcher for LegacyManager {
+        fn submit(&self, _payload: Vec<String>) -> Result<(), Error> {
+            self.result.read().clone()
+        }
+    }
+
+    #[test]
+    fn legacy_dispatchers_still_receive_shares() {
+        let addr = "127.0.0.1:19990".parse().unwrap();
+        let dispatcher = Arc::new(LegacyManager {
+            result: RwLock::new(Ok(())),
+        });
+        let stratum =
+            Stratum::start_with_config(&config(addr).build().unwrap(), dispatcher.clone())
+                .expect("There should be no error starting stratum");
+        stratum.push_work_all(r#"["0x1234"]"#.to_owned());
+        let request = |job_id: &str| {
+            format!(
+                r#"{{"jsonrpc": "2.0", "method": "mining.submit", "params": ["miner1", "{job_id}", "0x01"], "id": 1}}"#
+            )
+        };
+
+        let response = String::from_utf8(dummy_request(&addr, &request("0x1"))).unwrap();
+        assert_eq!(
+            terminated_str(r#"{"jsonrpc":"2.0","result":true,"id":1}"#),
+            response
+        );
+
+        *dispatcher.result.write() = Err(Error::Dispatch("Bad seal".into()));
+        let response = String::from_utf8(dummy_request(&addr, &request("0x1"))).unwrap();
+        assert_eq!(
+            terminated_str(
+                r#"{"jsonrpc":"2.0","error":{"code":20,"message":"Dispatch(\"Bad seal\")"},"id":1}"#
+            ),
+            response
+        );
+
+        // rejecting shares for unknown jobs is left to the dispatcher
+        *dispatcher.result.write() = Ok(());
+        let response = String::from_utf8(dummy_request(&addr, &request("0x2a"))).unwrap();
+        assert_eq!(
+            terminated_str(r#"{"jsonrpc":"2.0","result":true,"id":1}"#),
+            response
+        );
+    }
+
+    struct RecordingManager {
+        jobs: RwLock<Vec<JobStatus>>,
+    }
+
+    impl JobDispatcher for RecordingManager {
+        fn submit_share(&self, _payload: Vec<String>, job: JobStatus) -> Result<(), SubmitError> {
+            self.jobs.write().push(job);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn classifies_shares_by_job_id() {
+        let addr = "127.0.0.1:19930".parse().unwrap();
+        let dispatcher = Arc::new(RecordingManager {
+            jobs: RwLock::default(),
+        });
+        let stratum =
+            Stratum::start_with_config(&config(addr).build().unwrap(), dispatcher.clone())
+                .expect("There should be no error starting stratum");
+        stratum.set_stale_share_grace(time::Duration::from_secs(60));
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Tokio Runtime should be created with no errors");
+
+        let notifies = rt.block_on(async {
+            let stream = TcpStream::connect(&addr).await.expect("Failed to connect");
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            let auth = "{\"jsonrpc\": \"2.0\", \"method\": \"mining.authorize\", \"params\": [\"miner1\", \"\"], \"id\": 1}\n";
+            writer.write_all(auth.as_bytes()).await.expect("Failed to write");
+            let mut response = String::new();
+            reader.read_line(&mut response).await.expect("Failed to read");
+
+            stratum.push_work_all(r#"["0x1234"]"#.to_owned());
+            stratum.push_work_all(r#"["0x5678"]"#.to_owned());
+            let mut notifies = Vec::new();
+            for _ in 0..2 {
+                let mut notify = String::new();
+                reader.read_line(&mut notify).await.expect("Failed to read");
+                notifies.push(notify);
+            }
+
+            // the last share is sent once the grace window of the first job passed
+            let submits = [("0x1", 0u64), ("0x2", 0), ("0x2a", 0), ("0x1", 10)];
+            for (id, (job_id, delay)) in submits.iter().enumerate() {
+                if *delay > 0 {
+                    stratum.set_stale_share_grace(time::Duration::from_millis(*delay));
+                    time::sleep(time::Duration::from_millis(2 * delay)).await;
+                }
+                let submit = format!(
+                    "{{\"jsonrpc\": \"2.0\", \"method\": \"mining.submit\", \"params\": [\"miner1\", \"{job_id}\", \"0x01\"], \"id\": {id}}}\n"
+                );
+                writer.write_all(submit.as_bytes()).await.expect("Failed to write");
+                let mut response = String::new();
+                reader.read_line(&mut response).await.expect("Failed to read");
+            }
+            notifies
+        });
+
+        assert_eq!(
+            notifies,
+            vec![
+                "{ \"id\": 17, \"method\": \"mining.notify\", \"params\": [\"0x1\", \"0x1234\"] }\n",
+                "{ \"id\": 18, \"method\": \"mining.notify\", \"params\": [\"0x2\", \"0x5678\"] }\n",
+            ]
+        );
+        assert_eq!(
+            *dispatcher.jobs.read(),
+            vec![
+                JobStatus::Stale,
+                JobStatus::Current,
+                JobStatus::Unknown,
+                JobStatus::Unknown
+            ]
+        );
+    }
+
+    #[test]
+    fn answers_ping_and_records_last_seen() {
+        let addr = "127.0.0.1:19960".parse().unwrap();
+        let stratum =
+            Stratum::start_with_config(&config(addr).build().unwrap(), Arc::new(VoidManager))
+                .expect("There should be no error starting stratum");
+        let ping = r#"{"jsonrpc": "2.0", "method": "mining.ping", "params": [], "id": 3}"#;
+
+        // connections which did not authorize are answered but not tracked
+        let response = String::from_utf8(dummy_request(&addr, ping)).unwrap();
+        assert_eq!(
+            terminated_str(r#"{"jsonrpc":"2.0","result":"pong","id":3}"#),
+            response
+        );
+        assert!(stratum.worker_stats().is_empty());
+
+        let authorize = r#"{"jsonrpc": "2.0", "method": "mining.authorize", "params": ["miner1", ""], "id": 1}"#;
+        let response =
+            String::from_utf8(dummy_request(&addr, &format!("{authorize}\n{ping}"))).unwrap();
+        assert_eq!(
+            format!(
+                "{}{}",
+                terminated_str(r#"{"jsonrpc":"2.0","result":true,"id":1}"#),
+                terminated_str(r#"{"jsonrpc":"2.0","result":"pong","id":3}"#)
+            ),
+            response
+        );
+        let stats = stratum.worker_stats();
+        assert_eq!(stats.len(), 1);
+        assert!(stats["miner1"].last_seen.is_some());
+    }
+
+    #[test]
+    fn stops_pushing_work_to_idle_workers() {
+        let addr = "127.0.0.1:19935".parse().unwrap();
+        let clock = TestClock::new();
+        let stratum = Stratum::start_with_clock(
+            &config(addr).build().unwrap(),
+            Arc::new(VoidManager),
+            Arc::new(SecretAuthorizer::new(None)),
+            Arc::new(clock.clone()),
+        )
+        .expect("There should be no error starting stratum");
+        stratum.set_idle_timeout(Some(time::Duration::from_secs(60)));
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Tokio Runtime should be created with no errors");
+
+        let responses = rt.block_on(async {
+            let mut streams = Vec::new();
+            for i in 0..2 {
+                let request = format!(
+                    "{{\"jsonrpc\": \"2.0\", \"method\": \"mining.authorize\", \"params\": [\"miner{i}\", \"\"], \"id\": 1}}\n"
+                );
+                let auth_response = "{\"jsonrpc\":\"2.0\",\"result\":true,\"id\":1}\n";
+                let mut stream = TcpStream::connect(&addr).await.expect("Failed to connect");
+                stream
+                    .write_all(request.as_bytes())
+                    .await
+                    .expect("Failed to write auth request");
+                let mut read_buf = vec![0u8; auth_response.len()];
+                stream
+                    .read_exact(&mut read_buf)
+                    .await
+                    .expect("Failed to read auth response");
+                assert_eq!(String::from_utf8(read_buf).unwrap(), auth_response);
+                streams.push(stream);
+            }
+
+            // only the second worker is heard from within the timeout
+            clock.advance(time::Duration::from_secs(30));
+            let ping = "{\"jsonrpc\": \"2.0\", \"method\": \"mining.ping\", \"params\": [], \"id\": 2}\n";
+            let pong = "{\"jsonrpc\":\"2.0\",\"result\":\"pong\",\"id\":2}\n";
+            streams[1]
+                .write_all(ping.as_bytes())
+                .await
+                .expect("Failed to write ping");
+            let mut read_buf = vec![0u8; pong.len()];
+            streams[1]
+                .read_exact(&mut read_buf)
+                .await
+                .expect("Failed to read pong");
+            assert_eq!(String::from_utf8(read_buf).unwrap(), pong);
+            clock.advance(time::Duration::from_secs(40));
+
+            stratum.push_work_all(r#"{ "00040008", "100500" }"#.to_owned());
+
+            let mut responses = Vec::new();
+            for mut stream in streams {
+                stream.shutdown().await.expect("Failed to shutdown write");
+                let mut read_buf = Vec::with_capacity(2048);
+                stream
+                    .read_to_end(&mut read_buf)
+                    .await
+                    .expect("Failed to read work");
+                responses.push(String::from_utf8(read_buf).expect("Response should be utf-8"));
+            }
+            responses
+        });
+
+        let work =
+            "{ \"id\": 17, \"method\": \"mining.notify\", \"params\": { \"00040008\", \"100500\" } }\n";
+        assert_eq!(responses, vec!["", work]);
+        let workers = stratum.implementation.workers.read();
+        assert_eq!(
+            workers.values().map(|w| w.id.as_str()).collect::<Vec<_>>(),
+            vec!["miner1"]
+        );
+        assert_eq!(
+            stratum
+                .implementation
+                .counters
+                .idle_workers_dropped
+                .load(Ordering::Relaxed),
+            1
+        );
+    }
+
+    #[test]
+    fn ignores_blank_keep_alive_lines() {
+        let addr = "127.0.0.1:19955".parse().unwrap();
+        let stratum =
+            Stratum::start_with_config(&config(addr).build().unwrap(), DummyManager::new())
+                .expect("There should be no error starting stratum");
+        let request =
+            "\n  \r\n{\"jsonrpc\": \"2.0\", \"method\": \"mining.subscribe\", \"params\": [], \"id\": 4}\n\n";
+
+        let response = String::from_utf8(dummy_request(&addr, request)).unwrap();
+
+        assert_eq!(
+            terminated_str(r#"{"jsonrpc":"2.0","result":["dummy payload"],"id":4}"#),
+            response
+        );
+        assert_eq!(1, stratum.implementation.subscribers.read().len());
+    }
+
+    fn submit_request(id: usize) -> String {
+        format!(
+            "{{\"jsonrpc\": \"2.0\", \"method\": \"mining.submit\", \"params\": [\"miner1\", \"job1\", \"0x01\"], \"id\": {id}}}\n"
+        )
+    }
+
+    /// Send `count` submits, each once the previous one is answered, `delay` apart. Returns the
+    /// responses received until the connection was closed.
+    async fn submit_in_turn(addr: SocketAddr, count: usize, delay: time::Duration) -> Vec<String> {
+        let stream = TcpStream::connect(&addr).await.expect("Failed to connect");
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut responses = Vec::new();
+        for id in 0..count {
+            if writer
+                .write_all(submit_request(id).as_bytes())
+                .await
+                .is_err()
+            {
+                break;
+            }
+            let mut response = String::new();
+            match reader.read_line(&mut response).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => responses.push(response),
+            }
+            time::sleep(delay).await;
+        }
+        responses
+    }
+
+    #[test]
+    fn flooding_client_is_rate_limited_and_dropped() {
+        let addr = "127.0.0.1:19950".parse().unwrap();
+        let limits = ConnectionLimits {
+            requests_per_second: 10,
+            burst: 5,
+            max_violations: 20,
+            ..Default::default()
+        };
+        let stratum = Stratum::start_with_config(
+            &config(addr).with_limits(limits).build().unwrap(),
+            Arc::new(VoidManager),
+        )
+        .expect("There should be no error starting stratum");
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Tokio Runtime should be created with no errors");
+        let (flooding, normal) = rt.block_on(futures::future::join(
+            submit_in_turn(addr, 1000, time::Duration::from_millis(0)),
+            submit_in_turn(addr, 5, time::Duration::from_millis(150)),
+        ));
+
+        let accepted = terminated_str(r#"{"jsonrpc":"2.0","result":true,"id":0}"#);
+        assert_eq!(flooding[0], accepted);
+        assert!(flooding.len() < 1000, "flooding client was not dropped");
+        assert!(flooding.iter().any(|r| r.contains("Rate limit exceeded")));
+        assert_eq!(normal.len(), 5);
+        assert!(normal.iter().all(|r| r.contains(r#""result":true"#)));
+
+        let stats = stratum.worker_stats();
+        assert!(stats
+            .values()
+            .any(|s| s.rate_limited > 20 && s.rate_limit_drops == 1));
+        assert!(stats
+            .values()
+            .any(|s| s.accepted == 5 && s.rate_limited == 0));
+    }
+
+    #[test]
+    fn oversized_request_drops_connection() {
+        let addr = "127.0.0.1:19945".parse().unwrap();
+        let limits = ConnectionLimits {
+            max_request_size: 256,
+            ..Default::default()
+        };
+        let stratum = Stratum::start_with_config(
+            &config(addr).with_limits(limits).build().unwrap(),
+            Arc::new(VoidManager),
+        )
+        .expect("There should be no error starting stratum");
+        let request = format!(
+            r#"{{"jsonrpc": "2.0", "method": "mining.ping", "params": ["{}"], "id": 5}}"#,
+            "0".repeat(1000)
+        );
+
+        let response = dummy_request(&addr, &request);
+
+        assert!(response.is_empty());
+        let stats = stratum.worker_stats();
+        assert_eq!(stats.values().map(|s| s.oversized_requests).sum::<u64>(), 1);
+        assert!(stats.values().all(|s| s.last_seen.is_none()));
+    }
+
+    struct SlowManager;
+
+    impl JobDispatcher for SlowManager {
+        fn submit_share(&self, _payload: Vec<String>, _job: JobStatus) -> Result<(), SubmitError> {
+            ::std::thread::sleep(::std::time::Duration::from_millis(200));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn submits_in_flight_are_capped() {
+        let addr = "127.0.0.1:19940".parse().unwrap();
+        let limits = ConnectionLimits {
+            max_inflight_submits: 1,
+            ..Default::default()
+        };
+        let stratum = Stratum::start_with_config(
+            &config(addr).with_limits(limits).build().unwrap(),
+            Arc::new(SlowManager),
+        )
+        .expect("There should be no error starting stratum");
+        let requests: String = (1..=3).map(submit_request).collect();
+
+        let response = String::from_utf8(dummy_request(&addr, &requests)).unwrap();
+
+        let refused = r#"{"jsonrpc":"2.0","error":{"code":-32005,"message":"Too many submits in flight"},"id":"#;
+        assert_eq!(
+            response,
+            format!(
+                "{{\"jsonrpc\":\"2.0\",\"result\":true,\"id\":1}}\n{refused}2}}\n{refused}3}}\n"
+            )
+        );
+        let stats = stratum.worker_stats();
+        assert_eq!(stats.values().map(|s| s.submits_over_limit).sum::<u64>(), 2);
+    }
+
+    fn render_metrics(stratum: &Stratum) -> String {
+        use stats::prometheus::{Encoder, TextEncoder};
+
+        let mut registry = PrometheusRegistry::new("".into()).unwrap();
+        stratum.prometheus_metrics(&mut registry);
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&registry.registry().gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn exports_prometheus_metrics() {
+        let addr = "127.0.0.1:19935".parse().unwrap();
+        let dispatcher = Arc::new(RejectingManager {
+            result: RwLock::new(Ok(())),
+        });
+        let stratum = Stratum::start_with_config(
+            &config(addr)
+                .with_secret(Some(keccak("secret")))
+                .build()
+                .unwrap(),
+            dispatcher.clone(),
+        )
+        .expect("There should be no error starting stratum");
+
+        let subscribe =
+            r#"{"jsonrpc": "2.0", "method": "mining.subscribe", "params": [], "id": 1}"#;
+        dummy_request(&addr, subscribe);
+        let authorize = r#"{"jsonrpc": "2.0", "method": "mining.authorize", "params": ["miner1", "secret"], "id": 2}"#;
+        dummy_request(&addr, authorize);
+        let bad_secret = r#"{"jsonrpc": "2.0", "method": "mining.authorize", "params": ["miner2", "wrong"], "id": 3}"#;
+        dummy_request(&addr, bad_secret);
+
+        let submit = r#"{"jsonrpc": "2.0", "method": "mining.submit", "params": ["miner1", "job1", "0x01"], "id": 4}"#;
+        dummy_request(&addr, submit);
+        *dispatcher.result.write() = Err(SubmitError::Stale);
+        dummy_request(&addr, submit);
+        dummy_request(&addr, submit);
+
+        // the authorized connection is closed already
+        stratum.push_work_all(r#"["job"]"#.to_owned());
+
+        let metrics = render_metrics(&stratum);
+        for line in [
+            "stratum_subscribers 1",
+            "stratum_jobs_broadcast 1",
+            "stratum_push_failures{kind=\"no_such_peer\"} 1",
+            "stratum_push_failures{kind=\"transport\"} 0",
+            "stratum_shares_accepted 1",
+            "stratum_shares_rejected 2",
+            "stratum_authorization_failures 1",
+        ] {
+            assert!(
+                metrics.lines().any(|l| l == line),
+                "{line} not in metrics:\n{metrics}"
+            );
+        }
+        // the worker is dropped once the push failed
+        assert!(metrics.lines().any(|l| l == "stratum_workers 0"));
+        let worker_shares = metrics
+            .lines()
+            .filter(|l| l.starts_with("stratum_worker_shares_"))
+            .count();
+        assert!(worker_shares >= 2);
+    }
+
     #[test]
     fn jsonprc_server_is_send_and_sync() {
         fn is_send_and_sync<T: Send + Sync>() {}
 
-        is_send_and_sync::<JsonRpcServer>();
+        is_send_and_sync::<TcpServer>();
     }
 }
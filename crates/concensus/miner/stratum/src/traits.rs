@@ -14,8 +14,8 @@
 // You should have received a copy of the GNU General Public License
 // along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
 
-use ethereum_types::H256;
-use jsonrpc_tcp_server::PushMessageError;
+use crate::{config::ConfigError, jobs::JobStatus, transport::PushMessageError};
+use std::net::SocketAddr;
 
 #[derive(Debug, Clone)]
 pub enum Error {
@@ -24,6 +24,7 @@ pub enum Error {
     Io(String),
     Tcp(String),
     Dispatch(String),
+    Config(ConfigError),
 }
 
 impl From<std::io::Error> for Error {
@@ -32,12 +33,66 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Self {
+        Error::Config(err)
+    }
+}
+
 impl From<PushMessageError> for Error {
     fn from(err: PushMessageError) -> Self {
         Error::Tcp(format!("Push message error: {err:?}"))
     }
 }
 
+/// Reason a share submitted by a miner was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubmitError {
+    /// Share for a job which is unknown or no longer current
+    Stale,
+    /// Share does not meet the job difficulty
+    LowDifficulty,
+    /// Share was already submitted
+    Duplicate,
+    /// Share is not a valid solution of the job
+    InvalidSolution,
+    /// Share from a worker which is not authorized
+    Unauthorized,
+    /// Any other reason
+    Other(String),
+}
+
+impl SubmitError {
+    /// Conventional stratum error code
+    pub fn code(&self) -> i64 {
+        match *self {
+            SubmitError::Stale => 21,
+            SubmitError::Duplicate => 22,
+            SubmitError::LowDifficulty => 23,
+            SubmitError::Unauthorized => 24,
+            SubmitError::InvalidSolution | SubmitError::Other(_) => 20,
+        }
+    }
+
+    /// Human readable message sent along with the error code
+    pub fn message(&self) -> &str {
+        match *self {
+            SubmitError::Stale => "Job not found",
+            SubmitError::Duplicate => "Duplicate share",
+            SubmitError::LowDifficulty => "Low difficulty share",
+            SubmitError::Unauthorized => "Unauthorized worker",
+            SubmitError::InvalidSolution => "Invalid solution",
+            SubmitError::Other(ref message) => message,
+        }
+    }
+}
+
+impl From<Error> for SubmitError {
+    fn from(err: Error) -> Self {
+        SubmitError::Other(format!("{err:?}"))
+    }
+}
+
 /// Interface that can provide pow/blockchain-specific responses for the clients
 pub trait JobDispatcher: Send + Sync {
     // json for initial client handshake
@@ -52,8 +107,33 @@ pub trait JobDispatcher: Send + Sync {
     fn job(&self) -> Option<String> {
         None
     }
-    // miner job result
-    fn submit(&self, payload: Vec<String>) -> Result<(), Error>;
+    // miner job result, kept for dispatchers which predate `submit_share`
+    fn submit(&self, _payload: Vec<String>) -> Result<(), Error> {
+        Err(Error::Dispatch("share submission is not supported".into()))
+    }
+    // miner job result for the job classified as `job`, `Error`s convert into `SubmitError::Other`.
+    // Goes to `submit` by default, whatever the job.
+    fn submit_share(&self, payload: Vec<String>, _job: JobStatus) -> Result<(), SubmitError> {
+        self.submit(payload).map_err(Into::into)
+    }
+}
+
+/// Outcome of a worker authorization
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthResult {
+    /// The worker may mine, with its own share difficulty if given
+    Allow { difficulty_override: Option<f64> },
+    /// The worker is refused, the reason is sent back as the error message
+    Deny { reason: String },
+}
+
+/// Interface deciding which workers may authorize
+///
+/// Called on the request path of every `mining.authorize`, so implementations should answer
+/// fast, e.g. from a cache kept up to date in the background. A panic denies the worker.
+pub trait WorkerAuthorizer: Send + Sync {
+    /// Authorize `worker_id` with `password`, connected from `addr`
+    fn authorize(&self, worker_id: &str, password: &str, addr: SocketAddr) -> AuthResult;
 }
 
 /// Interface that can handle requests to push job for workers
@@ -61,10 +141,3 @@ pub trait PushWorkHandler: Send + Sync {
     /// push the same work package for all workers (`payload`: json of pow-specific set of work specification)
     fn push_work_all(&self, payload: String);
 }
-
-pub struct ServiceConfiguration {
-    pub io_path: String,
-    pub listen_addr: String,
-    pub port: u16,
-    pub secret: Option<H256>,
-}
@@ -0,0 +1,516 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! TCP transport of the stratum server. Requests are split at newlines and checked against the
+//! limits of their connection before they reach the RPC handler.
+
+use crate::SocketMetadata;
+use jsonrpc_core::{
+    serde_from_str, Call, Error, ErrorCode, Failure, MetaIoHandler, Output, Request, Response,
+};
+use parity_runtime::Clock;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    io,
+    net::{SocketAddr, TcpListener as StdTcpListener},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    runtime::{self, Runtime},
+    sync::mpsc,
+};
+
+/// Error code of requests refused by the connection limits
+const LIMIT_EXCEEDED: i64 = -32005;
+
+/// Bytes read from a socket at once
+const READ_CHUNK: usize = 4096;
+
+/// Limits protecting the job dispatcher from a single connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConnectionLimits {
+    /// Largest request accepted, the connection is dropped when a request exceeds it
+    pub max_request_size: usize,
+    /// Requests per second allowed on average
+    pub requests_per_second: u32,
+    /// Requests allowed at once above the average rate
+    pub burst: u32,
+    /// Requests refused by the rate limit before the connection is dropped. The count is reset
+    /// once the connection stayed below the rate long enough to fill up the burst again.
+    pub max_violations: u32,
+    /// Submits received but not answered yet
+    pub max_inflight_submits: usize,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        ConnectionLimits {
+            max_request_size: 16 * 1024,
+            requests_per_second: 20,
+            burst: 10,
+            max_violations: 50,
+            max_inflight_submits: 8,
+        }
+    }
+}
+
+/// Action taken against a connection exceeding its limits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Enforcement {
+    /// Connection dropped for a request above the size limit
+    OversizedRequest,
+    /// Request refused by the rate limit
+    RateLimited,
+    /// Connection dropped for repeated rate limit violations
+    RateLimitDrop,
+    /// Submit refused because too many were in flight
+    TooManySubmits,
+}
+
+/// Notified of every enforcement action with the address of the connection
+pub type EnforcementHandler = Arc<dyn Fn(&SocketAddr, Enforcement) + Send + Sync>;
+
+/// Notified with the address of every connection once it is closed
+pub type DisconnectHandler = Arc<dyn Fn(&SocketAddr) + Send + Sync>;
+
+/// Error pushing a message to a peer
+#[derive(Debug)]
+pub enum PushMessageError {
+    /// The peer is not connected
+    NoSuchPeer,
+    /// The connection of the peer is closing and does not take messages anymore
+    Transport,
+}
+
+enum Outgoing {
+    /// Request to handle, with the number of submits it contains
+    Request(String, usize),
+    /// Message sent as is
+    Message(String),
+}
+
+/// Pushes messages to connected peers
+#[derive(Clone, Default)]
+pub struct Dispatcher {
+    peers: Arc<Mutex<HashMap<SocketAddr, mpsc::UnboundedSender<Outgoing>>>>,
+}
+
+impl Dispatcher {
+    /// Queue `message` for the peer connected from `peer_addr`, after the responses to the
+    /// requests it already sent.
+    pub fn push_message(
+        &self,
+        peer_addr: &SocketAddr,
+        message: String,
+    ) -> Result<(), PushMessageError> {
+        self.peers
+            .lock()
+            .get(peer_addr)
+            .ok_or(PushMessageError::NoSuchPeer)?
+            .send(Outgoing::Message(message))
+            .map_err(|_| PushMessageError::Transport)
+    }
+}
+
+struct Context {
+    handler: MetaIoHandler<SocketMetadata>,
+    dispatcher: Dispatcher,
+    limits: ConnectionLimits,
+    on_enforcement: EnforcementHandler,
+    on_disconnect: DisconnectHandler,
+    clock: Arc<dyn Clock>,
+}
+
+/// Stratum TCP server
+pub struct Server {
+    runtime: Option<Runtime>,
+    local_addrs: Vec<SocketAddr>,
+}
+
+impl Server {
+    /// Listen on every address of `addrs` and handle the requests of every connection with
+    /// `handler`. The connections of all listeners share `dispatcher`, their request rate is
+    /// measured with `clock`.
+    pub fn start(
+        addrs: &[SocketAddr],
+        handler: MetaIoHandler<SocketMetadata>,
+        dispatcher: Dispatcher,
+        limits: ConnectionLimits,
+        on_enforcement: EnforcementHandler,
+        on_disconnect: DisconnectHandler,
+        clock: Arc<dyn Clock>,
+    ) -> io::Result<Server> {
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no address to listen on",
+            ));
+        }
+        // bind all addresses before anything is served, so that none is left listening on error
+        let listeners = addrs
+            .iter()
+            .map(|addr| {
+                let listener = StdTcpListener::bind(addr)?;
+                listener.set_nonblocking(true)?;
+                Ok(listener)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let local_addrs = listeners
+            .iter()
+            .map(|listener| listener.local_addr())
+            .collect::<io::Result<Vec<_>>>()?;
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("stratum")
+            .enable_io()
+            .build()?;
+        let listeners = {
+            let _guard = runtime.enter();
+            listeners
+                .into_iter()
+                .map(TcpListener::from_std)
+                .collect::<io::Result<Vec<_>>>()?
+        };
+        let context = Arc::new(Context {
+            handler,
+            dispatcher,
+            limits,
+            on_enforcement,
+            on_disconnect,
+            clock,
+        });
+        for listener in listeners {
+            let context = context.clone();
+            runtime.spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((socket, peer_addr)) => {
+                            tokio::spawn(serve(socket, peer_addr, context.clone()));
+                        }
+                        Err(e) => warn!(target: "stratum", "Error accepting connection: {e}"),
+                    }
+                }
+            });
+        }
+        Ok(Server {
+            runtime: Some(runtime),
+            local_addrs,
+        })
+    }
+
+    /// Addresses listened on, with the ports assigned to the ones requested with port 0
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Stop listening and drop all connections
+    pub fn close(mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+async fn serve(socket: TcpStream, peer_addr: SocketAddr, context: Arc<Context>) {
+    trace!(target: "stratum", "New connection from {peer_addr}");
+    let (reader, writer) = socket.into_split();
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let inflight = Arc::new(AtomicUsize::new(0));
+    {
+        let mut peers = context.dispatcher.peers.lock();
+        // the peer may reuse its port for another of our listeners, messages pushed to the
+        // address could not tell the connections apart
+        if peers.contains_key(&peer_addr) {
+            warn!(target: "stratum", "Refused connection of {peer_addr}, already connected to another listener");
+            return;
+        }
+        peers.insert(peer_addr, sender.clone());
+    }
+    let meta = SocketMetadata {
+        addr: peer_addr,
+        tcp_dispatcher: Some(context.dispatcher.clone()),
+    };
+    let responses = tokio::spawn(respond(
+        writer,
+        receiver,
+        meta,
+        context.clone(),
+        inflight.clone(),
+    ));
+
+    if let Err(reason) = read(reader, &sender, &peer_addr, &context, &inflight).await {
+        warn!(target: "stratum", "Dropping connection of {peer_addr}: {reason}");
+    }
+
+    // the responses are sent once all senders are gone
+    context.dispatcher.peers.lock().remove(&peer_addr);
+    drop(sender);
+    let _ = responses.await;
+    (context.on_disconnect)(&peer_addr);
+    trace!(target: "stratum", "Connection of {peer_addr} closed");
+}
+
+/// Read the requests of a connection until it is closed or exceeds its limits.
+async fn read(
+    mut reader: OwnedReadHalf,
+    sender: &mpsc::UnboundedSender<Outgoing>,
+    peer_addr: &SocketAddr,
+    context: &Context,
+    inflight: &AtomicUsize,
+) -> Result<(), String> {
+    let limits = &context.limits;
+    let enforce = |action| (context.on_enforcement)(peer_addr, action);
+    let mut framer = Framer::default();
+    let mut rate_limit = RateLimit::new(limits, context.clock.now());
+    let mut chunk = [0u8; READ_CHUNK];
+    loop {
+        let read = reader.read(&mut chunk).await.map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Ok(());
+        }
+        framer.extend(&chunk[..read]);
+
+        while let Some(frame) = framer.next_frame() {
+            if frame.len() > limits.max_request_size {
+                enforce(Enforcement::OversizedRequest);
+                return Err(format!("request of {} bytes too large", frame.len()));
+            }
+            let request = match String::from_utf8(frame) {
+                Ok(request) => request,
+                Err(_) => {
+                    debug!(target: "stratum", "Ignored non UTF-8 request from {peer_addr}");
+                    continue;
+                }
+            };
+            let parsed = serde_from_str::<Request>(&request).ok();
+
+            let outgoing = if !rate_limit.allow(context.clock.now()) {
+                enforce(Enforcement::RateLimited);
+                if rate_limit.violations > limits.max_violations {
+                    enforce(Enforcement::RateLimitDrop);
+                    return Err("repeated rate limit violations".into());
+                }
+                trace!(target: "stratum", "Rate limited request from {peer_addr}");
+                refusal(parsed, "Rate limit exceeded")
+            } else {
+                let submits = parsed.as_ref().map_or(0, submits);
+                if submits > 0
+                    && inflight.load(Ordering::SeqCst) + submits > limits.max_inflight_submits
+                {
+                    enforce(Enforcement::TooManySubmits);
+                    trace!(target: "stratum", "Refused submit from {peer_addr}, too many in flight");
+                    refusal(parsed, "Too many submits in flight")
+                } else {
+                    inflight.fetch_add(submits, Ordering::SeqCst);
+                    Some(Outgoing::Request(request, submits))
+                }
+            };
+
+            if let Some(outgoing) = outgoing {
+                if sender.send(outgoing).is_err() {
+                    // the connection can't be written to anymore
+                    return Ok(());
+                }
+            }
+        }
+
+        if framer.pending() > limits.max_request_size {
+            enforce(Enforcement::OversizedRequest);
+            return Err(format!(
+                "request of more than {} bytes",
+                limits.max_request_size
+            ));
+        }
+    }
+}
+
+/// Handle the requests and send the responses and pushed messages in order.
+async fn respond(
+    mut writer: OwnedWriteHalf,
+    mut receiver: mpsc::UnboundedReceiver<Outgoing>,
+    meta: SocketMetadata,
+    context: Arc<Context>,
+    inflight: Arc<AtomicUsize>,
+) {
+    while let Some(outgoing) = receiver.recv().await {
+        let message = match outgoing {
+            Outgoing::Request(request, submits) => {
+                let response = context.handler.handle_request(&request, meta.clone()).await;
+                inflight.fetch_sub(submits, Ordering::SeqCst);
+                match response {
+                    Some(response) => response,
+                    None => continue,
+                }
+            }
+            Outgoing::Message(message) => message,
+        };
+        let mut bytes = message.into_bytes();
+        bytes.push(b'\n');
+        if let Err(e) = writer.write_all(&bytes).await {
+            debug!(target: "stratum", "Error writing to {}: {}", meta.addr(), e);
+            return;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+/// Number of submits in a request
+fn submits(request: &Request) -> usize {
+    let is_submit =
+        |call: &Call| matches!(call, Call::MethodCall(call) if call.method == "mining.submit");
+    match request {
+        Request::Single(call) => is_submit(call) as usize,
+        Request::Batch(calls) => calls.iter().filter(|call| is_submit(call)).count(),
+    }
+}
+
+/// Error response to every call of a request refused without handling it
+fn refusal(request: Option<Request>, message: &str) -> Option<Outgoing> {
+    let error = Error {
+        code: ErrorCode::ServerError(LIMIT_EXCEEDED),
+        message: message.into(),
+        data: None,
+    };
+    let failure = |call: Call| match call {
+        Call::MethodCall(call) => Some(Output::Failure(Failure {
+            jsonrpc: call.jsonrpc,
+            error: error.clone(),
+            id: call.id,
+        })),
+        Call::Notification(_) => None,
+        Call::Invalid { id } => Some(Output::Failure(Failure {
+            jsonrpc: None,
+            error: error.clone(),
+            id,
+        })),
+    };
+    let response = match request {
+        Some(Request::Single(call)) => Response::Single(failure(call)?),
+        Some(Request::Batch(calls)) => {
+            let outputs: Vec<_> = calls.into_iter().filter_map(failure).collect();
+            if outputs.is_empty() {
+                return None;
+            }
+            Response::Batch(outputs)
+        }
+        None => Response::from(error.clone(), None),
+    };
+    let message = serde_json::to_string(&response).expect("Responses are always serializable; qed");
+    Some(Outgoing::Message(message))
+}
+
+/// Token bucket limiting the request rate of a connection
+struct RateLimit {
+    tokens: f64,
+    rate: f64,
+    burst: f64,
+    last: Instant,
+    /// Requests refused since the bucket was full the last time
+    violations: u32,
+}
+
+impl RateLimit {
+    fn new(limits: &ConnectionLimits, now: Instant) -> Self {
+        let burst = f64::from(limits.burst.max(1));
+        RateLimit {
+            tokens: burst,
+            rate: f64::from(limits.requests_per_second),
+            burst,
+            last: now,
+            violations: 0,
+        }
+    }
+
+    /// Take a token for a request made at `now`. Returns false if there is none left.
+    fn allow(&mut self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last = now;
+        if self.tokens >= self.burst {
+            self.violations = 0;
+        }
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            self.violations += 1;
+            false
+        }
+    }
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b'\r' | b'\n' | b' ' | b'\t')
+}
+
+/// Splits the inbound bytes of a connection into newline terminated requests. Blank lines, sent
+/// as keep-alives by some firmwares, are skipped instead of answered with parse errors.
+#[derive(Default)]
+struct Framer {
+    buf: Vec<u8>,
+    /// Bytes of `buf` scanned for a newline so far
+    scanned: usize,
+}
+
+impl Framer {
+    fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Bytes of an incomplete request
+    fn pending(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Next complete request, if any.
+    fn next_frame(&mut self) -> Option<Vec<u8>> {
+        while let Some(end) = self.buf[self.scanned..].iter().position(|b| *b == b'\n') {
+            self.scanned += end + 1;
+            let frame = self.take(1);
+            if !frame.iter().all(|b| is_whitespace(*b)) {
+                return Some(frame);
+            }
+        }
+        self.scanned = self.buf.len();
+        None
+    }
+
+    /// Remove the scanned bytes, returning them without the `trailing` ones.
+    fn take(&mut self, trailing: usize) -> Vec<u8> {
+        let mut frame: Vec<u8> = self.buf.drain(..self.scanned).collect();
+        frame.truncate(self.scanned - trailing);
+        self.scanned = 0;
+        frame
+    }
+}
@@ -430,22 +430,22 @@ impl KeyValueDB for DatabaseWithMetrics {}
 
 impl PrometheusMetrics for DatabaseWithMetrics {
     fn prometheus_metrics(&self, p: &mut PrometheusRegistry) {
-        p.register_counter(
+        let _ = p.register_counter(
             "kvdb_reads",
             "db reads",
             self.reads.load(std::sync::atomic::Ordering::Relaxed) as i64,
         );
-        p.register_counter(
+        let _ = p.register_counter(
             "kvdb_writes",
             "db writes",
             self.writes.load(std::sync::atomic::Ordering::Relaxed) as i64,
         );
-        p.register_counter(
+        let _ = p.register_counter(
             "kvdb_bytes_read",
             "db bytes_reads",
             self.bytes_read.load(std::sync::atomic::Ordering::Relaxed) as i64,
         );
-        p.register_counter(
+        let _ = p.register_counter(
             "kvdb_bytes_written",
             "db bytes_written",
             self.bytes_written
@@ -0,0 +1,89 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Benchmark of a contract calling itself recursively, each frame with its own interpreter.
+//!
+//! Run with `cargo bench -p ethcore --features test-helpers --bench nested_calls`.
+
+#[macro_use]
+extern crate criterion;
+extern crate ethcore;
+extern crate ethereum_types;
+extern crate rustc_hex;
+extern crate vm;
+
+use std::sync::Arc;
+
+use criterion::{black_box, Criterion};
+use ethcore::{
+    ethereum::new_byzantium_test_machine,
+    executive::Executive,
+    state::{State, Substate},
+    state_db::StateDB,
+    test_helpers::get_temp_state,
+    trace::{NoopTracer, NoopVMTracer},
+};
+use ethereum_types::{Address, BigEndianHash, H256, U256};
+use rustc_hex::FromHex;
+use vm::{ActionParams, EnvInfo};
+
+criterion_group!(nested_calls, recursive_calls_32);
+criterion_main!(nested_calls);
+
+// Calls itself with the depth left as the call data, writing it to memory first. The frame at
+// the bottom stores 1 at slot 0.
+//
+// PUSH1 0 CALLDATALOAD DUP1 ISZERO PUSH1 0x1e JUMPI
+// PUSH1 1 SWAP1 SUB PUSH1 0 MSTORE
+// PUSH1 0 PUSH1 0 PUSH1 32 PUSH1 0 PUSH1 0 ADDRESS GAS CALL POP STOP
+// JUMPDEST PUSH1 1 PUSH1 0 SSTORE
+const RECURSIVE_CALL: &str =
+    "6000358015601e576001900360005260006000602060006000305af150005b6001600055";
+
+fn recursive_calls_32(c: &mut Criterion) {
+    let machine = new_byzantium_test_machine();
+    let info = EnvInfo::default();
+    let schedule = machine.schedule(info.number);
+    let address = Address::from_low_u64_be(0x1000);
+    let code: Arc<Vec<u8>> = Arc::new(RECURSIVE_CALL.from_hex().unwrap());
+    let mut state = get_temp_state();
+    state.init_code(&address, code.to_vec()).unwrap();
+
+    let call = |state: &mut State<StateDB>| {
+        let mut params = ActionParams::default();
+        params.address = address;
+        params.code_address = address;
+        params.gas = U256::from(10_000_000);
+        params.code = Some(code.clone());
+        params.data = Some(H256::from_uint(&U256::from(31)).as_bytes().to_vec());
+        let mut substate = Substate::new();
+        let mut executive = Executive::new(state, &info, &machine, &schedule);
+        executive
+            .call(params, &mut substate, &mut NoopTracer, &mut NoopVMTracer)
+            .unwrap()
+    };
+
+    // all 32 frames ran
+    call(&mut state);
+    assert_eq!(
+        state.storage_at(&address, &H256::zero()).unwrap(),
+        BigEndianHash::from_uint(&U256::one())
+    );
+
+    c.bench_function("recursive_calls_32", |b| {
+        b.iter(|| black_box(call(&mut state)))
+    });
+}
@@ -629,6 +629,13 @@ impl<'a> Iterator for EpochTransitionIter<'a> {
     }
 }
 
+/// Key of the number to hash index of blocks stored with headers only.
+fn header_only_key(number: BlockNumber) -> [u8; 9] {
+    let mut key = [b'h'; 9];
+    key[1..].copy_from_slice(&number.to_be_bytes());
+    key
+}
+
 impl BlockChain {
     /// Create new instance of blockchain from given Genesis.
     pub fn new(
@@ -1143,6 +1150,86 @@ impl BlockChain {
         }
     }
 
+    /// Replaces the body and receipts of a known block of the canonical chain, e.g. to repair
+    /// ancient blocks whose stored data does not match the header.
+    /// Returns false if the block is not in the canonical chain.
+    pub fn repair_block(
+        &self,
+        batch: &mut DBTransaction,
+        block: encoded::Block,
+        receipts: Vec<TypedReceipt>,
+    ) -> bool {
+        let hash = block.header_view().hash();
+        let number = block.header_view().number();
+        let total_difficulty = match self.block_details(&hash) {
+            Some(details) if self.block_hash(number) == Some(hash) => details.total_difficulty,
+            _ => return false,
+        };
+
+        assert!(self.pending_best_block.read().is_none());
+
+        let info = BlockInfo {
+            hash,
+            number,
+            total_difficulty,
+            location: BlockLocation::CanonChain,
+        };
+        // drop the addresses of the transactions in the replaced body.
+        let mut transactions_addresses: HashMap<_, _> = self
+            .block_body(&hash)
+            .map(|body| body.transaction_hashes())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|tx_hash| (tx_hash, None))
+            .collect();
+        transactions_addresses.extend(
+            self.prepare_transaction_addresses_update(block.view().transaction_hashes(), &info),
+        );
+
+        let compressed_body = compress(&Self::block_to_body(block.raw()), blocks_swapper());
+        batch.put(db::COL_BODIES, hash.as_bytes(), &compressed_body);
+        self.block_bodies.write().remove(&hash);
+
+        let mut write_receipts = self.block_receipts.write();
+        batch.extend_with_cache(
+            db::COL_EXTRA,
+            &mut *write_receipts,
+            self.prepare_block_receipts_update(receipts, &info),
+            CacheUpdatePolicy::Remove,
+        );
+        let mut write_txs = self.pending_transaction_addresses.write();
+        batch.extend_with_option_cache(
+            db::COL_EXTRA,
+            &mut *write_txs,
+            transactions_addresses,
+            CacheUpdatePolicy::Overwrite,
+        );
+        true
+    }
+
+    /// Stores the header of a block whose body and receipts are not downloaded, e.g. when
+    /// backfilling headers from a trusted checkpoint. The block does not become known, its header
+    /// can be looked up by hash and its hash by number through `header_only_hash`.
+    pub fn insert_header_only(&self, batch: &mut DBTransaction, header: &encoded::Header) {
+        let hash = header.hash();
+        let compressed_header = compress(header.rlp().as_raw(), blocks_swapper());
+        batch.put(db::COL_HEADERS, hash.as_bytes(), &compressed_header);
+        batch.put(
+            db::COL_EXTRA,
+            &header_only_key(header.number()),
+            hash.as_bytes(),
+        );
+    }
+
+    /// Hash of the block with the given number stored by `insert_header_only`.
+    pub fn header_only_hash(&self, number: BlockNumber) -> Option<H256> {
+        self.db
+            .key_value()
+            .get(db::COL_EXTRA, &header_only_key(number))
+            .expect("Low level database error. Some issue with disk?")
+            .map(|hash| H256::from_slice(&hash))
+    }
+
     /// Insert an epoch transition. Provide an epoch number being transitioned to
     /// and epoch transition object.
     ///
@@ -1930,6 +2017,8 @@ impl BlockChain {
             first_block_number,
             ancient_block_hash: best_ancient_block.as_ref().map(|b| b.hash),
             ancient_block_number: best_ancient_block.as_ref().map(|b| b.number),
+            // state pruning keeps every block, no blocks are deleted
+            retention_boundary: None,
         }
     }
 }
@@ -2255,6 +2344,87 @@ mod tests {
         assert_eq!(bc.transaction_address(&t1_hash), None);
     }
 
+    #[test]
+    fn repair_block_replaces_body_and_receipts() {
+        let t1 = TypedTransaction::Legacy(Transaction {
+            nonce: 0.into(),
+            gas_price: 0.into(),
+            gas: 100_000.into(),
+            action: Action::Create,
+            value: 100.into(),
+            data: vec![],
+        })
+        .sign(&secret(), None);
+        let t1_hash = t1.hash();
+
+        let genesis = BlockBuilder::genesis();
+        let b1 = genesis.add_block_with_transactions(iter::once(t1));
+        let b1_hash = b1.last().hash();
+
+        let db = new_db();
+        let bc = new_chain(
+            genesis.last().encoded(),
+            db.clone(),
+            BlockNumber::max_value(),
+        );
+        insert_block(&db, &bc, b1.last().encoded(), vec![]);
+        assert!(bc.transaction_address(&t1_hash).is_some());
+
+        // same header, no transactions
+        let block = b1.last().encoded();
+        let mut stream = RlpStream::new_list(3);
+        stream.append_raw(block.header_view().rlp().as_raw(), 1);
+        stream.append_raw(&rlp::EMPTY_LIST_RLP, 1);
+        stream.append_raw(block.rlp().at(2).unwrap().as_raw(), 1);
+        let repaired = encoded::Block::new(stream.out());
+        let receipt = TypedReceipt::Legacy(LegacyReceipt {
+            outcome: TransactionOutcome::StateRoot(H256::default()),
+            gas_used: 21_000.into(),
+            log_bloom: Default::default(),
+            logs: vec![],
+        });
+
+        let mut batch = db.key_value().transaction();
+        assert!(bc.repair_block(&mut batch, repaired, vec![receipt.clone()]));
+        db.key_value().write(batch).unwrap();
+        bc.commit();
+
+        assert_eq!(bc.block_body(&b1_hash).unwrap().transactions_count(), 0);
+        assert_eq!(bc.transaction_address(&t1_hash), None);
+        assert_eq!(bc.block_receipts(&b1_hash).unwrap().receipts, vec![receipt]);
+
+        // blocks which are not in the chain are left alone
+        let unknown = genesis.add_block_with_difficulty(9).last().encoded();
+        let mut batch = db.key_value().transaction();
+        assert!(!bc.repair_block(&mut batch, unknown, vec![]));
+    }
+
+    #[test]
+    fn header_only_blocks_have_no_body() {
+        let genesis = BlockBuilder::genesis();
+        let b1 = genesis.add_block();
+        let b2 = b1.add_block();
+        let b2_hash = b2.last().hash();
+
+        let db = new_db();
+        let bc = new_chain(
+            genesis.last().encoded(),
+            db.clone(),
+            BlockNumber::max_value(),
+        );
+        let header = b2.last().encoded().header();
+        let mut batch = db.key_value().transaction();
+        bc.insert_header_only(&mut batch, &header);
+        db.key_value().write(batch).unwrap();
+
+        assert_eq!(bc.header_only_hash(2), Some(b2_hash));
+        assert_eq!(bc.header_only_hash(1), None);
+        assert_eq!(bc.block_header_data(&b2_hash), Some(header));
+        assert!(bc.block_body(&b2_hash).is_none());
+        assert!(!bc.is_known(&b2_hash));
+        assert_eq!(bc.block_hash(2), None);
+    }
+
     #[test]
     fn test_overwriting_transaction_addresses() {
         let t1 = TypedTransaction::Legacy(Transaction {
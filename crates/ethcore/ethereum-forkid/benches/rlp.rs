@@ -0,0 +1,36 @@
+//! Encoding of a fork id into a status-like RLP list, through `rlp::Encodable` and through
+//! `ForkId::append_to_rlp`. Reported as encodes per second.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use ethereum_forkid::{ForkHash, ForkId};
+use rlp::RlpStream;
+
+const FORK_ID: ForkId = ForkId {
+    hash: ForkHash(0xa00b_c324),
+    next: 7_280_000,
+};
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fork_id_rlp");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("encodable", |b| {
+        b.iter(|| {
+            let mut stream = RlpStream::new_list(2);
+            stream.append(&64u32);
+            stream.append(black_box(&FORK_ID));
+            black_box(stream.out())
+        })
+    });
+    group.bench_function("append_to_rlp", |b| {
+        b.iter(|| {
+            let mut stream = RlpStream::new_list(2);
+            stream.append(&64u32);
+            black_box(&FORK_ID).append_to_rlp(&mut stream);
+            black_box(stream.out())
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, encode);
+criterion_main!(benches);
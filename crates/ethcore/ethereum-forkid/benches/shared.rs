@@ -0,0 +1,109 @@
+//! Validation of fork ids by several threads at once while the head advances on another one, through
+//! a locked `ForkFilter` and through a `SharedForkFilter`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use ethereum_forkid::{ForkFilter, ForkHash, ForkId, SharedForkFilter};
+use ethereum_types::H256;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const FORKS: [u64; 6] = [
+    1_150_000, 1_920_000, 2_463_000, 2_675_000, 4_370_000, 7_280_000,
+];
+
+const READERS: usize = 4;
+
+const FORK_ID: ForkId = ForkId {
+    hash: ForkHash(0xa00b_c324),
+    next: 7_280_000,
+};
+
+fn filter() -> ForkFilter {
+    ForkFilter::new(7_279_000, H256::repeat_byte(1), FORKS.iter().copied())
+}
+
+/// Run `read` on `READERS` threads while another thread keeps moving the head across the last fork
+/// with `set_head`. Reports the wall time until every reader is done.
+fn with_writer<S, W, R>(c: &mut Criterion, name: &str, state: S, set_head: W, read: R)
+where
+    S: Send + Sync + 'static,
+    W: Fn(&S, u64) + Send + 'static,
+    R: Fn(&S) + Send + Sync + 'static,
+{
+    let read = Arc::new(read);
+    let state = Arc::new(state);
+    let done = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let (state, done) = (state.clone(), done.clone());
+        thread::spawn(move || {
+            let mut head = 7_279_990;
+            while !done.load(Ordering::Relaxed) {
+                head = if head == 7_280_010 {
+                    7_279_990
+                } else {
+                    head + 1
+                };
+                set_head(&state, head);
+                thread::sleep(Duration::from_micros(10));
+            }
+        })
+    };
+
+    c.bench_function(name, |b| {
+        b.iter_custom(|iters| {
+            let start = Instant::now();
+            let readers = (0..READERS)
+                .map(|_| {
+                    let (state, read) = (state.clone(), read.clone());
+                    thread::spawn(move || {
+                        for _ in 0..iters {
+                            read(&state);
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            for reader in readers {
+                reader.join().unwrap();
+            }
+            start.elapsed()
+        });
+    });
+
+    done.store(true, Ordering::Relaxed);
+    writer.join().unwrap();
+}
+
+fn locked_filter(c: &mut Criterion) {
+    with_writer(
+        c,
+        "locked_filter_is_compatible",
+        Mutex::new(filter()),
+        |filter, head| filter.lock().unwrap().set_head(head),
+        |filter| {
+            black_box(filter.lock().unwrap().is_compatible(black_box(FORK_ID))).ok();
+        },
+    );
+}
+
+fn shared_filter(c: &mut Criterion) {
+    with_writer(
+        c,
+        "shared_filter_is_compatible",
+        SharedForkFilter::new(filter()),
+        |filter, head| {
+            filter.set_head(head);
+        },
+        |filter| {
+            black_box(filter.is_compatible(black_box(FORK_ID))).ok();
+        },
+    );
+}
+
+criterion_group!(benches, locked_filter, shared_filter);
+criterion_main!(benches);
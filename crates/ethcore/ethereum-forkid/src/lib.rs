@@ -4,13 +4,19 @@
 #![warn(clippy::all, clippy::pedantic, clippy::nursery)]
 #![allow(clippy::too_many_lines)]
 
+use arc_swap::ArcSwap;
 use crc::crc32;
 use ethereum_types::H256;
 use maplit::btreemap;
 use parity_util_mem::MallocSizeOf;
 use rlp::{DecoderError, Rlp, RlpStream};
 use rlp_derive::{RlpDecodable, RlpEncodable};
-use std::collections::{BTreeMap, BTreeSet};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
 
 /// Block number.
 pub type BlockNumber = u64;
@@ -46,6 +52,67 @@ impl From<H256> for ForkHash {
     }
 }
 
+/// Formats as `0x`-prefixed, zero-padded 8-digit lowercase hex, e.g. `0x668db0af`.
+impl fmt::Display for ForkHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{self:#x}")
+    }
+}
+
+/// Formats as zero-padded 8-digit lowercase hex; the alternate flag (`{:#x}`) adds `0x`.
+impl fmt::LowerHex for ForkHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "0x")?;
+        }
+        write!(f, "{:08x}", self.0)
+    }
+}
+
+/// Error returned when parsing a `ForkHash` from a string fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ParseForkHashError {
+    /// The string (without `0x` prefix) does not consist of exactly 8 characters.
+    InvalidLength(usize),
+    /// The string contains a character which is not a hex digit.
+    InvalidCharacter(char),
+}
+
+impl fmt::Display for ParseForkHashError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::InvalidLength(len) => {
+                write!(f, "invalid fork hash length: expected 8 hex digits, got {len}")
+            }
+            Self::InvalidCharacter(c) => write!(f, "invalid hex character {c:?} in fork hash"),
+        }
+    }
+}
+
+impl std::error::Error for ParseForkHashError {}
+
+/// Parses exactly 8 hex digits, with or without a `0x` prefix.
+impl FromStr for ForkHash {
+    type Err = ParseForkHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s
+            .strip_prefix("0x")
+            .or_else(|| s.strip_prefix("0X"))
+            .unwrap_or(s);
+        if let Some(c) = digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+            return Err(ParseForkHashError::InvalidCharacter(c));
+        }
+        if digits.len() != 8 {
+            return Err(ParseForkHashError::InvalidLength(digits.len()));
+        }
+
+        u32::from_str_radix(digits, 16)
+            .map(Self)
+            .map_err(|_| ParseForkHashError::InvalidLength(digits.len()))
+    }
+}
+
 impl std::ops::AddAssign<BlockNumber> for ForkHash {
     fn add_assign(&mut self, block: BlockNumber) {
         let blob = block.to_be_bytes();
@@ -71,6 +138,43 @@ pub struct ForkId {
     pub next: BlockNumber,
 }
 
+impl ForkId {
+    /// Append the fork id to `stream` as a single item, encoded exactly as `rlp::Encodable` does.
+    /// The encoding is assembled on the stack and copied into the stream at once, for the status
+    /// packet sent to every connecting peer.
+    // the lengths are at most 14
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn append_to_rlp(&self, stream: &mut RlpStream) {
+        // list prefix, 4 byte hash with its prefix, up to 8 bytes of `next` with its prefix
+        let mut out = [0u8; 1 + 5 + 9];
+        out[1] = 0x80 + 4;
+        out[2..6].copy_from_slice(&self.hash.0.to_be_bytes());
+        let next = self.next.to_be_bytes();
+        let next = &next[self.next.leading_zeros() as usize / 8..];
+        let len = match *next {
+            // a single byte below 0x80 is its own encoding
+            [byte] if byte < 0x80 => {
+                out[6] = byte;
+                7
+            }
+            _ => {
+                out[6] = 0x80 + next.len() as u8;
+                out[7..7 + next.len()].copy_from_slice(next);
+                7 + next.len()
+            }
+        };
+        out[0] = 0xc0 + (len - 1) as u8;
+        stream.append_raw(&out[..len], 1);
+    }
+}
+
+/// Formats in the compact `<hash>@<next>` form, e.g. `0x668db0af@0`.
+impl fmt::Display for ForkId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{}", self.hash, self.next)
+    }
+}
+
 /// Reason for rejecting provided `ForkId`.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum RejectReason {
@@ -194,53 +298,218 @@ impl ForkFilter {
         self.cache.fork_id
     }
 
+    /// Return the block of the next fork after the current head, if any is known.
+    #[must_use]
+    pub const fn next_fork(&self) -> Option<BlockNumber> {
+        self.cache.epoch_end
+    }
+
+    /// Return the fork id of every epoch from genesis on, together with the block the epoch starts at.
+    /// The `next` of the last fork id is 0.
+    #[must_use]
+    pub fn fork_id_history(&self) -> Vec<(BlockNumber, ForkId)> {
+        let mut history = Vec::with_capacity(self.forks.len());
+        let mut forks = self.forks.iter().peekable();
+        while let Some((block, hash)) = forks.next() {
+            let next = forks.peek().map_or(0, |(next, _)| **next);
+            history.push((*block, ForkId { hash: *hash, next }));
+        }
+        history
+    }
+
     /// Check whether the provided `ForkId` is compatible based on the validation rules in `EIP-2124`.
     ///
     /// # Errors
     /// Returns a `RejectReason` if the `ForkId` is not compatible.
     pub fn is_compatible(&self, fork_id: ForkId) -> Result<(), RejectReason> {
-        // 1) If local and remote FORK_HASH matches...
-        if self.current().hash == fork_id.hash {
-            if fork_id.next == 0 {
-                // 1b) No remotely announced fork, connect.
-                return Ok(());
-            }
+        validate(
+            fork_id,
+            self.head,
+            self.cache.fork_id,
+            &self.cache.past,
+            &self.cache.future,
+        )
+    }
 
-            //... compare local head to FORK_NEXT.
-            if self.head >= fork_id.next {
-                // 1a) A remotely announced but remotely not passed block is already passed locally, disconnect,
-                // since the chains are incompatible.
-                return Err(RejectReason::LocalIncompatibleOrStale);
-            }
-            // 1b) Remotely announced fork not yet passed locally, connect.
+    /// Snapshot of the state `is_compatible` depends on.
+    #[must_use]
+    pub fn view(&self) -> ValidationView {
+        ValidationView {
+            head: self.head,
+            fork_id: self.cache.fork_id,
+            past: self.cache.past.clone(),
+            future: self.cache.future.clone(),
+        }
+    }
+}
+
+fn validate(
+    fork_id: ForkId,
+    head: BlockNumber,
+    current: ForkId,
+    past: &[(BlockNumber, ForkHash)],
+    future: &[ForkHash],
+) -> Result<(), RejectReason> {
+    // 1) If local and remote FORK_HASH matches...
+    if current.hash == fork_id.hash {
+        if fork_id.next == 0 {
+            // 1b) No remotely announced fork, connect.
             return Ok(());
         }
 
-        // 2) If the remote FORK_HASH is a subset of the local past forks...
-        let mut it = self.cache.past.iter();
-        while let Some((_, hash)) = it.next() {
-            if *hash == fork_id.hash {
-                // ...and the remote FORK_NEXT matches with the locally following fork block number, connect.
-                if let Some((actual_fork_block, _)) = it.next() {
-                    if *actual_fork_block == fork_id.next {
-                        return Ok(());
-                    }
-                    return Err(RejectReason::RemoteStale);
-                }
+        //... compare local head to FORK_NEXT.
+        if head >= fork_id.next {
+            // 1a) A remotely announced but remotely not passed block is already passed locally, disconnect,
+            // since the chains are incompatible.
+            return Err(RejectReason::LocalIncompatibleOrStale);
+        }
+        // 1b) Remotely announced fork not yet passed locally, connect.
+        return Ok(());
+    }
 
-                break;
+    // 2) If the remote FORK_HASH is a subset of the local past forks...
+    let mut it = past.iter();
+    while let Some((_, hash)) = it.next() {
+        if *hash == fork_id.hash {
+            // ...and the remote FORK_NEXT matches with the locally following fork block number, connect.
+            if let Some((actual_fork_block, _)) = it.next() {
+                if *actual_fork_block == fork_id.next {
+                    return Ok(());
+                }
+                return Err(RejectReason::RemoteStale);
             }
+
+            break;
         }
+    }
 
-        // 3) If the remote FORK_HASH is a superset of the local past forks and can be completed with locally known future forks, connect.
-        for future_fork_hash in &self.cache.future {
-            if *future_fork_hash == fork_id.hash {
-                return Ok(());
-            }
+    // 3) If the remote FORK_HASH is a superset of the local past forks and can be completed with locally known future forks, connect.
+    for future_fork_hash in future {
+        if *future_fork_hash == fork_id.hash {
+            return Ok(());
+        }
+    }
+
+    // 4) Reject in all other cases.
+    Err(RejectReason::LocalIncompatibleOrStale)
+}
+
+/// Immutable snapshot of a `ForkFilter` at some head, carrying everything needed to validate fork ids.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationView {
+    head: BlockNumber,
+    fork_id: ForkId,
+    past: Vec<(BlockNumber, ForkHash)>,
+    future: Vec<ForkHash>,
+}
+
+impl ValidationView {
+    /// Head the view was taken at.
+    #[must_use]
+    pub const fn head(&self) -> BlockNumber {
+        self.head
+    }
+
+    /// Fork id at the head of the view.
+    #[must_use]
+    pub const fn current(&self) -> ForkId {
+        self.fork_id
+    }
+
+    /// Forks passed at the head of the view, from genesis on, with the fork hash of the epoch they start.
+    #[must_use]
+    pub fn past(&self) -> &[(BlockNumber, ForkHash)] {
+        &self.past
+    }
+
+    /// Fork hashes of the epochs after the head of the view.
+    #[must_use]
+    pub fn future(&self) -> &[ForkHash] {
+        &self.future
+    }
+
+    /// Check whether the provided `ForkId` is compatible based on the validation rules in `EIP-2124`.
+    ///
+    /// # Errors
+    /// Returns a `RejectReason` if the `ForkId` is not compatible.
+    pub fn is_compatible(&self, fork_id: ForkId) -> Result<(), RejectReason> {
+        validate(fork_id, self.head, self.fork_id, &self.past, &self.future)
+    }
+}
+
+/// `ForkFilter` shared between threads.
+///
+/// Readers load the latest `ValidationView` without locking, writers update the filter under a lock
+/// and publish a fresh view of it.
+#[derive(Debug)]
+pub struct SharedForkFilter {
+    filter: Mutex<ForkFilter>,
+    view: ArcSwap<ValidationView>,
+}
+
+impl SharedForkFilter {
+    /// Share `filter`.
+    #[must_use]
+    pub fn new(filter: ForkFilter) -> Self {
+        let view = ArcSwap::from_pointee(filter.view());
+        Self {
+            filter: Mutex::new(filter),
+            view,
+        }
+    }
+
+    fn filter(&self) -> MutexGuard<'_, ForkFilter> {
+        self.filter.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Latest view of the filter.
+    #[must_use]
+    pub fn view(&self) -> Arc<ValidationView> {
+        self.view.load_full()
+    }
+
+    /// Return current fork id
+    #[must_use]
+    pub fn current(&self) -> ForkId {
+        self.view.load().current()
+    }
+
+    /// Validate `fork_id` against the latest view, see `ValidationView::is_compatible`.
+    ///
+    /// # Errors
+    /// Returns a `RejectReason` if the `ForkId` is not compatible.
+    pub fn is_compatible(&self, fork_id: ForkId) -> Result<(), RejectReason> {
+        self.view.load().is_compatible(fork_id)
+    }
+
+    /// Set the current head and return the view at it.
+    pub fn set_head(&self, head: BlockNumber) -> Arc<ValidationView> {
+        let mut filter = self.filter();
+        if filter.head == head {
+            return self.view();
         }
+        filter.set_head(head);
+        let view = Arc::new(filter.view());
+        // publish while still holding the lock, so views are stored in the order of the updates
+        self.view.store(view.clone());
+        drop(filter);
+        view
+    }
 
-        // 4) Reject in all other cases.
-        Err(RejectReason::LocalIncompatibleOrStale)
+    /// Replace the filter, e.g. after the set of forks changed, and return the view of the new one.
+    pub fn replace(&self, new: ForkFilter) -> Arc<ValidationView> {
+        let mut filter = self.filter();
+        let view = Arc::new(new.view());
+        *filter = new;
+        self.view.store(view.clone());
+        drop(filter);
+        view
+    }
+
+    /// Copy of the filter.
+    #[must_use]
+    pub fn filter_snapshot(&self) -> ForkFilter {
+        self.filter().clone()
     }
 }
 
@@ -267,6 +536,50 @@ mod tests {
         assert_eq!(fork_hash.0, 0x91d1_f948);
     }
 
+    #[test]
+    fn forkhash_display_roundtrip() {
+        for (value, text) in [
+            (0, "0x00000000"),
+            (0x668d_b0af, "0x668db0af"),
+            (u32::MAX, "0xffffffff"),
+        ] {
+            let hash = ForkHash(value);
+            assert_eq!(hash.to_string(), text);
+            assert_eq!(format!("{hash:x}"), &text[2..]);
+            assert_eq!(text.parse::<ForkHash>(), Ok(hash));
+            assert_eq!(text[2..].parse::<ForkHash>(), Ok(hash));
+        }
+    }
+
+    #[test]
+    fn forkhash_from_str_rejects_malformed() {
+        assert_eq!(
+            "0x668db0a".parse::<ForkHash>(),
+            Err(ParseForkHashError::InvalidLength(7))
+        );
+        assert_eq!(
+            "0x668db0af0".parse::<ForkHash>(),
+            Err(ParseForkHashError::InvalidLength(9))
+        );
+        assert_eq!(
+            "0xgggggggg".parse::<ForkHash>(),
+            Err(ParseForkHashError::InvalidCharacter('g'))
+        );
+        assert_eq!(
+            "+668db0a".parse::<ForkHash>(),
+            Err(ParseForkHashError::InvalidCharacter('+'))
+        );
+    }
+
+    #[test]
+    fn forkid_display() {
+        let fork_id = ForkId {
+            hash: ForkHash(0x668d_b0af),
+            next: 0,
+        };
+        assert_eq!(fork_id.to_string(), "0x668db0af@0");
+    }
+
     #[test]
     fn compatibility_check() {
         let mut filter = ForkFilter::new(
@@ -491,6 +804,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn append_to_rlp_matches_encodable() {
+        for &(hash, next) in &[
+            (0, 0),
+            (0xdead_beef, 1),
+            (0xdead_beef, 0x7f),
+            (0xdead_beef, 0x80),
+            (0xfc64_ec04, 1_150_000),
+            (0xdead_beef, 0xBADD_CAFE),
+            (u32::MAX, u64::MAX),
+        ] {
+            let fork_id = ForkId {
+                hash: ForkHash(hash),
+                next,
+            };
+            let mut stream = RlpStream::new();
+            fork_id.append_to_rlp(&mut stream);
+            assert_eq!(stream.out(), rlp::encode(&fork_id), "{fork_id}");
+
+            // within a list, as in the status packet
+            let mut stream = RlpStream::new_list(3);
+            stream.append(&1u8);
+            fork_id.append_to_rlp(&mut stream);
+            stream.append(&2u8);
+            let mut expected = RlpStream::new_list(3);
+            expected.append(&1u8).append(&fork_id).append(&2u8);
+            assert_eq!(stream.out(), expected.out(), "{fork_id}");
+        }
+    }
+
     #[test]
     fn compute_cache() {
         let b1 = 1_150_000;
@@ -535,4 +878,145 @@ mod tests {
         assert!(fork_filter.set_head_priv(b2));
         assert_eq!(fork_filter.current(), h2);
     }
+
+    #[test]
+    fn fork_id_history() {
+        let forks = vec![
+            1_150_000, 1_920_000, 2_463_000, 2_675_000, 4_370_000, 7_280_000,
+        ];
+        let filter = ForkFilter::new(4_370_000, GENESIS_HASH, forks.clone());
+
+        let expected = vec![
+            (0, 0xfc64_ec04, 1_150_000),
+            (1_150_000, 0x97c2_c34c, 1_920_000),
+            (1_920_000, 0x91d1_f948, 2_463_000),
+            (2_463_000, 0x7a64_da13, 2_675_000),
+            (2_675_000, 0x3edd_5b10, 4_370_000),
+            (4_370_000, 0xa00b_c324, 7_280_000),
+            (7_280_000, 0x668d_b0af, 0),
+        ]
+        .into_iter()
+        .map(|(block, hash, next)| {
+            (
+                block,
+                ForkId {
+                    hash: ForkHash(hash),
+                    next,
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+        let history = filter.fork_id_history();
+        assert_eq!(history, expected);
+        assert_eq!(history.last().unwrap().1.next, 0);
+
+        // every epoch starts with the fork id a filter at its first block reports
+        for (block, fork_id) in history {
+            let at_block = ForkFilter::new(block, GENESIS_HASH, forks.clone());
+            assert_eq!(at_block.current(), fork_id);
+        }
+    }
+
+    #[test]
+    fn next_fork() {
+        let mut filter = ForkFilter::new(0, GENESIS_HASH, vec![1_150_000, 1_920_000]);
+        assert_eq!(filter.next_fork(), Some(1_150_000));
+
+        filter.set_head(1_150_000);
+        assert_eq!(filter.next_fork(), Some(1_920_000));
+
+        filter.set_head(1_920_000);
+        assert_eq!(filter.next_fork(), None);
+
+        // the history does not depend on the head
+        assert_eq!(filter.fork_id_history().len(), 3);
+    }
+
+    #[test]
+    fn view_matches_filter() {
+        let forks = vec![1_150_000, 1_920_000];
+        let mut filter = ForkFilter::new(0, GENESIS_HASH, forks);
+        let shared = SharedForkFilter::new(filter.clone());
+
+        for head in [0, 1_149_999, 1_150_000, 2_000_000, 1_000] {
+            filter.set_head(head);
+            let view = shared.set_head(head);
+            assert_eq!(*view, filter.view());
+            assert_eq!(shared.current(), filter.current());
+            for fork_id in [
+                ForkId {
+                    hash: ForkHash(0xfc64_ec04),
+                    next: 1_150_000,
+                },
+                ForkId {
+                    hash: ForkHash(0x97c2_c34c),
+                    next: 0,
+                },
+                ForkId {
+                    hash: ForkHash(0x91d1_f948),
+                    next: 0,
+                },
+                ForkId {
+                    hash: ForkHash(0x91d1_f948),
+                    next: 1_500_000,
+                },
+            ] {
+                assert_eq!(shared.is_compatible(fork_id), filter.is_compatible(fork_id));
+            }
+        }
+        assert_eq!(shared.filter_snapshot(), filter);
+    }
+
+    #[test]
+    fn concurrent_readers_see_consistent_views() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let b1 = 1_150_000;
+        let shared = Arc::new(SharedForkFilter::new(ForkFilter::new(
+            0,
+            GENESIS_HASH,
+            vec![b1, 1_920_000],
+        )));
+        let before = Arc::new(ForkFilter::new(b1 - 1, GENESIS_HASH, vec![b1, 1_920_000]).view());
+        let after = Arc::new(ForkFilter::new(b1, GENESIS_HASH, vec![b1, 1_920_000]).view());
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                let done = done.clone();
+                let (before, after) = (before.clone(), after.clone());
+                std::thread::spawn(move || {
+                    let mut checked = 0_u64;
+                    while !done.load(Ordering::Relaxed) || checked == 0 {
+                        let view = shared.view();
+                        let current = view.current();
+                        // the fork id, past and future always belong to the same head
+                        assert_eq!(current.hash, view.past().last().unwrap().1);
+                        assert!(view.past().last().unwrap().0 <= view.head());
+                        assert!(current.next == 0 || view.head() < current.next);
+                        assert_eq!(view.past().len() + view.future().len(), 3);
+                        if view.head() == b1 - 1 {
+                            assert_eq!(view, before);
+                        } else if view.head() == b1 {
+                            assert_eq!(view, after);
+                        }
+                        // a peer on the same epoch is accepted by every view
+                        assert_eq!(view.is_compatible(current), Ok(()));
+                        checked += 1;
+                    }
+                    checked
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for _ in 0..1_000 {
+            shared.set_head(b1 - 1);
+            shared.set_head(b1);
+        }
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            assert!(reader.join().unwrap() > 0);
+        }
+    }
 }
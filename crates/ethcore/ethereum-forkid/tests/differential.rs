@@ -0,0 +1,240 @@
+//! Differential test of `ForkFilter` against a naive EIP-2124 implementation.
+//!
+//! `ForkFilter` caches the past and future fork hashes of the current epoch and only recomputes
+//! them when the head leaves it. The reference implementation below recomputes everything from
+//! scratch on every call, straight from the wording of the spec, so any divergence points at the
+//! cache.
+
+use ethereum_forkid::{BlockNumber, ForkFilter, ForkHash, ForkId, RejectReason};
+use ethereum_types::H256;
+use proptest::{collection::vec, prelude::*, sample::Index};
+
+/// Upper bound of generated fork blocks. Kept small so that heads, forks and announced
+/// `FORK_NEXT` values collide often.
+const MAX_BLOCK: BlockNumber = 64;
+
+mod reference {
+    use super::*;
+    use crc::crc32;
+
+    /// Fork blocks in ascending order, without duplicates and without genesis.
+    pub fn fork_blocks(forks: &[BlockNumber]) -> Vec<BlockNumber> {
+        let mut blocks = forks
+            .iter()
+            .copied()
+            .filter(|b| *b != 0)
+            .collect::<Vec<_>>();
+        blocks.sort_unstable();
+        blocks.dedup();
+        blocks
+    }
+
+    /// `FORK_HASH` after `n` forks: CRC32 checksum of the genesis hash followed by the first `n`
+    /// fork block numbers as big-endian 8-byte integers.
+    pub fn fork_hash(genesis: H256, blocks: &[BlockNumber], n: usize) -> ForkHash {
+        let mut data = genesis.as_bytes().to_vec();
+        for block in &blocks[..n] {
+            data.extend_from_slice(&block.to_be_bytes());
+        }
+        ForkHash(crc32::checksum_ieee(&data))
+    }
+
+    /// Number of forks passed at `head`.
+    pub fn passed(blocks: &[BlockNumber], head: BlockNumber) -> usize {
+        blocks.iter().filter(|b| **b <= head).count()
+    }
+
+    pub fn fork_id(genesis: H256, forks: &[BlockNumber], head: BlockNumber) -> ForkId {
+        let blocks = fork_blocks(forks);
+        let passed = passed(&blocks, head);
+        ForkId {
+            hash: fork_hash(genesis, &blocks, passed),
+            next: blocks.get(passed).copied().unwrap_or(0),
+        }
+    }
+
+    pub fn is_compatible(
+        genesis: H256,
+        forks: &[BlockNumber],
+        head: BlockNumber,
+        remote: ForkId,
+    ) -> Result<(), RejectReason> {
+        let blocks = fork_blocks(forks);
+        let passed = passed(&blocks, head);
+
+        // 1) Local and remote FORK_HASH match: reject only if the remote FORK_NEXT is already
+        // passed locally.
+        if fork_hash(genesis, &blocks, passed) == remote.hash {
+            if remote.next != 0 && head >= remote.next {
+                return Err(RejectReason::LocalIncompatibleOrStale);
+            }
+            return Ok(());
+        }
+
+        // 2) Remote FORK_HASH is a subset of the local past forks: the remote FORK_NEXT must be the
+        // locally following fork block.
+        for n in 0..passed {
+            if fork_hash(genesis, &blocks, n) == remote.hash {
+                if blocks[n] == remote.next {
+                    return Ok(());
+                }
+                return Err(RejectReason::RemoteStale);
+            }
+        }
+
+        // 3) Remote FORK_HASH is a superset of the local past forks and can be completed with
+        // locally known future forks.
+        for n in passed + 1..=blocks.len() {
+            if fork_hash(genesis, &blocks, n) == remote.hash {
+                return Ok(());
+            }
+        }
+
+        // 4) Reject in all other cases.
+        Err(RejectReason::LocalIncompatibleOrStale)
+    }
+}
+
+/// How to pick the `FORK_HASH` of a remote `ForkId`.
+#[derive(Clone, Debug)]
+enum RemoteHash {
+    /// One of the fork hashes of the local schedule.
+    Known(Index),
+    /// Arbitrary, most likely unknown, hash.
+    Random(u32),
+}
+
+/// How to pick the `FORK_NEXT` of a remote `ForkId`.
+#[derive(Clone, Debug)]
+enum RemoteNext {
+    Zero,
+    /// One of the local fork blocks, optionally off by one.
+    Fork(Index, i8),
+    Block(BlockNumber),
+}
+
+fn remote_hash() -> impl Strategy<Value = RemoteHash> {
+    prop_oneof![
+        3 => any::<Index>().prop_map(RemoteHash::Known),
+        1 => any::<u32>().prop_map(RemoteHash::Random),
+    ]
+}
+
+fn remote_next() -> impl Strategy<Value = RemoteNext> {
+    prop_oneof![
+        Just(RemoteNext::Zero),
+        (any::<Index>(), -1i8..=1).prop_map(|(i, offset)| RemoteNext::Fork(i, offset)),
+        (0..MAX_BLOCK * 2).prop_map(RemoteNext::Block),
+        Just(RemoteNext::Block(BlockNumber::max_value())),
+    ]
+}
+
+fn remote_fork_id(
+    genesis: H256,
+    forks: &[BlockNumber],
+    hash: &RemoteHash,
+    next: &RemoteNext,
+) -> ForkId {
+    let blocks = reference::fork_blocks(forks);
+    let hash = match *hash {
+        RemoteHash::Known(ref i) => {
+            reference::fork_hash(genesis, &blocks, i.index(blocks.len() + 1))
+        }
+        RemoteHash::Random(hash) => ForkHash(hash),
+    };
+    let next = match *next {
+        RemoteNext::Zero => 0,
+        RemoteNext::Fork(_, _) if blocks.is_empty() => 0,
+        RemoteNext::Fork(ref i, offset) => {
+            let block = blocks[i.index(blocks.len())];
+            if offset < 0 {
+                block - 1
+            } else {
+                block + offset as BlockNumber
+            }
+        }
+        RemoteNext::Block(block) => block,
+    };
+    ForkId { hash, next }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2048))]
+
+    #[test]
+    fn current_matches_reference(
+        genesis in any::<[u8; 32]>().prop_map(H256),
+        forks in vec(0..MAX_BLOCK, 0..8),
+        heads in vec(0..MAX_BLOCK * 2, 1..8),
+    ) {
+        let mut filter = ForkFilter::new(heads[0], genesis, forks.iter().copied());
+        for head in heads {
+            filter.set_head(head);
+            prop_assert_eq!(filter.current(), reference::fork_id(genesis, &forks, head));
+            prop_assert_eq!(
+                ForkFilter::new(head, genesis, forks.iter().copied()).current(),
+                filter.current()
+            );
+        }
+    }
+
+    #[test]
+    fn is_compatible_matches_reference(
+        genesis in any::<[u8; 32]>().prop_map(H256),
+        forks in vec(0..MAX_BLOCK, 0..8),
+        initial_head in 0..MAX_BLOCK * 2,
+        checks in vec((0..MAX_BLOCK * 2, remote_hash(), remote_next()), 1..16),
+    ) {
+        let mut filter = ForkFilter::new(initial_head, genesis, forks.iter().copied());
+        for (head, hash, next) in checks {
+            filter.set_head(head);
+            let remote = remote_fork_id(genesis, &forks, &hash, &next);
+            prop_assert_eq!(
+                filter.is_compatible(remote),
+                reference::is_compatible(genesis, &forks, head, remote),
+                "head {}, forks {:?}, remote {}",
+                head,
+                forks,
+                remote
+            );
+        }
+    }
+}
+
+/// Sanity check of the reference implementation itself against the mainnet values from EIP-2124.
+#[test]
+fn reference_matches_eip_examples() {
+    let genesis = H256(hex_literal::hex!(
+        "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3"
+    ));
+    let forks = [
+        1_150_000, 1_920_000, 2_463_000, 2_675_000, 4_370_000, 7_280_000,
+    ];
+
+    assert_eq!(
+        reference::fork_id(genesis, &forks, 0),
+        ForkId {
+            hash: ForkHash(0xfc64_ec04),
+            next: 1_150_000
+        }
+    );
+    assert_eq!(
+        reference::fork_id(genesis, &forks, 7_987_396),
+        ForkId {
+            hash: ForkHash(0x668d_b0af),
+            next: 0
+        }
+    );
+    assert_eq!(
+        reference::is_compatible(
+            genesis,
+            &forks,
+            7_987_396,
+            ForkId {
+                hash: ForkHash(0xa00b_c324),
+                next: 0
+            }
+        ),
+        Err(RejectReason::RemoteStale)
+    );
+}
@@ -0,0 +1,189 @@
+//! EIP-2124 test vectors.
+//!
+//! Every `*.json` file in `tests/vectors` describes one chain config: its genesis hash, the fork
+//! blocks, the expected `ForkId` at a number of heads and the validation cases in the schema geth
+//! uses (`{head, hash, next, want-error}`). Adding a chain only requires dropping another file
+//! into that directory.
+
+use ethereum_forkid::{BlockNumber, ForkFilter, ForkHash, ForkId, RejectReason};
+use ethereum_types::H256;
+use serde::{de::Error as _, Deserialize, Deserializer};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Fork configuration of a chain together with its test vectors.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChainVectors {
+    name: String,
+    genesis: H256,
+    forks: Vec<BlockNumber>,
+    #[serde(default)]
+    ids: Vec<IdVector>,
+    #[serde(default)]
+    cases: Vec<CaseVector>,
+}
+
+/// Expected local `ForkId` at given head.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IdVector {
+    head: BlockNumber,
+    #[serde(deserialize_with = "fork_hash")]
+    hash: ForkHash,
+    next: BlockNumber,
+}
+
+/// Validation of a remote `ForkId` at given local head.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CaseVector {
+    head: BlockNumber,
+    #[serde(deserialize_with = "fork_hash")]
+    hash: ForkHash,
+    next: BlockNumber,
+    #[serde(rename = "want-error")]
+    want_error: Option<WantError>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum WantError {
+    RemoteStale,
+    LocalIncompatibleOrStale,
+}
+
+impl From<WantError> for RejectReason {
+    fn from(e: WantError) -> Self {
+        match e {
+            WantError::RemoteStale => Self::RemoteStale,
+            WantError::LocalIncompatibleOrStale => Self::LocalIncompatibleOrStale,
+        }
+    }
+}
+
+fn fork_hash<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ForkHash, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    s.parse().map_err(D::Error::custom)
+}
+
+fn vector_files() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("vectors");
+    let mut files = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("cannot read {}: {}", dir.display(), e))
+        .map(|entry| entry.expect("readable directory entry").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+fn load(path: &Path) -> ChainVectors {
+    let file =
+        fs::File::open(path).unwrap_or_else(|e| panic!("cannot open {}: {}", path.display(), e));
+    serde_json::from_reader(file)
+        .unwrap_or_else(|e| panic!("cannot parse {}: {}", path.display(), e))
+}
+
+/// Run all vectors of one chain, returning a description of every mismatch.
+fn check(chain: &ChainVectors) -> Vec<String> {
+    let mut failures = Vec::new();
+
+    for id in &chain.ids {
+        let filter = ForkFilter::new(id.head, chain.genesis, chain.forks.iter().copied());
+        let want = ForkId {
+            hash: id.hash,
+            next: id.next,
+        };
+        if filter.current() != want {
+            failures.push(format!(
+                "{}: id at head {}: got {}, want {}",
+                chain.name,
+                id.head,
+                filter.current(),
+                want
+            ));
+        }
+
+        let in_history = filter
+            .fork_id_history()
+            .into_iter()
+            .take_while(|(block, _)| *block <= id.head)
+            .last()
+            .map(|(_, fork_id)| fork_id);
+        if in_history != Some(want) {
+            failures.push(format!(
+                "{}: history at head {}: got {:?}, want {}",
+                chain.name, id.head, in_history, want
+            ));
+        }
+    }
+
+    // The cases are run both against a fresh filter and against a single filter whose head
+    // moves from case to case, so that stale cached epochs show up as well.
+    let mut moving = ForkFilter::new(0, chain.genesis, chain.forks.iter().copied());
+    for (i, case) in chain.cases.iter().enumerate() {
+        let remote = ForkId {
+            hash: case.hash,
+            next: case.next,
+        };
+        let want = case.want_error.map_or(Ok(()), |e| Err(e.into()));
+
+        let fresh = ForkFilter::new(case.head, chain.genesis, chain.forks.iter().copied());
+        moving.set_head(case.head);
+        for (kind, filter) in &[("fresh", &fresh), ("moving", &moving)] {
+            let got = filter.is_compatible(remote);
+            if got != want {
+                failures.push(format!(
+                    "{}: case #{} ({} filter) head {} remote {}: got {:?}, want {:?}",
+                    chain.name, i, kind, case.head, remote, got, want
+                ));
+            }
+        }
+    }
+
+    failures
+}
+
+#[test]
+fn eip2124_vectors() {
+    let files = vector_files();
+    assert!(!files.is_empty(), "no test vectors found");
+
+    let mut failures = Vec::new();
+    for path in &files {
+        let chain = load(path);
+        assert!(
+            !chain.ids.is_empty() || !chain.cases.is_empty(),
+            "{} contains no vectors",
+            path.display()
+        );
+        failures.extend(check(&chain));
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} vector(s) failed:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn known_chains_are_present() {
+    let names = vector_files()
+        .iter()
+        .map(|path| load(path).name)
+        .collect::<Vec<_>>();
+    for chain in &["mainnet", "sepolia", "classic"] {
+        assert!(
+            names.iter().any(|name| name == chain),
+            "missing vectors for {}",
+            chain
+        );
+    }
+}
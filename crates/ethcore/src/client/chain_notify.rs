@@ -16,7 +16,7 @@
 
 use blockchain::ImportRoute;
 use bytes::Bytes;
-use ethereum_types::{H256, U256};
+use ethereum_types::{Address, H256, U256};
 use std::{collections::HashMap, time::Duration};
 use types::transaction::UnverifiedTransaction;
 
@@ -194,4 +194,15 @@ pub trait ChainNotify: Send + Sync {
     fn transactions_received(&self, _txs: &[UnverifiedTransaction], _peer_id: usize) {
         // does nothing by default
     }
+
+    /// fires when transactions received from a peer are rejected by the local transaction pool
+    fn transactions_rejected(&self, _hashes: &[H256], _peer_id: usize) {
+        // does nothing by default
+    }
+
+    /// fires when the validators of the engine changed, with the validators of the block
+    /// following the best block
+    fn validators_changed(&self, _validators: &[Address]) {
+        // does nothing by default
+    }
 }
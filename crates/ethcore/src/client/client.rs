@@ -246,6 +246,9 @@ pub struct Client {
     /// Number of eras kept in a journal before they are pruned
     history: u64,
 
+    /// Validators of the engine last notified about
+    validators: RwLock<Option<Vec<Address>>>,
+
     /// An action to be done if a mode/spec_name change happens
     on_user_defaults_change: Mutex<Option<Box<dyn FnMut(Option<Mode>) + 'static + Send>>>,
 
@@ -269,6 +272,7 @@ impl Importer {
             engine.clone(),
             message_channel.clone(),
             config.verifier_type.verifying_seal(),
+            None,
         );
 
         Ok(Importer {
@@ -307,7 +311,11 @@ impl Importer {
             let mut import_results = Vec::with_capacity(max_blocks_to_import);
 
             let _import_lock = self.import_lock.lock();
-            let blocks = self.block_queue.drain(max_blocks_to_import);
+            let blocks = {
+                let chain = client.chain.read();
+                self.block_queue
+                    .drain_ordered(max_blocks_to_import, |hash| chain.is_known(hash))
+            };
             if blocks.is_empty() {
                 debug!(target: "block_import", "block_queue is empty");
                 self.block_queue.resignal_verification();
@@ -412,6 +420,9 @@ impl Importer {
                         has_more_blocks_to_import,
                     ));
                 });
+                if !has_more_blocks_to_import {
+                    client.notify_validators();
+                }
             }
         }
         trace!(target:"block_import","Flush block to db");
@@ -1033,6 +1044,7 @@ impl Client {
             last_hashes: RwLock::new(VecDeque::new()),
             factories,
             history,
+            validators: RwLock::new(None),
             on_user_defaults_change: Mutex::new(None),
             registrar_address,
             exit_handler: Mutex::new(None),
@@ -1167,6 +1179,25 @@ impl Client {
         }
     }
 
+    /// Notify about the validators of the engine for the block following the best block, if they
+    /// changed since the last notification.
+    fn notify_validators(&self) {
+        let best_block_hash = self.chain.read().best_block_hash();
+        let validators = match self.engine.validators(&best_block_hash) {
+            Some(validators) => validators,
+            None => return,
+        };
+        {
+            let mut last = self.validators.write();
+            if last.as_ref() == Some(&validators) {
+                return;
+            }
+            *last = Some(validators.clone());
+        }
+        debug!(target: "client", "Validators changed: {validators:?}");
+        self.notify(|notify| notify.validators_changed(&validators));
+    }
+
     /// Register an action to be done if a mode/spec_name change happens.
     pub fn on_user_defaults_change<F>(&self, f: F)
     where
@@ -1239,6 +1270,61 @@ impl Client {
         self.importer.import_verified_blocks(self)
     }
 
+    // Queue a block for verification, `priority` blocks are verified before the blocks queued already.
+    fn queue_block(&self, unverified: Unverified, priority: bool) -> EthcoreResult<H256> {
+        // t_nb 2.1 check if header hash is known to us.
+        if self.chain.read().is_known(&unverified.hash()) {
+            bail!(EthcoreErrorKind::Import(ImportErrorKind::AlreadyInChain));
+        }
+
+        // t_nb 2.2 check if parent is known
+        let status = self.block_status(BlockId::Hash(unverified.parent_hash()));
+        if status == BlockStatus::Unknown {
+            bail!(EthcoreErrorKind::Block(BlockError::UnknownParent(
+                unverified.parent_hash()
+            )));
+        }
+
+        let raw = if priority || self.importer.block_queue.is_empty() {
+            Some((
+                unverified.bytes.clone(),
+                unverified.header.hash(),
+                *unverified.header.difficulty(),
+            ))
+        } else {
+            None
+        };
+
+        // t_nb 2.3
+        let imported = match priority {
+            true => self.importer.block_queue.import_priority(unverified),
+            false => self.importer.block_queue.import(unverified),
+        };
+        match imported {
+            Ok(hash) => {
+                // t_nb 2.4 If block is okay and the queue is empty or the block was queued with priority we propagate the block in a `PriorityTask` to be rebrodcasted
+                if let Some((raw, hash, difficulty)) = raw {
+                    self.notify(move |n| n.block_pre_import(&raw, &hash, &difficulty));
+                }
+                Ok(hash)
+            }
+            // t_nb 2.5 if block is not okay print error. we only care about block errors (not import errors)
+            Err((Some(block), EthcoreError(EthcoreErrorKind::Block(err), _))) => {
+                self.importer.bad_blocks.report(
+                    block.bytes,
+                    err.to_string(),
+                    self.engine.params().eip1559_transition,
+                );
+                bail!(EthcoreErrorKind::Block(err))
+            }
+            Err((None, EthcoreError(EthcoreErrorKind::Block(err), _))) => {
+                error!(target: "client", "BlockError {err} detected but it was missing raw_bytes of the block");
+                bail!(EthcoreErrorKind::Block(err))
+            }
+            Err((_, e)) => Err(e),
+        }
+    }
+
     // use a state-proving closure for the given block.
     fn with_proving_caller<F, T>(&self, id: BlockId, with_call: F) -> T
     where
@@ -1550,7 +1636,9 @@ impl Client {
     fn block_hash(chain: &BlockChain, id: BlockId) -> Option<H256> {
         match id {
             BlockId::Hash(hash) => Some(hash),
-            BlockId::Number(number) => chain.block_hash(number),
+            BlockId::Number(number) => chain
+                .block_hash(number)
+                .or_else(|| chain.header_only_hash(number)),
             BlockId::Earliest => chain.block_hash(0),
             BlockId::Latest => Some(chain.best_block_hash()),
         }
@@ -1916,53 +2004,11 @@ impl CallContract for Client {
 impl ImportBlock for Client {
     // t_nb 2.0 import block to client
     fn import_block(&self, unverified: Unverified) -> EthcoreResult<H256> {
-        // t_nb 2.1 check if header hash is known to us.
-        if self.chain.read().is_known(&unverified.hash()) {
-            bail!(EthcoreErrorKind::Import(ImportErrorKind::AlreadyInChain));
-        }
-
-        // t_nb 2.2 check if parent is known
-        let status = self.block_status(BlockId::Hash(unverified.parent_hash()));
-        if status == BlockStatus::Unknown {
-            bail!(EthcoreErrorKind::Block(BlockError::UnknownParent(
-                unverified.parent_hash()
-            )));
-        }
-
-        let raw = if self.importer.block_queue.is_empty() {
-            Some((
-                unverified.bytes.clone(),
-                unverified.header.hash(),
-                *unverified.header.difficulty(),
-            ))
-        } else {
-            None
-        };
+        self.queue_block(unverified, false)
+    }
 
-        // t_nb 2.3
-        match self.importer.block_queue.import(unverified) {
-            Ok(hash) => {
-                // t_nb 2.4 If block is okay and the queue is empty we propagate the block in a `PriorityTask` to be rebrodcasted
-                if let Some((raw, hash, difficulty)) = raw {
-                    self.notify(move |n| n.block_pre_import(&raw, &hash, &difficulty));
-                }
-                Ok(hash)
-            }
-            // t_nb 2.5 if block is not okay print error. we only care about block errors (not import errors)
-            Err((Some(block), EthcoreError(EthcoreErrorKind::Block(err), _))) => {
-                self.importer.bad_blocks.report(
-                    block.bytes,
-                    err.to_string(),
-                    self.engine.params().eip1559_transition,
-                );
-                bail!(EthcoreErrorKind::Block(err))
-            }
-            Err((None, EthcoreError(EthcoreErrorKind::Block(err), _))) => {
-                error!(target: "client", "BlockError {err} detected but it was missing raw_bytes of the block");
-                bail!(EthcoreErrorKind::Block(err))
-            }
-            Err((_, e)) => Err(e),
-        }
+    fn import_block_priority(&self, unverified: Unverified) -> EthcoreResult<H256> {
+        self.queue_block(unverified, true)
     }
 }
 
@@ -2280,6 +2326,10 @@ impl BlockChainClient for Client {
         }
     }
 
+    fn is_queued(&self, hash: &H256) -> bool {
+        self.importer.block_queue.is_queued(hash)
+    }
+
     fn is_processing_fork(&self) -> bool {
         let chain = self.chain.read();
         self.importer
@@ -2852,10 +2902,25 @@ impl IoClient for Client {
                     notify.transactions_received(&txs, peer_id);
                 });
 
-                client
+                let hashes: Vec<H256> = txs.iter().map(|tx| tx.hash()).collect();
+                let results = client
                     .importer
                     .miner
                     .import_external_transactions(client, txs);
+                // only refusals of the local pool policy, not invalid or known transactions
+                let rejected: Vec<H256> = hashes
+                    .into_iter()
+                    .zip(results)
+                    .filter_map(|(hash, result)| match result {
+                        Err(ref e) if e.is_policy_rejection() => Some(hash),
+                        _ => None,
+                    })
+                    .collect();
+                if !rejected.is_empty() {
+                    client.notify(|notify| {
+                        notify.transactions_rejected(&rejected, peer_id);
+                    });
+                }
             })
             .unwrap_or_else(|e| {
                 debug!(target: "client", "Ignoring {len} transactions: {e}");
@@ -2903,6 +2968,46 @@ impl IoClient for Client {
         Ok(hash)
     }
 
+    fn repair_ancient_block(
+        &self,
+        unverified: Unverified,
+        receipts_bytes: Bytes,
+    ) -> EthcoreResult<bool> {
+        trace_time!("repair_ancient_block");
+
+        let receipts = TypedReceipt::decode_rlp_list(&Rlp::new(&receipts_bytes))?;
+        let _import_lock = self.importer.import_lock.lock();
+        let chain = self.chain.read();
+        let db = self.db.read();
+
+        let mut batch = DBTransaction::new();
+        if !chain.repair_block(&mut batch, encoded::Block::new(unverified.bytes), receipts) {
+            return Ok(false);
+        }
+        db.key_value().write_buffered(batch);
+        chain.commit();
+        db.key_value().flush().expect("DB flush failed.");
+        Ok(true)
+    }
+
+    fn import_headers_only(&self, headers: Vec<encoded::Header>) -> usize {
+        trace_time!("import_headers_only");
+
+        let _import_lock = self.importer.import_lock.lock();
+        let chain = self.chain.read();
+        let db = self.db.read();
+
+        let mut batch = DBTransaction::new();
+        let mut imported = 0;
+        for header in headers.iter().filter(|h| !chain.is_known(&h.hash())) {
+            chain.insert_header_only(&mut batch, header);
+            imported += 1;
+        }
+        db.key_value().write_buffered(batch);
+        db.key_value().flush().expect("DB flush failed.");
+        imported
+    }
+
     fn ancient_block_queue_fullness(&self) -> f32 {
         match self.queued_ancient_blocks_executer.lock().as_ref() {
             Some(queue) => queue.len() as f32 / ANCIENT_BLOCKS_QUEUE_SIZE as f32,
@@ -3088,6 +3193,7 @@ impl ImportSealedBlock for Client {
                 false,
             ));
         });
+        self.notify_validators();
         self.db
             .read()
             .key_value()
@@ -3453,31 +3559,31 @@ impl PrometheusMetrics for Client {
         let report = self.report();
 
         for (key, value) in report.item_sizes.iter() {
-            r.register_gauge(
+            let _ = r.register_gauge(
                 key,
                 format!("Total item number of {key}").as_str(),
                 *value as i64,
             );
         }
 
-        r.register_counter(
+        let _ = r.register_counter(
             "import_gas",
             "Gas processed",
             report.gas_processed.as_u64() as i64,
         );
-        r.register_counter(
+        let _ = r.register_counter(
             "import_blocks",
             "Blocks imported",
             report.blocks_imported as i64,
         );
-        r.register_counter(
+        let _ = r.register_counter(
             "import_txs",
             "Transactions applied",
             report.transactions_applied as i64,
         );
 
         let state_db = self.state_db.read();
-        r.register_gauge(
+        let _ = r.register_gauge(
             "statedb_cache_size",
             "State DB cache size",
             state_db.cache_size() as i64,
@@ -3485,27 +3591,27 @@ impl PrometheusMetrics for Client {
 
         // blockchain cache
         let blockchain_cache_info = self.blockchain_cache_info();
-        r.register_gauge(
+        let _ = r.register_gauge(
             "blockchaincache_block_details",
             "BlockDetails cache size",
             blockchain_cache_info.block_details as i64,
         );
-        r.register_gauge(
+        let _ = r.register_gauge(
             "blockchaincache_block_recipts",
             "Block receipts size",
             blockchain_cache_info.block_receipts as i64,
         );
-        r.register_gauge(
+        let _ = r.register_gauge(
             "blockchaincache_blocks",
             "Blocks cache size",
             blockchain_cache_info.blocks as i64,
         );
-        r.register_gauge(
+        let _ = r.register_gauge(
             "blockchaincache_txaddrs",
             "Transaction addresses cache size",
             blockchain_cache_info.transaction_addresses as i64,
         );
-        r.register_gauge(
+        let _ = r.register_gauge(
             "blockchaincache_size",
             "Total blockchain cache size",
             blockchain_cache_info.total() as i64,
@@ -3523,19 +3629,19 @@ impl PrometheusMetrics for Client {
                     .map(|last| (first, U256::from(last)))
             });
         if let Some((first, last)) = gap {
-            r.register_gauge(
+            let _ = r.register_gauge(
                 "chain_warpsync_gap_first",
                 "Warp sync gap, first block",
                 first.as_u64() as i64,
             );
-            r.register_gauge(
+            let _ = r.register_gauge(
                 "chain_warpsync_gap_last",
                 "Warp sync gap, last block",
                 last.as_u64() as i64,
             );
         }
 
-        r.register_gauge(
+        let _ = r.register_gauge(
             "chain_block",
             "Best block number",
             chain.best_block_number as i64,
@@ -3543,12 +3649,12 @@ impl PrometheusMetrics for Client {
 
         // prunning info
         let prunning = self.pruning_info();
-        r.register_gauge(
+        let _ = r.register_gauge(
             "prunning_earliest_chain",
             "The first block which everything can be served after",
             prunning.earliest_chain as i64,
         );
-        r.register_gauge(
+        let _ = r.register_gauge(
             "prunning_earliest_state",
             "The first block where state requests may be served",
             prunning.earliest_state as i64,
@@ -3556,27 +3662,27 @@ impl PrometheusMetrics for Client {
 
         // queue info
         let queue = self.queue_info();
-        r.register_gauge(
+        let _ = r.register_gauge(
             "queue_mem_used",
             "Queue heap memory used in bytes",
             queue.mem_used as i64,
         );
-        r.register_gauge(
+        let _ = r.register_gauge(
             "queue_size_total",
             "The total size of the queues",
             queue.total_queue_size() as i64,
         );
-        r.register_gauge(
+        let _ = r.register_gauge(
             "queue_size_unverified",
             "Number of queued items pending verification",
             queue.unverified_queue_size as i64,
         );
-        r.register_gauge(
+        let _ = r.register_gauge(
             "queue_size_verified",
             "Number of verified queued items pending import",
             queue.verified_queue_size as i64,
         );
-        r.register_gauge(
+        let _ = r.register_gauge(
             "queue_size_verifying",
             "Number of items being verified",
             queue.verifying_queue_size as i64,
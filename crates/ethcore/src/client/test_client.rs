@@ -17,7 +17,7 @@
 //! Test client.
 
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
     str::FromStr,
     sync::{
         atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrder},
@@ -67,7 +67,7 @@ use client::{
     StateClient, StateOrBlock, TraceFilter, TraceId, TransactionId, TransactionInfo, UncleId,
 };
 use engines::EthEngine;
-use error::{Error, EthcoreResult};
+use error::{BlockError, Error, ErrorKind, EthcoreResult, ImportErrorKind};
 use executed::CallError;
 use executive::Executed;
 use journaldb;
@@ -85,6 +85,8 @@ pub struct TestBlockChainClient {
     pub blocks: RwLock<HashMap<H256, Bytes>>,
     /// Mapping of numbers to hashes.
     pub numbers: RwLock<HashMap<usize, H256>>,
+    /// Headers of blocks stored without body, by number.
+    pub headers_only: RwLock<BTreeMap<BlockNumber, encoded::Header>>,
     /// Genesis block hash.
     pub genesis_hash: H256,
     /// Last block hash.
@@ -111,6 +113,8 @@ pub struct TestBlockChainClient {
     pub error_on_logs: RwLock<Option<BlockId>>,
     /// Block queue size.
     pub queue_size: AtomicUsize,
+    /// Number of blocks imported ahead of the queued blocks.
+    pub priority_imports: AtomicUsize,
     /// Miner
     pub miner: Arc<Miner>,
     /// Spec
@@ -121,14 +125,20 @@ pub struct TestBlockChainClient {
     pub ancient_block: RwLock<Option<(H256, u64)>>,
     /// First block info.
     pub first_block: RwLock<Option<(H256, u64)>>,
+    /// Retention boundary to report.
+    pub retention_boundary: RwLock<Option<BlockNumber>>,
     /// Traces to return
     pub traces: RwLock<Option<Vec<LocalizedTrace>>>,
     /// Pruning history size to report.
     pub history: RwLock<Option<u64>>,
     /// Is disabled
     pub disabled: AtomicBool,
+    /// Is processing a fork
+    pub processing_fork: AtomicBool,
     /// Transaction hashes producer
     pub new_transaction_hashes: RwLock<Option<crossbeam_channel::Sender<H256>>>,
+    /// Blocks failing to import as if rejected by the engine, along with their descendants.
+    pub rejected_blocks: RwLock<HashSet<H256>>,
 }
 
 /// Used for generating test client blocks.
@@ -177,6 +187,7 @@ impl TestBlockChainClient {
         let mut client = TestBlockChainClient {
             blocks: RwLock::new(HashMap::new()),
             numbers: RwLock::new(HashMap::new()),
+            headers_only: RwLock::new(BTreeMap::new()),
             genesis_hash: H256::default(),
             extra_data,
             last_hash: RwLock::new(H256::default()),
@@ -189,16 +200,20 @@ impl TestBlockChainClient {
             receipts: RwLock::new(HashMap::new()),
             logs: RwLock::new(Vec::new()),
             queue_size: AtomicUsize::new(0),
+            priority_imports: AtomicUsize::new(0),
             miner: Arc::new(Miner::new_for_tests(&spec, None)),
             spec,
             latest_block_timestamp: RwLock::new(10_000_000),
             ancient_block: RwLock::new(None),
             first_block: RwLock::new(None),
+            retention_boundary: RwLock::new(None),
             traces: RwLock::new(None),
             history: RwLock::new(None),
             disabled: AtomicBool::new(false),
+            processing_fork: AtomicBool::new(false),
             error_on_logs: RwLock::new(None),
             new_transaction_hashes: RwLock::new(None),
+            rejected_blocks: RwLock::new(HashSet::new()),
         };
 
         // insert genesis hash.
@@ -244,6 +259,11 @@ impl TestBlockChainClient {
         self.queue_size.store(size, AtomicOrder::SeqCst);
     }
 
+    /// Set whether the client is processing a fork for testing
+    pub fn set_processing_fork(&self, processing: bool) {
+        self.processing_fork.store(processing, AtomicOrder::SeqCst);
+    }
+
     /// Set timestamp assigned to latest sealed block
     pub fn set_latest_block_timestamp(&self, ts: u64) {
         *self.latest_block_timestamp.write() = ts;
@@ -352,6 +372,12 @@ impl TestBlockChainClient {
         self.blocks.write().insert(hash, rlp.out());
     }
 
+    /// Reject the block on import, as the engine would reject an invalid seal. Its descendants
+    /// are rejected as known bad, like the block queue does.
+    pub fn reject_block(&self, hash: H256) {
+        self.rejected_blocks.write().insert(hash);
+    }
+
     /// Get block hash with `delta` as offset from the most recent blocks.
     pub fn block_hash_delta_minus(&mut self, delta: usize) -> H256 {
         let blocks_read = self.numbers.read();
@@ -374,6 +400,31 @@ impl TestBlockChainClient {
 
     /// Inserts a transaction with given gas price to miners transactions queue.
     pub fn insert_transaction_with_gas_price_to_queue(&self, gas_price: U256) -> H256 {
+        let signed_tx = self.new_funded_transaction(gas_price);
+        let hash = signed_tx.hash();
+        let res = self
+            .miner
+            .import_external_transactions(self, vec![signed_tx.into()]);
+        let res = res.into_iter().next().unwrap();
+        assert!(res.is_ok());
+
+        self.notify_new_transaction_hash(hash);
+        hash
+    }
+
+    /// Inserts a locally submitted transaction to miners transactions queue.
+    pub fn insert_local_transaction_to_queue(&self) -> H256 {
+        let signed_tx = self.new_funded_transaction(U256::from(20_000_000_000u64));
+        let hash = signed_tx.hash();
+        self.miner
+            .import_own_transaction(self, signed_tx.into())
+            .unwrap();
+
+        self.notify_new_transaction_hash(hash);
+        hash
+    }
+
+    fn new_funded_transaction(&self, gas_price: U256) -> SignedTransaction {
         let keypair = Random.generate();
         let tx = TypedTransaction::Legacy(Transaction {
             action: Action::Create,
@@ -385,21 +436,16 @@ impl TestBlockChainClient {
         });
         let signed_tx = tx.sign(keypair.secret(), None);
         self.set_balance(signed_tx.sender(), 10_000_000_000_000_000_000u64.into());
-        let hash = signed_tx.hash();
-        let res = self
-            .miner
-            .import_external_transactions(self, vec![signed_tx.into()]);
-        let res = res.into_iter().next().unwrap();
-        assert!(res.is_ok());
+        signed_tx
+    }
 
+    fn notify_new_transaction_hash(&self, hash: H256) {
         // if new_transaction_hashes producer channel exists, send the transaction hash
         let _ = self
             .new_transaction_hashes
             .write()
             .as_ref()
             .map(|tx| tx.send(hash));
-
-        hash
     }
 
     /// Inserts a transaction to miners transactions queue.
@@ -550,6 +596,7 @@ impl ChainInfo for TestBlockChainClient {
             first_block_number: self.first_block.read().as_ref().map(|x| x.1),
             ancient_block_hash: self.ancient_block.read().as_ref().map(|x| x.0),
             ancient_block_number: self.ancient_block.read().as_ref().map(|x| x.1),
+            retention_boundary: *self.retention_boundary.read(),
         }
     }
 }
@@ -617,6 +664,16 @@ impl ImportBlock for TestBlockChainClient {
         let header = unverified.header;
         let h = header.hash();
         let number: usize = header.number() as usize;
+        {
+            let mut rejected = self.rejected_blocks.write();
+            if rejected.contains(header.parent_hash()) {
+                rejected.insert(h);
+                return Err(ErrorKind::Import(ImportErrorKind::KnownBad).into());
+            }
+            if rejected.contains(&h) {
+                return Err(ErrorKind::Block(BlockError::InvalidSeal).into());
+            }
+        }
         if number > self.blocks.read().len() {
             panic!(
                 "Unexpected block number. Expected {}, got {}",
@@ -666,6 +723,11 @@ impl ImportBlock for TestBlockChainClient {
         }
         Ok(h)
     }
+
+    fn import_block_priority(&self, unverified: Unverified) -> EthcoreResult<H256> {
+        self.priority_imports.fetch_add(1, AtomicOrder::SeqCst);
+        self.import_block(unverified)
+    }
 }
 
 impl Call for TestBlockChainClient {
@@ -916,10 +978,14 @@ impl BlockChainClient for TestBlockChainClient {
         }
     }
 
-    fn is_processing_fork(&self) -> bool {
+    fn is_queued(&self, _hash: &H256) -> bool {
         false
     }
 
+    fn is_processing_fork(&self) -> bool {
+        self.processing_fork.load(AtomicOrder::SeqCst)
+    }
+
     // works only if blocks are one after another 1 -> 2 -> 3
     fn tree_route(&self, from: &H256, to: &H256) -> Option<TreeRoute> {
         Some(TreeRoute {
@@ -1142,6 +1208,30 @@ impl IoClient for TestBlockChainClient {
         self.import_block(unverified)
     }
 
+    fn import_headers_only(&self, headers: Vec<encoded::Header>) -> usize {
+        let blocks = self.blocks.read();
+        let mut headers_only = self.headers_only.write();
+        let mut imported = 0;
+        for header in headers
+            .into_iter()
+            .filter(|h| !blocks.contains_key(&h.hash()))
+        {
+            headers_only.insert(header.number(), header);
+            imported += 1;
+        }
+        imported
+    }
+
+    fn repair_ancient_block(&self, unverified: Unverified, _r: Bytes) -> EthcoreResult<bool> {
+        match self.blocks.write().get_mut(&unverified.hash()) {
+            Some(block) => {
+                *block = unverified.bytes;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
     fn queue_consensus_message(&self, message: Bytes) {
         self.spec.engine.handle_message(&message).unwrap();
     }
@@ -168,6 +168,11 @@ pub trait BlockChain: ChainInfo + BlockInfo + TransactionInfo {}
 pub trait ImportBlock {
     /// Import a block into the blockchain.
     fn import_block(&self, block: Unverified) -> EthcoreResult<H256>;
+
+    /// Import a block extending the best block ahead of the blocks already queued.
+    fn import_block_priority(&self, block: Unverified) -> EthcoreResult<H256> {
+        self.import_block(block)
+    }
 }
 
 /// Provides `call` and `call_many` methods
@@ -220,6 +225,15 @@ pub trait IoClient: Sync + Send {
         receipts_bytes: Bytes,
     ) -> EthcoreResult<H256>;
 
+    /// Replace body and receipts of an already imported ancient block, e.g. when the stored data
+    /// does not match the block header. Returns `false` if the block is not in the chain.
+    fn repair_ancient_block(&self, block: Unverified, receipts_bytes: Bytes)
+        -> EthcoreResult<bool>;
+
+    /// Store headers of blocks whose bodies and receipts are not downloaded. The headers must be
+    /// verified by the caller. Headers of known blocks are skipped, returns the number stored.
+    fn import_headers_only(&self, headers: Vec<encoded::Header>) -> usize;
+
     /// Return percentage of how full is queue that handles ancient blocks. 0 if empty, 1 if full.
     fn ancient_block_queue_fullness(&self) -> f32;
 
@@ -255,6 +269,9 @@ pub trait BlockChainClient:
     /// Get block status by block header hash.
     fn block_status(&self, id: BlockId) -> BlockStatus;
 
+    /// Returns true if the block with given hash is in the import queue.
+    fn is_queued(&self, hash: &H256) -> bool;
+
     /// Get block total difficulty.
     fn block_total_difficulty(&self, id: BlockId) -> Option<U256>;
 
@@ -2284,6 +2284,10 @@ impl Engine<EthereumMachine> for AuthorityRound {
         self.validators.register_client(client);
     }
 
+    fn validators(&self, parent: &H256) -> Option<Vec<Address>> {
+        self.validators.validators(parent)
+    }
+
     fn set_signer(&self, signer: Option<Box<dyn EngineSigner>>) {
         *self.signer.write() = signer;
     }
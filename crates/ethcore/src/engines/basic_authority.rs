@@ -22,7 +22,7 @@ use client::EngineClient;
 use crypto::publickey::{self, Signature};
 use engines::{signer::EngineSigner, ConstructedVerifier, Engine, EngineError, Seal, SealingState};
 use error::{BlockError, Error};
-use ethereum_types::{H256, H520};
+use ethereum_types::{Address, H256, H520};
 use ethjson;
 use machine::{AuxiliaryData, Call, EthereumMachine};
 use parking_lot::RwLock;
@@ -201,6 +201,10 @@ impl Engine<EthereumMachine> for BasicAuthority {
         self.validators.register_client(client);
     }
 
+    fn validators(&self, parent: &H256) -> Option<Vec<Address>> {
+        self.validators.validators(parent)
+    }
+
     fn set_signer(&self, signer: Option<Box<dyn EngineSigner>>) {
         *self.signer.write() = signer;
     }
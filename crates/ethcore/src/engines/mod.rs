@@ -328,6 +328,13 @@ pub trait Engine<M: Machine>: Sync + Send {
         None
     }
 
+    /// Generous upper bound of the difficulty of a single block. Used to spot peers advertising
+    /// an implausible total difficulty.
+    fn maximum_block_difficulty(&self) -> U256 {
+        // Aura block scores stay just above 2^128, proof-of-work difficulties far below it.
+        U256::one() << 129
+    }
+
     /// Block transformation functions, before the transactions.
     /// `epoch_begin` set to true if this block kicks off an epoch.
     fn on_new_block(
@@ -485,6 +492,11 @@ pub trait Engine<M: Machine>: Sync + Send {
     /// Add Client which can be used for sealing, potentially querying the state and sending messages.
     fn register_client(&self, _client: Weak<M::EngineClient>) {}
 
+    /// Validators of the block following `parent`, for engines sealing with a validator set.
+    fn validators(&self, _parent: &H256) -> Option<Vec<Address>> {
+        None
+    }
+
     /// Trigger next step of the consensus engine.
     fn step(&self) {}
 
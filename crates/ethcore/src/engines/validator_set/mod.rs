@@ -117,6 +117,20 @@ pub trait ValidatorSet: Send + Sync + 'static {
         self.count_with_caller(parent, &*default)
     }
 
+    /// Returns all current validators, `None` if they could not be read.
+    fn validators(&self, parent: &H256) -> Option<Vec<Address>> {
+        let default = self.default_caller(BlockId::Hash(*parent));
+        match self.count_with_caller(parent, &*default) {
+            // the count of a set which failed to read its validators
+            count if count == usize::max_value() => None,
+            count => Some(
+                (0..count)
+                    .map(|nonce| self.get_with_caller(parent, nonce, &*default))
+                    .collect(),
+            ),
+        }
+    }
+
     /// Signalling that a new epoch has begun.
     ///
     /// All calls here will be from the `SYSTEM_ADDRESS`: 2^160 - 2
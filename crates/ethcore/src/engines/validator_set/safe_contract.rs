@@ -722,7 +722,7 @@ mod tests {
     use rustc_hex::FromHex;
     use spec::Spec;
     use std::sync::Arc;
-    use test_helpers::generate_dummy_client_with_spec;
+    use test_helpers::{generate_dummy_client_with_spec, TestNotify};
     use types::{
         ids::BlockId,
         transaction::{Action, Transaction, TypedTransaction},
@@ -750,6 +750,54 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn client_notifies_validator_changes() {
+        let tap = Arc::new(AccountProvider::transient_provider());
+        let s0: Secret = keccak("1").into();
+        let v1 = tap.insert_account(keccak("0").into(), &"".into()).unwrap();
+        let chain_id = Spec::new_validator_safe_contract().chain_id();
+        let client = generate_dummy_client_with_spec(Spec::new_validator_safe_contract);
+        client
+            .engine()
+            .register_client(Arc::downgrade(&client) as _);
+        let notify = Arc::new(TestNotify::default());
+        client.add_notify(notify.clone());
+        client
+            .miner()
+            .set_author(miner::Author::Sealer(Box::new((tap, v1, "".into()))));
+
+        for nonce in 0..2 {
+            let tx = TypedTransaction::Legacy(Transaction {
+                nonce: nonce.into(),
+                gas_price: 0.into(),
+                gas: 21000.into(),
+                action: Action::Call(Address::default()),
+                value: 0.into(),
+                data: Vec::new(),
+            })
+            .sign(&s0, Some(chain_id));
+            client
+                .miner()
+                .import_own_transaction(client.as_ref(), tx.into())
+                .unwrap();
+            EngineClient::update_sealing(&*client, ForceUpdateSealing::No);
+        }
+        assert_eq!(client.chain_info().best_block_number, 2);
+
+        // the validators did not change with the second block
+        let notified = notify.validators.read();
+        assert_eq!(notified.len(), 1);
+        let mut validators = notified[0].clone();
+        validators.sort();
+        assert_eq!(
+            validators,
+            vec![
+                "7d577a597b2742b498cb5cf0c26cdcd726d39e6e".parse().unwrap(),
+                "82a978b3f5962a5b0957d9ee9eef472ee55b42f1".parse().unwrap(),
+            ]
+        );
+    }
+
     #[test]
     fn knows_validators() {
         let tap = Arc::new(AccountProvider::transient_provider());
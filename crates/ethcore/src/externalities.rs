@@ -24,8 +24,9 @@ use std::{cmp, sync::Arc};
 use trace::{Tracer, VMTracer};
 use types::transaction::UNSIGNED_SENDER;
 use vm::{
-    self, AccessList, ActionParams, ActionValue, CallType, ContractCreateResult,
-    CreateContractAddress, EnvInfo, Ext, MessageCallResult, ReturnData, Schedule, TrapKind,
+    self, schedule::EIP2935_HISTORY_SERVE_WINDOW, AccessList, ActionParams, ActionValue, CallType,
+    ContractCreateResult, CreateContractAddress, EnvInfo, Ext, MessageCallResult, ReturnData,
+    Schedule, TrapKind,
 };
 
 /// Policy for handling output data on `RETURN` opcode.
@@ -258,6 +259,30 @@ where
         }
     }
 
+    fn blockhash_extended(&mut self, number: &U256) -> H256 {
+        let current = U256::from(self.env_info.number);
+        if *number >= current || current - *number <= U256::from(256) {
+            return self.blockhash(number);
+        }
+        if current - *number > U256::from(EIP2935_HISTORY_SERVE_WINDOW) {
+            return H256::zero();
+        }
+
+        let history_contract_address = self.machine.params().eip2935_contract_address;
+        let key: H256 = BigEndianHash::from_uint(&(*number % EIP2935_HISTORY_SERVE_WINDOW));
+        let r = self
+            .state
+            .storage_at(&history_contract_address, &key)
+            .unwrap_or_default();
+        trace!(
+            "ext: blockhash_extended({}) -> {} self.env_info.number={}\n",
+            number,
+            r,
+            self.env_info.number
+        );
+        r
+    }
+
     fn create(
         &mut self,
         gas: &U256,
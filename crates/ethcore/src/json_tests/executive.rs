@@ -150,6 +150,10 @@ where
         self.ext.blockhash(number)
     }
 
+    fn blockhash_extended(&mut self, number: &U256) -> H256 {
+        self.ext.blockhash_extended(number)
+    }
+
     fn create(
         &mut self,
         gas: &U256,
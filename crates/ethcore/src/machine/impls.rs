@@ -310,6 +310,7 @@ impl EthereumMachine {
                 }
             }
         };
+        schedule.constant_time = self.params.constant_time_evm;
 
         if let Some(ref rules) = self.schedule_rules {
             (rules)(&mut schedule, block_number)
@@ -592,6 +593,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn constant_time_evm_applies_to_every_schedule() {
+        let mut params = ::ethereum::new_ropsten_test().params().clone();
+        let machine = EthereumMachine::regular(params.clone(), Default::default());
+        assert!(!machine.schedule(0).constant_time);
+
+        params.constant_time_evm = true;
+        let machine = EthereumMachine::with_ethash_extensions(
+            params,
+            Default::default(),
+            get_default_ethash_extensions(),
+        );
+        // frontier schedule, before the spec params are applied
+        assert!(machine.schedule(0).constant_time);
+        assert!(machine.schedule(5_000_000).constant_time);
+    }
+
     #[test]
     fn should_disallow_unsigned_transactions() {
         let rlp = "ea80843b9aca0083015f90948921ebb5f79e9e3920abe571004d0b1d5119c154865af3107a400080038080";
@@ -157,6 +157,9 @@ pub struct MinerOptions {
     pub tx_queue_no_unfamiliar_locals: bool,
     /// Do we refuse to accept service transactions even if sender is certified.
     pub refuse_service_transactions: bool,
+    /// Never seal blocks nor hand out work, for a node which doesn't propagate blocks,
+    /// e.g. one with sync in observer mode.
+    pub refuse_sealing: bool,
     /// Transaction pool limits.
     pub pool_limits: pool::Options,
     /// Initial transaction verification options.
@@ -180,6 +183,7 @@ impl Default for MinerOptions {
             tx_queue_penalization: Penalization::Disabled,
             tx_queue_no_unfamiliar_locals: false,
             refuse_service_transactions: false,
+            refuse_sealing: false,
             pool_limits: pool::Options {
                 max_count: 8_192,
                 max_per_sender: 81,
@@ -289,12 +293,16 @@ impl Miner {
         let balance_cache_size = cmp::max(4096, limits.max_count / 4);
         let refuse_service_transactions = options.refuse_service_transactions;
         let engine = spec.engine.clone();
+        if options.refuse_sealing {
+            info!(target: "miner", "Sealing is refused, sealed blocks would not be propagated in observer mode");
+        }
 
         Miner {
             sealing: Mutex::new(SealingWork {
                 queue: UsingQueue::new(options.work_queue_size),
-                enabled: options.force_sealing
-                    || spec.engine.sealing_state() != SealingState::External,
+                enabled: !options.refuse_sealing
+                    && (options.force_sealing
+                        || spec.engine.sealing_state() != SealingState::External),
                 next_allowed_reseal: Instant::now(),
                 next_mandatory_reseal: Instant::now() + options.reseal_max_period,
                 last_request: None,
@@ -1322,6 +1330,11 @@ impl miner::MinerService for Miner {
     {
         trace!(target: "miner", "update_sealing");
 
+        // the refusal is logged once on start
+        if self.options.refuse_sealing {
+            return;
+        }
+
         // Do nothing if we don't want to force update_sealing and reseal is not required.
         // but note that `requires_reseal` updates internal state.
         if force == ForceUpdateSealing::No
@@ -1378,14 +1391,16 @@ impl miner::MinerService for Miner {
     }
 
     fn is_currently_sealing(&self) -> bool {
-        self.sealing.lock().enabled && self.engine.is_allowed_to_seal()
+        !self.options.refuse_sealing
+            && self.sealing.lock().enabled
+            && self.engine.is_allowed_to_seal()
     }
 
     fn work_package<C>(&self, chain: &C) -> Option<(H256, BlockNumber, u64, U256)>
     where
         C: BlockChain + CallContract + BlockProducer + SealedBlockImporter + Nonce + Sync,
     {
-        if self.engine.sealing_state() != SealingState::External {
+        if self.options.refuse_sealing || self.engine.sealing_state() != SealingState::External {
             return None;
         }
 
@@ -1666,6 +1681,7 @@ mod tests {
                 tx_queue_strategy: PrioritizationStrategy::GasPriceOnly,
                 tx_queue_no_unfamiliar_locals: false,
                 refuse_service_transactions: false,
+                refuse_sealing: false,
                 pool_limits: Default::default(),
                 pool_verification_options: pool::verifier::Options {
                     minimal_gas_price: 0.into(),
@@ -2050,6 +2066,46 @@ mod tests {
         assert_eq!(client.chain_info().best_block_number, 4 as BlockNumber);
     }
 
+    #[test]
+    fn refuses_to_seal_when_configured() {
+        let spec = Spec::new_instant();
+        let miner = Miner::new(
+            MinerOptions {
+                refuse_sealing: true,
+                ..miner().options
+            },
+            GasPricer::new_fixed(0u64.into()),
+            &spec,
+            HashSet::<Address>::new(),
+        );
+        let client = generate_dummy_client(2);
+
+        assert!(miner
+            .import_own_transaction(
+                &*client,
+                PendingTransaction::new(transaction_with_chain_id(spec.chain_id()), None)
+            )
+            .is_ok());
+        miner.update_sealing(&*client, ForceUpdateSealing::Yes);
+        client.flush_queue();
+
+        assert!(!miner.is_currently_sealing());
+        assert_eq!(client.chain_info().best_block_number, 2 as BlockNumber);
+
+        let miner = Miner::new(
+            MinerOptions {
+                refuse_sealing: true,
+                ..miner().options
+            },
+            GasPricer::new_fixed(0u64.into()),
+            &Spec::new_test(),
+            HashSet::<Address>::new(),
+        );
+        assert!(miner
+            .work_package(&TestBlockChainClient::default())
+            .is_none());
+    }
+
     #[test]
     fn should_not_fail_setting_engine_signer_without_account_provider() {
         let spec = Spec::new_test_round;
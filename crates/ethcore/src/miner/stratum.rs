@@ -21,17 +21,21 @@
 
 use std::{
     fmt,
-    net::{AddrParseError, SocketAddr},
+    net::{AddrParseError, IpAddr, SocketAddr},
     sync::{Arc, Weak},
 };
 
 use client::{Client, ImportSealedBlock};
+use error::{Error as EthcoreError, ErrorKind, ImportErrorKind};
 use ethash::{self, SeedHashCompute};
 #[cfg(feature = "work-notify")]
 use ethcore_miner::work_notify::NotifyWork;
 #[cfg(feature = "work-notify")]
 use ethcore_stratum::PushWorkHandler;
-use ethcore_stratum::{Error as StratumServiceError, JobDispatcher, Stratum as StratumService};
+use ethcore_stratum::{
+    Error as StratumServiceError, JobDispatcher, JobStatus, Stratum as StratumService,
+    StratumConfig, SubmitError,
+};
 use ethereum_types::{H256, H64, U256};
 use miner::{Miner, MinerService};
 use parking_lot::Mutex;
@@ -50,6 +54,18 @@ pub struct Options {
     pub secret: Option<H256>,
 }
 
+impl Options {
+    /// Configuration of the stratum server listening on the address of these options
+    pub fn service_config(&self) -> Result<StratumConfig, Error> {
+        let addr = SocketAddr::new(self.listen_addr.parse::<IpAddr>()?, self.port);
+        StratumConfig::builder()
+            .with_listen_addr(addr)
+            .with_secret(self.secret)
+            .build()
+            .map_err(|e| Error::Service(e.into()))
+    }
+}
+
 fn clean_0x(s: &str) -> &str {
     if s.starts_with("0x") {
         &s[2..]
@@ -116,6 +132,17 @@ impl fmt::Display for PayloadError {
     }
 }
 
+/// Maps a sealing or import error to the reason reported to the miner.
+fn submit_error(e: EthcoreError) -> SubmitError {
+    match *e.kind() {
+        ErrorKind::PowHashInvalid => SubmitError::Stale,
+        ErrorKind::PowInvalid => SubmitError::InvalidSolution,
+        ErrorKind::Import(ImportErrorKind::AlreadyInChain)
+        | ErrorKind::Import(ImportErrorKind::AlreadyQueued) => SubmitError::Duplicate,
+        _ => SubmitError::Other(e.to_string()),
+    }
+}
+
 /// Job dispatcher for stratum service
 pub struct StratumJobDispatcher {
     seed_compute: Mutex<SeedHashCompute>,
@@ -125,8 +152,9 @@ pub struct StratumJobDispatcher {
 
 impl JobDispatcher for StratumJobDispatcher {
     fn initial(&self) -> Option<String> {
-        // initial payload may contain additional data, not in this case
-        self.job()
+        // initial payload may contain additional data, not in this case.
+        // It is not pushed as a job, so it goes without a job id.
+        self.job().map(|job| format!(r#"["0x", {}"#, &job[1..]))
     }
 
     fn job(&self) -> Option<String> {
@@ -139,16 +167,24 @@ impl JobDispatcher for StratumJobDispatcher {
         })
     }
 
-    fn submit(&self, payload: Vec<String>) -> Result<(), StratumServiceError> {
-        let payload = SubmitPayload::from_args(payload)
-            .map_err(|e| StratumServiceError::Dispatch(e.to_string()))?;
+    fn submit_share(&self, payload: Vec<String>, job: JobStatus) -> Result<(), SubmitError> {
+        // a share for a job never pushed or superseded too long ago is not checked at all
+        if job == JobStatus::Unknown {
+            debug!(target: "stratum", "Rejected share for an unknown job");
+            return Err(SubmitError::Stale);
+        }
+        let payload =
+            SubmitPayload::from_args(payload).map_err(|e| SubmitError::Other(e.to_string()))?;
 
+        // the pow hash identifies the work package, shares for a job which is not current
+        // are still checked against it
         trace!(
             target: "stratum",
-            "submit_work: Decoded: nonce={}, pow_hash={}, mix_hash={}",
+            "submit_work: Decoded: nonce={}, pow_hash={}, mix_hash={}, job={:?}",
             payload.nonce,
             payload.pow_hash,
             payload.mix_hash,
+            job,
         );
 
         self.with_core_result(|client, miner| {
@@ -161,7 +197,7 @@ impl JobDispatcher for StratumJobDispatcher {
                 Ok(_) => Ok(()),
                 Err(e) => {
                     warn!(target: "stratum", "submit_seal error: {e:?}");
-                    Err(StratumServiceError::Dispatch(e.to_string()))
+                    Err(submit_error(e))
                 }
             }
         })
@@ -178,13 +214,13 @@ impl StratumJobDispatcher {
         }
     }
 
-    /// Serializes payload for stratum service
+    /// Serializes payload for stratum service, the service prefixes it with the job id
     fn payload(&self, pow_hash: H256, difficulty: U256, number: u64) -> String {
         // TODO: move this to engine
         let target = ethash::difficulty_to_boundary(&difficulty);
         let seed_hash = &self.seed_compute.lock().hash_block_number(number);
         let seed_hash = H256::from_slice(&seed_hash[..]);
-        format!(r#"["0x", "0x{pow_hash:x}","0x{seed_hash:x}","0x{target:x}","0x{number:x}"]"#)
+        format!(r#"["0x{pow_hash:x}","0x{seed_hash:x}","0x{target:x}","0x{number:x}"]"#)
     }
 
     fn with_core<F, R>(&self, f: F) -> Option<R>
@@ -196,9 +232,9 @@ impl StratumJobDispatcher {
             .and_then(|client| self.miner.upgrade().and_then(|miner| (f)(client, miner)))
     }
 
-    fn with_core_result<F>(&self, f: F) -> Result<(), StratumServiceError>
+    fn with_core_result<F>(&self, f: F) -> Result<(), SubmitError>
     where
-        F: Fn(Arc<Client>, Arc<Miner>) -> Result<(), StratumServiceError>,
+        F: Fn(Arc<Client>, Arc<Miner>) -> Result<(), SubmitError>,
     {
         match (self.client.upgrade(), self.miner.upgrade()) {
             (Some(client), Some(miner)) => f(client, miner),
@@ -251,15 +287,10 @@ impl Stratum {
         miner: Weak<Miner>,
         client: Weak<Client>,
     ) -> Result<Stratum, Error> {
-        use std::net::IpAddr;
-
         let dispatcher = Arc::new(StratumJobDispatcher::new(miner, client));
 
-        let service = StratumService::start(
-            &SocketAddr::new(options.listen_addr.parse::<IpAddr>()?, options.port),
-            dispatcher.clone(),
-            options.secret,
-        )?;
+        let service =
+            StratumService::start_with_config(&options.service_config()?, dispatcher.clone())?;
 
         Ok(Stratum {
             dispatcher,
@@ -267,11 +298,36 @@ impl Stratum {
         })
     }
 
-    /// Start STRATUM job dispatcher and register it in the miner
+    /// Start STRATUM job dispatcher and register it in the miner.
+    /// Returns the stratum service, e.g. to collect its metrics.
     #[cfg(feature = "work-notify")]
-    pub fn register(cfg: &Options, miner: Arc<Miner>, client: Weak<Client>) -> Result<(), Error> {
+    pub fn register(
+        cfg: &Options,
+        miner: Arc<Miner>,
+        client: Weak<Client>,
+    ) -> Result<Arc<StratumService>, Error> {
         let stratum = Stratum::start(cfg, Arc::downgrade(&miner.clone()), client)?;
+        let service = stratum.service.clone();
         miner.add_work_listener(Box::new(stratum) as Box<dyn NotifyWork>);
-        Ok(())
+        Ok(service)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_shares_for_unknown_jobs() {
+        let dispatcher = StratumJobDispatcher::new(Weak::new(), Weak::new());
+        let payload = vec![
+            "0x0000000000000001".to_owned(),
+            format!("0x{:x}", H256::zero()),
+            format!("0x{:x}", H256::zero()),
+        ];
+        assert_eq!(
+            dispatcher.submit(payload, JobStatus::Unknown),
+            Err(SubmitError::Stale)
+        );
     }
 }
@@ -315,6 +315,11 @@ impl LooseReader {
 
         Ok(LooseReader { dir, manifest })
     }
+
+    /// Returns true if the chunk with the given hash is present on disk.
+    pub fn has_chunk(&self, hash: H256) -> bool {
+        self.dir.join(format!("{hash:x}")).is_file()
+    }
 }
 
 impl SnapshotReader for LooseReader {
@@ -51,6 +51,9 @@ use kvdb::DBTransaction;
 use parking_lot::{Mutex, RwLock, RwLockReadGuard};
 use snappy;
 
+/// File in the recovery directory naming the manifest its chunks belong to.
+const RECOVERY_MANIFEST_FILE: &str = "RESTORING";
+
 /// Helper for removing directories in case of error.
 struct Guard(bool, PathBuf);
 
@@ -270,6 +273,7 @@ pub struct Service {
     pruning: Algorithm,
     status: Mutex<RestorationStatus>,
     reader: RwLock<Option<LooseReader>>,
+    manifest_complete: AtomicBool,
     engine: Arc<dyn EthEngine>,
     genesis_block: Bytes,
     state_chunks: AtomicUsize,
@@ -281,6 +285,16 @@ pub struct Service {
     restoring_snapshot: AtomicBool,
 }
 
+/// Whether every chunk referenced by the reader's manifest is on disk.
+fn has_all_chunks(reader: &LooseReader) -> bool {
+    let manifest = reader.manifest();
+    manifest
+        .state_hashes
+        .iter()
+        .chain(manifest.block_hashes.iter())
+        .all(|hash| reader.has_chunk(*hash))
+}
+
 impl Service {
     /// Create a new snapshot service from the given parameters.
     pub fn new(params: ServiceParams) -> Result<Self, Error> {
@@ -292,6 +306,7 @@ impl Service {
             pruning: params.pruning,
             status: Mutex::new(RestorationStatus::Inactive),
             reader: RwLock::new(None),
+            manifest_complete: AtomicBool::new(false),
             engine: params.engine,
             genesis_block: params.genesis_block,
             state_chunks: AtomicUsize::new(0),
@@ -325,6 +340,7 @@ impl Service {
         }
 
         let reader = LooseReader::new(service.snapshot_dir()).ok();
+        *service.manifest_complete.get_mut() = reader.as_ref().is_some_and(has_all_chunks);
         *service.reader.get_mut() = reader;
 
         Ok(service)
@@ -568,6 +584,7 @@ impl Service {
 
             // destroy the old snapshot reader.
             *reader = None;
+            self.manifest_complete.store(false, Ordering::SeqCst);
 
             if snapshot_dir.exists() {
                 fs::remove_dir_all(&snapshot_dir)?;
@@ -575,7 +592,10 @@ impl Service {
 
             fs::rename(temp_dir, &snapshot_dir)?;
 
-            *reader = Some(LooseReader::new(snapshot_dir)?);
+            let new_reader = LooseReader::new(snapshot_dir)?;
+            self.manifest_complete
+                .store(has_all_chunks(&new_reader), Ordering::SeqCst);
+            *reader = Some(new_reader);
 
             guard.disarm();
             Ok(())
@@ -626,7 +646,15 @@ impl Service {
 
         // make new restoration.
         let writer = match recover {
-            true => Some(LooseWriter::new(recovery_temp)?),
+            true => {
+                let writer = LooseWriter::new(recovery_temp.clone())?;
+                let manifest_hash = keccak(manifest.clone().into_rlp());
+                fs::write(
+                    recovery_temp.join(RECOVERY_MANIFEST_FILE),
+                    manifest_hash.as_bytes(),
+                )?;
+                Some(writer)
+            }
             false => None,
         };
 
@@ -674,6 +702,15 @@ impl Service {
     ) -> Result<(), Error> {
         let prev_chunks = self.prev_chunks_dir();
 
+        // Chunks stashed for another manifest can't be trusted
+        let stashed_for = fs::read(prev_chunks.join(RECOVERY_MANIFEST_FILE))?;
+        let manifest_hash = keccak(manifest.clone().into_rlp());
+        if stashed_for != manifest_hash.as_bytes() {
+            trace!(target: "snapshot", "Discarding previous chunks of another manifest");
+            fs::remove_dir_all(&prev_chunks)?;
+            return Ok(());
+        }
+
         // Restore previous snapshot chunks
         let files = fs::read_dir(prev_chunks.as_path())?;
         let mut num_temp_chunks = 0;
@@ -716,6 +753,11 @@ impl Service {
 
         let hash = keccak(&buffer);
 
+        // A chunk is stored under its hash; anything else is corrupt or not a chunk at all
+        if path.file_name().and_then(|name| name.to_str()) != Some(format!("{hash:x}").as_str()) {
+            return Ok(false);
+        }
+
         let is_state = if manifest.block_hashes.contains(&hash) {
             false
         } else if manifest.state_hashes.contains(&hash) {
@@ -749,6 +791,7 @@ impl Service {
         if recover {
             let mut reader = self.reader.write();
             *reader = None; // destroy the old reader if it existed.
+            self.manifest_complete.store(false, Ordering::SeqCst);
 
             let snapshot_dir = self.snapshot_dir();
 
@@ -758,9 +801,13 @@ impl Service {
             }
 
             trace!(target: "snapshot", "copying restored snapshot files over");
+            let _ = fs::remove_file(self.temp_recovery_dir().join(RECOVERY_MANIFEST_FILE));
             fs::rename(self.temp_recovery_dir(), &snapshot_dir)?;
 
-            *reader = Some(LooseReader::new(snapshot_dir)?);
+            let new_reader = LooseReader::new(snapshot_dir)?;
+            self.manifest_complete
+                .store(has_all_chunks(&new_reader), Ordering::SeqCst);
+            *reader = Some(new_reader);
         }
 
         let _ = fs::remove_dir_all(self.restoration_dir());
@@ -882,6 +929,10 @@ impl SnapshotService for Service {
         self.reader.read().as_ref().and_then(|r| r.chunk(hash).ok())
     }
 
+    fn is_manifest_complete(&self) -> bool {
+        self.manifest_complete.load(Ordering::SeqCst)
+    }
+
     fn completed_chunks(&self) -> Option<Vec<H256>> {
         let restoration = self.restoration.lock();
 
@@ -386,3 +386,105 @@ fn recover_aborted_recovery() {
         _ => panic!("Snapshot restoration must be ongoing"),
     }
 }
+
+#[test]
+fn previous_chunks_of_other_manifest_are_discarded() {
+    let _ = ::env_logger::try_init();
+
+    const NUM_BLOCKS: u32 = 400;
+    let gas_prices = vec![1.into(), 2.into(), 3.into(), 999.into()];
+    let client =
+        generate_dummy_client_with_spec_and_data(Spec::new_null, NUM_BLOCKS, 5, &gas_prices, false);
+
+    let spec = Spec::new_null();
+    let tempdir = TempDir::new().unwrap();
+    let db_config = DatabaseConfig::with_columns(::db::NUM_COLUMNS);
+    let client2 = Client::new(
+        Default::default(),
+        &spec,
+        new_db(),
+        Arc::new(::miner::Miner::new_for_tests(&spec, None)),
+        IoChannel::disconnected(),
+    )
+    .unwrap();
+    let service_params = ServiceParams {
+        engine: spec.engine.clone(),
+        genesis_block: spec.genesis_block(),
+        restoration_db_handler: restoration_db_handler(db_config),
+        pruning: ::journaldb::Algorithm::Archive,
+        channel: IoChannel::disconnected(),
+        snapshot_root: tempdir.path().to_owned(),
+        client: client2.clone(),
+    };
+
+    let service = Service::new(service_params).unwrap();
+    service.take_snapshot(&client, NUM_BLOCKS as u64).unwrap();
+
+    let manifest = service.manifest().unwrap();
+    service.init_restore(manifest.clone(), true).unwrap();
+    for hash in &manifest.state_hashes {
+        let chunk = service.chunk(*hash).unwrap();
+        service.feed_state_chunk(*hash, &chunk);
+    }
+    service.abort_restore();
+
+    // Same chunks, but a differently hashed manifest
+    let other = ManifestData {
+        block_number: manifest.block_number + 1,
+        ..manifest.clone()
+    };
+    service.init_restore(other, true).unwrap();
+
+    match service.restoration_status() {
+        RestorationStatus::Ongoing {
+            block_chunks_done,
+            state_chunks_done,
+            ..
+        } => {
+            assert_eq!(block_chunks_done, 0);
+            assert_eq!(state_chunks_done, 0);
+        }
+        e => panic!("Snapshot restoration must be ongoing ; {:?}", e),
+    }
+    assert_eq!(service.completed_chunks(), Some(vec![]));
+    service.abort_restore();
+}
+
+#[test]
+fn manifest_completeness_is_cached_on_load() {
+    let _ = ::env_logger::try_init();
+
+    const NUM_BLOCKS: u32 = 20;
+
+    let client =
+        generate_dummy_client_with_spec_and_data(Spec::new_null, NUM_BLOCKS, 1, &[1.into()], false);
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("snapshot");
+    let spec = Spec::new_null();
+    let new_service = || {
+        let db_config = DatabaseConfig::with_columns(::db::NUM_COLUMNS);
+        Service::new(ServiceParams {
+            engine: spec.engine.clone(),
+            genesis_block: spec.genesis_block(),
+            restoration_db_handler: restoration_db_handler(db_config),
+            pruning: ::journaldb::Algorithm::Archive,
+            channel: IoChannel::disconnected(),
+            snapshot_root: path.clone(),
+            client: client.clone(),
+        })
+        .unwrap()
+    };
+
+    let service = new_service();
+    assert!(!service.is_manifest_complete());
+
+    service.take_snapshot(&client, NUM_BLOCKS as u64).unwrap();
+    assert!(service.is_manifest_complete());
+    assert!(new_service().is_manifest_complete());
+
+    let chunk = service.manifest().unwrap().block_hashes[0];
+    drop(service);
+    fs::remove_file(path.join("current").join(format!("{chunk:x}"))).unwrap();
+    assert!(!new_service().is_manifest_complete());
+}
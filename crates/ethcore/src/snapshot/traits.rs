@@ -39,6 +39,9 @@ pub trait SnapshotService: Sync + Send {
     /// Get raw chunk for a given hash.
     fn chunk(&self, hash: H256) -> Option<Bytes>;
 
+    /// Returns true if every chunk referenced by the current manifest is available.
+    fn is_manifest_complete(&self) -> bool;
+
     /// Ask the snapshot service for the restoration status.
     fn restoration_status(&self) -> RestorationStatus;
 
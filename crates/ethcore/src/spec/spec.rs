@@ -52,6 +52,7 @@ use trace::{NoopTracer, NoopVMTracer};
 pub use ethash::OptimizeFor;
 
 const MAX_TRANSACTION_SIZE: usize = 300 * 1024;
+const EIP2935_DEFAULT_CONTRACT_ADDRESS: &str = "0000f90827f1c53a10cb7a02335b175320002935";
 
 // helper for formatting errors.
 fn fmt_err<F: ::std::fmt::Display>(f: F) -> String {
@@ -149,12 +150,20 @@ pub struct CommonParams {
     pub eip3541_transition: BlockNumber,
     /// Number of first block where EIP-3607 rule begins.
     pub eip3607_transition: BlockNumber,
+    /// Number of first block where BLOCKHASH serves blocks older than 256 from the EIP-2935 history contract.
+    pub eip2935_transition: BlockNumber,
+    /// EIP-2935 block hash history contract address.
+    pub eip2935_contract_address: Address,
+    /// BLOCKHASH gas cost for blocks served from the EIP-2935 history contract.
+    pub eip2935_blockhash_gas: usize,
     /// Number of first block where dust cleanup rules (EIP-168 and EIP169) begin.
     pub dust_protection_transition: BlockNumber,
     /// Nonce cap increase per block. Nonce cap is only checked if dust protection is enabled.
     pub nonce_cap_increment: u64,
     /// Enable dust cleanup for contracts.
     pub remove_dust_contracts: bool,
+    /// Execute the EVM without operand dependent timing, see `vm::Schedule::constant_time`.
+    pub constant_time_evm: bool,
     /// Wasm activation blocknumber, if any disabled initially.
     pub wasm_activation_transition: BlockNumber,
     /// Wasm deactivation blocknumber, if enabled.
@@ -244,6 +253,8 @@ impl CommonParams {
         schedule.eip3541 = block_number >= self.eip3541_transition;
         schedule.eip1559 = block_number >= self.eip1559_transition;
         schedule.eip3198 = block_number >= self.eip3198_transition;
+        schedule.eip2935 = block_number >= self.eip2935_transition;
+        schedule.eip2935_blockhash_gas = self.eip2935_blockhash_gas;
         if schedule.eip1559 {
             schedule.eip1559_elasticity_multiplier = self.eip1559_elasticity_multiplier.as_usize();
 
@@ -437,8 +448,25 @@ impl From<ethjson::spec::Params> for CommonParams {
                 .dust_protection_transition
                 .map_or_else(BlockNumber::max_value, Into::into),
             eip3607_transition: p.eip3607_transition.map_or(0, Into::into),
+            eip2935_transition: p
+                .eip2935_transition
+                .map_or_else(BlockNumber::max_value, Into::into),
+            eip2935_contract_address: p.eip2935_contract_address.map_or_else(
+                || {
+                    Address::from_slice(
+                        &EIP2935_DEFAULT_CONTRACT_ADDRESS
+                            .from_hex::<Vec<u8>>()
+                            .expect("Default EIP-2935 contract address is valid"),
+                    )
+                },
+                Into::into,
+            ),
+            eip2935_blockhash_gas: p
+                .eip2935_blockhash_gas
+                .map_or(::vm::schedule::EIP2929_COLD_SLOAD_COST, Into::into),
             nonce_cap_increment: p.nonce_cap_increment.map_or(64, Into::into),
             remove_dust_contracts: p.remove_dust_contracts.unwrap_or(false),
+            constant_time_evm: p.constant_time_evm.unwrap_or(false),
             gas_limit_bound_divisor: p.gas_limit_bound_divisor.into(),
             registrar: p.registrar.map_or_else(Address::default, Into::into),
             node_permission_contract: p.node_permission_contract.map(Into::into),
@@ -751,6 +779,7 @@ impl Spec {
             params.eip3198_transition,
             params.eip3529_transition,
             params.eip3541_transition,
+            params.eip2935_transition,
             params.dust_protection_transition,
             params.wasm_activation_transition,
             params.wasm_disable_transition,
@@ -676,6 +676,8 @@ pub fn get_bad_state_dummy_block() -> Bytes {
 pub struct TestNotify {
     /// Messages store
     pub messages: RwLock<Vec<Bytes>>,
+    /// Validators notified about
+    pub validators: RwLock<Vec<Vec<Address>>>,
 }
 
 impl ChainNotify for TestNotify {
@@ -683,6 +685,10 @@ impl ChainNotify for TestNotify {
         let ChainMessageType::Consensus(data) = message;
         self.messages.write().push(data);
     }
+
+    fn validators_changed(&self, validators: &[Address]) {
+        self.validators.write().push(validators.to_vec());
+    }
 }
 
 /// Returns engine signer with specified address
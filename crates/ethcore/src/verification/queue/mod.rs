@@ -23,7 +23,7 @@ use engines::EthEngine;
 use error::{BlockError, Error, ErrorKind, ImportErrorKind};
 use ethereum_types::{H256, U256};
 use io::*;
-use len_caching_lock::LenCachingMutex;
+use len_caching_lock::{Len, LenCachingMutex};
 use parity_util_mem::{MallocSizeOf, MallocSizeOfExt};
 use parking_lot::{Condvar, Mutex, RwLock};
 use std::{
@@ -34,20 +34,27 @@ use std::{
         atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering},
         Arc,
     },
-    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-use self::kind::{BlockLike, Kind};
+use self::{
+    kind::{BlockLike, Kind},
+    pool::{Registration, Task},
+};
 
+pub use self::pool::VerifierPool;
 pub use types::verification_queue_info::VerificationQueueInfo as QueueInfo;
 
 pub mod kind;
+mod pool;
 
 const MIN_MEM_LIMIT: usize = 16384;
 const MIN_QUEUE_LIMIT: usize = 512;
 /// Empiric estimation of the minimal length of the processing queue,
 /// That definitely doesn't contain forks inside.
 const MAX_QUEUE_WITH_FORK: usize = 8;
+/// Most verified items held back waiting for their parent, the ones beyond are drained as they come.
+const MAX_HELD_ITEMS: usize = 2048;
 
 /// Type alias for block queue convenience.
 pub type BlockQueue = VerificationQueue<self::kind::Blocks>;
@@ -55,6 +62,9 @@ pub type BlockQueue = VerificationQueue<self::kind::Blocks>;
 /// Type alias for header queue convenience.
 pub type HeaderQueue = VerificationQueue<self::kind::Headers>;
 
+/// Predicate telling whether an item hash is known to be bad.
+pub type BadHashOracle = Arc<dyn Fn(&H256) -> bool + Send + Sync>;
+
 /// Verification queue configuration
 #[derive(Debug, PartialEq, Clone)]
 pub struct Config {
@@ -66,6 +76,12 @@ pub struct Config {
     pub max_mem_use: usize,
     /// Settings for the number of verifiers and adaptation strategy.
     pub verifier_settings: VerifierSettings,
+    /// Hold back verified items on drain until their parent is in the chain or drained
+    /// before them, instead of letting the import reject them as orphans.
+    pub strict_ordering: bool,
+    /// Longest time a verified item is held back waiting for its parent.
+    /// It is drained anyway afterwards, so that a reorg does not stall the queue.
+    pub max_hold: Duration,
 }
 
 impl Default for Config {
@@ -74,6 +90,8 @@ impl Default for Config {
             max_queue_size: 30000,
             max_mem_use: 50 * 1024 * 1024,
             verifier_settings: VerifierSettings::default(),
+            strict_ordering: false,
+            max_hold: Duration::from_secs(10),
         }
     }
 }
@@ -100,13 +118,6 @@ impl Default for VerifierSettings {
     }
 }
 
-// pool states
-enum State {
-    // all threads with id < inner value are to work.
-    Work(usize),
-    Exit,
-}
-
 /// An item which is in the process of being verified.
 #[derive(MallocSizeOf)]
 pub struct Verifying<K: Kind> {
@@ -140,13 +151,90 @@ struct Sizes {
     unverified: AtomicUsize,
     verifying: AtomicUsize,
     verified: AtomicUsize,
+    held: AtomicUsize,
+}
+
+/// Verified items waiting for their parent to be drained, keyed by parent hash.
+struct HeldItems<V> {
+    by_parent: HashMap<H256, Vec<(Instant, V)>>,
+    len: usize,
+}
+
+impl<V> Default for HeldItems<V> {
+    fn default() -> Self {
+        HeldItems {
+            by_parent: HashMap::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<V> Len for HeldItems<V> {
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl<V: BlockLike> HeldItems<V> {
+    fn hold(&mut self, since: Instant, item: V) {
+        self.by_parent
+            .entry(item.parent_hash())
+            .or_default()
+            .push((since, item));
+        self.len += 1;
+    }
+
+    /// Remove the items held back for `parent`.
+    fn release_children(&mut self, parent: &H256) -> Vec<V> {
+        let children = self.by_parent.remove(parent).unwrap_or_default();
+        self.len -= children.len();
+        children.into_iter().map(|(_, item)| item).collect()
+    }
+
+    /// Remove the items for which `release` holds given their parent hash and hold time, oldest first.
+    fn release<F: FnMut(&H256, Instant) -> bool>(&mut self, mut release: F) -> Vec<V> {
+        let mut released = Vec::new();
+        self.by_parent.retain(|parent, items| {
+            let (gone, kept): (Vec<_>, Vec<_>) = items
+                .drain(..)
+                .partition(|(since, _)| release(parent, *since));
+            released.extend(gone);
+            *items = kept;
+            !items.is_empty()
+        });
+        self.len -= released.len();
+        released.sort_by_key(|(since, _)| *since);
+        released.into_iter().map(|(_, item)| item).collect()
+    }
+
+    /// Drop the items for which `evict` holds, until no more are evicted, so that `evict`
+    /// may refuse the children of the items it evicted before.
+    fn evict<F: FnMut(&V) -> bool>(&mut self, mut evict: F) {
+        loop {
+            let before = self.len;
+            let mut len = 0;
+            self.by_parent.retain(|_, items| {
+                items.retain(|(_, item)| !evict(item));
+                len += items.len();
+                !items.is_empty()
+            });
+            self.len = len;
+            if len == before {
+                break;
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.by_parent.clear();
+        self.len = 0;
+    }
 }
 
 /// A queue of items to be verified. Sits between network or other I/O and the `BlockChain`.
 /// Keeps them in the same order as inserted, minus invalid items.
 pub struct VerificationQueue<K: Kind> {
     engine: Arc<dyn EthEngine>,
-    more_to_verify: Arc<Condvar>,
     verification: Arc<Verification<K>>,
     deleting: Arc<AtomicBool>,
     ready_signal: Arc<QueueSignal>,
@@ -156,9 +244,12 @@ pub struct VerificationQueue<K: Kind> {
     max_queue_size: usize,
     max_mem_use: usize,
     scale_verifiers: bool,
-    verifier_handles: Vec<JoinHandle<()>>,
-    state: Arc<(Mutex<State>, Condvar)>,
+    max_verifiers: usize,
+    verifiers: Registration,
     total_difficulty: RwLock<U256>,
+    bad_hash_oracle: RwLock<Option<BadHashOracle>>,
+    strict_ordering: bool,
+    max_hold: Duration,
 }
 
 struct QueueSignal {
@@ -214,32 +305,143 @@ struct Verification<K: Kind> {
     unverified: LenCachingMutex<VecDeque<K::Unverified>>,
     verifying: LenCachingMutex<VecDeque<Verifying<K>>>,
     verified: LenCachingMutex<VecDeque<K::Verified>>,
+    held: LenCachingMutex<HeldItems<K::Verified>>,
     bad: Mutex<HashSet<H256>>,
     sizes: Sizes,
     check_seal: bool,
 }
 
+/// Verification work of a queue, carried out by the verifier threads.
+struct VerifyTask<K: Kind> {
+    verification: Arc<Verification<K>>,
+    engine: Arc<dyn EthEngine>,
+    ready: Arc<QueueSignal>,
+    empty: Arc<Condvar>,
+}
+
+impl<K: Kind> Task for VerifyTask<K> {
+    fn has_work(&self) -> bool {
+        self.verification.unverified.load_len() > 0
+    }
+
+    fn verify_one(&self) {
+        let verification = &self.verification;
+
+        // do work on this item.
+        let item = {
+            // acquire these locks before getting the item to verify.
+            let mut unverified = verification.unverified.lock();
+            let mut verifying = verification.verifying.lock();
+
+            let item = match unverified.pop_front() {
+                Some(item) => item,
+                None => return,
+            };
+
+            verification
+                .sizes
+                .unverified
+                .fetch_sub(item.malloc_size_of(), AtomicOrdering::SeqCst);
+            verifying.push_back(Verifying {
+                hash: item.hash(),
+                output: None,
+            });
+            item
+        };
+
+        let hash = item.hash();
+        // t_nb 5.0 verify standalone block (this verification is done in VerificationQueue thread pool)
+        let is_ready = match K::verify(item, &*self.engine, verification.check_seal) {
+            Ok(verified) => {
+                let mut verifying = verification.verifying.lock();
+                let mut idx = None;
+                // find item again and remove it from verified queue
+                for (i, e) in verifying.iter_mut().enumerate() {
+                    if e.hash == hash {
+                        idx = Some(i);
+
+                        verification
+                            .sizes
+                            .verifying
+                            .fetch_add(verified.malloc_size_of(), AtomicOrdering::SeqCst);
+                        e.output = Some(verified);
+                        break;
+                    }
+                }
+
+                if idx == Some(0) {
+                    // we're next!
+                    let mut verified = verification.verified.lock();
+                    let mut bad = verification.bad.lock();
+                    VerificationQueue::<K>::drain_verifying(
+                        &mut verifying,
+                        &mut verified,
+                        &mut bad,
+                        &verification.sizes,
+                    );
+                    true
+                } else {
+                    false
+                }
+            }
+            Err(_) => {
+                let mut verifying = verification.verifying.lock();
+                let mut verified = verification.verified.lock();
+                let mut bad = verification.bad.lock();
+
+                bad.insert(hash);
+                verifying.retain(|e| e.hash != hash);
+
+                if verifying.front().is_some_and(|x| x.output.is_some()) {
+                    VerificationQueue::<K>::drain_verifying(
+                        &mut verifying,
+                        &mut verified,
+                        &mut bad,
+                        &verification.sizes,
+                    );
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+
+        if is_ready {
+            // Import the block immediately
+            self.ready.set_sync();
+        }
+
+        let unverified = verification.unverified.lock();
+        if unverified.is_empty() && verification.verifying.lock().is_empty() {
+            self.empty.notify_all();
+        }
+    }
+}
+
 impl<K: Kind> VerificationQueue<K> {
-    /// Creates a new queue instance.
+    /// Creates a new queue instance. Items are verified by `pool` if given,
+    /// otherwise by threads dedicated to this queue.
     pub fn new(
         config: Config,
         engine: Arc<dyn EthEngine>,
         message_channel: IoChannel<ClientIoMessage>,
         check_seal: bool,
+        pool: Option<VerifierPool>,
     ) -> Self {
         let verification = Arc::new(Verification {
             unverified: LenCachingMutex::new(VecDeque::new()),
             verifying: LenCachingMutex::new(VecDeque::new()),
             verified: LenCachingMutex::new(VecDeque::new()),
+            held: LenCachingMutex::new(HeldItems::default()),
             bad: Mutex::new(HashSet::from_iter(config.verifier_settings.bad_hashes)),
             sizes: Sizes {
                 unverified: AtomicUsize::new(0),
                 verifying: AtomicUsize::new(0),
                 verified: AtomicUsize::new(0),
+                held: AtomicUsize::new(0),
             },
             check_seal,
         });
-        let more_to_verify = Arc::new(Condvar::new());
         let deleting = Arc::new(AtomicBool::new(false));
         let ready_signal = Arc::new(QueueSignal {
             deleting: deleting.clone(),
@@ -255,43 +457,34 @@ impl<K: Kind> VerificationQueue<K> {
             cmp::min(max_verifiers, config.verifier_settings.num_verifiers),
         );
 
-        // if `auto-scaling` is enabled spawn up extra threads as they might be needed
-        // otherwise just spawn the number of threads specified by the config
-        let number_of_threads = if scale_verifiers {
-            max_verifiers
-        } else {
-            cmp::min(default_amount, max_verifiers)
-        };
-
-        let state = Arc::new((Mutex::new(State::Work(default_amount)), Condvar::new()));
-        let mut verifier_handles = Vec::with_capacity(number_of_threads);
+        let pool = pool.unwrap_or_else(|| {
+            // if `auto-scaling` is enabled spawn up extra threads as they might be needed
+            // otherwise just spawn the number of threads specified by the config
+            let number_of_threads = if scale_verifiers {
+                max_verifiers
+            } else {
+                default_amount
+            };
+            debug!(target: "verification", "Allocating {number_of_threads} verifiers, {default_amount} initially active");
+            VerifierPool::new(number_of_threads)
+        });
+        // never use more verifiers than there are threads in the pool
+        let max_verifiers = pool.num_threads();
+        let default_amount = cmp::min(default_amount, max_verifiers);
 
-        debug!(target: "verification", "Allocating {number_of_threads} verifiers, {default_amount} initially active");
         debug!(target: "verification", "Verifier auto-scaling {}", if scale_verifiers { "enabled" } else { "disabled" });
 
-        for i in 0..number_of_threads {
-            debug!(target: "verification", "Adding verification thread #{i}");
-
-            let verification = verification.clone();
-            let engine = engine.clone();
-            let wait = more_to_verify.clone();
-            let ready = ready_signal.clone();
-            let empty = empty.clone();
-            let state = state.clone();
-
-            let handle = thread::Builder::new()
-                .name(format!("Verifier #{i}"))
-                .spawn(move || {
-                    VerificationQueue::verify(verification, engine, wait, ready, empty, state, i)
-                })
-                .expect("Failed to create verifier thread.");
-            verifier_handles.push(handle);
-        }
+        let task = Arc::new(VerifyTask {
+            verification: verification.clone(),
+            engine: engine.clone(),
+            ready: ready_signal.clone(),
+            empty: empty.clone(),
+        });
+        let verifiers = pool.register(task, default_amount);
 
         VerificationQueue {
             engine,
             ready_signal,
-            more_to_verify,
             verification,
             deleting,
             processing: RwLock::new(HashMap::new()),
@@ -300,147 +493,12 @@ impl<K: Kind> VerificationQueue<K> {
             max_queue_size: cmp::max(config.max_queue_size, MIN_QUEUE_LIMIT),
             max_mem_use: cmp::max(config.max_mem_use, MIN_MEM_LIMIT),
             scale_verifiers,
-            verifier_handles,
-            state,
+            max_verifiers,
+            verifiers,
             total_difficulty: RwLock::new(0.into()),
-        }
-    }
-
-    fn verify(
-        verification: Arc<Verification<K>>,
-        engine: Arc<dyn EthEngine>,
-        wait: Arc<Condvar>,
-        ready: Arc<QueueSignal>,
-        empty: Arc<Condvar>,
-        state: Arc<(Mutex<State>, Condvar)>,
-        id: usize,
-    ) {
-        loop {
-            // check current state.
-            {
-                let mut cur_state = state.0.lock();
-                while let State::Work(x) = *cur_state {
-                    // sleep until this thread is required.
-                    if id < x {
-                        break;
-                    }
-
-                    debug!(target: "verification", "verifier {id} sleeping");
-                    state.1.wait(&mut cur_state);
-                    debug!(target: "verification", "verifier {id} waking up");
-                }
-
-                if let State::Exit = *cur_state {
-                    debug!(target: "verification", "verifier {id} exiting");
-                    break;
-                }
-            }
-
-            // wait for work if empty.
-            {
-                let mut unverified = verification.unverified.lock();
-
-                if unverified.is_empty() && verification.verifying.lock().is_empty() {
-                    empty.notify_all();
-                }
-
-                while unverified.is_empty() {
-                    if let State::Exit = *state.0.lock() {
-                        debug!(target: "verification", "verifier {id} exiting");
-                        return;
-                    }
-
-                    wait.wait(unverified.inner_mut());
-                }
-
-                if let State::Exit = *state.0.lock() {
-                    debug!(target: "verification", "verifier {id} exiting");
-                    return;
-                }
-            }
-
-            // do work on this item.
-            let item = {
-                // acquire these locks before getting the item to verify.
-                let mut unverified = verification.unverified.lock();
-                let mut verifying = verification.verifying.lock();
-
-                let item = match unverified.pop_front() {
-                    Some(item) => item,
-                    None => continue,
-                };
-
-                verification
-                    .sizes
-                    .unverified
-                    .fetch_sub(item.malloc_size_of(), AtomicOrdering::SeqCst);
-                verifying.push_back(Verifying {
-                    hash: item.hash(),
-                    output: None,
-                });
-                item
-            };
-
-            let hash = item.hash();
-            // t_nb 5.0 verify standalone block (this verification is done in VerificationQueue thread pool)
-            let is_ready = match K::verify(item, &*engine, verification.check_seal) {
-                Ok(verified) => {
-                    let mut verifying = verification.verifying.lock();
-                    let mut idx = None;
-                    // find item again and remove it from verified queue
-                    for (i, e) in verifying.iter_mut().enumerate() {
-                        if e.hash == hash {
-                            idx = Some(i);
-
-                            verification
-                                .sizes
-                                .verifying
-                                .fetch_add(verified.malloc_size_of(), AtomicOrdering::SeqCst);
-                            e.output = Some(verified);
-                            break;
-                        }
-                    }
-
-                    if idx == Some(0) {
-                        // we're next!
-                        let mut verified = verification.verified.lock();
-                        let mut bad = verification.bad.lock();
-                        VerificationQueue::drain_verifying(
-                            &mut verifying,
-                            &mut verified,
-                            &mut bad,
-                            &verification.sizes,
-                        );
-                        true
-                    } else {
-                        false
-                    }
-                }
-                Err(_) => {
-                    let mut verifying = verification.verifying.lock();
-                    let mut verified = verification.verified.lock();
-                    let mut bad = verification.bad.lock();
-
-                    bad.insert(hash);
-                    verifying.retain(|e| e.hash != hash);
-
-                    if verifying.front().is_some_and(|x| x.output.is_some()) {
-                        VerificationQueue::drain_verifying(
-                            &mut verifying,
-                            &mut verified,
-                            &mut bad,
-                            &verification.sizes,
-                        );
-                        true
-                    } else {
-                        false
-                    }
-                }
-            };
-            if is_ready {
-                // Import the block immediately
-                ready.set_sync();
-            }
+            bad_hash_oracle: RwLock::new(None),
+            strict_ordering: config.strict_ordering,
+            max_hold: config.max_hold,
         }
     }
 
@@ -479,14 +537,17 @@ impl<K: Kind> VerificationQueue<K> {
         let mut unverified = self.verification.unverified.lock();
         let mut verifying = self.verification.verifying.lock();
         let mut verified = self.verification.verified.lock();
+        let mut held = self.verification.held.lock();
         unverified.clear();
         verifying.clear();
         verified.clear();
+        held.clear();
 
         let sizes = &self.verification.sizes;
         sizes.unverified.store(0, AtomicOrdering::SeqCst);
         sizes.verifying.store(0, AtomicOrdering::SeqCst);
         sizes.verified.store(0, AtomicOrdering::SeqCst);
+        sizes.held.store(0, AtomicOrdering::SeqCst);
         *self.total_difficulty.write() = 0.into();
 
         self.processing.write().clear();
@@ -511,9 +572,24 @@ impl<K: Kind> VerificationQueue<K> {
         Status::Unknown
     }
 
+    /// Check if the item is currently being verified or waiting for import.
+    /// Cheaper than `status` as the bad set is not consulted.
+    pub fn is_queued(&self, hash: &H256) -> bool {
+        self.processing.read().contains_key(hash)
+    }
+
     /// Add a block to the queue.
     // t_nb 3.0 import block to verification queue
     pub fn import(&self, input: K::Input) -> Result<H256, (Option<K::Input>, Error)> {
+        self.enqueue(input, false)
+    }
+
+    /// Add a block to the queue, ahead of the blocks waiting for verification.
+    pub fn import_priority(&self, input: K::Input) -> Result<H256, (Option<K::Input>, Error)> {
+        self.enqueue(input, true)
+    }
+
+    fn enqueue(&self, input: K::Input, priority: bool) -> Result<H256, (Option<K::Input>, Error)> {
         let hash = input.hash();
         let raw_hash = input.raw_hash();
         // t_nb 3.1 check if block is currently processing or marked as bad.
@@ -541,6 +617,16 @@ impl<K: Kind> VerificationQueue<K> {
                     ErrorKind::Import(ImportErrorKind::KnownBad).into()
                 ));
             }
+            // t_nb 3.1.3 is rejected by the bad hash oracle
+            if let Some(ref oracle) = *self.bad_hash_oracle.read() {
+                if oracle(&hash) {
+                    bad.insert(hash);
+                    bail!((
+                        Some(input),
+                        ErrorKind::Import(ImportErrorKind::KnownBad).into()
+                    ));
+                }
+            }
         }
 
         match K::create(input, &*self.engine, self.verification.check_seal) {
@@ -566,8 +652,14 @@ impl<K: Kind> VerificationQueue<K> {
                     let mut td = self.total_difficulty.write();
                     *td += item.difficulty();
                 }
-                self.verification.unverified.lock().push_back(item);
-                self.more_to_verify.notify_all();
+                {
+                    let mut unverified = self.verification.unverified.lock();
+                    match priority {
+                        true => unverified.push_front(item),
+                        false => unverified.push_back(item),
+                    }
+                }
+                self.verifiers.notify();
                 Ok(hash)
             }
             Err((input, err)) => {
@@ -601,6 +693,7 @@ impl<K: Kind> VerificationQueue<K> {
         }
         let mut verified_lock = self.verification.verified.lock();
         let verified = &mut *verified_lock;
+        let mut held = self.verification.held.lock();
         let mut bad = self.verification.bad.lock();
         let mut processing = self.processing.write();
         bad.reserve(hashes.len());
@@ -632,6 +725,134 @@ impl<K: Kind> VerificationQueue<K> {
             .verified
             .fetch_sub(removed_size, AtomicOrdering::SeqCst);
         *verified = new_verified;
+
+        let mut removed_size = 0;
+        held.evict(|item| {
+            let is_bad = bad.contains(&item.parent_hash());
+            if is_bad {
+                removed_size += item.malloc_size_of();
+                bad.insert(item.hash());
+                if let Some((difficulty, _)) = processing.remove(&item.hash()) {
+                    let mut td = self.total_difficulty.write();
+                    *td -= difficulty;
+                }
+            }
+            is_bad
+        });
+        self.verification
+            .sizes
+            .held
+            .fetch_sub(removed_size, AtomicOrdering::SeqCst);
+    }
+
+    /// Set a predicate consulted in addition to the configured bad hashes.
+    /// Items it rejects are refused on import and evicted by `recheck_queued`.
+    ///
+    /// The oracle is called with the queue locks held, so it must be cheap,
+    /// e.g. a lookup in an in-memory set kept up to date by the caller.
+    pub fn set_bad_hash_oracle(&self, oracle: BadHashOracle) {
+        *self.bad_hash_oracle.write() = Some(oracle);
+    }
+
+    /// Mark given items as bad and evict them and their descendants from the queue.
+    /// Returns the hashes of the evicted items.
+    pub fn mark_bad(&self, hashes: &[H256]) -> Vec<H256> {
+        self.verification.bad.lock().extend(hashes.iter().cloned());
+        self.recheck_queued()
+    }
+
+    /// Evict queued items which are marked as bad, rejected by the bad hash oracle
+    /// or descend from such an item. Evicted items are marked as bad as well, so their
+    /// descendants are refused on import.
+    /// Returns the hashes of the evicted items in queue order.
+    pub fn recheck_queued(&self) -> Vec<H256> {
+        let oracle = self.bad_hash_oracle.read().clone();
+        let sizes = &self.verification.sizes;
+        let mut unverified = self.verification.unverified.lock();
+        let mut verifying = self.verification.verifying.lock();
+        let mut verified = self.verification.verified.lock();
+        let mut held = self.verification.held.lock();
+        let mut bad = self.verification.bad.lock();
+        let mut evicted = Vec::new();
+
+        {
+            let mut processing = self.processing.write();
+            let mut td = self.total_difficulty.write();
+            // items are visited oldest first, so parents are checked before their children.
+            let mut evict = |hash: H256| {
+                let is_bad = bad.contains(&hash)
+                    || processing
+                        .get(&hash)
+                        .is_some_and(|(_, parent)| bad.contains(parent))
+                    || oracle.as_ref().is_some_and(|oracle| oracle(&hash));
+                if is_bad {
+                    bad.insert(hash);
+                    if let Some((difficulty, _)) = processing.remove(&hash) {
+                        *td -= difficulty;
+                    }
+                    evicted.push(hash);
+                }
+                is_bad
+            };
+
+            let mut removed_size = 0;
+            verified.retain(|item| {
+                let keep = !evict(item.hash());
+                if !keep {
+                    removed_size += item.malloc_size_of();
+                }
+                keep
+            });
+            sizes
+                .verified
+                .fetch_sub(removed_size, AtomicOrdering::SeqCst);
+
+            // items still being verified are dropped by the verifiers once done.
+            let mut removed_size = 0;
+            verifying.retain(|item| {
+                let keep = !evict(item.hash);
+                if !keep {
+                    removed_size += item.output.as_ref().map_or(0, |o| o.malloc_size_of());
+                }
+                keep
+            });
+            sizes
+                .verifying
+                .fetch_sub(removed_size, AtomicOrdering::SeqCst);
+
+            let mut removed_size = 0;
+            unverified.retain(|item| {
+                let keep = !evict(item.hash());
+                if !keep {
+                    removed_size += item.malloc_size_of();
+                }
+                keep
+            });
+            sizes
+                .unverified
+                .fetch_sub(removed_size, AtomicOrdering::SeqCst);
+
+            // held items wait for parents queued after them, so they are visited last.
+            let mut removed_size = 0;
+            held.evict(|item| {
+                let is_bad = evict(item.hash());
+                if is_bad {
+                    removed_size += item.malloc_size_of();
+                }
+                is_bad
+            });
+            sizes.held.fetch_sub(removed_size, AtomicOrdering::SeqCst);
+        }
+
+        if verifying.front().is_some_and(|x| x.output.is_some()) {
+            Self::drain_verifying(&mut verifying, &mut verified, &mut bad, sizes);
+            self.ready_signal.set_async();
+        }
+        if unverified.is_empty() && verifying.is_empty() {
+            self.empty.notify_all();
+        }
+
+        evicted
     }
 
     /// Mark given item as processed.
@@ -650,21 +871,87 @@ impl<K: Kind> VerificationQueue<K> {
         processing.is_empty()
     }
 
-    /// Removes up to `max` verified items from the queue
+    /// Removes up to `max` verified items from the queue.
+    /// With strict ordering, no parent is considered to be in the chain, see `drain_ordered`.
     pub fn drain(&self, max: usize) -> Vec<K::Verified> {
+        self.drain_ordered(max, |_| false)
+    }
+
+    /// Removes up to `max` verified items from the queue.
+    /// With strict ordering, an item is only removed once `in_chain` holds for its parent or
+    /// the parent is removed before it. Until then it is held back, for `Config::max_hold` at most.
+    pub fn drain_ordered<F: Fn(&H256) -> bool>(&self, max: usize, in_chain: F) -> Vec<K::Verified> {
+        let sizes = &self.verification.sizes;
         let mut verified = self.verification.verified.lock();
-        let count = cmp::min(max, verified.len());
-        let result = verified.drain(..count).collect::<Vec<_>>();
+        if !self.strict_ordering {
+            let count = cmp::min(max, verified.len());
+            let result = verified.drain(..count).collect::<Vec<_>>();
+
+            let drained_size = result
+                .iter()
+                .map(MallocSizeOfExt::malloc_size_of)
+                .sum::<usize>();
+            sizes
+                .verified
+                .fetch_sub(drained_size, AtomicOrdering::SeqCst);
 
-        let drained_size = result
+            return result;
+        }
+
+        let mut held = self.verification.held.lock();
+        let now = Instant::now();
+        let mut verified_size = 0;
+        let mut held_size = 0;
+        // items whose parent arrived and the ones waiting too long go first
+        let mut ready = VecDeque::from(held.release(|parent, since| {
+            in_chain(parent) || now.saturating_duration_since(since) >= self.max_hold
+        }));
+        held_size -= ready
             .iter()
-            .map(MallocSizeOfExt::malloc_size_of)
-            .sum::<usize>();
-        self.verification
-            .sizes
-            .verified
-            .fetch_sub(drained_size, AtomicOrdering::SeqCst);
+            .map(|i| i.malloc_size_of() as isize)
+            .sum::<isize>();
+
+        let mut drained = HashSet::new();
+        let mut result = Vec::new();
+        while result.len() < max {
+            let item = match ready.pop_front() {
+                Some(item) => item,
+                None => match verified.pop_front() {
+                    Some(item) => {
+                        let size = item.malloc_size_of() as isize;
+                        verified_size -= size;
+                        let parent = item.parent_hash();
+                        if !drained.contains(&parent)
+                            && !in_chain(&parent)
+                            && held.len() < MAX_HELD_ITEMS
+                        {
+                            held_size += size;
+                            held.hold(now, item);
+                            continue;
+                        }
+                        item
+                    }
+                    None => break,
+                },
+            };
+            let hash = item.hash();
+            let children = held.release_children(&hash);
+            held_size -= children
+                .iter()
+                .map(|i| i.malloc_size_of() as isize)
+                .sum::<isize>();
+            ready.extend(children);
+            drained.insert(hash);
+            result.push(item);
+        }
+        // released beyond `max`, their parents are drained now
+        while let Some(item) = ready.pop_back() {
+            verified_size += item.malloc_size_of() as isize;
+            verified.push_front(item);
+        }
 
+        add_size(&sizes.verified, verified_size);
+        add_size(&sizes.held, held_size);
         result
     }
 
@@ -687,7 +974,10 @@ impl<K: Kind> VerificationQueue<K> {
     pub fn is_empty(&self) -> bool {
         let v = &self.verification;
 
-        v.unverified.load_len() == 0 && v.verifying.load_len() == 0 && v.verified.load_len() == 0
+        v.unverified.load_len() == 0
+            && v.verifying.load_len() == 0
+            && v.verified.load_len() == 0
+            && v.held.load_len() == 0
     }
 
     /// Returns true if there are descendants of the current best block in the processing queue
@@ -740,14 +1030,20 @@ impl<K: Kind> VerificationQueue<K> {
                 .load(AtomicOrdering::SeqCst);
             (len, size + len * size_of::<K::Verified>())
         };
+        // held items are verified, just not ready for import yet
+        let (held_len, held_bytes) = {
+            let len = self.verification.held.load_len();
+            let size = self.verification.sizes.held.load(AtomicOrdering::SeqCst);
+            (len, size + len * size_of::<K::Verified>())
+        };
 
         QueueInfo {
             unverified_queue_size: unverified_len,
             verifying_queue_size: verifying_len,
-            verified_queue_size: verified_len,
+            verified_queue_size: verified_len + held_len,
             max_queue_size: self.max_queue_size,
             max_mem_use: self.max_mem_use,
-            mem_used: unverified_bytes + verifying_bytes + verified_bytes,
+            mem_used: unverified_bytes + verifying_bytes + verified_bytes + held_bytes,
         }
     }
 
@@ -758,10 +1054,12 @@ impl<K: Kind> VerificationQueue<K> {
 
     /// Get the current number of working verifiers.
     pub fn num_verifiers(&self) -> usize {
-        match *self.state.0.lock() {
-            State::Work(x) => x,
-            State::Exit => panic!("state only set to exit on drop; queue live now; qed"),
-        }
+        self.verifiers.workers()
+    }
+
+    /// The pool verifying the items of this queue.
+    pub fn verifier_pool(&self) -> &VerifierPool {
+        self.verifiers.pool()
     }
 
     /// Optimise memory footprint of the heap fields, and adjust the number of threads
@@ -794,6 +1092,22 @@ impl<K: Kind> VerificationQueue<K> {
 
         self.processing.write().shrink_to_fit();
 
+        // held items are only released on drain, wake the import up once they are due
+        if self.strict_ordering {
+            let now = Instant::now();
+            let expired = self
+                .verification
+                .held
+                .lock()
+                .by_parent
+                .values()
+                .flatten()
+                .any(|(since, _)| now.saturating_duration_since(*since) >= self.max_hold);
+            if expired {
+                self.ready_signal.set_async();
+            }
+        }
+
         if !self.scale_verifiers {
             return;
         }
@@ -825,18 +1139,25 @@ impl<K: Kind> VerificationQueue<K> {
         });
     }
 
-    // wake up or sleep verifiers to get as close to the target as
-    // possible, never going over the amount of initially allocated threads
-    // or below 1.
+    // allow more or fewer threads of the pool to verify items of this queue
+    // at the same time to get as close to the target as possible, never going
+    // over the amount of threads in the pool or below 1.
     fn scale_verifiers(&self, target: usize) {
         let current = self.num_verifiers();
-        let target = cmp::min(self.verifier_handles.len(), target);
+        let target = cmp::min(self.max_verifiers, target);
         let target = cmp::max(1, target);
 
         debug!(target: "verification", "Scaling from {current} to {target} verifiers");
 
-        *self.state.0.lock() = State::Work(target);
-        self.state.1.notify_all();
+        self.verifiers.set_workers(target);
+    }
+}
+
+fn add_size(size: &AtomicUsize, delta: isize) {
+    if delta < 0 {
+        size.fetch_sub(delta.unsigned_abs(), AtomicOrdering::SeqCst);
+    } else {
+        size.fetch_add(delta as usize, AtomicOrdering::SeqCst);
     }
 }
 
@@ -846,23 +1167,9 @@ impl<K: Kind> Drop for VerificationQueue<K> {
         self.clear();
         self.deleting.store(true, AtomicOrdering::SeqCst);
 
-        // set exit state; should be done before `more_to_verify` notification.
-        *self.state.0.lock() = State::Exit;
-        self.state.1.notify_all();
-
-        // acquire this lock to force threads to reach the waiting point
-        // if they're in-between the exit check and the more_to_verify wait.
-        {
-            let _unverified = self.verification.unverified.lock();
-            self.more_to_verify.notify_all();
-        }
-
-        // wait for all verifier threads to join.
-        for thread in self.verifier_handles.drain(..) {
-            thread
-                .join()
-                .expect("Propagating verifier thread panic on shutdown");
-        }
+        // the queue is unregistered from the pool once `verifiers` is dropped, after
+        // the items currently being verified are finished. Dedicated verifier threads
+        // are joined along with it.
 
         trace!(target: "shutdown", "[VerificationQueue] Closed.");
     }
@@ -870,11 +1177,17 @@ impl<K: Kind> Drop for VerificationQueue<K> {
 
 #[cfg(test)]
 mod tests {
-    use super::{kind::blocks::Unverified, BlockQueue, Config, State};
+    use super::{kind::blocks::Unverified, BlockQueue, Config, Status, VerifierPool};
     use bytes::Bytes;
     use error::*;
+    use ethereum_types::H256;
     use io::*;
     use spec::Spec;
+    use std::{
+        sync::{atomic::Ordering, Arc},
+        thread,
+        time::{Duration, Instant},
+    };
     use test_helpers::{get_good_dummy_block, get_good_dummy_block_seq};
     use types::{view, views::BlockView, BlockNumber};
 
@@ -886,7 +1199,7 @@ mod tests {
 
         let mut config = Config::default();
         config.verifier_settings.scale_verifiers = auto_scale;
-        BlockQueue::new(config, engine, IoChannel::disconnected(), true)
+        BlockQueue::new(config, engine, IoChannel::disconnected(), true, None)
     }
 
     fn get_test_config(num_verifiers: usize, is_auto_scale: bool) -> Config {
@@ -905,7 +1218,13 @@ mod tests {
         // TODO better test
         let spec = Spec::new_test();
         let engine = spec.engine;
-        let _ = BlockQueue::new(Config::default(), engine, IoChannel::disconnected(), true);
+        let _ = BlockQueue::new(
+            Config::default(),
+            engine,
+            IoChannel::disconnected(),
+            true,
+            None,
+        );
     }
 
     #[test]
@@ -989,13 +1308,130 @@ mod tests {
         assert!(queue.queue_info().is_empty());
     }
 
+    #[test]
+    fn recheck_evicts_bad_items_and_descendants() {
+        let queue = get_test_queue(false);
+        let blocks = get_good_dummy_block_seq(2);
+        let hashes: Vec<_> = blocks
+            .iter()
+            .map(|b| view!(BlockView, b).header(BlockNumber::max_value()).hash())
+            .collect();
+        for block in blocks {
+            queue
+                .import(new_unverified(block))
+                .expect("error importing block that is valid by definition");
+        }
+        queue.flush();
+
+        let bad_hash = hashes[1];
+        queue.set_bad_hash_oracle(Arc::new(move |hash: &H256| *hash == bad_hash));
+        assert_eq!(queue.recheck_queued(), vec![hashes[1], hashes[2]]);
+        assert!(queue.recheck_queued().is_empty());
+
+        match queue.status(&hashes[2]) {
+            Status::Bad => {}
+            _ => panic!("descendant of a bad block must be marked as bad"),
+        }
+        let drained = queue.drain(10);
+        assert_eq!(drained.len(), 1);
+        assert_eq!(drained[0].header.hash(), hashes[0]);
+    }
+
+    #[test]
+    fn mark_bad_evicts_unverified_items() {
+        let queue = get_test_queue(false);
+        queue.verifiers.set_workers(0);
+        let blocks = get_good_dummy_block_seq(2);
+        let hashes: Vec<_> = blocks
+            .iter()
+            .map(|b| view!(BlockView, b).header(BlockNumber::max_value()).hash())
+            .collect();
+        for block in blocks {
+            queue
+                .import(new_unverified(block))
+                .expect("error importing block that is valid by definition");
+        }
+
+        assert_eq!(queue.mark_bad(&[hashes[1]]), vec![hashes[1], hashes[2]]);
+        assert!(queue.is_queued(&hashes[0]));
+        assert!(!queue.is_queued(&hashes[1]));
+        assert_eq!(queue.queue_info().unverified_queue_size, 1);
+    }
+
+    fn get_strict_queue(max_hold: Duration) -> BlockQueue {
+        let spec = Spec::new_test();
+        let mut config = Config::default();
+        config.strict_ordering = true;
+        config.max_hold = max_hold;
+        BlockQueue::new(config, spec.engine, IoChannel::disconnected(), true, None)
+    }
+
+    #[test]
+    fn strict_ordering_holds_children_until_parent_drains() {
+        let spec = Spec::new_test();
+        let genesis = spec.genesis_header().hash();
+        let in_chain = |hash: &H256| *hash == genesis;
+        let queue = get_strict_queue(Duration::from_secs(3600));
+        let blocks = get_good_dummy_block_seq(1);
+        let hashes: Vec<_> = blocks
+            .iter()
+            .map(|b| view!(BlockView, b).header(BlockNumber::max_value()).hash())
+            .collect();
+
+        queue
+            .import(new_unverified(blocks[1].clone()))
+            .expect("error importing block that is valid by definition");
+        queue.flush();
+        let mem_used = queue.queue_info().mem_used;
+        assert!(queue.drain_ordered(10, in_chain).is_empty());
+
+        // the child is held, still accounted as queued
+        assert!(queue.is_queued(&hashes[1]));
+        assert!(!queue.is_empty());
+        assert_eq!(queue.queue_info().verified_queue_size, 1);
+        assert_eq!(queue.queue_info().mem_used, mem_used);
+
+        queue
+            .import(new_unverified(blocks[0].clone()))
+            .expect("error importing block that is valid by definition");
+        queue.flush();
+        let drained: Vec<_> = queue
+            .drain_ordered(10, in_chain)
+            .iter()
+            .map(|b| b.header.hash())
+            .collect();
+        assert_eq!(drained, hashes);
+        assert!(queue.is_empty());
+        assert_eq!(queue.verification.sizes.held.load(Ordering::SeqCst), 0);
+        assert_eq!(queue.verification.sizes.verified.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn strict_ordering_releases_orphans_after_max_hold() {
+        let queue = get_strict_queue(Duration::from_secs(0));
+        let blocks = get_good_dummy_block_seq(1);
+        let orphan = view!(BlockView, &blocks[1])
+            .header(BlockNumber::max_value())
+            .hash();
+        queue
+            .import(new_unverified(blocks[1].clone()))
+            .expect("error importing block that is valid by definition");
+        queue.flush();
+
+        assert!(queue.drain(10).is_empty());
+        let drained = queue.drain(10);
+        assert_eq!(drained.len(), 1);
+        assert_eq!(drained[0].header.hash(), orphan);
+        assert!(queue.is_empty());
+    }
+
     #[test]
     fn test_mem_limit() {
         let spec = Spec::new_test();
         let engine = spec.engine;
         let mut config = Config::default();
         config.max_mem_use = super::MIN_MEM_LIMIT; // empty queue uses about 15000
-        let queue = BlockQueue::new(config, engine, IoChannel::disconnected(), true);
+        let queue = BlockQueue::new(config, engine, IoChannel::disconnected(), true, None);
         assert!(!queue.queue_info().is_full());
         let mut blocks = get_good_dummy_block_seq(50);
         for b in blocks.drain(..) {
@@ -1023,7 +1459,7 @@ mod tests {
 
         // put all the verifiers to sleep to ensure
         // the test isn't timing sensitive.
-        *queue.state.0.lock() = State::Work(0);
+        queue.verifiers.set_workers(0);
 
         for block in get_good_dummy_block_seq(5000) {
             queue
@@ -1047,7 +1483,7 @@ mod tests {
         let spec = Spec::new_test();
         let engine = spec.engine;
         let config = get_test_config(1, false);
-        let queue = BlockQueue::new(config, engine, IoChannel::disconnected(), true);
+        let queue = BlockQueue::new(config, engine, IoChannel::disconnected(), true, None);
 
         assert_eq!(queue.num_verifiers(), 1);
     }
@@ -1057,7 +1493,7 @@ mod tests {
         let spec = Spec::new_test();
         let engine = spec.engine;
         let config = get_test_config(0, false);
-        let queue = BlockQueue::new(config, engine, IoChannel::disconnected(), true);
+        let queue = BlockQueue::new(config, engine, IoChannel::disconnected(), true, None);
 
         assert_eq!(queue.num_verifiers(), 1);
     }
@@ -1067,7 +1503,7 @@ mod tests {
         let spec = Spec::new_test();
         let engine = spec.engine;
         let config = get_test_config(10_000, false);
-        let queue = BlockQueue::new(config, engine, IoChannel::disconnected(), true);
+        let queue = BlockQueue::new(config, engine, IoChannel::disconnected(), true, None);
         let num_cpus = ::num_cpus::get();
 
         assert_eq!(queue.num_verifiers(), num_cpus);
@@ -1081,10 +1517,61 @@ mod tests {
             let spec = Spec::new_test();
             let engine = spec.engine;
             let config = get_test_config(num_cpus - 1, true);
-            let queue = BlockQueue::new(config, engine, IoChannel::disconnected(), true);
+            let queue = BlockQueue::new(config, engine, IoChannel::disconnected(), true, None);
             queue.scale_verifiers(num_cpus);
 
             assert_eq!(queue.num_verifiers(), num_cpus);
         }
     }
+
+    #[test]
+    fn shared_pool_serves_queues_fairly() {
+        let pool = VerifierPool::new(2);
+        let new_queue = || {
+            let spec = Spec::new_test();
+            let config = get_test_config(2, false);
+            BlockQueue::new(
+                config,
+                spec.engine,
+                IoChannel::disconnected(),
+                true,
+                Some(pool.clone()),
+            )
+        };
+        let heavy = new_queue();
+        let light = new_queue();
+
+        // both queues are served by the threads of the pool only.
+        assert_eq!(pool.num_threads(), 2);
+        assert_eq!(heavy.verifier_pool().num_threads(), 2);
+        assert_eq!(light.verifier_pool().num_threads(), 2);
+
+        // fill both queues while paused, then let the heavy queue occupy all threads first.
+        heavy.verifiers.set_workers(0);
+        light.verifiers.set_workers(0);
+        for block in get_good_dummy_block_seq(5000) {
+            heavy
+                .import(new_unverified(block))
+                .expect("Block good by definition; qed");
+        }
+        light
+            .import(new_unverified(get_good_dummy_block()))
+            .expect("Block good by definition; qed");
+        heavy.scale_verifiers(2);
+        light.scale_verifiers(2);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while light.queue_info().verified_queue_size == 0 {
+            assert!(
+                Instant::now() < deadline,
+                "light queue starved by heavy queue"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(heavy.queue_info().unverified_queue_size > 0);
+        assert!(heavy.num_verifiers() <= pool.num_threads());
+
+        heavy.flush();
+        assert_eq!(heavy.queue_info().verified_queue_size, 5000);
+    }
 }
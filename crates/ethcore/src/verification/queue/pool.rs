@@ -0,0 +1,342 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Verifier threads, optionally shared between several verification queues.
+//!
+//! Every queue registers itself with a pool together with the maximum number of threads
+//! allowed to verify its items at the same time. Idle threads serve the registered queues
+//! round-robin, one item at a time, so a queue with a deep backlog cannot starve the others.
+
+use parking_lot::{Condvar, Mutex};
+use stats::profiler;
+use std::{
+    cmp,
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+/// Verification work of a single queue.
+pub trait Task: Send + Sync {
+    /// Whether there are items waiting to be verified.
+    fn has_work(&self) -> bool;
+    /// Verify a single item, if there is one.
+    fn verify_one(&self);
+}
+
+// a queue registered with the pool.
+struct Queue {
+    id: usize,
+    task: Arc<dyn Task>,
+    // maximum number of threads verifying items of this queue at the same time.
+    workers: usize,
+    // number of threads currently verifying items of this queue.
+    active: usize,
+}
+
+struct State {
+    queues: Vec<Queue>,
+    // index of the queue to be served next.
+    next: usize,
+    next_id: usize,
+    exit: bool,
+}
+
+impl State {
+    // pick the next queue with pending work and a free worker slot.
+    fn schedule(&mut self) -> Option<(usize, Arc<dyn Task>)> {
+        let len = self.queues.len();
+        for offset in 0..len {
+            let idx = (self.next + offset) % len;
+            let queue = &mut self.queues[idx];
+            if queue.active < queue.workers && queue.task.has_work() {
+                queue.active += 1;
+                self.next = (idx + 1) % len;
+                return Some((queue.id, queue.task.clone()));
+            }
+        }
+        None
+    }
+
+    fn position(&self, id: usize) -> Option<usize> {
+        self.queues.iter().position(|q| q.id == id)
+    }
+
+    fn queue_mut(&mut self, id: usize) -> Option<&mut Queue> {
+        self.queues.iter_mut().find(|q| q.id == id)
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // signalled when there may be new work or the pool is shutting down.
+    more_to_verify: Condvar,
+    // signalled whenever a thread finishes an item.
+    idle: Condvar,
+}
+
+impl Shared {
+    fn notify(&self, all: bool) {
+        // take the lock so the notification can't slip in between a thread's
+        // scheduling attempt and its wait.
+        let _state = self.state.lock();
+        if all {
+            self.more_to_verify.notify_all();
+        } else {
+            self.more_to_verify.notify_one();
+        }
+    }
+}
+
+struct Inner {
+    shared: Arc<Shared>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.shared.state.lock().exit = true;
+        self.shared.notify(true);
+
+        for thread in self.handles.drain(..) {
+            thread
+                .join()
+                .expect("Propagating verifier thread panic on shutdown");
+        }
+    }
+}
+
+/// Handle to a pool of verifier threads, which can be passed to several verification queues.
+/// Cheap to clone; the threads exit once the last handle and all queues using it are dropped.
+#[derive(Clone)]
+pub struct VerifierPool {
+    inner: Arc<Inner>,
+}
+
+impl VerifierPool {
+    /// Spawn a pool of `threads` verifier threads, at least one.
+    pub fn new(threads: usize) -> Self {
+        let threads = cmp::max(1, threads);
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                queues: Vec::new(),
+                next: 0,
+                next_id: 0,
+                exit: false,
+            }),
+            more_to_verify: Condvar::new(),
+            idle: Condvar::new(),
+        });
+
+        let handles = (0..threads)
+            .map(|i| {
+                debug!(target: "verification", "Adding verification thread #{i}");
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("Verifier #{i}"))
+                    .spawn(move || VerifierPool::run(shared, i))
+                    .expect("Failed to create verifier thread.")
+            })
+            .collect();
+
+        VerifierPool {
+            inner: Arc::new(Inner { shared, handles }),
+        }
+    }
+
+    /// Number of threads in the pool.
+    pub fn num_threads(&self) -> usize {
+        self.inner.handles.len()
+    }
+
+    /// Register the work of a queue, to be verified by at most `workers` threads at once.
+    pub(super) fn register(&self, task: Arc<dyn Task>, workers: usize) -> Registration {
+        let id = {
+            let mut state = self.inner.shared.state.lock();
+            let id = state.next_id;
+            state.next_id += 1;
+            state.queues.push(Queue {
+                id,
+                task,
+                workers,
+                active: 0,
+            });
+            id
+        };
+        self.inner.shared.notify(true);
+
+        Registration {
+            pool: self.clone(),
+            id,
+        }
+    }
+
+    fn run(shared: Arc<Shared>, id: usize) {
+        profiler::register_current_thread("verifier");
+        loop {
+            let (queue, task) = {
+                let mut state = shared.state.lock();
+                loop {
+                    if state.exit {
+                        debug!(target: "verification", "verifier {id} exiting");
+                        return;
+                    }
+                    if let Some(next) = state.schedule() {
+                        break next;
+                    }
+
+                    debug!(target: "verification", "verifier {id} sleeping");
+                    shared.more_to_verify.wait(&mut state);
+                    debug!(target: "verification", "verifier {id} waking up");
+                }
+            };
+
+            task.verify_one();
+
+            let mut state = shared.state.lock();
+            if let Some(queue) = state.queue_mut(queue) {
+                queue.active -= 1;
+            }
+            // a worker slot was freed, another thread may be able to take it.
+            shared.more_to_verify.notify_one();
+            shared.idle.notify_all();
+        }
+    }
+}
+
+/// Registration of a queue with a `VerifierPool`. The queue is unregistered on drop,
+/// after all items currently being verified are finished.
+pub(super) struct Registration {
+    pool: VerifierPool,
+    id: usize,
+}
+
+impl Registration {
+    /// Maximum number of threads verifying items of the queue at the same time.
+    pub fn workers(&self) -> usize {
+        let mut state = self.pool.inner.shared.state.lock();
+        state.queue_mut(self.id).map_or(0, |q| q.workers)
+    }
+
+    /// Change the maximum number of threads verifying items of the queue at the same time.
+    pub fn set_workers(&self, workers: usize) {
+        if let Some(queue) = self.pool.inner.shared.state.lock().queue_mut(self.id) {
+            queue.workers = workers;
+        }
+        self.pool.inner.shared.notify(true);
+    }
+
+    /// Signal that new items are waiting to be verified.
+    pub fn notify(&self) {
+        self.pool.inner.shared.notify(false);
+    }
+
+    /// The pool the queue is registered with.
+    pub fn pool(&self) -> &VerifierPool {
+        &self.pool
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let shared = &self.pool.inner.shared;
+        let mut state = shared.state.lock();
+        if let Some(queue) = state.queue_mut(self.id) {
+            queue.workers = 0;
+        }
+        while state.queue_mut(self.id).is_some_and(|q| q.active > 0) {
+            shared.idle.wait(&mut state);
+        }
+        if let Some(idx) = state.position(self.id) {
+            state.queues.remove(idx);
+            if state.next > idx {
+                state.next -= 1;
+            }
+            if state.next >= state.queues.len() {
+                state.next = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::{Duration, Instant},
+    };
+
+    #[derive(Default)]
+    struct Counter {
+        pending: AtomicUsize,
+        done: AtomicUsize,
+    }
+
+    impl Task for Counter {
+        fn has_work(&self) -> bool {
+            self.pending.load(Ordering::SeqCst) > 0
+        }
+
+        fn verify_one(&self) {
+            let pending = self.pending.load(Ordering::SeqCst);
+            if pending > 0
+                && self
+                    .pending
+                    .compare_exchange(pending, pending - 1, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+            {
+                thread::sleep(Duration::from_millis(1));
+                self.done.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[test]
+    fn round_robin_between_queues() {
+        let pool = VerifierPool::new(1);
+        let heavy = Arc::new(Counter::default());
+        let light = Arc::new(Counter::default());
+        let heavy_registration = pool.register(heavy.clone(), 1);
+        let light_registration = pool.register(light.clone(), 1);
+
+        heavy.pending.store(1000, Ordering::SeqCst);
+        heavy_registration.notify();
+        light.pending.store(3, Ordering::SeqCst);
+        light_registration.notify();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while light.done.load(Ordering::SeqCst) < 3 {
+            assert!(Instant::now() < deadline, "light queue starved");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(heavy.done.load(Ordering::SeqCst) < 100);
+    }
+
+    #[test]
+    fn unregister_waits_for_active_items() {
+        let pool = VerifierPool::new(2);
+        let task = Arc::new(Counter::default());
+        let registration = pool.register(task.clone(), 2);
+        task.pending.store(10, Ordering::SeqCst);
+        registration.notify();
+
+        drop(registration);
+        let done = task.done.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(task.done.load(Ordering::SeqCst), done);
+        assert!(pool.inner.shared.state.lock().queues.is_empty());
+    }
+}
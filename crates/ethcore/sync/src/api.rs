@@ -16,32 +16,42 @@
 
 use bytes::Bytes;
 use crypto::publickey::Secret;
-use devp2p::NetworkService;
+use devp2p::{CaptureHandle, NetworkService, Reachability};
 use network::{
     client_version::ClientVersion, ConnectionFilter, Error, ErrorKind,
     NetworkConfiguration as BasicNetworkConfiguration, NetworkContext, NetworkProtocolHandler,
-    NonReservedPeerMode, PeerId, ProtocolId,
+    NodeId, NonReservedPeerMode, PeerId, ProtocolId,
 };
 use std::{
-    collections::{BTreeMap, BTreeSet, HashMap},
+    cmp,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     io,
     ops::RangeInclusive,
+    path::{Path, PathBuf},
     sync::{atomic, mpsc, Arc},
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
+use block_sync::DownloaderDiagnostics;
 use chain::{
-    fork_filter::ForkFilterApi, ChainSyncApi, SyncState, SyncStatus as EthSyncStatus,
-    ETH_PROTOCOL_VERSION_63, ETH_PROTOCOL_VERSION_64, ETH_PROTOCOL_VERSION_65,
-    ETH_PROTOCOL_VERSION_66, PAR_PROTOCOL_VERSION_1, PAR_PROTOCOL_VERSION_2,
+    fork_filter::{self, ForkFilterApi, ForkId},
+    BlockSet, ChainSyncApi, ForkConfirmation, PacketDispatchStats, PeerFeeMarket,
+    SealedBlockRejectedHandler, SyncEvent, SyncHealthReport, SyncState,
+    SyncStatus as EthSyncStatus, ETH_PROTOCOL_VERSION_63, ETH_PROTOCOL_VERSION_64,
+    ETH_PROTOCOL_VERSION_65, ETH_PROTOCOL_VERSION_66, MAX_PEERS_PROPAGATION,
+    MAX_PEER_LAG_PROPAGATION, MAX_TRANSACTIONS_TO_REQUEST, MAX_TRANSACTION_PACKET_SIZE,
+    MIN_PEERS_PROPAGATION, PAR_PROTOCOL_VERSION_1, PAR_PROTOCOL_VERSION_2, PAR_PROTOCOL_VERSION_3,
+    PAR_PROTOCOL_VERSION_4, TRANSACTION_PACKET_SIZE_BOUNDS,
 };
 use ethcore::{
     client::{BlockChainClient, ChainMessageType, ChainNotify, NewBlocks},
     snapshot::SnapshotService,
 };
-use ethereum_types::{H256, H512, U256, U64};
+use ethereum_types::{Address, H256, H512, U256, U64};
+use head_hints::{HeadHints, HEAD_HINTS_FILE};
 use io::TimerToken;
 use network::IpFilter;
+use parity_runtime::Executor;
 use parking_lot::{Mutex, RwLock};
 use stats::{PrometheusMetrics, PrometheusRegistry};
 
@@ -95,6 +105,27 @@ impl WarpSync {
     }
 }
 
+/// Selection of the peers block bodies and receipts are requested from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerSelection {
+    /// Request from whichever peer is idle first.
+    Random,
+    /// Prefer the peer with the highest `keccak(node_id || block_hash)` among the idle peers,
+    /// so that the same blocks are requested from the same serving peers.
+    Rendezvous,
+}
+
+/// Peers proposed blocks and consensus packets are sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsensusPropagation {
+    /// All peers speaking `PAR` v2 or higher.
+    All,
+    /// Only the peers registered with `EthSync::set_validator_nodes`, and the peers of the
+    /// validators notified with `ChainNotify::validators_changed` whose node id is given in
+    /// `Params::validator_nodes`. Nothing is sent while none are known.
+    Validators,
+}
+
 /// Sync configuration
 #[derive(Debug, Clone, Copy)]
 pub struct SyncConfig {
@@ -114,6 +145,80 @@ pub struct SyncConfig {
     pub eip1559_transition: BlockNumber,
     /// Number of blocks for which new transactions will be returned in a result of `parity_newTransactionsStats` RPC call
     pub new_transactions_stats_period: u64,
+    /// Time for which new transactions will be returned in a result of `parity_newTransactionsStats` RPC call.
+    /// Overrides `new_transactions_stats_period` when set.
+    pub new_transactions_stats_retention: Option<Duration>,
+    /// Advertise and serve the local snapshot to warp-syncing peers
+    pub serve_snapshots: bool,
+    /// Only serve headers, bodies and receipts of blocks at or above this number
+    pub serve_from_block: Option<BlockNumber>,
+    /// Max number of locally submitted transactions broadcast to all peers until mined
+    pub max_local_transactions: usize,
+    /// Interval after which a locally submitted transaction not yet mined is broadcast again
+    pub local_transactions_retry_interval: Duration,
+    /// Trusted checkpoint to download headers backwards from once the chain head is reached.
+    /// The headers are stored without bodies and receipts.
+    pub header_checkpoint: Option<(BlockNumber, H256)>,
+    /// Selection of the peers block bodies and receipts are requested from.
+    pub peer_selection: PeerSelection,
+    /// Upper bound of the difficulty of a single block. Peers advertising a total difficulty
+    /// above what such blocks could accumulate are not trusted until they back it with a header.
+    pub max_block_difficulty: Option<U256>,
+    /// Lowest `eth` protocol version of the peers transactions are relayed to and fetched from.
+    /// Peers below it are only used for block sync.
+    pub tx_relay_min_protocol: u8,
+    /// Size of the downloaded block headers, bodies and receipts held before the import,
+    /// above which no further blocks are requested.
+    pub max_download_memory: usize,
+    /// Number of subchain heads requests sent to a single peer at once when a sync round starts.
+    pub chain_head_pipeline: usize,
+    /// Number of subchain heads requests outstanding at once over all peers when a sync round starts.
+    pub chain_head_requests: usize,
+    /// Fixed number of blocks a new block or hash announced by a peer may be behind
+    /// the last imported block. Derived from the observed block interval if not set.
+    pub max_new_block_age: Option<BlockNumber>,
+    /// Fixed number of hashes taken from a `NewHashes` packet.
+    /// Derived from the observed block interval if not set.
+    pub max_new_hashes: Option<usize>,
+    /// Number of blocks peers need to be ahead of a best block which does not advance
+    /// to consider sync stalled.
+    pub stall_block_lag: BlockNumber,
+    /// Time without best block progress after which the next stall recovery action is taken.
+    pub stall_timeout: Duration,
+    /// Number of rejections of a block, counting its descendants, after which peers serving
+    /// its branch are probed for an alternative one, if most peers are on it.
+    pub branch_probe_threshold: usize,
+    /// Time after the last rejection of a block for which the rejections are remembered.
+    pub branch_probe_window: Duration,
+    /// Minimum time between two probes for an alternative branch.
+    pub branch_probe_interval: Duration,
+    /// Minimum time between two sync rounds triggered by peer and snapshot events. Events
+    /// within it are handled by a single round on the next tick. Zero disables the deferral.
+    pub continue_sync_interval: Duration,
+    /// Size of a packet with transactions above which no further transactions are added to it.
+    /// The packet is sent in a single RLPx frame limited to 16MB, hence the size must be within
+    /// `TRANSACTION_PACKET_SIZE_BOUNDS`. Peers which limit the frame size further drop larger
+    /// packets.
+    pub max_transaction_packet_size: usize,
+    /// Number of announced transactions requested from a peer at once.
+    pub max_transactions_to_request: usize,
+    /// Minimal number of peers blocks and transactions are propagated to, at most `max_peers`
+    /// of the network configuration or the default.
+    pub min_peers_propagation: usize,
+    /// Maximal number of peers blocks and transactions are propagated to.
+    pub max_peers_propagation: usize,
+    /// Number of blocks the best block may move since the last propagation for new blocks to
+    /// be propagated. Larger moves are taken for a sync and not propagated.
+    pub max_peer_lag_propagation: BlockNumber,
+    /// Number of blocks below the best block a branch may diverge from the local chain to be
+    /// synced. Deeper branches are not synced until allowed with `EthSync::set_allow_deep_reorg`.
+    /// Zero removes the limit.
+    pub max_reorg_depth: BlockNumber,
+    /// Follow the chain and serve requests without ever propagating transactions, blocks or
+    /// consensus packets. Sealing is refused with `MinerOptions::refuse_sealing` in this mode.
+    pub observer_mode: bool,
+    /// Peers proposed blocks and consensus packets are sent to.
+    pub consensus_propagation: ConsensusPropagation,
 }
 
 impl Default for SyncConfig {
@@ -127,7 +232,72 @@ impl Default for SyncConfig {
             warp_sync: WarpSync::Disabled,
             eip1559_transition: BlockNumber::max_value(),
             new_transactions_stats_period: 0,
+            new_transactions_stats_retention: None,
+            serve_snapshots: true,
+            serve_from_block: None,
+            max_local_transactions: 1024,
+            local_transactions_retry_interval: Duration::from_secs(60),
+            header_checkpoint: None,
+            peer_selection: PeerSelection::Random,
+            max_block_difficulty: None,
+            tx_relay_min_protocol: 0,
+            max_download_memory: 128 * 1024 * 1024,
+            chain_head_pipeline: 2,
+            chain_head_requests: 8,
+            max_new_block_age: None,
+            max_new_hashes: None,
+            stall_block_lag: 32,
+            stall_timeout: Duration::from_secs(5 * 60),
+            branch_probe_threshold: 3,
+            branch_probe_window: Duration::from_secs(10 * 60),
+            branch_probe_interval: Duration::from_secs(10),
+            continue_sync_interval: Duration::from_millis(75),
+            max_transaction_packet_size: MAX_TRANSACTION_PACKET_SIZE,
+            max_transactions_to_request: MAX_TRANSACTIONS_TO_REQUEST,
+            min_peers_propagation: MIN_PEERS_PROPAGATION,
+            max_peers_propagation: MAX_PEERS_PROPAGATION,
+            max_peer_lag_propagation: MAX_PEER_LAG_PROPAGATION,
+            max_reorg_depth: 10_000,
+            observer_mode: false,
+            consensus_propagation: ConsensusPropagation::All,
+        }
+    }
+}
+
+impl SyncConfig {
+    /// Check the propagation limits. `max_peers` is the peer limit of the network configuration.
+    pub fn validate(&self, max_peers: u32) -> Result<(), String> {
+        if !TRANSACTION_PACKET_SIZE_BOUNDS.contains(&self.max_transaction_packet_size) {
+            return Err(format!(
+                "Transaction packet size of {} bytes is out of the bounds {}..={}",
+                self.max_transaction_packet_size,
+                TRANSACTION_PACKET_SIZE_BOUNDS.start(),
+                TRANSACTION_PACKET_SIZE_BOUNDS.end(),
+            ));
+        }
+        if self.max_transactions_to_request == 0 {
+            return Err("Number of transactions to request must be positive".into());
+        }
+        if self.min_peers_propagation == 0 {
+            return Err("Minimal number of propagation peers must be positive".into());
+        }
+        if self.min_peers_propagation > self.max_peers_propagation {
+            return Err(format!(
+                "Minimal number of propagation peers {} is above the maximal number {}",
+                self.min_peers_propagation, self.max_peers_propagation
+            ));
+        }
+        // a few peers more than the limit do no harm, the default applies to small limits too
+        if self.min_peers_propagation > cmp::max(max_peers as usize, MIN_PEERS_PROPAGATION) {
+            return Err(format!(
+                "Minimal number of propagation peers {} is above the peer limit {}",
+                self.min_peers_propagation, max_peers
+            ));
         }
+        if self.max_peer_lag_propagation == 0 {
+            return Err("Propagation lag must be positive".into());
+        }
+        Ok(())
     }
 }
 
@@ -142,11 +312,16 @@ pub trait SyncProvider: Send + Sync + PrometheusMetrics {
     /// Get the enode if available.
     fn enode(&self) -> Option<String>;
 
+    /// Get the node record (EIP-778) in its text form if available.
+    fn node_record(&self) -> Option<String>;
+
     /// Returns propagation count for pending transactions.
-    fn pending_transactions_stats(&self) -> BTreeMap<H256, TransactionStats>;
+    /// With `verbose` set, the per-peer breakdown is included.
+    fn pending_transactions_stats(&self, verbose: bool) -> BTreeMap<H256, TransactionStats>;
 
     /// Returns propagation count for new transactions.
-    fn new_transactions_stats(&self) -> BTreeMap<H256, TransactionStats>;
+    /// With `verbose` set, the per-peer breakdown is included.
+    fn new_transactions_stats(&self, verbose: bool) -> BTreeMap<H256, TransactionStats>;
 }
 
 /// Transaction stats
@@ -156,6 +331,30 @@ pub struct TransactionStats {
     pub first_seen: u64,
     /// Peers it was propagated to.
     pub propagated_to: BTreeMap<H512, usize>,
+    /// Most recent exchanges of this TX with peers, oldest first. Only set in verbose mode.
+    pub peers: Option<Vec<TransactionPeerStats>>,
+}
+
+/// Direction of a transaction exchange with a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionDirection {
+    /// Transaction was sent to the peer.
+    Sent,
+    /// Transaction was received from the peer.
+    Received,
+}
+
+/// Single exchange of a transaction with a peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionPeerStats {
+    /// Session id of the peer.
+    pub peer_id: PeerId,
+    /// Public node id of the peer, if known.
+    pub node_id: Option<H512>,
+    /// Whether the transaction was sent or received.
+    pub direction: TransactionDirection,
+    /// When the exchange happened.
+    pub timestamp: SystemTime,
 }
 
 /// Peer connection information
@@ -184,6 +383,26 @@ pub struct EthProtocolInfo {
     pub head: H256,
     /// Peer total difficulty if known
     pub difficulty: Option<U256>,
+    /// Negotiated eth protocol version
+    pub eth_version: u8,
+    /// Negotiated par protocol version, 0 if not supported by the peer
+    pub par_version: u8,
+    /// Fork block confirmation status
+    pub confirmation: ForkConfirmation,
+    /// Network id advertised by the peer
+    pub network_id: u64,
+    /// Genesis hash advertised by the peer
+    pub genesis: H256,
+    /// Best snapshot block number if advertised
+    pub snapshot_number: Option<BlockNumber>,
+    /// Best snapshot hash if advertised
+    pub snapshot_hash: Option<H256>,
+    /// Blocks the peer is currently downloading from, if any
+    pub block_set: Option<BlockSet>,
+    /// Version of the software the peer is running
+    pub client_version: ClientVersion,
+    /// Gas limit and base fee of the highest validated header received from the peer
+    pub fee_market: Option<PeerFeeMarket>,
 }
 
 /// A prioritized tasks run in a specialised timer.
@@ -228,6 +447,10 @@ pub struct Params {
     pub snapshot_service: Arc<dyn SnapshotService>,
     /// Network layer configuration.
     pub network_config: NetworkConfiguration,
+    /// Executor the blocking work of sync is spawned on.
+    pub executor: Executor,
+    /// Node ids of validators by address, for `ConsensusPropagation::Validators`.
+    pub validator_nodes: HashMap<Address, NodeId>,
 }
 
 /// Ethereum network protocol handler
@@ -242,6 +465,12 @@ pub struct EthSync {
     priority_tasks: Mutex<mpsc::Sender<PriorityTask>>,
     /// New incoming transactions notification channel
     new_transaction_hashes: crossbeam_channel::Sender<H256>,
+    /// Prefix of the metric names, derived from the network instance label
+    metrics_prefix: String,
+    /// Running packet captures by node id of the peer
+    packet_captures: Mutex<HashMap<H512, CaptureHandle>>,
+    /// Fork id last published in the node record
+    published_fork_id: Mutex<Option<ForkId>>,
 }
 
 impl EthSync {
@@ -250,6 +479,7 @@ impl EthSync {
         params: Params,
         connection_filter: Option<Arc<dyn ConnectionFilter>>,
     ) -> Result<Arc<EthSync>, Error> {
+        params.config.validate(params.network_config.max_peers)?;
         let (priority_tasks_tx, priority_tasks_rx) = mpsc::channel();
         let (new_transaction_hashes_tx, new_transaction_hashes_rx) = crossbeam_channel::unbounded();
         let fork_filter = ForkFilterApi::new(&*params.chain, params.forks);
@@ -261,10 +491,29 @@ impl EthSync {
             priority_tasks_rx,
             new_transaction_hashes_rx,
         );
-        let service = NetworkService::new(
-            params.network_config.clone().into_basic()?,
-            connection_filter,
-        )?;
+        sync.write().set_executor(params.executor);
+        sync.write().set_validator_node_map(params.validator_nodes);
+        let metrics_prefix = match params.network_config.instance_label.as_str() {
+            "" => String::new(),
+            label => format!(
+                "{}_",
+                label.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+            ),
+        };
+        // the head hints are kept next to the node table
+        let head_hints_path = params
+            .network_config
+            .net_config_path
+            .as_ref()
+            .map(|dir| Path::new(dir).join(HEAD_HINTS_FILE));
+        if let Some(ref path) = head_hints_path {
+            let genesis = params.chain.chain_info().genesis_hash;
+            sync.write()
+                .set_head_hints(HeadHints::load(path, genesis, SystemTime::now()));
+        }
+        let mut network_config = params.network_config.clone().into_basic()?;
+        network_config.network_id = Some(params.config.network_id);
+        let service = NetworkService::new(network_config, connection_filter)?;
 
         let sync = Arc::new(EthSync {
             network: service,
@@ -273,15 +522,40 @@ impl EthSync {
                 chain: params.chain,
                 snapshot_service: params.snapshot_service,
                 overlay: RwLock::new(HashMap::new()),
+                head_hints_path,
+                continue_sync_interval: params.config.continue_sync_interval,
             }),
             subprotocol_name: params.config.subprotocol_name,
             priority_tasks: Mutex::new(priority_tasks_tx),
             new_transaction_hashes: new_transaction_hashes_tx,
+            metrics_prefix,
+            packet_captures: Mutex::new(HashMap::new()),
+            published_fork_id: Mutex::new(None),
         });
+        sync.publish_fork_id();
 
         Ok(sync)
     }
 
+    /// Announce the fork id of the best block in the `eth` entry of the node record,
+    /// if it changed since it was last published.
+    fn publish_fork_id(&self) {
+        let fork_id = self.eth_handler.sync.fork_id(&*self.eth_handler.chain);
+        let mut published = self.published_fork_id.lock();
+        if *published == Some(fork_id) {
+            return;
+        }
+        match self.network.set_node_record_entry(
+            fork_filter::NODE_RECORD_KEY,
+            fork_filter::node_record_entry(fork_id),
+        ) {
+            Ok(()) => *published = Some(fork_id),
+            Err(e) => {
+                warn!(target: "sync", "Failed to publish the fork id in the node record: {e}")
+            }
+        }
+    }
+
     /// Priority tasks producer
     pub fn priority_tasks(&self) -> mpsc::Sender<PriorityTask> {
         self.priority_tasks.lock().clone()
@@ -291,6 +565,65 @@ impl EthSync {
     pub fn new_transaction_hashes(&self) -> crossbeam_channel::Sender<H256> {
         self.new_transaction_hashes.clone()
     }
+
+    /// Enable or disable serving the local snapshot. Affects subsequent handshakes and requests.
+    pub fn set_serve_snapshots(&self, serve: bool) {
+        self.eth_handler.sync.write().set_serve_snapshots(serve);
+    }
+
+    /// Allow or forbid syncing branches which diverge deeper than the maximum reorg depth.
+    pub fn set_allow_deep_reorg(&self, allow: bool) {
+        self.eth_handler.sync.write().set_allow_deep_reorg(allow);
+    }
+
+    /// Subscribe to sync events. Events are dropped while the channel is full, use a bounded
+    /// channel sized for the expected consumer lag.
+    pub fn set_event_sender(&self, sender: Option<crossbeam_channel::Sender<SyncEvent>>) {
+        self.eth_handler.sync.write().set_event_sender(sender);
+    }
+
+    /// Set the handler notified when a block sealed by this node is rejected by the block queue.
+    /// See `ChainSync::set_sealed_block_rejected_handler`.
+    pub fn set_sealed_block_rejected_handler(&self, handler: Option<SealedBlockRejectedHandler>) {
+        self.eth_handler
+            .sync
+            .write()
+            .set_sealed_block_rejected_handler(handler);
+    }
+
+    /// Replace the node ids of the current validators, to be called on every validator set
+    /// change. Proposals and consensus packets are restricted to them with
+    /// `ConsensusPropagation::Validators`.
+    pub fn set_validator_nodes(&self, nodes: HashSet<NodeId>) {
+        self.eth_handler.sync.set_validator_nodes(nodes);
+    }
+
+    /// Scan the ancient blocks in `range` for bodies or receipts not matching the block headers
+    /// and download the mismatching blocks again. Returns the numbers of the mismatching blocks.
+    pub fn repair_ancient_blocks(&self, range: RangeInclusive<BlockNumber>) -> Vec<BlockNumber> {
+        self.eth_handler
+            .sync
+            .repair_ancient_blocks(&*self.eth_handler.chain, range)
+    }
+
+    /// Check the sync state for the usual reasons of a node not syncing. An empty report means
+    /// no problem was found.
+    pub fn health_check(&self) -> SyncHealthReport {
+        self.eth_handler.sync.health_check(
+            &*self.eth_handler.chain,
+            &*self.eth_handler.snapshot_service,
+        )
+    }
+
+    /// Snapshot of the new and, if present, old blocks downloader state.
+    pub fn downloader_diagnostics(&self) -> Vec<DownloaderDiagnostics> {
+        self.eth_handler.sync.downloader_diagnostics()
+    }
+
+    /// Stop downloading headers backwards from the configured checkpoint. Stored headers are kept.
+    pub fn abort_header_backfill(&self) {
+        self.eth_handler.sync.write().abort_header_backfill();
+    }
 }
 
 impl SyncProvider for EthSync {
@@ -334,21 +667,31 @@ impl SyncProvider for EthSync {
         self.network.external_url()
     }
 
-    fn pending_transactions_stats(&self) -> BTreeMap<H256, TransactionStats> {
-        self.eth_handler.sync.pending_transactions_stats()
+    fn node_record(&self) -> Option<String> {
+        self.network.node_record()
+    }
+
+    fn pending_transactions_stats(&self, verbose: bool) -> BTreeMap<H256, TransactionStats> {
+        self.eth_handler.sync.pending_transactions_stats(verbose)
     }
 
-    fn new_transactions_stats(&self) -> BTreeMap<H256, TransactionStats> {
-        self.eth_handler.sync.new_transactions_stats()
+    fn new_transactions_stats(&self, verbose: bool) -> BTreeMap<H256, TransactionStats> {
+        self.eth_handler.sync.new_transactions_stats(verbose)
     }
 }
 
 impl PrometheusMetrics for EthSync {
     fn prometheus_metrics(&self, r: &mut PrometheusRegistry) {
+        let _ = r.with_prefix(&self.metrics_prefix, |r| self.sync_metrics(r));
+    }
+}
+
+impl EthSync {
+    fn sync_metrics(&self, r: &mut PrometheusRegistry) {
         let scalar = |b| if b { 1i64 } else { 0i64 };
         let sync_status = self.status();
 
-        r.register_gauge(
+        let _ = r.register_gauge(
 			"sync_status",
 			"WaitingPeers(0), SnapshotManifest(1), SnapshotData(2), SnapshotWaiting(3), Blocks(4), Idle(5), Waiting(6), NewBlocks(7)", 
 			match self.eth_handler.sync.status().state {
@@ -363,54 +706,362 @@ impl PrometheusMetrics for EthSync {
         });
 
         for (key, value) in sync_status.item_sizes.iter() {
-            r.register_gauge(
+            let _ = r.register_gauge(
                 key,
                 format!("Total item number of {key}").as_str(),
                 *value as i64,
             );
         }
 
-        r.register_gauge(
+        let _ = r.register_gauge(
             "net_peers",
             "Total number of connected peers",
             sync_status.num_peers as i64,
         );
-        r.register_gauge(
+        let _ = r.register_gauge(
             "net_active_peers",
             "Total number of active peers",
             sync_status.num_active_peers as i64,
         );
-        r.register_counter(
+        let _ = r.register_gauge(
+            "sync_inactive_peers",
+            "Number of peers set aside until they have something to give us",
+            sync_status.num_inactive_peers as i64,
+        );
+        let _ = r.register_gauge(
+            "sync_disabled_peers",
+            "Number of peers disabled for misbehaving, until they are disconnected",
+            sync_status.num_disabled_peers as i64,
+        );
+        let distribution = self.network.peer_distribution();
+        let networks: Vec<_> = distribution
+            .networks
+            .iter()
+            .map(|(network, count)| (network.as_str(), *count as i64))
+            .collect();
+        let _ = r.register_labeled_gauge(
+            "net_peers_by_network",
+            "Number of connected peers by IP prefix",
+            "network",
+            &networks,
+        );
+        let clients: Vec<_> = distribution
+            .clients
+            .iter()
+            .map(|(client, count)| (client.as_str(), *count as i64))
+            .collect();
+        let _ = r.register_labeled_gauge(
+            "net_peers_by_client",
+            "Number of connected peers by client name",
+            "client",
+            &clients,
+        );
+        let buffer_pool = self.network.buffer_pool_stats();
+        let _ = r.register_counter(
+            "net_buffer_pool_hits",
+            "Number of network buffers served from the buffer pool",
+            buffer_pool.hits as i64,
+        );
+        let _ = r.register_counter(
+            "net_buffer_pool_misses",
+            "Number of network buffers allocated because the buffer pool had none",
+            buffer_pool.misses as i64,
+        );
+        let _ = r.register_gauge(
+            "net_buffer_pool_resident_bytes",
+            "Bytes held by idle buffers in the network buffer pool",
+            buffer_pool.resident_bytes as i64,
+        );
+        let io = self.network.io_stats();
+        let _ = r.register_gauge(
+            "net_io_queued",
+            "Number of network IO work items waiting for a worker",
+            io.queued as i64,
+        );
+        let _ = r.register_gauge(
+            "net_io_max_queued",
+            "Highest number of network IO work items waiting for a worker",
+            io.max_queued as i64,
+        );
+        let _ = r.register_gauge(
+            "net_io_busy_workers",
+            "Number of network IO workers running a handler",
+            io.busy_workers as i64,
+        );
+        let _ = r.register_counter(
+            "net_io_saturation_warnings",
+            "Number of warnings about network IO work waiting too long for a worker",
+            io.saturation_warnings as i64,
+        );
+        let latencies = [
+            ("timer", io.timer),
+            ("message", io.message),
+            ("readable", io.readable),
+            ("writable", io.writable),
+            ("hup", io.hup),
+        ];
+        let queue_times: Vec<_> = latencies
+            .iter()
+            .map(|(kind, latency)| (*kind, latency.queue_time.as_micros() as i64))
+            .collect();
+        let _ = r.register_labeled_gauge(
+            "net_io_queue_time_us",
+            "Average time network IO work waits for a worker, in microseconds, by kind of work",
+            "kind",
+            &queue_times,
+        );
+        let execution_times: Vec<_> = latencies
+            .iter()
+            .map(|(kind, latency)| (*kind, latency.execution_time.as_micros() as i64))
+            .collect();
+        let _ = r.register_labeled_gauge(
+            "net_io_execution_time_us",
+            "Average time spent in network IO handlers, in microseconds, by kind of work",
+            "kind",
+            &execution_times,
+        );
+        let stats = self.network.stats();
+        let _ = r.register_counter(
+            "net_reachability_probes_reachable",
+            "Number of probes which found the public address reachable",
+            stats.reachable_probes as i64,
+        );
+        let _ = r.register_counter(
+            "net_reachability_probes_unreachable",
+            "Number of probes which found the public address not reachable",
+            stats.unreachable_probes as i64,
+        );
+        if let Some(probe) = stats.reachability {
+            let _ = r.register_gauge(
+                "net_reachable",
+                "Whether the last probe found the public address reachable",
+                (probe.result == Reachability::Reachable) as i64,
+            );
+        }
+        if let Some(dns) = self.network.dns_discovery_stats() {
+            for (name, help, value) in [
+                ("lookups", "Number of DNS discovery lookups", dns.lookups),
+                (
+                    "failed_lookups",
+                    "Number of DNS discovery lookups which failed or returned no record",
+                    dns.failed_lookups,
+                ),
+                (
+                    "malformed_records",
+                    "Number of DNS discovery records skipped as malformed",
+                    dns.malformed_records,
+                ),
+                (
+                    "bad_signatures",
+                    "Number of DNS discovery records skipped for an invalid signature",
+                    dns.bad_signatures,
+                ),
+                (
+                    "hash_mismatches",
+                    "Number of DNS discovery records skipped for not matching their hash",
+                    dns.hash_mismatches,
+                ),
+                (
+                    "cycles",
+                    "Number of DNS discovery branch entries skipped as cycles",
+                    dns.cycles,
+                ),
+                ("nodes", "Number of nodes found by DNS discovery", dns.nodes),
+            ] {
+                let _ =
+                    r.register_counter(&format!("net_dns_discovery_{name}"), help, value as i64);
+            }
+        }
+        let _ = r.register_counter(
             "sync_blocks_recieved",
             "Number of blocks downloaded so far",
             sync_status.blocks_received as i64,
         );
-        r.register_counter(
+        let _ = r.register_counter(
             "sync_blocks_total",
             "Total number of blocks for the sync process",
             sync_status.blocks_total as i64,
         );
-        r.register_gauge(
+        let _ = r.register_counter(
+            "sync_new_blocks_skipped",
+            "Number of announced blocks skipped because they were already known",
+            sync_status.num_skipped_new_blocks as i64,
+        );
+        let _ = r.register_counter(
+            "sync_new_blocks_fast_path",
+            "Number of announced blocks extending the best block verified ahead of the queued blocks",
+            sync_status.num_fast_path_new_blocks as i64,
+        );
+        let _ = r.register_gauge(
+            "sync_new_block_import_latency_ms",
+            "Time from receiving the last fast path block until it was imported",
+            sync_status
+                .new_block_import_latency
+                .map_or(0, |latency| latency.as_millis() as i64),
+        );
+        let _ = r.register_counter(
+            "sync_status_timeout_drops",
+            "Number of peers dropped because they did not send Status in time",
+            sync_status.num_status_timeout_drops as i64,
+        );
+        let _ = r.register_counter(
+            "sync_rejected_sealed_blocks",
+            "Number of blocks sealed by this node and rejected by the block queue",
+            sync_status.num_rejected_sealed_blocks as i64,
+        );
+        let _ = r.register_counter(
+            "sync_compressed_packets_sent",
+            "Number of compressed block bodies and receipts packets sent",
+            sync_status.num_compressed_packets_sent as i64,
+        );
+        let _ = r.register_counter(
+            "sync_compressed_bytes_saved",
+            "Bytes saved by sending compressed block bodies and receipts",
+            sync_status.compressed_bytes_saved as i64,
+        );
+        let _ = r.register_counter(
+            "sync_events_dropped",
+            "Number of sync events dropped because the subscriber lagged behind",
+            sync_status.num_dropped_sync_events as i64,
+        );
+        let _ = r.register_counter(
+            "sync_delayed_requests",
+            "Number of requests delayed because the client was busy",
+            sync_status.num_delayed_requests as i64,
+        );
+        let _ = r.register_counter(
+            "sync_delayed_requests_evicted",
+            "Number of delayed requests evicted to stay within the queue caps",
+            sync_status.num_evicted_delayed_requests as i64,
+        );
+        let _ = r.register_counter(
+            "sync_delayed_requests_expired",
+            "Number of delayed requests dropped because they were queued for too long",
+            sync_status.num_expired_delayed_requests as i64,
+        );
+        let packets = |value: fn(&PacketDispatchStats) -> i64| {
+            sync_status
+                .packet_stats
+                .iter()
+                .map(|p| (p.name, value(p)))
+                .collect::<Vec<_>>()
+        };
+        let _ = r.register_labeled_counter(
+            "sync_packets_processed",
+            "Number of packets received from peers and processed, by packet",
+            "packet",
+            &packets(|p| p.count as i64),
+        );
+        let _ = r.register_labeled_counter(
+            "sync_packets_processed_bytes",
+            "Payload bytes of the packets processed, by packet",
+            "packet",
+            &packets(|p| p.bytes as i64),
+        );
+        let _ = r.register_labeled_counter(
+            "sync_packets_processing_us",
+            "Time spent processing packets in microseconds, by packet",
+            "packet",
+            &packets(|p| p.processing_time.as_micros() as i64),
+        );
+        let _ = r.register_labeled_counter(
+            "sync_packets_delayed",
+            "Number of packets delayed because the client was busy, by packet",
+            "packet",
+            &packets(|p| p.delayed as i64),
+        );
+        let _ = r.register_labeled_counter(
+            "sync_packets_decode_failures",
+            "Number of packets which failed to decode, by packet",
+            "packet",
+            &packets(|p| p.decode_failures as i64),
+        );
+        let _ = r.register_counter(
+            "sync_stall_warnings",
+            "Number of times the best block was reported not advancing while peers were ahead",
+            sync_status.stall_recoveries.warnings as i64,
+        );
+        let _ = r.register_counter(
+            "sync_stall_restarts",
+            "Number of sync restarts because the best block was not advancing",
+            sync_status.stall_recoveries.restarts as i64,
+        );
+        let _ = r.register_counter(
+            "sync_stall_peer_reconfirmations",
+            "Number of times all peers were confirmed again because the best block was not advancing",
+            sync_status.stall_recoveries.peer_reconfirmations as i64,
+        );
+        let _ = r.register_gauge(
+            "sync_rejected_blocks",
+            "Number of blocks rejected on import within the branch probe window",
+            sync_status.branch_probe.rejected.len() as i64,
+        );
+        let _ = r.register_gauge(
+            "sync_rejected_block_rejections",
+            "Number of rejections of the blocks rejected within the branch probe window, counting their descendants",
+            sync_status
+                .branch_probe
+                .rejected
+                .iter()
+                .map(|b| b.rejections as i64)
+                .sum(),
+        );
+        let _ = r.register_counter(
+            "sync_branch_probes",
+            "Number of peers probed for a branch without the rejected block most peers are stuck at",
+            sync_status.branch_probe.probes as i64,
+        );
+        let _ = r.register_counter(
+            "sync_branch_probe_alternatives",
+            "Number of branch probes which found a branch without the rejected block",
+            sync_status.branch_probe.alternatives_found as i64,
+        );
+        let _ = r.register_gauge(
+            "sync_tx_relay_peers",
+            "Number of peers transactions are relayed to",
+            sync_status.num_tx_relay_peers as i64,
+        );
+        let _ = r.register_gauge(
+            "sync_sync_only_peers",
+            "Number of peers only used for block sync",
+            sync_status.num_sync_only_peers as i64,
+        );
+        let _ = r.register_gauge(
             "sync_blocks_highest",
             "Highest block number in the download queue",
             sync_status.highest_block_number.unwrap_or(0) as i64,
         );
+        let _ = r.register_gauge(
+            "sync_headers_backfilled",
+            "Number of blocks below the header checkpoint covered by backfilled headers",
+            sync_status.headers_backfilled as i64,
+        );
+        let _ = r.register_gauge(
+            "sync_headers_backfill_target",
+            "Number of blocks below the header checkpoint to backfill headers for",
+            sync_status.headers_backfill_target as i64,
+        );
 
-        r.register_gauge(
+        let _ = r.register_gauge(
             "snapshot_download_active",
             "1 if downloading snapshots",
             scalar(sync_status.is_snapshot_syncing()),
         );
-        r.register_gauge(
+        let _ = r.register_gauge(
             "snapshot_download_chunks",
             "Snapshot chunks",
             sync_status.num_snapshot_chunks as i64,
         );
-        r.register_gauge(
+        let _ = r.register_gauge(
             "snapshot_download_chunks_done",
             "Snapshot chunks downloaded",
             sync_status.snapshot_chunks_done as i64,
         );
+        let _ = r.register_counter(
+            "snapshot_download_bad_chunks",
+            "Snapshot chunks dropped because they were too large or not the chunk requested",
+            sync_status.num_bad_snapshot_chunks as i64,
+        );
 
         let restoration = self.eth_handler.snapshot_service.restoration_status();
         let creation = self.eth_handler.snapshot_service.creation_status();
@@ -420,7 +1071,7 @@ impl PrometheusMetrics for EthSync {
             .manifest_block()
             .unwrap_or((0, H256::zero()));
 
-        r.register_gauge(
+        let _ = r.register_gauge(
             "snapshot_create_block",
             "First block of the current snapshot creation",
             if let CreationStatus::Ongoing { block_number } = creation {
@@ -429,7 +1080,7 @@ impl PrometheusMetrics for EthSync {
                 0
             },
         );
-        r.register_gauge(
+        let _ = r.register_gauge(
             "snapshot_restore_block",
             "First block of the current snapshot restoration",
             if let RestorationStatus::Ongoing { block_number, .. } = restoration {
@@ -438,7 +1089,7 @@ impl PrometheusMetrics for EthSync {
                 0
             },
         );
-        r.register_gauge(
+        let _ = r.register_gauge(
             "snapshot_manifest_block",
             "First block number of the present snapshot",
             manifest_block_num as i64,
@@ -452,6 +1103,8 @@ const CONTINUE_SYNC_TIMER: TimerToken = 2;
 const TX_TIMER: TimerToken = 3;
 const PRIORITY_TIMER: TimerToken = 4;
 const DELAYED_PROCESSING_TIMER: TimerToken = 5;
+const HEAD_HINTS_TIMER: TimerToken = 6;
+const CONTINUE_SYNC_FLUSH_TIMER: TimerToken = 7;
 
 pub(crate) const PRIORITY_TIMER_INTERVAL: Duration = Duration::from_millis(250);
 
@@ -464,6 +1117,18 @@ struct SyncProtocolHandler {
     sync: ChainSyncApi,
     /// Chain overlay used to cache data such as fork block.
     overlay: RwLock<HashMap<BlockNumber, Bytes>>,
+    /// File the heads advertised by peers are saved to, if any.
+    head_hints_path: Option<PathBuf>,
+    /// Interval of the deferred sync rounds.
+    continue_sync_interval: Duration,
+}
+
+impl SyncProtocolHandler {
+    fn save_head_hints(&self) {
+        if let Some(ref path) = self.head_hints_path {
+            self.sync.head_hints().save(path, SystemTime::now());
+        }
+    }
 }
 
 impl NetworkProtocolHandler for SyncProtocolHandler {
@@ -479,6 +1144,12 @@ impl NetworkProtocolHandler for SyncProtocolHandler {
                 .expect("Error registering transactions timer");
             io.register_timer(DELAYED_PROCESSING_TIMER, Duration::from_millis(2100))
                 .expect("Error registering delayed processing timer");
+            io.register_timer(HEAD_HINTS_TIMER, Duration::from_secs(5 * 60))
+                .expect("Error registering head hints timer");
+            if !self.continue_sync_interval.is_zero() {
+                io.register_timer(CONTINUE_SYNC_FLUSH_TIMER, self.continue_sync_interval)
+                    .expect("Error registering sync timer");
+            }
 
             io.register_timer(PRIORITY_TIMER, PRIORITY_TIMER_INTERVAL)
                 .expect("Error registering peers timer");
@@ -527,6 +1198,8 @@ impl NetworkProtocolHandler for SyncProtocolHandler {
             TX_TIMER => self.sync.write().propagate_new_transactions(&mut io),
             PRIORITY_TIMER => self.sync.process_priority_queue(&mut io),
             DELAYED_PROCESSING_TIMER => self.sync.process_delayed_requests(&mut io),
+            HEAD_HINTS_TIMER => self.save_head_hints(),
+            CONTINUE_SYNC_FLUSH_TIMER => self.sync.flush_continue_sync(&mut io),
             _ => warn!("Unknown timer {timer} triggered."),
         }
     }
@@ -567,6 +1240,7 @@ impl ChainNotify for EthSync {
                 &new_blocks.proposed,
             );
         });
+        self.publish_fork_id();
     }
 
     fn start(&self) {
@@ -596,7 +1270,12 @@ impl ChainNotify for EthSync {
             .register_protocol(
                 self.eth_handler.clone(),
                 PAR_PROTOCOL,
-                &[PAR_PROTOCOL_VERSION_1, PAR_PROTOCOL_VERSION_2],
+                &[
+                    PAR_PROTOCOL_VERSION_1,
+                    PAR_PROTOCOL_VERSION_2,
+                    PAR_PROTOCOL_VERSION_3,
+                    PAR_PROTOCOL_VERSION_4,
+                ],
             )
             .unwrap_or_else(|e| warn!("Error registering snapshot sync protocol: {e:?}"));
     }
@@ -604,6 +1283,7 @@ impl ChainNotify for EthSync {
     fn stop(&self) {
         self.eth_handler.snapshot_service.abort_restore();
         self.network.stop();
+        self.eth_handler.save_head_hints();
     }
 
     fn broadcast(&self, message_type: ChainMessageType) {
@@ -628,6 +1308,17 @@ impl ChainNotify for EthSync {
         let mut sync = self.eth_handler.sync.write();
         sync.transactions_received(txs, peer_id);
     }
+
+    fn transactions_rejected(&self, hashes: &[H256], peer_id: PeerId) {
+        let mut sync = self.eth_handler.sync.write();
+        sync.transactions_rejected(hashes, peer_id);
+    }
+
+    fn validators_changed(&self, validators: &[Address]) {
+        self.eth_handler
+            .sync
+            .set_validators(validators.iter().cloned().collect());
+    }
 }
 
 /// Trait for managing network
@@ -648,6 +1339,17 @@ pub trait ManageNetwork: Send + Sync {
     fn num_peers_range(&self) -> RangeInclusive<u32>;
     /// Get network context for protocol.
     fn with_proto_context(&self, proto: ProtocolId, f: &mut dyn FnMut(&dyn NetworkContext));
+    /// Start capturing the decrypted subprotocol packets of a connected peer, given by its
+    /// node id, to a new file `name` in the capture directory of the network until `max_bytes`
+    /// are written, the peer disconnects or the capture is stopped.
+    fn start_packet_capture(
+        &self,
+        node_id: String,
+        max_bytes: usize,
+        name: String,
+    ) -> Result<(), String>;
+    /// Stop capturing the packets of a peer. Returns false if they were not captured.
+    fn stop_packet_capture(&self, node_id: String) -> Result<bool, String>;
 }
 
 impl ManageNetwork for EthSync {
@@ -698,6 +1400,45 @@ impl ManageNetwork for EthSync {
     fn with_proto_context(&self, proto: ProtocolId, f: &mut dyn FnMut(&dyn NetworkContext)) {
         self.network.with_context_eval(proto, f);
     }
+
+    fn start_packet_capture(
+        &self,
+        node_id: String,
+        max_bytes: usize,
+        name: String,
+    ) -> Result<(), String> {
+        let node_id = parse_node_id(&node_id)?;
+        let peer = self
+            .network
+            .with_context_eval(self.subprotocol_name, |ctx| {
+                self.network
+                    .connected_peers()
+                    .into_iter()
+                    .find(|peer| ctx.session_info(*peer).and_then(|s| s.id) == Some(node_id))
+            })
+            .flatten()
+            .ok_or_else(|| "Peer is not connected".to_owned())?;
+        let mut captures = self.packet_captures.lock();
+        captures.retain(|_, capture| capture.is_active());
+        let capture = self
+            .network
+            .start_capture(peer, max_bytes, &name)
+            .map_err(|e| format!("{e}"))?;
+        captures.insert(node_id, capture);
+        Ok(())
+    }
+
+    fn stop_packet_capture(&self, node_id: String) -> Result<bool, String> {
+        let node_id = parse_node_id(&node_id)?;
+        Ok(self.packet_captures.lock().remove(&node_id).is_some())
+    }
+}
+
+fn parse_node_id(node_id: &str) -> Result<H512, String> {
+    node_id
+        .trim_start_matches("0x")
+        .parse()
+        .map_err(|_| format!("Invalid node id: {node_id}"))
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -711,6 +1452,10 @@ pub struct NetworkConfiguration {
     pub listen_address: Option<String>,
     /// IP address to advertise. Detected automatically if none.
     pub public_address: Option<String>,
+    /// Network interface the sockets are bound to (Linux only)
+    pub bind_interface: Option<String>,
+    /// Local address outbound connections are made from
+    pub outbound_source_address: Option<String>,
     /// Port for UDP connections, same as TCP by default
     pub udp_port: Option<u16>,
     /// Enable NAT configuration
@@ -719,12 +1464,16 @@ pub struct NetworkConfiguration {
     pub discovery_enabled: bool,
     /// List of initial node addresses
     pub boot_nodes: Vec<String>,
+    /// EIP-1459 node tree URLs to discover nodes from
+    pub dns_discovery_urls: Vec<String>,
     /// Use provided node key instead of default
     pub use_secret: Option<Secret>,
     /// Max number of connected peers to maintain
     pub max_peers: u32,
     /// Min number of connected peers to maintain
     pub min_peers: u32,
+    /// Percent of the peer slots one IP prefix or client may take, 100 turns the limit off.
+    pub max_peer_share: u32,
     /// Max pending peers.
     pub max_pending_peers: u32,
     /// Reserved snapshot sync peers.
@@ -737,6 +1486,16 @@ pub struct NetworkConfiguration {
     pub ip_filter: IpFilter,
     /// Client version string
     pub client_version: String,
+    /// How long a disabled peer stays banned, in seconds
+    pub peer_ban_duration: u64,
+    /// Label telling apart several network instances of one process. Prefixes their thread
+    /// and metric names and names the subdirectory of their files.
+    pub instance_label: String,
+    /// How often the public address is probed for reachability, in seconds. Not probed if `None`.
+    pub reachability_probe_interval: Option<u64>,
+    /// Maximum number of peers whose packets may be captured to files at once. Capturing is
+    /// disabled if zero.
+    pub max_packet_captures: usize,
 }
 
 impl Default for NetworkConfiguration {
@@ -769,13 +1528,20 @@ impl NetworkConfiguration {
                 .public_address
                 .map(|addr| SocketAddr::from_str(&addr))
                 .transpose()?,
+            bind_interface: self.bind_interface,
+            outbound_source_address: self
+                .outbound_source_address
+                .map(|addr| SocketAddr::from_str(&addr))
+                .transpose()?,
             udp_port: self.udp_port,
             nat_enabled: self.nat_enabled,
             discovery_enabled: self.discovery_enabled,
             boot_nodes: self.boot_nodes,
+            dns_discovery_urls: self.dns_discovery_urls,
             use_secret: self.use_secret,
             max_peers: self.max_peers,
             min_peers: self.min_peers,
+            max_peer_share: self.max_peer_share,
             max_handshakes: self.max_pending_peers,
             reserved_protocols: hash_map![PAR_PROTOCOL => self.snapshot_peers],
             reserved_nodes: self.reserved_nodes,
@@ -786,6 +1552,11 @@ impl NetworkConfiguration {
                 NonReservedPeerMode::Deny
             },
             client_version: self.client_version,
+            peer_ban_duration: Duration::from_secs(self.peer_ban_duration),
+            instance_label: self.instance_label,
+            reachability_probe_interval: self.reachability_probe_interval.map(Duration::from_secs),
+            max_packet_captures: self.max_packet_captures,
+            ..BasicNetworkConfiguration::new()
         })
     }
 }
@@ -797,13 +1568,17 @@ impl From<BasicNetworkConfiguration> for NetworkConfiguration {
             net_config_path: other.net_config_path,
             listen_address: other.listen_address.map(|addr| format!("{addr}")),
             public_address: other.public_address.map(|addr| format!("{addr}")),
+            bind_interface: other.bind_interface,
+            outbound_source_address: other.outbound_source_address.map(|addr| format!("{addr}")),
             udp_port: other.udp_port,
             nat_enabled: other.nat_enabled,
             discovery_enabled: other.discovery_enabled,
             boot_nodes: other.boot_nodes,
+            dns_discovery_urls: other.dns_discovery_urls,
             use_secret: other.use_secret,
             max_peers: other.max_peers,
             min_peers: other.min_peers,
+            max_peer_share: other.max_peer_share,
             max_pending_peers: other.max_handshakes,
             snapshot_peers: *other.reserved_protocols.get(&PAR_PROTOCOL).unwrap_or(&0),
             reserved_nodes: other.reserved_nodes,
@@ -813,6 +1588,12 @@ impl From<BasicNetworkConfiguration> for NetworkConfiguration {
                 _ => false,
             },
             client_version: other.client_version,
+            peer_ban_duration: other.peer_ban_duration.as_secs(),
+            instance_label: other.instance_label,
+            reachability_probe_interval: other
+                .reachability_probe_interval
+                .map(|interval| interval.as_secs()),
+            max_packet_captures: other.max_packet_captures,
         }
     }
 }
@@ -840,3 +1621,49 @@ pub struct PeerNumbers {
     /// Min peers.
     pub min: usize,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transaction_packet_size_is_bounded() {
+        let config = |size| SyncConfig {
+            max_transaction_packet_size: size,
+            ..SyncConfig::default()
+        };
+        assert_eq!(SyncConfig::default().validate(50), Ok(()));
+        assert_eq!(SyncConfig::default().validate(1), Ok(()));
+        assert_eq!(config(64 * 1024).validate(50), Ok(()));
+        assert_eq!(config(15 * 1024 * 1024).validate(50), Ok(()));
+        assert!(config(64 * 1024 - 1).validate(50).is_err());
+        assert!(config(15 * 1024 * 1024 + 1).validate(50).is_err());
+    }
+
+    #[test]
+    fn propagation_peers_are_bounded() {
+        let config = |min, max| SyncConfig {
+            min_peers_propagation: min,
+            max_peers_propagation: max,
+            ..SyncConfig::default()
+        };
+        assert_eq!(config(1, 1).validate(1), Ok(()));
+        assert_eq!(config(25, 200).validate(25), Ok(()));
+        assert!(config(0, 128).validate(50).is_err());
+        assert!(config(8, 4).validate(50).is_err());
+        // more than the peer limit
+        assert!(config(26, 128).validate(25).is_err());
+        assert!(SyncConfig {
+            max_peer_lag_propagation: 0,
+            ..SyncConfig::default()
+        }
+        .validate(50)
+        .is_err());
+        assert!(SyncConfig {
+            max_transactions_to_request: 0,
+            ..SyncConfig::default()
+        }
+        .validate(50)
+        .is_err());
+    }
+}
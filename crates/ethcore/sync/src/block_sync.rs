@@ -25,12 +25,17 @@ use ethcore::{
 };
 use ethereum_types::H256;
 use network::{client_version::ClientCapabilities, PeerId};
+use peer_selection::Rendezvous;
 use rlp::{self, Rlp};
 use std::cmp;
 ///
 /// Blockchain downloader
 ///
-use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    time::{Instant, SystemTime},
+};
+use subchain_heads::{HeadsStart, Inserted, SubchainHeads};
 use sync_io::SyncIo;
 use types::BlockNumber;
 
@@ -39,9 +44,12 @@ const MAX_BODIES_TO_REQUEST_LARGE: usize = 128;
 const MAX_BODIES_TO_REQUEST_SMALL: usize = 32; // Size request for parity clients prior to 2.4.0
 const MAX_RECEPITS_TO_REQUEST: usize = 256;
 const SUBCHAIN_SIZE: u64 = 256;
+const SUBCHAIN_HEADS_PER_REQUEST: u64 = 64;
+const MAX_CHAIN_HEAD_REQUESTS: usize = 8;
 const MAX_ROUND_PARENTS: usize = 16;
-const MAX_PARALLEL_SUBCHAIN_DOWNLOAD: usize = 5;
 const MAX_USELESS_HEADERS_PER_ROUND: usize = 3;
+const MAX_DIAGNOSTIC_HEADS: usize = 64;
+const MAX_RESET_EVENTS: usize = 16;
 
 // logging macros prepend BlockSet context for log filtering
 macro_rules! trace_sync {
@@ -77,9 +85,22 @@ pub enum State {
 
 /// Data that needs to be requested from a peer.
 pub enum BlockRequest {
-    Headers { start: H256, count: u64, skip: u64 },
-    Bodies { hashes: Vec<H256> },
-    Receipts { hashes: Vec<H256> },
+    Headers {
+        start: H256,
+        count: u64,
+        skip: u64,
+    },
+    HeadersByNumber {
+        start: BlockNumber,
+        count: u64,
+        skip: u64,
+    },
+    Bodies {
+        hashes: Vec<H256>,
+    },
+    Receipts {
+        hashes: Vec<H256>,
+    },
 }
 
 /// Indicates sync action
@@ -99,6 +120,107 @@ pub enum BlockDownloaderImportError {
     Useless,
 }
 
+/// Why a downloader dropped its downloaded block data.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ResetReason {
+    /// Peers kept answering header requests with nothing useful.
+    UselessHeaders,
+    /// No common block was found, download restarts from the best block.
+    Retracted,
+    /// The block import queue was full.
+    QueueFull,
+    /// A downloaded block failed to import.
+    BadBlock,
+    /// The sync state machine restarted the download.
+    Restart,
+}
+
+/// A block which failed to import, with the downloaded blocks of the round built on it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RejectedBlock {
+    /// Hash of the block.
+    pub hash: H256,
+    /// Number of the block.
+    pub number: BlockNumber,
+    /// Hash of the parent block.
+    pub parent: H256,
+    /// Peer which supplied the header.
+    pub peer: PeerId,
+    /// Downloaded descendants of the block, dropped along with it.
+    pub descendants: Vec<H256>,
+}
+
+/// A branch of peers diverging from ours further below the best block than the reorg limit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeepReorg {
+    /// Number of the block the branch links to, or of the lowest block searched for it.
+    pub number: BlockNumber,
+    /// Hash of the block the branch links to, or of the lowest block searched for it.
+    pub hash: H256,
+    /// Best block number at the time.
+    pub best: BlockNumber,
+    /// Peers asked for the branch since blocks were imported the last time.
+    pub peers: Vec<PeerId>,
+}
+
+/// A reset of a downloader.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ResetEvent {
+    /// Why the downloaded data was dropped.
+    pub reason: ResetReason,
+    /// Last imported block number at the time of the reset.
+    pub last_imported_block: BlockNumber,
+    /// When the reset happened.
+    pub at: SystemTime,
+}
+
+/// Download status of a subchain head.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum HeadStatus {
+    /// Headers following the head are not requested from any peer.
+    Unassigned,
+    /// Headers following the head are requested from a peer.
+    AssignedToPeer(Option<PeerId>),
+    /// The body of the head block is requested from a peer.
+    DownloadingBodies(Option<PeerId>),
+}
+
+/// A subchain head along with its download status.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct HeadDiagnostics {
+    /// Hash of the head block.
+    pub hash: H256,
+    /// Download status of the subchain.
+    pub status: HeadStatus,
+}
+
+/// Snapshot of a downloader state, for debugging a stalled sync.
+#[derive(Clone, Debug)]
+pub struct DownloaderDiagnostics {
+    /// Which set of blocks is downloaded.
+    pub block_set: BlockSet,
+    /// Downloader state.
+    pub state: State,
+    /// Last imported block number.
+    pub last_imported_block: BlockNumber,
+    /// Last imported block hash.
+    pub last_imported_hash: H256,
+    /// Block the download stops at, for old blocks.
+    pub target_hash: Option<H256>,
+    /// Highest block number seen.
+    pub highest_block: Option<BlockNumber>,
+    /// Subchain heads, at most `MAX_DIAGNOSTIC_HEADS` of them.
+    pub heads: Vec<HeadDiagnostics>,
+    /// Number of subchain heads.
+    pub heads_total: usize,
+    /// Number of downloaded headers waiting for their body.
+    pub awaiting_bodies: usize,
+    /// Number of downloaded headers waiting for their receipts.
+    pub awaiting_receipts: usize,
+    /// Most recent resets, oldest first.
+    pub resets: Vec<ResetEvent>,
+}
+
 impl From<rlp::DecoderError> for BlockDownloaderImportError {
     fn from(_: rlp::DecoderError) -> BlockDownloaderImportError {
         BlockDownloaderImportError::Invalid
@@ -135,6 +257,33 @@ pub struct BlockDownloader {
     retract_step: u64,
     /// consecutive useless headers this round
     useless_headers_count: usize,
+    /// Already imported blocks whose stored data is replaced with the downloaded one.
+    /// Set for downloaders repairing ancient blocks.
+    repairs: Option<HashSet<H256>>,
+    /// Blocks put into the verification queue with the peers which supplied their headers.
+    queued_suppliers: Vec<(H256, PeerId)>,
+    /// New blocks which failed to import.
+    rejected: Vec<RejectedBlock>,
+    /// Size of the downloaded block data above which only the blocks the import waits for are requested.
+    max_memory: usize,
+    /// Subchain heads of the round, while in `State::ChainHead`.
+    subchain_heads: Option<SubchainHeads>,
+    /// Subchain heads requests outstanding at once for a single peer.
+    chain_head_pipeline: usize,
+    /// Subchain heads requests outstanding at once over all peers.
+    chain_head_requests: usize,
+    /// Bodies dropped for belonging to blocks not requested from the peer, or already downloaded.
+    dropped_bodies: usize,
+    /// Receipts dropped for belonging to blocks not requested from the peer, or already downloaded.
+    dropped_receipts: usize,
+    /// Most recent resets, at most `MAX_RESET_EVENTS` of them.
+    resets: VecDeque<ResetEvent>,
+    /// Number of blocks below the best block the search for a common block may go, for new blocks.
+    max_reorg_depth: Option<BlockNumber>,
+    /// Peers asked for subchain heads since blocks were imported the last time.
+    round_peers: HashSet<PeerId>,
+    /// Branch given up on for diverging deeper than `max_reorg_depth`.
+    deep_reorg: Option<DeepReorg>,
 }
 
 impl BlockDownloader {
@@ -160,16 +309,133 @@ impl BlockDownloader {
             target_hash: None,
             retract_step: 1,
             useless_headers_count: 0,
+            repairs: None,
+            queued_suppliers: Vec::new(),
+            rejected: Vec::new(),
+            max_memory: usize::MAX,
+            subchain_heads: None,
+            chain_head_pipeline: 1,
+            chain_head_requests: MAX_CHAIN_HEAD_REQUESTS,
+            dropped_bodies: 0,
+            dropped_receipts: 0,
+            resets: VecDeque::new(),
+            max_reorg_depth: None,
+            round_peers: HashSet::new(),
+            deep_reorg: None,
         }
     }
 
+    /// Create a downloader fetching ancient blocks after `start_hash` up to the block `target_hash`
+    /// again, replacing the stored data of the blocks in `repairs`.
+    pub fn new_repair(
+        start_hash: &H256,
+        start_number: BlockNumber,
+        target_hash: &H256,
+        repairs: HashSet<H256>,
+    ) -> Self {
+        let mut downloader = BlockDownloader::new(BlockSet::OldBlocks, start_hash, start_number);
+        downloader.set_target(target_hash);
+        downloader.repairs = Some(repairs);
+        downloader
+    }
+
+    /// Check if this downloader repairs already imported blocks.
+    pub fn is_repair(&self) -> bool {
+        self.repairs.is_some()
+    }
+
+    /// Take the blocks put into the verification queue along with the peers which supplied them.
+    pub fn take_queued_suppliers(&mut self) -> Vec<(H256, PeerId)> {
+        std::mem::take(&mut self.queued_suppliers)
+    }
+
+    /// Take the new blocks which failed to import.
+    pub fn take_rejected(&mut self) -> Vec<RejectedBlock> {
+        std::mem::take(&mut self.rejected)
+    }
+
+    /// Take the branch given up on for diverging deeper than the reorg limit, if any.
+    pub fn take_deep_reorg(&mut self) -> Option<DeepReorg> {
+        self.deep_reorg.take()
+    }
+
+    /// Parent of a block imported in the last rounds, if it is still known.
+    pub fn imported_parent(&self, hash: &H256) -> Option<H256> {
+        self.round_parents
+            .iter()
+            .find(|(h, _)| h == hash)
+            .map(|(_, parent)| *parent)
+    }
+
+    /// Check if the block was imported on the chain this downloader currently extends.
+    pub fn is_on_import_path(&self, hash: &H256) -> bool {
+        self.last_imported_hash == *hash
+            || self
+                .round_parents
+                .iter()
+                .any(|(h, parent)| h == hash || parent == hash)
+    }
+
     /// Reset sync. Clear all local downloaded data.
     pub fn reset(&mut self) {
         self.blocks.clear();
+        self.subchain_heads = None;
         self.useless_headers_count = 0;
         self.state = State::Idle;
     }
 
+    /// Reset sync on request of the sync state machine. Clear all local downloaded data.
+    pub fn restart(&mut self) {
+        self.note_reset(ResetReason::Restart);
+        self.reset();
+    }
+
+    fn note_reset(&mut self, reason: ResetReason) {
+        if self.resets.len() == MAX_RESET_EVENTS {
+            self.resets.pop_front();
+        }
+        self.resets.push_back(ResetEvent {
+            reason,
+            last_imported_block: self.last_imported_block,
+            at: SystemTime::now(),
+        });
+    }
+
+    /// Snapshot of the download state. Peers the heads are assigned to are left for the caller
+    /// to fill in.
+    pub fn diagnostics(&self) -> DownloaderDiagnostics {
+        let heads = self
+            .blocks
+            .heads()
+            .iter()
+            .take(MAX_DIAGNOSTIC_HEADS)
+            .map(|hash| HeadDiagnostics {
+                hash: *hash,
+                status: if self.blocks.is_downloading_headers(hash) {
+                    HeadStatus::AssignedToPeer(None)
+                } else if self.blocks.is_downloading_body(hash) {
+                    HeadStatus::DownloadingBodies(None)
+                } else {
+                    HeadStatus::Unassigned
+                },
+            })
+            .collect();
+        let (awaiting_bodies, awaiting_receipts) = self.blocks.awaiting_data();
+        DownloaderDiagnostics {
+            block_set: self.block_set,
+            state: self.state,
+            last_imported_block: self.last_imported_block,
+            last_imported_hash: self.last_imported_hash,
+            target_hash: self.target_hash,
+            highest_block: self.highest_block,
+            heads,
+            heads_total: self.blocks.heads_len(),
+            awaiting_bodies,
+            awaiting_receipts,
+            resets: self.resets.iter().cloned().collect(),
+        }
+    }
+
     /// Mark a block as known in the chain
     pub fn mark_as_known(&mut self, hash: &H256, number: BlockNumber) {
         if number > self.last_imported_block {
@@ -181,6 +447,11 @@ impl BlockDownloader {
         }
     }
 
+    /// Check if subchain heads or blocks are being downloaded
+    pub fn is_active(&self) -> bool {
+        self.state == State::ChainHead || self.state == State::Blocks
+    }
+
     /// Check if download is complete
     pub fn is_complete(&self) -> bool {
         self.state == State::Complete
@@ -196,6 +467,49 @@ impl BlockDownloader {
         self.target_hash = Some(*hash);
     }
 
+    /// Set the size of the downloaded block data above which no more headers are requested.
+    pub fn set_max_memory(&mut self, bytes: usize) {
+        self.max_memory = bytes;
+    }
+
+    /// Set the number of subchain heads requests sent to a single peer and to all peers at once.
+    pub fn set_chain_head_pipeline(&mut self, per_peer: usize, total: usize) {
+        self.chain_head_pipeline = per_peer;
+        self.chain_head_requests = total;
+    }
+
+    /// Set the number of blocks below the best block the search for a common block with the
+    /// peers may go, `None` for no limit. Ignored when downloading old blocks.
+    pub fn set_max_reorg_depth(&mut self, depth: Option<BlockNumber>) {
+        self.max_reorg_depth = depth;
+    }
+
+    /// Hash of the block the subchain heads requested from `peer_id` start from, if any are
+    /// still to come.
+    pub fn chain_head_start(&self, peer_id: PeerId) -> Option<H256> {
+        self.subchain_heads
+            .as_ref()
+            .filter(|heads| heads.expects(peer_id))
+            .map(|heads| heads.start_hash())
+    }
+
+    /// Forget the subchain heads requests of a peer which is gone.
+    pub fn clear_chain_head_requests(&mut self, peer_id: PeerId) {
+        if let Some(ref mut heads) = self.subchain_heads {
+            heads.clear_peer(peer_id);
+        }
+    }
+
+    /// Give up on the subchain heads requests past their deadline so other peers are asked.
+    pub fn expire_chain_head_requests(&mut self, now: Instant) {
+        if let Some(ref mut heads) = self.subchain_heads {
+            let expired = heads.expire(now);
+            if expired > 0 {
+                trace_sync!(self, "{} subchain heads requests expired", expired);
+            }
+        }
+    }
+
     /// Unmark header as being downloaded.
     pub fn clear_header_download(&mut self, hash: &H256) {
         self.blocks.clear_header_download(hash)
@@ -225,9 +539,20 @@ impl BlockDownloader {
             format!("{}{}", prefix, "round_parents"),
             self.round_parents.len(),
         );
+        sizes.insert(
+            format!("{}{}", prefix, "dropped_bodies"),
+            self.dropped_bodies,
+        );
+        if self.download_receipts {
+            sizes.insert(
+                format!("{}{}", prefix, "dropped_receipts"),
+                self.dropped_receipts,
+            );
+        }
     }
 
     fn reset_to_block(&mut self, start_hash: &H256, start_number: BlockNumber) {
+        self.note_reset(ResetReason::Retracted);
         self.reset();
         self.last_imported_block = start_number;
         self.last_imported_hash = *start_hash;
@@ -244,10 +569,11 @@ impl BlockDownloader {
         self.last_imported_block
     }
 
-    /// Add new block headers.
+    /// Add new block headers supplied by `peer_id`.
     pub fn import_headers(
         &mut self,
         io: &mut dyn SyncIo,
+        peer_id: PeerId,
         r: &Rlp,
         expected_hash: H256,
         eip1559_transition: BlockNumber,
@@ -261,11 +587,41 @@ impl BlockDownloader {
             return Err(BlockDownloaderImportError::Invalid);
         }
 
+        // Subchain heads requested in segments are matched to their request by the first header.
+        let segment = match self.subchain_heads {
+            Some(ref mut heads) if self.state == State::ChainHead => {
+                let first = match item_count {
+                    0 => None,
+                    _ => {
+                        let info =
+                            SyncHeader::from_rlp(r.at(0)?.as_raw().to_vec(), eip1559_transition)?;
+                        Some((info.header.number(), info.header.hash()))
+                    }
+                };
+                match heads.take_request(peer_id, first) {
+                    Some(segment) => Some((segment, heads.start(segment), heads.count(segment))),
+                    None => {
+                        trace_sync!(self, "Ignored subchain heads not requested (any more)");
+                        return Ok(DownloadAction::None);
+                    }
+                }
+            }
+            _ => None,
+        };
+
         // The request is generated in ::request_blocks.
-        let (max_count, skip) = if self.state == State::ChainHead {
-            (SUBCHAIN_SIZE as usize, (MAX_HEADERS_TO_REQUEST - 2) as u64)
-        } else {
-            (MAX_HEADERS_TO_REQUEST, 0)
+        let (max_count, skip) = match (self.state, segment) {
+            (State::ChainHead, Some((_, _, count))) => {
+                (count as usize, (MAX_HEADERS_TO_REQUEST - 2) as u64)
+            }
+            (State::ChainHead, None) => {
+                (SUBCHAIN_SIZE as usize, (MAX_HEADERS_TO_REQUEST - 2) as u64)
+            }
+            _ => (MAX_HEADERS_TO_REQUEST, 0),
+        };
+        let expected_start = match segment {
+            Some((_, start, _)) => start,
+            None => HeadsStart::Hash(expected_hash),
         };
 
         if item_count > max_count {
@@ -276,6 +632,9 @@ impl BlockDownloader {
         let mut headers = Vec::new();
         let mut hashes = Vec::new();
         let mut last_header = None;
+        let floor = self.reorg_floor(io);
+        // block of our chain a new header links to below the reorg floor, if any
+        let mut deep_link = None;
         for i in 0..item_count {
             let info = SyncHeader::from_rlp(r.at(i)?.as_raw().to_vec(), eip1559_transition)?;
             let number = BlockNumber::from(info.header.number());
@@ -284,7 +643,10 @@ impl BlockDownloader {
             // This part checks if first header is what we expect and that all other header are chained correctly.
             let valid_response = match last_header {
                 // First header must match expected hash.
-                None => expected_hash == hash,
+                None => match expected_start {
+                    HeadsStart::Hash(expected_hash) => expected_hash == hash,
+                    HeadsStart::Number(expected_number) => expected_number == number,
+                },
                 Some((last_number, last_hash)) => {
                     // Subsequent headers must be spaced by skip interval.
                     let skip_valid = number == last_number + skip + 1;
@@ -335,12 +697,26 @@ impl BlockDownloader {
                     return Err(BlockDownloaderImportError::Invalid);
                 }
                 BlockStatus::Unknown => {
+                    let parent_hash = *info.header.parent_hash();
+                    if deep_link.is_none()
+                        && floor.is_some_and(|floor| number > 0 && number - 1 < floor)
+                        && io.chain().block_status(BlockId::Hash(parent_hash))
+                            == BlockStatus::InChain
+                    {
+                        deep_link = Some((number - 1, parent_hash));
+                    }
                     headers.push(info);
                     hashes.push(hash);
                 }
             }
         }
 
+        if let Some((number, hash)) = deep_link {
+            self.round_peers.insert(peer_id);
+            self.stop_at_reorg_limit(io, number, hash);
+            return Ok(DownloadAction::Reset);
+        }
+
         // Set highest block that we receive from network. This is only used as stat and nothing more.
         if let Some((number, _)) = last_header {
             if self.highest_block.as_ref().is_none_or(|n| number > *n) {
@@ -350,6 +726,13 @@ impl BlockDownloader {
 
         match self.state {
             State::ChainHead => {
+                // The peer may not know of blocks far down the chain, only the first segment
+                // must have heads.
+                if let Some((segment, _, _)) =
+                    segment.filter(|&(s, _, _)| s > 0 || !headers.is_empty())
+                {
+                    return Ok(self.insert_subchain_heads(segment, hashes));
+                }
                 if !headers.is_empty() {
                     trace_sync!(
                         self,
@@ -404,11 +787,12 @@ impl BlockDownloader {
                             "Received {:?} useless responses this round. Resetting sync",
                             MAX_USELESS_HEADERS_PER_ROUND
                         );
+                        self.note_reset(ResetReason::UselessHeaders);
                         self.reset();
                     }
                     return Err(BlockDownloaderImportError::Useless);
                 }
-                self.blocks.insert_headers(headers);
+                self.blocks.insert_headers(headers, peer_id);
                 trace_sync!(self, "Inserted {} headers", count);
             }
             _ => trace_sync!(self, "Unexpected headers({})", headers.len()),
@@ -417,7 +801,50 @@ impl BlockDownloader {
         Ok(DownloadAction::None)
     }
 
-    /// Called by peer once it has new block bodies
+    // Add the subchain heads received for a segment, start downloading blocks once all are there.
+    fn insert_subchain_heads(&mut self, segment: usize, hashes: Vec<H256>) -> DownloadAction {
+        let heads = self
+            .subchain_heads
+            .as_mut()
+            .expect("segments are only matched to requests of subchain heads; qed");
+        match heads.insert(segment, hashes) {
+            Inserted::Stored => (),
+            Inserted::Duplicate => {
+                trace_sync!(self, "Subchain heads segment {} received already", segment);
+                return DownloadAction::None;
+            }
+            Inserted::Unlinked(before, after) => {
+                debug!(target: "sync", "Subchain heads segments {before} and {after} are of different forks, requesting both again");
+                return DownloadAction::None;
+            }
+        }
+        match heads.merged() {
+            Some(merged) => {
+                trace_sync!(
+                    self,
+                    "Received {} subchain heads, proceeding to download",
+                    merged.len()
+                );
+                self.subchain_heads = None;
+                self.blocks.reset_to(merged);
+                self.state = State::Blocks;
+                DownloadAction::Reset
+            }
+            None => {
+                trace_sync!(
+                    self,
+                    "Received subchain heads segment {}, {} requests outstanding",
+                    segment,
+                    heads.outstanding()
+                );
+                DownloadAction::None
+            }
+        }
+    }
+
+    /// Called by peer once it has new block bodies. Bodies are only attached to the blocks in
+    /// `expected_hashes`, bodies of other blocks are late answers to expired requests or
+    /// duplicates and dropped.
     pub fn import_bodies(
         &mut self,
         r: &Rlp,
@@ -436,8 +863,8 @@ impl BlockDownloader {
                 bodies.push(body);
             }
 
-            let hashes = self.blocks.insert_bodies(bodies);
-            if hashes.len() != item_count {
+            let (hashes, dropped) = self.blocks.insert_bodies(bodies, expected_hashes);
+            if hashes.len() + dropped != item_count {
                 trace_sync!(self, "Deactivating peer for giving invalid block bodies");
                 return Err(BlockDownloaderImportError::Invalid);
             }
@@ -445,11 +872,17 @@ impl BlockDownloader {
                 trace_sync!(self, "Deactivating peer for giving unexpected block bodies");
                 return Err(BlockDownloaderImportError::Invalid);
             }
+            if dropped > 0 {
+                self.dropped_bodies += dropped;
+                trace_sync!(self, "Dropped {} late or duplicate block bodies", dropped);
+            }
         }
         Ok(())
     }
 
-    /// Called by peer once it has new block bodies
+    /// Called by peer once it has new block receipts. Receipts are only attached to the blocks in
+    /// `expected_hashes`, receipts of other blocks are late answers to expired requests or
+    /// duplicates and dropped.
     pub fn import_receipts(
         &mut self,
         r: &Rlp,
@@ -469,8 +902,8 @@ impl BlockDownloader {
                 })?;
                 receipts.push(receipt.as_raw().to_vec());
             }
-            let hashes = self.blocks.insert_receipts(receipts);
-            if hashes.len() != item_count {
+            let (hashes, dropped) = self.blocks.insert_receipts(receipts, expected_hashes);
+            if hashes.len() + dropped != item_count {
                 trace_sync!(self, "Deactivating peer for giving invalid block receipts");
                 return Err(BlockDownloaderImportError::Invalid);
             }
@@ -481,12 +914,17 @@ impl BlockDownloader {
                 );
                 return Err(BlockDownloaderImportError::Invalid);
             }
+            if dropped > 0 {
+                self.dropped_receipts += dropped;
+                trace_sync!(self, "Dropped {} late or duplicate block receipts", dropped);
+            }
         }
         Ok(())
     }
 
     fn start_sync_round(&mut self, io: &mut dyn SyncIo) {
         self.state = State::ChainHead;
+        self.subchain_heads = None;
         trace_sync!(
             self,
             "Starting round (last imported count = {:?}, last started = {}, block = {:?}",
@@ -500,6 +938,11 @@ impl BlockDownloader {
         let start_hash = self.last_round_start_hash;
         match self.imported_this_round {
             Some(n) if n == 0 && start > 0 => {
+                let floor = self.reorg_floor(io);
+                if floor.is_some_and(|floor| start <= floor) {
+                    self.stop_at_reorg_limit(io, start, start_hash);
+                    return;
+                }
                 // nothing was imported last round, step back to a previous block
                 // search parent in last round known parents first
                 if let Some(&(_, p)) = self.round_parents.iter().find(|&&(h, _)| h == start_hash) {
@@ -542,6 +985,8 @@ impl BlockDownloader {
                         self.reset_to_block(&best_hash, best);
                     } else {
                         let n = start - cmp::min(self.retract_step, start);
+                        // do not step over the limit, the common block may be right above it
+                        let n = floor.map_or(n, |floor| cmp::max(n, floor));
                         if n == 0 {
                             info!("Header not found, bottom line reached, resetting, last imported: {}", self.last_imported_hash);
                             self.reset_to_block(&best_hash, best);
@@ -579,32 +1024,79 @@ impl BlockDownloader {
         self.imported_this_round = None;
     }
 
-    /// Find some headers or blocks to download for a peer.
+    /// Lowest block the search for a common block may go down to, if limited.
+    fn reorg_floor(&self, io: &dyn SyncIo) -> Option<BlockNumber> {
+        match self.block_set {
+            BlockSet::NewBlocks => self.max_reorg_depth.map(|depth| {
+                io.chain()
+                    .chain_info()
+                    .best_block_number
+                    .saturating_sub(depth)
+            }),
+            BlockSet::OldBlocks => None,
+        }
+    }
+
+    /// Give up on the branch of the peers, the common block is deeper below the best block than
+    /// allowed. The download restarts from the best block.
+    fn stop_at_reorg_limit(&mut self, io: &mut dyn SyncIo, start: BlockNumber, start_hash: H256) {
+        let chain_info = io.chain().chain_info();
+        let best = chain_info.best_block_number;
+        warn!(
+            target: "sync",
+            "Peers advertise a branch diverging below #{} ({}), more than {} blocks below the best block #{}. It is not synced unless deeper reorgs are allowed.",
+            start,
+            start_hash,
+            self.max_reorg_depth.unwrap_or_default(),
+            best
+        );
+        self.deep_reorg = Some(DeepReorg {
+            number: start,
+            hash: start_hash,
+            best,
+            peers: self.round_peers.drain().collect(),
+        });
+        self.reset_to_block(&chain_info.best_block_hash, best);
+    }
+
+    /// Find some headers or blocks to download for a peer. With a `route`, bodies and receipts
+    /// preferred for the peer are requested first, any other missing ones if there are none.
     pub fn request_blocks(
         &mut self,
         peer_id: PeerId,
         io: &mut dyn SyncIo,
-        num_active_peers: usize,
+        route: Option<&Rendezvous>,
     ) -> Option<BlockRequest> {
+        self.request_blocks_from(peer_id, io, route, 0)
+    }
+
+    /// Same as `request_blocks`, for a peer which serves only the blocks from `serve_from_block`
+    /// on. The peer is given the part of the download at or above it.
+    pub fn request_blocks_from(
+        &mut self,
+        peer_id: PeerId,
+        io: &mut dyn SyncIo,
+        route: Option<&Rendezvous>,
+        serve_from_block: BlockNumber,
+    ) -> Option<BlockRequest> {
+        // everything downloaded follows the last imported block
+        let floor = if serve_from_block > self.last_imported_block + 1 {
+            serve_from_block
+        } else {
+            0
+        };
         match self.state {
             State::Idle => {
                 self.start_sync_round(io);
                 if self.state == State::ChainHead {
-                    return self.request_blocks(peer_id, io, num_active_peers);
+                    return self.request_blocks_from(peer_id, io, route, serve_from_block);
                 }
             }
+            // subchain heads are found from the last imported block on
+            State::ChainHead if floor > 0 => return None,
             State::ChainHead => {
-                if num_active_peers < MAX_PARALLEL_SUBCHAIN_DOWNLOAD {
-                    // Request subchain headers
-                    trace_sync!(self, "Starting sync with better chain");
-                    // Request MAX_HEADERS_TO_REQUEST - 2 headers apart so that
-                    // MAX_HEADERS_TO_REQUEST would include headers for neighbouring subchains
-                    return Some(BlockRequest::Headers {
-                        start: self.last_imported_hash,
-                        count: SUBCHAIN_SIZE,
-                        skip: (MAX_HEADERS_TO_REQUEST - 2) as u64,
-                    });
-                }
+                trace_sync!(self, "Starting sync with better chain");
+                return self.request_chain_heads(peer_id);
             }
             State::Blocks => {
                 // check to see if we need to download any block bodies first
@@ -616,9 +1108,47 @@ impl BlockDownloader {
                     MAX_BODIES_TO_REQUEST_SMALL
                 };
 
-                let needed_bodies = self
-                    .blocks
-                    .needed_bodies(number_of_bodies_to_request, false);
+                if self.blocks.memory_used() >= self.max_memory {
+                    // Only fetch the blocks the import waits for until the collection drains.
+                    trace_sync!(
+                        self,
+                        "Downloaded block data exceeds {} bytes",
+                        self.max_memory
+                    );
+                    let needed_bodies = self
+                        .blocks
+                        .needed_bodies_at_head(number_of_bodies_to_request, floor);
+                    if !needed_bodies.is_empty() {
+                        return Some(BlockRequest::Bodies {
+                            hashes: needed_bodies,
+                        });
+                    }
+                    let needed_receipts = self
+                        .blocks
+                        .needed_receipts_at_head(MAX_RECEPITS_TO_REQUEST, floor);
+                    if !needed_receipts.is_empty() {
+                        return Some(BlockRequest::Receipts {
+                            hashes: needed_receipts,
+                        });
+                    }
+                    return None;
+                }
+
+                let mut needed_bodies = match route {
+                    Some(route) => self.blocks.needed_bodies_where(
+                        number_of_bodies_to_request,
+                        false,
+                        |h, number| number >= floor && route.is_preferred(h),
+                    ),
+                    None => Vec::new(),
+                };
+                if needed_bodies.is_empty() {
+                    needed_bodies = self.blocks.needed_bodies_where(
+                        number_of_bodies_to_request,
+                        false,
+                        |_, number| number >= floor,
+                    );
+                }
                 if !needed_bodies.is_empty() {
                     return Some(BlockRequest::Bodies {
                         hashes: needed_bodies,
@@ -626,8 +1156,21 @@ impl BlockDownloader {
                 }
 
                 if self.download_receipts {
-                    let needed_receipts =
-                        self.blocks.needed_receipts(MAX_RECEPITS_TO_REQUEST, false);
+                    let mut needed_receipts = match route {
+                        Some(route) => self.blocks.needed_receipts_where(
+                            MAX_RECEPITS_TO_REQUEST,
+                            false,
+                            |h, number| number >= floor && route.is_preferred(h),
+                        ),
+                        None => Vec::new(),
+                    };
+                    if needed_receipts.is_empty() {
+                        needed_receipts = self.blocks.needed_receipts_where(
+                            MAX_RECEPITS_TO_REQUEST,
+                            false,
+                            |_, number| number >= floor,
+                        );
+                    }
                     if !needed_receipts.is_empty() {
                         return Some(BlockRequest::Receipts {
                             hashes: needed_receipts,
@@ -635,8 +1178,13 @@ impl BlockDownloader {
                     }
                 }
 
-                // find subchain to download
-                if let Some((h, count)) = self.blocks.needed_headers(MAX_HEADERS_TO_REQUEST, false)
+                // find subchain to download, the number of a head is known once its header is
+                let served = |number: Option<BlockNumber>| {
+                    floor == 0 || number.is_some_and(|number| number >= floor)
+                };
+                if let Some((h, count)) =
+                    self.blocks
+                        .needed_headers_where(MAX_HEADERS_TO_REQUEST, false, served)
                 {
                     return Some(BlockRequest::Headers {
                         start: h,
@@ -650,6 +1198,37 @@ impl BlockDownloader {
         None
     }
 
+    /// Find a segment of subchain heads to request from a peer. While downloading subchain heads,
+    /// this is called again after each request until the peer is asked for as many as allowed.
+    pub fn request_chain_heads(&mut self, peer_id: PeerId) -> Option<BlockRequest> {
+        if self.state != State::ChainHead {
+            return None;
+        }
+        let (last_imported_block, last_imported_hash) =
+            (self.last_imported_block, self.last_imported_hash);
+        let (pipeline, total) = (self.chain_head_pipeline, self.chain_head_requests);
+        let heads = self.subchain_heads.get_or_insert_with(|| {
+            SubchainHeads::new(
+                last_imported_block,
+                last_imported_hash,
+                SUBCHAIN_SIZE,
+                SUBCHAIN_HEADS_PER_REQUEST,
+                (MAX_HEADERS_TO_REQUEST - 1) as u64,
+                pipeline,
+                total,
+            )
+        });
+        let segment = heads.request(peer_id, Instant::now())?;
+        self.round_peers.insert(peer_id);
+        // Request MAX_HEADERS_TO_REQUEST - 2 headers apart so that
+        // MAX_HEADERS_TO_REQUEST would include headers for neighbouring subchains
+        let (count, skip) = (heads.count(segment), (MAX_HEADERS_TO_REQUEST - 2) as u64);
+        Some(match heads.start(segment) {
+            HeadsStart::Hash(start) => BlockRequest::Headers { start, count, skip },
+            HeadsStart::Number(start) => BlockRequest::HeadersByNumber { start, count, skip },
+        })
+    }
+
     /// Checks if there are blocks fully downloaded that can be imported into the blockchain and does the import.
     /// Returns DownloadAction::Reset if it is imported all the the blocks it can and all downloading peers should be reset
     pub fn collect_blocks(
@@ -659,15 +1238,17 @@ impl BlockDownloader {
     ) -> DownloadAction {
         let mut download_action = DownloadAction::None;
         let mut imported = HashSet::new();
-        let blocks = self.blocks.drain();
+        let mut rejected = None;
+        let mut blocks = self.blocks.drain().into_iter();
         let count = blocks.len();
-        for block_and_receipts in blocks {
+        for block_and_receipts in blocks.by_ref() {
             let block = block_and_receipts.block;
             let receipts = block_and_receipts.receipts;
 
             let h = block.header.hash();
             let number = block.header.number();
             let parent = *block.header.parent_hash();
+            let verified_by_queue = receipts.is_none();
 
             if self.target_hash.as_ref() == Some(&h) {
                 self.state = State::Complete;
@@ -679,7 +1260,11 @@ impl BlockDownloader {
             }
 
             let result = if let Some(receipts) = receipts {
-                io.chain().queue_ancient_block(block, receipts)
+                if self.repairs.as_mut().is_some_and(|r| r.remove(&h)) {
+                    io.chain().repair_ancient_block(block, receipts).map(|_| h)
+                } else {
+                    io.chain().queue_ancient_block(block, receipts)
+                }
             } else {
                 io.chain().import_block(block)
             };
@@ -695,6 +1280,10 @@ impl BlockDownloader {
                 }
                 Ok(_) => {
                     trace_sync!(self, "Block queued {:?}", h);
+                    if verified_by_queue {
+                        self.queued_suppliers
+                            .push((h, block_and_receipts.header_peer));
+                    }
                     imported.insert(h);
                     self.block_imported(&h, number, &parent);
                 }
@@ -716,17 +1305,41 @@ impl BlockDownloader {
                 }
                 Err(EthcoreError(EthcoreErrorKind::Queue(QueueErrorKind::Full(limit)), _)) => {
                     debug_sync!(self, "Block import queue full ({}), restarting sync", limit);
+                    self.note_reset(ResetReason::QueueFull);
                     download_action = DownloadAction::Reset;
                     break;
                 }
                 Err(e) => {
                     debug_sync!(self, "Bad block {:?} : {:?}", h, e);
+                    rejected = Some(RejectedBlock {
+                        hash: h,
+                        number,
+                        parent,
+                        peer: block_and_receipts.header_peer,
+                        descendants: Vec::new(),
+                    });
+                    self.note_reset(ResetReason::BadBlock);
                     download_action = DownloadAction::Reset;
                     break;
                 }
             }
         }
+        if let Some(mut rejected) = rejected.filter(|_| self.block_set == BlockSet::NewBlocks) {
+            let mut branch: HashSet<H256> = HashSet::new();
+            branch.insert(rejected.hash);
+            for block_and_receipts in blocks {
+                let header = &block_and_receipts.block.header;
+                if branch.contains(header.parent_hash()) {
+                    branch.insert(header.hash());
+                    rejected.descendants.push(header.hash());
+                }
+            }
+            self.rejected.push(rejected);
+        }
         trace_sync!(self, "Imported {} of {}", imported.len(), count);
+        if !imported.is_empty() {
+            self.round_peers.clear();
+        }
         self.imported_this_round = Some(self.imported_this_round.unwrap_or(0) + imported.len());
 
         if self.blocks.is_empty() {
@@ -766,8 +1379,12 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use blocks::verify_block_data;
     use crypto::publickey::{Generator, Random};
-    use ethcore::{client::TestBlockChainClient, spec::Spec};
+    use ethcore::{
+        client::{EachBlockWith, TestBlockChainClient},
+        spec::Spec,
+    };
     use hash::keccak;
     use parking_lot::RwLock;
     use rlp::{encode_list, RlpStream};
@@ -805,7 +1422,7 @@ mod tests {
         let bytes = stream.out();
         let rlp = Rlp::new(&bytes);
         let expected_hash = headers.first().unwrap().hash();
-        downloader.import_headers(io, &rlp, expected_hash, eip1559_transition)
+        downloader.import_headers(io, 0, &rlp, expected_hash, eip1559_transition)
     }
 
     fn import_headers_ok(
@@ -844,6 +1461,7 @@ mod tests {
 
         match downloader.import_headers(
             &mut io,
+            0,
             &valid_rlp,
             genesis_hash,
             spec.params().eip1559_transition,
@@ -863,6 +1481,7 @@ mod tests {
 
         match downloader.import_headers(
             &mut io,
+            0,
             &invalid_start_block_rlp,
             genesis_hash,
             spec.params().eip1559_transition,
@@ -882,6 +1501,7 @@ mod tests {
 
         match downloader.import_headers(
             &mut io,
+            0,
             &invalid_skip_rlp,
             genesis_hash,
             spec.params().eip1559_transition,
@@ -904,6 +1524,7 @@ mod tests {
         let too_many_rlp = Rlp::new(&rlp_data);
         match downloader.import_headers(
             &mut io,
+            0,
             &too_many_rlp,
             genesis_hash,
             spec.params().eip1559_transition,
@@ -913,6 +1534,37 @@ mod tests {
         };
     }
 
+    #[test]
+    fn headers_linking_below_the_reorg_floor_stop_the_download() {
+        let mut chain = TestBlockChainClient::new();
+        chain.add_blocks(30, EachBlockWith::Nothing);
+        let snapshot_service = TestSnapshotService::new();
+        let queue = RwLock::new(VecDeque::new());
+        let mut io = TestIo::new(&mut chain, &snapshot_service, &queue, None);
+        let best_hash = io.chain().chain_info().best_block_hash;
+
+        let mut import_fork = |parent: BlockNumber| {
+            let parent_hash = io.chain().block_hash(BlockId::Number(parent)).unwrap();
+            let header = dummy_header(parent + 1, parent_hash);
+            let mut downloader = BlockDownloader::new(BlockSet::NewBlocks, &best_hash, 30);
+            downloader.set_max_reorg_depth(Some(10));
+            downloader.state = State::Blocks;
+            downloader.blocks.reset_to(vec![header.hash()]);
+            let result = import_headers(&[header], &mut downloader, &mut io, 0);
+            (result, downloader.take_deep_reorg())
+        };
+
+        // the fork diverges 10 blocks below the best block
+        assert_eq!(import_fork(20), (Ok(DownloadAction::None), None));
+
+        let (result, reorg) = import_fork(19);
+        assert_eq!(result, Ok(DownloadAction::Reset));
+        let reorg = reorg.unwrap();
+        assert_eq!(reorg.number, 19);
+        assert_eq!(reorg.best, 30);
+        assert_eq!(reorg.peers, vec![0]);
+    }
+
     #[test]
     fn import_headers_in_blocks_state() {
         ::env_logger::try_init().ok();
@@ -940,6 +1592,7 @@ mod tests {
 
         match downloader.import_headers(
             &mut io,
+            0,
             &headers_rlp,
             headers[0].hash(),
             eip1559_transition,
@@ -955,6 +1608,7 @@ mod tests {
 
         match downloader.import_headers(
             &mut io,
+            0,
             &headers_rlp,
             headers[0].hash(),
             eip1559_transition,
@@ -970,6 +1624,7 @@ mod tests {
 
         match downloader.import_headers(
             &mut io,
+            0,
             &headers_rlp,
             headers[0].hash(),
             eip1559_transition,
@@ -1033,7 +1688,13 @@ mod tests {
         let rlp_data = encode_list(&headers[0..3]);
         let headers_rlp = Rlp::new(&rlp_data);
         assert!(downloader
-            .import_headers(&mut io, &headers_rlp, headers[0].hash(), eip1559_transition)
+            .import_headers(
+                &mut io,
+                0,
+                &headers_rlp,
+                headers[0].hash(),
+                eip1559_transition
+            )
             .is_ok());
 
         // Import first body successfully.
@@ -1060,20 +1721,275 @@ mod tests {
             )
             .is_ok());
 
-        // Import unexpected third body.
+        // The third body was not requested, it is dropped.
         let mut rlp_data = RlpStream::new_list(1);
         rlp_data.append_raw(&bodies[2], 1);
         let bodies_rlp = Rlp::new(rlp_data.as_raw());
-        match downloader.import_bodies(
-            &bodies_rlp,
-            &[headers[0].hash(), headers[1].hash()],
-            eip1559_transition,
-        ) {
+        assert!(downloader
+            .import_bodies(
+                &bodies_rlp,
+                &[headers[0].hash(), headers[1].hash()],
+                eip1559_transition
+            )
+            .is_ok());
+        assert_eq!(downloader.dropped_bodies, 1);
+        assert_eq!(
+            downloader.blocks.needed_bodies(4, false),
+            vec![headers[2].hash()]
+        );
+
+        // A body matching no header is invalid.
+        let mut rlp_data = RlpStream::new_list(1);
+        rlp_data.append_raw(&bodies[3], 1);
+        let bodies_rlp = Rlp::new(rlp_data.as_raw());
+        match downloader.import_bodies(&bodies_rlp, &[headers[2].hash()], eip1559_transition) {
             Err(BlockDownloaderImportError::Invalid) => (),
             _ => panic!("expected BlockDownloaderImportError"),
         };
     }
 
+    #[test]
+    fn late_bodies_are_attached_by_hash_or_dropped() {
+        ::env_logger::try_init().ok();
+
+        let mut chain = TestBlockChainClient::new();
+        let eip1559_transition = chain.spec.params().eip1559_transition;
+        let snapshot_service = TestSnapshotService::new();
+        let queue = RwLock::new(VecDeque::new());
+        let mut io = TestIo::new(&mut chain, &snapshot_service, &queue, None);
+
+        let mut headers = Vec::with_capacity(3);
+        let mut bodies = Vec::with_capacity(3);
+        let mut parent_hash = H256::zero();
+        for i in 0..3 {
+            let mut txs = RlpStream::new();
+            SignedTransaction::rlp_append_list(&mut txs, &[dummy_signed_tx()]);
+            let txs = txs.out();
+            let mut body = RlpStream::new_list(2);
+            body.append_raw(&txs, 1);
+            body.append_raw(&::rlp::EMPTY_LIST_RLP, 1);
+            bodies.push(body.out());
+
+            let mut header = dummy_header(i, parent_hash);
+            header.set_transactions_root(ordered_trie_root(
+                Rlp::new(&txs).iter().map(|r| r.as_raw()),
+            ));
+            parent_hash = header.hash();
+            headers.push(header);
+        }
+        let hashes: Vec<_> = headers.iter().map(|h| h.hash()).collect();
+        let response = |indices: &[usize]| {
+            let mut rlp = RlpStream::new_list(indices.len());
+            for i in indices {
+                rlp.append_raw(&bodies[*i], 1);
+            }
+            rlp.out()
+        };
+
+        let mut downloader = BlockDownloader::new(BlockSet::OldBlocks, &hashes[0], 0);
+        downloader.state = State::Blocks;
+        downloader.blocks.reset_to(vec![hashes[0]]);
+        import_headers_ok(&headers, &mut downloader, &mut io, eip1559_transition);
+
+        // The request for the first two bodies expires and the peer is asked for an overlapping
+        // range. The late response to the first request is checked against the second one.
+        let first = &hashes[0..2];
+        let second = &hashes[1..3];
+        downloader.clear_body_download(first);
+        assert_eq!(
+            downloader.import_bodies(&Rlp::new(&response(&[0, 1])), second, eip1559_transition),
+            Ok(())
+        );
+        assert_eq!(downloader.dropped_bodies, 1);
+
+        // The response to the second request repeats a body attached already.
+        assert_eq!(
+            downloader.import_bodies(&Rlp::new(&response(&[1, 2])), second, eip1559_transition),
+            Ok(())
+        );
+        assert_eq!(downloader.dropped_bodies, 2);
+
+        // The dropped body is requested again.
+        assert_eq!(downloader.blocks.needed_bodies(3, false), vec![hashes[0]]);
+        assert_eq!(
+            downloader.import_bodies(&Rlp::new(&response(&[0])), first, eip1559_transition),
+            Ok(())
+        );
+
+        let drained = downloader.blocks.drain();
+        assert_eq!(drained.len(), 3);
+        for (block, body) in drained.iter().zip(&bodies) {
+            let block_rlp = Rlp::new(&block.block.bytes);
+            let body = Rlp::new(body);
+            assert_eq!(
+                block_rlp.at(1).unwrap().as_raw(),
+                body.at(0).unwrap().as_raw()
+            );
+            assert_eq!(
+                verify_block_data(
+                    &block.block.header,
+                    &block_rlp.at(1).unwrap(),
+                    &block_rlp.at(2).unwrap(),
+                    None
+                ),
+                Ok(())
+            );
+        }
+    }
+
+    #[test]
+    fn limits_memory_of_downloaded_blocks() {
+        ::env_logger::try_init().ok();
+
+        let mut chain = TestBlockChainClient::new();
+        let eip1559_transition = chain.spec.params().eip1559_transition;
+        let snapshot_service = TestSnapshotService::new();
+        let queue = RwLock::new(VecDeque::new());
+        let mut io = TestIo::new(&mut chain, &snapshot_service, &queue, None);
+
+        // Blocks with a single large transaction each.
+        let mut headers = Vec::with_capacity(4);
+        let mut bodies = Vec::with_capacity(4);
+        let mut parent_hash = H256::zero();
+        for i in 0..4 {
+            let tx = Transaction {
+                data: vec![0u8; 4096],
+                ..Default::default()
+            };
+            let tx = TypedTransaction::Legacy(tx).sign(Random.generate().secret(), None);
+            let mut rlp_strem = RlpStream::new();
+            SignedTransaction::rlp_append_list(&mut rlp_strem, &[tx]);
+            let txs = rlp_strem.drain();
+            let tx_root = ordered_trie_root(Rlp::new(&txs).iter().map(|r| r.as_raw()));
+
+            let mut rlp = RlpStream::new_list(2);
+            rlp.append_raw(&txs, 1);
+            rlp.append_raw(&::rlp::EMPTY_LIST_RLP, 1);
+            bodies.push(rlp.out());
+
+            let mut header = dummy_header(i, parent_hash);
+            header.set_transactions_root(tx_root);
+            header.set_uncles_hash(keccak(&::rlp::EMPTY_LIST_RLP));
+            parent_hash = header.hash();
+            headers.push(header);
+        }
+        let hashes: Vec<_> = headers.iter().map(|h| h.hash()).collect();
+        let body_size = |body: &[u8]| Rlp::new(body).payload_info().unwrap().value_len;
+        let item_size = |downloader: &BlockDownloader, name: &str| {
+            let mut sizes = BTreeMap::new();
+            downloader.get_sizes(&mut sizes);
+            sizes[&format!("new_blocks_{}", name)]
+        };
+        let import_body = |downloader: &mut BlockDownloader, i: usize| {
+            let mut rlp_data = RlpStream::new_list(1);
+            rlp_data.append_raw(&bodies[i], 1);
+            downloader
+                .import_bodies(&Rlp::new(rlp_data.as_raw()), &hashes, eip1559_transition)
+                .unwrap();
+        };
+
+        let mut downloader = BlockDownloader::new(BlockSet::NewBlocks, &hashes[0], 0);
+        downloader.state = State::Blocks;
+        downloader.blocks.reset_to(vec![hashes[0]]);
+        import_headers_ok(&headers, &mut downloader, &mut io, eip1559_transition);
+
+        let headers_bytes: usize = headers.iter().map(|h| ::rlp::encode(h).len()).sum();
+        assert_eq!(item_size(&downloader, "headers_bytes"), headers_bytes);
+        assert_eq!(item_size(&downloader, "bodies_bytes"), 0);
+
+        // Below the limit all bodies are requested.
+        downloader.set_max_memory(headers_bytes + body_size(&bodies[0]));
+        match downloader.request_blocks(0, &mut io, None) {
+            Some(BlockRequest::Bodies { hashes: requested }) => assert_eq!(requested, hashes),
+            _ => panic!("expected bodies request"),
+        }
+
+        // Bodies which can not be imported yet push the collection above the limit.
+        import_body(&mut downloader, 1);
+        import_body(&mut downloader, 2);
+        assert_eq!(
+            item_size(&downloader, "bodies_bytes"),
+            body_size(&bodies[1]) + body_size(&bodies[2])
+        );
+
+        // The last body is not requested again while the first one is still being downloaded.
+        downloader.clear_body_download(&hashes[3..]);
+        assert!(downloader.request_blocks(0, &mut io, None).is_none());
+
+        // The body the import waits for is still requested.
+        downloader.clear_body_download(&hashes[..1]);
+        match downloader.request_blocks(0, &mut io, None) {
+            Some(BlockRequest::Bodies { hashes: requested }) => {
+                assert_eq!(requested, &hashes[..1])
+            }
+            _ => panic!("expected bodies request"),
+        }
+
+        // Importing the drained blocks resumes the download.
+        import_body(&mut downloader, 0);
+        assert_eq!(
+            downloader.collect_blocks(&mut io, false),
+            DownloadAction::None
+        );
+        assert_eq!(
+            item_size(&downloader, "headers_bytes"),
+            ::rlp::encode(&headers[3]).len()
+        );
+        assert_eq!(item_size(&downloader, "bodies_bytes"), 0);
+        match downloader.request_blocks(0, &mut io, None) {
+            Some(BlockRequest::Bodies { hashes: requested }) => {
+                assert_eq!(requested, &hashes[3..])
+            }
+            _ => panic!("expected bodies request"),
+        }
+    }
+
+    #[test]
+    fn peer_serving_from_a_block_is_given_the_blocks_above_it() {
+        ::env_logger::try_init().ok();
+
+        let mut chain = TestBlockChainClient::new();
+        let eip1559_transition = chain.spec.params().eip1559_transition;
+        let snapshot_service = TestSnapshotService::new();
+        let queue = RwLock::new(VecDeque::new());
+        let mut io = TestIo::new(&mut chain, &snapshot_service, &queue, None);
+
+        let mut headers = Vec::with_capacity(4);
+        let mut parent_hash = H256::zero();
+        for i in 0..4 {
+            let mut header = dummy_header(i, parent_hash);
+            header.set_transactions_root(H256::from_low_u64_be(i + 1));
+            header.set_uncles_hash(keccak(&::rlp::EMPTY_LIST_RLP));
+            parent_hash = header.hash();
+            headers.push(header);
+        }
+        let hashes: Vec<_> = headers.iter().map(|h| h.hash()).collect();
+
+        let mut downloader = BlockDownloader::new(BlockSet::NewBlocks, &hashes[0], 0);
+        // subchain heads are not requested from a peer serving from above the last import
+        downloader.state = State::ChainHead;
+        assert!(downloader
+            .request_blocks_from(0, &mut io, None, 2)
+            .is_none());
+
+        downloader.state = State::Blocks;
+        downloader.blocks.reset_to(vec![hashes[0]]);
+        import_headers_ok(&headers, &mut downloader, &mut io, eip1559_transition);
+
+        match downloader.request_blocks_from(0, &mut io, None, 2) {
+            Some(BlockRequest::Bodies { hashes: requested }) => {
+                assert_eq!(requested, &hashes[2..])
+            }
+            _ => panic!("expected bodies request"),
+        }
+        match downloader.request_blocks_from(1, &mut io, None, 1) {
+            Some(BlockRequest::Bodies { hashes: requested }) => {
+                assert_eq!(requested, &hashes[..2])
+            }
+            _ => panic!("expected bodies request"),
+        }
+    }
+
     #[test]
     fn import_receipts() {
         ::env_logger::try_init().ok();
@@ -1123,7 +2039,13 @@ mod tests {
         let rlp_data = encode_list(&headers[0..3]);
         let headers_rlp = Rlp::new(&rlp_data);
         assert!(downloader
-            .import_headers(&mut io, &headers_rlp, headers[0].hash(), eip1559_transition)
+            .import_headers(
+                &mut io,
+                0,
+                &headers_rlp,
+                headers[0].hash(),
+                eip1559_transition
+            )
             .is_ok());
 
         // Import second and third receipts successfully.
@@ -1135,6 +2057,15 @@ mod tests {
             .import_receipts(&receipts_rlp, &[headers[1].hash(), headers[2].hash()])
             .is_ok());
 
+        // Receipts attached before are dropped when they arrive again.
+        let mut rlp_data = RlpStream::new_list(1);
+        rlp_data.append_raw(&receipts[1], 1);
+        let receipts_rlp = Rlp::new(rlp_data.as_raw());
+        assert!(downloader
+            .import_receipts(&receipts_rlp, &[headers[1].hash(), headers[2].hash()])
+            .is_ok());
+        assert_eq!(downloader.dropped_receipts, 1);
+
         // Import unexpected fourth receipt.
         let mut rlp_data = RlpStream::new_list(1);
         rlp_data.append_raw(&receipts[3], 1);
@@ -1149,6 +2080,317 @@ mod tests {
         };
     }
 
+    #[test]
+    fn reject_altered_ancient_body_and_request_it_again() {
+        ::env_logger::try_init().ok();
+
+        let mut chain = TestBlockChainClient::new();
+        let eip1559_transition = chain.spec.params().eip1559_transition;
+        let snapshot_service = TestSnapshotService::new();
+        let queue = RwLock::new(VecDeque::new());
+        let mut io = TestIo::new(&mut chain, &snapshot_service, &queue, None);
+
+        let mut headers = Vec::with_capacity(2);
+        let mut parent_hash = H256::zero();
+        for i in 0..2 {
+            let mut txs = RlpStream::new();
+            SignedTransaction::rlp_append_list(&mut txs, &[dummy_signed_tx()]);
+            let txs = txs.out();
+            let mut header = dummy_header(i, parent_hash);
+            header.set_transactions_root(ordered_trie_root(
+                Rlp::new(&txs).iter().map(|r| r.as_raw()),
+            ));
+            parent_hash = header.hash();
+            headers.push(header);
+        }
+
+        let mut downloader = BlockDownloader::new(BlockSet::OldBlocks, &headers[0].hash(), 0);
+        downloader.state = State::Blocks;
+        downloader.blocks.reset_to(vec![headers[0].hash()]);
+        let rlp_data = encode_list(&headers);
+        assert!(downloader
+            .import_headers(
+                &mut io,
+                0,
+                &Rlp::new(&rlp_data),
+                headers[0].hash(),
+                eip1559_transition
+            )
+            .is_ok());
+
+        let hashes = match downloader.request_blocks(1, &mut io, None) {
+            Some(BlockRequest::Bodies { hashes }) => hashes,
+            _ => panic!("expected a block bodies request"),
+        };
+        assert!(hashes.contains(&headers[0].hash()));
+
+        // peer 1 answers with a body whose transaction differs from the one in the header
+        let mut body = RlpStream::new_list(2);
+        SignedTransaction::rlp_append_list(&mut body, &[dummy_signed_tx()]);
+        body.append_raw(&::rlp::EMPTY_LIST_RLP, 1);
+        let mut bodies = RlpStream::new_list(1);
+        bodies.append_raw(&body.out(), 1);
+        assert_eq!(
+            downloader.import_bodies(&Rlp::new(&bodies.out()), &hashes, eip1559_transition),
+            Err(BlockDownloaderImportError::Invalid)
+        );
+
+        // the body is requested again from another peer
+        downloader.clear_body_download(&hashes);
+        match downloader.request_blocks(2, &mut io, None) {
+            Some(BlockRequest::Bodies { hashes: again }) => assert_eq!(again, hashes),
+            _ => panic!("expected a block bodies request"),
+        }
+    }
+
+    // Subchain heads of a chain `count` heads long, starting at the genesis block.
+    fn subchain_heads(spec: &Spec, count: u64) -> Vec<BlockHeader> {
+        let spacing = MAX_HEADERS_TO_REQUEST as u64 - 1;
+        let mut heads = vec![spec.genesis_header()];
+        heads.extend((1..count).map(|i| dummy_header(i * spacing, H256::random())));
+        heads
+    }
+
+    // The heads answering each segment request, every segment but the first starting with the
+    // last head of the segment before.
+    fn heads_segments(heads: &[BlockHeader]) -> Vec<&[BlockHeader]> {
+        let size = SUBCHAIN_HEADS_PER_REQUEST as usize;
+        (0..heads.len().div_ceil(size))
+            .map(|i| &heads[(i * size).saturating_sub(1)..heads.len().min((i + 1) * size)])
+            .collect()
+    }
+
+    fn import_heads(
+        downloader: &mut BlockDownloader,
+        io: &mut dyn SyncIo,
+        peer_id: PeerId,
+        heads: &[BlockHeader],
+        expected_hash: H256,
+    ) -> Result<DownloadAction, BlockDownloaderImportError> {
+        let rlp_data = encode_list(heads);
+        let expected_hash = downloader
+            .chain_head_start(peer_id)
+            .unwrap_or(expected_hash);
+        downloader.import_headers(
+            io,
+            peer_id,
+            &Rlp::new(&rlp_data),
+            expected_hash,
+            BlockNumber::max_value(),
+        )
+    }
+
+    fn subchain_starts(downloader: &mut BlockDownloader) -> Vec<H256> {
+        let mut starts = Vec::new();
+        while let Some((start, _)) = downloader
+            .blocks
+            .needed_headers(MAX_HEADERS_TO_REQUEST, false)
+        {
+            starts.push(start);
+        }
+        starts
+    }
+
+    #[test]
+    fn pipelined_subchain_heads_match_serial_download() {
+        ::env_logger::try_init().ok();
+
+        let spec = Spec::new_test();
+        let genesis_hash = spec.genesis_header().hash();
+        let heads = subchain_heads(&spec, 200);
+        let spacing = MAX_HEADERS_TO_REQUEST as u64 - 1;
+
+        let mut chain = TestBlockChainClient::new();
+        let snapshot_service = TestSnapshotService::new();
+        let queue = RwLock::new(VecDeque::new());
+        let mut io = TestIo::new(&mut chain, &snapshot_service, &queue, None);
+
+        let mut serial = BlockDownloader::new(BlockSet::NewBlocks, &genesis_hash, 0);
+        serial.state = State::ChainHead;
+        assert_eq!(
+            import_heads(&mut serial, &mut io, 1, &heads, genesis_hash),
+            Ok(DownloadAction::Reset)
+        );
+
+        // the peers are asked for two segments each before any of them answers
+        let mut downloader = BlockDownloader::new(BlockSet::NewBlocks, &genesis_hash, 0);
+        downloader.set_chain_head_pipeline(2, MAX_CHAIN_HEAD_REQUESTS);
+        let mut requests = Vec::new();
+        for peer in 1..4 {
+            requests.extend(downloader.request_blocks(peer, &mut io, None));
+            while let Some(request) = downloader.request_chain_heads(peer) {
+                requests.push(request);
+            }
+        }
+        assert_eq!(downloader.state, State::ChainHead);
+        let starts: Vec<_> = requests
+            .iter()
+            .map(|request| match *request {
+                BlockRequest::Headers { start, count, .. } => {
+                    assert_eq!(count, SUBCHAIN_HEADS_PER_REQUEST);
+                    assert_eq!(start, genesis_hash);
+                    0
+                }
+                BlockRequest::HeadersByNumber { start, count, .. } => {
+                    // starting with the last head of the segment before
+                    assert_eq!(count, SUBCHAIN_HEADS_PER_REQUEST + 1);
+                    start
+                }
+                _ => panic!("expected a subchain heads request"),
+            })
+            .collect();
+        let segment = SUBCHAIN_HEADS_PER_REQUEST * spacing;
+        // the third peer duplicates the segments of the first one
+        assert_eq!(
+            starts,
+            vec![
+                0,
+                segment - spacing,
+                2 * segment - spacing,
+                3 * segment - spacing,
+                0,
+                segment - spacing
+            ]
+        );
+
+        // the responses arrive in any order, a duplicate is not counted twice
+        let segments = heads_segments(&heads);
+        for &(peer, index) in &[(2, 3), (1, 1), (3, 1), (1, 0)] {
+            assert_eq!(
+                import_heads(
+                    &mut downloader,
+                    &mut io,
+                    peer,
+                    segments[index],
+                    genesis_hash
+                ),
+                Ok(DownloadAction::None)
+            );
+            assert_eq!(downloader.state, State::ChainHead);
+        }
+        assert_eq!(
+            import_heads(&mut downloader, &mut io, 2, segments[2], genesis_hash),
+            Ok(DownloadAction::Reset)
+        );
+        assert_eq!(downloader.state, State::Blocks);
+
+        assert_eq!(downloader.blocks.heads_len(), serial.blocks.heads_len());
+        assert_eq!(
+            subchain_starts(&mut downloader),
+            subchain_starts(&mut serial)
+        );
+    }
+
+    #[test]
+    fn subchain_heads_of_different_forks_are_requested_again() {
+        ::env_logger::try_init().ok();
+
+        let spec = Spec::new_test();
+        let genesis_hash = spec.genesis_header().hash();
+        let heads = subchain_heads(&spec, 200);
+        let segments = heads_segments(&heads);
+
+        let mut chain = TestBlockChainClient::new();
+        let snapshot_service = TestSnapshotService::new();
+        let queue = RwLock::new(VecDeque::new());
+        let mut io = TestIo::new(&mut chain, &snapshot_service, &queue, None);
+
+        let mut downloader = BlockDownloader::new(BlockSet::NewBlocks, &genesis_hash, 0);
+        downloader.set_chain_head_pipeline(2, MAX_CHAIN_HEAD_REQUESTS);
+        for peer in 1..3 {
+            downloader.request_blocks(peer, &mut io, None);
+            while downloader.request_chain_heads(peer).is_some() {}
+        }
+
+        assert_eq!(
+            import_heads(&mut downloader, &mut io, 1, segments[0], genesis_hash),
+            Ok(DownloadAction::None)
+        );
+        // the second segment of peer 1 is of another fork
+        let spacing = MAX_HEADERS_TO_REQUEST as u64 - 1;
+        let fork: Vec<_> = (0..SUBCHAIN_HEADS_PER_REQUEST + 1)
+            .map(|i| {
+                dummy_header(
+                    (SUBCHAIN_HEADS_PER_REQUEST - 1 + i) * spacing,
+                    H256::random(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            import_heads(&mut downloader, &mut io, 1, &fork, genesis_hash),
+            Ok(DownloadAction::None)
+        );
+        assert_eq!(downloader.state, State::ChainHead);
+
+        // both segments are requested again
+        match downloader.request_chain_heads(3) {
+            Some(BlockRequest::Headers { start, .. }) => assert_eq!(start, genesis_hash),
+            _ => panic!("expected the first segment to be requested again"),
+        }
+        match downloader.request_chain_heads(3) {
+            Some(BlockRequest::HeadersByNumber { start, .. }) => {
+                assert_eq!(start, (SUBCHAIN_HEADS_PER_REQUEST - 1) * spacing)
+            }
+            _ => panic!("expected the second segment to be requested again"),
+        }
+    }
+
+    #[test]
+    fn failed_subchain_heads_request_only_requeues_its_range() {
+        ::env_logger::try_init().ok();
+
+        let spec = Spec::new_test();
+        let genesis_hash = spec.genesis_header().hash();
+
+        let mut chain = TestBlockChainClient::new();
+        let snapshot_service = TestSnapshotService::new();
+        let queue = RwLock::new(VecDeque::new());
+        let mut io = TestIo::new(&mut chain, &snapshot_service, &queue, None);
+
+        let mut downloader = BlockDownloader::new(BlockSet::NewBlocks, &genesis_hash, 0);
+        downloader.set_chain_head_pipeline(2, MAX_CHAIN_HEAD_REQUESTS);
+        for peer in 1..3 {
+            downloader.request_blocks(peer, &mut io, None);
+            downloader.request_chain_heads(peer);
+        }
+        assert!(downloader.request_chain_heads(3).is_some());
+        assert!(downloader.request_chain_heads(3).is_some());
+        downloader.clear_chain_head_requests(3);
+
+        // peer 1 has no heads from the genesis block, its other request stays outstanding
+        assert_eq!(
+            import_heads(&mut downloader, &mut io, 1, &[], genesis_hash),
+            Ok(DownloadAction::None)
+        );
+        assert!(downloader.chain_head_start(1).is_some());
+        match downloader.request_chain_heads(4) {
+            Some(BlockRequest::Headers { start, .. }) => assert_eq!(start, genesis_hash),
+            _ => panic!("expected the first segment to be requested again"),
+        }
+        match downloader.request_chain_heads(4) {
+            Some(BlockRequest::HeadersByNumber { start, .. }) => {
+                assert_eq!(
+                    start,
+                    (SUBCHAIN_HEADS_PER_REQUEST - 1) * (MAX_HEADERS_TO_REQUEST as u64 - 1)
+                )
+            }
+            _ => panic!("expected a duplicate of an outstanding segment"),
+        }
+
+        // a gone peer only gives up its own segments
+        downloader.clear_chain_head_requests(2);
+        assert!(downloader.chain_head_start(2).is_none());
+        let starts: Vec<_> = (0..2)
+            .filter_map(|_| match downloader.request_chain_heads(5) {
+                Some(BlockRequest::HeadersByNumber { start, .. }) => Some(start),
+                _ => None,
+            })
+            .collect();
+        let spacing = MAX_HEADERS_TO_REQUEST as u64 - 1;
+        let segment = SUBCHAIN_HEADS_PER_REQUEST * spacing;
+        assert_eq!(starts, vec![2 * segment - spacing, 3 * segment - spacing]);
+    }
+
     #[test]
     fn reset_after_multiple_sets_of_useless_headers() {
         ::env_logger::try_init().ok();
@@ -1204,6 +2446,94 @@ mod tests {
         assert!(downloader.blocks.is_empty());
     }
 
+    #[test]
+    fn diagnostics_report_heads_awaiting_data_and_resets() {
+        ::env_logger::try_init().ok();
+
+        let mut chain = TestBlockChainClient::new();
+        let eip1559_transition = chain.spec.params().eip1559_transition;
+        let snapshot_service = TestSnapshotService::new();
+        let queue = RwLock::new(VecDeque::new());
+        let mut io = TestIo::new(&mut chain, &snapshot_service, &queue, None);
+
+        let mut headers: Vec<BlockHeader> = Vec::with_capacity(4);
+        for i in 0..4 {
+            let parent_hash = headers.last().map_or_else(H256::zero, |h| h.hash());
+            let mut header = dummy_header(i, parent_hash);
+            header.set_transactions_root(H256::random());
+            headers.push(header);
+        }
+
+        let mut downloader = BlockDownloader::new(BlockSet::NewBlocks, &headers[0].hash(), 0);
+        downloader.state = State::Blocks;
+        downloader
+            .blocks
+            .reset_to(vec![headers[0].hash(), headers[3].hash()]);
+        downloader.blocks.needed_headers(3, false);
+
+        let diagnostics = downloader.diagnostics();
+        assert_eq!(diagnostics.block_set, BlockSet::NewBlocks);
+        assert_eq!(diagnostics.heads_total, 2);
+        assert_eq!(
+            diagnostics.heads,
+            vec![
+                HeadDiagnostics {
+                    hash: headers[0].hash(),
+                    status: HeadStatus::AssignedToPeer(None),
+                },
+                HeadDiagnostics {
+                    hash: headers[3].hash(),
+                    status: HeadStatus::Unassigned,
+                },
+            ]
+        );
+        assert_eq!(diagnostics.awaiting_bodies, 0);
+
+        // The first subchain is downloaded up to the second head, bodies are requested.
+        import_headers_ok(&headers[0..3], &mut downloader, &mut io, eip1559_transition);
+        assert_eq!(downloader.blocks.needed_bodies(3, false).len(), 3);
+
+        let diagnostics = downloader.diagnostics();
+        assert_eq!(
+            diagnostics.heads,
+            vec![
+                HeadDiagnostics {
+                    hash: headers[2].hash(),
+                    status: HeadStatus::DownloadingBodies(None),
+                },
+                HeadDiagnostics {
+                    hash: headers[3].hash(),
+                    status: HeadStatus::Unassigned,
+                },
+            ]
+        );
+        assert_eq!(diagnostics.awaiting_bodies, 3);
+        assert_eq!(diagnostics.awaiting_receipts, 0);
+        assert!(diagnostics.resets.is_empty());
+
+        for _ in 0..MAX_USELESS_HEADERS_PER_ROUND {
+            assert!(
+                import_headers(&headers[2..3], &mut downloader, &mut io, eip1559_transition)
+                    .is_err()
+            );
+        }
+
+        let diagnostics = downloader.diagnostics();
+        assert_eq!(diagnostics.state, State::Idle);
+        assert_eq!(diagnostics.heads_total, 0);
+        assert_eq!(diagnostics.awaiting_bodies, 0);
+        assert_eq!(diagnostics.resets.len(), 1);
+        assert_eq!(diagnostics.resets[0].reason, ResetReason::UselessHeaders);
+        assert_eq!(diagnostics.resets[0].last_imported_block, 0);
+
+        for _ in 0..MAX_RESET_EVENTS {
+            downloader.restart();
+        }
+        let resets = downloader.diagnostics().resets;
+        assert_eq!(resets.len(), MAX_RESET_EVENTS);
+        assert!(resets.iter().all(|r| r.reason == ResetReason::Restart));
+    }
+
     #[test]
     fn dont_reset_after_multiple_sets_of_useless_headers_for_chain_head() {
         ::env_logger::try_init().ok();
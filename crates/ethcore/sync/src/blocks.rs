@@ -18,7 +18,7 @@ use bytes::Bytes;
 use ethcore::verification::queue::kind::blocks::Unverified;
 use ethereum_types::H256;
 use hash::{keccak, KECCAK_EMPTY_LIST_RLP, KECCAK_NULL_RLP};
-use network;
+use network::{self, PeerId};
 use parity_util_mem::MallocSizeOf;
 use rlp::{DecoderError, Rlp, RlpStream};
 use std::collections::{hash_map, BTreeMap, HashMap, HashSet};
@@ -73,6 +73,11 @@ impl SyncBody {
         Ok(result)
     }
 
+    /// Size of the body RLP data held in memory.
+    fn size(&self) -> usize {
+        self.transactions_bytes.len() + self.uncles_bytes.len()
+    }
+
     fn empty_body() -> Self {
         SyncBody {
             transactions_bytes: ::rlp::EMPTY_LIST_RLP.to_vec(),
@@ -90,6 +95,37 @@ struct SyncBlock {
     body: Option<SyncBody>,
     receipts: Option<Bytes>,
     receipts_root: H256,
+    header_peer: PeerId,
+}
+
+impl SyncBlock {
+    fn number(&self) -> BlockNumber {
+        self.header.header.number()
+    }
+
+    /// Size of the header, body and receipts RLP data held in memory.
+    fn sizes(&self) -> (usize, usize, usize) {
+        (
+            self.header.bytes.len(),
+            self.body.as_ref().map_or(0, SyncBody::size),
+            self.receipts.as_ref().map_or(0, Bytes::len),
+        )
+    }
+
+    /// Check if the body and receipts held match the header.
+    fn matches_header(&self) -> bool {
+        let receipts = self.receipts.as_ref().map(|r| Rlp::new(r));
+        match self.body {
+            Some(ref body) => verify_block_data(
+                &self.header.header,
+                &Rlp::new(&body.transactions_bytes),
+                &Rlp::new(&body.uncles_bytes),
+                receipts.as_ref(),
+            )
+            .is_ok(),
+            None => true,
+        }
+    }
 }
 
 fn unverified_from_sync(header: SyncHeader, body: Option<SyncBody>) -> Unverified {
@@ -113,6 +149,66 @@ pub struct BlockAndReceipts {
     pub block: Unverified,
     /// Block receipts RLP list.
     pub receipts: Option<Bytes>,
+    /// Peer which supplied the block header.
+    pub header_peer: PeerId,
+}
+
+/// Part of the block data which does not match the block header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataMismatch {
+    /// Transactions root or uncles hash differ from the header.
+    Body,
+    /// Receipts root differs from the header.
+    Receipts,
+}
+
+/// Check block transactions, uncles and, if given, receipts RLP lists against the header.
+pub fn verify_block_data(
+    header: &BlockHeader,
+    transactions: &Rlp,
+    uncles: &Rlp,
+    receipts: Option<&Rlp>,
+) -> Result<(), DataMismatch> {
+    let body_matches = transactions_root(transactions)
+        .is_ok_and(|root| root == *header.transactions_root())
+        && keccak(uncles.as_raw()) == *header.uncles_hash();
+    if !body_matches {
+        return Err(DataMismatch::Body);
+    }
+    match receipts.map(receipts_root) {
+        Some(Ok(ref root)) if root != header.receipts_root() => Err(DataMismatch::Receipts),
+        Some(Err(_)) => Err(DataMismatch::Receipts),
+        _ => Ok(()),
+    }
+}
+
+fn transactions_root(transactions: &Rlp) -> Result<H256, DecoderError> {
+    let transactions = transactions
+        .iter()
+        .map(|r| {
+            if r.is_list() {
+                Ok(r.as_raw())
+            } else {
+                r.data()
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ordered_trie_root(transactions))
+}
+
+fn receipts_root(receipts: &Rlp) -> Result<H256, DecoderError> {
+    //check receipts data before calculating trie root
+    let receipts = receipts
+        .iter()
+        .map(|r| {
+            if r.is_list() {
+                Ok(r.as_raw())
+            } else {
+                r.data()
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ordered_trie_root(receipts))
 }
 
 /// Used to identify header by transactions and uncles hashes
@@ -122,6 +218,18 @@ struct HeaderId {
     uncles: H256,
 }
 
+impl HeaderId {
+    fn of_body(body: &SyncBody) -> Self {
+        // this list is already decoded and passed validation, for this we are okay to expect proper data
+        let transactions_root = transactions_root(&Rlp::new(&body.transactions_bytes))
+            .expect("Expect raw transaction list to be valid");
+        HeaderId {
+            transactions_root,
+            uncles: keccak(&body.uncles_bytes),
+        }
+    }
+}
+
 /// A collection of blocks and subchain pointers being downloaded. This keeps track of
 /// which headers/bodies need to be downloaded, which are being downloaded and also holds
 /// the downloaded blocks.
@@ -139,6 +247,10 @@ pub struct BlockCollection {
     header_ids: HashMap<HeaderId, H256>,
     /// Used to map receipts root to headers.
     receipt_ids: HashMap<H256, Vec<H256>>,
+    /// Bodies attached this round, to tell late and duplicate bodies from unknown ones.
+    attached_bodies: HashSet<HeaderId>,
+    /// Receipts roots attached this round, to tell late and duplicate receipts from unknown ones.
+    attached_receipts: HashSet<H256>,
     /// First block in `blocks`.
     head: Option<H256>,
     /// Set of block header hashes being downloaded
@@ -147,6 +259,12 @@ pub struct BlockCollection {
     downloading_bodies: HashSet<H256>,
     /// Set of block receipts being downloaded identified by receipt root.
     downloading_receipts: HashSet<H256>,
+    /// Total size of the downloaded headers RLP.
+    headers_bytes: usize,
+    /// Total size of the downloaded bodies RLP.
+    bodies_bytes: usize,
+    /// Total size of the downloaded receipts RLP.
+    receipts_bytes: usize,
 }
 
 impl BlockCollection {
@@ -157,12 +275,17 @@ impl BlockCollection {
             blocks: HashMap::new(),
             header_ids: HashMap::new(),
             receipt_ids: HashMap::new(),
+            attached_bodies: HashSet::new(),
+            attached_receipts: HashSet::new(),
             heads: Vec::new(),
             parents: HashMap::new(),
             head: None,
             downloading_headers: HashSet::new(),
             downloading_bodies: HashSet::new(),
             downloading_receipts: HashSet::new(),
+            headers_bytes: 0,
+            bodies_bytes: 0,
+            receipts_bytes: 0,
         }
     }
 
@@ -172,11 +295,16 @@ impl BlockCollection {
         self.parents.clear();
         self.header_ids.clear();
         self.receipt_ids.clear();
+        self.attached_bodies.clear();
+        self.attached_receipts.clear();
         self.heads.clear();
         self.head = None;
         self.downloading_headers.clear();
         self.downloading_bodies.clear();
         self.downloading_receipts.clear();
+        self.headers_bytes = 0;
+        self.bodies_bytes = 0;
+        self.receipts_bytes = 0;
     }
 
     /// Reset collection for a new sync round with given subchain block hashes.
@@ -186,44 +314,73 @@ impl BlockCollection {
     }
 
     /// Insert a set of headers into collection and advance subchain head pointers.
-    pub fn insert_headers(&mut self, headers: Vec<SyncHeader>) {
+    pub fn insert_headers(&mut self, headers: Vec<SyncHeader>, peer_id: PeerId) {
         for h in headers {
-            if let Err(e) = self.insert_header(h) {
+            if let Err(e) = self.insert_header(h, peer_id) {
                 trace!(target: "sync", "Ignored invalid header: {e:?}");
             }
         }
         self.update_heads();
     }
 
-    /// Insert a collection of block bodies for previously downloaded headers.
-    pub fn insert_bodies(&mut self, bodies: Vec<SyncBody>) -> Vec<H256> {
-        bodies
-            .into_iter()
-            .filter_map(|b| {
-                self.insert_body(b)
-                    .map_err(|e| trace!(target: "sync", "Ignored invalid body: {e:?}"))
-                    .ok()
-            })
-            .collect()
+    /// Insert a collection of block bodies for previously downloaded headers. A body is attached
+    /// to the header with the same transactions root and uncles hash, if that block is one of
+    /// `requested`. Bodies of other blocks and bodies attached before are dropped.
+    /// Returns the hashes of the blocks which got a body and the number of dropped bodies.
+    pub fn insert_bodies(
+        &mut self,
+        bodies: Vec<SyncBody>,
+        requested: &[H256],
+    ) -> (Vec<H256>, usize) {
+        let mut inserted = Vec::with_capacity(bodies.len());
+        let mut dropped = 0;
+        for body in bodies {
+            match self.insert_body(body, requested) {
+                Ok(Some(hash)) => inserted.push(hash),
+                Ok(None) => dropped += 1,
+                Err(e) => trace!(target: "sync", "Ignored invalid body: {e:?}"),
+            }
+        }
+        (inserted, dropped)
     }
 
-    /// Insert a collection of block receipts for previously downloaded headers.
-    pub fn insert_receipts(&mut self, receipts: Vec<Bytes>) -> Vec<Vec<H256>> {
+    /// Insert a collection of block receipts for previously downloaded headers. Receipts are
+    /// attached to the headers with the same receipts root, if one of these blocks is one of
+    /// `requested`. Receipts of other blocks and receipts attached before are dropped.
+    /// Returns the hashes of the blocks which got receipts and the number of dropped receipts.
+    pub fn insert_receipts(
+        &mut self,
+        receipts: Vec<Bytes>,
+        requested: &[H256],
+    ) -> (Vec<Vec<H256>>, usize) {
         if !self.need_receipts {
-            return Vec::new();
+            return (Vec::new(), 0);
         }
-        receipts
-            .into_iter()
-            .filter_map(|r| {
-                self.insert_receipt(r)
-                    .map_err(|e| trace!(target: "sync", "Ignored invalid receipt: {e:?}"))
-                    .ok()
-            })
-            .collect()
+        let mut inserted = Vec::with_capacity(receipts.len());
+        let mut dropped = 0;
+        for r in receipts {
+            match self.insert_receipt(r, requested) {
+                Ok(Some(hashes)) => inserted.push(hashes),
+                Ok(None) => dropped += 1,
+                Err(e) => trace!(target: "sync", "Ignored invalid receipt: {e:?}"),
+            }
+        }
+        (inserted, dropped)
     }
 
     /// Returns a set of block hashes that require a body download. The returned set is marked as being downloaded.
-    pub fn needed_bodies(&mut self, count: usize, _ignore_downloading: bool) -> Vec<H256> {
+    pub fn needed_bodies(&mut self, count: usize, ignore_downloading: bool) -> Vec<H256> {
+        self.needed_bodies_where(count, ignore_downloading, |_, _| true)
+    }
+
+    /// Same as `needed_bodies`, but only returns the blocks matching `filter`, given their hash
+    /// and number.
+    pub fn needed_bodies_where<F: Fn(&H256, BlockNumber) -> bool>(
+        &mut self,
+        count: usize,
+        _ignore_downloading: bool,
+        filter: F,
+    ) -> Vec<H256> {
         if self.head.is_none() {
             return Vec::new();
         }
@@ -234,7 +391,9 @@ impl BlockCollection {
             if let Some(head) = head {
                 match self.blocks.get(&head) {
                     Some(block)
-                        if block.body.is_none() && !self.downloading_bodies.contains(&head) =>
+                        if block.body.is_none()
+                            && !self.downloading_bodies.contains(&head)
+                            && filter(&head, block.number()) =>
                     {
                         self.downloading_bodies.insert(head);
                         needed_bodies.push(head);
@@ -247,7 +406,9 @@ impl BlockCollection {
             if needed_bodies.len() >= count {
                 break;
             }
-            if !self.downloading_bodies.contains(h) {
+            if !self.downloading_bodies.contains(h)
+                && self.blocks.get(h).is_some_and(|b| filter(h, b.number()))
+            {
                 needed_bodies.push(*h);
                 self.downloading_bodies.insert(*h);
             }
@@ -256,7 +417,18 @@ impl BlockCollection {
     }
 
     /// Returns a set of block hashes that require a receipt download. The returned set is marked as being downloaded.
-    pub fn needed_receipts(&mut self, count: usize, _ignore_downloading: bool) -> Vec<H256> {
+    pub fn needed_receipts(&mut self, count: usize, ignore_downloading: bool) -> Vec<H256> {
+        self.needed_receipts_where(count, ignore_downloading, |_, _| true)
+    }
+
+    /// Same as `needed_receipts`, but only returns the blocks matching `filter`, given their
+    /// hash and number.
+    pub fn needed_receipts_where<F: Fn(&H256, BlockNumber) -> bool>(
+        &mut self,
+        count: usize,
+        _ignore_downloading: bool,
+        filter: F,
+    ) -> Vec<H256> {
         if self.head.is_none() || !self.need_receipts {
             return Vec::new();
         }
@@ -268,6 +440,7 @@ impl BlockCollection {
                 if let Some(block) = self.blocks.get(&head) {
                     if block.receipts.is_none()
                         && !self.downloading_receipts.contains(&block.receipts_root)
+                        && filter(&head, block.number())
                     {
                         self.downloading_receipts.insert(block.receipts_root);
                         needed_receipts.push(head);
@@ -284,7 +457,9 @@ impl BlockCollection {
             if needed_receipts.len() >= count {
                 break;
             }
-            if !self.downloading_receipts.contains(root) {
+            if !self.downloading_receipts.contains(root)
+                && self.blocks.get(&h).is_some_and(|b| filter(&h, b.number()))
+            {
                 needed_receipts.push(h);
                 self.downloading_receipts.insert(*root);
             }
@@ -292,17 +467,83 @@ impl BlockCollection {
         needed_receipts
     }
 
+    /// Returns a set of block hashes that require a body download out of the blocks the import
+    /// waits for, i.e. the ones following the import head up to the first block with a body,
+    /// skipping the ones below `from`. The returned set is marked as being downloaded.
+    pub fn needed_bodies_at_head(&mut self, count: usize, from: BlockNumber) -> Vec<H256> {
+        let needed_bodies: Vec<H256> = self
+            .missing_at_head(count, |block| {
+                block.body.is_none() && block.number() >= from
+            })
+            .into_iter()
+            .filter(|h| !self.downloading_bodies.contains(h))
+            .collect();
+        self.downloading_bodies
+            .extend(needed_bodies.iter().cloned());
+        needed_bodies
+    }
+
+    /// Returns a set of block hashes that require a receipt download out of the blocks the import
+    /// waits for, i.e. the ones following the import head up to the first block with receipts,
+    /// skipping the ones below `from`. The returned set is marked as being downloaded.
+    pub fn needed_receipts_at_head(&mut self, count: usize, from: BlockNumber) -> Vec<H256> {
+        if !self.need_receipts {
+            return Vec::new();
+        }
+        let mut needed_receipts = Vec::new();
+        for h in self.missing_at_head(count, |block| {
+            block.receipts.is_none() && block.number() >= from
+        }) {
+            let root = self.blocks[&h].receipts_root;
+            if self.downloading_receipts.insert(root) {
+                needed_receipts.push(h);
+            }
+        }
+        needed_receipts
+    }
+
+    // Up to `count` blocks following the import head up to the first one not `missing` some data.
+    fn missing_at_head<F: Fn(&SyncBlock) -> bool>(&self, count: usize, missing: F) -> Vec<H256> {
+        let mut result = Vec::new();
+        let mut head = self.head;
+        while let Some(h) = head.and_then(|h| self.parents.get(&h).cloned()) {
+            if result.len() >= count {
+                break;
+            }
+            match self.blocks.get(&h) {
+                Some(block) if missing(block) => result.push(h),
+                Some(_) if result.is_empty() => (),
+                _ => break,
+            }
+            head = Some(h);
+        }
+        result
+    }
+
     /// Returns a set of block hashes that require a header download. The returned set is marked as being downloaded.
     pub fn needed_headers(
         &mut self,
         count: usize,
         ignore_downloading: bool,
+    ) -> Option<(H256, usize)> {
+        self.needed_headers_where(count, ignore_downloading, |_| true)
+    }
+
+    /// Same as `needed_headers`, but only for the subchain heads matching `filter`, given their
+    /// number if the header of the head was downloaded.
+    pub fn needed_headers_where<F: Fn(Option<BlockNumber>) -> bool>(
+        &mut self,
+        count: usize,
+        ignore_downloading: bool,
+        filter: F,
     ) -> Option<(H256, usize)> {
         // find subchain to download
         let mut download = None;
         {
             for h in &self.heads {
-                if ignore_downloading || !self.downloading_headers.contains(h) {
+                if (ignore_downloading || !self.downloading_headers.contains(h))
+                    && filter(self.blocks.get(h).map(SyncBlock::number))
+                {
                     self.downloading_headers.insert(*h);
                     download = Some(*h);
                     break;
@@ -352,6 +593,10 @@ impl BlockCollection {
                             if block.body.is_some()
                                 && (!self.need_receipts || block.receipts.is_some())
                             {
+                                let (header, body, receipts) = block.sizes();
+                                self.headers_bytes -= header;
+                                self.bodies_bytes -= body;
+                                self.receipts_bytes -= receipts;
                                 blocks.push(block);
                                 hashes.push(head);
                                 self.head = Some(head);
@@ -368,10 +613,16 @@ impl BlockCollection {
             }
 
             for block in blocks.into_iter() {
+                debug_assert!(
+                    block.matches_header(),
+                    "Block {} data does not match its header",
+                    block.header.header.hash()
+                );
                 let unverified = unverified_from_sync(block.header, block.body);
                 drained.push(BlockAndReceipts {
                     block: unverified,
                     receipts: block.receipts.clone(),
+                    header_peer: block.header_peer,
                 });
             }
         }
@@ -397,6 +648,38 @@ impl BlockCollection {
         self.heads.len()
     }
 
+    /// Subchain heads to download.
+    pub fn heads(&self) -> &[H256] {
+        &self.heads
+    }
+
+    /// Check if the headers following the given block hash are being downloaded.
+    pub fn is_downloading_headers(&self, hash: &H256) -> bool {
+        self.downloading_headers.contains(hash)
+    }
+
+    /// Check if the body of the given block hash is being downloaded.
+    pub fn is_downloading_body(&self, hash: &H256) -> bool {
+        self.downloading_bodies.contains(hash)
+    }
+
+    /// Number of downloaded headers missing their body and missing their receipts.
+    pub fn awaiting_data(&self) -> (usize, usize) {
+        self.blocks
+            .values()
+            .fold((0, 0), |(bodies, receipts), block| {
+                (
+                    bodies + block.body.is_none() as usize,
+                    receipts + (self.need_receipts && block.receipts.is_none()) as usize,
+                )
+            })
+    }
+
+    /// Total size of the block data held by the collection.
+    pub fn memory_used(&self) -> usize {
+        self.headers_bytes + self.bodies_bytes + self.receipts_bytes
+    }
+
     /// Return number of items size.
     pub fn get_sizes(&self, sizes: &mut BTreeMap<String, usize>, insert_prefix: &str) {
         sizes.insert(format!("{}{}", insert_prefix, "heads"), self.heads.len());
@@ -417,6 +700,14 @@ impl BlockCollection {
             format!("{}{}", insert_prefix, "downloading_bodies_len"),
             self.downloading_bodies.len(),
         );
+        sizes.insert(
+            format!("{}{}", insert_prefix, "headers_bytes"),
+            self.headers_bytes,
+        );
+        sizes.insert(
+            format!("{}{}", insert_prefix, "bodies_bytes"),
+            self.bodies_bytes,
+        );
 
         if self.need_receipts {
             sizes.insert(
@@ -427,6 +718,10 @@ impl BlockCollection {
                 format!("{}{}", insert_prefix, "receipt_ids_len"),
                 self.receipt_ids.len(),
             );
+            sizes.insert(
+                format!("{}{}", insert_prefix, "receipts_bytes"),
+                self.receipts_bytes,
+            );
         }
     }
 
@@ -435,22 +730,23 @@ impl BlockCollection {
         self.downloading_headers.contains(hash) || self.downloading_bodies.contains(hash)
     }
 
-    fn insert_body(&mut self, body: SyncBody) -> Result<H256, network::Error> {
-        let header_id = {
-            let tx_root = ordered_trie_root(Rlp::new(&body.transactions_bytes).iter().map(|r| {
-                if r.is_list() {
-                    r.as_raw()
-                } else {
-                    // this list is already decoded and passed validation, for this we are okay to expect proper data
-                    r.data().expect("Expect raw transaction list to be valid")
-                }
-            }));
-            let uncles = keccak(&body.uncles_bytes);
-            HeaderId {
-                transactions_root: tx_root,
-                uncles,
+    fn insert_body(
+        &mut self,
+        body: SyncBody,
+        requested: &[H256],
+    ) -> Result<Option<H256>, network::Error> {
+        let header_id = HeaderId::of_body(&body);
+        match self.header_ids.get(&header_id) {
+            Some(h) if !requested.contains(h) => {
+                trace!(target: "sync", "Dropped body of block {h}, it was not requested");
+                return Ok(None);
             }
-        };
+            None if self.attached_bodies.contains(&header_id) => {
+                trace!(target: "sync", "Dropped duplicate block body. tx_root = {:?}, uncles = {:?}", header_id.transactions_root, header_id.uncles);
+                return Ok(None);
+            }
+            _ => (),
+        }
 
         match self.header_ids.remove(&header_id) {
             Some(h) => {
@@ -458,8 +754,10 @@ impl BlockCollection {
                 match self.blocks.get_mut(&h) {
                     Some(ref mut block) => {
                         trace!(target: "sync", "Got body {h}");
+                        self.bodies_bytes += body.size();
                         block.body = Some(body);
-                        Ok(h)
+                        self.attached_bodies.insert(header_id);
+                        Ok(Some(h))
                     }
                     None => {
                         warn!("Got body with no header {h}");
@@ -474,30 +772,35 @@ impl BlockCollection {
         }
     }
 
-    fn insert_receipt(&mut self, r: Bytes) -> Result<Vec<H256>, network::Error> {
-        let receipt_root = {
-            let receipts = Rlp::new(&r);
-            //check receipts data before calculating trie root
-            let mut temp_receipts: Vec<&[u8]> = Vec::new();
-            for receipt_byte in receipts.iter() {
-                if receipt_byte.is_list() {
-                    temp_receipts.push(receipt_byte.as_raw())
-                } else {
-                    temp_receipts.push(receipt_byte.data().map_err(network::ErrorKind::Rlp)?);
-                }
+    fn insert_receipt(
+        &mut self,
+        r: Bytes,
+        requested: &[H256],
+    ) -> Result<Option<Vec<H256>>, network::Error> {
+        // calculate trie root and use it as hash
+        let receipt_root = receipts_root(&Rlp::new(&r)).map_err(network::ErrorKind::Rlp)?;
+        match self.receipt_ids.get(&receipt_root) {
+            Some(hashes) if !hashes.iter().any(|h| requested.contains(h)) => {
+                trace!(target: "sync", "Dropped receipt {receipt_root:?}, it was not requested");
+                return Ok(None);
+            }
+            None if self.attached_receipts.contains(&receipt_root) => {
+                trace!(target: "sync", "Dropped duplicate block receipt {receipt_root:?}");
+                return Ok(None);
             }
+            _ => (),
+        }
 
-            // calculate trie root and use it as hash
-            ordered_trie_root(temp_receipts.iter())
-        };
         self.downloading_receipts.remove(&receipt_root);
         match self.receipt_ids.entry(receipt_root) {
             hash_map::Entry::Occupied(entry) => {
+                self.attached_receipts.insert(receipt_root);
                 let block_hashes = entry.remove();
                 for h in block_hashes.iter() {
                     match self.blocks.get_mut(h) {
                         Some(ref mut block) => {
                             trace!(target: "sync", "Got receipt {h}");
+                            self.receipts_bytes += r.len();
                             block.receipts = Some(r.clone());
                         }
                         None => {
@@ -506,7 +809,7 @@ impl BlockCollection {
                         }
                     }
                 }
-                Ok(block_hashes)
+                Ok(Some(block_hashes))
             }
             hash_map::Entry::Vacant(_) => {
                 trace!(target: "sync", "Ignored unknown/stale block receipt {receipt_root:?}");
@@ -515,7 +818,7 @@ impl BlockCollection {
         }
     }
 
-    fn insert_header(&mut self, info: SyncHeader) -> Result<H256, DecoderError> {
+    fn insert_header(&mut self, info: SyncHeader, peer_id: PeerId) -> Result<H256, DecoderError> {
         let hash = info.header.hash();
         if self.blocks.contains_key(&hash) {
             return Ok(hash);
@@ -571,8 +874,13 @@ impl BlockCollection {
             body,
             receipts,
             receipts_root,
+            header_peer: peer_id,
         };
 
+        let (header, body, receipts) = block.sizes();
+        self.headers_bytes += header;
+        self.bodies_bytes += body;
+        self.receipts_bytes += receipts;
         self.blocks.insert(hash, block);
         trace!(target: "sync", "New header: {hash:x}");
         Ok(hash)
@@ -692,7 +1000,7 @@ mod test {
         assert_eq!(bc.downloading_headers.len(), 1);
         assert!(bc.drain().is_empty());
 
-        bc.insert_headers(headers[0..6].iter().map(Clone::clone).collect());
+        bc.insert_headers(headers[0..6].iter().map(Clone::clone).collect(), 0);
         assert_eq!(hashes[5], bc.heads[0]);
         for h in &hashes[0..6] {
             bc.clear_header_download(h)
@@ -718,9 +1026,9 @@ mod test {
         assert_eq!(hashes[5], h);
         let (h, _) = bc.needed_headers(6, false).unwrap();
         assert_eq!(hashes[20], h);
-        bc.insert_headers(headers[10..16].iter().map(Clone::clone).collect());
+        bc.insert_headers(headers[10..16].iter().map(Clone::clone).collect(), 0);
         assert!(bc.drain().is_empty());
-        bc.insert_headers(headers[5..10].iter().map(Clone::clone).collect());
+        bc.insert_headers(headers[5..10].iter().map(Clone::clone).collect(), 0);
         assert_eq!(
             bc.drain().into_iter().map(|b| b.block).collect::<Vec<_>>(),
             blocks[6..16]
@@ -734,7 +1042,7 @@ mod test {
 
         assert_eq!(hashes[15], bc.heads[0]);
 
-        bc.insert_headers(headers[15..].iter().map(Clone::clone).collect());
+        bc.insert_headers(headers[15..].iter().map(Clone::clone).collect(), 0);
         bc.drain();
         assert!(bc.is_empty());
     }
@@ -772,11 +1080,11 @@ mod test {
             .collect();
         bc.reset_to(heads);
 
-        bc.insert_headers(headers[2..22].iter().map(Clone::clone).collect());
+        bc.insert_headers(headers[2..22].iter().map(Clone::clone).collect(), 0);
         assert_eq!(hashes[0], bc.heads[0]);
         assert_eq!(hashes[21], bc.heads[1]);
         assert!(bc.head.is_none());
-        bc.insert_headers(headers[0..2].iter().map(Clone::clone).collect());
+        bc.insert_headers(headers[0..2].iter().map(Clone::clone).collect(), 0);
         assert!(bc.head.is_some());
         assert_eq!(hashes[21], bc.heads[0]);
     }
@@ -814,9 +1122,9 @@ mod test {
             .collect();
         bc.reset_to(heads);
 
-        bc.insert_headers(headers[1..2].iter().map(Clone::clone).collect());
+        bc.insert_headers(headers[1..2].iter().map(Clone::clone).collect(), 0);
         assert!(bc.drain().is_empty());
-        bc.insert_headers(headers[0..1].iter().map(Clone::clone).collect());
+        bc.insert_headers(headers[0..1].iter().map(Clone::clone).collect(), 0);
         assert_eq!(bc.drain().len(), 2);
     }
 }
@@ -0,0 +1,423 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Recovery from peers which keep serving a branch our verification rejects. Once the same
+//! block was rejected often enough and most peers are on its branch, the peers are asked in
+//! turn for their headers at the height of the rejected block until one is found on another
+//! branch. New blocks are only downloaded from such peers until the rejections stop.
+
+use ethereum_types::H256;
+use network::PeerId;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+use types::BlockNumber;
+
+/// Headers requested from a probed peer, backwards from the height of the rejected block.
+pub const BRANCH_PROBE_HEADERS: u64 = 2;
+/// Rejected blocks tracked at most, the ones rejected longest ago are forgotten first.
+const MAX_REJECTED_BLOCKS: usize = 32;
+/// Descendants of the rejected blocks remembered at most.
+const MAX_DESCENDANTS: usize = 4096;
+
+/// Branch a peer is on, relative to the rejected blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerBranch {
+    /// The peer served a rejected block or one of its descendants.
+    Rejected,
+    /// The peer has another block at the height of the rejected one and a higher total
+    /// difficulty than ours.
+    Alternative,
+}
+
+/// Rejections of a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedBlockStats {
+    /// Hash of the block.
+    pub hash: H256,
+    /// Number of the block.
+    pub number: BlockNumber,
+    /// Times the block or one of its descendants was rejected within the window.
+    pub rejections: usize,
+    /// Known descendants of the block.
+    pub descendants: usize,
+}
+
+/// Branch probing state, for diagnostics.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BranchProbeStatus {
+    /// Rejected block most peers are stuck at, new blocks are only downloaded from peers
+    /// on an alternative branch meanwhile.
+    pub stuck_at: Option<H256>,
+    /// Peer asked for its headers at the height of the rejected block, if any.
+    pub probing_peer: Option<PeerId>,
+    /// Peers known to be on the branch of a rejected block.
+    pub peers_on_rejected_branch: usize,
+    /// Peers known to be on an alternative branch.
+    pub peers_on_alternative_branch: usize,
+    /// Probes sent so far.
+    pub probes: usize,
+    /// Probes which found an alternative branch.
+    pub alternatives_found: usize,
+    /// Blocks rejected within the window.
+    pub rejected: Vec<RejectedBlockStats>,
+}
+
+struct Rejection {
+    number: BlockNumber,
+    count: usize,
+    last: Instant,
+}
+
+/// Counts the rejections of blocks and their descendants, and keeps track of the branch
+/// of the peers while they are probed.
+pub struct BranchProber {
+    threshold: usize,
+    window: Duration,
+    interval: Duration,
+    rejected: HashMap<H256, Rejection>,
+    descendants: HashMap<H256, H256>,
+    peers: HashMap<PeerId, PeerBranch>,
+    inconclusive: HashSet<PeerId>,
+    probing: Option<PeerId>,
+    last_probe: Option<Instant>,
+    probes: usize,
+    alternatives_found: usize,
+}
+
+impl BranchProber {
+    /// Consider peers stuck once a block was rejected `threshold` times with less than
+    /// `window` between the rejections, and probe one peer per `interval` then.
+    pub fn new(threshold: usize, window: Duration, interval: Duration) -> Self {
+        BranchProber {
+            threshold,
+            window,
+            interval,
+            rejected: HashMap::new(),
+            descendants: HashMap::new(),
+            peers: HashMap::new(),
+            inconclusive: HashSet::new(),
+            probing: None,
+            last_probe: None,
+            probes: 0,
+            alternatives_found: 0,
+        }
+    }
+
+    /// Account a rejected block supplied by `peer`. A block whose parent was rejected counts
+    /// as another rejection of that block. Returns the rejected block the rejection counts for.
+    pub fn note_rejected(
+        &mut self,
+        now: Instant,
+        hash: H256,
+        number: BlockNumber,
+        parent: &H256,
+        peer: Option<PeerId>,
+    ) -> H256 {
+        self.expire(now);
+        let root = match self.root_of(&hash).or_else(|| self.root_of(parent)) {
+            Some(root) => {
+                self.note_descendant(hash, root);
+                root
+            }
+            None => {
+                if self.rejected.len() == MAX_REJECTED_BLOCKS {
+                    let oldest = self
+                        .rejected
+                        .iter()
+                        .min_by_key(|(_, r)| r.last)
+                        .map(|(hash, _)| *hash);
+                    if let Some(oldest) = oldest {
+                        self.forget(&oldest);
+                    }
+                }
+                self.rejected.insert(
+                    hash,
+                    Rejection {
+                        number,
+                        count: 0,
+                        last: now,
+                    },
+                );
+                hash
+            }
+        };
+        let rejection = self
+            .rejected
+            .get_mut(&root)
+            .expect("root is tracked or inserted above; qed");
+        rejection.count += 1;
+        rejection.last = now;
+        if let Some(peer) = peer {
+            self.note_peer(peer, PeerBranch::Rejected);
+        }
+        root
+    }
+
+    /// Remember `hash` as a descendant of the rejected block `root`.
+    pub fn note_descendant(&mut self, hash: H256, root: H256) {
+        if hash != root && self.descendants.len() < MAX_DESCENDANTS {
+            self.descendants.insert(hash, root);
+        }
+    }
+
+    /// Rejected block `hash` descends from, if it is a rejected block or a known descendant.
+    pub fn root_of(&self, hash: &H256) -> Option<H256> {
+        if self.rejected.contains_key(hash) {
+            Some(*hash)
+        } else {
+            self.descendants.get(hash).cloned()
+        }
+    }
+
+    /// Check if no block was rejected within the window.
+    pub fn is_empty(&self) -> bool {
+        self.rejected.is_empty()
+    }
+
+    /// Record the branch of a peer.
+    pub fn note_peer(&mut self, peer: PeerId, branch: PeerBranch) {
+        self.inconclusive.remove(&peer);
+        self.peers.insert(peer, branch);
+    }
+
+    /// Branch of a peer, if known.
+    pub fn peer_branch(&self, peer: PeerId) -> Option<PeerBranch> {
+        self.peers.get(&peer).cloned()
+    }
+
+    /// Forget a disconnected peer.
+    pub fn on_peer_disconnected(&mut self, peer: PeerId) {
+        self.peers.remove(&peer);
+        self.inconclusive.remove(&peer);
+        if self.probing == Some(peer) {
+            self.probing = None;
+        }
+    }
+
+    /// Rejected block rejected at least `threshold` times, while at least half of the
+    /// `peers` connected are on the branch of a rejected block.
+    pub fn stuck_at(&self, now: Instant, peers: usize) -> Option<H256> {
+        let rejected_peers = self
+            .peers
+            .values()
+            .filter(|b| **b == PeerBranch::Rejected)
+            .count();
+        if peers == 0 || rejected_peers * 2 < peers {
+            return None;
+        }
+        self.rejected
+            .iter()
+            .filter(|(_, r)| r.count >= self.threshold && now < r.last + self.window)
+            .max_by_key(|(_, r)| r.count)
+            .map(|(hash, _)| *hash)
+    }
+
+    /// Number of the rejected block `hash`.
+    pub fn rejected_number(&self, hash: &H256) -> Option<BlockNumber> {
+        self.rejected.get(hash).map(|r| r.number)
+    }
+
+    /// Peer to probe next among `candidates`, if a probe is due. Peers of a known branch are
+    /// skipped, the ones which gave no answer are probed again after all others.
+    pub fn next_probe<I>(&mut self, now: Instant, candidates: I) -> Option<PeerId>
+    where
+        I: IntoIterator<Item = PeerId>,
+    {
+        if self.probing.is_some() || self.last_probe.is_some_and(|at| now < at + self.interval) {
+            return None;
+        }
+        let candidates: Vec<_> = candidates
+            .into_iter()
+            .filter(|peer| !self.peers.contains_key(peer))
+            .collect();
+        let peer = match candidates
+            .iter()
+            .find(|peer| !self.inconclusive.contains(*peer))
+        {
+            Some(peer) => *peer,
+            None => {
+                self.inconclusive.clear();
+                *candidates.first()?
+            }
+        };
+        self.probing = Some(peer);
+        self.last_probe = Some(now);
+        self.probes += 1;
+        Some(peer)
+    }
+
+    /// Peer probed at the moment, if any.
+    pub fn probing(&self) -> Option<PeerId> {
+        self.probing
+    }
+
+    /// Account the answer of a probed peer, `None` if the branch of the peer is still unknown.
+    pub fn note_probe_result(&mut self, peer: PeerId, branch: Option<PeerBranch>) {
+        if self.probing == Some(peer) {
+            self.probing = None;
+        }
+        match branch {
+            Some(branch) => {
+                if branch == PeerBranch::Alternative {
+                    self.alternatives_found += 1;
+                }
+                self.note_peer(peer, branch);
+            }
+            None => {
+                self.inconclusive.insert(peer);
+            }
+        }
+    }
+
+    /// Forget the rejections which are older than the window, along with the branch of the
+    /// peers once no rejection is left.
+    pub fn expire(&mut self, now: Instant) {
+        let window = self.window;
+        let expired: Vec<_> = self
+            .rejected
+            .iter()
+            .filter(|(_, r)| now >= r.last + window)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in expired {
+            self.forget(&hash);
+        }
+        if self.rejected.is_empty() {
+            self.peers.clear();
+            self.inconclusive.clear();
+            self.probing = None;
+        }
+    }
+
+    fn forget(&mut self, hash: &H256) {
+        self.rejected.remove(hash);
+        self.descendants.retain(|_, root| root != hash);
+    }
+
+    /// Probing state, with `stuck_at` as returned by `stuck_at`.
+    pub fn status(&self, stuck_at: Option<H256>) -> BranchProbeStatus {
+        let count = |branch| self.peers.values().filter(|b| **b == branch).count();
+        let mut rejected: Vec<_> = self
+            .rejected
+            .iter()
+            .map(|(hash, r)| RejectedBlockStats {
+                hash: *hash,
+                number: r.number,
+                rejections: r.count,
+                descendants: self.descendants.values().filter(|d| *d == hash).count(),
+            })
+            .collect();
+        rejected.sort_by_key(|r| r.number);
+        BranchProbeStatus {
+            stuck_at,
+            probing_peer: self.probing,
+            peers_on_rejected_branch: count(PeerBranch::Rejected),
+            peers_on_alternative_branch: count(PeerBranch::Alternative),
+            probes: self.probes,
+            alternatives_found: self.alternatives_found,
+            rejected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(600);
+    const INTERVAL: Duration = Duration::from_secs(10);
+
+    fn hash(n: u64) -> H256 {
+        H256::from_low_u64_be(n)
+    }
+
+    #[test]
+    fn descendants_count_for_the_rejected_block() {
+        let mut prober = BranchProber::new(3, WINDOW, INTERVAL);
+        let now = Instant::now();
+        assert_eq!(
+            prober.note_rejected(now, hash(11), 11, &hash(10), Some(1)),
+            hash(11)
+        );
+        assert_eq!(
+            prober.note_rejected(now, hash(12), 12, &hash(11), Some(2)),
+            hash(11)
+        );
+        assert_eq!(
+            prober.note_rejected(now, hash(13), 13, &hash(12), None),
+            hash(11)
+        );
+        assert_eq!(prober.root_of(&hash(13)), Some(hash(11)));
+        assert_eq!(prober.root_of(&hash(10)), None);
+
+        let status = prober.status(None);
+        assert_eq!(status.peers_on_rejected_branch, 2);
+        assert_eq!(
+            status.rejected,
+            vec![RejectedBlockStats {
+                hash: hash(11),
+                number: 11,
+                rejections: 3,
+                descendants: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn stuck_once_most_peers_serve_the_rejected_branch() {
+        let mut prober = BranchProber::new(2, WINDOW, INTERVAL);
+        let now = Instant::now();
+        prober.note_rejected(now, hash(11), 11, &hash(10), Some(1));
+        assert_eq!(prober.stuck_at(now, 2), None);
+        prober.note_rejected(now, hash(11), 11, &hash(10), Some(1));
+        assert_eq!(prober.stuck_at(now, 3), None);
+        assert_eq!(prober.stuck_at(now, 2), Some(hash(11)));
+
+        // rejections stop
+        assert_eq!(prober.stuck_at(now + WINDOW, 2), None);
+        prober.expire(now + WINDOW);
+        assert!(prober.is_empty());
+        assert_eq!(prober.peer_branch(1), None);
+    }
+
+    #[test]
+    fn probes_peers_of_unknown_branch_in_turn() {
+        let mut prober = BranchProber::new(1, WINDOW, INTERVAL);
+        let now = Instant::now();
+        prober.note_rejected(now, hash(11), 11, &hash(10), Some(1));
+
+        assert_eq!(prober.next_probe(now, vec![1, 2, 3]), Some(2));
+        // one probe at a time
+        assert_eq!(prober.next_probe(now + INTERVAL, vec![1, 2, 3]), None);
+        prober.note_probe_result(2, None);
+        assert_eq!(prober.next_probe(now, vec![1, 2, 3]), None);
+        assert_eq!(prober.next_probe(now + INTERVAL, vec![1, 2, 3]), Some(3));
+        prober.note_probe_result(3, Some(PeerBranch::Alternative));
+        // peers without an answer are probed again
+        assert_eq!(
+            prober.next_probe(now + INTERVAL * 2, vec![1, 2, 3]),
+            Some(2)
+        );
+
+        let status = prober.status(None);
+        assert_eq!(status.probing_peer, Some(2));
+        assert_eq!(status.probes, 3);
+        assert_eq!(status.alternatives_found, 1);
+        assert_eq!(prober.peer_branch(3), Some(PeerBranch::Alternative));
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Compressed block bodies and receipts for peers which negotiated `PAR_PROTOCOL_VERSION_3`.
+//!
+//! A compressed packet wraps the complete standard response (including the request id,
+//! if any) as `[dictionary: u8, decompressed_size: P, payload: B]`, where `payload` is a
+//! zstd frame. Payloads are compressed with `TX_PAYLOADS_DICTIONARY`, a zstd dictionary
+//! trained on RLP encoded block bodies and receipts (`zstd --train --maxdict=16384 --dictID=1`).
+//! Frames without dictionary (`0`) are accepted as well, any other id is rejected. A
+//! retrained dictionary must take a new id so that peers still using the old one reject it.
+
+use std::io::Read;
+
+use bytes::Bytes;
+use rlp::{DecoderError, Rlp, RlpStream};
+use zstd;
+
+use super::sync_packet::SyncPacket::{self, *};
+
+/// Responses smaller than this are always sent uncompressed.
+pub const COMPRESSION_THRESHOLD: usize = 4 * 1024;
+/// Largest accepted decompressed payload, same as the RLPx frame limit.
+pub const MAX_DECOMPRESSED_SIZE: usize = (1 << 24) - 1;
+
+const COMPRESSION_LEVEL: i32 = 3;
+const NO_DICTIONARY: u8 = 0;
+const TX_PAYLOADS_DICTIONARY_ID: u8 = 1;
+/// zstd dictionary trained on typical transaction and receipt payloads.
+const TX_PAYLOADS_DICTIONARY: &[u8] = include_bytes!("../res/tx_payloads.zdict");
+
+/// Dictionary registered under `id`, if any.
+fn dictionary(id: u8) -> Option<&'static [u8]> {
+    match id {
+        NO_DICTIONARY => Some(&[]),
+        TX_PAYLOADS_DICTIONARY_ID => Some(TX_PAYLOADS_DICTIONARY),
+        _ => None,
+    }
+}
+
+/// Compressed counterpart of a standard response packet.
+pub fn compressed_packet(packet: SyncPacket) -> Option<SyncPacket> {
+    match packet {
+        BlockBodiesPacket => Some(CompressedBlockBodiesPacket),
+        ReceiptsPacket => Some(CompressedReceiptsPacket),
+        _ => None,
+    }
+}
+
+/// Standard response packet wrapped by a compressed packet.
+pub fn decompressed_packet(packet: SyncPacket) -> Option<SyncPacket> {
+    match packet {
+        CompressedBlockBodiesPacket => Some(BlockBodiesPacket),
+        CompressedReceiptsPacket => Some(ReceiptsPacket),
+        _ => None,
+    }
+}
+
+/// Compress a response payload. Returns `None` if the payload is below
+/// `COMPRESSION_THRESHOLD` or compression does not make it smaller.
+pub fn compress(data: &[u8]) -> Option<Bytes> {
+    if data.len() < COMPRESSION_THRESHOLD {
+        return None;
+    }
+    let compressed =
+        zstd::bulk::Compressor::with_dictionary(COMPRESSION_LEVEL, TX_PAYLOADS_DICTIONARY)
+            .and_then(|mut compressor| compressor.compress(data));
+    let compressed = match compressed {
+        Ok(compressed) => compressed,
+        Err(e) => {
+            debug!(target: "sync", "Error compressing {} bytes: {e:?}", data.len());
+            return None;
+        }
+    };
+    let mut rlp = RlpStream::new_list(3);
+    rlp.append(&TX_PAYLOADS_DICTIONARY_ID);
+    rlp.append(&data.len());
+    rlp.append(&compressed);
+    let out = rlp.out();
+    if out.len() < data.len() {
+        Some(out)
+    } else {
+        None
+    }
+}
+
+/// Decompress a compressed packet. The announced size is checked against
+/// `MAX_DECOMPRESSED_SIZE` before decompression. The payload is streamed and
+/// decompression stops once it expands beyond the announced size, so memory
+/// grows with the actual output rather than with the announcement.
+pub fn decompress(r: &Rlp) -> Result<Bytes, DecoderError> {
+    let dictionary =
+        dictionary(r.val_at(0)?).ok_or(DecoderError::Custom("Unknown compression dictionary"))?;
+    let size: usize = r.val_at(1)?;
+    if size > MAX_DECOMPRESSED_SIZE {
+        return Err(DecoderError::Custom("Decompressed size limit exceeded"));
+    }
+    let mut data = Vec::new();
+    zstd::stream::read::Decoder::with_dictionary(r.at(2)?.data()?, dictionary)
+        .and_then(|decoder| decoder.take(size as u64 + 1).read_to_end(&mut data))
+        .map_err(|_| DecoderError::Custom("Invalid compressed payload"))?;
+    if data.len() != size {
+        return Err(DecoderError::Custom("Decompressed size mismatch"));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(len: usize) -> Bytes {
+        (0..len).map(|i| (i % 7) as u8).collect()
+    }
+
+    fn frame(dictionary: u8, size: usize, data: &[u8]) -> Bytes {
+        let mut rlp = RlpStream::new_list(3);
+        rlp.append(&dictionary);
+        rlp.append(&size);
+        rlp.append(&zstd::bulk::compress(data, COMPRESSION_LEVEL).unwrap());
+        rlp.out()
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = payload(64 * 1024);
+        let compressed = compress(&data).unwrap();
+        assert!(compressed.len() < data.len());
+        assert_eq!(
+            Rlp::new(&compressed).val_at::<u8>(0),
+            Ok(TX_PAYLOADS_DICTIONARY_ID)
+        );
+        assert_eq!(decompress(&Rlp::new(&compressed)).unwrap(), data);
+    }
+
+    #[test]
+    fn payload_needs_the_announced_dictionary() {
+        let data = payload(8 * 1024);
+        let compressed = compress(&data).unwrap();
+        let mut rlp = RlpStream::new_list(3);
+        rlp.append(&NO_DICTIONARY);
+        rlp.append(&data.len());
+        rlp.append(&Rlp::new(&compressed).val_at::<Vec<u8>>(2).unwrap());
+        assert_eq!(
+            decompress(&Rlp::new(&rlp.out())),
+            Err(DecoderError::Custom("Invalid compressed payload"))
+        );
+    }
+
+    #[test]
+    fn accepts_frames_without_dictionary() {
+        let data = payload(8 * 1024);
+        assert_eq!(
+            decompress(&Rlp::new(&frame(NO_DICTIONARY, data.len(), &data))).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn small_payload_is_not_compressed() {
+        assert!(compress(&payload(COMPRESSION_THRESHOLD - 1)).is_none());
+    }
+
+    #[test]
+    fn rejects_oversized_announcement() {
+        let data = payload(1024);
+        let packet = frame(NO_DICTIONARY, MAX_DECOMPRESSED_SIZE + 1, &data);
+        assert_eq!(
+            decompress(&Rlp::new(&packet)),
+            Err(DecoderError::Custom("Decompressed size limit exceeded"))
+        );
+    }
+
+    #[test]
+    fn rejects_payload_expanding_beyond_announcement() {
+        // 16 MiB of zeros compress to a few hundred bytes
+        let bomb = vec![0u8; MAX_DECOMPRESSED_SIZE + 1];
+        let packet = frame(NO_DICTIONARY, 1024, &bomb);
+        assert!(packet.len() < 4096);
+        assert_eq!(
+            decompress(&Rlp::new(&packet)),
+            Err(DecoderError::Custom("Decompressed size mismatch"))
+        );
+    }
+
+    #[test]
+    fn rejects_corrupt_payload() {
+        let mut rlp = RlpStream::new_list(3);
+        rlp.append(&NO_DICTIONARY);
+        rlp.append(&1024usize);
+        rlp.append(&payload(64));
+        assert_eq!(
+            decompress(&Rlp::new(&rlp.out())),
+            Err(DecoderError::Custom("Invalid compressed payload"))
+        );
+    }
+
+    #[test]
+    fn rejects_size_mismatch_and_unknown_dictionary() {
+        let data = payload(1024);
+        assert_eq!(
+            decompress(&Rlp::new(&frame(NO_DICTIONARY, 2048, &data))),
+            Err(DecoderError::Custom("Decompressed size mismatch"))
+        );
+        assert_eq!(
+            decompress(&Rlp::new(&frame(2, 1024, &data))),
+            Err(DecoderError::Custom("Unknown compression dictionary"))
+        );
+    }
+}
@@ -0,0 +1,318 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Bounded queue of requests which could not be served while the client was busy.
+
+use super::{
+    sync_packet::SyncPacket::{self, *},
+    BODIES_TIMEOUT, HEADERS_TIMEOUT, POOLED_TRANSACTIONS_TIMEOUT, RECEIPTS_TIMEOUT,
+    SNAPSHOT_DATA_TIMEOUT, SNAPSHOT_MANIFEST_TIMEOUT,
+};
+use enum_primitive::FromPrimitive;
+use network::PeerId;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// Maximum number of delayed requests of a single peer.
+const MAX_REQUESTS_PER_PEER: usize = 4;
+/// Maximum size of the delayed requests of a single peer.
+const MAX_BYTES_PER_PEER: usize = 256 * 1024;
+/// Maximum number of delayed requests.
+const MAX_REQUESTS: usize = 256;
+/// Maximum size of all delayed requests.
+const MAX_BYTES: usize = 8 * 1024 * 1024;
+/// Maximum number of delayed requests served in one go.
+pub const MAX_REQUESTS_PER_DRAIN: usize = 32;
+
+/// Caps of the delayed requests queue.
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    /// Maximum number of requests of a single peer.
+    requests_per_peer: usize,
+    /// Maximum size of the requests of a single peer.
+    bytes_per_peer: usize,
+    /// Maximum number of requests.
+    requests: usize,
+    /// Maximum size of all requests.
+    bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            requests_per_peer: MAX_REQUESTS_PER_PEER,
+            bytes_per_peer: MAX_BYTES_PER_PEER,
+            requests: MAX_REQUESTS,
+            bytes: MAX_BYTES,
+        }
+    }
+}
+
+// Time after which a peer gives up on a request. We assume peers use the same
+// timeouts as we do, answering later would only waste bandwidth.
+fn serve_timeout(packet_id: u8) -> Duration {
+    match SyncPacket::from_u8(packet_id) {
+        Some(GetBlockBodiesPacket) => BODIES_TIMEOUT,
+        Some(GetReceiptsPacket) => RECEIPTS_TIMEOUT,
+        Some(GetPooledTransactionsPacket) => POOLED_TRANSACTIONS_TIMEOUT,
+        Some(GetSnapshotManifestPacket) => SNAPSHOT_MANIFEST_TIMEOUT,
+        Some(GetSnapshotDataPacket) => SNAPSHOT_DATA_TIMEOUT,
+        _ => HEADERS_TIMEOUT,
+    }
+}
+
+struct Request {
+    peer: PeerId,
+    packet_id: u8,
+    data: Vec<u8>,
+    added: Instant,
+}
+
+#[derive(Default)]
+struct PeerUsage {
+    requests: usize,
+    bytes: usize,
+}
+
+/// Requests, that can not be processed at the moment, oldest first.
+/// At most one request per peer and packet id is kept. When a cap is reached the
+/// oldest request (of the peer, if the peer's cap is reached) is evicted; peers retry
+/// unanswered requests anyway.
+#[derive(Default)]
+pub struct DelayedRequests {
+    limits: Limits,
+    queue: VecDeque<Request>,
+    ids: HashSet<(PeerId, u8)>,
+    peers: HashMap<PeerId, PeerUsage>,
+    bytes: usize,
+    delayed: usize,
+    evicted: usize,
+    expired: usize,
+}
+
+impl DelayedRequests {
+    /// Number of queued requests.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Total number of requests delayed so far.
+    pub fn delayed(&self) -> usize {
+        self.delayed
+    }
+
+    /// Total number of requests evicted to stay within the caps.
+    pub fn evicted(&self) -> usize {
+        self.evicted
+    }
+
+    /// Total number of requests dropped because they were queued for too long.
+    pub fn expired(&self) -> usize {
+        self.expired
+    }
+
+    /// Queue a request. Returns false if it was ignored.
+    pub fn add(&mut self, peer: PeerId, packet_id: u8, data: &[u8]) -> bool {
+        if self.ids.contains(&(peer, packet_id)) {
+            return false;
+        }
+        if data.len() > self.limits.bytes_per_peer || data.len() > self.limits.bytes {
+            debug!(target: "sync", "Delayed request with packet id {packet_id} from peer {peer} is too large: {} bytes", data.len());
+            self.evicted += 1;
+            return false;
+        }
+
+        self.expire(Instant::now());
+        while self.peers.get(&peer).is_some_and(|usage| {
+            usage.requests >= self.limits.requests_per_peer
+                || usage.bytes + data.len() > self.limits.bytes_per_peer
+        }) {
+            let index = self
+                .queue
+                .iter()
+                .position(|r| r.peer == peer)
+                .expect("peer usage is only tracked for queued requests; qed");
+            self.evict(index);
+        }
+        while self.queue.len() >= self.limits.requests
+            || self.bytes + data.len() > self.limits.bytes
+        {
+            self.evict(0);
+        }
+
+        self.ids.insert((peer, packet_id));
+        let usage = self.peers.entry(peer).or_default();
+        usage.requests += 1;
+        usage.bytes += data.len();
+        self.bytes += data.len();
+        self.delayed += 1;
+        self.queue.push_back(Request {
+            peer,
+            packet_id,
+            data: data.to_vec(),
+            added: Instant::now(),
+        });
+        true
+    }
+
+    /// Drop all requests of a peer.
+    pub fn remove_peer(&mut self, peer: PeerId) {
+        if self.peers.contains_key(&peer) {
+            self.retain(|r| r.peer != peer);
+        }
+    }
+
+    /// Take at most `max` of the oldest requests, dropping expired ones.
+    pub fn drain(&mut self, max: usize) -> Vec<(PeerId, u8, Vec<u8>)> {
+        self.expire(Instant::now());
+        let count = self.queue.len().min(max);
+        (0..count)
+            .filter_map(|_| self.remove(0))
+            .map(|r| (r.peer, r.packet_id, r.data))
+            .collect()
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let before = self.queue.len();
+        self.retain(|r| now.saturating_duration_since(r.added) <= serve_timeout(r.packet_id));
+        let expired = before - self.queue.len();
+        if expired > 0 {
+            debug!(target: "sync", "{expired} delayed requests expired");
+            self.expired += expired;
+        }
+    }
+
+    fn evict(&mut self, index: usize) {
+        if let Some(r) = self.remove(index) {
+            debug!(target: "sync", "Delayed request with packet id {} from peer {} evicted", r.packet_id, r.peer);
+            self.evicted += 1;
+        }
+    }
+
+    fn retain<F: Fn(&Request) -> bool>(&mut self, keep: F) {
+        let mut index = 0;
+        while index < self.queue.len() {
+            if keep(&self.queue[index]) {
+                index += 1;
+            } else {
+                self.remove(index);
+            }
+        }
+    }
+
+    fn remove(&mut self, index: usize) -> Option<Request> {
+        let r = self.queue.remove(index)?;
+        self.ids.remove(&(r.peer, r.packet_id));
+        self.bytes -= r.data.len();
+        if let Some(usage) = self.peers.get_mut(&r.peer) {
+            usage.requests -= 1;
+            usage.bytes -= r.data.len();
+            if usage.requests == 0 {
+                self.peers.remove(&r.peer);
+            }
+        }
+        Some(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue() -> DelayedRequests {
+        DelayedRequests {
+            limits: Limits {
+                requests_per_peer: 3,
+                bytes_per_peer: 100,
+                requests: 4,
+                bytes: 1000,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn ids(requests: &[(PeerId, u8, Vec<u8>)]) -> Vec<(PeerId, u8)> {
+        requests.iter().map(|r| (r.0, r.1)).collect()
+    }
+
+    #[test]
+    fn ignores_duplicates() {
+        let mut delayed = queue();
+        assert!(delayed.add(1, 3, &[0; 10]));
+        assert!(!delayed.add(1, 3, &[0; 10]));
+        assert_eq!(delayed.len(), 1);
+        assert_eq!(delayed.delayed(), 1);
+    }
+
+    #[test]
+    fn evicts_oldest_and_caps_each_peer() {
+        let mut delayed = queue();
+        // peer 1 floods the queue, its oldest request makes room for its newest
+        for packet_id in 0..5 {
+            delayed.add(1, packet_id, &[0; 10]);
+        }
+        assert_eq!(delayed.len(), 3);
+        assert_eq!(delayed.evicted(), 2);
+
+        // peer 2 still gets its requests in, pushing out the oldest overall
+        delayed.add(2, 0, &[0; 10]);
+        delayed.add(2, 1, &[0; 10]);
+        assert_eq!(delayed.len(), 4);
+        assert_eq!(delayed.evicted(), 3);
+
+        // byte cap per peer
+        delayed.add(2, 2, &[0; 95]);
+        assert_eq!(delayed.evicted(), 5);
+        assert!(!delayed.add(2, 3, &[0; 101]));
+        assert_eq!(delayed.evicted(), 6);
+
+        assert_eq!(ids(&delayed.drain(10)), vec![(1, 3), (1, 4), (2, 2)],);
+        assert_eq!(delayed.len(), 0);
+        assert_eq!(delayed.delayed(), 8);
+    }
+
+    #[test]
+    fn removes_requests_of_disconnected_peer() {
+        let mut delayed = queue();
+        delayed.add(1, 0, &[0; 10]);
+        delayed.add(2, 0, &[0; 10]);
+        delayed.add(1, 1, &[0; 10]);
+        delayed.add(2, 1, &[0; 10]);
+
+        delayed.remove_peer(1);
+        assert_eq!(delayed.len(), 2);
+        assert!(!delayed.peers.contains_key(&1));
+        assert!(delayed.add(1, 0, &[0; 10]));
+        assert_eq!(ids(&delayed.drain(10)), vec![(2, 0), (2, 1), (1, 0)]);
+    }
+
+    #[test]
+    fn drains_at_most_max_and_drops_expired() {
+        let mut delayed = queue();
+        delayed.add(1, 0, &[0; 10]);
+        delayed.add(1, GetBlockHeadersPacket as u8, &[0; 10]);
+        delayed.add(2, 0, &[0; 10]);
+        delayed.queue[1].added = Instant::now() - HEADERS_TIMEOUT - Duration::from_secs(1);
+
+        assert_eq!(ids(&delayed.drain(1)), vec![(1, 0)]);
+        assert_eq!(delayed.expired(), 1);
+        assert_eq!(ids(&delayed.drain(1)), vec![(2, 0)]);
+        assert!(delayed.drain(1).is_empty());
+        assert_eq!(delayed.bytes, 0);
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Structured notifications about sync progress, pushed to an optional subscriber.
+
+use super::SyncState;
+use crossbeam_channel::{Sender, TrySendError};
+use std::time::SystemTime;
+use types::BlockNumber;
+
+/// Number of downloaded snapshot chunks between two `SnapshotProgress` events.
+pub const SNAPSHOT_PROGRESS_CHUNKS: usize = 64;
+
+/// What happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncEventKind {
+    /// Sync moved to another state.
+    StateChanged {
+        /// Previous state.
+        from: SyncState,
+        /// New state.
+        to: SyncState,
+    },
+    /// A snapshot manifest was chosen and its restoration started.
+    SnapshotManifest {
+        /// Block the snapshot was taken at.
+        block_number: BlockNumber,
+        /// Number of chunks to download.
+        total_chunks: usize,
+    },
+    /// Snapshot chunks were downloaded.
+    SnapshotProgress {
+        /// Chunks downloaded so far.
+        done_chunks: usize,
+        /// Number of chunks to download.
+        total_chunks: usize,
+    },
+    /// A higher block was announced by the network.
+    HighestBlock {
+        /// Highest block number seen.
+        number: BlockNumber,
+    },
+    /// Downloading of ancient blocks is complete.
+    OldBlocksComplete {
+        /// Last ancient block imported.
+        last_imported: BlockNumber,
+    },
+}
+
+/// Sync event, timestamped when emitted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncEvent {
+    /// Time the event was emitted.
+    pub timestamp: SystemTime,
+    /// What happened.
+    pub kind: SyncEventKind,
+}
+
+/// Non-blocking sender of sync events. Events are dropped when there is no
+/// subscriber or its channel is full, so emitting can never stall sync.
+#[derive(Default)]
+pub struct SyncEvents {
+    sender: Option<Sender<SyncEvent>>,
+    dropped: usize,
+}
+
+impl SyncEvents {
+    /// Replace the subscriber.
+    pub fn set_sender(&mut self, sender: Option<Sender<SyncEvent>>) {
+        self.sender = sender;
+    }
+
+    /// Number of events dropped because the subscriber's channel was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Send an event to the subscriber, if any.
+    pub fn emit(&mut self, kind: SyncEventKind) {
+        let sender = match self.sender {
+            Some(ref sender) => sender,
+            None => return,
+        };
+        trace!(target: "sync", "Sync event: {kind:?}");
+        let event = SyncEvent {
+            timestamp: SystemTime::now(),
+            kind,
+        };
+        match sender.try_send(event) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => self.dropped += 1,
+            Err(TrySendError::Disconnected(_)) => {
+                debug!(target: "sync", "Sync event subscriber is gone");
+                self.sender = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_events_when_full() {
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        let mut events = SyncEvents::default();
+        events.emit(SyncEventKind::HighestBlock { number: 1 });
+        assert_eq!(events.dropped(), 0);
+
+        events.set_sender(Some(tx));
+        events.emit(SyncEventKind::HighestBlock { number: 2 });
+        events.emit(SyncEventKind::HighestBlock { number: 3 });
+        assert_eq!(events.dropped(), 1);
+        assert_eq!(
+            rx.try_recv().unwrap().kind,
+            SyncEventKind::HighestBlock { number: 2 }
+        );
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Continuity of the gas limit and base fee of the heads peers serve with our own chain.
+//!
+//! After a split over the gas limit or base fee policy past the last registered fork, peers of
+//! both sides pass the fork hash check, but their headers drift apart from one chain's
+//! trajectory. The check is advisory: it only orders peers of similar total difficulty, the
+//! difficulty and the block verification stay authoritative.
+
+use ethereum_types::{H256, U256};
+use types::{header::Header, BlockNumber};
+
+/// Headers further ahead of our best block are not checked, the bounds get too loose.
+pub const MAX_CONTINUITY_DISTANCE: BlockNumber = 256;
+
+// Largest change of the gas limit per block is the parent gas limit divided by this.
+const GAS_LIMIT_BOUND_DIVISOR: u64 = 1024;
+// Largest change of the base fee per block is the parent base fee divided by this.
+const BASE_FEE_MAX_CHANGE_DENOMINATOR: u64 = 8;
+// Total difficulties differing by less than the larger one divided by this are similar.
+const SIMILAR_DIFFICULTY_DIVISOR: u64 = 100;
+
+/// Gas limit and base fee of the highest validated header received from a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerFeeMarket {
+    /// Number of the header.
+    pub number: BlockNumber,
+    /// Hash of the header.
+    pub hash: H256,
+    /// Gas limit of the header.
+    pub gas_limit: U256,
+    /// Base fee of the header, if past the EIP-1559 transition.
+    pub base_fee: Option<U256>,
+    /// Whether the values can follow our chain, `None` if the header is too far ahead to tell.
+    pub consistent: Option<bool>,
+}
+
+impl PeerFeeMarket {
+    /// Record `header` and check it against `reference`, our header at the same height or our
+    /// best block if the header is ahead of it.
+    pub fn new(header: &Header, reference: &Header) -> Self {
+        PeerFeeMarket {
+            number: header.number(),
+            hash: header.hash(),
+            gas_limit: *header.gas_limit(),
+            base_fee: header.base_fee(),
+            consistent: is_continuous(reference, header),
+        }
+    }
+}
+
+/// Check if `header` can descend from `reference` as far as the gas limit and the base fee go.
+/// A header at the height of `reference` must match it.
+pub fn is_continuous(reference: &Header, header: &Header) -> Option<bool> {
+    if header.hash() == reference.hash() {
+        return Some(true);
+    }
+    let distance = header.number().checked_sub(reference.number())?;
+    if distance > MAX_CONTINUITY_DISTANCE {
+        return None;
+    }
+    let gas_limit = within_bounds(
+        *reference.gas_limit(),
+        *header.gas_limit(),
+        GAS_LIMIT_BOUND_DIVISOR,
+        distance,
+    );
+    // the base fee is compared on both sides of the transition only
+    let base_fee = match (reference.base_fee(), header.base_fee()) {
+        (Some(reference), Some(base_fee)) => within_bounds(
+            reference,
+            base_fee,
+            BASE_FEE_MAX_CHANGE_DENOMINATOR,
+            distance,
+        ),
+        _ => true,
+    };
+    Some(gas_limit && base_fee)
+}
+
+// Check if `value` is reachable from `start` in `steps` changes of at most a `divisor`th each.
+fn within_bounds(start: U256, value: U256, divisor: u64, steps: BlockNumber) -> bool {
+    let (mut low, mut high) = (start, start);
+    for _ in 0..steps {
+        if low <= value && value <= high {
+            return true;
+        }
+        low = low - low / divisor;
+        high = high.saturating_add(high / divisor + 1);
+    }
+    low <= value && value <= high
+}
+
+/// Check if two total difficulties are close enough for a split to be decided otherwise.
+pub fn is_similar_difficulty(a: Option<U256>, b: Option<U256>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            let (low, high) = if a < b { (a, b) } else { (b, a) };
+            high - low <= high / SIMILAR_DIFFICULTY_DIVISOR
+        }
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(number: BlockNumber, gas_limit: u64, base_fee: Option<u64>) -> Header {
+        let mut header = Header::new();
+        header.set_number(number);
+        header.set_gas_limit(gas_limit.into());
+        header.set_base_fee(base_fee.map(Into::into));
+        header
+    }
+
+    #[test]
+    fn bounds_grow_with_the_distance() {
+        let reference = header(100, 8_000_000, Some(1_000));
+        assert_eq!(
+            is_continuous(&reference, &header(100, 8_000_000, Some(1_000))),
+            Some(true)
+        );
+        assert_eq!(
+            is_continuous(&reference, &header(100, 8_001_000, Some(1_000))),
+            Some(false)
+        );
+        // two blocks of 1/1024 each
+        assert_eq!(
+            is_continuous(&reference, &header(102, 8_015_000, Some(1_000))),
+            Some(true)
+        );
+        assert_eq!(
+            is_continuous(&reference, &header(102, 8_100_000, Some(1_000))),
+            Some(false)
+        );
+        assert_eq!(
+            is_continuous(&reference, &header(101, 8_000_000, Some(2_000))),
+            Some(false)
+        );
+        // unknown before the reference and far ahead of it
+        assert_eq!(
+            is_continuous(&reference, &header(99, 8_000_000, Some(1_000))),
+            None
+        );
+        assert_eq!(
+            is_continuous(
+                &reference,
+                &header(101 + MAX_CONTINUITY_DISTANCE, 30_000_000, None)
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn base_fee_is_checked_past_the_transition_only() {
+        let reference = header(100, 8_000_000, None);
+        assert_eq!(
+            is_continuous(&reference, &header(101, 8_000_000, Some(1_000_000))),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn similar_difficulty_within_one_percent() {
+        assert!(is_similar_difficulty(Some(1000.into()), Some(991.into())));
+        assert!(!is_similar_difficulty(Some(1000.into()), Some(989.into())));
+        assert!(is_similar_difficulty(None, Some(1.into())));
+    }
+}
@@ -2,14 +2,29 @@
 //! to support Ethereum network protocol, version 64 and above.
 
 // Re-export ethereum-forkid crate contents here.
-pub use ethereum_forkid::{BlockNumber, ForkId, RejectReason};
+pub use ethereum_forkid::{BlockNumber, ForkId, RejectReason, ValidationView};
 
 use ethcore::client::ChainInfo;
-use ethereum_forkid::ForkFilter;
+use ethereum_forkid::{ForkFilter, SharedForkFilter};
+use rlp::RlpStream;
+use std::{collections::BTreeSet, sync::Arc};
+
+/// Key of the node record entry announcing the fork id, see EIP-2124.
+pub const NODE_RECORD_KEY: &[u8] = b"eth";
+
+/// Value of the `eth` node record entry for `fork_id`: a list holding the fork id.
+pub fn node_record_entry(fork_id: ForkId) -> Vec<u8> {
+    let mut stream = RlpStream::new_list(1);
+    stream.append(&fork_id);
+    stream.out()
+}
 
 /// Wrapper around fork filter that provides integration with `ForkFilter`.
+/// Fork ids are validated against a snapshot of the filter, so checks never wait for head updates.
 pub struct ForkFilterApi {
-    inner: ForkFilter,
+    inner: SharedForkFilter,
+    /// Registered forks beyond genesis
+    forks: BTreeSet<BlockNumber>,
 }
 
 impl ForkFilterApi {
@@ -20,8 +35,14 @@ impl ForkFilterApi {
     ) -> Self {
         let chain_info = client.chain_info();
         let genesis_hash = primitive_types07::H256::from_slice(&chain_info.genesis_hash.0);
+        let forks: BTreeSet<BlockNumber> = forks.into_iter().filter(|fork| *fork > 0).collect();
         Self {
-            inner: ForkFilter::new(chain_info.best_block_number, genesis_hash, forks),
+            inner: SharedForkFilter::new(ForkFilter::new(
+                chain_info.best_block_number,
+                genesis_hash,
+                forks.iter().cloned(),
+            )),
+            forks,
         }
     }
 
@@ -30,39 +51,57 @@ impl ForkFilterApi {
     pub fn new_dummy<C: ?Sized + ChainInfo>(client: &C) -> Self {
         let chain_info = client.chain_info();
         Self {
-            inner: ForkFilter::new(
+            inner: SharedForkFilter::new(ForkFilter::new(
                 chain_info.best_block_number,
                 primitive_types07::H256::from_slice(&chain_info.genesis_hash.0),
                 vec![],
-            ),
+            )),
+            forks: BTreeSet::new(),
         }
     }
 
-    fn update_head<C: ?Sized + ChainInfo>(&mut self, client: &C) {
-        self.inner.set_head(client.chain_info().best_block_number);
+    /// View of the filter at the best block of `client`, only taking the writer lock if the head moved.
+    fn view<C: ?Sized + ChainInfo>(&self, client: &C) -> Arc<ValidationView> {
+        let head = client.chain_info().best_block_number;
+        let view = self.inner.view();
+        if view.head() == head {
+            view
+        } else {
+            self.inner.set_head(head)
+        }
     }
 
     /// Wrapper for `ForkFilter::current`
-    pub fn current<C: ?Sized + ChainInfo>(&mut self, client: &C) -> ForkId {
-        self.update_head(client);
-        self.inner.current()
+    pub fn current<C: ?Sized + ChainInfo>(&self, client: &C) -> ForkId {
+        self.view(client).current()
+    }
+
+    /// Most recent fork beyond genesis at or below block `number`.
+    pub fn last_fork_at(&self, number: BlockNumber) -> Option<BlockNumber> {
+        self.forks.range(..=number).next_back().cloned()
     }
 
     /// Wrapper for `ForkFilter::is_compatible`
     pub fn is_compatible<C: ?Sized + ChainInfo>(
-        &mut self,
+        &self,
         client: &C,
         fork_id: ForkId,
     ) -> Result<(), RejectReason> {
-        self.update_head(client);
-        self.inner.is_compatible(fork_id)
+        self.view(client).is_compatible(fork_id)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use ethcore::{client::TestBlockChainClient, ethereum, spec::Spec};
+    use crypto::publickey::{Generator, Random};
+    use ethcore::{
+        client::{EachBlockWith, TestBlockChainClient},
+        ethereum,
+        spec::Spec,
+    };
+    use network::{LocalNodeRecord, NodeRecord};
+    use rlp::Rlp;
 
     fn test_spec<F: Fn() -> Spec>(spec_builder: F, forks: Vec<BlockNumber>) {
         let spec = (spec_builder)();
@@ -71,7 +110,9 @@ mod tests {
         let client = TestBlockChainClient::new_with_spec(spec);
 
         assert_eq!(
-            ForkFilterApi::new(&client, spec_forks).inner,
+            ForkFilterApi::new(&client, spec_forks)
+                .inner
+                .filter_snapshot(),
             ForkFilter::new(
                 0,
                 primitive_types07::H256::from_slice(&genesis_hash.0),
@@ -80,6 +121,71 @@ mod tests {
         );
     }
 
+    #[test]
+    fn last_fork_at() {
+        let client = TestBlockChainClient::new();
+        let filter = ForkFilterApi::new(&client, vec![0, 10, 20, 10]);
+
+        assert_eq!(filter.last_fork_at(0), None);
+        assert_eq!(filter.last_fork_at(9), None);
+        assert_eq!(filter.last_fork_at(10), Some(10));
+        assert_eq!(filter.last_fork_at(19), Some(10));
+        assert_eq!(filter.last_fork_at(100), Some(20));
+    }
+
+    #[test]
+    fn follows_best_block() {
+        let client = TestBlockChainClient::new();
+        let filter = ForkFilterApi::new(&client, vec![10]);
+        let before = filter.current(&client);
+        assert_eq!(before.next, 10);
+
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let after = filter.current(&client);
+        assert_eq!(after.next, 0);
+        assert_ne!(after.hash, before.hash);
+        assert_eq!(filter.is_compatible(&client, after), Ok(()));
+        // a peer still in the first epoch is fine as long as it knows about the fork
+        assert_eq!(filter.is_compatible(&client, before), Ok(()));
+        let unaware = ForkId {
+            hash: before.hash,
+            next: 0,
+        };
+        assert_eq!(
+            filter.is_compatible(&client, unaware),
+            Err(RejectReason::RemoteStale)
+        );
+    }
+
+    #[test]
+    fn node_record_entry_follows_fork_id() {
+        let client = TestBlockChainClient::new();
+        let filter = ForkFilterApi::new(&client, vec![10]);
+        let mut record = LocalNodeRecord::new(Random.generate().secret().clone(), 1).unwrap();
+        let announced = |record: &LocalNodeRecord| {
+            let decoded = NodeRecord::decode(&record.record().encode()).unwrap();
+            decoded
+                .get(NODE_RECORD_KEY)
+                .unwrap()
+                .val_at::<ForkId>(0)
+                .unwrap()
+        };
+
+        let entry = node_record_entry(filter.current(&client));
+        assert_eq!(Rlp::new(&entry).item_count().unwrap(), 1);
+        assert!(record.set(NODE_RECORD_KEY, entry).unwrap());
+        assert_eq!(record.record().seq(), 2);
+        assert_eq!(announced(&record), filter.current(&client));
+
+        client.add_blocks(10, EachBlockWith::Nothing);
+        assert!(record
+            .set(NODE_RECORD_KEY, node_record_entry(filter.current(&client)))
+            .unwrap());
+        assert_eq!(record.record().seq(), 3);
+        assert_eq!(announced(&record), filter.current(&client));
+        assert_eq!(announced(&record).next, 0);
+    }
+
     #[test]
     fn ethereum_spec() {
         test_spec(
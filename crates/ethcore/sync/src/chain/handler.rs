@@ -27,12 +27,22 @@ use ethereum_types::{H256, U256};
 use hash::keccak;
 use network::PeerId;
 use rlp::Rlp;
-use snapshot::ChunkType;
-use std::{cmp, time::Instant};
+use snapshot::{ChunkError, ChunkType, MAX_COMPRESSED_CHUNK_SIZE};
+use std::{
+    cmp,
+    time::{Instant, SystemTime},
+};
 use sync_io::SyncIo;
-use types::{block_status::BlockStatus, ids::BlockId, BlockNumber};
+use types::{
+    block_status::BlockStatus, header::Header as BlockHeader, ids::BlockId,
+    transaction::TypedTransaction, BlockNumber,
+};
 
 use super::{
+    branch_probe::PeerBranch,
+    compression,
+    events::{SyncEventKind, SNAPSHOT_PROGRESS_CHUNKS},
+    inactive::DeactivationReason,
     request_id::strip_request_id,
     sync_packet::{
         PacketInfo,
@@ -41,9 +51,10 @@ use super::{
 };
 
 use super::{
-    BlockSet, ChainSync, ForkConfirmation, PacketProcessError, PeerAsking, PeerInfo, SyncRequester,
-    SyncState, ETH_PROTOCOL_VERSION_63, ETH_PROTOCOL_VERSION_64, ETH_PROTOCOL_VERSION_66,
-    MAX_NEW_BLOCK_AGE, MAX_NEW_HASHES, PAR_PROTOCOL_VERSION_1, PAR_PROTOCOL_VERSION_2,
+    BlockSet, ChainSync, DifficultyCheck, ForkConfirmation, GetPooledTransactionsReport,
+    HashedChunk, InjectError, PacketProcessError, PeerAsking, PeerInfo, SyncRequester, SyncState,
+    ETH_PROTOCOL_VERSION_63, ETH_PROTOCOL_VERSION_64, ETH_PROTOCOL_VERSION_66,
+    PAR_PROTOCOL_VERSION_1, PAR_PROTOCOL_VERSION_3, PAR_PROTOCOL_VERSION_4,
 };
 
 /// The Chain Sync Handler: handles responses from peers
@@ -60,6 +71,9 @@ impl SyncHandler {
     ) {
         if let Some(packet_id) = SyncPacket::from_u8(packet_id) {
             let rlp_result = strip_request_id(data, sync, &peer, &packet_id);
+            if rlp_result.is_err() {
+                sync.packet_stats.note_decode_failure(packet_id.id());
+            }
 
             let result = match rlp_result {
                 Ok((rlp, _)) => match packet_id {
@@ -79,6 +93,12 @@ impl SyncHandler {
                         SyncHandler::on_snapshot_manifest(sync, io, peer, &rlp)
                     }
                     SnapshotDataPacket => SyncHandler::on_snapshot_data(sync, io, peer, &rlp),
+                    SnapshotDataPartPacket => {
+                        SyncHandler::on_snapshot_data_part(sync, io, peer, &rlp)
+                    }
+                    CompressedBlockBodiesPacket | CompressedReceiptsPacket => {
+                        SyncHandler::on_peer_compressed_packet(sync, io, peer, packet_id, &rlp)
+                    }
                     _ => {
                         debug!(target: "sync", "{}: Unknown packet {}", peer, packet_id.id());
                         Ok(())
@@ -90,11 +110,10 @@ impl SyncHandler {
             match result {
                 Err(DownloaderImportError::Invalid) => {
                     debug!(target:"sync", "{} -> Invalid packet {}", peer, packet_id.id());
-                    io.disable_peer(peer);
-                    sync.deactivate_peer(io, peer);
+                    sync.disable_peer(io, peer);
                 }
                 Err(DownloaderImportError::Useless) => {
-                    sync.deactivate_peer(io, peer);
+                    sync.deactivate_peer(io, peer, DeactivationReason::Useless);
                 }
                 Ok(()) => {
                     // give a task to the same peer first
@@ -116,13 +135,25 @@ impl SyncHandler {
     pub fn on_peer_aborting(sync: &mut ChainSync, io: &mut dyn SyncIo, peer_id: PeerId) {
         trace!(target: "sync", "== Disconnecting {}: {}", peer_id, io.peer_version(peer_id));
         sync.handshaking_peers.remove(&peer_id);
+        sync.delayed_requests.remove_peer(peer_id);
+        sync.snapshot_data_parts.remove(&peer_id);
+        // cleared whatever the handshake state, ids of ended sessions must not linger
+        sync.active_peers.remove(&peer_id);
+        sync.idle_peers.remove(&peer_id);
+        sync.inactive_peers.remove(peer_id);
+        sync.disabled_peers.remove(&peer_id);
         if sync.peers.contains_key(&peer_id) {
             debug!(target: "sync", "Disconnected {peer_id}");
             sync.clear_peer_download(peer_id);
+            sync.new_blocks.clear_chain_head_requests(peer_id);
+            if let Some(ref mut old_blocks) = sync.old_blocks {
+                old_blocks.clear_chain_head_requests(peer_id);
+            }
             sync.peers.remove(&peer_id);
-            sync.delayed_requests
-                .retain(|(request_peer_id, _, _)| *request_peer_id != peer_id);
-            sync.active_peers.remove(&peer_id);
+            sync.local_transactions.on_peer_disconnected(peer_id);
+            sync.block_suppliers.on_peer_disconnected(peer_id);
+            sync.branch_prober.on_peer_disconnected(peer_id);
+            sync.deep_reorg_peers.remove(&peer_id);
 
             if sync.state == SyncState::SnapshotManifest {
                 // Check if we are asking other peers for
@@ -138,10 +169,14 @@ impl SyncHandler {
                     .is_none();
 
                 if still_asking_manifest {
-                    sync.state = ChainSync::get_init_state(sync.warp_sync, io.chain());
+                    sync.set_state(ChainSync::get_init_state(sync.warp_sync, io.chain()));
                 }
             }
-            sync.continue_sync(io);
+            if sync.peers.is_empty() {
+                sync.continue_sync(io);
+            } else {
+                sync.schedule_continue_sync(io);
+            }
         }
     }
 
@@ -152,7 +187,7 @@ impl SyncHandler {
             debug!(target:"sync", "Error sending status request: {e:?}");
             io.disconnect_peer(peer);
         } else {
-            sync.handshaking_peers.insert(peer, Instant::now());
+            sync.add_handshaking_peer(io, peer, Instant::now());
         }
     }
 
@@ -167,20 +202,21 @@ impl SyncHandler {
             trace!(target: "sync", "Ignoring new block from unconfirmed peer {peer_id}");
             return Ok(());
         }
-        // t_nb 1.0 decode RLP
-        let block = Unverified::from_rlp(r.at(0)?.as_raw().to_vec(), sync.eip1559_transition)?;
-        let hash = block.header.hash();
-        let number = block.header.number();
+        let received = Instant::now();
+        // t_nb 1.0 decode header RLP, the full block is decoded only if it needs to be imported
+        let block_rlp = r.at(0)?;
+        let header = BlockHeader::decode_rlp(&block_rlp.at(0)?, sync.eip1559_transition)?;
+        let hash = header.hash();
+        let number = header.number();
         trace!(target: "sync", "{peer_id} -> NewBlock ({hash})");
-        if number > sync.highest_block.unwrap_or(0) {
-            sync.highest_block = Some(number);
-        }
-        let parent_hash = block.header.parent_hash();
+        sync.note_highest_block(number);
+        let parent_hash = header.parent_hash();
         let difficulty: U256 = r.val_at(1)?;
+        sync.note_head_hint(peer_id, hash, Some(number), Some(difficulty));
         // Most probably the sent block is being imported by peer right now
         // Use td and hash, that peer must have for now
         // t_nb 1.1 check new block diffuculty it can be found as second item in RLP and update peer diffuculty
-        let parent_td = difficulty.checked_sub(*block.header.difficulty());
+        let parent_td = difficulty.checked_sub(*header.difficulty());
 
         if let Some(ref mut peer) = sync.peers.get_mut(&peer_id) {
             if peer
@@ -195,14 +231,31 @@ impl SyncHandler {
         if let Some(ref mut peer) = sync.peers.get_mut(&peer_id) {
             peer.latest_hash = *parent_hash;
         }
+        sync.on_peer_announced_head(peer_id, hash, Some(difficulty));
 
-        // t_nb 1.2 if block number is to older then 20 dont process it
+        // t_nb 1.2 if block number is too far behind the last imported block dont process it
         let last_imported_number = sync.new_blocks.last_imported_block_number();
-        if last_imported_number > number && last_imported_number - number > MAX_NEW_BLOCK_AGE {
+        let max_age = sync.max_new_block_age();
+        if last_imported_number > number && last_imported_number - number > max_age {
             trace!(target: "sync", "Ignored ancient new block {hash:?}");
             return Err(DownloaderImportError::Invalid);
         }
-        match io.chain().import_block(block) {
+        // t_nb 1.3 skip blocks which are already imported or being verified
+        if io.chain().is_queued(&hash)
+            || io.chain().block_status(BlockId::Hash(hash)) == BlockStatus::InChain
+        {
+            trace!(target: "sync", "New block already known {hash:?}");
+            sync.skipped_new_blocks += 1;
+            return Ok(());
+        }
+        let block = Unverified::from_rlp(block_rlp.as_raw().to_vec(), sync.eip1559_transition)?;
+        // t_nb 1.4 a block extending our best block skips ahead of the queued blocks
+        let fast_path = SyncHandler::extends_best_block(io, &header, parent_td);
+        let imported = match fast_path {
+            true => io.chain().import_block_priority(block),
+            false => io.chain().import_block(block),
+        };
+        match imported {
             Err(EthcoreError(EthcoreErrorKind::Import(ImportErrorKind::AlreadyInChain), _)) => {
                 trace!(target: "sync", "New block already in chain {hash:?}");
             }
@@ -210,9 +263,18 @@ impl SyncHandler {
                 trace!(target: "sync", "New block already queued {hash:?}");
             }
             Ok(_) => {
+                if fast_path {
+                    // the peer has the block, it is not relayed back
+                    if let Some(ref mut peer) = sync.peers.get_mut(&peer_id) {
+                        peer.latest_hash = hash;
+                    }
+                    sync.note_fast_path_block(hash, received);
+                    trace!(target: "sync", "New block {hash:?} ({number}) extends the best block");
+                }
                 // abort current download of the same block
                 sync.complete_sync(io);
                 sync.new_blocks.mark_as_known(&hash, number);
+                sync.block_suppliers.insert(hash, peer_id);
                 trace!(target: "sync", "New block queued {hash:?} ({number})");
             }
             Err(EthcoreError(EthcoreErrorKind::Block(BlockError::UnknownParent(p)), _)) => {
@@ -236,6 +298,74 @@ impl SyncHandler {
         Ok(())
     }
 
+    /// Import a block obtained out of band as if a peer announced it, the downloader skips past it.
+    /// A `trusted` block is imported whatever its age and takes the fast path whenever it extends
+    /// the best block. The block is fully verified either way.
+    pub fn inject_block(
+        sync: &mut ChainSync,
+        io: &mut dyn SyncIo,
+        rlp_block: Bytes,
+        trusted: bool,
+    ) -> Result<H256, InjectError> {
+        let header =
+            BlockHeader::decode_rlp(&Rlp::new(&rlp_block).at(0)?, sync.eip1559_transition)?;
+        let hash = header.hash();
+        let number = header.number();
+        let last_imported_number = sync.new_blocks.last_imported_block_number();
+        if !trusted
+            && last_imported_number > number
+            && last_imported_number - number > sync.max_new_block_age()
+        {
+            return Err(InjectError::Ancient);
+        }
+        if io.chain().is_queued(&hash)
+            || io.chain().block_status(BlockId::Hash(hash)) == BlockStatus::InChain
+        {
+            return Err(InjectError::AlreadyKnown);
+        }
+        if io.chain().queue_info().is_full() {
+            return Err(InjectError::QueueFull);
+        }
+        let block = Unverified::from_rlp(rlp_block, sync.eip1559_transition)?;
+        // there is no total difficulty to check the block against, unlike with a NewBlock
+        let chain_info = io.chain().chain_info();
+        let fast_path = *header.parent_hash() == chain_info.best_block_hash
+            && number == chain_info.best_block_number + 1
+            && (trusted || header.timestamp() > chain_info.best_block_timestamp);
+        let imported = match fast_path {
+            true => io.chain().import_block_priority(block),
+            false => io.chain().import_block(block),
+        };
+        match imported {
+            Ok(_) => {}
+            Err(EthcoreError(EthcoreErrorKind::Import(ImportErrorKind::AlreadyInChain), _))
+            | Err(EthcoreError(EthcoreErrorKind::Import(ImportErrorKind::AlreadyQueued), _)) => {
+                return Err(InjectError::AlreadyKnown);
+            }
+            Err(EthcoreError(EthcoreErrorKind::Block(BlockError::UnknownParent(p)), _)) => {
+                return Err(InjectError::UnknownParent(p));
+            }
+            Err(e) => return Err(InjectError::Invalid(e.to_string())),
+        }
+        info!(target: "sync", "Injected block #{number} {hash:?}, trusted: {trusted}");
+        sync.note_highest_block(number);
+        // abort current download of the same block
+        sync.complete_sync(io);
+        sync.new_blocks.mark_as_known(&hash, number);
+        Ok(hash)
+    }
+
+    /// Check if a new block directly extends our best block and passes basic sanity checks,
+    /// so that it can be verified ahead of the queued blocks.
+    fn extends_best_block(io: &dyn SyncIo, header: &BlockHeader, parent_td: Option<U256>) -> bool {
+        let chain_info = io.chain().chain_info();
+        *header.parent_hash() == chain_info.best_block_hash
+            && header.number() == chain_info.best_block_number + 1
+            && header.timestamp() > chain_info.best_block_timestamp
+            && parent_td == Some(chain_info.total_difficulty)
+            && !io.chain().queue_info().is_full()
+    }
+
     /// Handles `NewHashes` packet. Initiates headers download for any unknown hashes.
     pub fn on_peer_new_hashes(
         sync: &mut ChainSync,
@@ -247,9 +377,10 @@ impl SyncHandler {
             trace!(target: "sync", "Ignoring new hashes from unconfirmed peer {peer_id}");
             return Ok(());
         }
+        let max_hashes = sync.max_new_hashes();
         let hashes: Vec<_> = r
             .iter()
-            .take(MAX_NEW_HASHES)
+            .take(max_hashes)
             .map(|item| (item.val_at::<H256>(0), item.val_at::<BlockNumber>(1)))
             .collect();
         if let Some(ref mut peer) = sync.peers.get_mut(&peer_id) {
@@ -259,32 +390,33 @@ impl SyncHandler {
                 peer.latest_hash = *h;
             }
         }
+        if let Some(&(Ok(hash), Ok(number))) = hashes.last() {
+            sync.note_head_hint(peer_id, hash, Some(number), None);
+            sync.on_peer_announced_head(peer_id, hash, None);
+        }
         if sync.state != SyncState::Idle {
             trace!(target: "sync", "Ignoring new hashes since we're already downloading.");
             let max = r
                 .iter()
-                .take(MAX_NEW_HASHES)
+                .take(max_hashes)
                 .map(|item| item.val_at::<BlockNumber>(1).unwrap_or(0))
                 .fold(0u64, cmp::max);
-            if max > sync.highest_block.unwrap_or(0) {
-                sync.highest_block = Some(max);
-            }
+            sync.note_highest_block(max);
             return Ok(());
         }
         trace!(target: "sync", "{} -> NewHashes ({} entries)", peer_id, r.item_count()?);
         let mut max_height: BlockNumber = 0;
         let mut new_hashes = Vec::new();
         let last_imported_number = sync.new_blocks.last_imported_block_number();
+        let max_age = sync.max_new_block_age();
         for (rh, rn) in hashes {
             let hash = rh?;
             let number = rn?;
-            if number > sync.highest_block.unwrap_or(0) {
-                sync.highest_block = Some(number);
-            }
+            sync.note_highest_block(number);
             if sync.new_blocks.is_downloading(&hash) {
                 continue;
             }
-            if last_imported_number > number && last_imported_number - number > MAX_NEW_BLOCK_AGE {
+            if last_imported_number > number && last_imported_number - number > max_age {
                 trace!(target: "sync", "Ignored ancient new block hash {hash:?}");
                 return Err(DownloaderImportError::Invalid);
             }
@@ -314,7 +446,7 @@ impl SyncHandler {
         if max_height != 0 {
             trace!(target: "sync", "Downloading blocks for new hashes");
             sync.new_blocks.reset_to(new_hashes);
-            sync.state = SyncState::NewBlocks;
+            sync.set_state(SyncState::NewBlocks);
             sync.sync_peer(io, peer_id, true);
         }
         Ok(())
@@ -387,11 +519,13 @@ impl SyncHandler {
                 .peers
                 .get_mut(&peer_id)
                 .expect("Is only called when peer is present in peers");
+            // checked against the checkpoint requested, the derived fork block may have moved since
+            let (fork_number, fork_hash) = peer
+                .asking_fork_header
+                .take()
+                .expect("ForkHeader request holds the requested fork checkpoint; qed");
             peer.asking = PeerAsking::Nothing;
             let item_count = r.item_count()?;
-            let (fork_number, fork_hash) = sync
-                .fork_block
-                .expect("ForkHeader request is sent only fork block is Some; qed");
 
             if item_count == 0 || item_count != 1 {
                 trace!(target: "sync", "{peer_id}: Chain is too short to confirm the block");
@@ -405,6 +539,7 @@ impl SyncHandler {
 
                 trace!(target: "sync", "{peer_id}: Confirmed peer");
                 peer.confirmation = ForkConfirmation::Confirmed;
+                sync.idle_peers.insert(peer_id);
 
                 if !io.chain_overlay().read().contains_key(&fork_number) {
                     trace!(target: "sync", "Inserting (fork) block {fork_number} header");
@@ -418,6 +553,108 @@ impl SyncHandler {
         Ok(())
     }
 
+    /// Called by peer once it has sent its best header to back the advertised total difficulty
+    fn on_peer_difficulty_probe(
+        sync: &mut ChainSync,
+        _io: &mut dyn SyncIo,
+        peer_id: PeerId,
+        r: &Rlp,
+    ) -> Result<(), DownloaderImportError> {
+        sync.reset_peer_asking(peer_id, PeerAsking::DifficultyProbe);
+        let (claimed, latest_hash) = match sync.peers.get(&peer_id) {
+            Some(peer) => match peer.difficulty_check {
+                DifficultyCheck::Unverified(claimed) => (claimed, peer.latest_hash),
+                _ => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+        let number = if r.item_count()? == 1 && keccak(r.at(0)?.as_raw()) == latest_hash {
+            Some(BlockHeader::decode_rlp(&r.at(0)?, sync.eip1559_transition)?.number())
+        } else {
+            None
+        };
+        let backed = number.is_some_and(|n| sync.is_difficulty_backed(claimed, n));
+        let peer = sync
+            .peers
+            .get_mut(&peer_id)
+            .expect("peer presence checked above; qed");
+        if backed {
+            trace!(target: "sync", "{peer_id}: Total difficulty {claimed} backed by block {number:?}");
+            peer.difficulty = Some(claimed);
+            peer.difficulty_check = DifficultyCheck::Plausible;
+        } else {
+            debug!(target: "sync", "{peer_id}: Failed to back total difficulty {claimed} (best block {number:?})");
+            peer.difficulty_check = DifficultyCheck::Failed;
+        }
+        Ok(())
+    }
+
+    /// Called by peer once it has sent its headers at the height of the rejected block
+    fn on_peer_branch_probe(
+        sync: &mut ChainSync,
+        io: &mut dyn SyncIo,
+        peer_id: PeerId,
+        r: &Rlp,
+    ) -> Result<(), DownloaderImportError> {
+        sync.reset_peer_asking(peer_id, PeerAsking::BranchProbe);
+        let mut headers = Vec::new();
+        for item in r.iter() {
+            let header = BlockHeader::decode_rlp(&item, sync.eip1559_transition)?;
+            headers.push((keccak(item.as_raw()), header));
+        }
+        // the headers come backwards from the height of the rejected block
+        let linked = headers.windows(2).all(|pair| {
+            pair[0].1.parent_hash() == &pair[1].0 && pair[0].1.number() == pair[1].1.number() + 1
+        });
+        if !linked {
+            debug!(target: "sync", "{peer_id}: Branch probe answered with unrelated headers");
+            return Err(DownloaderImportError::Invalid);
+        }
+        let number = sync
+            .branch_probe_target(Instant::now())
+            .and_then(|hash| sync.branch_prober.rejected_number(&hash));
+        let branch = match (number, headers.first()) {
+            (Some(number), Some((hash, header))) if header.number() == number => {
+                match sync.branch_prober.root_of(hash) {
+                    Some(_) => Some(PeerBranch::Rejected),
+                    None => Some(PeerBranch::Alternative),
+                }
+            }
+            // the chain of the peer is too short, or the rejections stopped meanwhile
+            _ => None,
+        };
+        sync.branch_prober.note_probe_result(peer_id, branch);
+        match branch {
+            Some(PeerBranch::Alternative) => {
+                info!(target: "sync", "{peer_id}: Found a branch without the rejected block, restarting sync");
+                sync.restart(io);
+            }
+            Some(PeerBranch::Rejected) => {
+                debug!(target: "sync", "{peer_id}: Probed peer is on the rejected branch");
+            }
+            None => trace!(target: "sync", "{peer_id}: Branch probe was inconclusive"),
+        }
+        Ok(())
+    }
+
+    /// Called by peer once it has sent the headers requested by the header backfill
+    fn on_peer_backfill_headers(
+        sync: &mut ChainSync,
+        io: &mut dyn SyncIo,
+        peer_id: PeerId,
+        r: &Rlp,
+    ) -> Result<(), DownloaderImportError> {
+        sync.reset_peer_asking(peer_id, PeerAsking::BackfillHeaders);
+        let eip1559_transition = sync.eip1559_transition;
+        match sync.header_backfill {
+            Some(ref mut backfill) => backfill.import_headers(io, peer_id, r, eip1559_transition),
+            None => {
+                trace!(target: "sync", "{peer_id}: Ignored headers, header backfill was aborted");
+                Ok(())
+            }
+        }
+    }
+
     /// Called by peer once it has new block headers during sync
     fn on_peer_block_headers(
         sync: &mut ChainSync,
@@ -434,6 +671,30 @@ impl SyncHandler {
             return SyncHandler::on_peer_fork_header(sync, io, peer_id, r);
         }
 
+        let is_difficulty_probe = sync
+            .peers
+            .get(&peer_id)
+            .is_some_and(|p| p.asking == PeerAsking::DifficultyProbe);
+        if is_difficulty_probe {
+            return SyncHandler::on_peer_difficulty_probe(sync, io, peer_id, r);
+        }
+
+        let is_branch_probe = sync
+            .peers
+            .get(&peer_id)
+            .is_some_and(|p| p.asking == PeerAsking::BranchProbe);
+        if is_branch_probe {
+            return SyncHandler::on_peer_branch_probe(sync, io, peer_id, r);
+        }
+
+        let is_backfill_request = sync
+            .peers
+            .get(&peer_id)
+            .is_some_and(|p| p.asking == PeerAsking::BackfillHeaders);
+        if is_backfill_request {
+            return SyncHandler::on_peer_backfill_headers(sync, io, peer_id, r);
+        }
+
         sync.clear_peer_download(peer_id);
         let expected_hash = sync.peers.get(&peer_id).and_then(|p| p.asking_hash);
         let allowed = sync
@@ -447,17 +708,19 @@ impl SyncHandler {
             .and_then(|p| p.block_set)
             .unwrap_or(BlockSet::NewBlocks);
 
-        if !sync.reset_peer_asking(peer_id, PeerAsking::BlockHeaders) {
+        let more_headers = sync.peers.get_mut(&peer_id).is_some_and(|p| {
+            p.asking_headers = p.asking_headers.saturating_sub(1);
+            p.asking == PeerAsking::BlockHeaders && p.asking_headers > 0
+        });
+        if more_headers {
+            // more subchain heads are to come from the peer
+            if let Some(peer) = sync.peers.get_mut(&peer_id) {
+                peer.ask_time = Instant::now();
+            }
+        } else if !sync.reset_peer_asking(peer_id, PeerAsking::BlockHeaders) {
             debug!(target: "sync", "{peer_id}: Ignored unexpected headers");
             return Ok(());
         }
-        let expected_hash = match expected_hash {
-            Some(hash) => hash,
-            None => {
-                debug!(target: "sync", "{peer_id}: Ignored unexpected headers (expected_hash is None)");
-                return Ok(());
-            }
-        };
         if !allowed {
             debug!(target: "sync", "{peer_id}: Ignored unexpected headers (peer not allowed)");
             return Ok(());
@@ -475,10 +738,21 @@ impl SyncHandler {
             trace!(target: "sync", "Ignored block headers while waiting");
             return Ok(());
         }
+        // downloading the branch of a rejected block again would only end in another restart
+        if block_set == BlockSet::NewBlocks {
+            if let Some(rejected) = sync.note_rejected_headers(peer_id, r)? {
+                debug!(target: "sync", "{peer_id}: Ignored headers on the branch of the rejected block {rejected}");
+                return Err(DownloaderImportError::Useless);
+            }
+        }
 
+        let mut fetching_heads = false;
         let result = {
             let downloader = match block_set {
-                BlockSet::NewBlocks => &mut sync.new_blocks,
+                BlockSet::NewBlocks => {
+                    fetching_heads = sync.new_blocks.is_active();
+                    &mut sync.new_blocks
+                }
                 BlockSet::OldBlocks => match sync.old_blocks {
                     None => {
                         trace!(target: "sync", "Ignored block headers while block download is inactive");
@@ -487,11 +761,23 @@ impl SyncHandler {
                     Some(ref mut blocks) => blocks,
                 },
             };
-            downloader.import_headers(io, r, expected_hash, sync.eip1559_transition)?
+            // subchain heads requested by number come without an expected hash
+            let expected_hash = match downloader.chain_head_start(peer_id).or(expected_hash) {
+                Some(hash) => hash,
+                None => {
+                    debug!(target: "sync", "{peer_id}: Ignored unexpected headers (expected_hash is None)");
+                    return Ok(());
+                }
+            };
+            downloader.import_headers(io, peer_id, r, expected_hash, sync.eip1559_transition)?
         };
 
+        if fetching_heads {
+            sync.note_fee_market(io, peer_id, r);
+        }
         if result == DownloadAction::Reset {
             sync.reset_downloads(block_set);
+            sync.note_deep_reorg();
         }
 
         sync.collect_blocks(io, block_set);
@@ -553,6 +839,68 @@ impl SyncHandler {
         }
     }
 
+    /// Called by peer once it has compressed block bodies or receipts
+    fn on_peer_compressed_packet(
+        sync: &mut ChainSync,
+        io: &mut dyn SyncIo,
+        peer_id: PeerId,
+        packet_id: SyncPacket,
+        r: &Rlp,
+    ) -> Result<(), DownloaderImportError> {
+        if io.protocol_version(PAR_PROTOCOL, peer_id) < PAR_PROTOCOL_VERSION_3.0 {
+            debug!(target: "sync", "{peer_id}: Unexpected {packet_id:?} from peer without compression support");
+            return Err(DownloaderImportError::Invalid);
+        }
+        let data = compression::decompress(r).map_err(|e| {
+            debug!(target: "sync", "{peer_id} -> Invalid {packet_id:?}: {e}");
+            DownloaderImportError::Invalid
+        })?;
+        trace!(target: "sync", "{} -> {:?} ({} bytes decompressed to {})", peer_id, packet_id, r.as_raw().len(), data.len());
+        let packet_id = compression::decompressed_packet(packet_id)
+            .expect("called for compressed packets only; qed");
+        let (rlp, _) = strip_request_id(&data, sync, &peer_id, &packet_id)?;
+        match packet_id {
+            BlockBodiesPacket => SyncHandler::on_peer_block_bodies(sync, io, peer_id, &rlp),
+            _ => SyncHandler::on_peer_block_receipts(sync, io, peer_id, &rlp),
+        }
+    }
+
+    /// Called by peer once it has a part of snapshot data
+    fn on_snapshot_data_part(
+        sync: &mut ChainSync,
+        io: &mut dyn SyncIo,
+        peer_id: PeerId,
+        r: &Rlp,
+    ) -> Result<(), DownloaderImportError> {
+        if io.protocol_version(PAR_PROTOCOL, peer_id) < PAR_PROTOCOL_VERSION_4.0 {
+            debug!(target: "sync", "{peer_id}: Unexpected snapshot data part from peer without support");
+            return Err(DownloaderImportError::Invalid);
+        }
+        if !sync
+            .peers
+            .get(&peer_id)
+            .is_some_and(|p| p.asking == PeerAsking::SnapshotData)
+        {
+            trace!(target: "sync", "{peer_id}: Ignored unexpected snapshot data part");
+            return Ok(());
+        }
+        let part: Bytes = r.as_val()?;
+        match sync
+            .snapshot_data_parts
+            .push(peer_id, &part, Instant::now())
+        {
+            Ok(Some(data)) => {
+                trace!(target: "sync", "{} -> Reassembled snapshot data of {} bytes", peer_id, data.len());
+                SyncHandler::on_snapshot_data(sync, io, peer_id, &Rlp::new(&data))
+            }
+            Ok(None) => Ok(()),
+            Err(e) => {
+                debug!(target: "sync", "{peer_id} -> Invalid snapshot data part: {e}");
+                Err(DownloaderImportError::Invalid)
+            }
+        }
+    }
+
     /// Called when snapshot manifest is downloaded from a peer.
     fn on_snapshot_manifest(
         sync: &mut ChainSync,
@@ -586,8 +934,12 @@ impl SyncHandler {
         }
         sync.snapshot
             .reset_to(&manifest, &keccak(manifest_rlp.as_raw()));
+        sync.events.emit(SyncEventKind::SnapshotManifest {
+            block_number: manifest.block_number,
+            total_chunks: sync.snapshot.total_chunks(),
+        });
         io.snapshot_service().begin_restore(manifest);
-        sync.state = SyncState::SnapshotData;
+        sync.set_state(SyncState::SnapshotData);
 
         Ok(())
     }
@@ -616,7 +968,7 @@ impl SyncHandler {
         match status {
             RestorationStatus::Inactive | RestorationStatus::Failed => {
                 trace!(target: "sync", "{peer_id}: Snapshot restoration aborted");
-                sync.state = SyncState::WaitingPeers;
+                sync.set_state(SyncState::WaitingPeers);
 
                 // only note bad if restoration failed.
                 if let (Some(hash), RestorationStatus::Failed) =
@@ -638,31 +990,80 @@ impl SyncHandler {
             }
         }
 
+        let requested = match sync
+            .peers
+            .get(&peer_id)
+            .and_then(|p| p.asking_snapshot_data)
+        {
+            Some(hash) => hash,
+            None => return Ok(()),
+        };
         let snapshot_data: Bytes = r.val_at(0)?;
-        match sync.snapshot.validate_chunk(&snapshot_data) {
+        if snapshot_data.len() > MAX_COMPRESSED_CHUNK_SIZE {
+            debug!(target: "sync", "{}: Snapshot chunk {} of {} bytes is too large", peer_id, requested, snapshot_data.len());
+            sync.bad_snapshot_chunks += 1;
+            return Err(DownloaderImportError::Invalid);
+        }
+        // hashing a large chunk would hold up the sync thread, it is imported once hashed
+        sync.snapshot.note_chunk_hashing(requested);
+        sync.hash_snapshot_chunk(peer_id, requested, snapshot_data);
+        SyncHandler::import_hashed_chunks(sync, io);
+        Ok(())
+    }
+
+    /// Import the snapshot chunks hashed so far.
+    pub fn import_hashed_chunks(sync: &mut ChainSync, io: &mut dyn SyncIo) {
+        let chunks: Vec<_> = sync.hashed_chunks_rx.try_iter().collect();
+        for chunk in chunks {
+            SyncHandler::on_hashed_snapshot_chunk(sync, io, chunk);
+        }
+    }
+
+    fn on_hashed_snapshot_chunk(sync: &mut ChainSync, io: &mut dyn SyncIo, chunk: HashedChunk) {
+        let HashedChunk {
+            peer_id,
+            requested,
+            hash,
+            data,
+        } = chunk;
+        if sync.state != SyncState::SnapshotData && sync.state != SyncState::SnapshotWaiting {
+            trace!(target: "sync", "{peer_id}: Dropped snapshot chunk {requested} hashed after the snapshot sync");
+            return;
+        }
+        sync.snapshot.clear_chunk_download(&requested);
+        match sync.snapshot.validate_hashed_chunk(&requested, &hash) {
             Ok(ChunkType::Block(hash)) => {
                 trace!(target: "sync", "{peer_id}: Processing block chunk");
-                io.snapshot_service()
-                    .restore_block_chunk(hash, snapshot_data);
+                io.snapshot_service().restore_block_chunk(hash, data);
             }
             Ok(ChunkType::State(hash)) => {
                 trace!(target: "sync", "{peer_id}: Processing state chunk");
-                io.snapshot_service()
-                    .restore_state_chunk(hash, snapshot_data);
+                io.snapshot_service().restore_state_chunk(hash, data);
             }
-            Err(()) => {
-                trace!(target: "sync", "{peer_id}: Got bad snapshot chunk");
-                io.disconnect_peer(peer_id);
-                return Ok(());
+            Err(ChunkError::Mismatch(hash)) => {
+                debug!(target: "sync", "{peer_id}: Got snapshot chunk {hash} for {requested}");
+                sync.bad_snapshot_chunks += 1;
+                sync.disable_peer(io, peer_id);
+                return;
             }
+            Err(e) => {
+                trace!(target: "sync", "{peer_id}: Ignored snapshot chunk {requested}: {e:?}");
+                return;
+            }
+        }
+
+        let done_chunks = sync.snapshot.done_chunks();
+        if done_chunks % SNAPSHOT_PROGRESS_CHUNKS == 0 || sync.snapshot.is_complete() {
+            sync.events.emit(SyncEventKind::SnapshotProgress {
+                done_chunks,
+                total_chunks: sync.snapshot.total_chunks(),
+            });
         }
 
         if sync.snapshot.is_complete() {
             // wait for snapshot restoration process to complete
-            sync.state = SyncState::SnapshotWaiting;
+            sync.set_state(SyncState::SnapshotWaiting);
         }
-
-        Ok(())
     }
 
     /// Called by peer to report status
@@ -732,30 +1133,43 @@ impl SyncHandler {
         } else {
             None
         };
-        let peer = PeerInfo {
+        let serve_from_block = if warp_protocol {
+            r_iter.next().map(|r| r.as_val()).transpose()?
+        } else {
+            None
+        };
+        let mut peer = PeerInfo {
             protocol_version,
+            eth_protocol_version,
+            par_protocol_version: warp_protocol_version,
             network_id,
             difficulty,
+            difficulty_check: DifficultyCheck::Plausible,
             latest_hash,
             genesis,
             asking: PeerAsking::Nothing,
             asking_blocks: Vec::new(),
             asking_hash: None,
+            asking_headers: 0,
             unfetched_pooled_transactions: Default::default(),
             asking_pooled_transactions: Default::default(),
             ask_time: Instant::now(),
             last_sent_transactions: Default::default(),
             expired: false,
-            confirmation: if sync.fork_block.is_none() {
+            confirmation: if sync.fork_checkpoint(eth_protocol_version).is_none() {
                 ForkConfirmation::Confirmed
             } else {
                 ForkConfirmation::Unconfirmed
             },
             asking_snapshot_data: None,
+            asking_fork_header: None,
             snapshot_hash,
             snapshot_number,
+            serve_from_block,
             block_set: None,
-            _client_version: io.peer_version(peer_id),
+            client_version: io.peer_version(peer_id),
+            node_id: io.peer_session_info(peer_id).and_then(|info| info.id),
+            fee_market: None,
         };
 
         trace!(target: "sync", "New peer {} (\
@@ -764,14 +1178,16 @@ impl SyncHandler {
             difficulty: {:?}, \
             latest:{}, \
             genesis:{}, \
-            snapshot:{:?})",
+            snapshot:{:?}, \
+            serves from:{:?})",
             peer_id,
             peer.protocol_version,
             peer.network_id,
             peer.difficulty,
             peer.latest_hash,
             peer.genesis,
-            peer.snapshot_number
+            peer.snapshot_number,
+            peer.serve_from_block
         );
         if io.is_expired() {
             trace!(target: "sync", "Status packet from expired session {}:{}", peer_id, io.peer_version(peer_id));
@@ -785,22 +1201,27 @@ impl SyncHandler {
         let chain_info = io.chain().chain_info();
         if peer.genesis != chain_info.genesis_hash {
             trace!(target: "sync", "Peer {} genesis hash mismatch (ours: {}, theirs: {})", peer_id, chain_info.genesis_hash, peer.genesis);
-            return Err(DownloaderImportError::Invalid);
+            // a peer of another network, not a misbehaving one: do not ban, just stop dialing it
+            io.avoid_peer(peer_id);
+            return Err(DownloaderImportError::Useless);
         }
         if peer.network_id != sync.network_id {
             trace!(target: "sync", "Peer {} network id mismatch (ours: {}, theirs: {})", peer_id, sync.network_id, peer.network_id);
+            sync.health
+                .note_network_id_mismatch(Instant::now(), peer.network_id);
             return Err(DownloaderImportError::Invalid);
         }
 
         if let Some((fork_id, reason)) = forkid_validation_error {
-            trace!(target: "sync", "Peer {} incompatible fork id (fork id: {:#x}/{}, error: {:?})", peer_id, fork_id.hash.0, fork_id.next, reason);
+            trace!(target: "sync", "Peer {} incompatible fork id (fork id: {}, error: {:?})", peer_id, fork_id, reason);
+            sync.health.note_fork_rejection(Instant::now(), reason);
             return Err(DownloaderImportError::Invalid);
         }
 
         if false
             || (warp_protocol
                 && (peer.protocol_version < PAR_PROTOCOL_VERSION_1.0
-                    || peer.protocol_version > PAR_PROTOCOL_VERSION_2.0))
+                    || peer.protocol_version > PAR_PROTOCOL_VERSION_4.0))
             || (!warp_protocol
                 && (peer.protocol_version < ETH_PROTOCOL_VERSION_63.0
                     || peer.protocol_version > ETH_PROTOCOL_VERSION_66.0))
@@ -809,18 +1230,40 @@ impl SyncHandler {
             return Err(DownloaderImportError::Invalid);
         }
 
+        if let Some(difficulty) = peer.difficulty {
+            let genesis_difficulty = io
+                .chain()
+                .block_header(BlockId::Number(0))
+                .map_or_else(U256::zero, |h| h.difficulty());
+            if difficulty < genesis_difficulty {
+                debug!(target: "sync", "Peer {} total difficulty {} below genesis difficulty {}", peer_id, difficulty, genesis_difficulty);
+                return Err(DownloaderImportError::Invalid);
+            }
+            if sync
+                .plausible_total_difficulty(&chain_info)
+                .is_some_and(|plausible| difficulty > plausible)
+            {
+                debug!(target: "sync", "Peer {peer_id} advertised implausible total difficulty {difficulty}");
+                peer.difficulty = None;
+                peer.difficulty_check = DifficultyCheck::Unverified(difficulty);
+            }
+        }
+
         if sync.sync_start_time.is_none() {
             sync.sync_start_time = Some(Instant::now());
         }
 
+        let (latest_hash, difficulty) = (peer.latest_hash, peer.difficulty);
         sync.peers.insert(peer_id, peer);
+        sync.note_head_hint(peer_id, latest_hash, None, difficulty);
         // Don't activate peer immediatelly when searching for common block.
         // Let the current sync round complete first.
         sync.active_peers.insert(peer_id);
+        sync.idle_peers.insert(peer_id);
         debug!(target: "sync", "Connected {}:{}", peer_id, io.peer_version(peer_id));
 
-        if let Some((fork_block, _)) = sync.fork_block {
-            SyncRequester::request_fork_header(sync, io, peer_id, fork_block);
+        if let Some(checkpoint) = sync.fork_checkpoint(eth_protocol_version) {
+            SyncRequester::request_fork_header(sync, io, peer_id, checkpoint);
         }
 
         Ok(())
@@ -833,12 +1276,23 @@ impl SyncHandler {
         peer_id: PeerId,
         tx_rlp: &Rlp,
     ) -> Result<(), DownloaderImportError> {
+        if !sync
+            .peers
+            .get(&peer_id)
+            .is_some_and(|peer| sync.relays_transactions(peer))
+        {
+            trace!(target: "sync", "{peer_id}: Ignored transaction hashes from sync-only peer");
+            return Ok(());
+        }
+
         for item in tx_rlp {
             let hash = item
                 .as_val::<H256>()
                 .map_err(|_| DownloaderImportError::Invalid)?;
 
-            if io.chain().queued_transaction(hash).is_none() {
+            if io.chain().queued_transaction(hash).is_none()
+                && !sync.unwanted_transactions.contains(&hash)
+            {
                 sync.peers
                     .get_mut(&peer_id)
                     .map(|peer| peer.unfetched_pooled_transactions.insert(hash));
@@ -848,39 +1302,80 @@ impl SyncHandler {
         Ok(())
     }
 
-    /// Called when peer sends us a list of pooled transactions
+    /// Called when peer sends us a list of pooled transactions. The requested transactions are
+    /// imported, undecodable and unrequested entries are counted as peer faults and make the
+    /// response invalid.
     pub fn on_peer_pooled_transactions(
-        sync: &ChainSync,
+        sync: &mut ChainSync,
         io: &mut dyn SyncIo,
         peer_id: PeerId,
         tx_rlp: &Rlp,
     ) -> Result<(), DownloaderImportError> {
-        let peer = match sync.peers.get(&peer_id).filter(|p| p.can_sync()) {
+        let peer = match sync.peers.get_mut(&peer_id).filter(|p| p.can_sync()) {
             Some(peer) => peer,
             None => {
                 trace!(target: "sync", "{peer_id} Ignoring transactions from unconfirmed/unknown peer");
                 return Ok(());
             }
         };
+        // the request is answered, whatever the response holds
+        let asked = std::mem::take(&mut peer.asking_pooled_transactions);
 
         let item_count = tx_rlp.item_count()?;
-        if item_count > peer.asking_pooled_transactions.len() {
+        if item_count > asked.len() {
             trace!(target: "sync", "{peer_id} Peer sent us more transactions than was supposed to");
             return Err(DownloaderImportError::Invalid);
         }
         trace!(target: "sync", "{peer_id:02} -> PooledTransactions ({item_count} entries)");
         let mut transactions = Vec::with_capacity(item_count);
-        for i in 0..item_count {
-            let rlp = tx_rlp.at(i)?;
-            let tx = if rlp.is_list() {
-                rlp.as_raw()
-            } else {
-                rlp.data()?
+        let mut received = Vec::with_capacity(item_count);
+        let mut peer_faults = 0;
+        for rlp in tx_rlp.iter() {
+            // Undecodable or unrequested transactions are the peer's fault,
+            // transactions rejected by the local pool policy are not.
+            match TypedTransaction::decode_rlp(&rlp) {
+                Ok(tx) if asked.contains(&tx.hash()) => {
+                    let hash = tx.hash();
+                    let tx = if rlp.is_list() {
+                        rlp.as_raw()
+                    } else {
+                        rlp.data()?
+                    }
+                    .to_vec();
+                    transactions.push(tx);
+                    received.push(hash);
+                }
+                Ok(tx) => {
+                    trace!(target: "sync", "{} Peer sent unrequested transaction {:?}", peer_id, tx.hash());
+                    peer_faults += 1;
+                }
+                Err(e) => {
+                    trace!(target: "sync", "{peer_id} Peer sent undecodable transaction: {e:?}");
+                    peer_faults += 1;
+                }
             }
-            .to_vec();
-            transactions.push(tx);
         }
-        io.chain().queue_transactions(transactions, peer_id);
+        // transactions left out of the response, e.g. because of its size, are requested again
+        if let Ok(report) = GetPooledTransactionsReport::generate(asked, received.iter().copied()) {
+            if let Some(peer) = sync.peers.get_mut(&peer_id) {
+                peer.unfetched_pooled_transactions.extend(report.not_sent);
+            }
+        }
+        if !transactions.is_empty() {
+            let id = io.peer_session_info(peer_id).and_then(|info| info.id);
+            let block_number = io.chain().chain_info().best_block_number;
+            let now = SystemTime::now();
+            for hash in &received {
+                sync.transactions_stats
+                    .received(hash, peer_id, id, block_number, now);
+            }
+            io.chain().queue_transactions(transactions, peer_id);
+        }
+        if peer_faults > 0 {
+            debug!(target: "sync", "{peer_id} Sent {peer_faults} invalid pooled transactions");
+            sync.peer_fault_transactions += peer_faults;
+            return Err(DownloaderImportError::Invalid);
+        }
         Ok(())
     }
 
@@ -921,16 +1416,29 @@ impl SyncHandler {
 
 #[cfg(test)]
 mod tests {
-    use ethcore::client::{ChainInfo, EachBlockWith, TestBlockChainClient};
+    use ethcore::{
+        client::{BlockChainClient, BlockInfo, ChainInfo, EachBlockWith, TestBlockChainClient},
+        snapshot::SnapshotService,
+    };
+    use parity_runtime::Executor;
     use parking_lot::RwLock;
     use rlp::Rlp;
-    use std::collections::VecDeque;
+    use std::{collections::VecDeque, sync::atomic::Ordering, thread, time::Duration};
     use tests::{helpers::TestIo, snapshot::TestSnapshotService};
 
     use super::{
-        super::tests::{dummy_sync_with_peer, get_dummy_block, get_dummy_blocks, get_dummy_hashes},
+        super::fork_filter::ForkFilterApi,
+        super::tests::{
+            dummy_sync, dummy_sync_with_peer, get_dummy_block, get_dummy_blocks, get_dummy_hashes,
+            insert_dummy_peer,
+        },
+        super::MAX_NEW_BLOCK_AGE,
         *,
     };
+    use api::SyncConfig;
+    use crypto::publickey::{Generator, Random};
+    use rlp::RlpStream;
+    use types::{encoded, transaction::Transaction};
 
     #[test]
     fn handles_peer_new_hashes() {
@@ -986,6 +1494,214 @@ mod tests {
         SyncHandler::on_peer_new_block(&mut sync, &mut io, 0, &block).expect("result to be ok");
     }
 
+    #[test]
+    fn skips_known_new_block() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Uncle);
+        let parent_hash = client.chain_info().best_block_hash;
+
+        let block_data = get_dummy_blocks(11, parent_hash);
+
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = dummy_sync_with_peer(client.block_hash_delta_minus(5), &client);
+        insert_dummy_peer(&mut sync, 1, client.block_hash_delta_minus(5));
+        insert_dummy_peer(&mut sync, 2, client.block_hash_delta_minus(5));
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        let block = Rlp::new(&block_data);
+        for peer_id in 0..3 {
+            SyncHandler::on_peer_new_block(&mut sync, &mut io, peer_id, &block)
+                .expect("result to be ok");
+        }
+
+        // imported by the first peer only
+        assert_eq!(io.chain().chain_info().best_block_number, 11);
+        assert_eq!(sync.status().num_skipped_new_blocks, 2);
+        for peer_id in 0..3 {
+            let peer = &sync.peers[&peer_id];
+            assert_eq!(peer.latest_hash, parent_hash);
+            assert_eq!(peer.difficulty, Some(U256::zero()));
+        }
+    }
+
+    fn new_block_packet(parent_hash: H256, number: u64, parent_td: U256) -> Bytes {
+        let mut header = BlockHeader::new();
+        header.set_number(number);
+        header.set_timestamp(number * 10);
+        header.set_difficulty(100.into());
+        header.set_parent_hash(parent_hash);
+        let mut block = RlpStream::new_list(3);
+        block.append(&header);
+        block.append_raw(&::rlp::EMPTY_LIST_RLP, 1);
+        block.append_raw(&::rlp::EMPTY_LIST_RLP, 1);
+        let mut packet = RlpStream::new_list(2);
+        packet.append_raw(&block.out(), 1);
+        packet.append(&(parent_td + 100));
+        packet.out()
+    }
+
+    #[test]
+    fn new_block_extending_best_block_takes_fast_path() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let best_hash = client.chain_info().best_block_hash;
+        let fork_parent = client.block_hash(BlockId::Number(9)).unwrap();
+
+        let extending = new_block_packet(best_hash, 11, client.chain_info().total_difficulty);
+        let fork = new_block_packet(fork_parent, 10, U256::zero());
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = dummy_sync_with_peer(best_hash, &client);
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        SyncHandler::on_peer_new_block(&mut sync, &mut io, 0, &Rlp::new(&extending))
+            .expect("result to be ok");
+        let hash = io.chain().chain_info().best_block_hash;
+        assert_eq!(io.chain().chain_info().best_block_number, 11);
+        assert_eq!(sync.status().num_fast_path_new_blocks, 1);
+        assert_eq!(sync.peers[&0].latest_hash, hash);
+
+        // a block on a fork follows the queue
+        SyncHandler::on_peer_new_block(&mut sync, &mut io, 0, &Rlp::new(&fork))
+            .expect("result to be ok");
+        assert_eq!(sync.status().num_fast_path_new_blocks, 1);
+        assert_eq!(io.chain.priority_imports.load(Ordering::SeqCst), 1);
+
+        assert_eq!(sync.status().new_block_import_latency, None);
+        sync.chain_new_blocks(&mut io, &[hash], &[], &[hash], &[], &[], &[]);
+        assert!(sync.status().new_block_import_latency.is_some());
+    }
+
+    #[test]
+    fn full_queue_disables_new_block_fast_path() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let best_hash = client.chain_info().best_block_hash;
+        client.queue_size.store(1, Ordering::SeqCst);
+
+        let extending = new_block_packet(best_hash, 11, client.chain_info().total_difficulty);
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = dummy_sync_with_peer(best_hash, &client);
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        SyncHandler::on_peer_new_block(&mut sync, &mut io, 0, &Rlp::new(&extending))
+            .expect("result to be ok");
+        assert_eq!(io.chain().chain_info().best_block_number, 11);
+        assert_eq!(sync.status().num_fast_path_new_blocks, 0);
+        assert_eq!(io.chain.priority_imports.load(Ordering::SeqCst), 0);
+        assert_eq!(sync.peers[&0].latest_hash, best_hash);
+    }
+
+    fn injected_block(parent_hash: H256, number: u64) -> Bytes {
+        let packet = new_block_packet(parent_hash, number, U256::zero());
+        Rlp::new(&packet).at(0).unwrap().as_raw().to_vec()
+    }
+
+    #[test]
+    fn injected_block_is_imported_like_a_new_block() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let best_hash = client.chain_info().best_block_hash;
+        let block = injected_block(best_hash, 11);
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = dummy_sync_with_peer(best_hash, &client);
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        let hash =
+            SyncHandler::inject_block(&mut sync, &mut io, block, false).expect("valid next block");
+
+        assert_eq!(io.chain().chain_info().best_block_hash, hash);
+        assert_eq!(io.chain.priority_imports.load(Ordering::SeqCst), 1);
+        assert_eq!(sync.new_blocks.last_imported_block_number(), 11);
+        assert_eq!(sync.status().highest_block_number, Some(11));
+    }
+
+    #[test]
+    fn injected_block_errors() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let best_hash = client.chain_info().best_block_hash;
+        let block = injected_block(best_hash, 11);
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = dummy_sync_with_peer(best_hash, &client);
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        assert!(matches!(
+            SyncHandler::inject_block(&mut sync, &mut io, vec![0xc2, 0x01], true),
+            Err(InjectError::Decoder(_))
+        ));
+        let hash = SyncHandler::inject_block(&mut sync, &mut io, block.clone(), true)
+            .expect("valid next block");
+        assert_eq!(
+            SyncHandler::inject_block(&mut sync, &mut io, block, true),
+            Err(InjectError::AlreadyKnown)
+        );
+
+        io.chain.queue_size.store(1, Ordering::SeqCst);
+        assert_eq!(
+            SyncHandler::inject_block(&mut sync, &mut io, injected_block(hash, 12), true),
+            Err(InjectError::QueueFull)
+        );
+        assert_eq!(io.chain().chain_info().best_block_number, 11);
+    }
+
+    #[test]
+    fn new_block_age_adapts_to_block_interval() {
+        let mut client = TestBlockChainClient::new();
+        // one block per second
+        for _ in 0..100 {
+            client.add_block(EachBlockWith::Nothing, |mut header| {
+                let number = header.number();
+                header.set_timestamp(number);
+                header
+            });
+        }
+        let enacted: Vec<_> = (60..=100)
+            .map(|n| client.block_hash(BlockId::Number(n)).unwrap())
+            .collect();
+        let parent_hash = client.block_hash(BlockId::Number(69)).unwrap();
+
+        // 30 blocks and 30 seconds behind the last imported block
+        let mut header = BlockHeader::new();
+        header.set_number(70);
+        header.set_timestamp(70);
+        header.set_parent_hash(parent_hash);
+        header.set_extra_data(b"fork".to_vec());
+        let mut block = RlpStream::new_list(3);
+        block.append(&header);
+        block.append_raw(&::rlp::EMPTY_LIST_RLP, 1);
+        block.append_raw(&::rlp::EMPTY_LIST_RLP, 1);
+        let mut packet = RlpStream::new_list(2);
+        packet.append_raw(&block.out(), 1);
+        packet.append(&U256::from(100));
+        let packet = packet.out();
+
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut fallback = dummy_sync_with_peer(parent_hash, &client);
+        let mut fixed = dummy_sync_with_peer(parent_hash, &client);
+        fixed.max_new_block_age = Some(MAX_NEW_BLOCK_AGE);
+        let mut adaptive = dummy_sync_with_peer(parent_hash, &client);
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        fixed.chain_new_blocks(&mut io, &[], &[], &enacted, &[], &[], &[]);
+        adaptive.chain_new_blocks(&mut io, &[], &[], &enacted, &[], &[], &[]);
+
+        assert_eq!(fallback.max_new_block_age(), MAX_NEW_BLOCK_AGE);
+        assert_eq!(fixed.max_new_block_age(), MAX_NEW_BLOCK_AGE);
+        assert_eq!(adaptive.max_new_block_age(), 240);
+        assert_eq!(adaptive.max_new_hashes(), 240);
+
+        let rlp = Rlp::new(&packet);
+        assert!(SyncHandler::on_peer_new_block(&mut fallback, &mut io, 0, &rlp).is_err());
+        assert!(SyncHandler::on_peer_new_block(&mut fixed, &mut io, 0, &rlp).is_err());
+        SyncHandler::on_peer_new_block(&mut adaptive, &mut io, 0, &rlp)
+            .expect("recent block to be accepted");
+    }
+
     #[test]
     fn handles_peer_new_block_empty() {
         let mut client = TestBlockChainClient::new();
@@ -1019,4 +1735,408 @@ mod tests {
 
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn pooled_transactions_penalize_only_peer_faults() {
+        let mut client = TestBlockChainClient::new();
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = dummy_sync_with_peer(H256::zero(), &client);
+        insert_dummy_peer(&mut sync, 1, H256::zero());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        let tx =
+            TypedTransaction::Legacy(Transaction::default()).sign(Random.generate().secret(), None);
+        let hash = tx.hash();
+        let not_sent = H256::random();
+        sync.peers.get_mut(&0).unwrap().asking_pooled_transactions = vec![hash, not_sent];
+
+        // one valid transaction and one undecodable entry
+        let mut response = RlpStream::new_list(2);
+        tx.rlp_append(&mut response);
+        response.append_raw(&[0xc1, 0x80], 1);
+        let response = response.out();
+
+        let result =
+            SyncHandler::on_peer_pooled_transactions(&mut sync, &mut io, 0, &Rlp::new(&response));
+        assert_eq!(result, Err(DownloaderImportError::Invalid));
+        assert_eq!(sync.status().num_peer_fault_transactions, 1);
+        assert!(sync.peers[&0].asking_pooled_transactions.is_empty());
+        assert!(sync.peers[&0]
+            .unfetched_pooled_transactions
+            .contains(&not_sent));
+
+        // the valid transaction is rejected by the local pool policy
+        sync.transactions_rejected(&[hash], 0);
+        assert_eq!(sync.status().num_policy_rejected_transactions, 1);
+        assert_eq!(sync.status().num_peer_fault_transactions, 1);
+
+        // and is not fetched again when announced by another peer
+        let mut announcement = RlpStream::new_list(1);
+        announcement.append(&hash);
+        let announcement = announcement.out();
+        SyncHandler::on_peer_new_pooled_transaction_hashes(
+            &mut sync,
+            &mut io,
+            1,
+            &Rlp::new(&announcement),
+        )
+        .unwrap();
+        assert!(sync.peers[&1].unfetched_pooled_transactions.is_empty());
+
+        // a transaction which was not requested is a peer fault as well
+        sync.peers.get_mut(&0).unwrap().asking_pooled_transactions = vec![H256::random()];
+        let mut response = RlpStream::new_list(1);
+        tx.rlp_append(&mut response);
+        let response = response.out();
+        let result =
+            SyncHandler::on_peer_pooled_transactions(&mut sync, &mut io, 0, &Rlp::new(&response));
+        assert_eq!(result, Err(DownloaderImportError::Invalid));
+        assert_eq!(sync.status().num_peer_fault_transactions, 2);
+    }
+
+    #[test]
+    fn peer_of_other_network_is_avoided_not_banned() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(1, EachBlockWith::Nothing);
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = dummy_sync(&client);
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        io.par_protocol_version = 0;
+
+        // our own status with another genesis hash
+        let (_, status) = sync.status_packet(&io, 1);
+        let mut packet = RlpStream::new_list(Rlp::new(&status).item_count().unwrap());
+        for (i, item) in Rlp::new(&status).iter().enumerate() {
+            if i == 4 {
+                packet.append(&H256::from_low_u64_be(0x1234));
+            } else {
+                packet.append_raw(item.as_raw(), 1);
+            }
+        }
+        let packet = packet.out();
+
+        let result = SyncHandler::on_peer_status(&mut sync, &mut io, 1, &Rlp::new(&packet));
+        assert_eq!(result, Err(DownloaderImportError::Useless));
+        assert!(io.to_avoid.contains(&1));
+        assert!(!sync.peers.contains_key(&1));
+
+        // the untouched status is accepted
+        let result = SyncHandler::on_peer_status(&mut sync, &mut io, 2, &Rlp::new(&status));
+        assert_eq!(result, Ok(()));
+        assert!(!io.to_avoid.contains(&2));
+    }
+
+    fn status_with_difficulty(status: &[u8], difficulty: U256) -> Vec<u8> {
+        let status = Rlp::new(status);
+        let mut packet = RlpStream::new_list(status.item_count().unwrap());
+        for (i, item) in status.iter().enumerate() {
+            if i == 2 {
+                packet.append(&difficulty);
+            } else {
+                packet.append_raw(item.as_raw(), 1);
+            }
+        }
+        packet.out().to_vec()
+    }
+
+    fn sent_to(io: &TestIo<TestBlockChainClient>, peer_id: PeerId, packet: SyncPacket) -> usize {
+        io.packets
+            .iter()
+            .filter(|p| p.recipient == peer_id && p.packet_id == packet.id())
+            .count()
+    }
+
+    #[test]
+    fn peer_below_genesis_difficulty_is_disconnected() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(1, EachBlockWith::Nothing);
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = dummy_sync(&client);
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        io.par_protocol_version = 0;
+
+        let (_, status) = sync.status_packet(&io, 1);
+        let packet = status_with_difficulty(&status, U256::zero());
+        SyncHandler::on_packet(&mut sync, &mut io, 1, StatusPacket.id(), &packet);
+        assert!(io.to_disconnect.contains(&1));
+        assert!(!sync.peers.contains_key(&1));
+    }
+
+    #[test]
+    fn implausible_difficulty_peer_is_probed_once() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = dummy_sync(&client);
+        sync.max_block_difficulty = Some(U256::one() << 129);
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        io.par_protocol_version = 0;
+
+        let (_, status) = sync.status_packet(&io, 1);
+        let our_difficulty = io.chain().chain_info().total_difficulty;
+        let honest = status_with_difficulty(&status, our_difficulty + 1);
+        SyncHandler::on_packet(&mut sync, &mut io, 2, StatusPacket.id(), &honest);
+        assert_eq!(sync.peers[&2].difficulty_check, DifficultyCheck::Plausible);
+        assert_eq!(sync.peers[&2].asking, PeerAsking::BlockHeaders);
+
+        let claimed = U256::one() << 255;
+        let implausible = status_with_difficulty(&status, claimed);
+        SyncHandler::on_packet(&mut sync, &mut io, 1, StatusPacket.id(), &implausible);
+        let peer = &sync.peers[&1];
+        assert_eq!(peer.difficulty_check, DifficultyCheck::Unverified(claimed));
+        assert_eq!(peer.difficulty, None);
+        assert_eq!(peer.asking, PeerAsking::DifficultyProbe);
+        assert_eq!(sent_to(&io, 1, GetBlockHeadersPacket), 1);
+
+        // the peer cannot back its claim with its best header
+        let empty = RlpStream::new_list(0).out();
+        SyncHandler::on_peer_block_headers(&mut sync, &mut io, 1, &Rlp::new(&empty)).unwrap();
+        assert_eq!(sync.peers[&1].difficulty_check, DifficultyCheck::Failed);
+        for _ in 0..3 {
+            sync.sync_peer(&mut io, 1, false);
+        }
+        assert_eq!(sync.peers[&1].asking, PeerAsking::Nothing);
+        assert_eq!(sent_to(&io, 1, GetBlockHeadersPacket), 1);
+        assert!(!io.to_disconnect.contains(&1));
+    }
+
+    #[test]
+    fn difficulty_probe_accepts_long_enough_chain() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let mut sync = dummy_sync(&client);
+        sync.max_block_difficulty = Some(100.into());
+        let best = client.block_header(BlockId::Number(10)).unwrap();
+        insert_dummy_peer(&mut sync, 1, best.hash());
+        sync.peers.get_mut(&1).unwrap().difficulty_check = DifficultyCheck::Unverified(1100.into());
+        sync.peers.get_mut(&1).unwrap().asking = PeerAsking::DifficultyProbe;
+
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        let mut headers = RlpStream::new_list(1);
+        headers.append_raw(best.rlp().as_raw(), 1);
+        let headers = headers.out();
+        SyncHandler::on_peer_block_headers(&mut sync, &mut io, 1, &Rlp::new(&headers)).unwrap();
+
+        let peer = &sync.peers[&1];
+        assert_eq!(peer.difficulty_check, DifficultyCheck::Plausible);
+        assert_eq!(peer.difficulty, Some(1100.into()));
+    }
+
+    fn sync_with_forks(
+        client: &TestBlockChainClient,
+        config: SyncConfig,
+        forks: Vec<BlockNumber>,
+    ) -> ChainSync {
+        let (_, transaction_hashes_rx) = crossbeam_channel::unbounded();
+        ChainSync::new(
+            config,
+            client,
+            ForkFilterApi::new(client, forks),
+            transaction_hashes_rx,
+        )
+    }
+
+    fn requested_header(io: &TestIo<TestBlockChainClient>, peer_id: PeerId) -> BlockNumber {
+        let request = io
+            .packets
+            .iter()
+            .find(|p| p.recipient == peer_id && p.packet_id == GetBlockHeadersPacket.id())
+            .expect("headers were requested");
+        Rlp::new(&request.data).val_at(0).unwrap()
+    }
+
+    fn headers_packet(header: &encoded::Header) -> Vec<u8> {
+        let mut headers = RlpStream::new_list(1);
+        headers.append_raw(header.rlp().as_raw(), 1);
+        headers.out().to_vec()
+    }
+
+    #[test]
+    fn legacy_peers_confirm_derived_fork_block() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let other_chain = TestBlockChainClient::new();
+        other_chain.add_blocks(10, EachBlockWith::Uncle);
+        let ours = client.block_header(BlockId::Number(5)).unwrap();
+        let theirs = other_chain.block_header(BlockId::Number(5)).unwrap();
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = sync_with_forks(&client, SyncConfig::default(), vec![2, 5, 20]);
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        io.par_protocol_version = 0;
+
+        // peers announcing a fork id are not asked
+        let (_, status) = sync.status_packet(&io, 3);
+        SyncHandler::on_packet(&mut sync, &mut io, 3, StatusPacket.id(), &status);
+        assert_eq!(sync.peers[&3].confirmation, ForkConfirmation::Confirmed);
+        assert_eq!(sent_to(&io, 3, GetBlockHeadersPacket), 0);
+
+        io.eth_protocol_version = ETH_PROTOCOL_VERSION_63.0;
+        let (_, status) = sync.status_packet(&io, 1);
+        for peer_id in 1..3 {
+            SyncHandler::on_packet(&mut sync, &mut io, peer_id, StatusPacket.id(), &status);
+            assert_eq!(
+                sync.peers[&peer_id].confirmation,
+                ForkConfirmation::Unconfirmed
+            );
+            assert_eq!(sync.peers[&peer_id].asking, PeerAsking::ForkHeader);
+            assert_eq!(requested_header(&io, peer_id), 5);
+        }
+
+        let packet = headers_packet(&theirs);
+        SyncHandler::on_packet(&mut sync, &mut io, 1, BlockHeadersPacket.id(), &packet);
+        assert!(io.to_disconnect.contains(&1));
+        assert!(sync.peers.get(&1).map_or(true, |peer| !peer.can_sync()));
+
+        let packet = headers_packet(&ours);
+        SyncHandler::on_packet(&mut sync, &mut io, 2, BlockHeadersPacket.id(), &packet);
+        assert!(!io.to_disconnect.contains(&2));
+        assert_eq!(sync.peers[&2].confirmation, ForkConfirmation::Confirmed);
+    }
+
+    #[test]
+    fn fork_header_is_checked_against_the_requested_checkpoint() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let ours = client.block_header(BlockId::Number(5)).unwrap();
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = sync_with_forks(&client, SyncConfig::default(), vec![2, 5, 20]);
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        io.par_protocol_version = 0;
+        io.eth_protocol_version = ETH_PROTOCOL_VERSION_63.0;
+
+        let (_, status) = sync.status_packet(&io, 1);
+        SyncHandler::on_packet(&mut sync, &mut io, 1, StatusPacket.id(), &status);
+        assert_eq!(requested_header(&io, 1), 5);
+
+        // the derived fork block moves on while the request is in flight
+        io.chain.add_blocks(15, EachBlockWith::Nothing);
+        sync.update_derived_fork_block(io.chain);
+        assert_eq!(
+            sync.fork_checkpoint(ETH_PROTOCOL_VERSION_63.0).unwrap().0,
+            20
+        );
+
+        let packet = headers_packet(&ours);
+        SyncHandler::on_packet(&mut sync, &mut io, 1, BlockHeadersPacket.id(), &packet);
+        assert!(!io.to_disconnect.contains(&1));
+        assert_eq!(sync.peers[&1].confirmation, ForkConfirmation::Confirmed);
+    }
+
+    #[test]
+    fn configured_fork_block_overrides_derived_one() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let mut config = SyncConfig::default();
+        config.fork_block = Some((3, client.block_hash(BlockId::Number(3)).unwrap()));
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = sync_with_forks(&client, config, vec![5]);
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        io.par_protocol_version = 0;
+        io.eth_protocol_version = ETH_PROTOCOL_VERSION_63.0;
+
+        let (_, status) = sync.status_packet(&io, 1);
+        SyncHandler::on_packet(&mut sync, &mut io, 1, StatusPacket.id(), &status);
+        assert_eq!(sync.peers[&1].asking, PeerAsking::ForkHeader);
+        assert_eq!(requested_header(&io, 1), 3);
+    }
+
+    #[test]
+    fn tampered_snapshot_chunk_is_requested_again() {
+        let mut client = TestBlockChainClient::new();
+        let mut sync = dummy_sync(&client);
+        let ss = TestSnapshotService::new_with_snapshot(2, H256::zero(), 100);
+        let manifest = ss.manifest().unwrap();
+        let manifest_hash = keccak(manifest.clone().into_rlp());
+        ss.begin_restore(manifest.clone());
+        sync.snapshot.reset_to(&manifest, &manifest_hash);
+        sync.state = SyncState::SnapshotData;
+        for peer in 1..3 {
+            insert_dummy_peer(&mut sync, peer, H256::zero());
+            sync.peers.get_mut(&peer).unwrap().snapshot_hash = Some(manifest_hash);
+            sync.active_peers.insert(peer);
+        }
+
+        let queue = RwLock::new(VecDeque::new());
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        SyncRequester::request_snapshot_data(&mut sync, &mut io, 1);
+        SyncRequester::request_snapshot_data(&mut sync, &mut io, 2);
+        let tampered_hash = sync.peers[&1].asking_snapshot_data.unwrap();
+        let good_hash = sync.peers[&2].asking_snapshot_data.unwrap();
+
+        let chunk_packet = |chunk: Bytes| {
+            let mut rlp = RlpStream::new_list(1);
+            rlp.append(&chunk);
+            rlp.out()
+        };
+        let mut tampered = ss.chunk(tampered_hash).unwrap();
+        tampered[0] ^= 1;
+        let good = ss.chunk(good_hash).unwrap();
+        SyncHandler::on_packet(
+            &mut sync,
+            &mut io,
+            1,
+            SnapshotDataPacket.id(),
+            &chunk_packet(tampered),
+        );
+        SyncHandler::on_packet(
+            &mut sync,
+            &mut io,
+            2,
+            SnapshotDataPacket.id(),
+            &chunk_packet(good),
+        );
+
+        assert_eq!(ss.completed_chunks(), Some(vec![good_hash]));
+        assert!(io.to_disconnect.contains(&1));
+        assert!(!sync.active_peers.contains(&1));
+        assert!(!io.to_disconnect.contains(&2));
+        assert_eq!(sync.status().num_bad_snapshot_chunks, 1);
+        // the good peer is asked for the chunk next
+        assert_eq!(sync.peers[&2].asking, PeerAsking::SnapshotData);
+        assert_eq!(sync.peers[&2].asking_snapshot_data, Some(tampered_hash));
+    }
+
+    #[test]
+    fn snapshot_chunk_hashed_off_thread_is_imported() {
+        let mut client = TestBlockChainClient::new();
+        let mut sync = dummy_sync(&client);
+        sync.set_executor(Executor::new_thread_per_future());
+        let ss = TestSnapshotService::new_with_snapshot(2, H256::zero(), 100);
+        let manifest = ss.manifest().unwrap();
+        let manifest_hash = keccak(manifest.clone().into_rlp());
+        ss.begin_restore(manifest.clone());
+        sync.snapshot.reset_to(&manifest, &manifest_hash);
+        sync.state = SyncState::SnapshotData;
+        insert_dummy_peer(&mut sync, 1, H256::zero());
+        sync.peers.get_mut(&1).unwrap().snapshot_hash = Some(manifest_hash);
+        sync.active_peers.insert(1);
+
+        let queue = RwLock::new(VecDeque::new());
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        SyncRequester::request_snapshot_data(&mut sync, &mut io, 1);
+        let hash = sync.peers[&1].asking_snapshot_data.unwrap();
+        let mut rlp = RlpStream::new_list(1);
+        rlp.append(&ss.chunk(hash).unwrap());
+        SyncHandler::on_packet(&mut sync, &mut io, 1, SnapshotDataPacket.id(), &rlp.out());
+        // not requested again while it is hashed
+        assert_ne!(sync.peers[&1].asking_snapshot_data, Some(hash));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while ss.completed_chunks() != Some(vec![hash]) {
+            assert!(Instant::now() < deadline, "chunk was not imported");
+            thread::sleep(Duration::from_millis(10));
+            SyncHandler::import_hashed_chunks(&mut sync, &mut io);
+        }
+        assert!(!io.to_disconnect.contains(&1));
+        assert_eq!(sync.status().num_bad_snapshot_chunks, 0);
+    }
 }
@@ -0,0 +1,632 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Self-check of the sync state for the usual reasons of a node not syncing.
+
+use super::fork_filter::RejectReason;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+use types::BlockNumber;
+
+/// Time the block queue may stay at capacity before it is reported.
+const QUEUE_FULL_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// Queue fill level in percent considered at capacity.
+const QUEUE_FULL_PERCENT: usize = 90;
+/// Time snapshot restoration may go without a chunk being restored.
+const SNAPSHOT_STALL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Time the best block may stay unchanged while peers are ahead.
+const BEST_BLOCK_STALL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Time the ancient blocks download may go without a block being imported.
+const ANCIENT_STALL_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// Age of the handshake rejections taken into account.
+const RECENT_REJECTIONS: Duration = Duration::from_secs(10 * 60);
+/// Handshake rejections remembered, by kind.
+const MAX_REJECTIONS: usize = 64;
+
+/// Severity of a health finding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HealthSeverity {
+    /// Worth knowing, sync is not affected.
+    Info,
+    /// Sync is slowed down or may stop.
+    Warning,
+    /// Sync does not make progress.
+    Error,
+}
+
+/// Kind of a health finding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthCode {
+    /// No peers are connected.
+    NoPeers,
+    /// None of the connected peers is confirmed to be on our chain.
+    NoConfirmedPeers,
+    /// Peers of another network id tried to connect.
+    NetworkIdMismatch,
+    /// The block queue stays at capacity.
+    QueueFull,
+    /// Snapshot restoration does not make progress.
+    SnapshotStalled,
+    /// The best block does not advance while peers are ahead.
+    BestBlockStalled,
+    /// The ancient blocks download does not make progress.
+    AncientBlocksStalled,
+}
+
+impl HealthCode {
+    /// Machine readable name of the finding.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            HealthCode::NoPeers => "no_peers",
+            HealthCode::NoConfirmedPeers => "no_confirmed_peers",
+            HealthCode::NetworkIdMismatch => "network_id_mismatch",
+            HealthCode::QueueFull => "queue_full",
+            HealthCode::SnapshotStalled => "snapshot_stalled",
+            HealthCode::BestBlockStalled => "best_block_stalled",
+            HealthCode::AncientBlocksStalled => "ancient_blocks_stalled",
+        }
+    }
+}
+
+/// A problem found by the sync health check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthFinding {
+    /// Kind of the problem.
+    pub code: HealthCode,
+    /// Severity of the problem.
+    pub severity: HealthSeverity,
+    /// Description for humans.
+    pub message: String,
+    /// Numbers behind the finding, by name.
+    pub details: Vec<(&'static str, u64)>,
+}
+
+impl HealthFinding {
+    /// Number named `name` behind the finding.
+    pub fn detail(&self, name: &str) -> Option<u64> {
+        self.details
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| *value)
+    }
+}
+
+/// Findings of the sync health check. No findings means no problem was found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncHealthReport {
+    /// Problems found, most severe first.
+    pub findings: Vec<HealthFinding>,
+}
+
+impl SyncHealthReport {
+    /// Check if no problem was found.
+    pub fn is_healthy(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// The finding of kind `code`, if any.
+    pub fn finding(&self, code: HealthCode) -> Option<&HealthFinding> {
+        self.findings.iter().find(|f| f.code == code)
+    }
+
+    fn push(
+        &mut self,
+        code: HealthCode,
+        severity: HealthSeverity,
+        message: String,
+        details: Vec<(&'static str, u64)>,
+    ) {
+        self.findings.push(HealthFinding {
+            code,
+            severity,
+            message,
+            details,
+        });
+    }
+}
+
+/// Sync state checked for problems.
+#[derive(Debug, Clone, Default)]
+pub struct HealthSample {
+    /// Network id of the node.
+    pub network_id: u64,
+    /// Connected peers.
+    pub peers: usize,
+    /// Connected peers confirmed to be on our chain.
+    pub confirmed_peers: usize,
+    /// Blocks in the verification queue.
+    pub queue_size: usize,
+    /// Capacity of the verification queue.
+    pub max_queue_size: usize,
+    /// The verification queue does not take more blocks.
+    pub queue_full: bool,
+    /// Best block of the chain.
+    pub best_block: BlockNumber,
+    /// Highest block advertised by peers.
+    pub highest_block: Option<BlockNumber>,
+    /// Chunks restored and total chunks while a snapshot is restored.
+    pub snapshot_chunks: Option<(usize, usize)>,
+    /// Last imported ancient block while ancient blocks are downloaded.
+    pub ancient_block: Option<BlockNumber>,
+}
+
+impl HealthSample {
+    fn queue_at_capacity(&self) -> bool {
+        self.queue_full
+            || (self.max_queue_size > 0
+                && self.queue_size * 100 >= self.max_queue_size * QUEUE_FULL_PERCENT)
+    }
+}
+
+/// A progress counter and since when it has its value.
+struct Progress {
+    value: Option<u64>,
+    since: Instant,
+}
+
+impl Progress {
+    fn new(now: Instant) -> Self {
+        Progress {
+            value: None,
+            since: now,
+        }
+    }
+
+    fn observe(&mut self, now: Instant, value: Option<u64>) {
+        if value != self.value {
+            self.value = value;
+            self.since = now;
+        }
+    }
+
+    /// Time the counter is at `value` already.
+    fn stuck_for(&self, now: Instant, value: Option<u64>) -> Option<Duration> {
+        match value {
+            Some(_) if value == self.value => Some(now.saturating_duration_since(self.since)),
+            _ => None,
+        }
+    }
+}
+
+/// Keeps track of the sync state over time for the health check.
+pub struct HealthMonitor {
+    queue_full_since: Option<Instant>,
+    best_block: Progress,
+    snapshot: Progress,
+    ancient: Progress,
+    fork_rejections: VecDeque<(Instant, RejectReason)>,
+    network_id_mismatches: VecDeque<(Instant, u64)>,
+}
+
+impl HealthMonitor {
+    /// Create a monitor without any history.
+    pub fn new(now: Instant) -> Self {
+        HealthMonitor {
+            queue_full_since: None,
+            best_block: Progress::new(now),
+            snapshot: Progress::new(now),
+            ancient: Progress::new(now),
+            fork_rejections: VecDeque::new(),
+            network_id_mismatches: VecDeque::new(),
+        }
+    }
+
+    /// Note a peer rejected in the handshake for an incompatible fork id.
+    pub fn note_fork_rejection(&mut self, now: Instant, reason: RejectReason) {
+        if self.fork_rejections.len() == MAX_REJECTIONS {
+            self.fork_rejections.pop_front();
+        }
+        self.fork_rejections.push_back((now, reason));
+    }
+
+    /// Note a peer rejected in the handshake for using network id `network_id`.
+    pub fn note_network_id_mismatch(&mut self, now: Instant, network_id: u64) {
+        if self.network_id_mismatches.len() == MAX_REJECTIONS {
+            self.network_id_mismatches.pop_front();
+        }
+        self.network_id_mismatches.push_back((now, network_id));
+    }
+
+    /// Take the current sync state into account, called periodically.
+    pub fn observe(&mut self, now: Instant, sample: &HealthSample) {
+        if sample.queue_at_capacity() {
+            self.queue_full_since.get_or_insert(now);
+        } else {
+            self.queue_full_since = None;
+        }
+        self.best_block.observe(now, Some(sample.best_block));
+        self.snapshot
+            .observe(now, sample.snapshot_chunks.map(|(done, _)| done as u64));
+        self.ancient.observe(now, sample.ancient_block);
+    }
+
+    /// Check the sync state for problems.
+    pub fn report(&self, now: Instant, sample: &HealthSample) -> SyncHealthReport {
+        let mut report = SyncHealthReport::default();
+        self.check_peers(now, sample, &mut report);
+        self.check_network_id(now, sample, &mut report);
+
+        if let Some(since) = self.queue_full_since.filter(|_| sample.queue_at_capacity()) {
+            let duration = now.saturating_duration_since(since);
+            if duration >= QUEUE_FULL_TIMEOUT {
+                report.push(
+                    HealthCode::QueueFull,
+                    HealthSeverity::Warning,
+                    format!(
+                        "Block queue is at capacity ({}/{}) for {}s, verification does not keep up",
+                        sample.queue_size,
+                        sample.max_queue_size,
+                        duration.as_secs()
+                    ),
+                    vec![
+                        ("queue_size", sample.queue_size as u64),
+                        ("max_queue_size", sample.max_queue_size as u64),
+                        ("seconds", duration.as_secs()),
+                    ],
+                );
+            }
+        }
+
+        if let Some((done, total)) = sample.snapshot_chunks {
+            if let Some(duration) = self
+                .snapshot
+                .stuck_for(now, Some(done as u64))
+                .filter(|d| *d >= SNAPSHOT_STALL_TIMEOUT)
+            {
+                report.push(
+                    HealthCode::SnapshotStalled,
+                    HealthSeverity::Error,
+                    format!(
+                        "Snapshot restoration is stuck at {}/{} chunks for {}s",
+                        done,
+                        total,
+                        duration.as_secs()
+                    ),
+                    vec![
+                        ("chunks_done", done as u64),
+                        ("chunks_total", total as u64),
+                        ("seconds", duration.as_secs()),
+                    ],
+                );
+            }
+        }
+
+        if let Some(highest) = sample.highest_block.filter(|h| *h > sample.best_block) {
+            if let Some(duration) = self
+                .best_block
+                .stuck_for(now, Some(sample.best_block))
+                .filter(|d| *d >= BEST_BLOCK_STALL_TIMEOUT)
+            {
+                report.push(
+                    HealthCode::BestBlockStalled,
+                    HealthSeverity::Error,
+                    format!(
+                        "Best block #{} did not advance for {}s while peers are at #{}",
+                        sample.best_block,
+                        duration.as_secs(),
+                        highest
+                    ),
+                    vec![
+                        ("best_block", sample.best_block),
+                        ("highest_block", highest),
+                        ("seconds", duration.as_secs()),
+                    ],
+                );
+            }
+        }
+
+        if let Some(duration) = self
+            .ancient
+            .stuck_for(now, sample.ancient_block)
+            .filter(|d| *d >= ANCIENT_STALL_TIMEOUT)
+        {
+            let ancient_block = sample.ancient_block.unwrap_or(0);
+            report.push(
+                HealthCode::AncientBlocksStalled,
+                HealthSeverity::Warning,
+                format!(
+                    "Ancient blocks download is stuck at #{} for {}s",
+                    ancient_block,
+                    duration.as_secs()
+                ),
+                vec![
+                    ("ancient_block", ancient_block),
+                    ("seconds", duration.as_secs()),
+                ],
+            );
+        }
+
+        report.findings.sort_by(|a, b| b.severity.cmp(&a.severity));
+        report
+    }
+
+    fn check_peers(&self, now: Instant, sample: &HealthSample, report: &mut SyncHealthReport) {
+        if sample.confirmed_peers > 0 {
+            return;
+        }
+        let (remote_stale, local_incompatible) = self
+            .fork_rejections
+            .iter()
+            .filter(|(at, _)| now.saturating_duration_since(*at) < RECENT_REJECTIONS)
+            .fold((0, 0), |(remote, local), (_, reason)| match reason {
+                RejectReason::RemoteStale => (remote + 1, local),
+                RejectReason::LocalIncompatibleOrStale => (remote, local + 1),
+            });
+        let rejections = match (remote_stale, local_incompatible) {
+            (0, 0) => String::new(),
+            (remote, local) if remote > local => format!(
+                ", {remote} of {} recently rejected peers run outdated software",
+                remote + local
+            ),
+            (remote, local) => format!(
+                ", {local} of {} recently rejected peers consider our chain incompatible or stale",
+                remote + local
+            ),
+        };
+        let details = vec![
+            ("peers", sample.peers as u64),
+            ("rejected_remote_stale", remote_stale),
+            ("rejected_local_incompatible", local_incompatible),
+        ];
+        if sample.peers == 0 {
+            report.push(
+                HealthCode::NoPeers,
+                HealthSeverity::Error,
+                format!("No peers connected{rejections}"),
+                details,
+            );
+        } else {
+            report.push(
+                HealthCode::NoConfirmedPeers,
+                HealthSeverity::Error,
+                format!(
+                    "None of {} connected peers is confirmed to be on our chain{}",
+                    sample.peers, rejections
+                ),
+                details,
+            );
+        }
+    }
+
+    fn check_network_id(&self, now: Instant, sample: &HealthSample, report: &mut SyncHealthReport) {
+        let recent: Vec<u64> = self
+            .network_id_mismatches
+            .iter()
+            .filter(|(at, _)| now.saturating_duration_since(*at) < RECENT_REJECTIONS)
+            .map(|(_, id)| *id)
+            .collect();
+        let (network_id, count) = match recent
+            .iter()
+            .map(|id| (*id, recent.iter().filter(|other| *other == id).count()))
+            .max_by_key(|(_, count)| *count)
+        {
+            Some(most_common) => most_common,
+            None => return,
+        };
+        // peers of other networks are common, they matter if they are all we see
+        let severity = if sample.confirmed_peers == 0 {
+            HealthSeverity::Warning
+        } else {
+            HealthSeverity::Info
+        };
+        report.push(
+            HealthCode::NetworkIdMismatch,
+            severity,
+            format!(
+                "{} peers recently used another network id, most of them {} (ours is {})",
+                recent.len(),
+                network_id,
+                sample.network_id
+            ),
+            vec![
+                ("mismatches", recent.len() as u64),
+                ("network_id", network_id),
+                ("mismatches_with_network_id", count as u64),
+                ("our_network_id", sample.network_id),
+            ],
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn healthy() -> HealthSample {
+        HealthSample {
+            network_id: 1,
+            peers: 5,
+            confirmed_peers: 5,
+            queue_size: 10,
+            max_queue_size: 1000,
+            best_block: 100,
+            highest_block: Some(100),
+            ..Default::default()
+        }
+    }
+
+    fn codes(report: &SyncHealthReport) -> Vec<HealthCode> {
+        report.findings.iter().map(|f| f.code).collect()
+    }
+
+    #[test]
+    fn healthy_state_has_no_findings() {
+        let start = Instant::now();
+        let mut monitor = HealthMonitor::new(start);
+        let sample = healthy();
+        monitor.observe(start, &sample);
+
+        let report = monitor.report(start + Duration::from_secs(3600), &sample);
+        assert!(report.is_healthy(), "{report:?}");
+    }
+
+    #[test]
+    fn reports_missing_peers_with_dominant_rejection() {
+        let now = Instant::now();
+        let mut monitor = HealthMonitor::new(now);
+        monitor.note_fork_rejection(now, RejectReason::LocalIncompatibleOrStale);
+        monitor.note_fork_rejection(now, RejectReason::LocalIncompatibleOrStale);
+        monitor.note_fork_rejection(now, RejectReason::RemoteStale);
+        let mut sample = healthy();
+        sample.peers = 0;
+        sample.confirmed_peers = 0;
+
+        let report = monitor.report(now, &sample);
+        assert_eq!(codes(&report), vec![HealthCode::NoPeers]);
+        let finding = &report.findings[0];
+        assert_eq!(finding.severity, HealthSeverity::Error);
+        assert_eq!(finding.detail("rejected_local_incompatible"), Some(2));
+        assert_eq!(finding.detail("rejected_remote_stale"), Some(1));
+        assert!(finding.message.contains("incompatible"));
+
+        sample.peers = 3;
+        let report = monitor.report(now, &sample);
+        assert_eq!(codes(&report), vec![HealthCode::NoConfirmedPeers]);
+        assert_eq!(report.findings[0].detail("peers"), Some(3));
+
+        // old rejections are not reported
+        let later = now + RECENT_REJECTIONS;
+        let report = monitor.report(later, &sample);
+        assert_eq!(
+            report.findings[0].detail("rejected_local_incompatible"),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn reports_network_id_mismatches() {
+        let now = Instant::now();
+        let mut monitor = HealthMonitor::new(now);
+        for id in &[5, 5, 42] {
+            monitor.note_network_id_mismatch(now, *id);
+        }
+
+        let report = monitor.report(now, &healthy());
+        let finding = report.finding(HealthCode::NetworkIdMismatch).unwrap();
+        assert_eq!(finding.severity, HealthSeverity::Info);
+        assert_eq!(finding.detail("mismatches"), Some(3));
+        assert_eq!(finding.detail("network_id"), Some(5));
+        assert_eq!(finding.detail("mismatches_with_network_id"), Some(2));
+        assert_eq!(finding.detail("our_network_id"), Some(1));
+
+        let mut sample = healthy();
+        sample.confirmed_peers = 0;
+        let report = monitor.report(now, &sample);
+        let finding = report.finding(HealthCode::NetworkIdMismatch).unwrap();
+        assert_eq!(finding.severity, HealthSeverity::Warning);
+    }
+
+    #[test]
+    fn reports_queue_at_capacity_for_too_long() {
+        let start = Instant::now();
+        let mut monitor = HealthMonitor::new(start);
+        let mut sample = healthy();
+        sample.queue_size = 950;
+        monitor.observe(start, &sample);
+
+        assert!(monitor.report(start, &sample).is_healthy());
+        let later = start + QUEUE_FULL_TIMEOUT;
+        let report = monitor.report(later, &sample);
+        assert_eq!(codes(&report), vec![HealthCode::QueueFull]);
+        assert_eq!(report.findings[0].detail("queue_size"), Some(950));
+        assert_eq!(
+            report.findings[0].detail("seconds"),
+            Some(QUEUE_FULL_TIMEOUT.as_secs())
+        );
+
+        // the queue drained meanwhile
+        sample.queue_size = 10;
+        monitor.observe(later, &sample);
+        assert!(monitor.report(later, &sample).is_healthy());
+    }
+
+    #[test]
+    fn reports_stalled_snapshot_restoration() {
+        let start = Instant::now();
+        let mut monitor = HealthMonitor::new(start);
+        let mut sample = healthy();
+        sample.snapshot_chunks = Some((10, 40));
+        monitor.observe(start, &sample);
+
+        let later = start + SNAPSHOT_STALL_TIMEOUT;
+        let report = monitor.report(later, &sample);
+        assert_eq!(codes(&report), vec![HealthCode::SnapshotStalled]);
+        assert_eq!(report.findings[0].detail("chunks_done"), Some(10));
+        assert_eq!(report.findings[0].detail("chunks_total"), Some(40));
+
+        // progress not observed yet counts as progress
+        sample.snapshot_chunks = Some((11, 40));
+        assert!(monitor.report(later, &sample).is_healthy());
+    }
+
+    #[test]
+    fn reports_best_block_stalled_while_peers_advance() {
+        let start = Instant::now();
+        let mut monitor = HealthMonitor::new(start);
+        let mut sample = healthy();
+        monitor.observe(start, &sample);
+
+        // nothing to import, the best block is expected to stay
+        let later = start + BEST_BLOCK_STALL_TIMEOUT;
+        assert!(monitor.report(later, &sample).is_healthy());
+
+        sample.highest_block = Some(150);
+        let report = monitor.report(later, &sample);
+        assert_eq!(codes(&report), vec![HealthCode::BestBlockStalled]);
+        assert_eq!(report.findings[0].detail("best_block"), Some(100));
+        assert_eq!(report.findings[0].detail("highest_block"), Some(150));
+    }
+
+    #[test]
+    fn reports_stalled_ancient_blocks_download() {
+        let start = Instant::now();
+        let mut monitor = HealthMonitor::new(start);
+        let mut sample = healthy();
+        sample.ancient_block = Some(5000);
+        monitor.observe(start, &sample);
+
+        let later = start + ANCIENT_STALL_TIMEOUT;
+        let report = monitor.report(later, &sample);
+        assert_eq!(codes(&report), vec![HealthCode::AncientBlocksStalled]);
+        assert_eq!(report.findings[0].detail("ancient_block"), Some(5000));
+        assert_eq!(report.findings[0].severity, HealthSeverity::Warning);
+
+        sample.ancient_block = None;
+        assert!(monitor.report(later, &sample).is_healthy());
+    }
+
+    #[test]
+    fn orders_findings_by_severity() {
+        let start = Instant::now();
+        let mut monitor = HealthMonitor::new(start);
+        monitor.note_network_id_mismatch(start, 5);
+        let mut sample = healthy();
+        sample.ancient_block = Some(5000);
+        sample.highest_block = Some(150);
+        monitor.observe(start, &sample);
+
+        let report = monitor.report(start + ANCIENT_STALL_TIMEOUT, &sample);
+        assert_eq!(
+            codes(&report),
+            vec![
+                HealthCode::BestBlockStalled,
+                HealthCode::AncientBlocksStalled
+            ]
+        );
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Peers set aside because they have nothing to give us for now, through no fault of theirs.
+
+use ethereum_types::{H256, U256};
+use network::PeerId;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Why a peer was set aside.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeactivationReason {
+    /// The total difficulty of the peer is below ours.
+    LowDifficulty,
+    /// The peer had nothing to offer for what we are downloading.
+    Useless,
+}
+
+struct InactivePeer {
+    reason: DeactivationReason,
+    since: Instant,
+    head: H256,
+    difficulty: Option<U256>,
+}
+
+/// Benignly deactivated peers. A peer comes back once it announces a head which may
+/// change its usefulness, or once `reprobe_after` passed.
+pub struct InactivePeers {
+    peers: HashMap<PeerId, InactivePeer>,
+    reprobe_after: Duration,
+}
+
+impl InactivePeers {
+    /// Create an empty set, peers are probed again after `reprobe_after`.
+    pub fn new(reprobe_after: Duration) -> Self {
+        InactivePeers {
+            peers: HashMap::new(),
+            reprobe_after,
+        }
+    }
+
+    /// Set aside a peer whose head and total difficulty are `head` and `difficulty`.
+    pub fn insert(
+        &mut self,
+        peer: PeerId,
+        reason: DeactivationReason,
+        head: H256,
+        difficulty: Option<U256>,
+        now: Instant,
+    ) {
+        self.peers.insert(
+            peer,
+            InactivePeer {
+                reason,
+                since: now,
+                head,
+                difficulty,
+            },
+        );
+    }
+
+    /// Forget a peer, returns whether it was inactive.
+    pub fn remove(&mut self, peer: PeerId) -> bool {
+        self.peers.remove(&peer).is_some()
+    }
+
+    /// Number of inactive peers.
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Forget all peers.
+    pub fn clear(&mut self) {
+        self.peers.clear();
+    }
+
+    /// A peer announced a new head. Returns true, and forgets the peer, if it is to be
+    /// reactivated: a peer behind us needs to announce a higher total difficulty, any
+    /// other one a different head.
+    pub fn on_announcement(&mut self, peer: PeerId, head: H256, difficulty: Option<U256>) -> bool {
+        let reactivate = match self.peers.get(&peer) {
+            Some(inactive) => match inactive.reason {
+                DeactivationReason::LowDifficulty => {
+                    difficulty.is_some_and(|td| inactive.difficulty.is_none_or(|old| td > old))
+                }
+                DeactivationReason::Useless => head != inactive.head,
+            },
+            None => false,
+        };
+        if reactivate {
+            self.peers.remove(&peer);
+        }
+        reactivate
+    }
+
+    /// Forget and return the peers inactive for at least `reprobe_after`.
+    pub fn take_due(&mut self, now: Instant) -> Vec<PeerId> {
+        let reprobe_after = self.reprobe_after;
+        let due: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|(_, inactive)| now.saturating_duration_since(inactive.since) >= reprobe_after)
+            .map(|(peer, _)| *peer)
+            .collect();
+        for peer in &due {
+            self.peers.remove(peer);
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPROBE: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn low_difficulty_peers_need_a_higher_difficulty() {
+        let now = Instant::now();
+        let mut inactive = InactivePeers::new(REPROBE);
+        let head = H256::from_low_u64_be(1);
+        inactive.insert(
+            1,
+            DeactivationReason::LowDifficulty,
+            head,
+            Some(100.into()),
+            now,
+        );
+
+        let new_head = H256::from_low_u64_be(2);
+        assert!(!inactive.on_announcement(1, new_head, None));
+        assert!(!inactive.on_announcement(1, new_head, Some(100.into())));
+        assert!(inactive.on_announcement(1, new_head, Some(101.into())));
+        assert_eq!(inactive.len(), 0);
+    }
+
+    #[test]
+    fn useless_peers_need_a_new_head() {
+        let now = Instant::now();
+        let mut inactive = InactivePeers::new(REPROBE);
+        let head = H256::from_low_u64_be(1);
+        inactive.insert(1, DeactivationReason::Useless, head, None, now);
+
+        assert!(!inactive.on_announcement(1, head, Some(100.into())));
+        assert!(!inactive.on_announcement(2, H256::from_low_u64_be(2), None));
+        assert!(inactive.on_announcement(1, H256::from_low_u64_be(2), None));
+        assert_eq!(inactive.len(), 0);
+    }
+
+    #[test]
+    fn peers_are_probed_again_after_the_backoff() {
+        let now = Instant::now();
+        let mut inactive = InactivePeers::new(REPROBE);
+        inactive.insert(1, DeactivationReason::Useless, H256::zero(), None, now);
+        inactive.insert(
+            2,
+            DeactivationReason::LowDifficulty,
+            H256::zero(),
+            None,
+            now + REPROBE / 2,
+        );
+
+        assert!(inactive.take_due(now + REPROBE / 2).is_empty());
+        assert_eq!(inactive.take_due(now + REPROBE), vec![1]);
+        assert_eq!(inactive.take_due(now + REPROBE * 2), vec![2]);
+        assert_eq!(inactive.len(), 0);
+    }
+}
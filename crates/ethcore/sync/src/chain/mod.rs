@@ -87,44 +87,80 @@
 //!
 //! All other messages are ignored.
 
+mod branch_probe;
+mod compression;
+mod delayed;
+mod events;
+mod fee_market;
 pub mod fork_filter;
 mod handler;
+mod health;
+mod inactive;
+mod packet_stats;
 mod propagator;
 pub mod request_id;
 mod requester;
+mod stall;
 mod supplier;
 pub mod sync_packet;
 
-pub use self::fork_filter::ForkFilterApi;
-use super::{SyncConfig, WarpSync};
+use self::packet_stats::PacketStats;
+pub use self::{
+    branch_probe::{BranchProbeStatus, RejectedBlockStats},
+    events::{SyncEvent, SyncEventKind},
+    fee_market::PeerFeeMarket,
+    fork_filter::ForkFilterApi,
+    health::{HealthCode, HealthFinding, HealthSeverity, SyncHealthReport},
+    packet_stats::PacketDispatchStats,
+    stall::StallRecoveryStats,
+};
+use super::{ConsensusPropagation, PeerSelection, SyncConfig, WarpSync};
 use api::{EthProtocolInfo as PeerInfoDigest, PriorityTask, ETH_PROTOCOL, PAR_PROTOCOL};
-use block_sync::{BlockDownloader, DownloadAction};
+use block_sync::{
+    BlockDownloader, DeepReorg, DownloadAction, DownloaderDiagnostics, HeadStatus, RejectedBlock,
+};
+use blocks::verify_block_data;
 use bytes::Bytes;
 use derive_more::Display;
 use ethcore::{
     client::{BlockChainClient, BlockChainInfo, BlockId, BlockQueueInfo, BlockStatus},
-    snapshot::RestorationStatus,
+    snapshot::{ManifestData, RestorationStatus, SnapshotService},
 };
-use ethereum_types::{H256, U256};
+use ethereum_types::{Address, H256, U256};
 use fastmap::{H256FastMap, H256FastSet};
 use hash::keccak;
-use network::{self, client_version::ClientVersion, PeerId};
+use head_hints::HeadHints;
+use header_backfill::HeaderBackfill;
+use network::{
+    self, chunking,
+    client_version::{self, ClientBreakdown, ClientVersion},
+    NodeId, PeerId, ProtocolId,
+};
+use parity_runtime::Executor;
 use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
+use peer_selection::Rendezvous;
 use rand::{seq::SliceRandom, Rng};
-use rlp::{DecoderError, RlpStream};
+use rlp::{DecoderError, Rlp, RlpStream};
 use snapshot::Snapshot;
 use std::{
     cmp,
-    collections::{BTreeMap, HashMap, HashSet},
-    sync::mpsc,
-    time::{Duration, Instant},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    ops::RangeInclusive,
+    sync::{mpsc, Arc},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use sync_io::SyncIo;
 use transactions_stats::{Stats as TransactionStats, TransactionsStats};
-use types::{transaction::UnverifiedTransaction, BlockNumber};
+use types::{header::Header as BlockHeader, transaction::UnverifiedTransaction, BlockNumber};
 
 use self::{
+    branch_probe::{BranchProber, PeerBranch},
+    delayed::{DelayedRequests, MAX_REQUESTS_PER_DRAIN},
+    events::SyncEvents,
     handler::SyncHandler,
+    health::{HealthMonitor, HealthSample},
+    inactive::{DeactivationReason, InactivePeers},
+    stall::{StallAction, StallDetector},
     sync_packet::{
         PacketInfo,
         SyncPacket::{self, NewBlockPacket, StatusPacket},
@@ -146,6 +182,9 @@ pub enum PacketProcessError {
     /// The packet should be postponed for later response
     #[display(fmt = "Underlying client is busy")]
     ClientBusy,
+    /// The response could not be sent
+    #[display(fmt = "Send Error: {_0}")]
+    Send(network::Error),
 }
 
 impl From<DecoderError> for PacketProcessError {
@@ -154,6 +193,35 @@ impl From<DecoderError> for PacketProcessError {
     }
 }
 
+/// Reasons a manually injected block is refused
+#[derive(Debug, Display, PartialEq)]
+pub enum InjectError {
+    /// Error of RLP decoder
+    #[display(fmt = "Decoder Error: {_0}")]
+    Decoder(DecoderError),
+    /// The block is in the chain or queued for import already
+    #[display(fmt = "Block already known")]
+    AlreadyKnown,
+    /// The import queue is full, the block should be injected again later
+    #[display(fmt = "Import queue is full")]
+    QueueFull,
+    /// The block is too far behind the last imported block to be imported untrusted
+    #[display(fmt = "Block too old")]
+    Ancient,
+    /// The parent of the block is unknown
+    #[display(fmt = "Unknown parent {_0:?}")]
+    UnknownParent(H256),
+    /// The block failed the import checks
+    #[display(fmt = "Invalid block: {_0}")]
+    Invalid(String),
+}
+
+impl From<DecoderError> for InjectError {
+    fn from(err: DecoderError) -> Self {
+        InjectError::Decoder(err)
+    }
+}
+
 /// Version 66 of the Ethereum protocol and number of packet IDs reserved by the protocol (packet count).
 pub const ETH_PROTOCOL_VERSION_66: (u8, u8) = (66, 0x11);
 /// Version 65 of the Ethereum protocol and number of packet IDs reserved by the protocol (packet count).
@@ -166,28 +234,74 @@ pub const ETH_PROTOCOL_VERSION_63: (u8, u8) = (63, 0x11);
 pub const PAR_PROTOCOL_VERSION_1: (u8, u8) = (1, 0x15);
 /// 2 version of OpenEthereum protocol (consensus messages added).
 pub const PAR_PROTOCOL_VERSION_2: (u8, u8) = (2, 0x16);
+/// 3 version of OpenEthereum protocol (compressed block bodies and receipts added).
+pub const PAR_PROTOCOL_VERSION_3: (u8, u8) = (3, 0x18);
+/// 4 version of OpenEthereum protocol (snapshot data split into several packets added).
+pub const PAR_PROTOCOL_VERSION_4: (u8, u8) = (4, 0x19);
 
 pub const MAX_BODIES_TO_SEND: usize = 256;
 pub const MAX_HEADERS_TO_SEND: usize = 512;
 pub const MAX_NODE_DATA_TO_SEND: usize = 1024;
 pub const MAX_RECEIPTS_HEADERS_TO_SEND: usize = 256;
+/// Default number of announced transactions requested from a peer at once.
 pub const MAX_TRANSACTIONS_TO_REQUEST: usize = 256;
-const MIN_PEERS_PROPAGATION: usize = 4;
-const MAX_PEERS_PROPAGATION: usize = 128;
-const MAX_PEER_LAG_PROPAGATION: BlockNumber = 20;
+// Max number of transactions rejected by the local pool to remember.
+const MAX_UNWANTED_TRANSACTIONS: usize = 4096;
+// How long transactions rejected by the local pool are not requested again.
+const UNWANTED_TRANSACTIONS_TTL: Duration = Duration::from_secs(10 * 60);
+// Max number of blocks in the verification queue to remember the supplying peer for.
+const MAX_BLOCK_SUPPLIERS: usize = 8192;
+/// Default minimal number of peers blocks and transactions are propagated to.
+pub const MIN_PEERS_PROPAGATION: usize = 4;
+/// Default maximal number of peers blocks and transactions are propagated to.
+pub const MAX_PEERS_PROPAGATION: usize = 128;
+/// Default number of blocks the best block may move since the last propagation for new
+/// blocks to be propagated.
+pub const MAX_PEER_LAG_PROPAGATION: BlockNumber = 20;
+// Limits of `NewHashes` and `NewBlock` packets used until the block interval is known.
 const MAX_NEW_HASHES: usize = 64;
 const MAX_NEW_BLOCK_AGE: BlockNumber = 20;
-// maximal packet size with transactions (cannot be greater than 16MB - protocol limitation).
-// keep it under 8MB as well, cause it seems that it may result oversized after compression.
-const MAX_TRANSACTION_PACKET_SIZE: usize = 5 * 1024 * 1024;
+// Chain time new blocks may be behind the last imported block, once the block interval is known.
+// Amounts to about `MAX_NEW_BLOCK_AGE` blocks of a chain with 13 seconds between blocks.
+const NEW_BLOCK_AGE_WINDOW: Duration = Duration::from_secs(240);
+const MIN_NEW_BLOCK_AGE: BlockNumber = 4;
+const MAX_NEW_BLOCK_AGE_CEILING: BlockNumber = 1024;
+const MAX_NEW_HASHES_CEILING: usize = 512;
+// Number of recent blocks the block interval is averaged over.
+const BLOCK_INTERVAL_SAMPLES: u64 = 32;
+// How long a block imported through the fast path is waited for to measure its import latency.
+const FAST_PATH_BLOCK_TTL: Duration = Duration::from_secs(60);
+/// Number of the most recently sealed local blocks whose rejection by the queue is reported.
+const MAX_SEALED_BLOCKS: usize = 64;
+/// Default size of a packet with transactions (cannot be greater than 16MB - protocol limitation).
+/// Kept under 8MB as well, cause it seems that it may result oversized after compression.
+pub const MAX_TRANSACTION_PACKET_SIZE: usize = 5 * 1024 * 1024;
+/// Bounds of the configured size of a packet with transactions. The packet is sent in a single
+/// RLPx frame, its payload is limited to `network::MAX_PAYLOAD_SIZE` (16MB). The packet may
+/// exceed the configured size by the transaction which crossed it, the upper bound leaves room
+/// for that.
+pub const TRANSACTION_PACKET_SIZE_BOUNDS: RangeInclusive<usize> = 64 * 1024..=15 * 1024 * 1024;
 // Min number of blocks to be behind for a snapshot sync
+/// Number of ancient blocks checked by the repair scan while holding the sync lock.
+const REPAIR_SCAN_CHUNK: BlockNumber = 1024;
 const SNAPSHOT_RESTORE_THRESHOLD: BlockNumber = 30000;
 const SNAPSHOT_MIN_PEERS: usize = 3;
+// Blocks a peer may be ahead of us on top of one block per second since our best block,
+// when checking the plausibility of its total difficulty.
+const PLAUSIBLE_DIFFICULTY_MARGIN_BLOCKS: u64 = 100_000;
 
 const MAX_SNAPSHOT_CHUNKS_DOWNLOAD_AHEAD: usize = 3;
+// Max number of snapshot data responses received in parts at the same time
+const MAX_PARTIAL_SNAPSHOT_DATA: usize = 16;
 
 const WAIT_PEERS_TIMEOUT: Duration = Duration::from_secs(5);
 const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+/// Connected peers waiting for their Status kept at most, the oldest are disconnected beyond it.
+const MAX_HANDSHAKING_PEERS: usize = 1024;
+/// Time after which a benignly deactivated peer is given something to do again.
+const INACTIVE_PEER_REPROBE: Duration = Duration::from_secs(30);
+// Status timeouts are spread by this percentage in both directions
+const STATUS_TIMEOUT_JITTER_PERCENT: u64 = 20;
 const HEADERS_TIMEOUT: Duration = Duration::from_secs(15);
 const BODIES_TIMEOUT: Duration = Duration::from_secs(20);
 const RECEIPTS_TIMEOUT: Duration = Duration::from_secs(10);
@@ -237,6 +351,9 @@ pub struct SyncStatus {
     pub last_imported_block_number: Option<BlockNumber>,
     /// Highest block number in the download queue (if any).
     pub highest_block_number: Option<BlockNumber>,
+    /// The highest block number is an unverified estimate from the heads peers advertised
+    /// before a restart, no peer announced a block yet.
+    pub highest_block_hinted: bool,
     /// Total number of blocks for the sync process.
     pub blocks_total: BlockNumber,
     /// Number of blocks downloaded so far.
@@ -245,6 +362,12 @@ pub struct SyncStatus {
     pub num_peers: usize,
     /// Total number of active peers.
     pub num_active_peers: usize,
+    /// Number of connected peers transactions are relayed to
+    pub num_tx_relay_peers: usize,
+    /// Number of connected peers only used for block sync
+    pub num_sync_only_peers: usize,
+    /// Connected peers by client software
+    pub clients: ClientBreakdown,
     /// Snapshot chunks
     pub num_snapshot_chunks: usize,
     /// Snapshot chunks downloaded
@@ -253,6 +376,71 @@ pub struct SyncStatus {
     pub last_imported_old_block_number: Option<BlockNumber>,
     /// Internal structure item numbers
     pub item_sizes: BTreeMap<String, usize>,
+    /// Number of fetched transactions rejected by the local transaction pool policy
+    pub num_policy_rejected_transactions: usize,
+    /// Number of fetched transactions which were undecodable or not requested
+    pub num_peer_fault_transactions: usize,
+    /// Number of `NewBlock` packets skipped because the block was already known
+    pub num_skipped_new_blocks: usize,
+    /// Number of snapshot chunks dropped because they were too large or not the chunk requested
+    pub num_bad_snapshot_chunks: usize,
+    /// Number of `NewBlock` packets extending the best block, verified ahead of the queued blocks
+    pub num_fast_path_new_blocks: usize,
+    /// Time from receiving the last fast path `NewBlock` packet until its block was imported
+    pub new_block_import_latency: Option<Duration>,
+    /// Number of peers dropped because they did not send Status in time
+    pub num_status_timeout_drops: usize,
+    /// Number of blocks sealed by this node and rejected by the block queue
+    pub num_rejected_sealed_blocks: usize,
+    /// Number of peers set aside until they have something to give us
+    pub num_inactive_peers: usize,
+    /// Number of peers disabled for misbehaving, until they are disconnected
+    pub num_disabled_peers: usize,
+    /// Number of compressed block bodies and receipts packets sent
+    pub num_compressed_packets_sent: usize,
+    /// Bytes saved by sending compressed instead of standard packets
+    pub compressed_bytes_saved: usize,
+    /// Number of sync events dropped because the subscriber's channel was full
+    pub num_dropped_sync_events: usize,
+    /// Number of requests delayed because the client was busy
+    pub num_delayed_requests: usize,
+    /// Number of delayed requests evicted to stay within the queue caps
+    pub num_evicted_delayed_requests: usize,
+    /// Number of delayed requests dropped because they were queued for too long
+    pub num_expired_delayed_requests: usize,
+    /// Number of blocks below the header checkpoint covered by backfilled headers
+    pub headers_backfilled: BlockNumber,
+    /// Number of blocks below the header checkpoint to backfill headers for
+    pub headers_backfill_target: BlockNumber,
+    /// Packets which could not be sent, by reason
+    pub send_errors: SendErrorStats,
+    /// Packets received from peers, by packet id
+    pub packet_stats: Vec<PacketDispatchStats>,
+    /// Recovery actions taken because the best block stopped advancing
+    pub stall_recoveries: StallRecoveryStats,
+    /// Rejected blocks and the search for a branch without them
+    pub branch_probe: BranchProbeStatus,
+    /// Branch not synced for diverging deeper than the maximum reorg depth
+    pub deep_reorg: Option<DeepReorg>,
+    /// Transactions, blocks and consensus packets are never propagated
+    pub observer_mode: bool,
+}
+
+/// Packets which could not be sent, by reason.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SendErrorStats {
+    /// The peer was gone already.
+    pub peer_gone: usize,
+    /// The send queue of the peer was full, so the packet was skipped.
+    pub send_queue_full: usize,
+    /// The session expired while responding.
+    pub session_expired: usize,
+    /// The packet exceeded the payload limit of the session.
+    pub oversized: usize,
+    /// The peer did not negotiate the protocol of the packet.
+    pub protocol_unsupported: usize,
+    /// Any other failure.
+    pub other: usize,
 }
 
 impl SyncStatus {
@@ -291,7 +479,10 @@ impl SyncStatus {
 pub enum PeerAsking {
     Nothing,
     ForkHeader,
+    DifficultyProbe,
+    BranchProbe,
     BlockHeaders,
+    BackfillHeaders,
     BlockBodies,
     BlockReceipts,
     PooledTransactions,
@@ -309,6 +500,7 @@ pub enum BlockSet {
 }
 
 impl BlockSet {
+    /// Name of the block set, as used in logs and item sizes.
     pub fn to_string(&self) -> &'static str {
         match *self {
             Self::NewBlocks => "new_blocks",
@@ -317,7 +509,8 @@ impl BlockSet {
     }
 }
 
-#[derive(Clone, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Fork block confirmation status of a peer.
 pub enum ForkConfirmation {
     /// Fork block confirmation pending.
     Unconfirmed,
@@ -327,11 +520,38 @@ pub enum ForkConfirmation {
     Confirmed,
 }
 
+impl ForkConfirmation {
+    /// Name of the confirmation status.
+    pub fn to_string(&self) -> &'static str {
+        match *self {
+            Self::Unconfirmed => "unconfirmed",
+            Self::TooShort => "too_short",
+            Self::Confirmed => "confirmed",
+        }
+    }
+}
+
+/// Plausibility of the total difficulty advertised by a peer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DifficultyCheck {
+    /// Advertised difficulty is plausible.
+    Plausible,
+    /// Advertised difficulty is above the plausible bound. The peer is of unknown difficulty
+    /// until its best header shows a chain long enough to accumulate it.
+    Unverified(U256),
+    /// The peer failed to back its advertised difficulty.
+    Failed,
+}
+
 #[derive(Clone)]
 /// Syncing peer information
 pub struct PeerInfo {
     /// eth protocol version
     protocol_version: u8,
+    /// Negotiated eth protocol version
+    eth_protocol_version: u8,
+    /// Negotiated par protocol version, 0 if not supported
+    par_protocol_version: u8,
     /// Peer chain genesis hash
     genesis: H256,
     /// Peer network id
@@ -340,18 +560,25 @@ pub struct PeerInfo {
     latest_hash: H256,
     /// Peer total difficulty if known
     difficulty: Option<U256>,
+    /// Plausibility of the advertised total difficulty
+    difficulty_check: DifficultyCheck,
     /// Type of data currenty being requested from peer.
     asking: PeerAsking,
     /// A set of block numbers being requested
     asking_blocks: Vec<H256>,
     /// Holds requested header hash if currently requesting block header by hash
     asking_hash: Option<H256>,
+    /// Number of block header requests not answered yet, more than one while subchain heads
+    /// are requested in segments.
+    asking_headers: usize,
     /// Hashes of transactions to be requested.
     unfetched_pooled_transactions: H256FastSet,
     /// Hashes of the transactions we're requesting.
     asking_pooled_transactions: Vec<H256>,
     /// Holds requested snapshot chunk hash if any.
     asking_snapshot_data: Option<H256>,
+    /// Holds the fork checkpoint the requested header is checked against, if any.
+    asking_fork_header: Option<(BlockNumber, H256)>,
     /// Request timestamp
     ask_time: Instant,
     /// Holds a set of transactions recently sent to this peer to avoid spamming.
@@ -364,10 +591,16 @@ pub struct PeerInfo {
     snapshot_hash: Option<H256>,
     /// Best snapshot block number
     snapshot_number: Option<BlockNumber>,
+    /// Lowest block the peer serves data for, if it advertised one
+    serve_from_block: Option<BlockNumber>,
     /// Block set requested
     block_set: Option<BlockSet>,
     /// Version of the software the peer is running
-    _client_version: ClientVersion,
+    client_version: ClientVersion,
+    /// Node id of the peer, if known
+    node_id: Option<NodeId>,
+    /// Gas limit and base fee of the highest validated header received from the peer
+    fee_market: Option<PeerFeeMarket>,
 }
 
 impl PeerInfo {
@@ -408,6 +641,8 @@ pub mod random {
 
 pub type RlpResponseResult = Result<Option<(SyncPacket, RlpStream)>, PacketProcessError>;
 pub type Peers = HashMap<PeerId, PeerInfo>;
+/// Notified with the hash and the rejection reason of a block sealed by this node.
+pub type SealedBlockRejectedHandler = Arc<dyn Fn(H256, &str) + Send + Sync>;
 
 /// Thread-safe wrapper for `ChainSync`.
 ///
@@ -455,31 +690,117 @@ impl ChainSyncApi {
         self.sync.read().status()
     }
 
+    /// Check the sync state for the usual reasons of a node not syncing
+    pub fn health_check(
+        &self,
+        chain: &dyn BlockChainClient,
+        snapshot_service: &dyn SnapshotService,
+    ) -> SyncHealthReport {
+        self.sync.read().health_check(chain, snapshot_service)
+    }
+
+    /// Returns a snapshot of the new and, if present, old blocks downloader state
+    pub fn downloader_diagnostics(&self) -> Vec<DownloaderDiagnostics> {
+        self.sync.read().downloader_diagnostics()
+    }
+
+    /// Scan the ancient blocks in `range` for data not matching the headers and download the
+    /// mismatching blocks again, see `ChainSync::repair_ancient_blocks`. The range is scanned in
+    /// chunks of `REPAIR_SCAN_CHUNK` blocks, the sync lock is released between the chunks.
+    /// Returns the numbers of the mismatching blocks.
+    pub fn repair_ancient_blocks(
+        &self,
+        chain: &dyn BlockChainClient,
+        range: RangeInclusive<BlockNumber>,
+    ) -> Vec<BlockNumber> {
+        let mut mismatched = Vec::new();
+        let (mut start, end) = range.into_inner();
+        while start <= end {
+            let chunk_end = cmp::min(start.saturating_add(REPAIR_SCAN_CHUNK - 1), end);
+            if !self
+                .sync
+                .read()
+                .scan_ancient_blocks(chain, start..=chunk_end, &mut mismatched)
+            {
+                break;
+            }
+            match chunk_end.checked_add(1) {
+                Some(next) => start = next,
+                None => break,
+            }
+        }
+        self.sync.write().repair_ancient_blocks(chain, &mismatched);
+        mismatched.into_iter().map(|(number, _)| number).collect()
+    }
+
+    /// Returns the fork id at the best block of `chain`
+    pub fn fork_id(&self, chain: &dyn BlockChainClient) -> fork_filter::ForkId {
+        self.sync.read().fork_filter.current(chain)
+    }
+
+    /// Returns a copy of the heads last advertised by peers
+    pub fn head_hints(&self) -> HeadHints {
+        self.sync.read().head_hints().clone()
+    }
+
     /// Returns pending transactions propagation statistics
-    pub fn pending_transactions_stats(&self) -> BTreeMap<H256, ::TransactionStats> {
+    pub fn pending_transactions_stats(&self, verbose: bool) -> BTreeMap<H256, ::TransactionStats> {
         self.sync
             .read()
             .pending_transactions_stats()
             .iter()
-            .map(|(hash, stats)| (*hash, stats.into()))
+            .map(|(hash, stats)| (*hash, stats.to_transaction_stats(verbose)))
             .collect()
     }
 
     /// Returns new transactions propagation statistics
-    pub fn new_transactions_stats(&self) -> BTreeMap<H256, ::TransactionStats> {
+    pub fn new_transactions_stats(&self, verbose: bool) -> BTreeMap<H256, ::TransactionStats> {
         self.sync
             .read()
             .new_transactions_stats()
             .iter()
-            .map(|(hash, stats)| (*hash, stats.into()))
+            .map(|(hash, stats)| (*hash, stats.to_transaction_stats(verbose)))
             .collect()
     }
 
+    /// Replace the node ids of the current validators, see `ChainSync::set_validator_nodes`
+    pub fn set_validator_nodes(&self, nodes: HashSet<NodeId>) {
+        self.sync.write().set_validator_nodes(nodes);
+    }
+
+    /// Replace the addresses of the current validators, see `ChainSync::set_validators`
+    pub fn set_validators(&self, validators: HashSet<Address>) {
+        self.sync.write().set_validators(validators);
+    }
+
+    /// Returns the statistics of the packets received from peers
+    pub fn packet_stats(&self) -> Vec<PacketDispatchStats> {
+        self.sync.read().packet_stats.snapshot()
+    }
+
     /// Dispatch incoming requests and responses
     pub fn dispatch_packet(&self, io: &mut dyn SyncIo, peer: PeerId, packet_id: u8, data: &[u8]) {
         SyncSupplier::dispatch_packet(&self.sync, io, peer, packet_id, data)
     }
 
+    /// Import a block obtained out of band, e.g. to recover from peers serving a bad branch only.
+    /// See `SyncHandler::inject_block`.
+    pub fn inject_block(
+        &self,
+        io: &mut dyn SyncIo,
+        rlp_block: Bytes,
+        trusted: bool,
+    ) -> Result<H256, InjectError> {
+        SyncHandler::inject_block(&mut self.sync.write(), io, rlp_block, trusted)
+    }
+
+    /// Run a deferred `continue_sync`, if any. The write lock is only taken when one is pending.
+    pub fn flush_continue_sync(&self, io: &mut dyn SyncIo) {
+        if self.sync.read().continue_sync_pending() {
+            self.sync.write().flush_continue_sync(io);
+        }
+    }
+
     /// Process the queue with requests, that were delayed with response.
     pub fn process_delayed_requests(&self, io: &mut dyn SyncIo) {
         let requests = self.sync.write().retrieve_delayed_requests();
@@ -525,6 +846,9 @@ impl ChainSyncApi {
             // wait for the sync lock until deadline,
             // note we might drop the task here if we won't manage to acquire the lock.
             let mut sync = self.sync.try_write_until(deadline)?;
+            if sync.observer_mode {
+                return Some(());
+            }
             // since we already have everything let's use a different deadline
             // to do the rest of the job now, so that previous work is not wasted.
             let deadline = Instant::now() + PRIORITY_TASK_DEADLINE;
@@ -549,7 +873,13 @@ impl ChainSyncApi {
                     for peers in sync.get_peers(&chain_info, PeerState::SameBlock).chunks(10) {
                         check_deadline(deadline)?;
                         for peer in peers {
-                            SyncPropagator::send_packet(io, *peer, NewBlockPacket, rlp.clone());
+                            SyncPropagator::send_packet(
+                                &mut sync,
+                                io,
+                                *peer,
+                                NewBlockPacket,
+                                rlp.clone(),
+                            );
                             if let Some(ref mut peer) = sync.peers.get_mut(peer) {
                                 peer.latest_hash = hash;
                             }
@@ -643,12 +973,12 @@ impl ChainSync {
         )
     }
 
-    fn select_random_peers(peers: &[PeerId]) -> Vec<PeerId> {
+    fn select_random_peers(&self, peers: &[PeerId]) -> Vec<PeerId> {
         // take sqrt(x) peers
         let mut peers = peers.to_vec();
         let mut count = (peers.len() as f64).powf(0.5).round() as usize;
-        count = cmp::min(count, MAX_PEERS_PROPAGATION);
-        count = cmp::max(count, MIN_PEERS_PROPAGATION);
+        count = cmp::min(count, self.max_peers_propagation);
+        count = cmp::max(count, self.min_peers_propagation);
         peers.shuffle(&mut random::new());
         peers.truncate(count);
         peers
@@ -681,31 +1011,61 @@ pub struct ChainSync {
     starting_block: BlockNumber,
     /// Highest block number seen
     highest_block: Option<BlockNumber>,
+    /// Unverified highest block number estimated from the head hints, until a peer announces one
+    hinted_highest_block: Option<BlockNumber>,
+    /// Heads last advertised by peers, by node id
+    head_hints: HeadHints,
     /// All connected peers
     peers: Peers,
     /// Peers active for current sync round
     active_peers: HashSet<PeerId>,
+    /// Peers deactivated through no fault of theirs, reactivated without waiting for
+    /// the next sync round
+    inactive_peers: InactivePeers,
+    /// Peers disabled for misbehaving, never reactivated
+    disabled_peers: HashSet<PeerId>,
+    /// Peers which may be idle and able to sync. Kept up to date as peers become idle,
+    /// the peers which are not are dropped when scanned.
+    idle_peers: HashSet<PeerId>,
+    /// Minimum time between two `continue_sync` runs triggered by sync events
+    continue_sync_interval: Duration,
+    /// A `continue_sync` run was deferred to the next timer tick
+    continue_sync_pending: bool,
+    /// Time of the last `continue_sync` run
+    last_continue_sync: Option<Instant>,
+    /// Number of scans for peers to give something to do
+    peer_scans: usize,
     /// Block download process for new blocks
     new_blocks: BlockDownloader,
     /// Block download process for ancient blocks
     old_blocks: Option<BlockDownloader>,
+    /// Backwards header download from the trusted checkpoint
+    header_backfill: Option<HeaderBackfill>,
     /// Last propagated block number
     last_sent_block_number: BlockNumber,
     /// Network ID
     network_id: u64,
     /// Optional fork block to check
     fork_block: Option<(BlockNumber, H256)>,
+    /// Fork block to check for peers without fork id, derived from the most recent past fork
+    /// if `fork_block` is not configured
+    derived_fork_block: Option<(BlockNumber, H256)>,
     /// Fork filter
     fork_filter: ForkFilterApi,
     /// Snapshot downloader.
     snapshot: Snapshot,
+    /// Snapshot data responses received in parts so far.
+    snapshot_data_parts: chunking::Reassembler<PeerId>,
+    /// Executor snapshot chunks are hashed on.
+    executor: Executor,
+    /// Sender of the snapshot chunks hashed on the executor.
+    hashed_chunks_tx: crossbeam_channel::Sender<HashedChunk>,
+    /// Snapshot chunks hashed on the executor, waiting to be imported.
+    hashed_chunks_rx: crossbeam_channel::Receiver<HashedChunk>,
     /// Connected peers pending Status message.
-    /// Value is request timestamp.
-    handshaking_peers: HashMap<PeerId, Instant>,
+    handshaking_peers: HashMap<PeerId, HandshakingPeer>,
     /// Requests, that can not be processed at the moment
-    delayed_requests: Vec<(PeerId, u8, Vec<u8>)>,
-    /// Ids of delayed requests, used for lookup, id is composed from peer id and packet id
-    delayed_requests_ids: HashSet<(PeerId, u8)>,
+    delayed_requests: DelayedRequests,
     /// Sync start timestamp. Measured when first peer is connected
     sync_start_time: Option<Instant>,
     /// Receiver of transactions that came after last propagation and should be broadcast
@@ -716,10 +1076,119 @@ pub struct ChainSync {
     download_old_blocks: bool,
     /// Enable warp sync.
     warp_sync: WarpSync,
+    /// Selection of the peers block bodies and receipts are requested from.
+    peer_selection: PeerSelection,
+    /// Peers proposed blocks and consensus packets are sent to.
+    consensus_propagation: ConsensusPropagation,
+    /// Node ids of the current validators, as registered.
+    validator_nodes: HashSet<NodeId>,
+    /// Addresses of the current validators, as notified by the engine.
+    validators: HashSet<Address>,
+    /// Configured node ids of validators by address, to find the peers of `validators`.
+    validator_node_map: HashMap<Address, NodeId>,
+    /// Upper bound of a single block difficulty, if peers' total difficulty is checked.
+    max_block_difficulty: Option<U256>,
+    /// Lowest protocol version of the peers transactions are relayed to.
+    tx_relay_min_protocol: u8,
+    /// Size of a packet with transactions above which no further transactions are added.
+    max_transaction_packet_size: usize,
+    /// Number of announced transactions requested from a peer at once.
+    max_transactions_to_request: usize,
+    /// Minimal number of peers blocks and transactions are propagated to.
+    min_peers_propagation: usize,
+    /// Maximal number of peers blocks and transactions are propagated to.
+    max_peers_propagation: usize,
+    /// Blocks the best block may move since the last propagation for new blocks to be propagated.
+    max_peer_lag_propagation: BlockNumber,
+    /// Size of the downloaded block data held by a block downloader.
+    max_download_memory: usize,
+    /// Subchain heads requests sent to a single peer at once.
+    chain_head_pipeline: usize,
+    /// Subchain heads requests outstanding at once over all peers.
+    chain_head_requests: usize,
+    /// Fixed number of blocks new blocks and hashes may be behind the last imported block.
+    max_new_block_age: Option<BlockNumber>,
+    /// Fixed number of hashes taken from a `NewHashes` packet.
+    max_new_hashes: Option<usize>,
+    /// Average interval between the imported blocks.
+    block_interval: BlockInterval,
+    /// Detects a best block which stopped advancing while peers move on.
+    stall_detector: StallDetector,
+    /// Recovery actions taken on a stalled best block.
+    stall_recoveries: StallRecoveryStats,
+    /// Rejected blocks and the branch of the peers serving them.
+    branch_prober: BranchProber,
+    /// Number of blocks below the best block the branch of peers may diverge to be synced.
+    max_reorg_depth: Option<BlockNumber>,
+    /// Operator override syncing branches deeper than `max_reorg_depth`.
+    allow_deep_reorg: bool,
+    /// Last branch given up on for diverging deeper than `max_reorg_depth`.
+    deep_reorg: Option<DeepReorg>,
+    /// Peers advertising a branch deeper than `max_reorg_depth`, no new blocks are downloaded
+    /// from them.
+    deep_reorg_peers: HashSet<PeerId>,
+    /// Never propagate transactions, blocks or consensus packets.
+    observer_mode: bool,
+    /// Sync progress and handshake rejections over time, for the health check.
+    health: HealthMonitor,
     /// New block encoding/decoding format is introduced by the EIP1559
     eip1559_transition: BlockNumber,
     /// Number of blocks for which new transactions will be returned in a result of `parity_newTransactionsStats` RPC call
     new_transactions_stats_period: BlockNumber,
+    /// Time for which new transactions stats are kept, overrides `new_transactions_stats_period`
+    new_transactions_stats_retention: Option<Duration>,
+    /// Advertise and serve the local snapshot.
+    serve_snapshots: bool,
+    /// Lowest block for which headers, bodies and receipts are served.
+    serve_from_block: Option<BlockNumber>,
+    /// Transactions rejected by the local pool, not requested again until they expire.
+    unwanted_transactions: UnwantedTransactions,
+    /// Peers which supplied the blocks waiting in the verification queue.
+    block_suppliers: BlockSuppliers,
+    /// Number of fetched transactions rejected by the local pool policy.
+    policy_rejected_transactions: usize,
+    /// Number of fetched transactions which were undecodable or not requested.
+    peer_fault_transactions: usize,
+    /// Number of `NewBlock` packets skipped because the block was already known.
+    skipped_new_blocks: usize,
+    /// Number of snapshot chunks dropped because they were too large or not the chunk requested.
+    bad_snapshot_chunks: usize,
+    /// Number of `NewBlock` packets extending the best block, verified ahead of the queued blocks.
+    fast_path_new_blocks: usize,
+    /// Blocks verified ahead of the queued blocks, with the time their `NewBlock` packet arrived.
+    fast_path_blocks: H256FastMap<Instant>,
+    /// Time from receiving the last fast path `NewBlock` packet until its block was imported.
+    new_block_import_latency: Option<Duration>,
+    /// Number of peers dropped because they did not send Status in time.
+    status_timeout_drops: usize,
+    /// Hashes of the most recently sealed local blocks, oldest first.
+    sealed_blocks: VecDeque<H256>,
+    /// Number of blocks sealed by this node and rejected by the block queue.
+    rejected_sealed_blocks: usize,
+    /// Notified when a block sealed by this node is rejected by the block queue.
+    sealed_block_rejected_handler: Option<SealedBlockRejectedHandler>,
+    /// Number of compressed block bodies and receipts packets sent.
+    compressed_packets_sent: usize,
+    /// Bytes saved by sending compressed instead of standard packets.
+    compressed_bytes_saved: usize,
+    /// Packets which could not be sent, by reason.
+    send_errors: SendErrorStats,
+    /// Packets received from peers, by packet id. Shared with the dispatch outside the lock.
+    packet_stats: Arc<PacketStats>,
+    /// Subscriber of sync events.
+    events: SyncEvents,
+    /// Locally submitted transactions which were not mined yet.
+    local_transactions: LocalTransactions,
+    /// Transactions of retracted blocks to announce to all peers in the next propagation round.
+    retracted_transactions: H256FastSet,
+}
+
+/// Snapshot chunk hashed off the sync thread.
+struct HashedChunk {
+    peer_id: PeerId,
+    requested: H256,
+    hash: H256,
+    data: Bytes,
 }
 
 #[derive(Debug, Default)]
@@ -769,6 +1238,237 @@ impl GetPooledTransactionsReport {
     }
 }
 
+/// Peer which is connected but has not sent its Status yet
+#[derive(Debug, Clone, Copy)]
+struct HandshakingPeer {
+    /// When our Status was sent
+    ask_time: Instant,
+    /// Time to wait for the peer's Status, jittered per peer
+    timeout: Duration,
+    /// Whether our Status was sent a second time
+    retried: bool,
+}
+
+impl HandshakingPeer {
+    fn new(now: Instant) -> Self {
+        HandshakingPeer {
+            ask_time: now,
+            timeout: Self::jittered_timeout(),
+            retried: false,
+        }
+    }
+
+    fn retry(now: Instant) -> Self {
+        HandshakingPeer {
+            retried: true,
+            ..HandshakingPeer::new(now)
+        }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.ask_time) > self.timeout
+    }
+
+    /// `STATUS_TIMEOUT` spread randomly, so that peers which connected at once
+    /// (e.g. after a mass reconnect) are not dropped at once as well.
+    fn jittered_timeout() -> Duration {
+        let timeout = STATUS_TIMEOUT.as_millis() as u64;
+        let jitter = timeout * STATUS_TIMEOUT_JITTER_PERCENT / 100;
+        Duration::from_millis(random::new().gen_range(timeout - jitter, timeout + jitter + 1))
+    }
+}
+
+/// Locally submitted transactions which were not mined yet.
+/// They are sent as full bodies to every peer, and again after the retry interval.
+struct LocalTransactions {
+    limit: usize,
+    retry_interval: Duration,
+    // peers the transaction was sent to and when
+    sent: H256FastMap<HashMap<PeerId, Instant>>,
+    order: VecDeque<H256>,
+}
+
+impl LocalTransactions {
+    fn new(limit: usize, retry_interval: Duration) -> Self {
+        LocalTransactions {
+            limit,
+            retry_interval,
+            sent: Default::default(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn insert(&mut self, hash: H256) {
+        if self.limit == 0 || self.sent.contains_key(&hash) {
+            return;
+        }
+        self.sent.insert(hash, HashMap::new());
+        self.order.push_back(hash);
+        while self.sent.len() > self.limit {
+            match self.order.pop_front() {
+                Some(oldest) => {
+                    self.sent.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn remove(&mut self, hash: &H256) {
+        if self.sent.remove(hash).is_some() {
+            self.order.retain(|h| h != hash);
+        }
+    }
+
+    fn contains(&self, hash: &H256) -> bool {
+        self.sent.contains_key(hash)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.sent.is_empty()
+    }
+
+    fn hashes(&self) -> Vec<H256> {
+        self.order.iter().cloned().collect()
+    }
+
+    /// Check if the transaction was never sent to the peer or the retry interval has passed.
+    fn is_due(&self, hash: &H256, peer_id: PeerId, now: Instant) -> bool {
+        self.sent.get(hash).is_some_and(|peers| {
+            peers
+                .get(&peer_id)
+                .is_none_or(|t| now.duration_since(*t) >= self.retry_interval)
+        })
+    }
+
+    fn mark_sent(&mut self, hash: &H256, peer_id: PeerId, now: Instant) {
+        if let Some(peers) = self.sent.get_mut(hash) {
+            peers.insert(peer_id, now);
+        }
+    }
+
+    fn on_peer_disconnected(&mut self, peer_id: PeerId) {
+        for peers in self.sent.values_mut() {
+            peers.remove(&peer_id);
+        }
+    }
+}
+
+/// Bounded set of transaction hashes rejected by the local transaction pool.
+/// Hashes are forgotten after `UNWANTED_TRANSACTIONS_TTL`.
+#[derive(Default)]
+struct UnwantedTransactions {
+    hashes: H256FastMap<Instant>,
+    order: VecDeque<H256>,
+}
+
+impl UnwantedTransactions {
+    fn insert(&mut self, hash: H256, now: Instant) {
+        self.prune(now);
+        if self.hashes.contains_key(&hash) {
+            return;
+        }
+        self.hashes.insert(hash, now);
+        self.order.push_back(hash);
+        if self.order.len() > MAX_UNWANTED_TRANSACTIONS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.hashes.remove(&oldest);
+            }
+        }
+    }
+
+    fn contains(&self, hash: &H256) -> bool {
+        self.hashes
+            .get(hash)
+            .is_some_and(|t| t.elapsed() < UNWANTED_TRANSACTIONS_TTL)
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while let Some(hash) = self.order.front() {
+            let expired = self
+                .hashes
+                .get(hash)
+                .is_none_or(|t| now.duration_since(*t) >= UNWANTED_TRANSACTIONS_TTL);
+            if !expired {
+                break;
+            }
+            self.hashes.remove(hash);
+            self.order.pop_front();
+        }
+    }
+}
+
+/// Peers which supplied the blocks put into the verification queue, so that the supplier
+/// of an invalid block can be penalized. Bounded to `MAX_BLOCK_SUPPLIERS` most recent blocks.
+#[derive(Default)]
+struct BlockSuppliers {
+    peers: H256FastMap<PeerId>,
+    order: VecDeque<H256>,
+}
+
+impl BlockSuppliers {
+    fn insert(&mut self, hash: H256, peer_id: PeerId) {
+        if self.peers.insert(hash, peer_id).is_some() {
+            return;
+        }
+        self.order.push_back(hash);
+        while self.peers.len() > MAX_BLOCK_SUPPLIERS {
+            match self.order.pop_front() {
+                Some(oldest) => {
+                    self.peers.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+        // hashes removed on import are left in `order`, drop them once they pile up
+        if self.order.len() > 2 * MAX_BLOCK_SUPPLIERS {
+            let peers = &self.peers;
+            self.order.retain(|h| peers.contains_key(h));
+        }
+    }
+
+    fn remove(&mut self, hash: &H256) -> Option<PeerId> {
+        self.peers.remove(hash)
+    }
+
+    fn on_peer_disconnected(&mut self, peer_id: PeerId) {
+        self.peers.retain(|_, p| *p != peer_id);
+    }
+}
+
+/// Exponential average of the chain time between imported blocks,
+/// taken from the timestamps of the enacted headers.
+#[derive(Default)]
+struct BlockInterval {
+    last: Option<(BlockNumber, u64)>,
+    average_ms: Option<u64>,
+}
+
+impl BlockInterval {
+    fn note(&mut self, number: BlockNumber, timestamp: u64) {
+        if let Some((last_number, last_timestamp)) = self.last {
+            // blocks of a reorganization only restart the measurement
+            if number > last_number && timestamp >= last_timestamp {
+                let sample =
+                    (timestamp - last_timestamp).saturating_mul(1000) / (number - last_number);
+                self.average_ms = Some(match self.average_ms {
+                    Some(average) => {
+                        (average * (BLOCK_INTERVAL_SAMPLES - 1) + sample) / BLOCK_INTERVAL_SAMPLES
+                    }
+                    None => sample,
+                });
+            }
+        }
+        self.last = Some((number, timestamp));
+    }
+
+    /// Number of blocks produced within `window` of chain time, if the interval is known.
+    fn blocks_within(&self, window: Duration) -> Option<u64> {
+        self.average_ms
+            .map(|average| window.as_millis() as u64 / cmp::max(average, 1))
+    }
+}
+
 impl ChainSync {
     pub fn new(
         config: SyncConfig,
@@ -779,36 +1479,122 @@ impl ChainSync {
         let chain_info = chain.chain_info();
         let best_block = chain.chain_info().best_block_number;
         let state = Self::get_init_state(config.warp_sync, chain);
+        let (hashed_chunks_tx, hashed_chunks_rx) = crossbeam_channel::unbounded();
 
         let mut sync = ChainSync {
             state,
             starting_block: best_block,
             highest_block: None,
+            hinted_highest_block: None,
+            head_hints: HeadHints::new(chain_info.genesis_hash),
             peers: HashMap::new(),
             handshaking_peers: HashMap::new(),
             active_peers: HashSet::new(),
-            delayed_requests: Vec::new(),
-            delayed_requests_ids: HashSet::new(),
+            inactive_peers: InactivePeers::new(INACTIVE_PEER_REPROBE),
+            disabled_peers: HashSet::new(),
+            idle_peers: HashSet::new(),
+            continue_sync_interval: config.continue_sync_interval,
+            continue_sync_pending: false,
+            last_continue_sync: None,
+            peer_scans: 0,
+            delayed_requests: DelayedRequests::default(),
             new_blocks: BlockDownloader::new(
                 BlockSet::NewBlocks,
                 &chain_info.best_block_hash,
                 chain_info.best_block_number,
             ),
             old_blocks: None,
+            header_backfill: config
+                .header_checkpoint
+                .filter(|(_, hash)| {
+                    chain.block_status(BlockId::Hash(*hash)) != BlockStatus::InChain
+                })
+                .map(HeaderBackfill::new),
             last_sent_block_number: 0,
             network_id: config.network_id,
             fork_block: config.fork_block,
+            derived_fork_block: None,
             fork_filter,
             download_old_blocks: config.download_old_blocks,
             snapshot: Snapshot::new(),
+            snapshot_data_parts: chunking::Reassembler::new(
+                network::MAX_PAYLOAD_SIZE,
+                MAX_PARTIAL_SNAPSHOT_DATA,
+                SNAPSHOT_DATA_TIMEOUT,
+            ),
             sync_start_time: None,
             new_transaction_hashes,
             transactions_stats: TransactionsStats::default(),
             warp_sync: config.warp_sync,
+            peer_selection: config.peer_selection,
+            consensus_propagation: config.consensus_propagation,
+            validator_nodes: HashSet::new(),
+            validators: HashSet::new(),
+            validator_node_map: HashMap::new(),
+            max_block_difficulty: config.max_block_difficulty,
+            tx_relay_min_protocol: config.tx_relay_min_protocol,
+            max_transaction_packet_size: config.max_transaction_packet_size,
+            max_transactions_to_request: config.max_transactions_to_request,
+            min_peers_propagation: config.min_peers_propagation,
+            max_peers_propagation: config.max_peers_propagation,
+            max_peer_lag_propagation: config.max_peer_lag_propagation,
+            max_download_memory: config.max_download_memory,
+            chain_head_pipeline: config.chain_head_pipeline,
+            chain_head_requests: config.chain_head_requests,
+            max_new_block_age: config.max_new_block_age,
+            max_new_hashes: config.max_new_hashes,
+            block_interval: BlockInterval::default(),
+            stall_detector: StallDetector::new(
+                config.stall_block_lag,
+                config.stall_timeout,
+                chain.chain_info().best_block_number,
+            ),
+            stall_recoveries: StallRecoveryStats::default(),
+            branch_prober: BranchProber::new(
+                config.branch_probe_threshold,
+                config.branch_probe_window,
+                config.branch_probe_interval,
+            ),
+            max_reorg_depth: Some(config.max_reorg_depth).filter(|depth| *depth > 0),
+            allow_deep_reorg: false,
+            deep_reorg: None,
+            deep_reorg_peers: HashSet::new(),
+            observer_mode: config.observer_mode,
+            health: HealthMonitor::new(Instant::now()),
             eip1559_transition: config.eip1559_transition,
             new_transactions_stats_period: config.new_transactions_stats_period,
+            new_transactions_stats_retention: config.new_transactions_stats_retention,
+            serve_snapshots: config.serve_snapshots,
+            serve_from_block: config.serve_from_block,
+            unwanted_transactions: UnwantedTransactions::default(),
+            block_suppliers: BlockSuppliers::default(),
+            policy_rejected_transactions: 0,
+            peer_fault_transactions: 0,
+            skipped_new_blocks: 0,
+            bad_snapshot_chunks: 0,
+            fast_path_new_blocks: 0,
+            fast_path_blocks: H256FastMap::default(),
+            new_block_import_latency: None,
+            status_timeout_drops: 0,
+            sealed_blocks: VecDeque::new(),
+            rejected_sealed_blocks: 0,
+            sealed_block_rejected_handler: None,
+            compressed_packets_sent: 0,
+            compressed_bytes_saved: 0,
+            send_errors: SendErrorStats::default(),
+            packet_stats: Arc::new(PacketStats::default()),
+            events: SyncEvents::default(),
+            local_transactions: LocalTransactions::new(
+                config.max_local_transactions,
+                config.local_transactions_retry_interval,
+            ),
+            retracted_transactions: Default::default(),
+            executor: Executor::new_sync(),
+            hashed_chunks_tx,
+            hashed_chunks_rx,
         };
         sync.update_targets(chain);
+        sync.update_derived_fork_block(chain);
         sync
     }
 
@@ -821,6 +1607,16 @@ impl ChainSync {
             .as_ref()
             .map_or((), |d| d.get_sizes(&mut item_sizes));
         self.new_blocks.get_sizes(&mut item_sizes);
+        item_sizes.insert("delayed_requests".into(), self.delayed_requests.len());
+        item_sizes.insert("handshaking_peers".into(), self.handshaking_peers.len());
+        let num_peers = self.peers.values().filter(|p| p.is_allowed()).count();
+        let num_tx_relay_peers = self
+            .peers
+            .values()
+            .filter(|p| p.is_allowed() && self.relays_transactions(p))
+            .count();
+
+        let highest_block = self.highest_block.or(self.hinted_highest_block);
 
         SyncStatus {
             state: self.state,
@@ -832,24 +1628,302 @@ impl ChainSync {
                 .old_blocks
                 .as_ref()
                 .map(|d| d.last_imported_block_number()),
-            highest_block_number: self
-                .highest_block
-                .map(|n| cmp::max(n, last_imported_number)),
+            highest_block_number: highest_block.map(|n| cmp::max(n, last_imported_number)),
+            highest_block_hinted: self.highest_block.is_none() && highest_block.is_some(),
             blocks_received: last_imported_number.saturating_sub(self.starting_block),
-            blocks_total: match self.highest_block {
+            blocks_total: match highest_block {
                 Some(x) if x > self.starting_block => x - self.starting_block,
                 _ => 0,
             },
-            num_peers: self.peers.values().filter(|p| p.is_allowed()).count(),
+            num_peers,
             num_active_peers: self
                 .peers
                 .values()
                 .filter(|p| p.is_allowed() && p.asking != PeerAsking::Nothing)
                 .count(),
+            num_tx_relay_peers,
+            num_sync_only_peers: num_peers - num_tx_relay_peers,
+            clients: client_version::summarize(
+                self.peers
+                    .values()
+                    .filter(|p| p.is_allowed())
+                    .map(|p| &p.client_version),
+            ),
             num_snapshot_chunks: self.snapshot.total_chunks(),
             snapshot_chunks_done: self.snapshot.done_chunks(),
             item_sizes,
+            num_policy_rejected_transactions: self.policy_rejected_transactions,
+            num_peer_fault_transactions: self.peer_fault_transactions,
+            num_skipped_new_blocks: self.skipped_new_blocks,
+            num_bad_snapshot_chunks: self.bad_snapshot_chunks,
+            num_fast_path_new_blocks: self.fast_path_new_blocks,
+            new_block_import_latency: self.new_block_import_latency,
+            num_status_timeout_drops: self.status_timeout_drops,
+            num_rejected_sealed_blocks: self.rejected_sealed_blocks,
+            num_inactive_peers: self.inactive_peers.len(),
+            num_disabled_peers: self.disabled_peers.len(),
+            num_compressed_packets_sent: self.compressed_packets_sent,
+            compressed_bytes_saved: self.compressed_bytes_saved,
+            num_dropped_sync_events: self.events.dropped(),
+            num_delayed_requests: self.delayed_requests.delayed(),
+            num_evicted_delayed_requests: self.delayed_requests.evicted(),
+            num_expired_delayed_requests: self.delayed_requests.expired(),
+            headers_backfilled: self.header_backfill.as_ref().map_or(0, |b| b.backfilled()),
+            headers_backfill_target: self.header_backfill.as_ref().map_or(0, |b| b.target()),
+            send_errors: self.send_errors,
+            packet_stats: self.packet_stats.snapshot(),
+            stall_recoveries: self.stall_recoveries,
+            branch_probe: self
+                .branch_prober
+                .status(self.branch_probe_target(Instant::now())),
+            deep_reorg: self.deep_reorg.clone(),
+            observer_mode: self.observer_mode,
+        }
+    }
+
+    /// Account a block received at `received` which was verified ahead of the queued blocks.
+    fn note_fast_path_block(&mut self, hash: H256, received: Instant) {
+        self.fast_path_new_blocks += 1;
+        // blocks dropped from the queue are never reported as imported
+        self.fast_path_blocks
+            .retain(|_, at| received.saturating_duration_since(*at) < FAST_PATH_BLOCK_TTL);
+        self.fast_path_blocks.insert(hash, received);
+    }
+
+    /// Account a compressed packet sent in place of an `original`-byte standard one.
+    fn on_compressed_packet_sent(&mut self, original: usize, compressed: usize) {
+        self.compressed_packets_sent += 1;
+        self.compressed_bytes_saved += original.saturating_sub(compressed);
+    }
+
+    /// React to a packet which could not be sent to `peer_id`: forget a peer which is gone,
+    /// skip packets to a peer which can't keep up and disconnect on any other failure.
+    fn on_send_error(&mut self, io: &mut dyn SyncIo, peer_id: PeerId, e: &network::Error) {
+        match *e.kind() {
+            network::ErrorKind::PeerGone => {
+                self.send_errors.peer_gone += 1;
+                trace!(target: "sync", "{peer_id}: Peer gone while sending, cleaning up");
+                SyncHandler::on_peer_aborting(self, io, peer_id);
+            }
+            network::ErrorKind::SendQueueFull(queued_bytes) => {
+                self.send_errors.send_queue_full += 1;
+                trace!(target: "sync", "{peer_id}: Send queue full ({queued_bytes} bytes), packet skipped");
+            }
+            network::ErrorKind::SessionExpired => {
+                self.send_errors.session_expired += 1;
+            }
+            network::ErrorKind::OversizedPacket => {
+                self.send_errors.oversized += 1;
+                debug!(target: "sync", "{peer_id}: Packet over the payload limit not sent");
+            }
+            network::ErrorKind::ProtocolUnsupported => {
+                self.send_errors.protocol_unsupported += 1;
+                debug!(target: "sync", "{peer_id}: Peer does not support the packet protocol");
+            }
+            _ => {
+                self.send_errors.other += 1;
+                debug!(target: "sync", "Error sending packet: {e:?}");
+                io.disconnect_peer(peer_id);
+            }
+        }
+    }
+
+    /// Set the channel sync events are sent to, replacing any previous one.
+    /// Events are dropped while the channel is full.
+    pub fn set_event_sender(&mut self, sender: Option<crossbeam_channel::Sender<SyncEvent>>) {
+        self.events.set_sender(sender);
+    }
+
+    /// Set the executor snapshot chunks are hashed on, they are hashed inline by default.
+    pub fn set_executor(&mut self, executor: Executor) {
+        self.executor = executor;
+    }
+
+    /// Hash a snapshot chunk received from `peer_id` on the blocking pool of the executor.
+    /// The chunk is imported by `SyncHandler::import_hashed_chunks` once hashed.
+    fn hash_snapshot_chunk(&self, peer_id: PeerId, requested: H256, data: Bytes) {
+        let sender = self.hashed_chunks_tx.clone();
+        // the chunk is delivered through the channel, the result is not awaited
+        let _ = self
+            .executor
+            .spawn_blocking("snapshot-chunk-hash", move || {
+                let hash = keccak(&data);
+                let _ = sender.send(HashedChunk {
+                    peer_id,
+                    requested,
+                    hash,
+                    data,
+                });
+            });
+    }
+
+    /// Set the handler notified when a block sealed by this node is rejected by the block queue,
+    /// e.g. for the miner to stop sealing on a parent the rejected block was built on.
+    /// It is called with the sync lock held and must not call back into sync.
+    pub fn set_sealed_block_rejected_handler(
+        &mut self,
+        handler: Option<SealedBlockRejectedHandler>,
+    ) {
+        self.sealed_block_rejected_handler = handler;
+    }
+
+    /// Report the rejection of a block sealed by this node.
+    fn on_sealed_block_rejected(&mut self, io: &dyn SyncIo, hash: &H256) {
+        self.rejected_sealed_blocks += 1;
+        let reason = io
+            .chain()
+            .bad_blocks()
+            .into_iter()
+            .find(|(block, _)| block.header.hash() == *hash)
+            .map_or_else(|| "unknown reason".to_owned(), |(_, reason)| reason);
+        error!(target: "sync", "Block {hash:?} sealed by this node was rejected: {reason}. Check the system clock and the engine configuration.");
+        if let Some(ref handler) = self.sealed_block_rejected_handler {
+            handler(*hash, &reason);
+        }
+    }
+
+    /// Move to `state`, notifying the event subscriber.
+    fn set_state(&mut self, state: SyncState) {
+        if self.state != state {
+            self.events.emit(SyncEventKind::StateChanged {
+                from: self.state,
+                to: state,
+            });
+            self.state = state;
+        }
+    }
+
+    /// Max number of blocks a new block or hash may be behind the last imported block.
+    /// Scales with the block interval unless configured.
+    fn max_new_block_age(&self) -> BlockNumber {
+        self.max_new_block_age.unwrap_or_else(|| {
+            self.block_interval
+                .blocks_within(NEW_BLOCK_AGE_WINDOW)
+                .map_or(MAX_NEW_BLOCK_AGE, |blocks| {
+                    blocks.clamp(MIN_NEW_BLOCK_AGE, MAX_NEW_BLOCK_AGE_CEILING)
+                })
+        })
+    }
+
+    /// Max number of hashes taken from a `NewHashes` packet.
+    /// Scales with the block frequency unless configured.
+    fn max_new_hashes(&self) -> usize {
+        self.max_new_hashes.unwrap_or_else(|| {
+            self.block_interval
+                .blocks_within(NEW_BLOCK_AGE_WINDOW)
+                .map_or(MAX_NEW_HASHES, |blocks| {
+                    (blocks as usize).clamp(MAX_NEW_HASHES, MAX_NEW_HASHES_CEILING)
+                })
+        })
+    }
+
+    /// Use the heads peers advertised before a restart to estimate the highest block until a
+    /// peer announces one.
+    pub fn set_head_hints(&mut self, hints: HeadHints) {
+        self.hinted_highest_block = hints.highest_block(SystemTime::now());
+        if let Some(number) = self.hinted_highest_block {
+            debug!(target: "sync", "Estimating highest block #{number} from {} head hints (unverified)", hints.len());
+        }
+        self.head_hints = hints;
+    }
+
+    /// Heads last advertised by peers, by node id.
+    pub fn head_hints(&self) -> &HeadHints {
+        &self.head_hints
+    }
+
+    /// Note the head `peer_id` advertised in a status packet or announced with a block.
+    fn note_head_hint(
+        &mut self,
+        peer_id: PeerId,
+        hash: H256,
+        number: Option<BlockNumber>,
+        total_difficulty: Option<U256>,
+    ) {
+        let node = match self.peers.get(&peer_id).and_then(|p| p.node_id) {
+            Some(node) => node,
+            None => return,
+        };
+        let now = SystemTime::now();
+        match number {
+            Some(number) => self
+                .head_hints
+                .note_block(node, hash, number, total_difficulty, now),
+            None => self
+                .head_hints
+                .note_status(node, hash, total_difficulty, now),
+        }
+    }
+
+    /// Record a block number announced by the network.
+    fn note_highest_block(&mut self, number: BlockNumber) {
+        if number > self.highest_block.unwrap_or(0) {
+            self.highest_block = Some(number);
+            self.events.emit(SyncEventKind::HighestBlock { number });
+        }
+    }
+
+    /// Enable or disable advertising and serving the local snapshot.
+    pub fn set_serve_snapshots(&mut self, serve: bool) {
+        self.serve_snapshots = serve;
+    }
+
+    /// Returns true if the local snapshot may be served to peers.
+    pub fn serves_snapshots(&self) -> bool {
+        self.serve_snapshots
+    }
+
+    /// Replace the node ids of the current validators, to be called on every validator set
+    /// change. With `ConsensusPropagation::Validators` proposed blocks and consensus packets
+    /// are only sent to connected validators.
+    pub fn set_validator_nodes(&mut self, nodes: HashSet<NodeId>) {
+        debug!(target: "sync", "Validator nodes set to {nodes:?}");
+        self.validator_nodes = nodes;
+    }
+
+    /// Replace the addresses of the current validators. Their peers are found with the node ids
+    /// of `set_validator_node_map`, in addition to the nodes of `set_validator_nodes`.
+    pub fn set_validators(&mut self, validators: HashSet<Address>) {
+        debug!(target: "sync", "Validators set to {validators:?}");
+        self.validators = validators;
+    }
+
+    /// Set the node ids of the validators by address. A validator's node key is usually not
+    /// its signing key, its node can't be derived from the address.
+    pub fn set_validator_node_map(&mut self, nodes: HashMap<Address, NodeId>) {
+        self.validator_node_map = nodes;
+    }
+
+    /// Allow or forbid syncing branches which diverge deeper than the maximum reorg depth.
+    pub fn set_allow_deep_reorg(&mut self, allow: bool) {
+        self.allow_deep_reorg = allow;
+        self.new_blocks
+            .set_max_reorg_depth(self.reorg_depth_limit());
+        if allow {
+            if let Some(reorg) = self.deep_reorg.take() {
+                info!(target: "sync", "Deep reorg allowed, syncing the branch diverging at #{} ({})", reorg.number, reorg.hash);
+            }
+            self.deep_reorg_peers.clear();
+        }
+    }
+
+    /// Returns the lowest block for which data is served to peers, if limited.
+    pub fn serve_from_block(&self) -> Option<BlockNumber> {
+        self.serve_from_block
+    }
+
+    /// Returns the manifest to advertise to peers, if any.
+    /// Only snapshots with all chunks present are advertised.
+    fn advertised_manifest(&self, io: &dyn SyncIo) -> Option<ManifestData> {
+        if !self.serve_snapshots {
+            return None;
         }
+        let manifest = io.snapshot_service().manifest()?;
+        if !io.snapshot_service().is_manifest_complete() {
+            trace!(target: "warp", "Not advertising incomplete snapshot #{}", manifest.block_number);
+            return None;
+        }
+        Some(manifest)
     }
 
     /// Returns information on peers connections
@@ -858,6 +1932,16 @@ impl ChainSync {
             version: peer_data.protocol_version as u32,
             difficulty: peer_data.difficulty,
             head: peer_data.latest_hash,
+            eth_version: peer_data.eth_protocol_version,
+            par_version: peer_data.par_protocol_version,
+            confirmation: peer_data.confirmation,
+            network_id: peer_data.network_id,
+            genesis: peer_data.genesis,
+            snapshot_number: peer_data.snapshot_number,
+            snapshot_hash: peer_data.snapshot_hash,
+            block_set: peer_data.block_set,
+            client_version: peer_data.client_version.clone(),
+            fee_market: peer_data.fee_market,
         })
     }
 
@@ -910,24 +1994,6 @@ impl ChainSync {
                 .copied()
                 .collect();
             if *pid == peer_id {
-                match GetPooledTransactionsReport::generate(
-                    std::mem::take(&mut peer_info.asking_pooled_transactions),
-                    txs.iter().map(UnverifiedTransaction::hash),
-                ) {
-                    Ok(report) => {
-                        // Some transactions were not received in this batch because of size.
-                        // Add them back to request feed.
-                        peer_info.unfetched_pooled_transactions = peer_info
-                            .unfetched_pooled_transactions
-                            .union(&report.not_sent)
-                            .copied()
-                            .collect();
-                    }
-                    Err(_unknown_tx) => {
-                        // punish peer?
-                    }
-                }
-
                 peer_info
                     .last_sent_transactions
                     .extend(txs.iter().map(|tx| tx.hash()));
@@ -935,19 +2001,52 @@ impl ChainSync {
         }
     }
 
+    /// Updates transactions received from a peer but rejected by the local pool policy.
+    /// The peer is not at fault; the transactions are just not requested again for a while.
+    pub fn transactions_rejected(&mut self, hashes: &[H256], peer_id: PeerId) {
+        trace!(target: "sync", "{} -> {} transactions rejected by local policy", peer_id, hashes.len());
+        let now = Instant::now();
+        for hash in hashes {
+            self.unwanted_transactions.insert(*hash, now);
+        }
+        self.policy_rejected_transactions += hashes.len();
+
+        let rejected = hashes.iter().copied().collect::<H256FastSet>();
+        for peer_info in self.peers.values_mut() {
+            peer_info
+                .unfetched_pooled_transactions
+                .retain(|hash| !rejected.contains(hash));
+        }
+    }
+
+    /// Whether transactions are relayed to and fetched from the peer.
+    fn relays_transactions(&self, peer: &PeerInfo) -> bool {
+        peer.eth_protocol_version >= self.tx_relay_min_protocol
+    }
+
+    /// Peers transactions are relayed to.
+    fn tx_relay_peers(&self) -> impl Iterator<Item = PeerId> + '_ {
+        self.peers
+            .iter()
+            .filter(move |(_, peer)| self.relays_transactions(peer))
+            .map(|(peer_id, _)| *peer_id)
+    }
+
     /// Abort all sync activity
     pub fn abort(&mut self, io: &mut dyn SyncIo) {
         self.reset_and_continue(io);
         self.peers.clear();
+        self.idle_peers.clear();
+        self.disabled_peers.clear();
     }
 
     /// Reset sync. Clear all downloaded data but keep the queue.
     /// Set sync state to the given state or to the initial state if `None` is provided.
     fn reset(&mut self, io: &mut dyn SyncIo, state: Option<SyncState>) {
-        self.new_blocks.reset();
+        self.new_blocks.restart();
         let chain_info = io.chain().chain_info();
         for ref mut p in self.peers.values_mut() {
-            if p.block_set != Some(BlockSet::OldBlocks) {
+            if p.block_set != Some(BlockSet::OldBlocks) && p.asking != PeerAsking::BackfillHeaders {
                 p.reset_asking();
                 if p.difficulty.is_none() {
                     // assume peer has up to date difficulty
@@ -955,26 +2054,32 @@ impl ChainSync {
                 }
             }
         }
-        self.state = state.unwrap_or_else(|| Self::get_init_state(self.warp_sync, io.chain()));
+        let state = state.unwrap_or_else(|| Self::get_init_state(self.warp_sync, io.chain()));
+        self.set_state(state);
         // Reactivate peers only if some progress has been made
         // since the last sync round of if starting fresh.
-        self.active_peers = self.peers.keys().cloned().collect();
+        let disabled = &self.disabled_peers;
+        self.active_peers = self
+            .peers
+            .keys()
+            .filter(|peer_id| !disabled.contains(*peer_id))
+            .cloned()
+            .collect();
+        self.idle_peers = self.active_peers.clone();
+        self.inactive_peers.clear();
     }
 
     /// Add a request for later processing
     pub fn add_delayed_request(&mut self, peer: PeerId, packet_id: u8, data: &[u8]) {
         // Ignore the request, if there is a request already in queue with the same id
-        if !self.delayed_requests_ids.contains(&(peer, packet_id)) {
-            self.delayed_requests_ids.insert((peer, packet_id));
-            self.delayed_requests.push((peer, packet_id, data.to_vec()));
+        if self.delayed_requests.add(peer, packet_id, data) {
             debug!(target: "sync", "Delayed request with packet id {packet_id} from peer {peer} added");
         }
     }
 
-    /// Drain and return all delayed requests
+    /// Drain and return the oldest delayed requests, the rest is left for the next round
     pub fn retrieve_delayed_requests(&mut self) -> Vec<(PeerId, u8, Vec<u8>)> {
-        self.delayed_requests_ids.clear();
-        self.delayed_requests.drain(..).collect()
+        self.delayed_requests.drain(MAX_REQUESTS_PER_DRAIN)
     }
 
     /// Restart sync
@@ -989,17 +2094,100 @@ impl ChainSync {
         self.continue_sync(io);
     }
 
-    /// Remove peer from active peer set. Peer will be reactivated on the next sync
-    /// round.
-    fn deactivate_peer(&mut self, _io: &mut dyn SyncIo, peer_id: PeerId) {
-        trace!(target: "sync", "Deactivating peer {peer_id}");
+    /// Wait for the Status of a peer ours was sent to. Above `MAX_HANDSHAKING_PEERS` the peers
+    /// waiting the longest are disconnected, so entries of sessions which ended without notice
+    /// can't accumulate.
+    fn add_handshaking_peer(&mut self, io: &mut dyn SyncIo, peer: PeerId, now: Instant) {
+        while self.handshaking_peers.len() >= MAX_HANDSHAKING_PEERS
+            && !self.handshaking_peers.contains_key(&peer)
+        {
+            let oldest = self
+                .handshaking_peers
+                .iter()
+                .min_by_key(|(_, handshake)| handshake.ask_time)
+                .map(|(peer, _)| *peer)
+                .expect("the map holds at least MAX_HANDSHAKING_PEERS entries; qed");
+            debug!(target: "sync", "Too many handshaking peers, dropping {oldest}");
+            self.handshaking_peers.remove(&oldest);
+            io.disconnect_peer(oldest);
+        }
+        self.handshaking_peers
+            .insert(peer, HandshakingPeer::new(now));
+    }
+
+    /// Remove a peer which has nothing to give us for now from the active peer set. The
+    /// peer is reactivated once it announces a head changing that, after
+    /// `INACTIVE_PEER_REPROBE` or on the next sync round, whichever comes first.
+    fn deactivate_peer(
+        &mut self,
+        _io: &mut dyn SyncIo,
+        peer_id: PeerId,
+        reason: DeactivationReason,
+    ) {
+        let peer = match self.peers.get(&peer_id) {
+            Some(peer) if !self.disabled_peers.contains(&peer_id) => peer,
+            _ => return,
+        };
+        trace!(target: "sync", "Deactivating peer {peer_id}: {reason:?}");
         self.active_peers.remove(&peer_id);
+        self.inactive_peers.insert(
+            peer_id,
+            reason,
+            peer.latest_hash,
+            peer.difficulty,
+            Instant::now(),
+        );
     }
 
-    fn maybe_start_snapshot_sync(&mut self, io: &mut dyn SyncIo) {
-        if !self.warp_sync.is_enabled() || io.snapshot_service().supported_versions().is_none() {
-            trace!(target: "sync", "Skipping warp sync. Disabled or not supported.");
-            return;
+    /// Disable a misbehaving peer. It is disconnected and never reactivated.
+    fn disable_peer(&mut self, io: &mut dyn SyncIo, peer_id: PeerId) {
+        trace!(target: "sync", "Disabling peer {peer_id}");
+        io.disable_peer(peer_id);
+        self.active_peers.remove(&peer_id);
+        self.inactive_peers.remove(peer_id);
+        if self.peers.contains_key(&peer_id) {
+            self.disabled_peers.insert(peer_id);
+        }
+    }
+
+    /// Disable a peer which provably misbehaved and have the network ban it, also across restarts.
+    fn ban_peer(&mut self, io: &mut dyn SyncIo, peer_id: PeerId, reason: &str) {
+        trace!(target: "sync", "Banning peer {peer_id}: {reason}");
+        io.ban_peer(peer_id, reason);
+        self.active_peers.remove(&peer_id);
+        self.inactive_peers.remove(peer_id);
+        if self.peers.contains_key(&peer_id) {
+            self.disabled_peers.insert(peer_id);
+        }
+    }
+
+    /// Reactivate a benignly deactivated peer announcing a head which may make it useful again.
+    fn on_peer_announced_head(&mut self, peer_id: PeerId, head: H256, difficulty: Option<U256>) {
+        if self
+            .inactive_peers
+            .on_announcement(peer_id, head, difficulty)
+        {
+            trace!(target: "sync", "Reactivating peer {peer_id} on a new head {head}");
+            self.reactivate_peer(peer_id);
+        }
+    }
+
+    fn reactivate_peer(&mut self, peer_id: PeerId) {
+        if self.peers.contains_key(&peer_id) {
+            self.active_peers.insert(peer_id);
+            self.idle_peers.insert(peer_id);
+        }
+    }
+
+    fn maybe_start_snapshot_sync(&mut self, io: &mut dyn SyncIo) {
+        if !self.warp_sync.is_enabled() || io.snapshot_service().supported_versions().is_none() {
+            trace!(target: "sync", "Skipping warp sync. Disabled or not supported.");
+            // peers were confirmed again after a stall, see `reconfirm_peers`
+            if self.state == SyncState::WaitingPeers && self.peers.values().any(|p| p.can_sync()) {
+                self.set_state(SyncState::Idle);
+                self.schedule_continue_sync(io);
+            }
+            return;
         }
         if self.state != SyncState::WaitingPeers
             && self.state != SyncState::Blocks
@@ -1012,6 +2200,8 @@ impl ChainSync {
         // that it is higher than fork detection block
         let our_best_block = io.chain().chain_info().best_block_number;
         let fork_block = self.fork_block.map_or(0, |(n, _)| n);
+        // the head hints are good enough to rule out stale snapshots until a peer announces a block
+        let highest_block = self.highest_block.or(self.hinted_highest_block);
 
         let (best_hash, max_peers, snapshot_peers) = {
             let expected_warp_block = match self.warp_sync {
@@ -1032,7 +2222,7 @@ impl ChainSync {
 					// Snapshot must be greater than the warp barrier if any
 					sn > expected_warp_block &&
 					// If we know a highest block, snapshot must be recent enough
-					self.highest_block.is_none_or(|highest| {
+					highest_block.is_none_or(|highest| {
 						highest < sn || (highest - sn) <= SNAPSHOT_RESTORE_THRESHOLD
 					}))
                 })
@@ -1070,8 +2260,8 @@ impl ChainSync {
             }
         } else if timeout && !self.warp_sync.is_warp_only() {
             trace!(target: "sync", "No snapshots found, starting full sync");
-            self.state = SyncState::Idle;
-            self.continue_sync(io);
+            self.set_state(SyncState::Idle);
+            self.schedule_continue_sync(io);
         }
     }
 
@@ -1086,10 +2276,10 @@ impl ChainSync {
                     SyncRequester::request_snapshot_manifest(self, io, *p);
                 }
             }
-            self.state = SyncState::SnapshotManifest;
+            self.set_state(SyncState::SnapshotManifest);
             trace!(target: "sync", "New snapshot sync with {peers:?}");
         } else {
-            self.state = SyncState::SnapshotData;
+            self.set_state(SyncState::SnapshotData);
             trace!(target: "sync", "Resumed snapshot sync with {peers:?}");
         }
     }
@@ -1109,51 +2299,173 @@ impl ChainSync {
             &chain.best_block_hash,
             chain.best_block_number,
         );
-        self.old_blocks = None;
-        if self.download_old_blocks {
-            if let (Some(ancient_block_hash), Some(ancient_block_number)) =
-                (chain.ancient_block_hash, chain.ancient_block_number)
+        self.new_blocks.set_max_memory(self.max_download_memory);
+        self.new_blocks
+            .set_chain_head_pipeline(self.chain_head_pipeline, self.chain_head_requests);
+        self.new_blocks
+            .set_max_reorg_depth(self.reorg_depth_limit());
+        self.old_blocks = self.old_blocks_downloader(&chain);
+    }
+
+    // Downloader for the missing ancient blocks, if any.
+    fn old_blocks_downloader(&self, chain: &BlockChainInfo) -> Option<BlockDownloader> {
+        if !self.download_old_blocks {
+            return None;
+        }
+        let (ancient_block_hash, ancient_block_number) =
+            match (chain.ancient_block_hash, chain.ancient_block_number) {
+                (Some(hash), Some(number)) => (hash, number),
+                _ => return None,
+            };
+        if let Some(boundary) = chain.retention_boundary {
+            if boundary > ancient_block_number + 1 {
+                info!(target: "sync", "Skipping old blocks download: blocks after #{} are below the retention boundary #{} and would be pruned", ancient_block_number, boundary);
+                return None;
+            }
+        }
+        info!(target: "sync", "Downloading old blocks from {:?} (#{}) till {:?} (#{:?})", ancient_block_hash, ancient_block_number, chain.first_block_hash, chain.first_block_number);
+        let mut downloader = BlockDownloader::new(
+            BlockSet::OldBlocks,
+            &ancient_block_hash,
+            ancient_block_number,
+        );
+        downloader.set_max_memory(self.max_download_memory);
+        downloader.set_chain_head_pipeline(self.chain_head_pipeline, self.chain_head_requests);
+        if let Some(hash) = chain.first_block_hash {
+            trace!(target: "sync", "Downloader target for old blocks is set to {hash:?}");
+            downloader.set_target(&hash);
+        } else {
+            trace!(target: "sync", "Downloader target could not be found");
+        }
+        Some(downloader)
+    }
+
+    /// Scan the ancient blocks in `range` for bodies or receipts which do not match their headers
+    /// and add the numbers and hashes of the mismatching blocks to `mismatched`. Missing bodies
+    /// count as mismatch, missing receipts are not checked. Returns `false` if the scan stopped
+    /// at a block not in the chain.
+    pub fn scan_ancient_blocks(
+        &self,
+        chain: &dyn BlockChainClient,
+        range: RangeInclusive<BlockNumber>,
+        mismatched: &mut Vec<(BlockNumber, H256)>,
+    ) -> bool {
+        for number in range {
+            let hash = match chain.block_hash(BlockId::Number(number)) {
+                Some(hash) => hash,
+                None => return false,
+            };
+            let header = match chain
+                .block_header(BlockId::Hash(hash))
+                .and_then(|h| h.decode(self.eip1559_transition).ok())
             {
-                info!(target: "sync", "Downloading old blocks from {:?} (#{}) till {:?} (#{:?})", ancient_block_hash, ancient_block_number, chain.first_block_hash, chain.first_block_number);
-                let mut downloader = BlockDownloader::new(
-                    BlockSet::OldBlocks,
-                    &ancient_block_hash,
-                    ancient_block_number,
-                );
-                if let Some(hash) = chain.first_block_hash {
-                    trace!(target: "sync", "Downloader target for old blocks is set to {hash:?}");
-                    downloader.set_target(&hash);
-                } else {
-                    trace!(target: "sync", "Downloader target could not be found");
-                }
+                Some(header) => header,
+                None => return false,
+            };
+            let receipts = chain.block_receipts(&hash).map(|r| ::rlp::encode(&r));
+            let receipts = receipts.as_ref().map(|r| Rlp::new(r));
+            let matches = chain.block_body(BlockId::Hash(hash)).is_some_and(|body| {
+                verify_block_data(
+                    &header,
+                    &body.transactions_rlp(),
+                    &body.uncles_rlp(),
+                    receipts.as_ref(),
+                )
+                .is_ok()
+            });
+            if !matches {
+                warn!(target: "sync", "Ancient block #{number} {hash:?} does not match its header");
+                mismatched.push((number, hash));
+            }
+        }
+        true
+    }
+
+    /// Download the `mismatched` ancient blocks found by `scan_ancient_blocks` again.
+    ///
+    /// All blocks between the first and the last mismatching one are downloaded, only the
+    /// mismatching ones are written. A running ancient blocks download resumes afterwards.
+    pub fn repair_ancient_blocks(
+        &mut self,
+        chain: &dyn BlockChainClient,
+        mismatched: &[(BlockNumber, H256)],
+    ) {
+        let (first, last) = match (mismatched.first(), mismatched.last()) {
+            (Some(first), Some(last)) => (first.0, last.0),
+            _ => return,
+        };
+        let repairs = mismatched.iter().map(|(_, hash)| *hash).collect();
+        let start = first
+            .checked_sub(1)
+            .and_then(|number| Some((number, chain.block_hash(BlockId::Number(number))?)));
+        let target = chain.block_hash(BlockId::Number(last + 1));
+        match (start, target) {
+            (Some((number, hash)), Some(target)) => {
+                info!(target: "sync", "Downloading ancient blocks #{first} to #{last} again");
+                self.reset_downloads(BlockSet::OldBlocks);
+                let mut downloader = BlockDownloader::new_repair(&hash, number, &target, repairs);
+                downloader.set_max_memory(self.max_download_memory);
+                downloader
+                    .set_chain_head_pipeline(self.chain_head_pipeline, self.chain_head_requests);
                 self.old_blocks = Some(downloader);
             }
+            _ => {
+                warn!(target: "sync", "Can not download ancient blocks #{first} to #{last} again, the range must not include the genesis or the best block");
+            }
+        }
+    }
+
+    /// Stop downloading headers backwards from the checkpoint. A pending response is ignored.
+    pub fn abort_header_backfill(&mut self) {
+        if self
+            .header_backfill
+            .take()
+            .is_some_and(|b| !b.is_complete())
+        {
+            info!(target: "sync", "Header backfill aborted");
+        }
+    }
+
+    /// Resume downloading, unless it was resumed less than the configured interval ago.
+    /// In that case the run is deferred to the next `flush_continue_sync`, so that a burst
+    /// of sync events scans the peers once.
+    pub fn schedule_continue_sync(&mut self, io: &mut dyn SyncIo) {
+        let due = self
+            .last_continue_sync
+            .is_none_or(|last| last.elapsed() >= self.continue_sync_interval);
+        if due {
+            self.continue_sync(io);
+        } else {
+            self.continue_sync_pending = true;
+        }
+    }
+
+    /// Check if a `continue_sync` run is deferred.
+    pub fn continue_sync_pending(&self) -> bool {
+        self.continue_sync_pending
+    }
+
+    /// Run a deferred `continue_sync`, if any.
+    pub fn flush_continue_sync(&mut self, io: &mut dyn SyncIo) {
+        if self.continue_sync_pending {
+            self.continue_sync(io);
         }
     }
 
     /// Resume downloading
     pub fn continue_sync(&mut self, io: &mut dyn SyncIo) {
+        self.continue_sync_pending = false;
+        self.last_continue_sync = Some(Instant::now());
         if self.state == SyncState::Waiting {
             trace!(target: "sync", "Waiting for the block queue");
         } else if self.state == SyncState::SnapshotWaiting {
             trace!(target: "sync", "Waiting for the snapshot restoration");
         } else {
-            // Collect active peers that can sync
-            let mut peers: Vec<(PeerId, u8)> = self
-                .peers
-                .iter()
-                .filter_map(|(peer_id, peer)| {
-                    if peer.can_sync()
-                        && peer.asking == PeerAsking::Nothing
-                        && self.active_peers.contains(peer_id)
-                    {
-                        Some((*peer_id, peer.protocol_version))
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-
+            for peer_id in self.inactive_peers.take_due(Instant::now()) {
+                trace!(target: "sync", "Probing inactive peer {peer_id} again");
+                self.reactivate_peer(peer_id);
+            }
+            let peers = self.sync_candidates();
             if !peers.is_empty() {
                 trace!(
                     target: "sync",
@@ -1161,12 +2473,7 @@ impl ChainSync {
                     self.active_peers.len(), peers.len(), self.peers.len()
                 );
 
-                peers.shuffle(&mut random::new()); // TODO (#646): sort by rating
-                                                   // prefer peers with higher protocol version
-
-                peers.sort_by(|(_, v1), (_, v2)| v1.cmp(v2));
-
-                for (peer_id, _) in peers {
+                for peer_id in peers {
                     self.sync_peer(io, peer_id, false);
                 }
             }
@@ -1175,6 +2482,7 @@ impl ChainSync {
         if (self.state == SyncState::Blocks || self.state == SyncState::NewBlocks)
             && !self.peers.values().any(|p| {
                 p.asking != PeerAsking::Nothing
+                    && p.asking != PeerAsking::BackfillHeaders
                     && p.block_set != Some(BlockSet::OldBlocks)
                     && p.can_sync()
             })
@@ -1183,6 +2491,95 @@ impl ChainSync {
         }
     }
 
+    /// Active idle peers that can sync, in the order they are given something to do.
+    fn sync_candidates(&mut self) -> Vec<PeerId> {
+        self.peer_scans += 1;
+        let peers = &self.peers;
+        self.idle_peers.retain(|peer_id| {
+            peers
+                .get(peer_id)
+                .is_some_and(|peer| peer.can_sync() && peer.asking == PeerAsking::Nothing)
+        });
+        let off_fee_market = self.off_fee_market_peers();
+        let mut peers: Vec<(PeerId, u8, bool, bool)> = self
+            .idle_peers
+            .iter()
+            .filter(|peer_id| self.active_peers.contains(*peer_id))
+            .map(|peer_id| {
+                let peer = &self.peers[peer_id];
+                let verified = peer.difficulty_check == DifficultyCheck::Plausible;
+                let off = off_fee_market.contains(peer_id);
+                (*peer_id, peer.protocol_version, verified, off)
+            })
+            .collect();
+
+        peers.shuffle(&mut random::new()); // TODO (#646): sort by rating
+                                           // prefer peers with higher protocol version
+
+        peers.sort_by(|(_, v1, _, _), (_, v2, _, _)| v1.cmp(v2));
+        // peers following the fee market of our chain go first
+        peers.sort_by_key(|&(_, _, _, off)| off);
+        // peers with a plausible difficulty go first
+        peers.sort_by_key(|&(_, _, verified, _)| !verified);
+        peers
+            .into_iter()
+            .map(|(peer_id, _, _, _)| peer_id)
+            .collect()
+    }
+
+    /// Peers whose recent headers do not follow the gas limit or base fee of our chain, while
+    /// peers of similar difficulty with another head do. They are only deprioritized.
+    fn off_fee_market_peers(&self) -> HashSet<PeerId> {
+        let consistent: Vec<_> = self
+            .peers
+            .values()
+            .filter(|peer| peer.fee_market.is_some_and(|f| f.consistent == Some(true)))
+            .collect();
+        self.peers
+            .iter()
+            .filter(|(_, peer)| peer.fee_market.is_some_and(|f| f.consistent == Some(false)))
+            .filter(|(_, peer)| {
+                consistent.iter().any(|other| {
+                    other.latest_hash != peer.latest_hash
+                        && fee_market::is_similar_difficulty(other.difficulty, peer.difficulty)
+                })
+            })
+            .map(|(peer_id, _)| *peer_id)
+            .collect()
+    }
+
+    /// Record the gas limit and base fee of the highest of the validated `headers` from a peer.
+    fn note_fee_market(&mut self, io: &dyn SyncIo, peer_id: PeerId, headers: &Rlp) {
+        let eip1559_transition = self.eip1559_transition;
+        let highest = headers
+            .iter()
+            .filter_map(|h| BlockHeader::decode_rlp(&h, eip1559_transition).ok())
+            .max_by_key(|h| h.number());
+        let header = match highest {
+            Some(header) => header,
+            None => return,
+        };
+        let peer = match self.peers.get_mut(&peer_id) {
+            Some(peer) => peer,
+            None => return,
+        };
+        if peer.fee_market.is_some_and(|f| f.number > header.number()) {
+            return;
+        }
+        let chain = io.chain();
+        let reference = cmp::min(header.number(), chain.chain_info().best_block_number);
+        let reference = match chain
+            .block_header(BlockId::Number(reference))
+            .and_then(|h| h.decode(eip1559_transition).ok())
+        {
+            Some(reference) => reference,
+            None => return,
+        };
+        let fee_market = PeerFeeMarket::new(&header, &reference);
+        trace!(target: "sync", "{peer_id}: Head #{} gas limit {} base fee {:?}, consistent: {:?}", fee_market.number, fee_market.gas_limit, fee_market.base_fee, fee_market.consistent);
+        peer.fee_market = Some(fee_market);
+    }
+
     /// Called after all blocks have been downloaded
     fn complete_sync(&mut self, io: &mut dyn SyncIo) {
         trace!(target: "sync", "Sync complete");
@@ -1192,7 +2589,51 @@ impl ChainSync {
     /// Enter waiting state
     fn pause_sync(&mut self) {
         trace!(target: "sync", "Block queue full, pausing sync");
-        self.state = SyncState::Waiting;
+        self.set_state(SyncState::Waiting);
+    }
+
+    /// Peers competing for the block bodies and receipts requested from `peer_id`: the peer
+    /// itself and the other idle peers. `None` unless rendezvous peer selection is enabled.
+    fn block_request_route(&self, peer_id: PeerId) -> Option<Rendezvous> {
+        if self.peer_selection != PeerSelection::Rendezvous {
+            return None;
+        }
+        let node_id = self.peers.get(&peer_id)?.node_id?;
+        let candidates = self
+            .peers
+            .iter()
+            .filter(|(id, peer)| {
+                peer.can_sync()
+                    && peer.asking == PeerAsking::Nothing
+                    && self.active_peers.contains(*id)
+            })
+            .filter_map(|(_, peer)| peer.node_id)
+            .collect();
+        Some(Rendezvous::new(node_id, candidates))
+    }
+
+    /// Highest total difficulty a peer may plausibly advertise: ours plus blocks of the maximum
+    /// difficulty at one per second since our best block, with a generous margin.
+    fn plausible_total_difficulty(&self, chain_info: &BlockChainInfo) -> Option<U256> {
+        let max_block_difficulty = self.max_block_difficulty?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let blocks = now
+            .saturating_sub(chain_info.best_block_timestamp)
+            .saturating_add(PLAUSIBLE_DIFFICULTY_MARGIN_BLOCKS);
+        Some(
+            chain_info
+                .total_difficulty
+                .saturating_add(max_block_difficulty.saturating_mul(blocks.into())),
+        )
+    }
+
+    /// Check if a chain ending with a block of the given number can accumulate the difficulty.
+    fn is_difficulty_backed(&self, difficulty: U256, number: BlockNumber) -> bool {
+        self.max_block_difficulty.is_none_or(|max| {
+            difficulty <= max.saturating_mul(U256::from(number).saturating_add(U256::one()))
+        })
     }
 
     /// Find something to do for a peer. Called for a new peer or when a peer is done with its task.
@@ -1201,7 +2642,14 @@ impl ChainSync {
             trace!(target: "sync", "Skipping deactivated peer {peer_id}");
             return;
         }
-        let (peer_latest, peer_difficulty, peer_snapshot_number, peer_snapshot_hash) = {
+        let (
+            peer_latest,
+            peer_difficulty,
+            peer_difficulty_check,
+            peer_snapshot_number,
+            peer_snapshot_hash,
+            peer_serve_from_block,
+        ) = {
             if let Some(peer) = self.peers.get_mut(&peer_id) {
                 if peer.asking != PeerAsking::Nothing || !peer.can_sync() {
                     trace!(target: "sync", "Skipping busy peer {peer_id}");
@@ -1210,8 +2658,10 @@ impl ChainSync {
                 (
                     peer.latest_hash,
                     peer.difficulty,
+                    peer.difficulty_check,
                     peer.snapshot_number.as_ref().cloned().unwrap_or(0),
                     peer.snapshot_hash.as_ref().cloned(),
+                    peer.serve_from_block.unwrap_or(0),
                 )
             } else {
                 return;
@@ -1219,14 +2669,19 @@ impl ChainSync {
         };
         let chain_info = io.chain().chain_info();
         let syncing_difficulty = chain_info.pending_total_difficulty;
-        let num_active_peers = self
-            .peers
-            .values()
-            .filter(|p| p.asking != PeerAsking::Nothing)
-            .count();
 
-        let higher_difficulty = peer_difficulty.is_none_or(|pd| pd > syncing_difficulty);
-        if force || higher_difficulty || self.old_blocks.is_some() {
+        if let DifficultyCheck::Unverified(_) = peer_difficulty_check {
+            // ask for the best header before trusting the peer with the download
+            SyncRequester::request_difficulty_probe(self, io, peer_id, &peer_latest);
+            return;
+        }
+        let higher_difficulty = peer_difficulty_check == DifficultyCheck::Plausible
+            && peer_difficulty.is_none_or(|pd| pd > syncing_difficulty);
+        let backfilling = self
+            .header_backfill
+            .as_ref()
+            .is_some_and(|b| !b.is_complete());
+        if force || higher_difficulty || self.old_blocks.is_some() || backfilling {
             match self.state {
 				SyncState::WaitingPeers => {
 					trace!(
@@ -1246,13 +2701,19 @@ impl ChainSync {
 
 					let have_latest = io.chain().block_status(BlockId::Hash(peer_latest)) != BlockStatus::Unknown;
 					trace!(target: "sync", "Considering peer {}, force={}, td={:?}, our td={}, latest={}, have_latest={}, state={:?}", peer_id, force, peer_difficulty, syncing_difficulty, peer_latest, have_latest, self.state);
-					if !have_latest && (higher_difficulty || force || self.state == SyncState::NewBlocks) {
+					let route = self.block_request_route(peer_id);
+					if !have_latest && (higher_difficulty || force || self.state == SyncState::NewBlocks) && self.may_download_new_blocks(peer_id) {
 						// check if got new blocks to download
 						trace!(target: "sync", "Syncing with peer {}, force={}, td={:?}, our td={}, state={:?}", peer_id, force, peer_difficulty, syncing_difficulty, self.state);
-						if let Some(request) = self.new_blocks.request_blocks(peer_id, io, num_active_peers) {
+						let request = self.new_blocks.request_blocks(peer_id, io, route.as_ref());
+						self.note_deep_reorg();
+						if let Some(request) = request {
 							SyncRequester::request_blocks(self, io, peer_id, request, BlockSet::NewBlocks);
+							while let Some(request) = self.new_blocks.request_chain_heads(peer_id) {
+								SyncRequester::request_blocks(self, io, peer_id, request, BlockSet::NewBlocks);
+							}
 							if self.state == SyncState::Idle {
-								self.state = SyncState::Blocks;
+								self.set_state(SyncState::Blocks);
 							}
 							return;
 						}
@@ -1264,8 +2725,22 @@ impl ChainSync {
                     let ancient_block_fullness = io.chain().ancient_block_queue_fullness();
 					if force || equal_or_higher_difficulty {
 						if ancient_block_fullness < 0.8 {
-                            if let Some(request) = self.old_blocks.as_mut().and_then(|d| d.request_blocks(peer_id, io, num_active_peers)) {
+                            // never ask the peer for blocks below the lowest one it serves
+                            if let Some(request) = self.old_blocks.as_mut()
+                                .and_then(|d| d.request_blocks_from(peer_id, io, route.as_ref(), peer_serve_from_block)) {
                                 SyncRequester::request_blocks(self, io, peer_id, request, BlockSet::OldBlocks);
+                                while let Some(request) = self.old_blocks.as_mut().and_then(|d| d.request_chain_heads(peer_id)) {
+                                    SyncRequester::request_blocks(self, io, peer_id, request, BlockSet::OldBlocks);
+                                }
+                                return;
+                            }
+                        }
+
+                        // backfill headers from the checkpoint once the chain head is reached
+                        if self.state != SyncState::Blocks {
+                            if let Some((hash, count)) = self.header_backfill.as_mut()
+                                .and_then(|b| b.request_headers(peer_id, peer_serve_from_block)) {
+                                SyncRequester::request_backfill_headers(self, io, peer_id, &hash, count);
                                 return;
                             }
                         }
@@ -1274,7 +2749,7 @@ impl ChainSync {
 						let mut to_send = Default::default();
 						if let Some(peer) = self.peers.get_mut(&peer_id) {
 							if peer.asking_pooled_transactions.is_empty() {
-								to_send = peer.unfetched_pooled_transactions.drain().take(MAX_TRANSACTIONS_TO_REQUEST).collect::<Vec<_>>();
+								to_send = peer.unfetched_pooled_transactions.drain().take(self.max_transactions_to_request).collect::<Vec<_>>();
 								peer.asking_pooled_transactions = to_send.clone();
 							}
 						}
@@ -1287,7 +2762,7 @@ impl ChainSync {
 							target: "sync",
 							"peer {peer_id:?} is not suitable for requesting old blocks, syncing_difficulty={syncing_difficulty:?}, peer_difficulty={peer_difficulty:?}"
 						);
-						self.deactivate_peer(io, peer_id);
+						self.deactivate_peer(io, peer_id, DeactivationReason::LowDifficulty);
 					}
 				},
 				SyncState::SnapshotData => {
@@ -1295,9 +2770,9 @@ impl ChainSync {
 						RestorationStatus::Ongoing { state_chunks_done, block_chunks_done, .. } => {
 							// Initialize the snapshot if not already done
 							self.snapshot.initialize(io.snapshot_service());
-							if self.snapshot.done_chunks() - (state_chunks_done + block_chunks_done) as usize > MAX_SNAPSHOT_CHUNKS_DOWNLOAD_AHEAD {
+							if self.snapshot.done_chunks().saturating_sub((state_chunks_done + block_chunks_done) as usize) > MAX_SNAPSHOT_CHUNKS_DOWNLOAD_AHEAD {
 								trace!(target: "sync", "Snapshot queue full, pausing sync");
-								self.state = SyncState::SnapshotWaiting;
+								self.set_state(SyncState::SnapshotWaiting);
 								return;
 							}
 						},
@@ -1353,6 +2828,11 @@ impl ChainSync {
                         self.snapshot.clear_chunk_download(&hash);
                     }
                 }
+                PeerAsking::BackfillHeaders => {
+                    if let Some(ref mut backfill) = self.header_backfill {
+                        backfill.clear_download(peer_id);
+                    }
+                }
                 _ => (),
             }
         }
@@ -1362,21 +2842,35 @@ impl ChainSync {
     fn collect_blocks(&mut self, io: &mut dyn SyncIo, block_set: BlockSet) {
         match block_set {
             BlockSet::NewBlocks => {
-                if self
+                let download_action = self
                     .new_blocks
-                    .collect_blocks(io, self.state == SyncState::NewBlocks)
-                    == DownloadAction::Reset
-                {
+                    .collect_blocks(io, self.state == SyncState::NewBlocks);
+                for (hash, peer_id) in self.new_blocks.take_queued_suppliers() {
+                    self.block_suppliers.insert(hash, peer_id);
+                }
+                let rejected = self.new_blocks.take_rejected();
+                self.note_rejected_blocks(rejected);
+                if download_action == DownloadAction::Reset {
                     self.reset_downloads(block_set);
                     self.new_blocks.reset();
                 }
             }
             BlockSet::OldBlocks => {
                 let mut is_complete = false;
+                let mut is_repair = false;
+                let mut last_imported = 0;
                 let mut download_action = DownloadAction::None;
+                let mut queued_suppliers = Vec::new();
                 if let Some(downloader) = self.old_blocks.as_mut() {
                     download_action = downloader.collect_blocks(io, false);
                     is_complete = downloader.is_complete();
+                    is_repair = downloader.is_repair();
+                    last_imported = downloader.last_imported_block_number();
+                    queued_suppliers = downloader.take_queued_suppliers();
+                }
+
+                for (hash, peer_id) in queued_suppliers {
+                    self.block_suppliers.insert(hash, peer_id);
                 }
 
                 if download_action == DownloadAction::Reset {
@@ -1386,9 +2880,14 @@ impl ChainSync {
                     }
                 }
 
-                if is_complete {
+                if is_complete && is_repair {
+                    trace!(target: "sync", "Ancient blocks repair is complete");
+                    self.old_blocks = self.old_blocks_downloader(&io.chain().chain_info());
+                } else if is_complete {
                     trace!(target: "sync", "Background block download is complete");
                     self.old_blocks = None;
+                    self.events
+                        .emit(SyncEventKind::OldBlocksComplete { last_imported });
                 }
             }
         };
@@ -1409,8 +2908,10 @@ impl ChainSync {
     /// Reset peer status after request is complete.
     fn reset_peer_asking(&mut self, peer_id: PeerId, asking: PeerAsking) -> bool {
         if let Some(ref mut peer) = self.peers.get_mut(&peer_id) {
+            self.idle_peers.insert(peer_id);
             peer.expired = false;
             peer.block_set = None;
+            peer.asking_headers = 0;
             if peer.asking != asking {
                 trace!(target:"sync", "Asking {:?} while expected {:?}", peer.asking, asking);
                 peer.asking = PeerAsking::Nothing;
@@ -1425,6 +2926,18 @@ impl ChainSync {
 
     /// Send Status message
     fn send_status(&mut self, io: &mut dyn SyncIo, peer: PeerId) -> Result<(), network::Error> {
+        let (_, packet) = self.status_packet(io, peer);
+        io.respond(StatusPacket.id(), packet)
+    }
+
+    /// Send Status message again to a peer which did not answer the first one
+    fn resend_status(&mut self, io: &mut dyn SyncIo, peer: PeerId) -> Result<(), network::Error> {
+        let (protocol, packet) = self.status_packet(io, peer);
+        io.send_protocol(protocol, peer, StatusPacket.id(), packet)
+    }
+
+    /// Build Status message for the peer, returns the protocol it should be sent on
+    fn status_packet(&self, io: &dyn SyncIo, peer: PeerId) -> (ProtocolId, Bytes) {
         let eth_protocol_version = io.protocol_version(ETH_PROTOCOL, peer);
         let warp_protocol_version = io.protocol_version(PAR_PROTOCOL, peer);
         let warp_protocol = warp_protocol_version != 0;
@@ -1441,29 +2954,49 @@ impl ChainSync {
         packet.append(&(protocol as u32));
         packet.append(&self.network_id);
         packet.append(&primitive_types07::U256(chain.total_difficulty.0));
-        packet.append(&primitive_types07::H256(chain.best_block_hash.0));
-        packet.append(&primitive_types07::H256(chain.genesis_hash.0));
+        // hashes are encoded as their bytes, without copies into `primitive_types07` ones
+        packet.append(&chain.best_block_hash.as_bytes());
+        packet.append(&chain.genesis_hash.as_bytes());
         if eth_protocol_version >= ETH_PROTOCOL_VERSION_64.0 {
-            packet.append(&self.fork_filter.current(io.chain()));
+            self.fork_filter
+                .current(io.chain())
+                .append_to_rlp(&mut packet);
         }
         if warp_protocol {
-            let manifest = io.snapshot_service().manifest();
+            let manifest = self.advertised_manifest(io);
             let block_number = manifest.as_ref().map_or(0, |m| m.block_number);
             let manifest_hash = manifest.map_or(H256::default(), |m| keccak(m.into_rlp()));
-            packet.append(&primitive_types07::H256(manifest_hash.0));
+            packet.append(&manifest_hash.as_bytes());
             packet.append(&block_number);
+            // optional trailing field, ignored by peers that don't know it
+            if let Some(serve_from_block) = self.serve_from_block {
+                packet.append(&serve_from_block);
+            }
         }
         packet.finalize_unbounded_list();
-        io.respond(StatusPacket.id(), packet.out())
+        let protocol = if warp_protocol {
+            PAR_PROTOCOL
+        } else {
+            ETH_PROTOCOL
+        };
+        (protocol, packet.out())
     }
 
     pub fn maintain_peers(&mut self, io: &mut dyn SyncIo) {
         let tick = Instant::now();
+        self.new_blocks.expire_chain_head_requests(tick);
+        if let Some(ref mut old_blocks) = self.old_blocks {
+            old_blocks.expire_chain_head_requests(tick);
+        }
+        self.snapshot_data_parts.expire(tick);
         let mut aborting = Vec::new();
         for (peer_id, peer) in &self.peers {
             let elapsed = tick - peer.ask_time;
             let timeout = match peer.asking {
-                PeerAsking::BlockHeaders => elapsed > HEADERS_TIMEOUT,
+                PeerAsking::BlockHeaders
+                | PeerAsking::BackfillHeaders
+                | PeerAsking::DifficultyProbe
+                | PeerAsking::BranchProbe => elapsed > HEADERS_TIMEOUT,
                 PeerAsking::BlockBodies => elapsed > BODIES_TIMEOUT,
                 PeerAsking::BlockReceipts => elapsed > RECEIPTS_TIMEOUT,
                 PeerAsking::PooledTransactions => elapsed > POOLED_TRANSACTIONS_TIMEOUT,
@@ -1482,12 +3015,25 @@ impl ChainSync {
             SyncHandler::on_peer_aborting(self, io, p);
         }
 
-        // Check for handshake timeouts
-        for (peer, &ask_time) in &self.handshaking_peers {
-            let elapsed = (tick - ask_time) / 1_000_000_000;
-            if elapsed > STATUS_TIMEOUT {
-                trace!(target:"sync", "Status timeout {peer}");
-                io.disconnect_peer(*peer);
+        // Check for handshake timeouts. Some clients miss our first Status during session setup,
+        // so it is sent once more before the peer is dropped.
+        let status_timeouts: Vec<PeerId> = self
+            .handshaking_peers
+            .iter()
+            .filter(|(_, handshake)| handshake.is_expired(tick))
+            .map(|(peer, _)| *peer)
+            .collect();
+        for peer in status_timeouts {
+            let retried = self.handshaking_peers.get(&peer).is_none_or(|h| h.retried);
+            if !retried && self.resend_status(io, peer).is_ok() {
+                trace!(target:"sync", "Status timeout {peer}, sending status again");
+                self.handshaking_peers
+                    .insert(peer, HandshakingPeer::retry(tick));
+            } else {
+                debug!(target:"sync", "Status timeout {peer}");
+                self.handshaking_peers.remove(&peer);
+                self.status_timeout_drops += 1;
+                io.disconnect_peer(peer);
             }
         }
     }
@@ -1495,12 +3041,12 @@ impl ChainSync {
     fn check_resume(&mut self, io: &mut dyn SyncIo) {
         match self.state {
             SyncState::Waiting if !io.chain().queue_info().is_full() => {
-                self.state = SyncState::Blocks;
+                self.set_state(SyncState::Blocks);
                 self.continue_sync(io);
             }
             SyncState::SnapshotData => match io.snapshot_service().restoration_status() {
                 RestorationStatus::Inactive | RestorationStatus::Failed => {
-                    self.state = SyncState::SnapshotWaiting;
+                    self.set_state(SyncState::SnapshotWaiting);
                 }
                 RestorationStatus::Initializing { .. } | RestorationStatus::Ongoing { .. } => (),
             },
@@ -1518,20 +3064,22 @@ impl ChainSync {
                     ..
                 } => {
                     if !self.snapshot.is_complete()
-                        && self.snapshot.done_chunks()
-                            - (state_chunks_done + block_chunks_done) as usize
+                        && self
+                            .snapshot
+                            .done_chunks()
+                            .saturating_sub((state_chunks_done + block_chunks_done) as usize)
                             <= MAX_SNAPSHOT_CHUNKS_DOWNLOAD_AHEAD
                     {
                         trace!(target:"sync", "Resuming snapshot sync");
-                        self.state = SyncState::SnapshotData;
-                        self.continue_sync(io);
+                        self.set_state(SyncState::SnapshotData);
+                        self.schedule_continue_sync(io);
                     }
                 }
                 RestorationStatus::Failed => {
                     trace!(target: "sync", "Snapshot restoration aborted");
-                    self.state = SyncState::WaitingPeers;
+                    self.set_state(SyncState::WaitingPeers);
                     self.snapshot.clear();
-                    self.continue_sync(io);
+                    self.schedule_continue_sync(io);
                 }
             },
             _ => (),
@@ -1568,7 +3116,7 @@ impl ChainSync {
         self.peers
             .iter()
             .filter_map(|(id, p)| {
-                if p.protocol_version >= PAR_PROTOCOL_VERSION_2.0 {
+                if p.protocol_version >= PAR_PROTOCOL_VERSION_2.0 && self.is_consensus_peer(p) {
                     Some(*id)
                 } else {
                     None
@@ -1577,93 +3125,572 @@ impl ChainSync {
             .collect()
     }
 
+    /// Returns true if proposed blocks and consensus packets may be sent to the peer.
+    fn is_consensus_peer(&self, peer: &PeerInfo) -> bool {
+        match self.consensus_propagation {
+            ConsensusPropagation::All => true,
+            ConsensusPropagation::Validators => peer.node_id.is_some_and(|node_id| {
+                self.validator_nodes.contains(&node_id)
+                    || self
+                        .validators
+                        .iter()
+                        .any(|validator| self.validator_node_map.get(validator) == Some(&node_id))
+            }),
+        }
+    }
+
+    /// Snapshot of the new and, if present, old blocks downloader state, with the peers the
+    /// subchain heads are requested from.
+    pub fn downloader_diagnostics(&self) -> Vec<DownloaderDiagnostics> {
+        let mut diagnostics = vec![self.new_blocks.diagnostics()];
+        diagnostics.extend(self.old_blocks.as_ref().map(|d| d.diagnostics()));
+        for d in &mut diagnostics {
+            let block_set = d.block_set;
+            for head in &mut d.heads {
+                let hash = head.hash;
+                let (asking, peer) = match head.status {
+                    HeadStatus::AssignedToPeer(ref mut peer) => (PeerAsking::BlockHeaders, peer),
+                    HeadStatus::DownloadingBodies(ref mut peer) => (PeerAsking::BlockBodies, peer),
+                    HeadStatus::Unassigned => continue,
+                };
+                *peer = self
+                    .peers
+                    .iter()
+                    .find(|(_, p)| {
+                        p.block_set == Some(block_set)
+                            && p.asking == asking
+                            && (p.asking_hash == Some(hash) || p.asking_blocks.contains(&hash))
+                    })
+                    .map(|(id, _)| *id);
+            }
+        }
+        diagnostics
+    }
+
     /// Maintain other peers. Send out any new blocks and transactions
     pub fn maintain_sync(&mut self, io: &mut dyn SyncIo) {
+        SyncHandler::import_hashed_chunks(self, io);
         self.maybe_start_snapshot_sync(io);
         self.check_resume(io);
+        self.check_stall(io);
+        self.check_retention_boundary(io);
+        self.check_branch_probe(io);
+        let sample = self.health_sample(io.chain(), io.snapshot_service());
+        self.health.observe(Instant::now(), &sample);
     }
 
-    // t_nb 11.4 called when block is imported to chain - propagates the blocks and updates transactions sent to peers
-    pub fn chain_new_blocks(
-        &mut self,
-        io: &mut dyn SyncIo,
-        _imported: &[H256],
-        invalid: &[H256],
-        enacted: &[H256],
-        _retracted: &[H256],
-        sealed: &[H256],
-        proposed: &[Bytes],
-    ) {
-        let queue_info = io.chain().queue_info();
-        let is_syncing = self.status().is_syncing(queue_info);
+    /// Check the sync state for the usual reasons of a node not syncing: missing or
+    /// incompatible peers, a full block queue and downloads which stopped making progress.
+    pub fn health_check(
+        &self,
+        chain: &dyn BlockChainClient,
+        snapshot_service: &dyn SnapshotService,
+    ) -> SyncHealthReport {
+        let sample = self.health_sample(chain, snapshot_service);
+        self.health.report(Instant::now(), &sample)
+    }
 
-        if !is_syncing || !sealed.is_empty() || !proposed.is_empty() {
-            trace!(target: "sync", "Propagating blocks, state={:?}", self.state);
-            // t_nb 11.4.1 propagate latest blocks
-            SyncPropagator::propagate_latest_blocks(self, io, sealed);
-            // t_nb 11.4.4 propagate proposed blocks
-            SyncPropagator::propagate_proposed_blocks(self, io, proposed);
-        }
-        if !invalid.is_empty() {
-            info!(target: "sync", "Bad blocks in the queue, restarting sync");
-            self.restart(io);
+    fn health_sample(
+        &self,
+        chain: &dyn BlockChainClient,
+        snapshot_service: &dyn SnapshotService,
+    ) -> HealthSample {
+        let queue_info = chain.queue_info();
+        let snapshot_chunks = match snapshot_service.restoration_status() {
+            RestorationStatus::Ongoing {
+                state_chunks,
+                block_chunks,
+                state_chunks_done,
+                block_chunks_done,
+                ..
+            } => Some((
+                (state_chunks_done + block_chunks_done) as usize,
+                (state_chunks + block_chunks) as usize,
+            )),
+            _ => None,
+        };
+        HealthSample {
+            network_id: self.network_id,
+            peers: self.peers.len(),
+            confirmed_peers: self.peers.values().filter(|p| p.can_sync()).count(),
+            queue_size: queue_info.total_queue_size(),
+            max_queue_size: queue_info.max_queue_size,
+            queue_full: queue_info.is_full(),
+            best_block: chain.chain_info().best_block_number,
+            highest_block: self.highest_block,
+            snapshot_chunks,
+            ancient_block: self
+                .old_blocks
+                .as_ref()
+                .map(|d| d.last_imported_block_number()),
         }
+    }
 
-        if !is_syncing && !enacted.is_empty() && !self.peers.is_empty() {
-            // t_nb 11.4.5 Select random peer to re-broadcast transactions to.
-            let peer = random::new().gen_range(0, self.peers.len());
-            trace!(target: "sync", "Re-broadcasting transactions to a random peer.");
-            if let Some(peer_info) = self.peers.values_mut().nth(peer) {
-                peer_info.last_sent_transactions.clear();
+    /// Escalate through the recovery actions while the best block does not advance
+    /// although peers are ahead.
+    fn check_stall(&mut self, io: &mut dyn SyncIo) {
+        let best_block = io.chain().chain_info().best_block_number;
+        let queue_info = io.chain().queue_info();
+        let restoring = match io.snapshot_service().restoration_status() {
+            RestorationStatus::Initializing { .. } | RestorationStatus::Ongoing { .. } => true,
+            RestorationStatus::Inactive | RestorationStatus::Failed => false,
+        };
+        let snapshot_syncing = match self.state {
+            SyncState::SnapshotManifest | SyncState::SnapshotData | SyncState::SnapshotWaiting => {
+                true
             }
+            _ => false,
+        };
+        let busy = restoring || snapshot_syncing || queue_info.is_full();
+        let now = Instant::now();
+        let action = match self
+            .stall_detector
+            .check(now, best_block, self.highest_block, busy)
+        {
+            Some(action) => action,
+            None => return,
+        };
+        self.stall_recoveries.note(action);
+        warn!(
+            target: "sync",
+            "Best block #{} is not advancing while peers are at #{}: state={:?}, peers={}, active peers={}, queue={}, recovery={:?}",
+            best_block,
+            self.highest_block.unwrap_or(0),
+            self.state,
+            self.peers.len(),
+            self.active_peers.len(),
+            queue_info.total_queue_size(),
+            action,
+        );
+        match action {
+            StallAction::Warn => {}
+            StallAction::Restart => self.restart(io),
+            StallAction::ReconfirmPeers => self.reconfirm_peers(io),
         }
     }
 
-    pub fn on_packet(&mut self, io: &mut dyn SyncIo, peer: PeerId, packet_id: u8, data: &[u8]) {
-        SyncHandler::on_packet(self, io, peer, packet_id, data);
+    /// Stop downloading ancient blocks once the retention boundary of the client moved above
+    /// the next block to download; the remaining blocks would be pruned right after import.
+    fn check_retention_boundary(&mut self, io: &mut dyn SyncIo) {
+        let chain = io.chain().chain_info();
+        let boundary = match chain.retention_boundary {
+            Some(boundary) => boundary,
+            None => return,
+        };
+        let (last_imported, is_repair) = match self.old_blocks {
+            Some(ref downloader) => (
+                downloader.last_imported_block_number(),
+                downloader.is_repair(),
+            ),
+            None => return,
+        };
+        if boundary <= last_imported + 1 {
+            return;
+        }
+        self.reset_downloads(BlockSet::OldBlocks);
+        if is_repair {
+            info!(target: "sync", "Ancient blocks repair stopped at #{last_imported}: blocks below the retention boundary #{boundary} would be pruned");
+            self.old_blocks = self.old_blocks_downloader(&chain);
+        } else {
+            info!(target: "sync", "Old blocks download stopped at #{last_imported}: blocks below the retention boundary #{boundary} would be pruned");
+            self.old_blocks = None;
+            self.events
+                .emit(SyncEventKind::OldBlocksComplete { last_imported });
+        }
     }
 
-    /// Called by peer when it is disconnecting
-    pub fn on_peer_aborting(&mut self, io: &mut dyn SyncIo, peer: PeerId) {
-        SyncHandler::on_peer_aborting(self, io, peer);
+    /// Rejected block most peers are stuck at, if any.
+    fn branch_probe_target(&self, now: Instant) -> Option<H256> {
+        self.branch_prober.stuck_at(now, self.peers.len())
     }
 
-    /// Called when a new peer is connected
-    pub fn on_peer_connected(&mut self, io: &mut dyn SyncIo, peer: PeerId) {
-        SyncHandler::on_peer_connected(self, io, peer);
+    /// Maximum reorg depth in effect, if not overridden.
+    fn reorg_depth_limit(&self) -> Option<BlockNumber> {
+        self.max_reorg_depth.filter(|_| !self.allow_deep_reorg)
     }
 
-    /// propagates new transactions to all peers
-    pub fn propagate_new_transactions(&mut self, io: &mut dyn SyncIo) {
-        let deadline = Instant::now() + Duration::from_millis(500);
-        SyncPropagator::propagate_ready_transactions(self, io, || {
-            if deadline > Instant::now() {
-                true
-            } else {
-                debug!(target: "sync", "Wasn't able to finish transaction propagation within a deadline.");
-                false
+    /// Flag the peers of a branch the new blocks downloader gave up on for diverging deeper
+    /// than the maximum reorg depth.
+    fn note_deep_reorg(&mut self) {
+        if let Some(reorg) = self.new_blocks.take_deep_reorg() {
+            self.deep_reorg_peers.extend(reorg.peers.iter().cloned());
+            self.deep_reorg = Some(reorg);
+        }
+    }
+
+    /// Account the new blocks which failed to import. Peers whose best block was downloaded
+    /// along with a rejected block are on its branch.
+    fn note_rejected_blocks(&mut self, rejected: Vec<RejectedBlock>) {
+        if rejected.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        for block in rejected {
+            let root = self.branch_prober.note_rejected(
+                now,
+                block.hash,
+                block.number,
+                &block.parent,
+                Some(block.peer),
+            );
+            for hash in block.descendants {
+                self.branch_prober.note_descendant(hash, root);
             }
-        });
+        }
+        let on_rejected_branch: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| self.branch_prober.root_of(&peer.latest_hash).is_some())
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+        for peer_id in on_rejected_branch {
+            self.branch_prober.note_peer(peer_id, PeerBranch::Rejected);
+        }
     }
 
-    /// Broadcast consensus message to peers.
-    pub fn propagate_consensus_packet(&mut self, io: &mut dyn SyncIo, packet: Bytes) {
-        SyncPropagator::propagate_consensus_packet(self, io, packet);
+    /// Account headers from a peer which contain a rejected block or descend from one, as
+    /// another rejection of that block. Returns the rejected block, if any.
+    fn note_rejected_headers(
+        &mut self,
+        peer_id: PeerId,
+        headers: &Rlp,
+    ) -> Result<Option<H256>, DecoderError> {
+        if self.branch_prober.is_empty() {
+            return Ok(None);
+        }
+        let mut rejected = None;
+        for item in headers.iter() {
+            let header = BlockHeader::decode_rlp(&item, self.eip1559_transition)?;
+            let hash = keccak(item.as_raw());
+            let root = self
+                .branch_prober
+                .root_of(&hash)
+                .or_else(|| self.branch_prober.root_of(header.parent_hash()));
+            if let Some(root) = root {
+                self.branch_prober.note_descendant(hash, root);
+                if rejected.is_none() {
+                    rejected = Some(self.branch_prober.note_rejected(
+                        Instant::now(),
+                        hash,
+                        header.number(),
+                        header.parent_hash(),
+                        Some(peer_id),
+                    ));
+                }
+            }
+        }
+        Ok(rejected)
     }
-}
 
-#[cfg(test)]
-pub mod tests {
-    use super::{PeerAsking, PeerInfo, *};
-    use bytes::Bytes;
-    use ethcore::{
-        client::{BlockChainClient, BlockInfo, ChainInfo, EachBlockWith, TestBlockChainClient},
-        miner::{MinerService, PendingOrdering},
-    };
-    use ethereum_types::{Address, H256, U256};
-    use network::PeerId;
-    use parking_lot::RwLock;
-    use rlp::{Rlp, RlpStream};
+    /// Check if new blocks may be downloaded from a peer. Peers advertising a branch deeper
+    /// than the maximum reorg depth don't qualify. While most peers are stuck at a rejected
+    /// block, only the peers found on an alternative branch qualify.
+    fn may_download_new_blocks(&self, peer_id: PeerId) -> bool {
+        if self.reorg_depth_limit().is_some() && self.deep_reorg_peers.contains(&peer_id) {
+            return false;
+        }
+        self.branch_probe_target(Instant::now()).is_none()
+            || self.branch_prober.peer_branch(peer_id) == Some(PeerBranch::Alternative)
+    }
+
+    /// Ask a peer of higher total difficulty for its headers at the height of the rejected
+    /// block most peers are stuck at, one peer per probe interval.
+    fn check_branch_probe(&mut self, io: &mut dyn SyncIo) {
+        let now = Instant::now();
+        self.branch_prober.expire(now);
+        // a sync reset drops the outstanding request along with its answer
+        if let Some(peer_id) = self.branch_prober.probing() {
+            if self
+                .peers
+                .get(&peer_id)
+                .is_none_or(|p| p.asking != PeerAsking::BranchProbe)
+            {
+                self.branch_prober.note_probe_result(peer_id, None);
+            }
+        }
+        let stuck_at = match self.branch_probe_target(now) {
+            Some(hash) => hash,
+            None => return,
+        };
+        let number = match self.branch_prober.rejected_number(&stuck_at) {
+            Some(number) => number,
+            None => return,
+        };
+        let total_difficulty = io.chain().chain_info().pending_total_difficulty;
+        let candidates: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| {
+                peer.can_sync()
+                    && peer.asking == PeerAsking::Nothing
+                    && peer.difficulty.is_none_or(|d| d > total_difficulty)
+            })
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+        if let Some(peer_id) = self.branch_prober.next_probe(now, candidates) {
+            debug!(target: "sync", "{peer_id}: Probing for a branch without the rejected block #{number} ({stuck_at})");
+            SyncRequester::request_branch_probe(self, io, peer_id, number);
+        }
+    }
+
+    /// Fork block a peer has to confirm by its header before it is used for sync.
+    /// The configured one applies to all peers, the derived one only to peers
+    /// which do not announce a fork id.
+    fn fork_checkpoint(&self, eth_protocol_version: u8) -> Option<(BlockNumber, H256)> {
+        match self.fork_block {
+            Some(fork_block) => Some(fork_block),
+            None if eth_protocol_version < ETH_PROTOCOL_VERSION_64.0 => self.derived_fork_block,
+            None => None,
+        }
+    }
+
+    /// Derive the fork block to check from the most recent fork our chain has passed,
+    /// unless one is configured.
+    fn update_derived_fork_block(&mut self, chain: &dyn BlockChainClient) {
+        if self.fork_block.is_some() {
+            return;
+        }
+        let best_block = chain.chain_info().best_block_number;
+        let number = match self.fork_filter.last_fork_at(best_block) {
+            Some(number) => number,
+            None => return,
+        };
+        if self.derived_fork_block.map(|(n, _)| n) == Some(number) {
+            return;
+        }
+        if let Some(hash) = chain.block_hash(BlockId::Number(number)) {
+            debug!(target: "sync", "Confirming peers without fork id at fork block #{number} ({hash})");
+            self.derived_fork_block = Some((number, hash));
+        }
+    }
+
+    /// Forget the download progress and the highest block announced by peers, confirm the
+    /// fork block of all peers again and wait for confirmed peers.
+    fn reconfirm_peers(&mut self, io: &mut dyn SyncIo) {
+        self.update_targets(io.chain());
+        self.snapshot.clear();
+        self.reset(io, Some(SyncState::WaitingPeers));
+        // learned again from the blocks and hashes the peers announce
+        self.highest_block = None;
+        let peers: Vec<(PeerId, (BlockNumber, H256))> = self
+            .peers
+            .iter()
+            .filter(|(_, p)| p.asking == PeerAsking::Nothing)
+            .filter_map(|(id, p)| {
+                self.fork_checkpoint(p.eth_protocol_version)
+                    .map(|checkpoint| (*id, checkpoint))
+            })
+            .collect();
+        for (peer_id, checkpoint) in peers {
+            if let Some(peer) = self.peers.get_mut(&peer_id) {
+                peer.confirmation = ForkConfirmation::Unconfirmed;
+            }
+            SyncRequester::request_fork_header(self, io, peer_id, checkpoint);
+        }
+        self.schedule_continue_sync(io);
+    }
+
+    // t_nb 11.4 called when block is imported to chain - propagates the blocks and updates transactions sent to peers
+    pub fn chain_new_blocks(
+        &mut self,
+        io: &mut dyn SyncIo,
+        imported: &[H256],
+        invalid: &[H256],
+        enacted: &[H256],
+        retracted: &[H256],
+        sealed: &[H256],
+        proposed: &[Bytes],
+    ) {
+        let queue_info = io.chain().queue_info();
+        let is_syncing = self.status().is_syncing(queue_info);
+
+        if !is_syncing || !sealed.is_empty() || !proposed.is_empty() {
+            trace!(target: "sync", "Propagating blocks, state={:?}", self.state);
+            // t_nb 11.4.4 propagate proposed blocks, ahead of the latest blocks and hashes
+            // queued to possibly many more peers
+            SyncPropagator::propagate_proposed_blocks(self, io, proposed);
+            // t_nb 11.4.1 propagate latest blocks
+            SyncPropagator::propagate_latest_blocks(self, io, sealed);
+        }
+        if !self.local_transactions.is_empty() {
+            for hash in enacted {
+                if let Some(body) = io.chain().block_body(BlockId::Hash(*hash)) {
+                    for tx_hash in body.transaction_hashes() {
+                        self.local_transactions.remove(&tx_hash);
+                    }
+                }
+            }
+        }
+        if let Some(retention) = self.new_transactions_stats_retention {
+            self.transactions_stats
+                .retain_new_since(SystemTime::now(), retention);
+        }
+        for hash in imported {
+            self.block_suppliers.remove(hash);
+            if let Some(received) = self.fast_path_blocks.remove(hash) {
+                self.new_block_import_latency = Some(received.elapsed());
+            }
+        }
+        // the enacted blocks are in ascending order, the interval over them is taken from the
+        // first and the last header only
+        let enacted_ends = enacted
+            .first()
+            .into_iter()
+            .chain(enacted.get(1..).and_then(<[H256]>::last));
+        for hash in enacted_ends {
+            if let Some(header) = io.chain().block_header(BlockId::Hash(*hash)) {
+                self.block_interval
+                    .note(header.number(), header.timestamp());
+            }
+        }
+        if !enacted.is_empty() {
+            self.update_derived_fork_block(io.chain());
+        }
+        for hash in sealed {
+            if self.sealed_blocks.len() == MAX_SEALED_BLOCKS {
+                self.sealed_blocks.pop_front();
+            }
+            self.sealed_blocks.push_back(*hash);
+        }
+        let mut restart = false;
+        for hash in invalid {
+            if self.sealed_blocks.contains(hash) {
+                // nobody else is to blame, the miner has to learn about it
+                self.on_sealed_block_rejected(io, hash);
+                continue;
+            }
+            self.fast_path_blocks.remove(hash);
+            let supplier = self.block_suppliers.remove(hash);
+            if let Some(peer_id) = supplier {
+                debug!(target: "sync", "{peer_id}: Supplied invalid block {hash:?}");
+                self.ban_peer(io, peer_id, "invalid block");
+            }
+            // the parent of the first block of a branch the queue rejects is in the chain,
+            // its descendants are rejected again when downloaded
+            let parent = self.new_blocks.imported_parent(hash).and_then(|parent| {
+                io.chain()
+                    .block_header(BlockId::Hash(parent))
+                    .map(|header| (parent, header.number()))
+            });
+            if let Some((parent, parent_number)) = parent {
+                self.branch_prober.note_rejected(
+                    Instant::now(),
+                    *hash,
+                    parent_number + 1,
+                    &parent,
+                    supplier,
+                );
+            }
+            // only a bad block on the chain being synced leaves the download stuck
+            restart |= self.new_blocks.is_on_import_path(hash);
+        }
+        if restart {
+            info!(target: "sync", "Bad blocks on the synced chain, restarting sync");
+            self.restart(io);
+        }
+        // a new best block does not wait for the deferred run
+        if !enacted.is_empty() {
+            self.flush_continue_sync(io);
+        }
+
+        if !is_syncing && !retracted.is_empty() {
+            self.note_retracted_transactions(io.chain(), enacted, retracted);
+        }
+        if !is_syncing && !enacted.is_empty() && !self.peers.is_empty() {
+            // t_nb 11.4.5 Select random peer to re-broadcast transactions to.
+            let peer = random::new().gen_range(0, self.peers.len());
+            trace!(target: "sync", "Re-broadcasting transactions to a random peer.");
+            if let Some(peer_info) = self.peers.values_mut().nth(peer) {
+                peer_info.last_sent_transactions.clear();
+            }
+        }
+    }
+
+    /// Queues the transactions of retracted blocks, which are not included in the enacted ones,
+    /// to be announced to all peers again. Peers are assumed to have forgotten them.
+    fn note_retracted_transactions(
+        &mut self,
+        chain: &dyn BlockChainClient,
+        enacted: &[H256],
+        retracted: &[H256],
+    ) {
+        let block_transactions = |blocks: &[H256]| {
+            blocks
+                .iter()
+                .filter_map(|hash| chain.block_body(BlockId::Hash(*hash)))
+                .flat_map(|body| body.transaction_hashes())
+                .collect::<H256FastSet>()
+        };
+        let enacted = block_transactions(enacted);
+        let retracted: H256FastSet = block_transactions(retracted)
+            .into_iter()
+            .filter(|hash| !enacted.contains(hash))
+            .collect();
+        if retracted.is_empty() {
+            return;
+        }
+
+        trace!(target: "sync", "Re-broadcasting {} transactions of retracted blocks.", retracted.len());
+        for peer_info in self.peers.values_mut() {
+            peer_info
+                .last_sent_transactions
+                .retain(|hash| !retracted.contains(hash));
+        }
+        self.retracted_transactions.extend(retracted);
+    }
+
+    pub fn on_packet(&mut self, io: &mut dyn SyncIo, peer: PeerId, packet_id: u8, data: &[u8]) {
+        SyncHandler::on_packet(self, io, peer, packet_id, data);
+    }
+
+    /// Called by peer when it is disconnecting
+    pub fn on_peer_aborting(&mut self, io: &mut dyn SyncIo, peer: PeerId) {
+        SyncHandler::on_peer_aborting(self, io, peer);
+    }
+
+    /// Called when a new peer is connected
+    pub fn on_peer_connected(&mut self, io: &mut dyn SyncIo, peer: PeerId) {
+        SyncHandler::on_peer_connected(self, io, peer);
+    }
+
+    /// propagates new transactions to all peers
+    pub fn propagate_new_transactions(&mut self, io: &mut dyn SyncIo) {
+        let deadline = Instant::now() + Duration::from_millis(500);
+        let mut should_continue = || {
+            if deadline > Instant::now() {
+                true
+            } else {
+                debug!(target: "sync", "Wasn't able to finish transaction propagation within a deadline.");
+                false
+            }
+        };
+        SyncPropagator::propagate_ready_transactions(self, io, &mut should_continue);
+        // ready transactions reach a subset of peers only, retracted ones are sent to all
+        if !self.retracted_transactions.is_empty() {
+            let retracted = self.retracted_transactions.drain().collect();
+            SyncPropagator::propagate_new_transactions(self, io, retracted, should_continue);
+        }
+    }
+
+    /// Broadcast consensus message to peers.
+    pub fn propagate_consensus_packet(&mut self, io: &mut dyn SyncIo, packet: Bytes) {
+        SyncPropagator::propagate_consensus_packet(self, io, packet);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{PeerAsking, PeerInfo, *};
+    use bytes::Bytes;
+    use ethcore::{
+        client::{BlockChainClient, BlockInfo, ChainInfo, EachBlockWith, TestBlockChainClient},
+        miner::{MinerService, PendingOrdering},
+        snapshot::SnapshotService,
+    };
+    use ethereum_types::{Address, H256, U256};
+    use network::PeerId;
+    use parking_lot::RwLock;
+    use rlp::{Rlp, RlpStream};
     use std::collections::VecDeque;
     use tests::{helpers::TestIo, snapshot::TestSnapshotService};
     use types::header::Header;
@@ -1727,14 +3754,42 @@ pub mod tests {
             start_block_number: 0,
             last_imported_block_number: None,
             highest_block_number: None,
+            highest_block_hinted: false,
             blocks_total: 0,
             blocks_received: 0,
             num_peers: 0,
             num_active_peers: 0,
+            num_tx_relay_peers: 0,
+            num_sync_only_peers: 0,
+            clients: ClientBreakdown::default(),
             item_sizes: BTreeMap::new(),
             num_snapshot_chunks: 0,
             snapshot_chunks_done: 0,
             last_imported_old_block_number: None,
+            num_policy_rejected_transactions: 0,
+            num_peer_fault_transactions: 0,
+            num_skipped_new_blocks: 0,
+            num_bad_snapshot_chunks: 0,
+            num_fast_path_new_blocks: 0,
+            new_block_import_latency: None,
+            num_status_timeout_drops: 0,
+            num_rejected_sealed_blocks: 0,
+            num_inactive_peers: 0,
+            num_disabled_peers: 0,
+            num_compressed_packets_sent: 0,
+            compressed_bytes_saved: 0,
+            num_dropped_sync_events: 0,
+            num_delayed_requests: 0,
+            num_evicted_delayed_requests: 0,
+            num_expired_delayed_requests: 0,
+            headers_backfilled: 0,
+            headers_backfill_target: 0,
+            send_errors: SendErrorStats::default(),
+            packet_stats: Vec::new(),
+            stall_recoveries: StallRecoveryStats::default(),
+            branch_probe: BranchProbeStatus::default(),
+            deep_reorg: None,
+            observer_mode: false,
         }
     }
 
@@ -1755,6 +3810,18 @@ pub mod tests {
         dummy_sync_with_tx_hashes_rx(client, transaction_hashes_rx)
     }
 
+    fn dummy_sync_api(client: &dyn BlockChainClient) -> ChainSyncApi {
+        let (_, priority_tasks_rx) = mpsc::channel();
+        let (_, transaction_hashes_rx) = crossbeam_channel::unbounded();
+        ChainSyncApi::new(
+            SyncConfig::default(),
+            client,
+            ForkFilterApi::new_dummy(client),
+            priority_tasks_rx,
+            transaction_hashes_rx,
+        )
+    }
+
     pub fn dummy_sync_with_tx_hashes_rx(
         client: &dyn BlockChainClient,
         transaction_hashes_rx: crossbeam_channel::Receiver<H256>,
@@ -1786,10 +3853,13 @@ pub mod tests {
     }
 
     pub fn insert_dummy_peer(sync: &mut ChainSync, peer_id: PeerId, peer_latest_hash: H256) {
+        sync.idle_peers.insert(peer_id);
         sync.peers.insert(
             peer_id,
             PeerInfo {
                 protocol_version: 0,
+                eth_protocol_version: 0,
+                par_protocol_version: 0,
                 genesis: H256::zero(),
                 network_id: 0,
                 latest_hash: peer_latest_hash,
@@ -1797,6 +3867,7 @@ pub mod tests {
                 asking: PeerAsking::Nothing,
                 asking_blocks: Vec::new(),
                 asking_hash: None,
+                asking_headers: 0,
                 unfetched_pooled_transactions: Default::default(),
                 asking_pooled_transactions: Default::default(),
                 ask_time: Instant::now(),
@@ -1805,13 +3876,65 @@ pub mod tests {
                 confirmation: super::ForkConfirmation::Confirmed,
                 snapshot_number: None,
                 snapshot_hash: None,
+                serve_from_block: None,
                 asking_snapshot_data: None,
+                asking_fork_header: None,
                 block_set: None,
-                _client_version: ClientVersion::from(""),
+                client_version: ClientVersion::from(""),
+                node_id: None,
+                fee_market: None,
+                difficulty_check: DifficultyCheck::Plausible,
             },
         );
     }
 
+    #[test]
+    fn peer_info_digest() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let mut sync = dummy_sync(&client);
+        insert_dummy_peer(&mut sync, 0, client.block_hash_delta_minus(1));
+        insert_dummy_peer(&mut sync, 1, H256::from_low_u64_be(2));
+        {
+            let peer = sync.peers.get_mut(&1).unwrap();
+            peer.protocol_version = PAR_PROTOCOL_VERSION_4.0;
+            peer.eth_protocol_version = ETH_PROTOCOL_VERSION_66.0;
+            peer.par_protocol_version = PAR_PROTOCOL_VERSION_4.0;
+            peer.confirmation = ForkConfirmation::TooShort;
+            peer.network_id = 5;
+            peer.genesis = H256::from_low_u64_be(1);
+            peer.snapshot_number = Some(8);
+            peer.snapshot_hash = Some(H256::from_low_u64_be(3));
+            peer.block_set = Some(BlockSet::OldBlocks);
+            peer.client_version = ClientVersion::from("OpenEthereum/v3.3.0/x86_64-linux-gnu/rustc");
+        }
+
+        let digest = sync.peer_info(&0).unwrap();
+        assert_eq!(digest.head, client.block_hash_delta_minus(1));
+        assert_eq!(digest.confirmation, ForkConfirmation::Confirmed);
+        assert_eq!(digest.par_version, 0);
+        assert_eq!(digest.snapshot_number, None);
+        assert_eq!(digest.snapshot_hash, None);
+        assert_eq!(digest.block_set, None);
+
+        let digest = sync.peer_info(&1).unwrap();
+        assert_eq!(digest.version, PAR_PROTOCOL_VERSION_4.0 as u32);
+        assert_eq!(digest.eth_version, ETH_PROTOCOL_VERSION_66.0);
+        assert_eq!(digest.par_version, PAR_PROTOCOL_VERSION_4.0);
+        assert_eq!(digest.confirmation, ForkConfirmation::TooShort);
+        assert_eq!(digest.network_id, 5);
+        assert_eq!(digest.genesis, H256::from_low_u64_be(1));
+        assert_eq!(digest.snapshot_number, Some(8));
+        assert_eq!(digest.snapshot_hash, Some(H256::from_low_u64_be(3)));
+        assert_eq!(digest.block_set, Some(BlockSet::OldBlocks));
+        assert_eq!(
+            digest.client_version.to_string(),
+            "OpenEthereum/v3.3.0/x86_64-linux-gnu/rustc"
+        );
+
+        assert!(sync.peer_info(&2).is_none());
+    }
+
     #[test]
     fn finds_lagging_peers() {
         let mut client = TestBlockChainClient::new();
@@ -1840,92 +3963,604 @@ pub mod tests {
         // size of three rlp encoded hash-difficulty
         assert_eq!(107, rlp.len());
     }
-    // idea is that what we produce when propagading latest hashes should be accepted in
-    // on_peer_new_hashes in our code as well
+
     #[test]
-    fn hashes_rlp_mutually_acceptable() {
+    fn old_blocks_not_requested_below_peer_serve_from_block() {
         let mut client = TestBlockChainClient::new();
-        client.add_blocks(100, EachBlockWith::Uncle);
+        client.add_blocks(100, EachBlockWith::Nothing);
+        let best_hash = client.chain_info().best_block_hash;
+        let ancient_hash = client.block_hash(BlockId::Number(10)).unwrap();
+        let mut sync = dummy_sync(&client);
+        sync.old_blocks = Some(BlockDownloader::new(BlockSet::OldBlocks, &ancient_hash, 10));
+        insert_dummy_peer(&mut sync, 0, best_hash);
+        insert_dummy_peer(&mut sync, 1, best_hash);
+        sync.peers.get_mut(&0).unwrap().serve_from_block = Some(50);
+        sync.active_peers = vec![0, 1].into_iter().collect();
+
         let queue = RwLock::new(VecDeque::new());
-        let mut sync = dummy_sync_with_peer(client.block_hash_delta_minus(5), &client);
-        let chain_info = client.chain_info();
         let ss = TestSnapshotService::new();
         let mut io = TestIo::new(&mut client, &ss, &queue, None);
 
-        let peers = sync.get_lagging_peers(&chain_info);
-        SyncPropagator::propagate_new_hashes(&mut sync, &chain_info, &mut io, &peers);
+        sync.sync_peer(&mut io, 0, false);
+        assert_eq!(sync.peers[&0].asking, PeerAsking::Nothing);
 
-        let data = &io.packets[0].data.clone();
-        let result = SyncHandler::on_peer_new_hashes(&mut sync, &mut io, 0, &Rlp::new(data));
-        assert!(result.is_ok());
+        sync.sync_peer(&mut io, 1, false);
+        assert_eq!(sync.peers[&1].asking, PeerAsking::BlockHeaders);
+        assert_eq!(sync.peers[&1].block_set, Some(BlockSet::OldBlocks));
+    }
+
+    fn client_with_ancient_gap() -> TestBlockChainClient {
+        let client = TestBlockChainClient::new();
+        client.add_blocks(100, EachBlockWith::Nothing);
+        let ancient_hash = client.block_hash(BlockId::Number(10)).unwrap();
+        let first_hash = client.block_hash(BlockId::Number(50)).unwrap();
+        *client.ancient_block.write() = Some((ancient_hash, 10));
+        *client.first_block.write() = Some((first_hash, 50));
+        client
     }
 
-    // idea is that what we produce when propagading latest block should be accepted in
-    // on_peer_new_block  in our code as well
     #[test]
-    fn block_rlp_mutually_acceptable() {
-        let mut client = TestBlockChainClient::new();
-        client.add_blocks(100, EachBlockWith::Uncle);
+    fn old_blocks_downloaded_with_default_pruning() {
+        // state pruning of the default configuration keeps every block
+        let client = ::ethcore::test_helpers::generate_dummy_client(20);
+        let mut chain = client.chain_info();
+        assert_eq!(chain.retention_boundary, None);
+
+        // as after a warp sync which left blocks #3 to #14 missing
+        chain.ancient_block_hash = client.block_hash(BlockId::Number(2));
+        chain.ancient_block_number = Some(2);
+        chain.first_block_hash = client.block_hash(BlockId::Number(15));
+        chain.first_block_number = Some(15);
+        let sync = dummy_sync(&*client);
+        assert!(sync.old_blocks_downloader(&chain).is_some());
+    }
+
+    #[test]
+    fn old_blocks_not_requested_below_retention_boundary() {
+        let mut client = client_with_ancient_gap();
+        *client.retention_boundary.write() = Some(60);
+        let best_hash = client.chain_info().best_block_hash;
+        let mut sync = dummy_sync(&client);
+        assert!(sync.old_blocks.is_none());
+
+        insert_dummy_peer(&mut sync, 0, best_hash);
+        sync.active_peers = vec![0].into_iter().collect();
         let queue = RwLock::new(VecDeque::new());
-        let mut sync = dummy_sync_with_peer(client.block_hash_delta_minus(5), &client);
-        let chain_info = client.chain_info();
         let ss = TestSnapshotService::new();
         let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        sync.sync_peer(&mut io, 0, false);
+        assert_eq!(sync.peers[&0].asking, PeerAsking::Nothing);
+    }
 
-        let peers = sync.get_lagging_peers(&chain_info);
-        SyncPropagator::propagate_blocks(&mut sync, &chain_info, &mut io, &[], &peers);
-
-        let data = &io.packets[0].data.clone();
-        let result = SyncHandler::on_peer_new_block(&mut sync, &mut io, 0, &Rlp::new(data));
-        assert!(result.is_ok());
+    #[test]
+    fn raised_retention_boundary_stops_old_blocks_download() {
+        let mut client = client_with_ancient_gap();
+        *client.retention_boundary.write() = Some(5);
+        let best_hash = client.chain_info().best_block_hash;
+        let mut sync = dummy_sync(&client);
+        assert!(sync.old_blocks.is_some());
+
+        insert_dummy_peer(&mut sync, 0, best_hash);
+        sync.active_peers = vec![0].into_iter().collect();
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        sync.sync_peer(&mut io, 0, false);
+        assert_eq!(sync.peers[&0].block_set, Some(BlockSet::OldBlocks));
+
+        // the boundary at the next block to download keeps the download going
+        *io.chain.retention_boundary.write() = Some(11);
+        sync.check_retention_boundary(&mut io);
+        assert!(sync.old_blocks.is_some());
+
+        *io.chain.retention_boundary.write() = Some(30);
+        sync.check_retention_boundary(&mut io);
+        assert!(sync.old_blocks.is_none());
+        assert!(sync.peers[&0].expired);
     }
 
     #[test]
-    fn should_add_transactions_to_queue() {
-        fn sender(tx: &UnverifiedTransaction) -> Address {
-            crypto::publickey::public_to_address(&tx.recover_public().unwrap())
+    fn repair_scan_requests_mismatching_ancient_blocks_again() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let best_hash = client.chain_info().best_block_hash;
+
+        // attach an uncle the header does not commit to
+        let altered = client.block_hash(BlockId::Number(5)).unwrap();
+        {
+            let mut blocks = client.blocks.write();
+            let bytes = blocks[&altered].clone();
+            let block = Rlp::new(&bytes);
+            let mut rlp = RlpStream::new_list(3);
+            rlp.append_raw(block.at(0).unwrap().as_raw(), 1);
+            rlp.append_raw(block.at(1).unwrap().as_raw(), 1);
+            rlp.append_list(&[Header::new()]);
+            blocks.insert(altered, rlp.out());
         }
 
-        // given
-        let mut client = TestBlockChainClient::new();
-        client.add_blocks(98, EachBlockWith::Uncle);
-        client.add_blocks(1, EachBlockWith::UncleAndTransaction);
-        client.add_blocks(1, EachBlockWith::Transaction);
-        let mut sync = dummy_sync_with_peer(client.block_hash_delta_minus(5), &client);
+        // the test client makes up receipts for some hashes, those do not match either
+        let expected: Vec<BlockNumber> = (3..8)
+            .filter(|&n| {
+                let hash = client.block_hash(BlockId::Number(n)).unwrap();
+                n == 5 || client.block_receipts(&hash).is_some()
+            })
+            .collect();
 
-        let good_blocks = vec![client.block_hash_delta_minus(2)];
-        let retracted_blocks = vec![client.block_hash_delta_minus(1)];
+        let api = dummy_sync_api(&client);
+        assert_eq!(api.repair_ancient_blocks(&client, 3..=7), expected);
+        let mut sync = api.write();
+        assert!(sync.old_blocks.as_ref().is_some_and(|d| d.is_repair()));
 
-        // Add some balance to clients and reset nonces
-        for h in &[good_blocks[0], retracted_blocks[0]] {
-            let block = client.block(BlockId::Hash(*h)).unwrap();
-            let sender = sender(&block.transactions()[0]);
-            client.set_balance(sender, U256::from(10_000_000_000_000_000_000u64));
-            client.set_nonce(sender, U256::from(0));
-        }
+        insert_dummy_peer(&mut sync, 0, best_hash);
+        sync.active_peers = vec![0].into_iter().collect();
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        sync.sync_peer(&mut io, 0, false);
+        assert_eq!(sync.peers[&0].asking, PeerAsking::BlockHeaders);
+        assert_eq!(sync.peers[&0].block_set, Some(BlockSet::OldBlocks));
+    }
 
-        // when
-        {
-            let queue = RwLock::new(VecDeque::new());
-            let ss = TestSnapshotService::new();
-            let mut io = TestIo::new(&mut client, &ss, &queue, None);
-            io.chain
-                .miner
-                .chain_new_blocks(io.chain, &[], &[], &[], &good_blocks, false);
-            sync.chain_new_blocks(&mut io, &[], &[], &[], &good_blocks, &[], &[]);
-            assert_eq!(
-                io.chain
-                    .miner
-                    .ready_transactions(io.chain, 10, PendingOrdering::Priority)
-                    .len(),
-                1
-            );
+    #[test]
+    fn repair_scan_of_matching_blocks_does_nothing() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let api = dummy_sync_api(&client);
+        let numbers: Vec<BlockNumber> = (1..10)
+            .filter(|&n| {
+                let hash = client.block_hash(BlockId::Number(n)).unwrap();
+                client.block_receipts(&hash).is_none()
+            })
+            .collect();
+        for n in numbers {
+            assert!(api.repair_ancient_blocks(&client, n..=n).is_empty());
         }
-        // We need to update nonce status (because we say that the block has been imported)
+        assert!(api.write().old_blocks.is_none());
+    }
+
+    #[test]
+    fn repair_scan_continues_over_chunks_until_the_end_of_the_chain() {
+        let mut client = TestBlockChainClient::new();
+        let count = REPAIR_SCAN_CHUNK as usize * 2 + 4;
+        client.add_blocks(count, EachBlockWith::Nothing);
+
+        // attach an uncle the header does not commit to in the last chunk
+        let altered = REPAIR_SCAN_CHUNK * 2 + 2;
+        let hash = client.block_hash(BlockId::Number(altered)).unwrap();
         {
-            let h = &good_blocks[0];
-            let block = client.block(BlockId::Hash(*h)).unwrap();
-            client.set_nonce(sender(&block.transactions()[0]), U256::from(1));
+            let mut blocks = client.blocks.write();
+            let bytes = blocks[&hash].clone();
+            let block = Rlp::new(&bytes);
+            let mut rlp = RlpStream::new_list(3);
+            rlp.append_raw(block.at(0).unwrap().as_raw(), 1);
+            rlp.append_raw(block.at(1).unwrap().as_raw(), 1);
+            rlp.append_list(&[Header::new()]);
+            blocks.insert(hash, rlp.out());
+        }
+
+        let expected: Vec<BlockNumber> = (1..=count as BlockNumber)
+            .filter(|&n| {
+                let hash = client.block_hash(BlockId::Number(n)).unwrap();
+                n == altered || client.block_receipts(&hash).is_some()
+            })
+            .collect();
+
+        // the range reaches beyond the best block, the scan stops there
+        let api = dummy_sync_api(&client);
+        let mismatched = api.repair_ancient_blocks(&client, 1..=BlockNumber::MAX);
+        assert_eq!(mismatched, expected);
+        assert!(mismatched.contains(&altered));
+    }
+
+    fn backfill_sync(client: &TestBlockChainClient, checkpoint: (BlockNumber, H256)) -> ChainSync {
+        let (_, transaction_hashes_rx) = crossbeam_channel::unbounded();
+        let config = SyncConfig {
+            header_checkpoint: Some(checkpoint),
+            ..SyncConfig::default()
+        };
+        let mut sync = ChainSync::new(
+            config,
+            client,
+            ForkFilterApi::new_dummy(client),
+            transaction_hashes_rx,
+        );
+        insert_dummy_peer(&mut sync, 0, client.chain_info().best_block_hash);
+        insert_dummy_peer(&mut sync, 1, client.chain_info().best_block_hash);
+        sync.active_peers = vec![0, 1].into_iter().collect();
+        sync
+    }
+
+    // headers `from` down to `from - count + 1`, as served for a reverse request
+    fn reverse_headers(client: &TestBlockChainClient, from: BlockNumber, count: u64) -> Bytes {
+        let mut rlp = RlpStream::new_list(count as usize);
+        for number in (from + 1 - count..=from).rev() {
+            let header = client.block_header(BlockId::Number(number)).unwrap();
+            rlp.append_raw(&header.into_inner(), 1);
+        }
+        rlp.out()
+    }
+
+    #[test]
+    fn header_backfill_advances_to_completion() {
+        let mut remote = TestBlockChainClient::new();
+        remote.add_blocks(200, EachBlockWith::Nothing);
+        let checkpoint = (200, remote.block_hash(BlockId::Number(200)).unwrap());
+        let mut client = TestBlockChainClient::new();
+        let mut sync = backfill_sync(&client, checkpoint);
+        assert_eq!(sync.status().headers_backfill_target, 200);
+        assert_eq!(sync.status().headers_backfilled, 0);
+
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        sync.sync_peer(&mut io, 0, false);
+        assert_eq!(sync.peers[&0].asking, PeerAsking::BackfillHeaders);
+        // one batch at a time
+        sync.sync_peer(&mut io, 1, false);
+        assert_eq!(sync.peers[&1].asking, PeerAsking::Nothing);
+
+        let headers = reverse_headers(&remote, 200, 128);
+        SyncHandler::on_packet(
+            &mut sync,
+            &mut io,
+            0,
+            SyncPacket::BlockHeadersPacket.id(),
+            &headers,
+        );
+        assert_eq!(sync.status().headers_backfilled, 128);
+        // the next batch is requested right away
+        assert_eq!(sync.peers[&0].asking, PeerAsking::BackfillHeaders);
+
+        let headers = reverse_headers(&remote, 72, 72);
+        SyncHandler::on_packet(
+            &mut sync,
+            &mut io,
+            0,
+            SyncPacket::BlockHeadersPacket.id(),
+            &headers,
+        );
+        assert_eq!(sync.status().headers_backfilled, 200);
+        assert_eq!(sync.peers[&0].asking, PeerAsking::Nothing);
+        assert!(io.to_disconnect.is_empty());
+
+        let headers_only = io.chain.headers_only.read();
+        assert_eq!(headers_only.len(), 200);
+        assert_eq!(
+            headers_only[&200],
+            remote.block_header(BlockId::Number(200)).unwrap()
+        );
+    }
+
+    #[test]
+    fn header_backfill_rejects_unlinked_headers() {
+        let mut remote = TestBlockChainClient::new();
+        remote.add_blocks(200, EachBlockWith::Nothing);
+        let mut other = TestBlockChainClient::new();
+        other.add_blocks(200, EachBlockWith::Uncle);
+        let checkpoint = (200, remote.block_hash(BlockId::Number(200)).unwrap());
+        let mut client = TestBlockChainClient::new();
+        let mut sync = backfill_sync(&client, checkpoint);
+
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        // a header of another chain in the middle of the batch
+        let mut rlp = RlpStream::new_list(128);
+        for number in (73..=200).rev() {
+            let source = if number == 150 { &other } else { &remote };
+            let header = source.block_header(BlockId::Number(number)).unwrap();
+            rlp.append_raw(&header.into_inner(), 1);
+        }
+        sync.sync_peer(&mut io, 0, false);
+        SyncHandler::on_packet(
+            &mut sync,
+            &mut io,
+            0,
+            SyncPacket::BlockHeadersPacket.id(),
+            &rlp.out(),
+        );
+
+        assert!(io.to_disconnect.contains(&0));
+        assert!(!sync.active_peers.contains(&0));
+        assert_eq!(sync.status().headers_backfilled, 0);
+        assert!(io.chain.headers_only.read().is_empty());
+
+        // the batch is requested again from another peer
+        sync.sync_peer(&mut io, 1, false);
+        assert_eq!(sync.peers[&1].asking, PeerAsking::BackfillHeaders);
+
+        sync.abort_header_backfill();
+        assert_eq!(sync.status().headers_backfill_target, 0);
+    }
+
+    #[test]
+    fn subchain_heads_are_requested_several_at_a_time() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let mut sync = dummy_sync(&client);
+        insert_dummy_peer(&mut sync, 0, H256::random());
+        sync.active_peers = vec![0].into_iter().collect();
+
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        sync.sync_peer(&mut io, 0, false);
+
+        let headers = SyncPacket::GetBlockHeadersPacket.id();
+        let requests = io
+            .packets
+            .iter()
+            .filter(|p| p.recipient == 0 && p.packet_id == headers)
+            .count();
+        assert_eq!(requests, SyncConfig::default().chain_head_pipeline);
+        assert_eq!(sync.peers[&0].asking, PeerAsking::BlockHeaders);
+        assert_eq!(sync.peers[&0].asking_headers, requests);
+    }
+
+    #[test]
+    fn subchain_heads_requests_are_limited_over_all_peers() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let config = SyncConfig {
+            chain_head_requests: 3,
+            ..SyncConfig::default()
+        };
+        let (_, transaction_hashes_rx) = crossbeam_channel::unbounded();
+        let mut sync = ChainSync::new(
+            config,
+            &client,
+            ForkFilterApi::new_dummy(&client),
+            transaction_hashes_rx,
+        );
+        insert_dummy_peer(&mut sync, 0, H256::random());
+        insert_dummy_peer(&mut sync, 1, H256::random());
+        sync.active_peers = vec![0, 1].into_iter().collect();
+
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        sync.sync_peer(&mut io, 0, false);
+        sync.sync_peer(&mut io, 1, false);
+
+        assert_eq!(sync.peers[&0].asking_headers, 2);
+        assert_eq!(sync.peers[&1].asking_headers, 1);
+    }
+
+    #[test]
+    fn delayed_requests_of_disconnected_peer_are_dropped() {
+        let mut client = TestBlockChainClient::new();
+        let mut sync = dummy_sync(&client);
+        insert_dummy_peer(&mut sync, 1, H256::zero());
+        insert_dummy_peer(&mut sync, 2, H256::zero());
+        let headers = SyncPacket::GetBlockHeadersPacket.id();
+        let bodies = SyncPacket::GetBlockBodiesPacket.id();
+        sync.add_delayed_request(1, headers, &[1]);
+        sync.add_delayed_request(2, headers, &[2]);
+        sync.add_delayed_request(1, bodies, &[1]);
+        sync.add_delayed_request(1, bodies, &[1]);
+
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        sync.on_peer_aborting(&mut io, 1);
+
+        assert_eq!(
+            sync.retrieve_delayed_requests(),
+            vec![(2, headers, vec![2])]
+        );
+        assert_eq!(sync.status().num_delayed_requests, 3);
+    }
+
+    #[test]
+    fn supplier_of_invalid_block_is_penalized() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let mut sync = dummy_sync(&client);
+        insert_dummy_peer(&mut sync, 1, H256::zero());
+        insert_dummy_peer(&mut sync, 2, H256::zero());
+        sync.active_peers.insert(1);
+        sync.active_peers.insert(2);
+
+        let side_block = H256::from_low_u64_be(0xa);
+        let valid_block = H256::from_low_u64_be(0xb);
+        sync.new_blocks.mark_as_known(&valid_block, 11);
+        sync.block_suppliers.insert(side_block, 1);
+        sync.block_suppliers.insert(valid_block, 2);
+
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        sync.chain_new_blocks(&mut io, &[valid_block], &[side_block], &[], &[], &[], &[]);
+
+        assert!(io.to_disconnect.contains(&1));
+        assert!(!io.to_disconnect.contains(&2));
+        assert!(!sync.active_peers.contains(&1));
+        assert!(sync.active_peers.contains(&2));
+        // the side branch does not affect the chain being synced
+        assert_eq!(sync.new_blocks.last_imported_block_number(), 11);
+        assert!(sync.block_suppliers.peers.is_empty());
+
+        // an invalid block on the synced chain restarts the download
+        sync.block_suppliers.insert(valid_block, 2);
+        sync.chain_new_blocks(&mut io, &[], &[valid_block], &[], &[], &[], &[]);
+        assert!(io.to_disconnect.contains(&2));
+        assert_eq!(sync.new_blocks.last_imported_block_number(), 10);
+    }
+
+    #[test]
+    fn rejected_sealed_block_notifies_handler() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let mut sync = dummy_sync(&client);
+        let rejected = Arc::new(RwLock::new(Vec::new()));
+        let notified = rejected.clone();
+        sync.set_sealed_block_rejected_handler(Some(Arc::new(move |hash, _reason: &str| {
+            notified.write().push(hash)
+        })));
+
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        let sealed = H256::from_low_u64_be(0xa);
+        let foreign = H256::from_low_u64_be(0xb);
+        sync.chain_new_blocks(&mut io, &[], &[], &[], &[], &[sealed], &[]);
+        sync.chain_new_blocks(&mut io, &[], &[foreign], &[], &[], &[], &[]);
+        assert!(rejected.read().is_empty());
+        assert_eq!(sync.status().num_rejected_sealed_blocks, 0);
+
+        sync.chain_new_blocks(&mut io, &[], &[sealed], &[], &[], &[], &[]);
+        assert_eq!(*rejected.read(), vec![sealed]);
+        assert_eq!(sync.status().num_rejected_sealed_blocks, 1);
+    }
+
+    #[test]
+    fn only_benignly_deactivated_peers_come_back_on_new_heads() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let mut sync = dummy_sync_with_peer(client.block_hash_delta_minus(5), &client);
+        insert_dummy_peer(&mut sync, 1, client.block_hash_delta_minus(5));
+        sync.active_peers = vec![0, 1].into_iter().collect();
+        sync.peers.get_mut(&0).unwrap().difficulty = Some(10.into());
+
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        sync.deactivate_peer(&mut io, 0, DeactivationReason::LowDifficulty);
+        sync.disable_peer(&mut io, 1);
+        assert!(sync.active_peers.is_empty());
+        assert_eq!(sync.status().num_inactive_peers, 1);
+        assert_eq!(sync.status().num_disabled_peers, 1);
+
+        // announce our best block, known already so that no new sync round starts
+        let best_hash = io.chain().chain_info().best_block_hash;
+        let best_block = io.chain().block(BlockId::Hash(best_hash)).unwrap();
+        let new_block = |difficulty: u64| {
+            let mut rlp = RlpStream::new_list(2);
+            rlp.append_raw(best_block.rlp().as_raw(), 1);
+            rlp.append(&U256::from(difficulty));
+            rlp.out()
+        };
+        let data = new_block(5);
+        SyncHandler::on_peer_new_block(&mut sync, &mut io, 0, &Rlp::new(&data)).unwrap();
+        assert!(!sync.active_peers.contains(&0));
+
+        let data = new_block(1000);
+        for peer in 0..2 {
+            SyncHandler::on_peer_new_block(&mut sync, &mut io, peer, &Rlp::new(&data)).unwrap();
+        }
+        assert!(sync.active_peers.contains(&0));
+        assert!(!sync.active_peers.contains(&1));
+        assert_eq!(sync.status().num_inactive_peers, 0);
+        assert_eq!(sync.status().num_disabled_peers, 1);
+
+        // a new sync round leaves the misbehaving peer out
+        sync.restart(&mut io);
+        assert!(!sync.active_peers.contains(&1));
+    }
+
+    #[test]
+    fn block_suppliers_are_bounded() {
+        let mut suppliers = BlockSuppliers::default();
+        for i in 0..(MAX_BLOCK_SUPPLIERS as u64 + 10) {
+            suppliers.insert(H256::from_low_u64_be(i), 1);
+        }
+        assert_eq!(suppliers.peers.len(), MAX_BLOCK_SUPPLIERS);
+        assert_eq!(suppliers.remove(&H256::from_low_u64_be(0)), None);
+
+        suppliers.insert(H256::zero(), 2);
+        suppliers.on_peer_disconnected(1);
+        assert_eq!(suppliers.remove(&H256::zero()), Some(2));
+        assert!(suppliers.peers.is_empty());
+    }
+
+    // idea is that what we produce when propagading latest hashes should be accepted in
+    // on_peer_new_hashes in our code as well
+    #[test]
+    fn hashes_rlp_mutually_acceptable() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(100, EachBlockWith::Uncle);
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = dummy_sync_with_peer(client.block_hash_delta_minus(5), &client);
+        let chain_info = client.chain_info();
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        let peers = sync.get_lagging_peers(&chain_info);
+        SyncPropagator::propagate_new_hashes(&mut sync, &chain_info, &mut io, &peers);
+
+        let data = &io.packets[0].data.clone();
+        let result = SyncHandler::on_peer_new_hashes(&mut sync, &mut io, 0, &Rlp::new(data));
+        assert!(result.is_ok());
+    }
+
+    // idea is that what we produce when propagading latest block should be accepted in
+    // on_peer_new_block  in our code as well
+    #[test]
+    fn block_rlp_mutually_acceptable() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(100, EachBlockWith::Uncle);
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = dummy_sync_with_peer(client.block_hash_delta_minus(5), &client);
+        let chain_info = client.chain_info();
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        let peers = sync.get_lagging_peers(&chain_info);
+        SyncPropagator::propagate_blocks(&mut sync, &chain_info, &mut io, &[], &peers);
+
+        let data = &io.packets[0].data.clone();
+        let result = SyncHandler::on_peer_new_block(&mut sync, &mut io, 0, &Rlp::new(data));
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn should_add_transactions_to_queue() {
+        fn sender(tx: &UnverifiedTransaction) -> Address {
+            crypto::publickey::public_to_address(&tx.recover_public().unwrap())
+        }
+
+        // given
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(98, EachBlockWith::Uncle);
+        client.add_blocks(1, EachBlockWith::UncleAndTransaction);
+        client.add_blocks(1, EachBlockWith::Transaction);
+        let mut sync = dummy_sync_with_peer(client.block_hash_delta_minus(5), &client);
+
+        let good_blocks = vec![client.block_hash_delta_minus(2)];
+        let retracted_blocks = vec![client.block_hash_delta_minus(1)];
+
+        // Add some balance to clients and reset nonces
+        for h in &[good_blocks[0], retracted_blocks[0]] {
+            let block = client.block(BlockId::Hash(*h)).unwrap();
+            let sender = sender(&block.transactions()[0]);
+            client.set_balance(sender, U256::from(10_000_000_000_000_000_000u64));
+            client.set_nonce(sender, U256::from(0));
+        }
+
+        // when
+        {
+            let queue = RwLock::new(VecDeque::new());
+            let ss = TestSnapshotService::new();
+            let mut io = TestIo::new(&mut client, &ss, &queue, None);
+            io.chain
+                .miner
+                .chain_new_blocks(io.chain, &[], &[], &[], &good_blocks, false);
+            sync.chain_new_blocks(&mut io, &[], &[], &[], &good_blocks, &[], &[]);
+            assert_eq!(
+                io.chain
+                    .miner
+                    .ready_transactions(io.chain, 10, PendingOrdering::Priority)
+                    .len(),
+                1
+            );
+        }
+        // We need to update nonce status (because we say that the block has been imported)
+        {
+            let h = &good_blocks[0];
+            let block = client.block(BlockId::Hash(*h)).unwrap();
+            client.set_nonce(sender(&block.transactions()[0]), U256::from(1));
         }
         {
             let queue = RwLock::new(VecDeque::new());
@@ -2002,4 +4637,595 @@ pub mod tests {
             vec![6, 7].into_iter().map(H256::from_low_u64_be).collect()
         );
     }
+
+    fn collect_events(events: &crossbeam_channel::Receiver<SyncEvent>) -> Vec<SyncEventKind> {
+        events.try_iter().map(|e| e.kind).collect()
+    }
+
+    #[test]
+    fn emits_state_change_when_waiting_for_peers_times_out() {
+        let mut client = TestBlockChainClient::new();
+        let mut config = SyncConfig::default();
+        config.warp_sync = WarpSync::Enabled;
+        let (_, transaction_hashes_rx) = crossbeam_channel::unbounded();
+        let mut sync = ChainSync::new(
+            config,
+            &client,
+            ForkFilterApi::new_dummy(&client),
+            transaction_hashes_rx,
+        );
+        let (tx, events) = crossbeam_channel::unbounded();
+        sync.set_event_sender(Some(tx));
+        assert_eq!(sync.state, SyncState::WaitingPeers);
+
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        let before = SystemTime::now();
+        sync.maybe_start_snapshot_sync(&mut io);
+        assert!(collect_events(&events).is_empty());
+
+        sync.sync_start_time = Some(Instant::now() - WAIT_PEERS_TIMEOUT - Duration::from_secs(1));
+        sync.maybe_start_snapshot_sync(&mut io);
+        let event = events.try_recv().unwrap();
+        assert!(event.timestamp >= before);
+        assert_eq!(
+            event.kind,
+            SyncEventKind::StateChanged {
+                from: SyncState::WaitingPeers,
+                to: SyncState::Idle,
+            }
+        );
+        assert!(collect_events(&events).is_empty());
+    }
+
+    #[test]
+    fn emits_state_changes_on_pause_and_resume() {
+        let mut client = TestBlockChainClient::new();
+        let mut sync = dummy_sync(&client);
+        let (tx, events) = crossbeam_channel::unbounded();
+        sync.set_event_sender(Some(tx));
+
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        sync.pause_sync();
+        sync.check_resume(&mut io);
+        assert_eq!(
+            collect_events(&events),
+            vec![
+                SyncEventKind::StateChanged {
+                    from: SyncState::Idle,
+                    to: SyncState::Waiting,
+                },
+                SyncEventKind::StateChanged {
+                    from: SyncState::Waiting,
+                    to: SyncState::Blocks,
+                },
+                SyncEventKind::StateChanged {
+                    from: SyncState::Blocks,
+                    to: SyncState::Idle,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn emits_highest_block_only_when_it_increases() {
+        let client = TestBlockChainClient::new();
+        let mut sync = dummy_sync(&client);
+        let (tx, events) = crossbeam_channel::bounded(1);
+        sync.set_event_sender(Some(tx));
+
+        sync.note_highest_block(10);
+        sync.note_highest_block(5);
+        assert_eq!(
+            collect_events(&events),
+            vec![SyncEventKind::HighestBlock { number: 10 }]
+        );
+
+        sync.note_highest_block(11);
+        sync.note_highest_block(12);
+        assert_eq!(sync.status().num_dropped_sync_events, 1);
+        assert_eq!(sync.status().highest_block_number, Some(12));
+    }
+
+    #[test]
+    fn reports_hinted_highest_block_until_a_peer_announces_one() {
+        let client = TestBlockChainClient::new();
+        let now = SystemTime::now();
+        let mut hints = HeadHints::new(client.chain_info().genesis_hash);
+        for (node, number) in [(1, 5000), (2, 4000)] {
+            let hash = H256::from_low_u64_be(number);
+            hints.note_block(NodeId::from_low_u64_be(node), hash, number, None, now);
+        }
+        let mut sync = dummy_sync(&client);
+        sync.set_head_hints(hints);
+
+        let status = sync.status();
+        assert_eq!(status.highest_block_number, Some(4000));
+        assert!(status.highest_block_hinted);
+        assert_eq!(status.blocks_total, 4000);
+
+        // the heads connected peers announce are noted for the next run
+        let node = NodeId::from_low_u64_be(3);
+        insert_dummy_peer(&mut sync, 0, H256::zero());
+        sync.peers.get_mut(&0).unwrap().node_id = Some(node);
+        sync.note_head_hint(0, H256::from_low_u64_be(3000), Some(3000), None);
+        sync.note_highest_block(3000);
+
+        let status = sync.status();
+        assert_eq!(status.highest_block_number, Some(3000));
+        assert!(!status.highest_block_hinted);
+        assert_eq!(sync.head_hints().get(&node).unwrap().number, Some(3000));
+    }
+
+    // Peers 1 and 2 serve a head keeping the gas limit of 1_000_000, peers 3 and 4 one doubling
+    // it. Returns the peers in the order they are synced with.
+    fn fee_market_order(our_gas_limit: u64, doubling_difficulty: u64) -> (ChainSync, Vec<PeerId>) {
+        let mut client = TestBlockChainClient::new();
+        for _ in 0..10 {
+            client.add_block(EachBlockWith::Nothing, |mut header| {
+                header.set_gas_limit(our_gas_limit.into());
+                header
+            });
+        }
+        let mut sync = dummy_sync(&client);
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let io = TestIo::new(&mut client, &ss, &queue, None);
+
+        for (peer_id, gas_limit, difficulty) in [
+            (1, 1_000_000u64, 1000),
+            (2, 1_000_000, 1000),
+            (3, 2_000_000, doubling_difficulty),
+            (4, 2_000_000, doubling_difficulty),
+        ] {
+            let mut header = Header::new();
+            header.set_number(12);
+            header.set_gas_limit(gas_limit.into());
+            insert_dummy_peer(&mut sync, peer_id, header.hash());
+            sync.peers.get_mut(&peer_id).unwrap().difficulty = Some(difficulty.into());
+            sync.active_peers.insert(peer_id);
+
+            let mut headers = RlpStream::new_list(1);
+            headers.append(&header);
+            sync.note_fee_market(&io, peer_id, &Rlp::new(&headers.out()));
+        }
+        let order = sync.sync_candidates();
+        (sync, order)
+    }
+
+    fn first_two(order: &[PeerId]) -> Vec<PeerId> {
+        let mut first = order[..2].to_vec();
+        first.sort();
+        first
+    }
+
+    #[test]
+    fn prefers_peers_following_our_fee_market() {
+        let (sync, order) = fee_market_order(1_000_000, 1001);
+        assert_eq!(order.len(), 4);
+        assert_eq!(first_two(&order), vec![1, 2]);
+        let recorded = sync.peer_info(&3).unwrap().fee_market.unwrap();
+        assert_eq!(recorded.number, 12);
+        assert_eq!(recorded.gas_limit, 2_000_000.into());
+        assert_eq!(recorded.consistent, Some(false));
+        assert_eq!(sync.peers[&1].fee_market.unwrap().consistent, Some(true));
+
+        // our chain follows the other side of the split
+        let (_, order) = fee_market_order(2_000_000, 1001);
+        assert_eq!(first_two(&order), vec![3, 4]);
+
+        // a clearly higher difficulty outweighs the fee market
+        let (sync, _) = fee_market_order(1_000_000, 2000);
+        assert!(sync.off_fee_market_peers().is_empty());
+    }
+
+    // Peers connect one by one, each followed by a sync trigger, either running the sync round
+    // at once or deferring it. Each peer is due a difficulty probe. Returns the requests sent
+    // and the number of peer scans.
+    fn requests_on_peer_burst(coalesce: bool) -> (HashSet<(PeerId, u8, Bytes)>, usize) {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let (_, transaction_hashes_rx) = crossbeam_channel::unbounded();
+        let config = SyncConfig {
+            continue_sync_interval: Duration::from_secs(3600),
+            ..SyncConfig::default()
+        };
+        let mut sync = ChainSync::new(
+            config,
+            &client,
+            ForkFilterApi::new_dummy(&client),
+            transaction_hashes_rx,
+        );
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        for peer_id in 1..=20 {
+            insert_dummy_peer(
+                &mut sync,
+                peer_id,
+                H256::from_low_u64_be(100 + peer_id as u64),
+            );
+            sync.peers.get_mut(&peer_id).unwrap().difficulty_check =
+                DifficultyCheck::Unverified(1000.into());
+            sync.active_peers.insert(peer_id);
+            if coalesce {
+                sync.schedule_continue_sync(&mut io);
+            } else {
+                sync.continue_sync(&mut io);
+            }
+        }
+        assert_eq!(sync.continue_sync_pending(), coalesce);
+        sync.flush_continue_sync(&mut io);
+        assert!(!sync.continue_sync_pending());
+
+        let requests = io
+            .packets
+            .iter()
+            .map(|p| (p.recipient, p.packet_id, p.data.clone()))
+            .collect();
+        (requests, sync.peer_scans)
+    }
+
+    #[test]
+    fn coalesced_sync_rounds_send_the_same_requests() {
+        let (direct, _) = requests_on_peer_burst(false);
+        let (coalesced, _) = requests_on_peer_burst(true);
+        assert_eq!(direct.len(), 20);
+        assert_eq!(direct, coalesced);
+    }
+
+    #[test]
+    fn coalesced_sync_rounds_scan_peers_once_per_burst() {
+        let (_, direct) = requests_on_peer_burst(false);
+        let (_, coalesced) = requests_on_peer_burst(true);
+        assert_eq!(direct, 20);
+        // the first trigger runs at once, the rest are handled by the flush
+        assert_eq!(coalesced, 2);
+    }
+
+    #[test]
+    fn idle_peers_are_indexed() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let mut sync = dummy_sync(&client);
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        for peer_id in 1..=3 {
+            insert_dummy_peer(
+                &mut sync,
+                peer_id,
+                H256::from_low_u64_be(100 + peer_id as u64),
+            );
+            sync.peers.get_mut(&peer_id).unwrap().difficulty_check =
+                DifficultyCheck::Unverified(1000.into());
+            sync.active_peers.insert(peer_id);
+        }
+        sync.continue_sync(&mut io);
+        // all peers were sent a difficulty probe and left the index
+        assert!(sync.sync_candidates().is_empty());
+        assert!(sync.idle_peers.is_empty());
+
+        let asking = sync.peers[&2].asking.clone();
+        sync.reset_peer_asking(2, asking);
+        assert_eq!(sync.sync_candidates(), vec![2]);
+    }
+
+    fn status_manifest(ss: &TestSnapshotService, serve_snapshots: bool) -> (H256, BlockNumber) {
+        let mut client = TestBlockChainClient::new();
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = dummy_sync(&client);
+        sync.set_serve_snapshots(serve_snapshots);
+        let mut io = TestIo::new(&mut client, ss, &queue, Some(0));
+
+        sync.send_status(&mut io, 0).unwrap();
+
+        assert_eq!(io.packets.len(), 1);
+        let status = Rlp::new(&io.packets[0].data);
+        (status.val_at(6).unwrap(), status.val_at(7).unwrap())
+    }
+
+    #[test]
+    fn status_advertises_complete_snapshot() {
+        let ss = TestSnapshotService::new_with_snapshot(4, H256::zero(), 100);
+        let expected = keccak(ss.manifest().unwrap().into_rlp());
+
+        assert_eq!(status_manifest(&ss, true), (expected, 100));
+    }
+
+    #[test]
+    fn status_omits_manifest_when_not_serving_snapshots() {
+        let ss = TestSnapshotService::new_with_snapshot(4, H256::zero(), 100);
+
+        assert_eq!(status_manifest(&ss, false), (H256::zero(), 0));
+    }
+
+    #[test]
+    fn status_omits_partial_snapshot() {
+        let mut ss = TestSnapshotService::new_with_snapshot(4, H256::zero(), 100);
+        ss.remove_first_chunk();
+
+        assert_eq!(status_manifest(&ss, true), (H256::zero(), 0));
+    }
+
+    fn handshaking_peer(age: Duration, retried: bool) -> HandshakingPeer {
+        HandshakingPeer {
+            ask_time: Instant::now() - age,
+            timeout: STATUS_TIMEOUT,
+            retried,
+        }
+    }
+
+    fn status_packets(io: &TestIo<TestBlockChainClient>, peer: PeerId) -> usize {
+        io.packets
+            .iter()
+            .filter(|p| p.packet_id == StatusPacket.id() && p.recipient == peer)
+            .count()
+    }
+
+    /// Status packet as encoded through the `Encodable` implementations of the fields.
+    fn reference_status_packet(
+        sync: &ChainSync,
+        io: &TestIo<TestBlockChainClient>,
+        protocol: u8,
+        eth_64: bool,
+        warp: Option<(BlockNumber, H256)>,
+    ) -> Bytes {
+        let chain = io.chain().chain_info();
+        let mut packet = RlpStream::new();
+        packet.begin_unbounded_list();
+        packet.append(&(protocol as u32));
+        packet.append(&sync.network_id);
+        packet.append(&primitive_types07::U256(chain.total_difficulty.0));
+        packet.append(&primitive_types07::H256(chain.best_block_hash.0));
+        packet.append(&primitive_types07::H256(chain.genesis_hash.0));
+        if eth_64 {
+            packet.append(&sync.fork_filter.current(io.chain()));
+        }
+        if let Some((block_number, manifest_hash)) = warp {
+            packet.append(&primitive_types07::H256(manifest_hash.0));
+            packet.append(&block_number);
+        }
+        packet.finalize_unbounded_list();
+        packet.out()
+    }
+
+    #[test]
+    fn status_packet_encoding_is_unchanged() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Uncle);
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new_with_snapshot(4, H256::zero(), 100);
+        let manifest_hash = keccak(ss.manifest().unwrap().into_rlp());
+        let sync = dummy_sync(&client);
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        io.eth_protocol_version = ETH_PROTOCOL_VERSION_64.0;
+        io.par_protocol_version = 0;
+        let (protocol, packet) = sync.status_packet(&io, 1);
+        assert_eq!(protocol, ETH_PROTOCOL);
+        assert_eq!(
+            packet,
+            reference_status_packet(&sync, &io, ETH_PROTOCOL_VERSION_64.0, true, None)
+        );
+
+        io.par_protocol_version = PAR_PROTOCOL_VERSION_2.0;
+        let (protocol, packet) = sync.status_packet(&io, 1);
+        assert_eq!(protocol, PAR_PROTOCOL);
+        assert_eq!(
+            packet,
+            reference_status_packet(
+                &sync,
+                &io,
+                PAR_PROTOCOL_VERSION_2.0,
+                true,
+                Some((100, manifest_hash))
+            )
+        );
+
+        io.eth_protocol_version = ETH_PROTOCOL_VERSION_63.0;
+        let (_, packet) = sync.status_packet(&io, 1);
+        assert_eq!(
+            packet,
+            reference_status_packet(
+                &sync,
+                &io,
+                PAR_PROTOCOL_VERSION_2.0,
+                false,
+                Some((100, manifest_hash))
+            )
+        );
+    }
+
+    #[test]
+    fn drops_peer_after_status_timeout() {
+        let mut client = TestBlockChainClient::new();
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut sync = dummy_sync(&client);
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        sync.handshaking_peers
+            .insert(1, handshaking_peer(Duration::from_secs(6), true));
+        sync.handshaking_peers
+            .insert(2, handshaking_peer(Duration::from_secs(4), false));
+        sync.maintain_peers(&mut io);
+
+        assert!(io.to_disconnect.contains(&1));
+        assert!(!io.to_disconnect.contains(&2));
+        assert!(!sync.handshaking_peers.contains_key(&1));
+        assert!(sync.handshaking_peers.contains_key(&2));
+        assert_eq!(sync.status().num_status_timeout_drops, 1);
+    }
+
+    #[test]
+    fn resends_status_once_before_dropping_peer() {
+        let mut client = TestBlockChainClient::new();
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut sync = dummy_sync(&client);
+        let mut io = TestIo::new(&mut client, &ss, &queue, Some(1));
+
+        sync.on_peer_connected(&mut io, 1);
+        assert_eq!(status_packets(&io, 1), 1);
+        let timeout = sync.handshaking_peers[&1].timeout;
+        assert!(timeout >= STATUS_TIMEOUT * 4 / 5 && timeout <= STATUS_TIMEOUT * 6 / 5);
+
+        sync.handshaking_peers
+            .insert(1, handshaking_peer(Duration::from_secs(7), false));
+        sync.maintain_peers(&mut io);
+        assert_eq!(status_packets(&io, 1), 2);
+        assert!(io.to_disconnect.is_empty());
+        assert!(sync.handshaking_peers[&1].retried);
+
+        sync.handshaking_peers
+            .insert(1, handshaking_peer(Duration::from_secs(7), true));
+        sync.maintain_peers(&mut io);
+        assert_eq!(status_packets(&io, 1), 2);
+        assert!(io.to_disconnect.contains(&1));
+        assert_eq!(sync.status().num_status_timeout_drops, 1);
+    }
+
+    #[test]
+    fn aborted_handshakes_leave_no_peer_state() {
+        let mut client = TestBlockChainClient::new();
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut sync = dummy_sync(&client);
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        let sizes = |sync: &ChainSync| {
+            (
+                sync.handshaking_peers.len(),
+                sync.active_peers.len(),
+                sync.delayed_requests.len(),
+            )
+        };
+        let baseline = sizes(&sync);
+
+        for peer in 0..500 {
+            sync.on_peer_connected(&mut io, peer);
+            sync.add_delayed_request(peer, SyncPacket::GetBlockHeadersPacket.id(), &[0xc0]);
+            // as left behind by a sync round
+            sync.active_peers.insert(peer);
+        }
+        assert_eq!(sync.status().item_sizes["handshaking_peers"], 500);
+        assert_ne!(sync.delayed_requests.len(), 0);
+
+        for peer in 0..500 {
+            sync.on_peer_aborting(&mut io, peer);
+        }
+        assert_eq!(sizes(&sync), baseline);
+        assert_eq!(sync.status().item_sizes["handshaking_peers"], 0);
+    }
+
+    #[test]
+    fn caps_handshaking_peers() {
+        let mut client = TestBlockChainClient::new();
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut sync = dummy_sync(&client);
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        let start = Instant::now();
+
+        for peer in 0..MAX_HANDSHAKING_PEERS {
+            sync.add_handshaking_peer(&mut io, peer, start + Duration::from_millis(peer as u64));
+        }
+        assert!(io.to_disconnect.is_empty());
+
+        let newest = MAX_HANDSHAKING_PEERS;
+        sync.add_handshaking_peer(&mut io, newest, start + Duration::from_secs(10));
+        assert_eq!(sync.handshaking_peers.len(), MAX_HANDSHAKING_PEERS);
+        assert!(!sync.handshaking_peers.contains_key(&0));
+        assert!(sync.handshaking_peers.contains_key(&newest));
+        assert_eq!(io.to_disconnect, vec![0].into_iter().collect());
+
+        // a known peer doesn't take another slot
+        sync.add_handshaking_peer(&mut io, newest, start + Duration::from_secs(11));
+        assert_eq!(sync.handshaking_peers.len(), MAX_HANDSHAKING_PEERS);
+        assert_eq!(io.to_disconnect.len(), 1);
+    }
+
+    #[test]
+    fn escalates_recovery_of_stalled_best_block() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        client.set_queue_size(1);
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let (_, transaction_hashes_rx) = crossbeam_channel::unbounded();
+        let config = SyncConfig {
+            stall_block_lag: 5,
+            stall_timeout: Duration::from_secs(0),
+            ..SyncConfig::default()
+        };
+        let mut sync = ChainSync::new(
+            config,
+            &client,
+            ForkFilterApi::new_dummy(&client),
+            transaction_hashes_rx,
+        );
+        insert_dummy_peer(&mut sync, 0, client.chain_info().best_block_hash);
+        // peers advance while our best block stays at #10
+        sync.highest_block = Some(100);
+
+        {
+            // the best block is expected not to advance while the queue is full
+            let mut io = TestIo::new(&mut client, &ss, &queue, None);
+            for _ in 0..3 {
+                sync.maintain_sync(&mut io);
+            }
+            assert_eq!(
+                sync.status().stall_recoveries,
+                StallRecoveryStats::default()
+            );
+        }
+
+        client.set_queue_size(0);
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        let mut escalation = Vec::new();
+        for _ in 0..3 {
+            sync.maintain_sync(&mut io);
+            let stats = sync.status().stall_recoveries;
+            escalation.push((stats.warnings, stats.restarts, stats.peer_reconfirmations));
+        }
+        assert_eq!(escalation, vec![(1, 0, 0), (1, 1, 0), (1, 1, 1)]);
+        assert_eq!(sync.state, SyncState::WaitingPeers);
+        assert_eq!(sync.highest_block, None);
+
+        // the peer has no fork block to confirm, syncing continues right away
+        sync.maintain_sync(&mut io);
+        assert_eq!(sync.state, SyncState::Idle);
+    }
+
+    #[test]
+    fn health_check_reports_peers_and_network_id_mismatches() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut sync = dummy_sync(&client);
+
+        let report = sync.health_check(&client, &ss);
+        assert_eq!(report.findings.len(), 1);
+        assert_eq!(report.findings[0].code, HealthCode::NoPeers);
+        assert_eq!(report.findings[0].severity, HealthSeverity::Error);
+
+        insert_dummy_peer(&mut sync, 0, client.chain_info().best_block_hash);
+        sync.health.note_network_id_mismatch(Instant::now(), 5);
+        {
+            let mut io = TestIo::new(&mut client, &ss, &queue, None);
+            sync.maintain_sync(&mut io);
+        }
+        let report = sync.health_check(&client, &ss);
+        assert_eq!(report.findings.len(), 1);
+        let finding = report.finding(HealthCode::NetworkIdMismatch).unwrap();
+        assert_eq!(finding.severity, HealthSeverity::Info);
+        assert_eq!(finding.detail("network_id"), Some(5));
+    }
 }
@@ -0,0 +1,136 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Number, size and processing time of the packets received from peers, by packet id.
+
+use super::sync_packet::SyncPacket;
+use enum_primitive::FromPrimitive;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Dispatch statistics of one packet id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketDispatchStats {
+    /// Packet id.
+    pub packet_id: u8,
+    /// Name of the packet.
+    pub name: &'static str,
+    /// Packets processed, including the ones which failed to decode.
+    pub count: u64,
+    /// Payload bytes of the processed packets.
+    pub bytes: u64,
+    /// Time spent processing the packets.
+    pub processing_time: Duration,
+    /// Packets delayed because the client was busy, they are processed later.
+    pub delayed: u64,
+    /// Processed packets which failed to decode.
+    pub decode_failures: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    count: AtomicU64,
+    bytes: AtomicU64,
+    nanos: AtomicU64,
+    delayed: AtomicU64,
+    decode_failures: AtomicU64,
+}
+
+/// Per packet id accumulators, updated without holding the sync lock.
+pub struct PacketStats {
+    counters: [Counters; 256],
+}
+
+impl Default for PacketStats {
+    fn default() -> Self {
+        PacketStats {
+            counters: [(); 256].map(|_| Counters::default()),
+        }
+    }
+}
+
+impl PacketStats {
+    /// Account a processed packet.
+    pub fn note_processed(&self, packet_id: u8, bytes: usize, elapsed: Duration) {
+        let counters = &self.counters[packet_id as usize];
+        counters.count.fetch_add(1, Ordering::Relaxed);
+        counters.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        counters
+            .nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Account a packet delayed because the client was busy.
+    pub fn note_delayed(&self, packet_id: u8) {
+        self.counters[packet_id as usize]
+            .delayed
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account a packet which failed to decode.
+    pub fn note_decode_failure(&self, packet_id: u8) {
+        self.counters[packet_id as usize]
+            .decode_failures
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Statistics of the known packets received so far, by packet id.
+    pub fn snapshot(&self) -> Vec<PacketDispatchStats> {
+        self.counters
+            .iter()
+            .enumerate()
+            .filter_map(|(packet_id, counters)| {
+                let packet = SyncPacket::from_usize(packet_id)?;
+                let stats = PacketDispatchStats {
+                    packet_id: packet_id as u8,
+                    name: packet.name(),
+                    count: counters.count.load(Ordering::Relaxed),
+                    bytes: counters.bytes.load(Ordering::Relaxed),
+                    processing_time: Duration::from_nanos(counters.nanos.load(Ordering::Relaxed)),
+                    delayed: counters.delayed.load(Ordering::Relaxed),
+                    decode_failures: counters.decode_failures.load(Ordering::Relaxed),
+                };
+                Some(stats).filter(|s| s.count > 0 || s.delayed > 0)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_received_packets_are_listed() {
+        let stats = PacketStats::default();
+        stats.note_processed(0x04, 100, Duration::from_millis(2));
+        stats.note_processed(0x04, 50, Duration::from_millis(1));
+        stats.note_delayed(0x03);
+        // unknown packet ids are not listed
+        stats.note_processed(0xff, 1, Duration::from_millis(1));
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[0].name, "get_block_headers");
+        assert_eq!((snapshot[0].count, snapshot[0].delayed), (0, 1));
+        assert_eq!(snapshot[1].name, "block_headers");
+        assert_eq!(snapshot[1].count, 2);
+        assert_eq!(snapshot[1].bytes, 150);
+        assert_eq!(snapshot[1].processing_time, Duration::from_millis(3));
+    }
+}
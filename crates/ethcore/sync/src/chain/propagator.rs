@@ -14,8 +14,13 @@
 // You should have received a copy of the GNU General Public License
 // along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::{cmp, collections::HashSet};
+use std::{
+    cmp,
+    collections::HashSet,
+    time::{Instant, SystemTime},
+};
 
+use api::ConsensusPropagation;
 use bytes::Bytes;
 use ethereum_types::H256;
 use fastmap::H256FastSet;
@@ -27,11 +32,8 @@ use types::{blockchain_info::BlockChainInfo, transaction::SignedTransaction};
 
 use super::sync_packet::SyncPacket::{self, *};
 
-use super::{
-    random, ChainSync, ETH_PROTOCOL_VERSION_65, MAX_PEERS_PROPAGATION, MAX_PEER_LAG_PROPAGATION,
-    MAX_TRANSACTION_PACKET_SIZE, MIN_PEERS_PROPAGATION,
-};
-use ethcore_miner::pool::VerifiedTransaction;
+use super::{random, ChainSync, ETH_PROTOCOL_VERSION_65};
+use ethcore_miner::pool::{Priority, ScoredTransaction, VerifiedTransaction};
 use std::sync::Arc;
 
 const NEW_POOLED_HASHES_LIMIT: usize = 4096;
@@ -48,11 +50,14 @@ impl SyncPropagator {
         blocks: &[H256],
         peers: &[PeerId],
     ) -> usize {
+        if sync.observer_mode {
+            return 0;
+        }
         trace!(target: "sync", "Sending NewBlocks to {peers:?}");
         let sent = peers.len();
         let mut send_packet = |io: &mut dyn SyncIo, rlp: Bytes| {
             for peer_id in peers {
-                SyncPropagator::send_packet(io, *peer_id, NewBlockPacket, rlp.clone());
+                SyncPropagator::send_packet(sync, io, *peer_id, NewBlockPacket, rlp.clone());
 
                 if let Some(ref mut peer) = sync.peers.get_mut(peer_id) {
                     peer.latest_hash = chain_info.best_block_hash;
@@ -80,6 +85,9 @@ impl SyncPropagator {
         io: &mut dyn SyncIo,
         peers: &[PeerId],
     ) -> usize {
+        if sync.observer_mode {
+            return 0;
+        }
         trace!(target: "sync", "Sending NewHashes to {peers:?}");
         let last_parent = *io.chain().best_block_header().parent_hash();
         let best_block_hash = chain_info.best_block_hash;
@@ -94,7 +102,7 @@ impl SyncPropagator {
             if let Some(ref mut peer) = sync.peers.get_mut(peer_id) {
                 peer.latest_hash = best_block_hash;
             }
-            SyncPropagator::send_packet(io, *peer_id, NewBlockHashesPacket, rlp.clone());
+            SyncPropagator::send_packet(sync, io, *peer_id, NewBlockHashesPacket, rlp.clone());
         }
         sent
     }
@@ -147,22 +155,29 @@ impl SyncPropagator {
             rlp::encode_list(&all_transactions_hashes.iter().copied().collect::<Vec<_>>());
 
         let block_number = io.chain().chain_info().best_block_number;
+        let now = SystemTime::now();
 
         if are_new {
-            sync.transactions_stats
-                .retain_new(block_number, sync.new_transactions_stats_period);
+            match sync.new_transactions_stats_retention {
+                Some(retention) => sync.transactions_stats.retain_new_since(now, retention),
+                None => sync
+                    .transactions_stats
+                    .retain_new(block_number, sync.new_transactions_stats_period),
+            }
         } else {
             sync.transactions_stats
                 .retain_pending(&all_transactions_hashes);
         }
 
-        let send_packet = |io: &mut dyn SyncIo,
+        let send_packet = |sync: &mut ChainSync,
+                           io: &mut dyn SyncIo,
                            peer_id: PeerId,
                            is_hashes: bool,
                            sent: usize,
                            rlp: Bytes| {
             let size = rlp.len();
             SyncPropagator::send_packet(
+                sync,
                 io,
                 peer_id,
                 if is_hashes {
@@ -190,13 +205,15 @@ impl SyncPropagator {
 				.expect("peer_id is form peers; peers is result of select_peers_for_transactions; select_peers_for_transactions selects peers from self.peers; qed");
 
             let is_hashes = peer_info.protocol_version >= ETH_PROTOCOL_VERSION_65.0;
+            let id = io.peer_session_info(peer_id).and_then(|info| info.id);
 
-            // Send all transactions, if the peer doesn't know about anything
-            if peer_info.last_sent_transactions.is_empty() {
+            // Send all transactions, if the peer doesn't know about anything and they fit a packet
+            if peer_info.last_sent_transactions.is_empty()
+                && (is_hashes || all_transactions_rlp.len() <= sync.max_transaction_packet_size)
+            {
                 // update stats
                 for hash in &all_transactions_hashes {
-                    let id = io.peer_session_info(peer_id).and_then(|info| info.id);
-                    stats.propagated(hash, are_new, id, block_number);
+                    stats.propagated(hash, are_new, peer_id, id, block_number, now);
                 }
                 peer_info.last_sent_transactions = all_transactions_hashes.clone();
 
@@ -207,7 +224,14 @@ impl SyncPropagator {
                         all_transactions_rlp.clone()
                     }
                 };
-                send_packet(io, peer_id, is_hashes, all_transactions_hashes.len(), rlp);
+                send_packet(
+                    sync,
+                    io,
+                    peer_id,
+                    is_hashes,
+                    all_transactions_hashes.len(),
+                    rlp,
+                );
                 sent_to_peers.insert(peer_id);
                 max_sent = cmp::max(max_sent, all_transactions_hashes.len());
                 continue;
@@ -241,7 +265,7 @@ impl SyncPropagator {
                             tx.rlp_append(&mut packet);
                             to_send_new.insert(hash);
                             // this is not hard limit and we are okay with it. Max default tx size is 300k.
-                            if packet.as_raw().len() >= MAX_TRANSACTION_PACKET_SIZE {
+                            if packet.as_raw().len() >= sync.max_transaction_packet_size {
                                 // Maximal packet size reached just proceed with sending
                                 debug!(target: "sync", "Transaction packet size limit reached. Sending incomplete set of {}/{} transactions.", to_send_new.len(), to_send.len());
                                 break;
@@ -254,9 +278,8 @@ impl SyncPropagator {
             };
 
             // Update stats.
-            let id = io.peer_session_info(peer_id).and_then(|info| info.id);
             for hash in &to_send {
-                stats.propagated(hash, are_new, id, block_number);
+                stats.propagated(hash, are_new, peer_id, id, block_number, now);
             }
 
             peer_info.last_sent_transactions = all_transactions_hashes
@@ -264,7 +287,7 @@ impl SyncPropagator {
                 .chain(&to_send)
                 .cloned()
                 .collect();
-            send_packet(io, peer_id, is_hashes, to_send.len(), packet.out());
+            send_packet(sync, io, peer_id, is_hashes, to_send.len(), packet.out());
             sent_to_peers.insert(peer_id);
             max_sent = cmp::max(max_sent, to_send.len());
         }
@@ -273,18 +296,90 @@ impl SyncPropagator {
         sent_to_peers
     }
 
+    /// Sends full bodies of local transactions to every peer which has not received them yet
+    /// or the last time more than the retry interval ago. Transactions which left the pool
+    /// are forgotten.
+    fn propagate_local_transactions(sync: &mut ChainSync, io: &mut dyn SyncIo) -> HashSet<PeerId> {
+        let mut sent_to_peers = HashSet::new();
+        if sync.local_transactions.is_empty() {
+            return sent_to_peers;
+        }
+
+        let mut transactions = Vec::new();
+        for hash in sync.local_transactions.hashes() {
+            match io.chain().transaction(&hash) {
+                Some(tx) => transactions.push(tx),
+                None => sync.local_transactions.remove(&hash),
+            }
+        }
+
+        let now = Instant::now();
+        let stats_now = SystemTime::now();
+        let block_number = io.chain().chain_info().best_block_number;
+        let peers: Vec<PeerId> = sync
+            .tx_relay_peers()
+            .filter(|peer_id| sync.peers[peer_id].can_sync())
+            .collect();
+        for peer_id in peers {
+            let accepts_service_transaction =
+                io.peer_version(peer_id).accepts_service_transaction();
+            let to_send: Vec<&SignedTransaction> = transactions
+                .iter()
+                .map(|tx| tx.signed())
+                .filter(|tx| sync.local_transactions.is_due(&tx.hash(), peer_id, now))
+                .filter(|tx| !tx.tx().gas_price.is_zero() || accepts_service_transaction)
+                .collect();
+            if to_send.is_empty() {
+                continue;
+            }
+
+            let id = io.peer_session_info(peer_id).and_then(|info| info.id);
+            let mut packet = RlpStream::new();
+            packet.begin_unbounded_list();
+            let mut sent = 0;
+            for tx in &to_send {
+                let hash = tx.hash();
+                tx.rlp_append(&mut packet);
+                sent += 1;
+                sync.local_transactions.mark_sent(&hash, peer_id, now);
+                sync.transactions_stats.propagated(
+                    &hash,
+                    true,
+                    peer_id,
+                    id,
+                    block_number,
+                    stats_now,
+                );
+                if packet.as_raw().len() >= sync.max_transaction_packet_size {
+                    debug!(target: "sync", "Transaction packet size limit reached. Sending incomplete set of local transactions.");
+                    break;
+                }
+            }
+            packet.finalize_unbounded_list();
+
+            trace!(target: "sync", "{:02} <- Transactions ({} local entries)", peer_id, sent);
+            SyncPropagator::send_packet(sync, io, peer_id, TransactionsPacket, packet.out());
+            sent_to_peers.insert(peer_id);
+        }
+
+        sent_to_peers
+    }
+
     // t_nb 11.4.1 propagate latest blocks to peers
     pub fn propagate_latest_blocks(sync: &mut ChainSync, io: &mut dyn SyncIo, sealed: &[H256]) {
+        if sync.observer_mode {
+            return;
+        }
         let chain_info = io.chain().chain_info();
         if ((chain_info.best_block_number as i64) - (sync.last_sent_block_number as i64))
             .unsigned_abs()
-            < MAX_PEER_LAG_PROPAGATION
+            < sync.max_peer_lag_propagation
         {
             let peers = sync.get_lagging_peers(&chain_info);
             if sealed.is_empty() {
                 // t_nb 11.4.2
                 let hashes = SyncPropagator::propagate_new_hashes(sync, &chain_info, io, &peers);
-                let peers = ChainSync::select_random_peers(&peers);
+                let peers = sync.select_random_peers(&peers);
                 // t_nb 11.4.3
                 let blocks =
                     SyncPropagator::propagate_blocks(sync, &chain_info, io, sealed, &peers);
@@ -308,22 +403,33 @@ impl SyncPropagator {
         io: &mut dyn SyncIo,
         proposed: &[Bytes],
     ) {
+        if sync.observer_mode {
+            return;
+        }
         let peers = sync.get_consensus_peers();
         trace!(target: "sync", "Sending proposed blocks to {peers:?}");
         for block in proposed {
             let rlp = ChainSync::create_block_rlp(block, io.chain().chain_info().total_difficulty);
             for peer_id in &peers {
-                SyncPropagator::send_packet(io, *peer_id, NewBlockPacket, rlp.clone());
+                SyncPropagator::send_packet(sync, io, *peer_id, NewBlockPacket, rlp.clone());
             }
         }
     }
 
     /// Broadcast consensus message to peers.
     pub fn propagate_consensus_packet(sync: &mut ChainSync, io: &mut dyn SyncIo, packet: Bytes) {
-        let lucky_peers = ChainSync::select_random_peers(&sync.get_consensus_peers());
+        if sync.observer_mode {
+            return;
+        }
+        let peers = sync.get_consensus_peers();
+        // validators all take part in the consensus, don't leave any out
+        let lucky_peers = match sync.consensus_propagation {
+            ConsensusPropagation::All => sync.select_random_peers(&peers),
+            ConsensusPropagation::Validators => peers,
+        };
         trace!(target: "sync", "Sending consensus packet to {lucky_peers:?}");
         for peer_id in lucky_peers {
-            SyncPropagator::send_packet(io, peer_id, ConsensusDataPacket, packet.clone());
+            SyncPropagator::send_packet(sync, io, peer_id, ConsensusDataPacket, packet.clone());
         }
     }
 
@@ -340,29 +446,27 @@ impl SyncPropagator {
             // sqrt(x)/x scaled to max u32
             let fraction =
                 ((sync.peers.len() as f64).powf(-0.5) * (u32::max_value() as f64).round()) as u32;
-            let small = sync.peers.len() < MIN_PEERS_PROPAGATION;
+            let small = sync.peers.len() < sync.min_peers_propagation;
             Box::new(move |_| small || random.next_u32() < fraction)
         };
 
-        sync.peers
-            .keys()
-            .cloned()
+        sync.tx_relay_peers()
             .filter(filter)
             .filter(fraction_filter)
-            .take(MAX_PEERS_PROPAGATION)
+            .take(sync.max_peers_propagation)
             .collect()
     }
 
     /// Generic packet sender
     pub fn send_packet(
-        sync: &mut dyn SyncIo,
+        sync: &mut ChainSync,
+        io: &mut dyn SyncIo,
         peer_id: PeerId,
         packet_id: SyncPacket,
         packet: Bytes,
     ) {
-        if let Err(e) = sync.send(peer_id, packet_id, packet) {
-            debug!(target:"sync", "Error sending packet: {e:?}");
-            sync.disconnect_peer(peer_id);
+        if let Err(e) = io.send(peer_id, packet_id, packet) {
+            sync.on_send_error(io, peer_id, &e);
         }
     }
 
@@ -378,37 +482,42 @@ impl SyncPropagator {
         F: FnMut() -> bool,
         G: Fn(&dyn SyncIo) -> Vec<Arc<VerifiedTransaction>>,
     {
-        // Early out if nobody to send to.
-        if sync.peers.is_empty() {
+        // Early out if nobody to send to or nothing is sent at all.
+        if sync.peers.is_empty() || sync.observer_mode {
             return 0;
         }
 
         let transactions = get_transactions(io);
-        if transactions.is_empty() {
-            return 0;
+        for tx in &transactions {
+            if tx.priority() == Priority::Local {
+                sync.local_transactions.insert(tx.signed().hash());
+            }
         }
 
         if !should_continue() {
             return 0;
         }
 
+        // local transactions are handled separately
+        let mut affected_peers = SyncPropagator::propagate_local_transactions(sync, io);
+
         let (transactions, service_transactions): (Vec<_>, Vec<_>) = transactions
             .iter()
+            .filter(|tx| !sync.local_transactions.contains(&tx.signed().hash()))
             .map(|tx| tx.signed())
             .partition(|tx| !tx.tx().gas_price.is_zero());
 
         // usual transactions could be propagated to all peers
-        let mut affected_peers = HashSet::new();
         if !transactions.is_empty() {
             let peers = SyncPropagator::select_peers_for_transactions(sync, |_| true, are_new);
-            affected_peers = SyncPropagator::propagate_transactions_to_peers(
+            affected_peers.extend(&SyncPropagator::propagate_transactions_to_peers(
                 sync,
                 io,
                 peers,
                 transactions,
                 are_new,
                 &mut should_continue,
-            );
+            ));
         }
 
         // most of times service_transactions will be empty
@@ -440,8 +549,11 @@ mod tests {
     use ethcore::client::{BlockInfo, ChainInfo, EachBlockWith, TestBlockChainClient};
     use parking_lot::RwLock;
     use rlp::Rlp;
-    use std::collections::VecDeque;
-    use tests::{helpers::TestIo, snapshot::TestSnapshotService};
+    use std::collections::{HashSet, VecDeque};
+    use tests::{
+        helpers::{TestIo, TestPacket},
+        snapshot::TestSnapshotService,
+    };
     use types::transaction::TypedTransaction;
 
     use super::{
@@ -472,6 +584,49 @@ mod tests {
         assert_eq!(0x01, io.packets[0].packet_id);
     }
 
+    fn propagate_hashes_failing_with(kind: fn() -> network::ErrorKind) -> (ChainSync, bool) {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(100, EachBlockWith::Uncle);
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = dummy_sync_with_peer(client.block_hash_delta_minus(5), &client);
+        let chain_info = client.chain_info();
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        io.send_failures.insert(0, kind);
+
+        let peers = sync.get_lagging_peers(&chain_info);
+        SyncPropagator::propagate_new_hashes(&mut sync, &chain_info, &mut io, &peers);
+
+        assert!(io.packets.is_empty());
+        let disconnected = io.to_disconnect.contains(&0);
+        (sync, disconnected)
+    }
+
+    #[test]
+    fn gone_peer_is_removed_from_propagation() {
+        let (sync, disconnected) = propagate_hashes_failing_with(|| network::ErrorKind::PeerGone);
+        assert!(!sync.peers.contains_key(&0));
+        assert!(!disconnected);
+        assert_eq!(sync.status().send_errors.peer_gone, 1);
+    }
+
+    #[test]
+    fn full_send_queue_skips_packet() {
+        let (sync, disconnected) =
+            propagate_hashes_failing_with(|| network::ErrorKind::SendQueueFull(1024));
+        assert!(sync.peers.contains_key(&0));
+        assert!(!disconnected);
+        assert_eq!(sync.status().send_errors.send_queue_full, 1);
+    }
+
+    #[test]
+    fn other_send_failure_disconnects_peer() {
+        let (sync, disconnected) =
+            propagate_hashes_failing_with(|| network::ErrorKind::BadProtocol);
+        assert!(disconnected);
+        assert_eq!(sync.status().send_errors.other, 1);
+    }
+
     #[test]
     fn sends_latest_block_to_lagging_peer() {
         let mut client = TestBlockChainClient::new();
@@ -527,13 +682,17 @@ mod tests {
             PeerInfo {
                 // Messaging protocol
                 protocol_version: 2,
+                eth_protocol_version: 0,
+                par_protocol_version: 2,
                 genesis: H256::zero(),
                 network_id: 0,
                 latest_hash: client.block_hash_delta_minus(1),
                 difficulty: None,
+                difficulty_check: DifficultyCheck::Plausible,
                 asking: PeerAsking::Nothing,
                 asking_blocks: Vec::new(),
                 asking_hash: None,
+                asking_headers: 0,
                 unfetched_pooled_transactions: Default::default(),
                 asking_pooled_transactions: Default::default(),
                 ask_time: Instant::now(),
@@ -542,9 +701,13 @@ mod tests {
                 confirmation: ForkConfirmation::Confirmed,
                 snapshot_number: None,
                 snapshot_hash: None,
+                serve_from_block: None,
                 asking_snapshot_data: None,
+                asking_fork_header: None,
                 block_set: None,
-                _client_version: ClientVersion::from(""),
+                client_version: ClientVersion::from(""),
+                node_id: None,
+                fee_market: None,
             },
         );
         let ss = TestSnapshotService::new();
@@ -557,6 +720,120 @@ mod tests {
         assert_eq!(0x07, io.packets[0].packet_id);
     }
 
+    #[test]
+    fn restricts_proposed_blocks_to_validators() {
+        let client = TestBlockChainClient::new();
+        client.add_blocks(2, EachBlockWith::Uncle);
+        let block = client.block(BlockId::Latest).unwrap().into_inner();
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let recipients = |sync: &mut ChainSync, client: &TestBlockChainClient| {
+            let mut io = TestIo::new(client, &ss, &queue, None);
+            SyncPropagator::propagate_proposed_blocks(sync, &mut io, &[block.clone()]);
+            SyncPropagator::propagate_consensus_packet(sync, &mut io, vec![0xc0]);
+            io.packets
+                .iter()
+                .map(|packet| (packet.recipient, packet.packet_id))
+                .collect::<Vec<_>>()
+        };
+
+        let mut sync = dummy_sync(&client);
+        for peer_id in 0..3 {
+            insert_dummy_peer(&mut sync, peer_id, client.block_hash_delta_minus(1));
+            let peer = sync.peers.get_mut(&peer_id).unwrap();
+            peer.protocol_version = PAR_PROTOCOL_VERSION_2.0;
+            peer.node_id = Some(NodeId::from_low_u64_be(peer_id as u64));
+        }
+        sync.set_validator_nodes(vec![NodeId::from_low_u64_be(1)].into_iter().collect());
+
+        // legacy mode reaches everyone
+        let mut sent = recipients(&mut sync, &client);
+        sent.sort();
+        assert_eq!(
+            sent.iter()
+                .filter(|(_, id)| *id == NewBlockPacket.id())
+                .map(|(peer, _)| *peer)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+
+        sync.consensus_propagation = ConsensusPropagation::Validators;
+        let sent = recipients(&mut sync, &client);
+        assert_eq!(
+            sent,
+            vec![(1, NewBlockPacket.id()), (1, ConsensusDataPacket.id())]
+        );
+
+        // nothing is sent without known validators
+        sync.set_validator_nodes(HashSet::new());
+        assert!(recipients(&mut sync, &client).is_empty());
+
+        // the validators notified by the engine are found with the configured node ids
+        let validator = Address::from_low_u64_be(7);
+        sync.set_validator_node_map(
+            vec![(validator, NodeId::from_low_u64_be(2))]
+                .into_iter()
+                .collect(),
+        );
+        assert!(recipients(&mut sync, &client).is_empty());
+        sync.set_validators(vec![validator].into_iter().collect());
+        let sent = recipients(&mut sync, &client);
+        assert_eq!(
+            sent,
+            vec![(2, NewBlockPacket.id()), (2, ConsensusDataPacket.id())]
+        );
+    }
+
+    #[test]
+    fn observer_mode_never_propagates() {
+        let (_, new_transaction_hashes_rx) = crossbeam_channel::unbounded();
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(100, EachBlockWith::Uncle);
+        let tx_hash = client.insert_transaction_to_queue();
+        let hash = client.block_hash(BlockId::Number(99)).unwrap();
+        let block = client.block(BlockId::Latest).unwrap().into_inner();
+        let mut sync = ChainSync::new(
+            SyncConfig {
+                observer_mode: true,
+                ..SyncConfig::default()
+            },
+            &client,
+            ForkFilterApi::new_dummy(&client),
+            new_transaction_hashes_rx,
+        );
+        insert_dummy_peer(&mut sync, 0, client.block_hash_delta_minus(5));
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        sync.chain_new_blocks(&mut io, &[hash], &[], &[hash], &[], &[], &[block]);
+        let peer_count =
+            SyncPropagator::propagate_new_transactions(&mut sync, &mut io, vec![tx_hash], || true);
+        sync.propagate_new_transactions(&mut io);
+        sync.propagate_consensus_packet(&mut io, vec![0xc0]);
+
+        assert_eq!(0, peer_count);
+        assert!(io.packets.is_empty());
+        assert!(sync.status().observer_mode);
+
+        // requests of peers are still served
+        let mut request = RlpStream::new_list(4);
+        request.append(&0u64);
+        request.append(&1u32);
+        request.append(&0u32);
+        request.append(&0u32);
+        io.sender = Some(0);
+        SyncSupplier::dispatch_packet(
+            &RwLock::new(sync),
+            &mut io,
+            0,
+            GetBlockHeadersPacket.id(),
+            &request.out(),
+        );
+        assert_eq!(1, io.packets.len());
+        assert_eq!(BlockHeadersPacket.id(), io.packets[0].packet_id);
+    }
+
     #[test]
     fn propagates_ready_transactions() {
         let mut client = TestBlockChainClient::new();
@@ -602,6 +879,142 @@ mod tests {
         assert_eq!(8, peer_count);
     }
 
+    fn transaction_recipients(packets: &[TestPacket], hash: &H256) -> HashSet<PeerId> {
+        packets
+            .iter()
+            .filter(|p| p.packet_id == 0x02)
+            .filter(|p| {
+                Rlp::new(&p.data)
+                    .iter()
+                    .any(|r| TypedTransaction::decode_rlp(&r).is_ok_and(|tx| tx.hash() == *hash))
+            })
+            .map(|p| p.recipient)
+            .collect()
+    }
+
+    #[test]
+    fn transaction_packets_respect_the_configured_size() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(100, EachBlockWith::Uncle);
+        for _ in 0..3 {
+            client.insert_transaction_to_queue();
+        }
+        let mut sync = dummy_sync_with_peer(client.block_hash_delta_minus(1), &client);
+        // every transaction crosses the size, packets carry a single one
+        sync.max_transaction_packet_size = 1;
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        for _ in 0..3 {
+            SyncPropagator::propagate_ready_transactions(&mut sync, &mut io, || true);
+        }
+
+        assert_eq!(io.packets.len(), 3);
+        for packet in &io.packets {
+            assert_eq!(packet.packet_id, 0x02);
+            assert_eq!(Rlp::new(&packet.data).item_count(), Ok(1));
+        }
+    }
+
+    #[test]
+    fn propagates_local_transactions_to_all_peers() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(100, EachBlockWith::Uncle);
+        let local_hash = client.insert_local_transaction_to_queue();
+        let gossip_hash = client.insert_transaction_to_queue();
+        let latest_hash = client.block_hash_delta_minus(1);
+        let mut sync = dummy_sync(&client);
+        for id in 0..25 {
+            insert_dummy_peer(&mut sync, id, latest_hash)
+        }
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        SyncPropagator::propagate_ready_transactions(&mut sync, &mut io, || true);
+
+        assert_eq!(transaction_recipients(&io.packets, &local_hash).len(), 25);
+        // gossip transactions are still sent to a subset of peers only
+        assert_eq!(transaction_recipients(&io.packets, &gossip_hash).len(), 8);
+
+        // a peer connecting later receives the local transaction as well, unless it cannot sync
+        insert_dummy_peer(&mut sync, 25, latest_hash);
+        insert_dummy_peer(&mut sync, 26, latest_hash);
+        sync.peers.get_mut(&26).unwrap().expired = true;
+        io.packets.clear();
+        SyncPropagator::propagate_ready_transactions(&mut sync, &mut io, || true);
+
+        assert_eq!(
+            transaction_recipients(&io.packets, &local_hash),
+            vec![25].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn reannounces_transactions_of_retracted_blocks_to_all_peers() {
+        use ethcore::{client::ImportBlock, verification::queue::kind::blocks::Unverified};
+        use types::header::Header;
+
+        // imports a child of `parent` including the given transactions
+        fn child_block(
+            client: &TestBlockChainClient,
+            parent: &BlockChainInfo,
+            difficulty: u64,
+            txs: &[H256],
+        ) -> H256 {
+            let mut header = Header::new();
+            header.set_parent_hash(parent.best_block_hash);
+            header.set_number(parent.best_block_number + 1);
+            header.set_difficulty(difficulty.into());
+            let mut rlp = RlpStream::new_list(3);
+            rlp.append(&header);
+            rlp.begin_list(txs.len());
+            for hash in txs {
+                client
+                    .transaction(hash)
+                    .unwrap()
+                    .signed()
+                    .rlp_append(&mut rlp);
+            }
+            rlp.append_raw(&rlp::EMPTY_LIST_RLP, 1);
+            let block = Unverified::from_rlp(rlp.out(), BlockNumber::max_value()).unwrap();
+            client.import_block(block).unwrap()
+        }
+
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(100, EachBlockWith::Uncle);
+        let retracted_only = client.insert_transaction_to_queue();
+        let in_both = client.insert_transaction_to_queue();
+        let parent = client.chain_info();
+        let retracted = child_block(&client, &parent, 1, &[retracted_only, in_both]);
+        let enacted = child_block(&client, &parent, 2, &[in_both]);
+
+        let mut sync = dummy_sync(&client);
+        for id in 0..3 {
+            insert_dummy_peer(&mut sync, id, client.block_hash_delta_minus(1))
+        }
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+        SyncPropagator::propagate_ready_transactions(&mut sync, &mut io, || true);
+        assert_eq!(
+            transaction_recipients(&io.packets, &retracted_only).len(),
+            3
+        );
+        assert_eq!(transaction_recipients(&io.packets, &in_both).len(), 3);
+
+        io.packets.clear();
+        sync.chain_new_blocks(&mut io, &[], &[], &[enacted], &[retracted], &[], &[]);
+        sync.propagate_new_transactions(&mut io);
+
+        assert_eq!(
+            transaction_recipients(&io.packets, &retracted_only),
+            (0..3).collect()
+        );
+        // only the random peer picked for re-broadcasting after new blocks hears it again
+        assert!(transaction_recipients(&io.packets, &in_both).len() <= 1);
+        assert!(sync.retracted_transactions.is_empty());
+    }
+
     #[test]
     fn propagates_new_transactions_to_all_peers() {
         let (new_transaction_hashes_tx, new_transaction_hashes_rx) = crossbeam_channel::unbounded();
@@ -623,6 +1036,73 @@ mod tests {
         assert_eq!(25, peer_count);
     }
 
+    #[test]
+    fn relays_transactions_only_to_peers_above_min_protocol() {
+        let (new_transaction_hashes_tx, new_transaction_hashes_rx) = crossbeam_channel::unbounded();
+
+        let mut client = TestBlockChainClient::new();
+        client.set_new_transaction_hashes_producer(new_transaction_hashes_tx);
+        client.add_blocks(100, EachBlockWith::Uncle);
+        let tx_hash = client.insert_transaction_to_queue();
+        let hash = client.block_hash(BlockId::Number(99)).unwrap();
+        let mut sync = ChainSync::new(
+            SyncConfig {
+                tx_relay_min_protocol: 66,
+                ..SyncConfig::default()
+            },
+            &client,
+            ForkFilterApi::new_dummy(&client),
+            new_transaction_hashes_rx,
+        );
+        for (id, version) in [(0, 63), (1, 66), (2, 66)] {
+            insert_dummy_peer(&mut sync, id, client.block_hash_delta_minus(5));
+            let peer = sync.peers.get_mut(&id).unwrap();
+            peer.protocol_version = version;
+            peer.eth_protocol_version = version;
+        }
+        // the status of a peer speaking PAR carries the PAR version
+        sync.peers.get_mut(&2).unwrap().protocol_version = PAR_PROTOCOL_VERSION_2.0;
+        let chain_info = client.chain_info();
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+        let peer_count =
+            SyncPropagator::propagate_new_transactions(&mut sync, &mut io, vec![tx_hash], || true);
+        assert_eq!(2, peer_count);
+        let recipients = io
+            .packets
+            .iter()
+            .map(|p| p.recipient)
+            .collect::<HashSet<_>>();
+        assert_eq!(recipients, vec![1, 2].into_iter().collect());
+        // NEW_POOLED_TRANSACTION_HASHES_PACKET
+        assert_eq!(
+            0x08,
+            io.packets
+                .iter()
+                .find(|p| p.recipient == 1)
+                .unwrap()
+                .packet_id
+        );
+
+        io.packets.clear();
+        let peers = sync.get_lagging_peers(&chain_info);
+        SyncPropagator::propagate_blocks(&mut sync, &chain_info, &mut io, &[hash], &peers);
+        let recipients = io
+            .packets
+            .iter()
+            .map(|p| (p.recipient, p.packet_id))
+            .collect::<HashSet<_>>();
+        // NEW_BLOCK_PACKET
+        assert!(recipients.contains(&(0, 0x07)));
+        assert!(recipients.contains(&(1, 0x07)));
+
+        let status = sync.status();
+        assert_eq!(status.num_tx_relay_peers, 2);
+        assert_eq!(status.num_sync_only_peers, 1);
+    }
+
     #[test]
     fn propagates_new_transactions() {
         let (new_transaction_hashes_tx, new_transaction_hashes_rx) = crossbeam_channel::unbounded();
@@ -24,6 +24,7 @@ use sync_io::SyncIo;
 use types::BlockNumber;
 
 use super::{
+    branch_probe::BRANCH_PROBE_HEADERS,
     request_id::generate_request_id,
     sync_packet::{SyncPacket::*, *},
 };
@@ -48,6 +49,11 @@ impl SyncRequester {
                     sync, io, peer_id, &start, count, skip, false, block_set,
                 );
             }
+            BlockRequest::HeadersByNumber { start, count, skip } => {
+                SyncRequester::request_headers_by_number(
+                    sync, io, peer_id, start, count, skip, block_set,
+                );
+            }
             BlockRequest::Bodies { hashes } => {
                 SyncRequester::request_bodies(sync, io, peer_id, hashes, block_set);
             }
@@ -83,13 +89,14 @@ impl SyncRequester {
         peer.block_set = Some(set);
     }
 
-    /// Request headers from a peer by block number
+    /// Request the header of the fork `checkpoint` from a peer
     pub fn request_fork_header(
         sync: &mut ChainSync,
         io: &mut dyn SyncIo,
         peer_id: PeerId,
-        n: BlockNumber,
+        checkpoint: (BlockNumber, H256),
     ) {
+        let n = checkpoint.0;
         trace!(target: "sync", "{peer_id} <- GetForkHeader: at {n}");
         let mut rlp = RlpStream::new_list(4);
         rlp.append(&n);
@@ -104,6 +111,80 @@ impl SyncRequester {
             GetBlockHeadersPacket,
             rlp.out(),
         );
+        if let Some(peer) = sync.peers.get_mut(&peer_id) {
+            peer.asking_fork_header = Some(checkpoint);
+        }
+    }
+
+    /// Request the best header of a peer which advertised an implausible total difficulty
+    pub fn request_difficulty_probe(
+        sync: &mut ChainSync,
+        io: &mut dyn SyncIo,
+        peer_id: PeerId,
+        h: &H256,
+    ) {
+        trace!(target: "sync", "{peer_id} <- GetBlockHeaders: {h}, difficulty probe");
+        let mut rlp = RlpStream::new_list(4);
+        rlp.append(h);
+        rlp.append(&1u32);
+        rlp.append(&0u32);
+        rlp.append(&0u32);
+        SyncRequester::send_request(
+            sync,
+            io,
+            peer_id,
+            PeerAsking::DifficultyProbe,
+            GetBlockHeadersPacket,
+            rlp.out(),
+        );
+    }
+
+    /// Request headers backwards from the height of a rejected block, to find out whether
+    /// the peer is on another branch
+    pub fn request_branch_probe(
+        sync: &mut ChainSync,
+        io: &mut dyn SyncIo,
+        peer_id: PeerId,
+        n: BlockNumber,
+    ) {
+        trace!(target: "sync", "{peer_id} <- GetBlockHeaders: {BRANCH_PROBE_HEADERS} entries backwards from {n}, branch probe");
+        let mut rlp = RlpStream::new_list(4);
+        rlp.append(&n);
+        rlp.append(&BRANCH_PROBE_HEADERS);
+        rlp.append(&0u32);
+        rlp.append(&1u32);
+        SyncRequester::send_request(
+            sync,
+            io,
+            peer_id,
+            PeerAsking::BranchProbe,
+            GetBlockHeadersPacket,
+            rlp.out(),
+        );
+    }
+
+    /// Request headers backwards from the given hash for the header backfill
+    pub fn request_backfill_headers(
+        sync: &mut ChainSync,
+        io: &mut dyn SyncIo,
+        peer_id: PeerId,
+        h: &H256,
+        count: u64,
+    ) {
+        trace!(target: "sync", "{peer_id} <- GetBlockHeaders: {count} entries backwards from {h}, backfill");
+        let mut rlp = RlpStream::new_list(4);
+        rlp.append(h);
+        rlp.append(&count);
+        rlp.append(&0u32);
+        rlp.append(&1u32);
+        SyncRequester::send_request(
+            sync,
+            io,
+            peer_id,
+            PeerAsking::BackfillHeaders,
+            GetBlockHeadersPacket,
+            rlp.out(),
+        );
     }
 
     /// Request pooled transactions from a peer
@@ -181,6 +262,36 @@ impl SyncRequester {
         );
         let peer = sync.peers.get_mut(&peer_id).expect("peer_id may originate either from on_packet, where it is already validated or from enumerating self.peers. qed");
         peer.asking_hash = Some(*h);
+        peer.asking_headers += 1;
+        peer.block_set = Some(set);
+    }
+
+    /// Request headers from a peer by block number
+    fn request_headers_by_number(
+        sync: &mut ChainSync,
+        io: &mut dyn SyncIo,
+        peer_id: PeerId,
+        n: BlockNumber,
+        count: u64,
+        skip: u64,
+        set: BlockSet,
+    ) {
+        trace!(target: "sync", "{peer_id} <- GetBlockHeaders: {count} entries starting from #{n}, set = {set:?}");
+        let mut rlp = RlpStream::new_list(4);
+        rlp.append(&n);
+        rlp.append(&count);
+        rlp.append(&skip);
+        rlp.append(&0u32);
+        SyncRequester::send_request(
+            sync,
+            io,
+            peer_id,
+            PeerAsking::BlockHeaders,
+            GetBlockHeadersPacket,
+            rlp.out(),
+        );
+        let peer = sync.peers.get_mut(&peer_id).expect("peer_id may originate either from on_packet, where it is already validated or from enumerating self.peers. qed");
+        peer.asking_headers += 1;
         peer.block_set = Some(set);
     }
 
@@ -240,7 +351,9 @@ impl SyncRequester {
         packet: Bytes,
     ) {
         if let Some(ref mut peer) = sync.peers.get_mut(&peer_id) {
-            if peer.asking != PeerAsking::Nothing {
+            // subchain heads are requested several at a time
+            let pipelined = asking == PeerAsking::BlockHeaders && peer.asking_headers > 0;
+            if peer.asking != PeerAsking::Nothing && !pipelined {
                 warn!(target:"sync", "Asking {:?} while requesting {:?}", peer.asking, asking);
             }
             peer.asking = asking;
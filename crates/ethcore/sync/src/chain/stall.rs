@@ -0,0 +1,185 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Detection of a chain head which stopped advancing while peers move on.
+
+use std::time::{Duration, Instant};
+use types::BlockNumber;
+
+/// Recovery from a stalled chain head, in the order of escalation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StallAction {
+    /// Log the sync state.
+    Warn,
+    /// Restart the block download.
+    Restart,
+    /// Confirm all peers again and wait for confirmed peers.
+    ReconfirmPeers,
+}
+
+const ESCALATION: [StallAction; 3] = [
+    StallAction::Warn,
+    StallAction::Restart,
+    StallAction::ReconfirmPeers,
+];
+
+/// Number of recovery actions taken because the chain head stalled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StallRecoveryStats {
+    /// Stalls reported in the log.
+    pub warnings: usize,
+    /// Sync restarts.
+    pub restarts: usize,
+    /// Peer re-confirmations.
+    pub peer_reconfirmations: usize,
+}
+
+impl StallRecoveryStats {
+    /// Account an action taken.
+    pub fn note(&mut self, action: StallAction) {
+        match action {
+            StallAction::Warn => self.warnings += 1,
+            StallAction::Restart => self.restarts += 1,
+            StallAction::ReconfirmPeers => self.peer_reconfirmations += 1,
+        }
+    }
+}
+
+/// Tracks the progress of the best block. Once peers are at least `min_lag` blocks
+/// ahead and the best block did not advance for `timeout`, one recovery action is
+/// taken per `timeout` until the best block advances again.
+pub struct StallDetector {
+    min_lag: BlockNumber,
+    timeout: Duration,
+    best_block: BlockNumber,
+    since: Instant,
+    actions: usize,
+}
+
+impl StallDetector {
+    /// Create a detector for a chain at `best_block`.
+    pub fn new(min_lag: BlockNumber, timeout: Duration, best_block: BlockNumber) -> Self {
+        StallDetector {
+            min_lag,
+            timeout,
+            best_block,
+            since: Instant::now(),
+            actions: 0,
+        }
+    }
+
+    /// Check for a stall given our best block and the highest block advertised by peers.
+    /// `busy` suppresses detection while the best block is expected not to advance, e.g.
+    /// during snapshot restoration or with a full block queue.
+    pub fn check(
+        &mut self,
+        now: Instant,
+        best_block: BlockNumber,
+        highest_block: Option<BlockNumber>,
+        busy: bool,
+    ) -> Option<StallAction> {
+        let lagging = highest_block.is_some_and(|h| h >= best_block.saturating_add(self.min_lag));
+        if best_block != self.best_block || !lagging || busy {
+            self.best_block = best_block;
+            self.since = now;
+            self.actions = 0;
+            return None;
+        }
+        let due = self.since + self.timeout * (self.actions as u32 + 1);
+        if now < due {
+            return None;
+        }
+        let action = ESCALATION[self.actions];
+        self.actions += 1;
+        if self.actions == ESCALATION.len() {
+            // start over, the peers are given another chance after being confirmed again
+            self.since = now;
+            self.actions = 0;
+        }
+        Some(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn escalates_while_stalled() {
+        let mut detector = StallDetector::new(10, TIMEOUT, 100);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(detector.check(at(0), 100, Some(120), false), None);
+        assert_eq!(detector.check(at(59), 100, Some(130), false), None);
+        assert_eq!(
+            detector.check(at(60), 100, Some(140), false),
+            Some(StallAction::Warn)
+        );
+        assert_eq!(detector.check(at(61), 100, Some(140), false), None);
+        assert_eq!(
+            detector.check(at(120), 100, Some(150), false),
+            Some(StallAction::Restart)
+        );
+        assert_eq!(detector.check(at(179), 100, Some(150), false), None);
+        assert_eq!(
+            detector.check(at(180), 100, Some(160), false),
+            Some(StallAction::ReconfirmPeers)
+        );
+        // another round after the peers were confirmed again
+        assert_eq!(detector.check(at(239), 100, Some(160), false), None);
+        assert_eq!(
+            detector.check(at(240), 100, Some(160), false),
+            Some(StallAction::Warn)
+        );
+    }
+
+    #[test]
+    fn progress_resets_detection() {
+        let mut detector = StallDetector::new(10, TIMEOUT, 100);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(detector.check(at(0), 100, Some(120), false), None);
+        assert_eq!(detector.check(at(50), 101, Some(120), false), None);
+        assert_eq!(detector.check(at(100), 101, Some(120), false), None);
+        assert_eq!(
+            detector.check(at(110), 101, Some(120), false),
+            Some(StallAction::Warn)
+        );
+    }
+
+    #[test]
+    fn no_action_while_peers_close_or_busy() {
+        let mut detector = StallDetector::new(10, TIMEOUT, 100);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(detector.check(at(0), 100, Some(109), false), None);
+        assert_eq!(detector.check(at(100), 100, Some(109), false), None);
+        assert_eq!(detector.check(at(200), 100, None, false), None);
+        for secs in 300..310 {
+            assert_eq!(detector.check(at(secs * 60), 100, Some(200), true), None);
+        }
+        assert_eq!(detector.check(at(18_599), 100, Some(200), false), None);
+        assert_eq!(
+            detector.check(at(18_600), 100, Some(200), false),
+            Some(StallAction::Warn)
+        );
+    }
+}
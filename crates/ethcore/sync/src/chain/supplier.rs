@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
 
+use api::PAR_PROTOCOL;
 use bytes::Bytes;
 
 #[cfg(not(test))]
@@ -23,26 +24,31 @@ pub const PAYLOAD_SOFT_LIMIT: usize = 100_000;
 
 use enum_primitive::FromPrimitive;
 use ethereum_types::H256;
-use network::{self, PeerId};
+use network::{self, chunking, PeerId};
 use parking_lot::RwLock;
 use rlp::{Rlp, RlpStream};
-use std::cmp;
+use std::{cmp, time::Instant};
 use types::{ids::BlockId, BlockNumber};
 
 use sync_io::SyncIo;
 
 use super::{
+    compression,
     request_id::{prepend_request_id, strip_request_id, RequestId},
     sync_packet::{PacketInfo, SyncPacket, SyncPacket::*},
 };
 
 use super::{
     ChainSync, PacketProcessError, RlpResponseResult, SyncHandler, MAX_BODIES_TO_SEND,
-    MAX_HEADERS_TO_SEND, MAX_RECEIPTS_HEADERS_TO_SEND,
+    MAX_HEADERS_TO_SEND, MAX_RECEIPTS_HEADERS_TO_SEND, PAR_PROTOCOL_VERSION_3,
+    PAR_PROTOCOL_VERSION_4,
 };
 use chain::MAX_NODE_DATA_TO_SEND;
 use std::borrow::Borrow;
 
+/// Size of the RLP header wrapping a snapshot data part of up to 16MB.
+const SNAPSHOT_DATA_PART_RLP_OVERHEAD: usize = 4;
+
 /// The Chain Sync Supplier: answers requests from peers with available data
 pub struct SyncSupplier;
 
@@ -58,7 +64,10 @@ impl SyncSupplier {
         data: &[u8],
     ) {
         if let Some(id) = SyncPacket::from_u8(packet_id) {
+            let started = Instant::now();
             let rlp_result = strip_request_id(data, sync.read().borrow(), &peer, &id);
+            let serve_snapshots = sync.read().serves_snapshots();
+            let serve_from_block = sync.read().serve_from_block();
 
             let result = match rlp_result {
                 Ok((rlp, request_id)) => match id {
@@ -71,12 +80,15 @@ impl SyncSupplier {
                         |e| format!("Error sending pooled transactions: {e:?}"),
                     ),
 
-                    GetBlockBodiesPacket => SyncSupplier::return_rlp(
+                    GetBlockBodiesPacket => SyncSupplier::return_rlp_compressible(
+                        sync,
                         io,
                         &rlp,
                         peer,
                         request_id,
-                        SyncSupplier::return_block_bodies,
+                        |io, rlp, peer| {
+                            SyncSupplier::return_block_bodies(io, rlp, peer, serve_from_block)
+                        },
                         |e| format!("Error sending block bodies: {e:?}"),
                     ),
 
@@ -85,7 +97,9 @@ impl SyncSupplier {
                         &rlp,
                         peer,
                         request_id,
-                        SyncSupplier::return_block_headers,
+                        |io, rlp, peer| {
+                            SyncSupplier::return_block_headers(io, rlp, peer, serve_from_block)
+                        },
                         |e| format!("Error sending block headers: {e:?}"),
                     ),
 
@@ -98,15 +112,29 @@ impl SyncSupplier {
                         |e| format!("Error sending node data: {e:?}"),
                     ),
 
-                    GetReceiptsPacket => SyncSupplier::return_rlp(
+                    GetReceiptsPacket => SyncSupplier::return_rlp_compressible(
+                        sync,
                         io,
                         &rlp,
                         peer,
                         request_id,
-                        SyncSupplier::return_receipts,
+                        |io, rlp, peer| {
+                            SyncSupplier::return_receipts(io, rlp, peer, serve_from_block)
+                        },
                         |e| format!("Error sending receipts: {e:?}"),
                     ),
 
+                    GetSnapshotManifestPacket | GetSnapshotDataPacket if !serve_snapshots => {
+                        SyncSupplier::return_rlp(
+                            io,
+                            &rlp,
+                            peer,
+                            request_id,
+                            |_, _, peer_id| SyncSupplier::return_snapshot_refused(peer_id, id),
+                            |e| format!("Error sending empty snapshot response: {e:?}"),
+                        )
+                    }
+
                     GetSnapshotManifestPacket => SyncSupplier::return_rlp(
                         io,
                         &rlp,
@@ -116,7 +144,7 @@ impl SyncSupplier {
                         |e| format!("Error sending snapshot manifest: {e:?}"),
                     ),
 
-                    GetSnapshotDataPacket => SyncSupplier::return_rlp(
+                    GetSnapshotDataPacket => SyncSupplier::return_rlp_in_parts(
                         io,
                         &rlp,
                         peer,
@@ -146,8 +174,7 @@ impl SyncSupplier {
                                 };
                                 if res.is_err() {
                                     // peer sent invalid data, disconnect.
-                                    io.disable_peer(peer);
-                                    sync.write().deactivate_peer(io, peer);
+                                    sync.write().disable_peer(io, peer);
                                 }
                             }
                             _ => {
@@ -160,15 +187,7 @@ impl SyncSupplier {
                 Err(e) => Err(e.into()),
             };
 
-            match result {
-                Err(PacketProcessError::Decoder(e)) => {
-                    debug!(target:"sync", "{peer} -> Malformed packet {packet_id} : {e}")
-                }
-                Err(PacketProcessError::ClientBusy) => {
-                    sync.write().add_delayed_request(peer, packet_id, data)
-                }
-                Ok(()) => {}
-            }
+            SyncSupplier::note_result(sync, io, peer, packet_id, data, started, result);
         }
     }
 
@@ -182,7 +201,9 @@ impl SyncSupplier {
         data: &[u8],
     ) {
         if let Some(id) = SyncPacket::from_u8(packet_id) {
+            let started = Instant::now();
             let rlp_result = strip_request_id(data, sync.read().borrow(), &peer, &id);
+            let serve_from_block = sync.read().serve_from_block();
 
             let result = match rlp_result {
                 Ok((rlp, request_id)) => match id {
@@ -191,7 +212,9 @@ impl SyncSupplier {
                         &rlp,
                         peer,
                         request_id,
-                        SyncSupplier::return_block_headers,
+                        |io, rlp, peer| {
+                            SyncSupplier::return_block_headers(io, rlp, peer, serve_from_block)
+                        },
                         |e| format!("Error sending block headers: {e:?}"),
                     ),
 
@@ -203,36 +226,72 @@ impl SyncSupplier {
                 Err(e) => Err(e.into()),
             };
 
-            match result {
-                Err(PacketProcessError::Decoder(e)) => {
-                    debug!(target:"sync", "{peer} -> Malformed packet {packet_id} : {e}")
-                }
-                Err(PacketProcessError::ClientBusy) => {
-                    sync.write().add_delayed_request(peer, packet_id, data)
-                }
-                Ok(()) => {}
-            }
+            SyncSupplier::note_result(sync, io, peer, packet_id, data, started, result);
         }
     }
 
-    /// Respond to GetBlockHeaders request
-    fn return_block_headers(io: &dyn SyncIo, r: &Rlp, peer_id: PeerId) -> RlpResponseResult {
-        // Cannot return blocks, if forks processing is in progress,
-        // The request should be postponed for later processing
-        if io.chain().is_processing_fork() {
-            return Err(PacketProcessError::ClientBusy);
+    // Handle the outcome of a dispatched packet and account it.
+    fn note_result(
+        sync: &RwLock<ChainSync>,
+        io: &mut dyn SyncIo,
+        peer: PeerId,
+        packet_id: u8,
+        data: &[u8],
+        started: Instant,
+        result: Result<(), PacketProcessError>,
+    ) {
+        let stats = sync.read().packet_stats.clone();
+        match result {
+            Err(PacketProcessError::ClientBusy) => {
+                stats.note_delayed(packet_id);
+                sync.write().add_delayed_request(peer, packet_id, data);
+                return;
+            }
+            Err(PacketProcessError::Decoder(e)) => {
+                debug!(target:"sync", "{peer} -> Malformed packet {packet_id} : {e}");
+                stats.note_decode_failure(packet_id);
+            }
+            Err(PacketProcessError::Send(e)) => sync.write().on_send_error(io, peer, &e),
+            Ok(()) => {}
         }
+        stats.note_processed(packet_id, data.len(), started.elapsed());
+    }
+
+    /// Respond to GetBlockHeaders request, without headers of blocks below `serve_from_block`
+    fn return_block_headers(
+        io: &dyn SyncIo,
+        r: &Rlp,
+        peer_id: PeerId,
+        serve_from_block: Option<BlockNumber>,
+    ) -> RlpResponseResult {
         // Packet layout:
         // [ block: { P , B_32 }, maxHeaders: P, skip: P, reverse: P in { 0 , 1 } ]
         let max_headers: usize = r.val_at(1)?;
         let skip: usize = r.val_at(2)?;
         let reverse: bool = r.val_at(3)?;
+        let floor = serve_from_block.unwrap_or(0);
+        if r.at(0)?.size() != 32 {
+            let number = r.val_at::<BlockNumber>(0)?;
+            if number < floor && !io.chain_overlay().read().contains_key(&number) {
+                trace!(target: "sync", "{peer_id} -> GetBlockHeaders: #{number} is below the served range");
+                return Ok(Some((BlockHeadersPacket, RlpStream::new_list(0))));
+            }
+        }
+        // Cannot return blocks, if forks processing is in progress,
+        // The request should be postponed for later processing
+        if io.chain().is_processing_fork() {
+            return Err(PacketProcessError::ClientBusy);
+        }
         let last = io.chain().chain_info().best_block_number;
         let number = if r.at(0)?.size() == 32 {
             // id is a hash
             let hash: H256 = r.val_at(0)?;
             trace!(target: "sync", "{peer_id} -> GetBlockHeaders (hash: {hash}, max: {max_headers}, skip: {skip}, reverse:{reverse})");
             match io.chain().block_header(BlockId::Hash(hash)) {
+                Some(hdr) if hdr.number() < floor => {
+                    trace!(target: "sync", "{peer_id} -> GetBlockHeaders: {hash} is below the served range");
+                    return Ok(Some((BlockHeadersPacket, RlpStream::new_list(0))));
+                }
                 Some(hdr) => {
                     let number = hdr.number();
                     debug_assert_eq!(hdr.hash(), hash);
@@ -271,7 +330,9 @@ impl SyncSupplier {
         // We are checking the `overlay` as well since it's where the ForkBlock
         // header is cached : so peers can confirm we are on the right fork,
         // even if we are not synced until the fork block
-        while (number <= last || overlay.contains_key(&number)) && count < max_count {
+        while ((number <= last && number >= floor) || overlay.contains_key(&number))
+            && count < max_count
+        {
             if let Some(hdr) = overlay.get(&number) {
                 trace!(target: "sync", "{peer_id}: Returning cached fork header");
                 data.extend_from_slice(hdr);
@@ -324,8 +385,13 @@ impl SyncSupplier {
         Ok(Some((PooledTransactionsPacket, rlp)))
     }
 
-    /// Respond to GetBlockBodies request
-    fn return_block_bodies(io: &dyn SyncIo, r: &Rlp, peer_id: PeerId) -> RlpResponseResult {
+    /// Respond to GetBlockBodies request, skipping bodies of blocks below `serve_from_block`
+    fn return_block_bodies(
+        io: &dyn SyncIo,
+        r: &Rlp,
+        peer_id: PeerId,
+        serve_from_block: Option<BlockNumber>,
+    ) -> RlpResponseResult {
         let mut count = r.item_count().unwrap_or(0);
         if count == 0 {
             debug!(target: "sync", "Empty GetBlockBodies request, ignoring.");
@@ -335,7 +401,11 @@ impl SyncSupplier {
         let mut added = 0usize;
         let mut data = Bytes::new();
         for i in 0..count {
-            if let Some(body) = io.chain().block_body(BlockId::Hash(r.val_at::<H256>(i)?)) {
+            let hash = r.val_at::<H256>(i)?;
+            if SyncSupplier::below_served_range(io, &hash, serve_from_block) {
+                continue;
+            }
+            if let Some(body) = io.chain().block_body(BlockId::Hash(hash)) {
                 data.append(&mut body.into_inner());
                 added += 1;
                 // Check that the packet won't be oversized
@@ -381,7 +451,12 @@ impl SyncSupplier {
         Ok(Some((NodeDataPacket, rlp)))
     }
 
-    fn return_receipts(io: &dyn SyncIo, rlp: &Rlp, peer_id: PeerId) -> RlpResponseResult {
+    fn return_receipts(
+        io: &dyn SyncIo,
+        rlp: &Rlp,
+        peer_id: PeerId,
+        serve_from_block: Option<BlockNumber>,
+    ) -> RlpResponseResult {
         let mut count = rlp.item_count().unwrap_or(0);
         trace!(target: "sync", "{peer_id} -> GetReceipts: {count} entries");
         if count == 0 {
@@ -393,7 +468,11 @@ impl SyncSupplier {
         let mut data = Bytes::new();
         let mut total_bytes = 0;
         for i in 0..count {
-            if let Some(receipts) = io.chain().block_receipts(&rlp.val_at::<H256>(i)?) {
+            let hash = rlp.val_at::<H256>(i)?;
+            if SyncSupplier::below_served_range(io, &hash, serve_from_block) {
+                continue;
+            }
+            if let Some(receipts) = io.chain().block_receipts(&hash) {
                 let mut receipts_bytes = ::rlp::encode(&receipts);
                 total_bytes += receipts_bytes.len();
                 if total_bytes > PAYLOAD_SOFT_LIMIT {
@@ -408,6 +487,19 @@ impl SyncSupplier {
         Ok(Some((ReceiptsPacket, rlp_result)))
     }
 
+    /// Returns true if the block is known to be below `serve_from_block`.
+    fn below_served_range(
+        io: &dyn SyncIo,
+        hash: &H256,
+        serve_from_block: Option<BlockNumber>,
+    ) -> bool {
+        serve_from_block.is_some_and(|floor| {
+            io.chain()
+                .block_number(BlockId::Hash(*hash))
+                .is_some_and(|number| number < floor)
+        })
+    }
+
     /// Respond to GetSnapshotManifest request
     fn return_snapshot_manifest(io: &dyn SyncIo, r: &Rlp, peer_id: PeerId) -> RlpResponseResult {
         let count = r.item_count().unwrap_or(0);
@@ -450,6 +542,16 @@ impl SyncSupplier {
         Ok(Some((SnapshotDataPacket, rlp)))
     }
 
+    /// Respond to GetSnapshotManifest/GetSnapshotData with an empty reply when snapshots are not served
+    fn return_snapshot_refused(peer_id: PeerId, request: SyncPacket) -> RlpResponseResult {
+        trace!(target: "warp", "{peer_id}: Not serving snapshots, refusing {request:?}");
+        let packet = match request {
+            GetSnapshotDataPacket => SnapshotDataPacket,
+            _ => SnapshotManifestPacket,
+        };
+        Ok(Some((packet, RlpStream::new_list(0))))
+    }
+
     fn return_rlp<FRlp, FError>(
         io: &mut dyn SyncIo,
         rlp: &Rlp,
@@ -460,17 +562,107 @@ impl SyncSupplier {
     ) -> Result<(), PacketProcessError>
     where
         FRlp: Fn(&dyn SyncIo, &Rlp, PeerId) -> RlpResponseResult,
-        FError: FnOnce(network::Error) -> String,
+        FError: FnOnce(&network::Error) -> String,
     {
         let response = rlp_func(io, rlp, peer);
         if let Some((packet_id, rlp_stream)) = response? {
             let rlp_stream = prepend_request_id(rlp_stream, request_id);
             io.respond(packet_id.id(), rlp_stream.out())
-                .unwrap_or_else(|e| debug!(target: "sync", "{:?}", error_func(e)));
+                .map_err(|e| SyncSupplier::send_failed(e, error_func))?;
+        }
+        Ok(())
+    }
+
+    /// Same as `return_rlp`, but sends the compressed counterpart of the response
+    /// instead if the peer negotiated `PAR_PROTOCOL_VERSION_3` and compression pays off.
+    fn return_rlp_compressible<FRlp, FError>(
+        sync: &RwLock<ChainSync>,
+        io: &mut dyn SyncIo,
+        rlp: &Rlp,
+        peer: PeerId,
+        request_id: Option<RequestId>,
+        rlp_func: FRlp,
+        error_func: FError,
+    ) -> Result<(), PacketProcessError>
+    where
+        FRlp: Fn(&dyn SyncIo, &Rlp, PeerId) -> RlpResponseResult,
+        FError: FnOnce(&network::Error) -> String,
+    {
+        let response = rlp_func(io, rlp, peer);
+        if let Some((packet_id, rlp_stream)) = response? {
+            let data = prepend_request_id(rlp_stream, request_id).out();
+            let compressed = match compression::compressed_packet(packet_id) {
+                Some(compressed_id)
+                    if io.protocol_version(PAR_PROTOCOL, peer) >= PAR_PROTOCOL_VERSION_3.0 =>
+                {
+                    compression::compress(&data).map(|c| (compressed_id, c))
+                }
+                _ => None,
+            };
+            let result = match compressed {
+                Some((compressed_id, compressed)) => {
+                    trace!(target: "sync", "{peer} <- {compressed_id:?}: {} bytes compressed to {}", data.len(), compressed.len());
+                    sync.write()
+                        .on_compressed_packet_sent(data.len(), compressed.len());
+                    io.send(peer, compressed_id, compressed)
+                }
+                None => io.respond(packet_id.id(), data),
+            };
+            result.map_err(|e| SyncSupplier::send_failed(e, error_func))?;
+        }
+        Ok(())
+    }
+
+    /// Same as `return_rlp`, but splits the response into `SnapshotDataPartPacket`s if it
+    /// exceeds the payload limit of the session and the peer negotiated `PAR_PROTOCOL_VERSION_4`.
+    fn return_rlp_in_parts<FRlp, FError>(
+        io: &mut dyn SyncIo,
+        rlp: &Rlp,
+        peer: PeerId,
+        request_id: Option<RequestId>,
+        rlp_func: FRlp,
+        error_func: FError,
+    ) -> Result<(), PacketProcessError>
+    where
+        FRlp: Fn(&dyn SyncIo, &Rlp, PeerId) -> RlpResponseResult,
+        FError: FnOnce(&network::Error) -> String,
+    {
+        let response = rlp_func(io, rlp, peer);
+        if let Some((packet_id, rlp_stream)) = response? {
+            let data = prepend_request_id(rlp_stream, request_id).out();
+            let max_payload_size = io.peer_max_payload_size(peer);
+            let result = if data.len() > max_payload_size
+                && io.protocol_version(PAR_PROTOCOL, peer) >= PAR_PROTOCOL_VERSION_4.0
+            {
+                chunking::split(
+                    &data,
+                    max_payload_size.saturating_sub(SNAPSHOT_DATA_PART_RLP_OVERHEAD),
+                )
+                .and_then(|parts| {
+                    trace!(target: "sync", "{peer} <- {packet_id:?}: {} bytes split into {} parts", data.len(), parts.len());
+                    parts.into_iter().try_for_each(|part| {
+                        io.send(peer, SnapshotDataPartPacket, ::rlp::encode(&part))
+                    })
+                })
+            } else {
+                io.respond(packet_id.id(), data)
+            };
+            result.map_err(|e| SyncSupplier::send_failed(e, error_func))?;
         }
         Ok(())
     }
 
+    /// Log a response which could not be sent, unless its session simply expired.
+    fn send_failed<FError>(e: network::Error, error_func: FError) -> PacketProcessError
+    where
+        FError: FnOnce(&network::Error) -> String,
+    {
+        if !matches!(*e.kind(), network::ErrorKind::SessionExpired) {
+            debug!(target: "sync", "{:?}", error_func(&e));
+        }
+        PacketProcessError::Send(e)
+    }
+
     fn send_rlp<FRlp, FError>(
         io: &mut dyn SyncIo,
         rlp: &Rlp,
@@ -481,7 +673,7 @@ impl SyncSupplier {
     ) -> Result<(), PacketProcessError>
     where
         FRlp: Fn(&dyn SyncIo, &Rlp, PeerId) -> RlpResponseResult,
-        FError: FnOnce(network::Error) -> String,
+        FError: FnOnce(&network::Error) -> String,
     {
         let response = rlp_func(io, rlp, peer);
         match response {
@@ -489,7 +681,7 @@ impl SyncSupplier {
             Ok(Some((packet_id, rlp_stream))) => {
                 let rlp_stream = prepend_request_id(rlp_stream, request_id);
                 io.send(peer, packet_id, rlp_stream.out())
-                    .unwrap_or_else(|e| debug!(target: "sync", "{:?}", error_func(e)));
+                    .map_err(|e| SyncSupplier::send_failed(e, error_func))?;
                 Ok(())
             }
             _ => Ok(()),
@@ -502,14 +694,22 @@ mod test {
     use super::{super::tests::*, *};
     use blocks::SyncHeader;
     use bytes::Bytes;
+    use chain::{SyncStatus, PAR_PROTOCOL_VERSION_2};
     use ethcore::{
         client::{BlockChainClient, EachBlockWith, TestBlockChainClient},
+        snapshot::SnapshotService,
         spec::Spec,
     };
     use ethereum_types::H256;
+    use network::{PacketId, ProtocolId, SessionInfo};
     use parking_lot::RwLock;
     use rlp::{Rlp, RlpStream};
-    use std::{collections::VecDeque, str::FromStr};
+    use std::{
+        cell::Cell,
+        collections::{HashMap, VecDeque},
+        str::FromStr,
+        time::Duration,
+    };
     use tests::{helpers::TestIo, snapshot::TestSnapshotService};
 
     #[test]
@@ -573,12 +773,14 @@ mod test {
             &io,
             &Rlp::new(&make_hash_req(&unknown, 1, 0, false)),
             0,
+            None,
         );
         assert!(to_header_vec(result, eip1559_transition).is_empty(),);
         let result = SyncSupplier::return_block_headers(
             &io,
             &Rlp::new(&make_hash_req(&unknown, 1, 0, true)),
             0,
+            None,
         );
         assert!(to_header_vec(result, eip1559_transition).is_empty());
 
@@ -586,6 +788,7 @@ mod test {
             &io,
             &Rlp::new(&make_hash_req(&hashes[2], 1, 0, true)),
             0,
+            None,
         );
         assert_eq!(
             to_header_vec(result, eip1559_transition),
@@ -596,6 +799,7 @@ mod test {
             &io,
             &Rlp::new(&make_hash_req(&hashes[2], 1, 0, false)),
             0,
+            None,
         );
         assert_eq!(
             to_header_vec(result, eip1559_transition),
@@ -606,6 +810,7 @@ mod test {
             &io,
             &Rlp::new(&make_hash_req(&hashes[50], 3, 5, false)),
             0,
+            None,
         );
         assert_eq!(
             to_header_vec(result, eip1559_transition),
@@ -620,6 +825,7 @@ mod test {
             &io,
             &Rlp::new(&make_hash_req(&hashes[50], 3, 5, true)),
             0,
+            None,
         );
         assert_eq!(
             to_header_vec(result, eip1559_transition),
@@ -630,22 +836,34 @@ mod test {
             ]
         );
 
-        let result =
-            SyncSupplier::return_block_headers(&io, &Rlp::new(&make_num_req(2, 1, 0, true)), 0);
+        let result = SyncSupplier::return_block_headers(
+            &io,
+            &Rlp::new(&make_num_req(2, 1, 0, true)),
+            0,
+            None,
+        );
         assert_eq!(
             to_header_vec(result, eip1559_transition),
             vec![headers[2].clone()]
         );
 
-        let result =
-            SyncSupplier::return_block_headers(&io, &Rlp::new(&make_num_req(2, 1, 0, false)), 0);
+        let result = SyncSupplier::return_block_headers(
+            &io,
+            &Rlp::new(&make_num_req(2, 1, 0, false)),
+            0,
+            None,
+        );
         assert_eq!(
             to_header_vec(result, eip1559_transition),
             vec![headers[2].clone()]
         );
 
-        let result =
-            SyncSupplier::return_block_headers(&io, &Rlp::new(&make_num_req(50, 3, 5, false)), 0);
+        let result = SyncSupplier::return_block_headers(
+            &io,
+            &Rlp::new(&make_num_req(50, 3, 5, false)),
+            0,
+            None,
+        );
         assert_eq!(
             to_header_vec(result, eip1559_transition),
             vec![
@@ -655,8 +873,12 @@ mod test {
             ]
         );
 
-        let result =
-            SyncSupplier::return_block_headers(&io, &Rlp::new(&make_num_req(50, 3, 5, true)), 0);
+        let result = SyncSupplier::return_block_headers(
+            &io,
+            &Rlp::new(&make_num_req(50, 3, 5, true)),
+            0,
+            None,
+        );
         assert_eq!(
             to_header_vec(result, eip1559_transition),
             vec![
@@ -667,6 +889,127 @@ mod test {
         );
     }
 
+    /// Wraps `TestIo`, counting how often the client is accessed.
+    struct CountingIo<'p> {
+        io: TestIo<'p, TestBlockChainClient>,
+        chain_calls: Cell<usize>,
+    }
+
+    impl<'p> SyncIo for CountingIo<'p> {
+        fn disable_peer(&mut self, peer_id: PeerId) {
+            self.io.disable_peer(peer_id)
+        }
+
+        fn ban_peer(&mut self, peer_id: PeerId, reason: &str) {
+            self.io.ban_peer(peer_id, reason)
+        }
+
+        fn disconnect_peer(&mut self, peer_id: PeerId) {
+            self.io.disconnect_peer(peer_id)
+        }
+
+        fn avoid_peer(&mut self, peer_id: PeerId) {
+            self.io.avoid_peer(peer_id)
+        }
+
+        fn respond(&mut self, packet_id: PacketId, data: Vec<u8>) -> Result<(), network::Error> {
+            self.io.respond(packet_id, data)
+        }
+
+        fn send(
+            &mut self,
+            peer_id: PeerId,
+            packet_id: SyncPacket,
+            data: Vec<u8>,
+        ) -> Result<(), network::Error> {
+            self.io.send(peer_id, packet_id, data)
+        }
+
+        fn send_protocol(
+            &mut self,
+            protocol: ProtocolId,
+            peer_id: PeerId,
+            packet_id: PacketId,
+            data: Vec<u8>,
+        ) -> Result<(), network::Error> {
+            self.io.send_protocol(protocol, peer_id, packet_id, data)
+        }
+
+        fn chain(&self) -> &dyn BlockChainClient {
+            self.chain_calls.set(self.chain_calls.get() + 1);
+            self.io.chain()
+        }
+
+        fn snapshot_service(&self) -> &dyn SnapshotService {
+            self.io.snapshot_service()
+        }
+
+        fn peer_session_info(&self, peer_id: PeerId) -> Option<SessionInfo> {
+            self.io.peer_session_info(peer_id)
+        }
+
+        fn protocol_version(&self, protocol: ProtocolId, peer_id: PeerId) -> u8 {
+            self.io.protocol_version(protocol, peer_id)
+        }
+
+        fn is_expired(&self) -> bool {
+            self.io.is_expired()
+        }
+
+        fn chain_overlay(&self) -> &RwLock<HashMap<BlockNumber, Bytes>> {
+            self.io.chain_overlay()
+        }
+    }
+
+    #[test]
+    fn return_block_headers_below_serve_from_block() {
+        fn make_num_req(n: usize, count: usize, reverse: bool) -> Bytes {
+            let mut rlp = RlpStream::new_list(4);
+            rlp.append(&n);
+            rlp.append(&count);
+            rlp.append(&0usize);
+            rlp.append(&if reverse { 1u32 } else { 0u32 });
+            rlp.out()
+        }
+        fn item_count(rlp: ::chain::RlpResponseResult) -> usize {
+            Rlp::new(&rlp.unwrap().unwrap().1.out())
+                .item_count()
+                .unwrap()
+        }
+
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(100, EachBlockWith::Nothing);
+        let queue = RwLock::new(VecDeque::new());
+        let ss = TestSnapshotService::new();
+        let io = CountingIo {
+            io: TestIo::new(&mut client, &ss, &queue, None),
+            chain_calls: Cell::new(0),
+        };
+
+        // answered without touching the client
+        let result = SyncSupplier::return_block_headers(
+            &io,
+            &Rlp::new(&make_num_req(10, 5, false)),
+            0,
+            Some(50),
+        );
+        assert_eq!(item_count(result), 0);
+        assert_eq!(io.chain_calls.get(), 0);
+
+        // reverse request crossing the floor stops at it
+        let result = SyncSupplier::return_block_headers(
+            &io,
+            &Rlp::new(&make_num_req(52, 5, true)),
+            0,
+            Some(50),
+        );
+        assert_eq!(item_count(result), 3);
+
+        let result =
+            SyncSupplier::return_block_headers(&io, &Rlp::new(&make_num_req(52, 5, true)), 0, None);
+        assert_eq!(item_count(result), 5);
+    }
+
     #[test]
     fn respect_packet_limit() {
         let small_num_blocks = 10;
@@ -695,7 +1038,7 @@ mod test {
         let io = TestIo::new(&mut client, &ss, &queue, None);
 
         let small_result =
-            SyncSupplier::return_block_bodies(&io, &Rlp::new(&small_rlp_request.out()), 0);
+            SyncSupplier::return_block_bodies(&io, &Rlp::new(&small_rlp_request.out()), 0, None);
         let small_result = small_result.unwrap().unwrap().1;
         assert_eq!(
             Rlp::new(&small_result.out()).item_count().unwrap(),
@@ -703,7 +1046,7 @@ mod test {
         );
 
         let large_result =
-            SyncSupplier::return_block_bodies(&io, &Rlp::new(&large_rlp_request.out()), 0);
+            SyncSupplier::return_block_bodies(&io, &Rlp::new(&large_rlp_request.out()), 0, None);
         let large_result = large_result.unwrap().unwrap().1;
         assert!(Rlp::new(&large_result.out()).item_count().unwrap() < large_num_blocks);
     }
@@ -715,7 +1058,7 @@ mod test {
         let ss = TestSnapshotService::new();
         let io = TestIo::new(&mut client, &ss, &queue, None);
 
-        let result = SyncSupplier::return_receipts(&io, &Rlp::new(&[0xc0]), 0);
+        let result = SyncSupplier::return_receipts(&io, &Rlp::new(&[0xc0]), 0, None);
 
         assert!(result.is_ok());
     }
@@ -748,7 +1091,8 @@ mod test {
 
         let receipts_request = receipt_list.out();
         // it returns rlp ONLY for hashes started with "f"
-        let result = SyncSupplier::return_receipts(&io, &Rlp::new(&receipts_request.clone()), 0);
+        let result =
+            SyncSupplier::return_receipts(&io, &Rlp::new(&receipts_request.clone()), 0, None);
 
         assert!(result.is_ok());
         let rlp_result = result.unwrap();
@@ -858,4 +1202,217 @@ mod test {
             ]
         );
     }
+
+    fn dispatch_block_bodies_request(
+        par_protocol_version: u8,
+    ) -> (network::PacketId, Bytes, SyncStatus) {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(100, EachBlockWith::UncleAndTransaction);
+        let mut request = RlpStream::new_list(100);
+        for n in 1..=100 {
+            request.append(&client.block_hash(BlockId::Number(n)).unwrap());
+        }
+        let request = prepend_request_id(request, Some(1)).out();
+
+        let queue = RwLock::new(VecDeque::new());
+        let sync = RwLock::new(dummy_sync(&client));
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, Some(0));
+        io.par_protocol_version = par_protocol_version;
+
+        SyncSupplier::dispatch_packet(&sync, &mut io, 0, GetBlockBodiesPacket.id(), &request);
+
+        assert_eq!(io.packets.len(), 1);
+        let status = sync.read().status();
+        (io.packets[0].packet_id, io.packets[0].data.clone(), status)
+    }
+
+    #[test]
+    fn expired_session_response_is_silent() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(100, EachBlockWith::Uncle);
+        let mut request = RlpStream::new_list(4);
+        request.append(&0u64);
+        request.append(&1u32);
+        request.append(&0u32);
+        request.append(&0u32);
+        let request = request.out();
+
+        let queue = RwLock::new(VecDeque::new());
+        let sync = RwLock::new(dummy_sync_with_peer(H256::zero(), &client));
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, Some(0));
+        io.send_failures
+            .insert(0, || network::ErrorKind::SessionExpired);
+
+        SyncSupplier::dispatch_packet(&sync, &mut io, 0, GetBlockHeadersPacket.id(), &request);
+
+        assert!(io.packets.is_empty());
+        assert!(io.to_disconnect.is_empty());
+        assert!(sync.read().peers.contains_key(&0));
+        assert_eq!(sync.read().status().send_errors.session_expired, 1);
+    }
+
+    #[test]
+    fn dispatch_block_bodies_request_compressed_for_par_v3() {
+        let (standard_id, standard, standard_status) =
+            dispatch_block_bodies_request(PAR_PROTOCOL_VERSION_2.0);
+        assert_eq!(standard_id, BlockBodiesPacket.id());
+        assert_eq!(standard_status.num_compressed_packets_sent, 0);
+
+        let (compressed_id, compressed, status) =
+            dispatch_block_bodies_request(PAR_PROTOCOL_VERSION_3.0);
+        assert_eq!(compressed_id, CompressedBlockBodiesPacket.id());
+        assert!(compressed.len() < standard.len());
+        assert_eq!(
+            compression::decompress(&Rlp::new(&compressed)).unwrap(),
+            standard
+        );
+        assert_eq!(status.num_compressed_packets_sent, 1);
+        assert_eq!(
+            status.compressed_bytes_saved,
+            standard.len() - compressed.len()
+        );
+    }
+
+    fn dispatch_snapshot_data_request(
+        par_protocol_version: u8,
+        max_payload_size: usize,
+    ) -> Vec<(PacketId, Bytes)> {
+        let mut client = TestBlockChainClient::new();
+        let ss = TestSnapshotService::new_with_snapshot(2, H256::zero(), 100);
+        let mut request = RlpStream::new_list(1);
+        request.append(&ss.manifest().unwrap().state_hashes[0]);
+
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = dummy_sync(&client);
+        sync.set_serve_snapshots(true);
+        let mut io = TestIo::new(&mut client, &ss, &queue, Some(0));
+        io.par_protocol_version = par_protocol_version;
+        io.max_payload_size = max_payload_size;
+
+        SyncSupplier::dispatch_packet(
+            &RwLock::new(sync),
+            &mut io,
+            0usize,
+            GetSnapshotDataPacket.id(),
+            &request.out(),
+        );
+
+        io.packets
+            .iter()
+            .map(|packet| (packet.packet_id, packet.data.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn dispatch_snapshot_data_request_split_for_par_v4() {
+        let standard = dispatch_snapshot_data_request(PAR_PROTOCOL_VERSION_3.0, 16);
+        assert_eq!(standard.len(), 1);
+        assert_eq!(standard[0].0, SnapshotDataPacket.id());
+        assert_eq!(
+            dispatch_snapshot_data_request(PAR_PROTOCOL_VERSION_4.0, network::MAX_PAYLOAD_SIZE),
+            standard
+        );
+
+        let parts = dispatch_snapshot_data_request(PAR_PROTOCOL_VERSION_4.0, 16);
+        assert!(parts.len() > 1);
+        let mut reassembler =
+            chunking::Reassembler::new(network::MAX_PAYLOAD_SIZE, 1, Duration::from_secs(1));
+        let mut reassembled = None;
+        for (packet_id, data) in parts {
+            assert_eq!(packet_id, SnapshotDataPartPacket.id());
+            assert!(data.len() <= 16);
+            let part: Bytes = Rlp::new(&data).as_val().unwrap();
+            reassembled = reassembler.push(0, &part, Instant::now()).unwrap();
+        }
+        assert_eq!(reassembled, Some(standard[0].1.clone()));
+    }
+
+    fn dispatch_snapshot_manifest_request(
+        ss: &TestSnapshotService,
+        serve_snapshots: bool,
+    ) -> Bytes {
+        let mut client = TestBlockChainClient::new();
+        let queue = RwLock::new(VecDeque::new());
+        let mut sync = dummy_sync(&client);
+        sync.set_serve_snapshots(serve_snapshots);
+        let mut io = TestIo::new(&mut client, ss, &queue, Some(0));
+
+        SyncSupplier::dispatch_packet(
+            &RwLock::new(sync),
+            &mut io,
+            0usize,
+            GetSnapshotManifestPacket.id(),
+            &RlpStream::new_list(0).out(),
+        );
+
+        assert_eq!(io.packets.len(), 1);
+        assert_eq!(io.packets[0].packet_id, SnapshotManifestPacket.id());
+        io.packets[0].data.clone()
+    }
+
+    #[test]
+    fn dispatch_snapshot_manifest_request_when_serving() {
+        let ss = TestSnapshotService::new_with_snapshot(4, H256::zero(), 100);
+
+        let data = dispatch_snapshot_manifest_request(&ss, true);
+        assert_eq!(Rlp::new(&data).item_count().unwrap(), 1);
+    }
+
+    #[test]
+    fn dispatch_snapshot_manifest_request_when_not_serving() {
+        let ss = TestSnapshotService::new_with_snapshot(4, H256::zero(), 100);
+
+        let data = dispatch_snapshot_manifest_request(&ss, false);
+        assert_eq!(data, RlpStream::new_list(0).out());
+    }
+
+    #[test]
+    fn dispatched_packets_are_accounted_by_packet_id() {
+        let mut client = TestBlockChainClient::new();
+        client.add_blocks(10, EachBlockWith::Nothing);
+        let mut headers_request = RlpStream::new_list(4);
+        headers_request.append(&0u64);
+        headers_request.append(&1u32);
+        headers_request.append(&0u32);
+        headers_request.append(&0u32);
+        let headers_request = headers_request.out();
+        let malformed = vec![0x01, 0x02];
+        let transactions = RlpStream::new_list(0).out();
+
+        let queue = RwLock::new(VecDeque::new());
+        let sync = RwLock::new(dummy_sync_with_peer(H256::zero(), &client));
+        let ss = TestSnapshotService::new();
+        let mut io = TestIo::new(&mut client, &ss, &queue, Some(0));
+
+        let headers = GetBlockHeadersPacket.id();
+        SyncSupplier::dispatch_packet(&sync, &mut io, 0, headers, &headers_request);
+        SyncSupplier::dispatch_packet(&sync, &mut io, 0, headers, &malformed);
+        SyncSupplier::dispatch_packet(&sync, &mut io, 0, TransactionsPacket.id(), &transactions);
+        io.chain.set_processing_fork(true);
+        SyncSupplier::dispatch_packet(&sync, &mut io, 0, headers, &headers_request);
+
+        let stats = sync.read().status().packet_stats;
+        assert_eq!(stats.len(), 2);
+        let headers_stats = stats.iter().find(|s| s.packet_id == headers).unwrap();
+        assert_eq!(headers_stats.name, "get_block_headers");
+        // the delayed request is not processed yet
+        assert_eq!(headers_stats.count, 2);
+        assert_eq!(headers_stats.delayed, 1);
+        assert_eq!(headers_stats.decode_failures, 1);
+        assert_eq!(
+            headers_stats.bytes,
+            (headers_request.len() + malformed.len()) as u64
+        );
+        let transactions_stats = stats
+            .iter()
+            .find(|s| s.packet_id == TransactionsPacket.id())
+            .unwrap();
+        assert_eq!(transactions_stats.name, "transactions");
+        assert_eq!(transactions_stats.count, 1);
+        assert_eq!(transactions_stats.bytes, transactions.len() as u64);
+        assert_eq!(transactions_stats.decode_failures, 0);
+        assert_eq!(sync.read().status().num_delayed_requests, 1);
+    }
 }
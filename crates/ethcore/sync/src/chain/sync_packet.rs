@@ -58,11 +58,45 @@ pub enum SyncPacket {
     GetSnapshotDataPacket = 0x13,
     SnapshotDataPacket = 0x14,
     ConsensusDataPacket = 0x15,
+    CompressedBlockBodiesPacket = 0x16,
+    CompressedReceiptsPacket = 0x17,
+    SnapshotDataPartPacket = 0x18,
 }
 }
 
 use self::SyncPacket::*;
 
+impl SyncPacket {
+    /// Name of the packet, e.g. for metric labels.
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatusPacket => "status",
+            NewBlockHashesPacket => "new_block_hashes",
+            TransactionsPacket => "transactions",
+            GetBlockHeadersPacket => "get_block_headers",
+            BlockHeadersPacket => "block_headers",
+            GetBlockBodiesPacket => "get_block_bodies",
+            BlockBodiesPacket => "block_bodies",
+            NewBlockPacket => "new_block",
+            NewPooledTransactionHashesPacket => "new_pooled_transaction_hashes",
+            GetPooledTransactionsPacket => "get_pooled_transactions",
+            PooledTransactionsPacket => "pooled_transactions",
+            GetNodeDataPacket => "get_node_data",
+            NodeDataPacket => "node_data",
+            GetReceiptsPacket => "get_receipts",
+            ReceiptsPacket => "receipts",
+            GetSnapshotManifestPacket => "get_snapshot_manifest",
+            SnapshotManifestPacket => "snapshot_manifest",
+            GetSnapshotDataPacket => "get_snapshot_data",
+            SnapshotDataPacket => "snapshot_data",
+            ConsensusDataPacket => "consensus_data",
+            CompressedBlockBodiesPacket => "compressed_block_bodies",
+            CompressedReceiptsPacket => "compressed_receipts",
+            SnapshotDataPartPacket => "snapshot_data_part",
+        }
+    }
+}
+
 /// Provide both subprotocol and packet id information within the
 /// same object.
 pub trait PacketInfo {
@@ -96,7 +130,10 @@ impl PacketInfo for SyncPacket {
             | SnapshotManifestPacket
             | GetSnapshotDataPacket
             | SnapshotDataPacket
-            | ConsensusDataPacket => PAR_PROTOCOL,
+            | ConsensusDataPacket
+            | CompressedBlockBodiesPacket
+            | CompressedReceiptsPacket
+            | SnapshotDataPartPacket => PAR_PROTOCOL,
         }
     }
 
@@ -153,4 +190,23 @@ mod tests {
         assert_eq!(ConsensusDataPacket.id(), ConsensusDataPacket as PacketId);
         assert_eq!(ConsensusDataPacket.protocol(), PAR_PROTOCOL);
     }
+
+    #[test]
+    fn compressed_packets_use_par_protocol_without_request_id() {
+        for packet in &[CompressedBlockBodiesPacket, CompressedReceiptsPacket] {
+            assert_eq!(SyncPacket::from_u8(packet.id()), Some(*packet));
+            assert_eq!(packet.protocol(), PAR_PROTOCOL);
+            assert!(!packet.has_request_id_in_eth_66());
+        }
+    }
+
+    #[test]
+    fn snapshot_data_part_packet_uses_par_protocol_without_request_id() {
+        assert_eq!(
+            SyncPacket::from_u8(SnapshotDataPartPacket.id()),
+            Some(SnapshotDataPartPacket)
+        );
+        assert_eq!(SnapshotDataPartPacket.protocol(), PAR_PROTOCOL);
+        assert!(!SnapshotDataPartPacket.has_request_id_in_eth_66());
+    }
 }
@@ -0,0 +1,354 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Heads last advertised by peers, kept across restarts to estimate the chain height before
+//! the peers tell it again.
+//!
+//! The hints are not verified. They are only used for the highest block reported while no peer
+//! announced a block yet and for the snapshot selection heuristics, never for consensus.
+
+use ethereum_types::{H256, U256};
+use network::NodeId;
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use types::BlockNumber;
+
+/// File the hints are kept in, next to the node table.
+pub const HEAD_HINTS_FILE: &str = "head_hints.rlp";
+
+/// Hints not updated for this long are ignored.
+pub const HEAD_HINT_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Hints kept at most, the least recently updated ones are dropped first.
+const MAX_HEAD_HINTS: usize = 1024;
+
+/// Head last advertised by a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeadHint {
+    /// Best block hash.
+    pub hash: H256,
+    /// Highest block number announced. The head may be ahead of it once the hash changed.
+    pub number: Option<BlockNumber>,
+    /// Total difficulty of the best block, if known.
+    pub total_difficulty: Option<U256>,
+    /// Time of the last update.
+    pub updated: SystemTime,
+}
+
+impl HeadHint {
+    fn is_recent(&self, now: SystemTime) -> bool {
+        now.duration_since(self.updated)
+            .is_ok_and(|age| age <= HEAD_HINT_TTL)
+    }
+}
+
+/// Heads advertised by peers of one chain, by node id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadHints {
+    genesis: H256,
+    hints: HashMap<NodeId, HeadHint>,
+}
+
+impl HeadHints {
+    /// No hints for the chain of `genesis`.
+    pub fn new(genesis: H256) -> Self {
+        HeadHints {
+            genesis,
+            hints: HashMap::new(),
+        }
+    }
+
+    /// Number of hints.
+    pub fn len(&self) -> usize {
+        self.hints.len()
+    }
+
+    /// Returns true if there are no hints.
+    pub fn is_empty(&self) -> bool {
+        self.hints.is_empty()
+    }
+
+    /// Hint of a node.
+    pub fn get(&self, node: &NodeId) -> Option<&HeadHint> {
+        self.hints.get(node)
+    }
+
+    /// Note the best block hash and total difficulty of a `Status` packet.
+    pub fn note_status(
+        &mut self,
+        node: NodeId,
+        hash: H256,
+        total_difficulty: Option<U256>,
+        now: SystemTime,
+    ) {
+        let hint = self.hints.entry(node).or_insert_with(|| HeadHint {
+            hash,
+            number: None,
+            total_difficulty: None,
+            updated: now,
+        });
+        hint.hash = hash;
+        hint.total_difficulty = total_difficulty;
+        hint.updated = now;
+        self.evict();
+    }
+
+    /// Note a block announced by `NewBlock` or `NewHashes`, only the former carries the total
+    /// difficulty. Announcements below the highest one noted are ignored.
+    pub fn note_block(
+        &mut self,
+        node: NodeId,
+        hash: H256,
+        number: BlockNumber,
+        total_difficulty: Option<U256>,
+        now: SystemTime,
+    ) {
+        let hint = self.hints.entry(node).or_insert_with(|| HeadHint {
+            hash,
+            number: None,
+            total_difficulty: None,
+            updated: now,
+        });
+        if hint.number.is_some_and(|n| n > number) {
+            return;
+        }
+        hint.hash = hash;
+        hint.number = Some(number);
+        hint.total_difficulty = total_difficulty;
+        hint.updated = now;
+        self.evict();
+    }
+
+    /// Highest credible block number of the recent hints: the second highest one, so a single
+    /// peer cannot inflate the estimate, unless only one peer announced a block.
+    pub fn highest_block(&self, now: SystemTime) -> Option<BlockNumber> {
+        let mut numbers: Vec<_> = self
+            .hints
+            .values()
+            .filter(|hint| hint.is_recent(now))
+            .filter_map(|hint| hint.number)
+            .collect();
+        numbers.sort_unstable_by(|a, b| b.cmp(a));
+        numbers.get(1).or(numbers.first()).cloned()
+    }
+
+    /// Drop the hints not updated within `HEAD_HINT_TTL`.
+    pub fn prune(&mut self, now: SystemTime) {
+        self.hints.retain(|_, hint| hint.is_recent(now));
+    }
+
+    fn evict(&mut self) {
+        while self.hints.len() > MAX_HEAD_HINTS {
+            let oldest = self
+                .hints
+                .iter()
+                .min_by_key(|(_, hint)| hint.updated)
+                .map(|(node, _)| *node)
+                .expect("more than MAX_HEAD_HINTS hints are present; qed");
+            self.hints.remove(&oldest);
+        }
+    }
+
+    /// Save the recent hints to `path`.
+    pub fn save(&self, path: &Path, now: SystemTime) {
+        if let Some(dir) = path.parent() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                warn!(target: "sync", "Error creating head hints directory: {e:?}");
+                return;
+            }
+        }
+        let mut hints = self.clone();
+        hints.prune(now);
+        if let Err(e) = fs::write(path, hints.rlp_bytes()) {
+            warn!(target: "sync", "Error writing head hints file: {e:?}");
+        }
+    }
+
+    /// Load the recent hints for the chain of `genesis` from `path`.
+    pub fn load(path: &Path, genesis: H256, now: SystemTime) -> Self {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                debug!(target: "sync", "Error opening head hints file: {e:?}");
+                return HeadHints::new(genesis);
+            }
+        };
+        match HeadHints::decode(&Rlp::new(&bytes)) {
+            Ok(hints) if hints.genesis != genesis => {
+                warn!(target: "sync", "Ignoring head hints {}: recorded for genesis {:?}, running genesis {:?}", path.display(), hints.genesis, genesis);
+                HeadHints::new(genesis)
+            }
+            Ok(mut hints) => {
+                hints.prune(now);
+                hints
+            }
+            Err(e) => {
+                warn!(target: "sync", "Error reading head hints file: {e:?}");
+                HeadHints::new(genesis)
+            }
+        }
+    }
+
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        fn optional<T: Decodable>(rlp: Rlp) -> Result<Option<T>, DecoderError> {
+            match rlp.item_count()? {
+                0 => Ok(None),
+                _ => rlp.val_at(0).map(Some),
+            }
+        }
+
+        let mut hints = HashMap::new();
+        for item in rlp.at(1)?.iter() {
+            let updated: u64 = item.val_at(4)?;
+            hints.insert(
+                item.val_at(0)?,
+                HeadHint {
+                    hash: item.val_at(1)?,
+                    number: optional(item.at(2)?)?,
+                    total_difficulty: optional(item.at(3)?)?,
+                    updated: UNIX_EPOCH + Duration::from_secs(updated),
+                },
+            );
+        }
+        Ok(HeadHints {
+            genesis: rlp.val_at(0)?,
+            hints,
+        })
+    }
+}
+
+impl Encodable for HeadHints {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        fn append_optional<T: Encodable>(s: &mut RlpStream, value: &Option<T>) {
+            match *value {
+                Some(ref value) => s.begin_list(1).append(value),
+                None => s.begin_list(0),
+            };
+        }
+
+        s.begin_list(2);
+        s.append(&self.genesis);
+        s.begin_list(self.hints.len());
+        for (node, hint) in &self.hints {
+            let updated = hint
+                .updated
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs());
+            s.begin_list(5);
+            s.append(node).append(&hint.hash);
+            append_optional(s, &hint.number);
+            append_optional(s, &hint.total_difficulty);
+            s.append(&updated);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    const GENESIS: u64 = 0x6e;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_600_000_000 + secs)
+    }
+
+    #[test]
+    fn persists_and_loads_hints() {
+        let mut hints = HeadHints::new(H256::from_low_u64_be(GENESIS));
+        hints.note_block(
+            NodeId::from_low_u64_be(1),
+            H256::from_low_u64_be(100),
+            100,
+            Some(U256::from(1000)),
+            at(0),
+        );
+        hints.note_block(
+            NodeId::from_low_u64_be(2),
+            H256::from_low_u64_be(90),
+            90,
+            None,
+            at(10),
+        );
+        hints.note_status(
+            NodeId::from_low_u64_be(3),
+            H256::from_low_u64_be(95),
+            Some(U256::from(950)),
+            at(20),
+        );
+
+        let path = env::temp_dir().join(format!("{}-{}", HEAD_HINTS_FILE, process::id()));
+        hints.save(&path, at(30));
+        let loaded = HeadHints::load(&path, H256::from_low_u64_be(GENESIS), at(30));
+        let other_chain = HeadHints::load(&path, H256::from_low_u64_be(GENESIS + 1), at(30));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, hints);
+        assert_eq!(
+            loaded.get(&NodeId::from_low_u64_be(3)).unwrap().number,
+            None
+        );
+        assert!(other_chain.is_empty());
+    }
+
+    #[test]
+    fn ignores_expired_hints() {
+        let mut hints = HeadHints::new(H256::from_low_u64_be(GENESIS));
+        for (node, number, updated) in [(1, 500, 0), (2, 400, 0), (3, 300, 3600), (4, 200, 3600)] {
+            hints.note_block(
+                NodeId::from_low_u64_be(node),
+                H256::from_low_u64_be(number),
+                number,
+                None,
+                at(updated),
+            );
+        }
+        let ttl = HEAD_HINT_TTL.as_secs();
+        assert_eq!(hints.highest_block(at(ttl)), Some(400));
+        assert_eq!(hints.highest_block(at(ttl + 1)), Some(200));
+        assert_eq!(hints.highest_block(at(ttl + 3601)), None);
+
+        hints.prune(at(ttl + 1));
+        assert_eq!(hints.len(), 2);
+    }
+
+    #[test]
+    fn status_keeps_the_announced_number() {
+        let node = NodeId::from_low_u64_be(1);
+        let mut hints = HeadHints::new(H256::from_low_u64_be(GENESIS));
+        hints.note_block(node, H256::from_low_u64_be(100), 100, None, at(0));
+        hints.note_status(
+            node,
+            H256::from_low_u64_be(101),
+            Some(U256::from(1)),
+            at(10),
+        );
+        // an older announcement does not lower the hint
+        hints.note_block(node, H256::from_low_u64_be(99), 99, None, at(20));
+
+        let hint = hints.get(&node).unwrap();
+        assert_eq!(hint.number, Some(100));
+        assert_eq!(hint.hash, H256::from_low_u64_be(101));
+        assert_eq!(hint.updated, at(10));
+        assert_eq!(hints.highest_block(at(20)), Some(100));
+    }
+}
@@ -0,0 +1,152 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Downloads block headers backwards from a trusted checkpoint towards the genesis and stores
+//! them without bodies and receipts.
+
+use block_sync::BlockDownloaderImportError;
+use blocks::SyncHeader;
+use ethcore::client::{BlockId, BlockStatus};
+use ethereum_types::H256;
+use network::PeerId;
+use rlp::Rlp;
+use std::cmp;
+use sync_io::SyncIo;
+use types::{encoded, BlockNumber};
+
+const MAX_HEADERS_TO_REQUEST: u64 = 128;
+
+/// Backwards header download from a trusted checkpoint.
+pub struct HeaderBackfill {
+    /// Number of the checkpoint.
+    target: BlockNumber,
+    /// Hash and number of the next header to download, `None` once complete.
+    next: Option<(H256, BlockNumber)>,
+    /// Peer the next batch is requested from.
+    downloading: Option<PeerId>,
+}
+
+impl HeaderBackfill {
+    /// Create a new backfill starting with the checkpoint header itself.
+    pub fn new(checkpoint: (BlockNumber, H256)) -> Self {
+        let (number, hash) = checkpoint;
+        HeaderBackfill {
+            target: number,
+            next: Some((hash, number)),
+            downloading: None,
+        }
+    }
+
+    /// Returns true once all headers down to the genesis or to the known blocks are stored.
+    pub fn is_complete(&self) -> bool {
+        self.next.is_none()
+    }
+
+    /// Number of blocks below the checkpoint covered by the stored headers.
+    pub fn backfilled(&self) -> BlockNumber {
+        match self.next {
+            Some((_, number)) => self.target - number,
+            None => self.target,
+        }
+    }
+
+    /// Number of blocks to cover.
+    pub fn target(&self) -> BlockNumber {
+        self.target
+    }
+
+    /// Start hash and number of headers of the next batch to request from a peer serving blocks
+    /// from `serve_from_block`. Only one batch is downloaded at a time.
+    pub fn request_headers(
+        &mut self,
+        peer_id: PeerId,
+        serve_from_block: BlockNumber,
+    ) -> Option<(H256, u64)> {
+        if self.downloading.is_some() {
+            return None;
+        }
+        let (hash, number) = self.next?;
+        let count = cmp::min(number, MAX_HEADERS_TO_REQUEST);
+        if number + 1 - count < serve_from_block {
+            return None;
+        }
+        self.downloading = Some(peer_id);
+        Some((hash, count))
+    }
+
+    /// Forget the batch requested from the peer, e.g. after a timeout or disconnect.
+    pub fn clear_download(&mut self, peer_id: PeerId) {
+        if self.downloading == Some(peer_id) {
+            self.downloading = None;
+        }
+    }
+
+    /// Verify a batch of headers by hash linkage to the child stored last and store the batch.
+    pub fn import_headers(
+        &mut self,
+        io: &mut dyn SyncIo,
+        peer_id: PeerId,
+        r: &Rlp,
+        eip1559_transition: BlockNumber,
+    ) -> Result<(), BlockDownloaderImportError> {
+        if self.downloading != Some(peer_id) {
+            trace!(target: "sync", "{peer_id}: Ignored unexpected backfill headers");
+            return Ok(());
+        }
+        self.downloading = None;
+        let (mut expected_hash, mut expected_number) = match self.next {
+            Some(next) => next,
+            None => return Ok(()),
+        };
+
+        let item_count = r.item_count()?;
+        if item_count == 0 {
+            trace!(target: "sync", "{peer_id}: Peer does not have the backfill headers");
+            return Err(BlockDownloaderImportError::Useless);
+        }
+        if item_count as u64 > cmp::min(expected_number, MAX_HEADERS_TO_REQUEST) {
+            debug!(target: "sync", "{peer_id}: Backfill headers response is larger than expected");
+            return Err(BlockDownloaderImportError::Invalid);
+        }
+
+        let mut headers = Vec::with_capacity(item_count);
+        for i in 0..item_count {
+            let info = SyncHeader::from_rlp(r.at(i)?.as_raw().to_vec(), eip1559_transition)?;
+            let hash = info.header.hash();
+            if hash != expected_hash || info.header.number() != expected_number {
+                debug!(target: "sync", "{}: Backfill header #{} {:?} does not link to #{} {:?}", peer_id, info.header.number(), hash, expected_number, expected_hash);
+                return Err(BlockDownloaderImportError::Invalid);
+            }
+            expected_hash = *info.header.parent_hash();
+            expected_number -= 1;
+            headers.push(encoded::Header::new(info.bytes));
+        }
+        io.chain().import_headers_only(headers);
+
+        self.next = match io.chain().block_status(BlockId::Hash(expected_hash)) {
+            BlockStatus::InChain => {
+                info!(target: "sync", "Header backfill from #{} complete", self.target);
+                None
+            }
+            _ if expected_number == 0 => {
+                warn!(target: "sync", "Backfilled headers do not lead to the local genesis, checkpoint #{} is not on the local chain", self.target);
+                None
+            }
+            _ => Some((expected_hash, expected_number)),
+        };
+        Ok(())
+    }
+}
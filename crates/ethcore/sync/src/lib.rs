@@ -35,6 +35,7 @@ extern crate fastmap;
 extern crate keccak_hash as hash;
 extern crate parity_bytes as bytes;
 extern crate parity_crypto as crypto;
+extern crate parity_runtime;
 #[macro_use]
 extern crate parity_util_mem;
 extern crate crossbeam_channel;
@@ -45,6 +46,7 @@ extern crate rand_xorshift;
 extern crate rlp;
 extern crate stats;
 extern crate triehash_ethereum;
+extern crate zstd;
 
 #[cfg(test)]
 extern crate env_logger;
@@ -66,7 +68,11 @@ extern crate ethcore_miner;
 mod block_sync;
 mod blocks;
 mod chain;
+mod head_hints;
+mod header_backfill;
+mod peer_selection;
 mod snapshot;
+mod subchain_heads;
 mod sync_io;
 mod transactions_stats;
 
@@ -76,6 +82,15 @@ mod tests;
 mod api;
 
 pub use api::*;
-pub use chain::{SyncState, SyncStatus};
+pub use block_sync::{
+    DeepReorg, DownloaderDiagnostics, HeadDiagnostics, HeadStatus, ResetEvent, ResetReason,
+    State as DownloaderState,
+};
+pub use chain::{
+    BlockSet, BranchProbeStatus, ForkConfirmation, HealthCode, HealthFinding, HealthSeverity,
+    InjectError, PacketDispatchStats, PeerFeeMarket, RejectedBlockStats,
+    SealedBlockRejectedHandler, SendErrorStats, StallRecoveryStats, SyncEvent, SyncEventKind,
+    SyncHealthReport, SyncState, SyncStatus,
+};
 pub use devp2p::validate_node_url;
 pub use network::{ConnectionDirection, ConnectionFilter, Error, ErrorKind, NonReservedPeerMode};
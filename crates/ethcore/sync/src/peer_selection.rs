@@ -0,0 +1,122 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Rendezvous hashing of block hashes onto peers. Every block prefers the peer with the highest
+//! `keccak(node_id || block_hash)`, so the same block is requested from the same serving peer
+//! across retries and across independent nodes syncing from the same peers.
+
+use ethereum_types::H256;
+use hash::keccak;
+use network::NodeId;
+
+/// Rendezvous score of a peer for a block.
+fn score(node_id: &NodeId, hash: &H256) -> H256 {
+    let mut data = [0u8; 96];
+    data[..64].copy_from_slice(node_id.as_bytes());
+    data[64..].copy_from_slice(hash.as_bytes());
+    keccak(&data[..])
+}
+
+/// The candidate with the highest score for the block.
+pub fn preferred<'a>(candidates: &'a [NodeId], hash: &H256) -> Option<&'a NodeId> {
+    candidates.iter().max_by_key(|node_id| score(node_id, hash))
+}
+
+/// Peers competing for the block requests of one peer.
+pub struct Rendezvous {
+    /// Node id of the requesting peer.
+    peer: NodeId,
+    /// Node ids of the peers able to take a request, including the requesting peer.
+    candidates: Vec<NodeId>,
+}
+
+impl Rendezvous {
+    /// Create a routing for `peer` among `candidates`.
+    pub fn new(peer: NodeId, mut candidates: Vec<NodeId>) -> Self {
+        if !candidates.contains(&peer) {
+            candidates.push(peer);
+        }
+        Rendezvous { peer, candidates }
+    }
+
+    /// Returns true if the block should be requested from the peer.
+    pub fn is_preferred(&self, hash: &H256) -> bool {
+        preferred(&self.candidates, hash) == Some(&self.peer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(peers: &[NodeId], hashes: &[H256]) -> Vec<NodeId> {
+        hashes
+            .iter()
+            .map(|hash| *preferred(peers, hash).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn mapping_is_stable_and_redistributes_removed_peer() {
+        let peers: Vec<NodeId> = (1..=3).map(NodeId::from_low_u64_be).collect();
+        let hashes: Vec<H256> = (0..64).map(H256::from_low_u64_be).collect();
+
+        let initial = mapping(&peers, &hashes);
+        for _ in 0..3 {
+            assert_eq!(mapping(&peers, &hashes), initial);
+        }
+        let mut reversed = peers.clone();
+        reversed.reverse();
+        assert_eq!(mapping(&reversed, &hashes), initial);
+        for peer in &peers {
+            assert!(
+                initial.contains(peer),
+                "every peer is preferred for some blocks"
+            );
+        }
+
+        let remaining = [peers[0], peers[2]];
+        let redistributed = mapping(&remaining, &hashes);
+        for ((hash, before), after) in hashes.iter().zip(&initial).zip(&redistributed) {
+            if *before == peers[1] {
+                let expected = if score(&peers[0], hash) > score(&peers[2], hash) {
+                    peers[0]
+                } else {
+                    peers[2]
+                };
+                assert_eq!(*after, expected);
+            } else {
+                assert_eq!(after, before);
+            }
+        }
+    }
+
+    #[test]
+    fn rendezvous_prefers_exactly_one_candidate() {
+        let peers: Vec<NodeId> = (1..=3).map(NodeId::from_low_u64_be).collect();
+        let routes: Vec<Rendezvous> = peers
+            .iter()
+            .map(|peer| Rendezvous::new(*peer, peers.clone()))
+            .collect();
+        for hash in (0..64).map(H256::from_low_u64_be) {
+            let preferred_by = routes.iter().filter(|r| r.is_preferred(&hash)).count();
+            assert_eq!(preferred_by, 1);
+        }
+
+        let alone = Rendezvous::new(peers[0], Vec::new());
+        assert!(alone.is_preferred(&H256::zero()));
+    }
+}
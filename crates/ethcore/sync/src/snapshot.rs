@@ -14,18 +14,35 @@
 // You should have received a copy of the GNU General Public License
 // along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
 
-use ethcore::snapshot::{ManifestData, SnapshotService};
+use ethcore::snapshot::{ManifestData, SnapshotService, MAX_CHUNK_SIZE};
 use ethereum_types::H256;
 use hash::keccak;
 
 use std::{collections::HashSet, iter::FromIterator};
 
+/// Largest snapshot chunk accepted from a peer: the snappy compressed form of the largest
+/// chunk the restoration decompresses.
+pub const MAX_COMPRESSED_CHUNK_SIZE: usize = 32 + MAX_CHUNK_SIZE + MAX_CHUNK_SIZE / 6;
+
 #[derive(PartialEq, Eq, Debug)]
 pub enum ChunkType {
     State(H256),
     Block(H256),
 }
 
+/// Reason a received chunk is not restored.
+#[derive(PartialEq, Eq, Debug)]
+pub enum ChunkError {
+    /// Chunk is larger than `MAX_COMPRESSED_CHUNK_SIZE`, it is not hashed.
+    TooLarge(usize),
+    /// Chunk hash differs from the hash requested.
+    Mismatch(H256),
+    /// Chunk was processed already.
+    Processed,
+    /// Chunk is not part of the manifest.
+    Unknown,
+}
+
 pub struct Snapshot {
     pending_state_chunks: Vec<H256>,
     pending_block_chunks: Vec<H256>,
@@ -56,8 +73,14 @@ impl Snapshot {
             return;
         }
 
+        // Chunks restored before a restart: only those of the current manifest count
         if let Some(completed_chunks) = snapshot_service.completed_chunks() {
-            self.completed_chunks = HashSet::from_iter(completed_chunks);
+            let in_manifest = |h: &H256| {
+                self.pending_block_chunks.contains(h) || self.pending_state_chunks.contains(h)
+            };
+            let completed: Vec<H256> = completed_chunks.into_iter().filter(in_manifest).collect();
+            self.downloading_chunks.retain(|h| !completed.contains(h));
+            self.completed_chunks = HashSet::from_iter(completed);
         }
 
         trace!(
@@ -92,12 +115,32 @@ impl Snapshot {
         self.snapshot_hash = Some(*hash);
     }
 
-    /// Validate chunk and mark it as downloaded
-    pub fn validate_chunk(&mut self, chunk: &[u8]) -> Result<ChunkType, ()> {
-        let hash = keccak(chunk);
+    /// Validate a chunk received for the `requested` hash and mark it as downloaded
+    #[cfg(test)]
+    pub fn validate_chunk(
+        &mut self,
+        requested: &H256,
+        chunk: &[u8],
+    ) -> Result<ChunkType, ChunkError> {
+        if chunk.len() > MAX_COMPRESSED_CHUNK_SIZE {
+            return Err(ChunkError::TooLarge(chunk.len()));
+        }
+        self.validate_hashed_chunk(requested, &keccak(chunk))
+    }
+
+    /// Validate the `hash` of a chunk received for the `requested` hash and mark it as downloaded
+    pub fn validate_hashed_chunk(
+        &mut self,
+        requested: &H256,
+        hash: &H256,
+    ) -> Result<ChunkType, ChunkError> {
+        let hash = *hash;
+        if hash != *requested {
+            return Err(ChunkError::Mismatch(hash));
+        }
         if self.completed_chunks.contains(&hash) {
             trace!(target: "sync", "Ignored proccessed chunk: {hash:x}");
-            return Err(());
+            return Err(ChunkError::Processed);
         }
         self.downloading_chunks.remove(&hash);
         if self.pending_block_chunks.iter().any(|h| h == &hash) {
@@ -109,7 +152,7 @@ impl Snapshot {
             return Ok(ChunkType::State(hash));
         }
         trace!(target: "sync", "Ignored unknown chunk: {hash:x}");
-        Err(())
+        Err(ChunkError::Unknown)
     }
 
     /// Find a chunk to download
@@ -148,6 +191,11 @@ impl Snapshot {
         self.downloading_chunks.remove(hash);
     }
 
+    /// Keep a received chunk from being requested again while it is hashed.
+    pub fn note_chunk_hashing(&mut self, hash: H256) {
+        self.downloading_chunks.insert(hash);
+    }
+
     // note snapshot hash as bad.
     pub fn note_bad(&mut self, hash: H256) {
         self.bad_hashes.insert(hash);
@@ -226,7 +274,11 @@ mod test {
         let (manifest, mhash, state_chunks, block_chunks) = test_manifest();
         snapshot.reset_to(&manifest, &mhash);
         assert_eq!(snapshot.done_chunks(), 0);
-        assert!(snapshot.validate_chunk(H256::random().as_bytes()).is_err());
+        let unknown = H256::random();
+        assert_eq!(
+            snapshot.validate_chunk(&keccak(unknown), unknown.as_bytes()),
+            Err(ChunkError::Unknown)
+        );
 
         let requested: Vec<H256> = (0..40).map(|_| snapshot.needed_chunk().unwrap()).collect();
         assert!(snapshot.needed_chunk().is_none());
@@ -246,14 +298,14 @@ mod test {
         assert_eq!(snapshot.downloading_chunks.len(), 40);
 
         assert_eq!(
-            snapshot.validate_chunk(&state_chunks[4]),
+            snapshot.validate_chunk(&manifest.state_hashes[4], &state_chunks[4]),
             Ok(ChunkType::State(manifest.state_hashes[4]))
         );
         assert_eq!(snapshot.completed_chunks.len(), 1);
         assert_eq!(snapshot.downloading_chunks.len(), 39);
 
         assert_eq!(
-            snapshot.validate_chunk(&block_chunks[10]),
+            snapshot.validate_chunk(&manifest.block_hashes[10], &block_chunks[10]),
             Ok(ChunkType::Block(manifest.block_hashes[10]))
         );
         assert_eq!(snapshot.completed_chunks.len(), 2);
@@ -261,22 +313,46 @@ mod test {
 
         for (i, data) in state_chunks.iter().enumerate() {
             if i != 4 {
-                assert!(snapshot.validate_chunk(data).is_ok());
+                assert!(snapshot.validate_chunk(&keccak(data), data).is_ok());
             }
         }
 
         for (i, data) in block_chunks.iter().enumerate() {
             if i != 10 {
-                assert!(snapshot.validate_chunk(data).is_ok());
+                assert!(snapshot.validate_chunk(&keccak(data), data).is_ok());
             }
         }
 
         assert!(snapshot.is_complete());
+        assert_eq!(
+            snapshot.validate_chunk(&manifest.state_hashes[0], &state_chunks[0]),
+            Err(ChunkError::Processed)
+        );
         assert_eq!(snapshot.done_chunks(), 40);
         assert_eq!(snapshot.done_chunks(), snapshot.total_chunks());
         assert_eq!(snapshot.snapshot_hash(), Some(keccak(manifest.into_rlp())));
     }
 
+    #[test]
+    fn rejects_chunks_not_requested() {
+        let mut snapshot = Snapshot::new();
+        let (manifest, mhash, state_chunks, _) = test_manifest();
+        snapshot.reset_to(&manifest, &mhash);
+        let requested = snapshot.needed_chunk().unwrap();
+
+        // a chunk of the manifest, but not the one requested
+        assert_eq!(
+            snapshot.validate_chunk(&requested, &state_chunks[1]),
+            Err(ChunkError::Mismatch(manifest.state_hashes[1]))
+        );
+        let oversized = vec![0u8; MAX_COMPRESSED_CHUNK_SIZE + 1];
+        assert_eq!(
+            snapshot.validate_chunk(&requested, &oversized),
+            Err(ChunkError::TooLarge(MAX_COMPRESSED_CHUNK_SIZE + 1))
+        );
+        assert_eq!(snapshot.done_chunks(), 0);
+    }
+
     #[test]
     fn tracks_known_bad() {
         let mut snapshot = Snapshot::new();
@@ -0,0 +1,402 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Subchain heads of a sync round, requested in segments from several peers at once.
+
+use ethereum_types::H256;
+use network::PeerId;
+use std::time::{Duration, Instant};
+use types::BlockNumber;
+
+/// Time a peer has to answer a subchain heads request before the range is requested
+/// from other peers. The peer itself is only dropped once it stops answering at all.
+const REQUEST_DEADLINE: Duration = Duration::from_secs(10);
+
+/// First header of a subchain heads request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeadsStart {
+    /// The last imported block, the heads are checked to start from it.
+    Hash(H256),
+    /// A block further down the chain.
+    Number(BlockNumber),
+}
+
+struct Request {
+    peer: PeerId,
+    segment: usize,
+    deadline: Instant,
+}
+
+/// Outcome of storing the heads of a segment.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Inserted {
+    /// The heads are stored.
+    Stored,
+    /// The segment was received from another peer already, the heads are not used.
+    Duplicate,
+    /// The heads do not link to a neighbouring segment, the peers are on different forks.
+    /// Both segments are dropped and requested again.
+    Unlinked(usize, usize),
+}
+
+/// Subchain heads `spacing` blocks apart, starting from the last imported block. The heads are
+/// split into segments of `segment_size` heads requested independently. Every segment but the
+/// first also starts with the last head of the segment before, so that the segments of peers
+/// on different forks are not combined. The segments are merged in order once every segment up
+/// to the first incomplete one is received.
+pub struct SubchainHeads {
+    start_number: BlockNumber,
+    start_hash: H256,
+    segment_size: u64,
+    spacing: u64,
+    /// Heads received, by segment
+    segments: Vec<Option<Vec<H256>>>,
+    /// Requests not answered yet, oldest first
+    requests: Vec<Request>,
+    /// Requests outstanding at once for a single peer
+    max_per_peer: usize,
+    /// Requests outstanding at once over all peers
+    max_outstanding: usize,
+}
+
+impl SubchainHeads {
+    /// Create the heads of a round starting from the last imported block.
+    pub fn new(
+        start_number: BlockNumber,
+        start_hash: H256,
+        count: u64,
+        segment_size: u64,
+        spacing: u64,
+        max_per_peer: usize,
+        max_outstanding: usize,
+    ) -> Self {
+        let segments = count.div_ceil(segment_size) as usize;
+        SubchainHeads {
+            start_number,
+            start_hash,
+            segment_size,
+            spacing,
+            segments: vec![None; segments],
+            requests: Vec::new(),
+            max_per_peer: max_per_peer.max(1),
+            max_outstanding: max_outstanding.max(1),
+        }
+    }
+
+    /// Hash of the block the heads start from.
+    pub fn start_hash(&self) -> H256 {
+        self.start_hash
+    }
+
+    /// Number of heads requested for `segment`, including the last head of the segment before.
+    pub fn count(&self, segment: usize) -> u64 {
+        match segment {
+            0 => self.segment_size,
+            _ => self.segment_size + 1,
+        }
+    }
+
+    /// First header of `segment`, the last head of the segment before for all but the first.
+    pub fn start(&self, segment: usize) -> HeadsStart {
+        match segment {
+            0 => HeadsStart::Hash(self.start_hash),
+            _ => HeadsStart::Number(
+                self.start_number + (segment as u64 * self.segment_size - 1) * self.spacing,
+            ),
+        }
+    }
+
+    /// Pick a segment to request from `peer`. Segments nobody was asked for come first, then
+    /// segments outstanding with other peers. Returns `None` once the peer or all peers together
+    /// have as many requests outstanding as allowed.
+    pub fn request(&mut self, peer: PeerId, now: Instant) -> Option<usize> {
+        if self.requests.len() >= self.max_outstanding
+            || self.requests.iter().filter(|r| r.peer == peer).count() >= self.max_per_peer
+        {
+            return None;
+        }
+        let needed: Vec<usize> = (0..self.segments.len())
+            .filter(|&s| self.is_needed(s))
+            .collect();
+        let segment = needed
+            .iter()
+            .find(|&&s| self.requests.iter().all(|r| r.segment != s))
+            .or_else(|| {
+                needed.iter().find(|&&s| {
+                    self.requests
+                        .iter()
+                        .all(|r| r.segment != s || r.peer != peer)
+                })
+            })
+            .cloned()?;
+        self.requests.push(Request {
+            peer,
+            segment,
+            deadline: now + REQUEST_DEADLINE,
+        });
+        Some(segment)
+    }
+
+    /// Check if heads requested from `peer` are still to come.
+    pub fn expects(&self, peer: PeerId) -> bool {
+        self.requests.iter().any(|r| r.peer == peer)
+    }
+
+    /// Number of requests not answered yet.
+    pub fn outstanding(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// Find the request of `peer` a response starting with `first` answers and forget about it.
+    /// An empty response answers the oldest request, peers respond in order.
+    pub fn take_request(
+        &mut self,
+        peer: PeerId,
+        first: Option<(BlockNumber, H256)>,
+    ) -> Option<usize> {
+        let position = self.requests.iter().position(|r| {
+            r.peer == peer
+                && first.map_or(true, |(number, hash)| match self.start(r.segment) {
+                    HeadsStart::Hash(h) => h == hash,
+                    HeadsStart::Number(n) => n == number,
+                })
+        })?;
+        Some(self.requests.remove(position).segment)
+    }
+
+    /// Store the heads received for `segment`, checking that they link to the segments next to it.
+    pub fn insert(&mut self, segment: usize, heads: Vec<H256>) -> Inserted {
+        match self.segments.get_mut(segment) {
+            Some(received @ None) => *received = Some(heads),
+            _ => return Inserted::Duplicate,
+        }
+        for (before, after) in [(segment.wrapping_sub(1), segment), (segment, segment + 1)] {
+            if !self.linked(before, after) {
+                self.segments[before] = None;
+                self.segments[after] = None;
+                return Inserted::Unlinked(before, after);
+            }
+        }
+        Inserted::Stored
+    }
+
+    /// Forget the requests past their deadline, their segments are requested again.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.requests.len();
+        self.requests.retain(|r| r.deadline > now);
+        before - self.requests.len()
+    }
+
+    /// Forget the requests of a peer which is gone.
+    pub fn clear_peer(&mut self, peer: PeerId) {
+        self.requests.retain(|r| r.peer != peer);
+    }
+
+    /// All heads in order, once complete.
+    pub fn merged(&self) -> Option<Vec<H256>> {
+        let mut heads = Vec::new();
+        for (index, segment) in self.segments.iter().enumerate() {
+            let segment = segment.as_ref()?;
+            // the first head of a segment is the last one of the segment before
+            let skip = if index == 0 { 0 } else { 1 };
+            heads.extend(segment.iter().skip(skip));
+            if !self.is_complete(index) {
+                // the peers do not know of more blocks
+                break;
+            }
+        }
+        Some(heads)
+    }
+
+    // A segment is not needed once a segment before it came back incomplete.
+    fn is_needed(&self, segment: usize) -> bool {
+        self.segments[segment].is_none() && (0..segment).all(|s| self.is_complete(s))
+    }
+
+    fn is_complete(&self, segment: usize) -> bool {
+        self.segments[segment]
+            .as_ref()
+            .map_or(true, |heads| heads.len() as u64 == self.count(segment))
+    }
+
+    // Check that the segment `after` starts with the last head of the complete segment `before`.
+    // Segments not received yet are checked once they are.
+    fn linked(&self, before: usize, after: usize) -> bool {
+        match (self.segments.get(before), self.segments.get(after)) {
+            (Some(Some(heads)), Some(Some(next))) if self.is_complete(before) => {
+                heads.last() == next.first() || next.is_empty()
+            }
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heads(count: usize) -> Vec<H256> {
+        (0..count).map(|_| H256::random()).collect()
+    }
+
+    fn subchain_heads() -> SubchainHeads {
+        SubchainHeads::new(1000, H256::from_low_u64_be(1), 256, 64, 127, 2, 8)
+    }
+
+    // Heads of consecutive segments, each starting with the last head of the one before.
+    fn linked_segments(sizes: &[usize]) -> Vec<Vec<H256>> {
+        let mut segments: Vec<Vec<H256>> = Vec::new();
+        for &size in sizes {
+            let mut segment = heads(size);
+            if let Some(last) = segments.last().and_then(|s| s.last()) {
+                segment[0] = *last;
+            }
+            segments.push(segment);
+        }
+        segments
+    }
+
+    #[test]
+    fn requests_segments_up_to_the_limits() {
+        let mut subchain = subchain_heads();
+        let now = Instant::now();
+
+        assert_eq!(subchain.request(1, now), Some(0));
+        assert_eq!(subchain.request(1, now), Some(1));
+        assert_eq!(subchain.request(1, now), None);
+        assert_eq!(subchain.request(2, now), Some(2));
+        assert_eq!(subchain.request(2, now), Some(3));
+        // duplicates of segments outstanding with other peers
+        assert_eq!(subchain.request(3, now), Some(0));
+        assert_eq!(subchain.request(3, now), Some(1));
+        assert_eq!(subchain.request(4, now), Some(2));
+        assert_eq!(subchain.request(4, now), Some(3));
+        assert_eq!(subchain.outstanding(), 8);
+        assert_eq!(subchain.request(5, now), None);
+
+        assert_eq!(
+            subchain.start(0),
+            HeadsStart::Hash(H256::from_low_u64_be(1))
+        );
+        assert_eq!(
+            subchain.start(2),
+            HeadsStart::Number(1000 + (2 * 64 - 1) * 127)
+        );
+        assert_eq!(subchain.count(0), 64);
+        assert_eq!(subchain.count(2), 65);
+    }
+
+    #[test]
+    fn merges_segments_in_order() {
+        let mut subchain = subchain_heads();
+        let now = Instant::now();
+        for peer in 1..3 {
+            subchain.request(peer, now);
+            subchain.request(peer, now);
+        }
+        let received = linked_segments(&[64, 65, 65, 10]);
+
+        for &segment in &[3, 1, 0] {
+            assert_eq!(
+                subchain.insert(segment, received[segment].clone()),
+                Inserted::Stored
+            );
+            assert_eq!(subchain.merged(), None);
+        }
+        assert_eq!(subchain.insert(1, heads(65)), Inserted::Duplicate);
+        assert_eq!(subchain.insert(2, received[2].clone()), Inserted::Stored);
+        let expected: Vec<_> = received[0]
+            .iter()
+            .chain(received[1..].iter().flat_map(|s| s.iter().skip(1)))
+            .cloned()
+            .collect();
+        assert_eq!(subchain.merged(), Some(expected));
+    }
+
+    #[test]
+    fn segments_of_different_forks_are_not_combined() {
+        let mut subchain = subchain_heads();
+        let received = linked_segments(&[64, 65, 65]);
+        assert_eq!(subchain.insert(0, received[0].clone()), Inserted::Stored);
+        assert_eq!(subchain.insert(2, received[2].clone()), Inserted::Stored);
+
+        // a segment of another fork does not start with the last head of segment 0
+        assert_eq!(subchain.insert(1, heads(65)), Inserted::Unlinked(0, 1));
+        assert_eq!(subchain.merged(), None);
+
+        // both segments are requested again
+        let now = Instant::now();
+        assert_eq!(subchain.request(1, now), Some(0));
+        assert_eq!(subchain.request(1, now), Some(1));
+        assert_eq!(subchain.insert(0, received[0].clone()), Inserted::Stored);
+        assert_eq!(subchain.insert(1, received[1].clone()), Inserted::Stored);
+        assert_eq!(subchain.merged().map(|h| h.len()), Some(64 * 3));
+    }
+
+    #[test]
+    fn incomplete_segment_ends_the_heads() {
+        let mut subchain = subchain_heads();
+        let now = Instant::now();
+        assert_eq!(subchain.request(1, now), Some(0));
+        let first = heads(3);
+        assert_eq!(subchain.insert(0, first.clone()), Inserted::Stored);
+        assert_eq!(subchain.merged(), Some(first));
+        assert_eq!(subchain.request(1, now), None);
+    }
+
+    #[test]
+    fn matches_responses_to_requests() {
+        let mut subchain = subchain_heads();
+        let now = Instant::now();
+        subchain.request(1, now);
+        subchain.request(1, now);
+
+        assert_eq!(subchain.take_request(2, None), None);
+        assert_eq!(
+            subchain.take_request(1, Some((1000 + 63 * 127, H256::zero()))),
+            Some(1)
+        );
+        assert_eq!(subchain.take_request(1, Some((1000, H256::zero()))), None);
+        assert_eq!(
+            subchain.take_request(1, Some((1000, H256::from_low_u64_be(1)))),
+            Some(0)
+        );
+        assert!(!subchain.expects(1));
+    }
+
+    #[test]
+    fn expired_request_only_requeues_its_segment() {
+        let mut subchain = subchain_heads();
+        let start = Instant::now();
+        assert_eq!(subchain.request(1, start), Some(0));
+        assert_eq!(subchain.request(1, start), Some(1));
+        assert_eq!(subchain.request(2, start + Duration::from_secs(5)), Some(2));
+        assert_eq!(subchain.request(2, start + Duration::from_secs(5)), Some(3));
+
+        assert_eq!(subchain.take_request(1, None), Some(0));
+        assert_eq!(subchain.insert(0, heads(64)), Inserted::Stored);
+        assert_eq!(
+            subchain.expire(start + REQUEST_DEADLINE + Duration::from_secs(1)),
+            1
+        );
+
+        // the expired segment is the only one not requested from anybody
+        assert_eq!(subchain.request(3, start), Some(1));
+        assert!(subchain.expects(2));
+        // a late response finds no request
+        assert_eq!(subchain.take_request(1, None), None);
+    }
+}
@@ -18,7 +18,8 @@ use bytes::Bytes;
 use chain::sync_packet::{PacketInfo, SyncPacket};
 use ethcore::{client::BlockChainClient, snapshot::SnapshotService};
 use network::{
-    client_version::ClientVersion, Error, NetworkContext, PacketId, PeerId, ProtocolId, SessionInfo,
+    client_version::ClientVersion, Error, NetworkContext, PacketId, PeerId, ProtocolId,
+    SessionInfo, MAX_PAYLOAD_SIZE,
 };
 use parking_lot::RwLock;
 use std::collections::HashMap;
@@ -30,12 +31,24 @@ use types::BlockNumber;
 pub trait SyncIo {
     /// Disable a peer
     fn disable_peer(&mut self, peer_id: PeerId);
+    /// Disconnect a misbehaving peer and ban it, also across restarts
+    fn ban_peer(&mut self, peer_id: PeerId, reason: &str);
     /// Disconnect peer
     fn disconnect_peer(&mut self, peer_id: PeerId);
+    /// Disconnect a peer of a different network and do not dial it again for a while
+    fn avoid_peer(&mut self, peer_id: PeerId);
     /// Respond to current request with a packet. Can be called from an IO handler for incoming packet.
     fn respond(&mut self, packet_id: PacketId, data: Vec<u8>) -> Result<(), Error>;
     /// Send a packet to a peer using specified protocol.
     fn send(&mut self, peer_id: PeerId, packet_id: SyncPacket, data: Vec<u8>) -> Result<(), Error>;
+    /// Send a raw packet to a peer on the given protocol.
+    fn send_protocol(
+        &mut self,
+        protocol: ProtocolId,
+        peer_id: PeerId,
+        packet_id: PacketId,
+        data: Vec<u8>,
+    ) -> Result<(), Error>;
     /// Get the blockchain
     fn chain(&self) -> &dyn BlockChainClient;
     /// Get the snapshot service.
@@ -46,6 +59,11 @@ pub trait SyncIo {
     }
     /// Returns information on p2p session
     fn peer_session_info(&self, peer_id: PeerId) -> Option<SessionInfo>;
+    /// Returns the largest packet payload which may be sent to the peer
+    fn peer_max_payload_size(&self, peer_id: PeerId) -> usize {
+        self.peer_session_info(peer_id)
+            .map_or(MAX_PAYLOAD_SIZE, |info| info.max_payload_size)
+    }
     /// Maximum mutually supported version of a gien protocol.
     fn protocol_version(&self, protocol: ProtocolId, peer_id: PeerId) -> u8;
     /// Returns if the chain block queue empty
@@ -88,10 +106,18 @@ impl<'s> SyncIo for NetSyncIo<'s> {
         self.network.disable_peer(peer_id);
     }
 
+    fn ban_peer(&mut self, peer_id: PeerId, reason: &str) {
+        self.network.ban_peer(peer_id, reason);
+    }
+
     fn disconnect_peer(&mut self, peer_id: PeerId) {
         self.network.disconnect_peer(peer_id);
     }
 
+    fn avoid_peer(&mut self, peer_id: PeerId) {
+        self.network.avoid_peer(peer_id);
+    }
+
     fn respond(&mut self, packet_id: PacketId, data: Vec<u8>) -> Result<(), Error> {
         self.network.respond(packet_id, data)
     }
@@ -101,6 +127,17 @@ impl<'s> SyncIo for NetSyncIo<'s> {
             .send_protocol(packet_id.protocol(), peer_id, packet_id.id(), data)
     }
 
+    fn send_protocol(
+        &mut self,
+        protocol: ProtocolId,
+        peer_id: PeerId,
+        packet_id: PacketId,
+        data: Vec<u8>,
+    ) -> Result<(), Error> {
+        self.network
+            .send_protocol(protocol, peer_id, packet_id, data)
+    }
+
     fn chain(&self) -> &dyn BlockChainClient {
         self.chain
     }
@@ -15,11 +15,11 @@
 // along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
 
 use super::helpers::*;
-use chain::SyncState;
+use chain::{SyncState, PAR_PROTOCOL_VERSION_3};
 use ethcore::client::{
     BlockChainClient, BlockId, BlockInfo, ChainInfo, EachBlockWith, TestBlockChainClient,
 };
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use SyncConfig;
 use WarpSync;
 
@@ -299,3 +299,119 @@ fn disconnect_on_unrelated_chain() {
     net.sync();
     assert_eq!(net.disconnect_events, vec![(0, 0)]);
 }
+
+#[test]
+fn compressed_block_bodies_between_par_v3_peers() {
+    ::env_logger::try_init().ok();
+    let mut net = TestNet::new(2);
+    for i in 0..2 {
+        net.peer_mut(i).par_protocol_version = PAR_PROTOCOL_VERSION_3.0;
+    }
+    net.peer(1)
+        .chain
+        .add_blocks(1000, EachBlockWith::UncleAndTransaction);
+    net.sync();
+    assert_eq!(
+        *net.peer(0).chain.blocks.read(),
+        *net.peer(1).chain.blocks.read()
+    );
+
+    let status = net.peer(1).sync.read().status();
+    assert!(status.num_compressed_packets_sent > 0);
+    assert!(status.compressed_bytes_saved > 0);
+}
+
+#[test]
+fn standard_block_bodies_between_par_v2_peers() {
+    ::env_logger::try_init().ok();
+    let mut net = TestNet::new(2);
+    net.peer(1)
+        .chain
+        .add_blocks(1000, EachBlockWith::UncleAndTransaction);
+    net.sync();
+    assert_eq!(
+        *net.peer(0).chain.blocks.read(),
+        *net.peer(1).chain.blocks.read()
+    );
+
+    let status = net.peer(1).sync.read().status();
+    assert_eq!(status.num_compressed_packets_sent, 0);
+    assert_eq!(status.compressed_bytes_saved, 0);
+}
+
+#[test]
+fn probes_for_a_branch_without_the_rejected_block() {
+    ::env_logger::try_init().ok();
+    let mut config = SyncConfig::default();
+    config.branch_probe_interval = Duration::from_secs(0);
+    let mut net = TestNet::new_with_config(5, config);
+    for peer in 0..5 {
+        net.peer(peer).chain.add_blocks(10, EachBlockWith::Nothing);
+    }
+    // most peers are on a longer branch starting with a block peers 0 and 4 reject
+    for peer in 1..4 {
+        net.peer(peer).chain.add_blocks(30, EachBlockWith::Uncle);
+    }
+    net.peer(4).chain.add_blocks(20, EachBlockWith::Nothing);
+    let rejected = net.peer(1).chain.block_hash(BlockId::Number(11)).unwrap();
+    net.peer(0).chain.reject_block(rejected);
+    net.peer(4).chain.reject_block(rejected);
+    let valid_chain = net.peer(4).chain.numbers.read().clone();
+
+    net.sync_steps(1000);
+
+    assert_eq!(&*net.peer(0).chain.numbers.read(), &valid_chain);
+    let status = net.peer(0).sync.read().status().branch_probe;
+    assert_eq!(status.rejected.len(), 1);
+    assert_eq!(status.rejected[0].hash, rejected);
+    assert_eq!(status.rejected[0].number, 11);
+    assert!(status.rejected[0].rejections > 0);
+}
+
+fn deep_fork_net(fork_depth: usize) -> TestNet<EthPeer<TestBlockChainClient>> {
+    let mut config = SyncConfig::default();
+    config.max_reorg_depth = 10;
+    let mut net = TestNet::new_with_config(2, config);
+    net.peer(0).chain.add_blocks(30, EachBlockWith::Nothing);
+    net.peer(1).chain.add_blocks(30, EachBlockWith::Nothing);
+    // peer 0 forked `fork_depth` blocks below its best block from the heavier chain of peer 1
+    net.peer(0)
+        .chain
+        .add_blocks(fork_depth, EachBlockWith::Nothing);
+    net.peer(1).chain.add_blocks(20, EachBlockWith::Uncle);
+    net
+}
+
+#[test]
+fn syncs_fork_within_max_reorg_depth() {
+    ::env_logger::try_init().ok();
+    let mut net = deep_fork_net(9);
+    let peer1_chain = net.peer(1).chain.numbers.read().clone();
+
+    net.sync_steps(1000);
+
+    assert_eq!(&*net.peer(0).chain.numbers.read(), &peer1_chain);
+    assert_eq!(net.peer(0).sync.read().status().deep_reorg, None);
+}
+
+#[test]
+fn stops_at_fork_beyond_max_reorg_depth_until_allowed() {
+    ::env_logger::try_init().ok();
+    let mut net = deep_fork_net(11);
+    let peer0_chain = net.peer(0).chain.numbers.read().clone();
+    let peer1_chain = net.peer(1).chain.numbers.read().clone();
+
+    net.sync_steps(1000);
+
+    assert_eq!(&*net.peer(0).chain.numbers.read(), &peer0_chain);
+    let reorg = net.peer(0).sync.read().status().deep_reorg.unwrap();
+    assert_eq!(reorg.number, 31);
+    assert_eq!(reorg.best, 41);
+    assert_eq!(reorg.peers, vec![1]);
+
+    net.peer(0).sync.write().set_allow_deep_reorg(true);
+    net.sync_steps(1000);
+
+    assert_eq!(&*net.peer(0).chain.numbers.read(), &peer1_chain);
+    assert_eq!(net.peer(0).sync.read().status().deep_reorg, None);
+}
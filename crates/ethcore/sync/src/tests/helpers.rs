@@ -67,8 +67,17 @@ where
     pub queue: &'p RwLock<VecDeque<TestPacket>>,
     pub sender: Option<PeerId>,
     pub to_disconnect: HashSet<PeerId>,
+    pub to_avoid: HashSet<PeerId>,
     pub packets: Vec<TestPacket>,
     pub peers_info: HashMap<PeerId, String>,
+    /// Negotiated version of the ETH protocol reported for every peer.
+    pub eth_protocol_version: u8,
+    /// Negotiated version of the PAR protocol reported for every peer.
+    pub par_protocol_version: u8,
+    /// Payload limit of the session reported for every peer.
+    pub max_payload_size: usize,
+    /// Failures reported for sends and responses to the given peers.
+    pub send_failures: HashMap<PeerId, fn() -> network::ErrorKind>,
     overlay: RwLock<HashMap<BlockNumber, Bytes>>,
 }
 
@@ -89,9 +98,21 @@ where
             queue,
             sender,
             to_disconnect: HashSet::new(),
+            to_avoid: HashSet::new(),
             overlay: RwLock::new(HashMap::new()),
             packets: Vec::new(),
             peers_info: HashMap::new(),
+            eth_protocol_version: ETH_PROTOCOL_VERSION_66.0,
+            par_protocol_version: PAR_PROTOCOL_VERSION_2.0,
+            max_payload_size: network::MAX_PAYLOAD_SIZE,
+            send_failures: HashMap::new(),
+        }
+    }
+
+    fn send_failure(&self, peer_id: PeerId) -> Result<(), network::Error> {
+        match self.send_failures.get(&peer_id) {
+            Some(kind) => Err(kind().into()),
+            None => Ok(()),
         }
     }
 }
@@ -115,15 +136,25 @@ where
         self.disconnect_peer(peer_id);
     }
 
+    fn ban_peer(&mut self, peer_id: PeerId, _reason: &str) {
+        self.disconnect_peer(peer_id);
+    }
+
     fn disconnect_peer(&mut self, peer_id: PeerId) {
         self.to_disconnect.insert(peer_id);
     }
 
+    fn avoid_peer(&mut self, peer_id: PeerId) {
+        self.to_avoid.insert(peer_id);
+        self.disconnect_peer(peer_id);
+    }
+
     fn is_expired(&self) -> bool {
         false
     }
 
     fn respond(&mut self, packet_id: PacketId, data: Vec<u8>) -> Result<(), network::Error> {
+        self.send_failure(self.sender.unwrap())?;
         self.packets.push(TestPacket {
             data,
             packet_id,
@@ -138,6 +169,7 @@ where
         packet_id: SyncPacket,
         data: Vec<u8>,
     ) -> Result<(), network::Error> {
+        self.send_failure(peer_id)?;
         self.packets.push(TestPacket {
             data,
             packet_id: packet_id.id(),
@@ -146,6 +178,22 @@ where
         Ok(())
     }
 
+    fn send_protocol(
+        &mut self,
+        _protocol: ProtocolId,
+        peer_id: PeerId,
+        packet_id: PacketId,
+        data: Vec<u8>,
+    ) -> Result<(), network::Error> {
+        self.send_failure(peer_id)?;
+        self.packets.push(TestPacket {
+            data,
+            packet_id,
+            recipient: peer_id,
+        });
+        Ok(())
+    }
+
     fn chain(&self) -> &dyn BlockChainClient {
         self.chain
     }
@@ -168,11 +216,15 @@ where
         None
     }
 
+    fn peer_max_payload_size(&self, _peer_id: PeerId) -> usize {
+        self.max_payload_size
+    }
+
     fn protocol_version(&self, protocol: ProtocolId, _peer_id: PeerId) -> u8 {
         if protocol == PAR_PROTOCOL {
-            PAR_PROTOCOL_VERSION_2.0
+            self.par_protocol_version
         } else {
-            ETH_PROTOCOL_VERSION_66.0
+            self.eth_protocol_version
         }
     }
 
@@ -252,6 +304,8 @@ where
     pub sync: RwLock<ChainSync>,
     pub queue: RwLock<VecDeque<TestPacket>>,
     pub io_queue: RwLock<VecDeque<ChainMessageType>>,
+    /// Negotiated version of the PAR protocol reported to the sync.
+    pub par_protocol_version: u8,
     new_blocks_queue: RwLock<VecDeque<NewBlockMessage>>,
 }
 
@@ -259,6 +313,12 @@ impl<C> EthPeer<C>
 where
     C: FlushingBlockChainClient,
 {
+    fn io(&self, sender: Option<PeerId>) -> TestIo<C> {
+        let mut io = TestIo::new(&*self.chain, &self.snapshot_service, &self.queue, sender);
+        io.par_protocol_version = self.par_protocol_version;
+        io
+    }
+
     fn is_io_queue_empty(&self) -> bool {
         self.io_queue.read().is_empty()
     }
@@ -268,7 +328,7 @@ where
     }
 
     fn process_io_message(&self, message: ChainMessageType) {
-        let mut io = TestIo::new(&*self.chain, &self.snapshot_service, &self.queue, None);
+        let mut io = self.io(None);
         match message {
             ChainMessageType::Consensus(data) => {
                 self.sync.write().propagate_consensus_packet(&mut io, data)
@@ -277,7 +337,7 @@ where
     }
 
     fn process_new_block_message(&self, message: NewBlockMessage) {
-        let mut io = TestIo::new(&*self.chain, &self.snapshot_service, &self.queue, None);
+        let mut io = self.io(None);
         self.sync.write().chain_new_blocks(
             &mut io,
             &message.imported,
@@ -295,34 +355,18 @@ impl<C: FlushingBlockChainClient> Peer for EthPeer<C> {
 
     fn on_connect(&self, other: PeerId) {
         self.sync.write().update_targets(&*self.chain);
-        self.sync.write().on_peer_connected(
-            &mut TestIo::new(
-                &*self.chain,
-                &self.snapshot_service,
-                &self.queue,
-                Some(other),
-            ),
-            other,
-        );
+        self.sync
+            .write()
+            .on_peer_connected(&mut self.io(Some(other)), other);
     }
 
     fn on_disconnect(&self, other: PeerId) {
-        let mut io = TestIo::new(
-            &*self.chain,
-            &self.snapshot_service,
-            &self.queue,
-            Some(other),
-        );
+        let mut io = self.io(Some(other));
         self.sync.write().on_peer_aborting(&mut io, other);
     }
 
     fn receive_message(&self, from: PeerId, msg: TestPacket) -> HashSet<PeerId> {
-        let mut io = TestIo::new(
-            &*self.chain,
-            &self.snapshot_service,
-            &self.queue,
-            Some(from),
-        );
+        let mut io = self.io(Some(from));
         SyncSupplier::dispatch_packet(&self.sync, &mut io, from, msg.packet_id, &msg.data);
         self.chain.flush();
         io.to_disconnect.clone()
@@ -338,7 +382,7 @@ impl<C: FlushingBlockChainClient> Peer for EthPeer<C> {
     }
 
     fn sync_step(&self) {
-        let mut io = TestIo::new(&*self.chain, &self.snapshot_service, &self.queue, None);
+        let mut io = self.io(None);
         self.chain.flush();
         self.sync.write().maintain_peers(&mut io);
         self.sync.write().maintain_sync(&mut io);
@@ -347,12 +391,7 @@ impl<C: FlushingBlockChainClient> Peer for EthPeer<C> {
     }
 
     fn restart_sync(&self) {
-        self.sync.write().restart(&mut TestIo::new(
-            &*self.chain,
-            &self.snapshot_service,
-            &self.queue,
-            None,
-        ));
+        self.sync.write().restart(&mut self.io(None));
     }
 
     fn process_all_io_messages(&self) {
@@ -412,6 +451,7 @@ impl TestNet<EthPeer<TestBlockChainClient>> {
                 miner: Arc::new(Miner::new_for_tests(&Spec::new_test(), None)),
                 queue: RwLock::new(VecDeque::new()),
                 io_queue: RwLock::new(VecDeque::new()),
+                par_protocol_version: PAR_PROTOCOL_VERSION_2.0,
                 new_blocks_queue: RwLock::new(VecDeque::new()),
             }));
         }
@@ -467,6 +507,7 @@ impl TestNet<EthPeer<EthcoreClient>> {
             miner,
             queue: RwLock::new(VecDeque::new()),
             io_queue: RwLock::new(VecDeque::new()),
+            par_protocol_version: PAR_PROTOCOL_VERSION_2.0,
             new_blocks_queue: RwLock::new(VecDeque::new()),
         });
         peer.chain.add_notify(peer.clone());
@@ -570,15 +611,9 @@ where
 impl<C: FlushingBlockChainClient> TestNet<EthPeer<C>> {
     pub fn trigger_chain_new_blocks(&mut self, peer_id: usize) {
         let peer = &mut self.peers[peer_id];
-        peer.sync.write().chain_new_blocks(
-            &mut TestIo::new(&*peer.chain, &peer.snapshot_service, &peer.queue, None),
-            &[],
-            &[],
-            &[],
-            &[],
-            &[],
-            &[],
-        );
+        peer.sync
+            .write()
+            .chain_new_blocks(&mut peer.io(None), &[], &[], &[], &[], &[], &[]);
     }
 }
 
@@ -23,14 +23,20 @@ use ethcore::{
 use ethereum_types::H256;
 use hash::keccak;
 use parking_lot::Mutex;
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 use types::BlockNumber;
 use SyncConfig;
+use SyncEventKind;
+use SyncState;
 use WarpSync;
 
 pub struct TestSnapshotService {
     manifest: Option<ManifestData>,
     chunks: HashMap<H256, Bytes>,
+    served_chunks: Mutex<Vec<H256>>,
 
     restoration_manifest: Mutex<Option<ManifestData>>,
     state_restoration_chunks: Mutex<HashMap<H256, Bytes>>,
@@ -42,6 +48,7 @@ impl TestSnapshotService {
         TestSnapshotService {
             manifest: None,
             chunks: HashMap::new(),
+            served_chunks: Mutex::new(Vec::new()),
             restoration_manifest: Mutex::new(None),
             state_restoration_chunks: Mutex::new(HashMap::new()),
             block_restoration_chunks: Mutex::new(HashMap::new()),
@@ -77,11 +84,36 @@ impl TestSnapshotService {
         TestSnapshotService {
             manifest: Some(manifest),
             chunks,
+            served_chunks: Mutex::new(Vec::new()),
             restoration_manifest: Mutex::new(None),
             state_restoration_chunks: Mutex::new(HashMap::new()),
             block_restoration_chunks: Mutex::new(HashMap::new()),
         }
     }
+
+    /// Starts a restoration of `manifest` with the given chunks already restored,
+    /// emulating a node restarted midway through a restore.
+    pub fn prime_restoration(&self, manifest: &ManifestData, chunks: &HashMap<H256, Bytes>) {
+        *self.restoration_manifest.lock() = Some(manifest.clone());
+        for (hash, chunk) in chunks {
+            if manifest.state_hashes.contains(hash) {
+                self.state_restoration_chunks
+                    .lock()
+                    .insert(*hash, chunk.clone());
+            } else if manifest.block_hashes.contains(hash) {
+                self.block_restoration_chunks
+                    .lock()
+                    .insert(*hash, chunk.clone());
+            }
+        }
+    }
+
+    /// Drops one of the chunks referenced by the manifest, emulating a partially pruned snapshot.
+    pub fn remove_first_chunk(&mut self) {
+        if let Some(hash) = self.manifest.as_ref().and_then(|m| m.state_hashes.first()) {
+            self.chunks.remove(hash);
+        }
+    }
 }
 
 impl SnapshotService for TestSnapshotService {
@@ -100,13 +132,31 @@ impl SnapshotService for TestSnapshotService {
     }
 
     fn completed_chunks(&self) -> Option<Vec<H256>> {
-        Some(vec![])
+        let state_chunks = self.state_restoration_chunks.lock();
+        let block_chunks = self.block_restoration_chunks.lock();
+        Some(
+            state_chunks
+                .keys()
+                .chain(block_chunks.keys())
+                .copied()
+                .collect(),
+        )
     }
 
     fn chunk(&self, hash: H256) -> Option<Bytes> {
+        self.served_chunks.lock().push(hash);
         self.chunks.get(&hash).cloned()
     }
 
+    fn is_manifest_complete(&self) -> bool {
+        self.manifest.as_ref().is_some_and(|m| {
+            m.state_hashes
+                .iter()
+                .chain(m.block_hashes.iter())
+                .all(|h| self.chunks.contains_key(h))
+        })
+    }
+
     fn creation_status(&self) -> CreationStatus {
         CreationStatus::Inactive
     }
@@ -225,3 +275,112 @@ fn snapshot_sync() {
             .len()
     );
 }
+
+#[test]
+fn snapshot_sync_resumes_restoration() {
+    ::env_logger::try_init().ok();
+    let mut config = SyncConfig::default();
+    config.warp_sync = WarpSync::Enabled;
+    let mut net = TestNet::new_with_config(5, config);
+    let snapshot_service = Arc::new(TestSnapshotService::new_with_snapshot(
+        16,
+        H256::default(),
+        500000,
+    ));
+    for i in 0..4 {
+        net.peer_mut(i).snapshot_service = snapshot_service.clone();
+        net.peer(i).chain.add_blocks(1, EachBlockWith::Nothing);
+    }
+
+    // the restarting node restored half of the chunks before going down
+    let manifest = snapshot_service.manifest.clone().unwrap();
+    let restored: HashMap<H256, Bytes> = manifest.state_hashes[..4]
+        .iter()
+        .chain(manifest.block_hashes[..4].iter())
+        .map(|h| (*h, snapshot_service.chunks[h].clone()))
+        .collect();
+    let resumed = TestSnapshotService::new();
+    resumed.prime_restoration(&manifest, &restored);
+    net.peer_mut(4).snapshot_service = Arc::new(resumed);
+
+    net.sync_steps(50);
+
+    let missing: HashSet<H256> = manifest
+        .state_hashes
+        .iter()
+        .chain(manifest.block_hashes.iter())
+        .filter(|h| !restored.contains_key(h))
+        .copied()
+        .collect();
+    let served: HashSet<H256> = snapshot_service
+        .served_chunks
+        .lock()
+        .iter()
+        .copied()
+        .collect();
+    assert_eq!(served, missing);
+
+    let resumed = &net.peer(4).snapshot_service;
+    assert_eq!(resumed.state_restoration_chunks.lock().len(), 8);
+    assert_eq!(resumed.block_restoration_chunks.lock().len(), 8);
+    assert_eq!(resumed.restoration_status(), RestorationStatus::Inactive);
+}
+
+#[test]
+fn snapshot_sync_events() {
+    ::env_logger::try_init().ok();
+    let mut config = SyncConfig::default();
+    config.warp_sync = WarpSync::Enabled;
+    let mut net = TestNet::new_with_config(5, config);
+    let snapshot_service = Arc::new(TestSnapshotService::new_with_snapshot(
+        16,
+        H256::default(),
+        500000,
+    ));
+    for i in 0..4 {
+        net.peer_mut(i).snapshot_service = snapshot_service.clone();
+        net.peer(i).chain.add_blocks(1, EachBlockWith::Nothing);
+    }
+    let (tx, events) = crossbeam_channel::unbounded();
+    net.peer(4).sync.write().set_event_sender(Some(tx));
+    net.sync_steps(50);
+
+    let events: Vec<_> = events
+        .try_iter()
+        .map(|e| e.kind)
+        .filter(|kind| match *kind {
+            SyncEventKind::HighestBlock { .. } => false,
+            _ => true,
+        })
+        .collect();
+    let position = |kind: SyncEventKind| {
+        events
+            .iter()
+            .position(|e| *e == kind)
+            .unwrap_or_else(|| panic!("{kind:?} not emitted, got {events:?}"))
+    };
+    let manifest_requested = position(SyncEventKind::StateChanged {
+        from: SyncState::WaitingPeers,
+        to: SyncState::SnapshotManifest,
+    });
+    let manifest = position(SyncEventKind::SnapshotManifest {
+        block_number: 500000,
+        total_chunks: 16,
+    });
+    let downloading = position(SyncEventKind::StateChanged {
+        from: SyncState::SnapshotManifest,
+        to: SyncState::SnapshotData,
+    });
+    let downloaded = position(SyncEventKind::SnapshotProgress {
+        done_chunks: 16,
+        total_chunks: 16,
+    });
+    let restoring = position(SyncEventKind::StateChanged {
+        from: SyncState::SnapshotData,
+        to: SyncState::SnapshotWaiting,
+    });
+    assert!(manifest_requested < manifest);
+    assert!(manifest < downloading);
+    assert!(downloading < downloaded);
+    assert!(downloaded < restoring);
+}
@@ -14,45 +14,100 @@
 // You should have received a copy of the GNU General Public License
 // along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
 
-use api::TransactionStats;
+use api::{TransactionDirection, TransactionPeerStats, TransactionStats};
 use ethereum_types::{H256, H512};
 use fastmap::H256FastMap;
+use network::PeerId;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     hash::BuildHasher,
+    time::{Duration, SystemTime},
 };
 use types::BlockNumber;
 
 type NodeId = H512;
 
+/// Max number of peer exchanges kept per transaction.
+const MAX_PEER_RECORDS: usize = 64;
+/// Max number of transactions tracked in each of the stats maps.
+const MAX_TRACKED_HASHES: usize = 16_384;
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Stats {
     first_seen: BlockNumber,
+    first_seen_at: SystemTime,
     propagated_to: HashMap<NodeId, usize>,
+    peers: VecDeque<TransactionPeerStats>,
 }
 
 impl Stats {
-    pub fn new(number: BlockNumber) -> Self {
+    pub fn new(number: BlockNumber, now: SystemTime) -> Self {
         Stats {
             first_seen: number,
+            first_seen_at: now,
             propagated_to: Default::default(),
+            peers: Default::default(),
         }
     }
-}
 
-impl<'a> From<&'a Stats> for TransactionStats {
-    fn from(other: &'a Stats) -> Self {
+    fn record(
+        &mut self,
+        peer_id: PeerId,
+        node_id: Option<NodeId>,
+        direction: TransactionDirection,
+        now: SystemTime,
+    ) {
+        if self.peers.len() >= MAX_PEER_RECORDS {
+            self.peers.pop_front();
+        }
+        self.peers.push_back(TransactionPeerStats {
+            peer_id,
+            node_id,
+            direction,
+            timestamp: now,
+        });
+    }
+
+    /// Convert into RPC-facing stats. The per-peer breakdown is included only if `verbose` is set.
+    pub fn to_transaction_stats(&self, verbose: bool) -> TransactionStats {
         TransactionStats {
-            first_seen: other.first_seen,
-            propagated_to: other
+            first_seen: self.first_seen,
+            propagated_to: self
                 .propagated_to
                 .iter()
                 .map(|(hash, size)| (*hash, *size))
                 .collect(),
+            peers: if verbose {
+                Some(self.peers.iter().cloned().collect())
+            } else {
+                None
+            },
         }
     }
 }
 
+/// Get stats for given hash, start tracking it if not known yet.
+/// When the map is full, the oldest eighth of the entries is dropped.
+fn entry<'a>(
+    map: &'a mut H256FastMap<Stats>,
+    hash: &H256,
+    current_block_num: BlockNumber,
+    now: SystemTime,
+) -> &'a mut Stats {
+    if !map.contains_key(hash) && map.len() >= MAX_TRACKED_HASHES {
+        let mut by_age: Vec<_> = map
+            .iter()
+            .map(|(hash, stats)| (stats.first_seen_at, *hash))
+            .collect();
+        by_age.sort();
+        for (_, hash) in by_age.into_iter().take(MAX_TRACKED_HASHES / 8) {
+            map.remove(&hash);
+        }
+    }
+    map.entry(*hash)
+        .or_insert_with(|| Stats::new(current_block_num, now))
+}
+
 #[derive(Debug, Default)]
 pub struct TransactionsStats {
     pending_transactions: H256FastMap<Stats>,
@@ -65,21 +120,40 @@ impl TransactionsStats {
         &mut self,
         hash: &H256,
         is_new: bool,
+        peer_id: PeerId,
         enode_id: Option<NodeId>,
         current_block_num: BlockNumber,
+        now: SystemTime,
     ) {
-        let enode_id = enode_id.unwrap_or_default();
         let stats = if is_new {
-            self.new_transactions
-                .entry(*hash)
-                .or_insert_with(|| Stats::new(current_block_num))
+            entry(&mut self.new_transactions, hash, current_block_num, now)
         } else {
-            self.pending_transactions
-                .entry(*hash)
-                .or_insert_with(|| Stats::new(current_block_num))
+            entry(&mut self.pending_transactions, hash, current_block_num, now)
         };
-        let count = stats.propagated_to.entry(enode_id).or_insert(0);
+        let count = stats
+            .propagated_to
+            .entry(enode_id.unwrap_or_default())
+            .or_insert(0);
         *count = count.saturating_add(1);
+        stats.record(peer_id, enode_id, TransactionDirection::Sent, now);
+    }
+
+    /// Records that a transaction was received from given peer.
+    /// Received transactions are tracked as pending until they leave the queue.
+    pub fn received(
+        &mut self,
+        hash: &H256,
+        peer_id: PeerId,
+        enode_id: Option<NodeId>,
+        current_block_num: BlockNumber,
+        now: SystemTime,
+    ) {
+        entry(&mut self.pending_transactions, hash, current_block_num, now).record(
+            peer_id,
+            enode_id,
+            TransactionDirection::Received,
+            now,
+        );
     }
 
     /// Returns propagation stats for given hash or `None` if hash is not known or
@@ -138,14 +212,25 @@ impl TransactionsStats {
             self.new_transactions.remove(&hash);
         }
     }
+
+    /// Retains only new transactions first seen within `retention` before `now`.
+    pub fn retain_new_since(&mut self, now: SystemTime, retention: Duration) {
+        self.new_transactions.retain(|_, stats| {
+            now.duration_since(stats.first_seen_at)
+                .map_or(true, |age| age <= retention)
+        });
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use super::{Stats, TransactionsStats};
+    use super::{TransactionDirection, TransactionsStats};
     use ethereum_types::{H256, H512};
-    use std::collections::{HashMap, HashSet};
+    use std::{
+        collections::{HashMap, HashSet},
+        time::{Duration, SystemTime},
+    };
 
     #[test]
     fn should_keep_track_of_propagations() {
@@ -153,27 +238,30 @@ mod tests {
         let hash = H256::from_low_u64_be(5);
         let enodeid1 = H512::from_low_u64_be(2);
         let enodeid2 = H512::from_low_u64_be(5);
+        let now = SystemTime::now();
 
         {
             // given
             let mut stats = TransactionsStats::default();
 
             // when
-            stats.propagated(&hash, false, Some(enodeid1), 5);
-            stats.propagated(&hash, false, Some(enodeid1), 10);
-            stats.propagated(&hash, false, Some(enodeid2), 15);
+            stats.propagated(&hash, false, 1, Some(enodeid1), 5, now);
+            stats.propagated(&hash, false, 1, Some(enodeid1), 10, now);
+            stats.propagated(&hash, false, 2, Some(enodeid2), 15, now);
 
             // then
-            let pending_stats = stats.get_pending(&hash);
+            let pending_stats = stats
+                .get_pending(&hash)
+                .map(|s| (s.first_seen, s.propagated_to.clone()));
             assert_eq!(
                 pending_stats,
-                Some(&Stats {
-                    first_seen: 5,
-                    propagated_to: hash_map![
+                Some((
+                    5,
+                    hash_map![
                         enodeid1 => 2,
                         enodeid2 => 1
-                    ],
-                }),
+                    ]
+                )),
                 "Pending transactions propagation should update pending_transactions stats"
             );
 
@@ -189,9 +277,9 @@ mod tests {
             let mut stats = TransactionsStats::default();
 
             // when
-            stats.propagated(&hash, true, Some(enodeid1), 5);
-            stats.propagated(&hash, true, Some(enodeid1), 10);
-            stats.propagated(&hash, true, Some(enodeid2), 15);
+            stats.propagated(&hash, true, 1, Some(enodeid1), 5, now);
+            stats.propagated(&hash, true, 1, Some(enodeid1), 10, now);
+            stats.propagated(&hash, true, 2, Some(enodeid2), 15, now);
 
             // then
             let pending_stats = stats.get_pending(&hash);
@@ -200,16 +288,18 @@ mod tests {
                 "New transactions propagation should not update pending_transactions stats"
             );
 
-            let new_stats = stats.get_new(&hash);
+            let new_stats = stats
+                .get_new(&hash)
+                .map(|s| (s.first_seen, s.propagated_to.clone()));
             assert_eq!(
                 new_stats,
-                Some(&Stats {
-                    first_seen: 5,
-                    propagated_to: hash_map![
+                Some((
+                    5,
+                    hash_map![
                         enodeid1 => 2,
                         enodeid2 => 1
-                    ],
-                }),
+                    ]
+                )),
                 "New transactions propagation should update new_transactions stats"
             );
         }
@@ -221,7 +311,7 @@ mod tests {
         let mut stats = TransactionsStats::default();
         let hash = H256::from_low_u64_be(5);
         let enodeid1 = H512::from_low_u64_be(5);
-        stats.propagated(&hash, false, Some(enodeid1), 10);
+        stats.propagated(&hash, false, 1, Some(enodeid1), 10, SystemTime::now());
 
         // when
         stats.retain_pending(&HashSet::new());
@@ -235,6 +325,7 @@ mod tests {
     fn should_remove_expired_new_hashes_from_tracking() {
         //given
         let mut stats = TransactionsStats::default();
+        let now = SystemTime::now();
 
         let hash1 = H256::from_low_u64_be(5);
         let hash2 = H256::from_low_u64_be(6);
@@ -244,9 +335,9 @@ mod tests {
         let enodeid2 = H512::from_low_u64_be(6);
         let enodeid3 = H512::from_low_u64_be(7);
 
-        stats.propagated(&hash1, true, Some(enodeid1), 5);
-        stats.propagated(&hash2, true, Some(enodeid2), 6);
-        stats.propagated(&hash3, true, Some(enodeid3), 7);
+        stats.propagated(&hash1, true, 1, Some(enodeid1), 5, now);
+        stats.propagated(&hash2, true, 2, Some(enodeid2), 6, now);
+        stats.propagated(&hash3, true, 3, Some(enodeid3), 7, now);
 
         // when
         stats.retain_new(10, 3);
@@ -255,13 +346,92 @@ mod tests {
         assert_eq!(stats.get_new(&hash1), None);
         assert_eq!(stats.get_new(&hash2), None);
         assert_eq!(
-            stats.get_new(&hash3),
-            Some(&Stats {
-                first_seen: 7,
-                propagated_to: hash_map![
+            stats
+                .get_new(&hash3)
+                .map(|s| (s.first_seen, s.propagated_to.clone())),
+            Some((
+                7,
+                hash_map![
                     enodeid3 => 1
-                ],
-            }),
+                ]
+            )),
         )
     }
+
+    #[test]
+    fn should_keep_per_peer_breakdown_within_retention() {
+        // given
+        let mut stats = TransactionsStats::default();
+        let retention = Duration::from_secs(600);
+        let start = SystemTime::now();
+        let hash = H256::from_low_u64_be(5);
+        let later = H256::from_low_u64_be(6);
+
+        // when
+        for peer in 0..3 {
+            let at = start + Duration::from_secs(peer as u64);
+            stats.propagated(
+                &hash,
+                true,
+                peer,
+                Some(H512::from_low_u64_be(peer as u64)),
+                1,
+                at,
+            );
+        }
+        stats.propagated(&later, true, 0, None, 1, start + Duration::from_secs(300));
+
+        // then
+        let breakdown = stats.get_new(&hash).unwrap().to_transaction_stats(true);
+        let peers = breakdown.peers.unwrap();
+        assert_eq!(peers.len(), 3);
+        for (i, record) in peers.iter().enumerate() {
+            assert_eq!(record.peer_id, i);
+            assert_eq!(record.node_id, Some(H512::from_low_u64_be(i as u64)));
+            assert_eq!(record.direction, TransactionDirection::Sent);
+            assert_eq!(record.timestamp, start + Duration::from_secs(i as u64));
+        }
+        assert!(stats
+            .get_new(&hash)
+            .unwrap()
+            .to_transaction_stats(false)
+            .peers
+            .is_none());
+
+        // when
+        stats.retain_new_since(start + retention, retention);
+        assert!(stats.get_new(&hash).is_some());
+        stats.retain_new_since(start + retention + Duration::from_secs(10), retention);
+
+        // then
+        assert_eq!(stats.get_new(&hash), None);
+        assert!(stats.get_new(&later).is_some());
+    }
+
+    #[test]
+    fn should_bound_per_peer_breakdown() {
+        // given
+        let mut stats = TransactionsStats::default();
+        let hash = H256::from_low_u64_be(5);
+        let now = SystemTime::now();
+
+        // when
+        stats.received(&hash, 0, None, 1, now);
+        for peer in 1..=super::MAX_PEER_RECORDS {
+            stats.propagated(&hash, false, peer, None, 1, now);
+        }
+
+        // then
+        let peers = stats
+            .get_pending(&hash)
+            .unwrap()
+            .to_transaction_stats(true)
+            .peers
+            .unwrap();
+        assert_eq!(peers.len(), super::MAX_PEER_RECORDS);
+        assert!(peers
+            .iter()
+            .all(|r| r.direction == TransactionDirection::Sent));
+        assert_eq!(peers[0].peer_id, 1);
+    }
 }
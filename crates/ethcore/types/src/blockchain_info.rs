@@ -44,6 +44,8 @@ pub struct BlockChainInfo {
     pub first_block_hash: Option<H256>,
     /// Number of the first block on the best sequence.
     pub first_block_number: Option<BlockNumber>,
+    /// Earliest block the client intends to keep, older blocks are pruned.
+    pub retention_boundary: Option<BlockNumber>,
 }
 
 impl BlockChainInfo {
@@ -97,6 +97,25 @@ pub enum Error {
     SenderIsNotEOA,
 }
 
+impl Error {
+    /// Whether the transaction was refused by the local pool policy (limits, minimal gas price,
+    /// bans or permissions) rather than for being invalid or outdated.
+    pub fn is_policy_rejection(&self) -> bool {
+        matches!(
+            *self,
+            Error::LimitReached
+                | Error::InsufficientGasPrice { .. }
+                | Error::GasPriceLowerThanBaseFee { .. }
+                | Error::TooCheapToReplace { .. }
+                | Error::SenderBanned
+                | Error::RecipientBanned
+                | Error::CodeBanned
+                | Error::NotAllowed
+                | Error::TooBig
+        )
+    }
+}
+
 impl From<crypto::publickey::Error> for Error {
     fn from(err: crypto::publickey::Error) -> Self {
         Error::InvalidSignature(format!("{err}"))
@@ -122,12 +122,20 @@ pub struct Params {
     /// See `CommonParams` docs.
     pub eip3607_transition: Option<Uint>,
     /// See `CommonParams` docs.
+    pub eip2935_transition: Option<Uint>,
+    /// See `CommonParams` docs.
+    pub eip2935_contract_address: Option<Address>,
+    /// See `CommonParams` docs.
+    pub eip2935_blockhash_gas: Option<Uint>,
+    /// See `CommonParams` docs.
     pub dust_protection_transition: Option<Uint>,
     /// See `CommonParams` docs.
     pub nonce_cap_increment: Option<Uint>,
     /// See `CommonParams` docs.
     pub remove_dust_contracts: Option<bool>,
     /// See `CommonParams` docs.
+    pub constant_time_evm: Option<bool>,
+    /// See `CommonParams` docs.
     #[serde(deserialize_with = "uint::validate_non_zero")]
     pub gas_limit_bound_divisor: Uint,
     /// See `CommonParams` docs.
@@ -190,7 +198,8 @@ mod tests {
 			"gasLimitBoundDivisor": "0x20",
 			"maxCodeSize": "0x1000",
 			"wasmActivationTransition": "0x1010",
-            "wasmDisableTransition": "0x2010"
+            "wasmDisableTransition": "0x2010",
+			"constantTimeEvm": true
 		}"#;
 
         let deserialized: Params = serde_json::from_str(s).unwrap();
@@ -213,6 +222,7 @@ mod tests {
             deserialized.wasm_disable_transition,
             Some(Uint(U256::from(0x2010)))
         );
+        assert_eq!(deserialized.constant_time_evm, Some(true));
     }
 
     #[test]
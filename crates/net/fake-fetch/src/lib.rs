@@ -18,11 +18,59 @@ extern crate fetch;
 extern crate futures;
 extern crate hyper;
 
-use fetch::{ClientCompatExt, Fetch, FetchResult01, Request, Url};
-use futures::{future, Future, TryFutureExt};
+use fetch::{Fetch, Request, Url};
+use futures::{future, Future};
 use http::StatusCode;
 use hyper::Body;
-use std::pin::Pin;
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// A canned response served by `FakeFetch`.
+#[derive(Clone, Debug)]
+pub struct FakeResponse {
+    status: StatusCode,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl FakeResponse {
+    /// An empty response with the given status.
+    pub fn new(status: StatusCode) -> Self {
+        FakeResponse {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    /// Add a header, may be called more than once for the same name.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Respond with `body`.
+    pub fn with_body(mut self, body: &str) -> Self {
+        self.body = body.to_owned();
+        self
+    }
+
+    fn into_hyper(self) -> hyper::Response<Body> {
+        let mut builder = hyper::Response::builder().status(self.status);
+        for (name, value) in &self.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        builder
+            .body(self.body.into())
+            .expect("Invalid header in a fake response")
+    }
+}
 
 #[derive(Clone, Default)]
 pub struct FakeFetch<T>
@@ -30,6 +78,9 @@ where
     T: Clone + Send + Sync,
 {
     val: Option<T>,
+    body: Option<String>,
+    responses: Arc<Mutex<VecDeque<FakeResponse>>>,
+    requests: Arc<AtomicUsize>,
 }
 
 impl<T> FakeFetch<T>
@@ -37,7 +88,38 @@ where
     T: Clone + Send + Sync,
 {
     pub fn new(t: Option<T>) -> Self {
-        FakeFetch { val: t }
+        FakeFetch {
+            val: t,
+            body: None,
+            responses: Default::default(),
+            requests: Default::default(),
+        }
+    }
+
+    /// Respond with `body` instead of placeholder content.
+    pub fn with_body(mut self, body: &str) -> Self {
+        self.body = Some(body.to_owned());
+        self
+    }
+
+    /// Queue `response` for the next request, queued responses are served in order
+    /// before falling back to the default one.
+    pub fn with_response(self, response: FakeResponse) -> Self {
+        self.push_response(response);
+        self
+    }
+
+    /// Queue `response` for a request still to come, shared with all clones of this fetcher.
+    pub fn push_response(&self, response: FakeResponse) {
+        self.responses
+            .lock()
+            .expect("FakeFetch lock poisoned")
+            .push_back(response);
+    }
+
+    /// Number of requests made through this fetcher and its clones.
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
     }
 }
 
@@ -49,9 +131,20 @@ where
         Pin<Box<dyn Future<Output = Result<fetch::Response, fetch::Error>> + Send + 'static>>;
 
     fn fetch(&self, request: Request, abort: fetch::Abort) -> Self::Result {
+        self.requests.fetch_add(1, Ordering::SeqCst);
         let u = request.url().clone();
+        let queued = self
+            .responses
+            .lock()
+            .expect("FakeFetch lock poisoned")
+            .pop_front();
+        if let Some(response) = queued {
+            let r = response.into_hyper();
+            return Box::pin(future::ready(Ok(fetch::client::Response::new(u, r, abort))));
+        }
         Box::pin(future::ready(if self.val.is_some() {
-            let r = hyper::Response::new("Some content".into());
+            let body = self.body.clone().unwrap_or_else(|| "Some content".into());
+            let r = hyper::Response::new(body.into());
             Ok(fetch::client::Response::new(u, r, abort))
         } else {
             let r = hyper::Response::builder()
@@ -79,15 +172,41 @@ where
     }
 }
 
-impl<T: 'static> ClientCompatExt for FakeFetch<T>
-where
-    T: Clone + Send + Sync,
-{
-    fn get_compat(&self, url: &str, abort: fetch::Abort) -> FetchResult01 {
-        Box::new(self.get(url, abort).compat())
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fetch::Abort;
+    use futures::{executor::block_on, StreamExt};
+
+    #[test]
+    fn serves_queued_responses_in_order() {
+        let fetch = FakeFetch::new(Some(1)).with_response(
+            FakeResponse::new(StatusCode::TOO_MANY_REQUESTS)
+                .with_header("Retry-After", "30")
+                .with_header("X-RateLimit-Limit", "100"),
+        );
+        fetch.clone().push_response(
+            FakeResponse::new(StatusCode::OK)
+                .with_header("ETag", "\"v1\"")
+                .with_body("hello"),
+        );
+
+        let first = block_on(fetch.get("https://example.com", Abort::default())).unwrap();
+        assert_eq!(first.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            first.retry_after(),
+            Some(std::time::Duration::from_secs(30))
+        );
+        assert_eq!(first.header("x-ratelimit-limit"), Some("100"));
+
+        let mut second = block_on(fetch.get("https://example.com", Abort::default())).unwrap();
+        assert!(second.is_success());
+        assert_eq!(second.header("etag"), Some("\"v1\""));
+        assert_eq!(&block_on(second.next()).unwrap().unwrap()[..], b"hello");
 
-    fn fetch_compat(&self, request: Request, abort: fetch::Abort) -> FetchResult01 {
-        Box::new(self.fetch(request, abort).compat())
+        let third = block_on(fetch.get("https://example.com", Abort::default())).unwrap();
+        assert!(third.is_success());
+        assert_eq!(third.header("etag"), None);
+        assert_eq!(fetch.requests(), 3);
     }
 }
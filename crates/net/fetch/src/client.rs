@@ -23,27 +23,110 @@ use futures::{Future, FutureExt, Stream};
 use http::header::{self, IntoHeaderName};
 use http::{HeaderMap, HeaderValue, Method, StatusCode};
 use hyper::body::HttpBody;
-use hyper::client::HttpConnector;
 use std::pin::Pin;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
 use std::sync::mpsc::RecvTimeoutError;
 use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 use std::{cmp::min, fmt, io, thread, time::Duration};
 use tokio::sync::mpsc as tokio_mpsc;
 use url::Url;
 
+use crate::connector::{
+    ConnectConfig, ConnectStats, Connector, Resolve, SystemResolver, HAPPY_EYEBALLS_HEAD_START,
+};
+use crate::middleware::{self, Chain, Middleware};
+use crate::multipart::{Multipart, Part};
+use crate::tls::{self, SpkiHash, TlsConfig, TlsVersion};
+
 const MAX_SIZE: usize = 64 * 1024 * 1024;
 const MAX_SECS: Duration = Duration::from_secs(5);
 const MAX_REDR: usize = 5;
 
+/// Why a request was aborted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbortReason {
+    /// The calling code cancelled the request.
+    UserAbort,
+    /// The deadline of the operation passed.
+    Timeout,
+    /// The operation exceeded a size limit.
+    SizeLimit,
+    /// The application is shutting down.
+    Shutdown,
+}
+
+impl AbortReason {
+    fn to_u8(self) -> u8 {
+        match self {
+            AbortReason::UserAbort => 1,
+            AbortReason::Timeout => 2,
+            AbortReason::SizeLimit => 3,
+            AbortReason::Shutdown => 4,
+        }
+    }
+
+    // Flags set through a bare `AtomicBool` carry no reason, they count as user aborts.
+    fn from_u8(n: u8) -> AbortReason {
+        match n {
+            2 => AbortReason::Timeout,
+            3 => AbortReason::SizeLimit,
+            4 => AbortReason::Shutdown,
+            _ => AbortReason::UserAbort,
+        }
+    }
+}
+
+impl fmt::Display for AbortReason {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AbortReason::UserAbort => write!(fmt, "cancelled by the caller"),
+            AbortReason::Timeout => write!(fmt, "deadline exceeded"),
+            AbortReason::SizeLimit => write!(fmt, "size limit reached"),
+            AbortReason::Shutdown => write!(fmt, "shutting down"),
+        }
+    }
+}
+
+// Abort flag of an operation, set along with the reason of the abort. An operation is aborted
+// as well once any of its parents is.
+#[derive(Debug)]
+struct Flag {
+    aborted: Arc<AtomicBool>,
+    reason: AtomicU8,
+    parent: Option<Arc<Flag>>,
+}
+
+impl Flag {
+    fn new(aborted: Arc<AtomicBool>, parent: Option<Arc<Flag>>) -> Arc<Flag> {
+        Arc::new(Flag {
+            aborted,
+            reason: AtomicU8::new(0),
+            parent,
+        })
+    }
+
+    fn reason(&self) -> Option<AbortReason> {
+        if self.aborted.load(Ordering::SeqCst) {
+            return Some(AbortReason::from_u8(self.reason.load(Ordering::SeqCst)));
+        }
+        self.parent.as_ref().and_then(|parent| parent.reason())
+    }
+}
+
 /// A handle to abort requests.
 ///
 /// Requests are either aborted based on reaching thresholds such as
 /// maximum response size, timeouts or too many redirects, or else
 /// they can be aborted explicitly by the calling code.
+///
+/// An optional deadline bounds the whole operation, including redirects and the response
+/// body. Handles of sub-operations created by `child` can be aborted independently while
+/// still being aborted along with their parent.
 #[derive(Clone, Debug)]
 pub struct Abort {
-    abort: Arc<AtomicBool>,
+    flag: Arc<Flag>,
+    deadline: Option<Instant>,
     size: usize,
     time: Duration,
     redir: usize,
@@ -51,19 +134,15 @@ pub struct Abort {
 
 impl Default for Abort {
     fn default() -> Abort {
-        Abort {
-            abort: Arc::new(AtomicBool::new(false)),
-            size: MAX_SIZE,
-            time: MAX_SECS,
-            redir: MAX_REDR,
-        }
+        Abort::from(Arc::new(AtomicBool::new(false)))
     }
 }
 
 impl From<Arc<AtomicBool>> for Abort {
     fn from(a: Arc<AtomicBool>) -> Abort {
         Abort {
-            abort: a,
+            flag: Flag::new(a, None),
+            deadline: None,
             size: MAX_SIZE,
             time: MAX_SECS,
             redir: MAX_REDR,
@@ -72,9 +151,18 @@ impl From<Arc<AtomicBool>> for Abort {
 }
 
 impl Abort {
-    /// True if `abort` has been invoked.
+    /// True if `abort` has been invoked on this handle or a parent, or the deadline passed.
     pub fn is_aborted(&self) -> bool {
-        self.abort.load(Ordering::SeqCst)
+        self.reason().is_some()
+    }
+
+    /// The reason the operation was aborted for, if it was.
+    pub fn reason(&self) -> Option<AbortReason> {
+        self.flag.reason().or_else(|| {
+            self.deadline
+                .filter(|deadline| Instant::now() >= *deadline)
+                .map(|_| AbortReason::Timeout)
+        })
     }
 
     /// The maximum response body size.
@@ -92,9 +180,34 @@ impl Abort {
         self.redir
     }
 
-    /// Mark as aborted.
+    /// The deadline of the whole operation, if any.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Mark as aborted by the calling code.
     pub fn abort(&self) {
-        self.abort.store(true, Ordering::SeqCst)
+        self.abort_with(AbortReason::UserAbort)
+    }
+
+    /// Mark as aborted for `reason`. The first reason given is kept.
+    pub fn abort_with(&self, reason: AbortReason) {
+        let _ = self.flag.reason.compare_exchange(
+            0,
+            reason.to_u8(),
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+        self.flag.aborted.store(true, Ordering::SeqCst)
+    }
+
+    /// A handle for a sub-operation with the same limits and deadline. Aborting it leaves this
+    /// handle alone, aborting this handle aborts the child as well.
+    pub fn child(&self) -> Abort {
+        Abort {
+            flag: Flag::new(Arc::new(AtomicBool::new(false)), Some(self.flag.clone())),
+            ..self.clone()
+        }
     }
 
     /// Set the maximum reponse body size.
@@ -111,6 +224,36 @@ impl Abort {
     pub fn with_max_redirects(self, n: usize) -> Abort {
         Abort { redir: n, ..self }
     }
+
+    /// Set the deadline of the whole operation. A deadline set before, e.g. by a parent,
+    /// can only be brought forward.
+    pub fn with_deadline(self, deadline: Instant) -> Abort {
+        let deadline = self.deadline.map_or(deadline, |d| min(d, deadline));
+        Abort {
+            deadline: Some(deadline),
+            ..self
+        }
+    }
+
+    // Time left until the deadline or the maximum duration from now is reached.
+    fn time_left(&self) -> Duration {
+        self.deadline.map_or(self.time, |deadline| {
+            min(
+                self.time,
+                deadline.saturating_duration_since(Instant::now()),
+            )
+        })
+    }
+
+    // The error to fail the operation with once it is aborted.
+    fn error(&self) -> Error {
+        match self.reason() {
+            Some(AbortReason::Timeout) => Error::Timeout,
+            Some(AbortReason::SizeLimit) => Error::SizeLimit,
+            Some(reason) => Error::Aborted(reason),
+            None => Error::Aborted(AbortReason::UserAbort),
+        }
+    }
 }
 
 /// Types which retrieve content from some URL.
@@ -139,6 +282,7 @@ type ChanItem = Option<(Request, Abort, TxResponse)>;
 pub struct Client {
     runtime: tokio_mpsc::Sender<ChanItem>,
     refs: Arc<AtomicUsize>,
+    stats: Arc<ConnectStats>,
 }
 
 // When cloning a client we increment the internal reference counter.
@@ -148,6 +292,7 @@ impl Clone for Client {
         Client {
             runtime: self.runtime.clone(),
             refs: self.refs.clone(),
+            stats: self.stats.clone(),
         }
     }
 }
@@ -163,13 +308,102 @@ impl Drop for Client {
     }
 }
 
-impl Client {
-    /// Create a new fetch client.
-    pub fn new() -> Result<Self, Error> {
+/// Builder of a fetch client.
+pub struct ClientBuilder {
+    middleware: Vec<Arc<dyn Middleware>>,
+    resolver: Arc<dyn Resolve>,
+    connect: ConnectConfig,
+    #[cfg_attr(not(feature = "tls"), allow(dead_code))]
+    tls: TlsConfig,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        ClientBuilder {
+            middleware: Vec::new(),
+            resolver: Arc::new(SystemResolver),
+            connect: ConnectConfig::default(),
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+impl ClientBuilder {
+    /// Run the given middleware around every request.
+    /// Request hooks run in the given order, response hooks in reverse order.
+    pub fn with_middleware(mut self, middleware: Vec<Arc<dyn Middleware>>) -> Self {
+        self.middleware = middleware;
+        self
+    }
+
+    /// Race IPv4 and IPv6 connection attempts, giving the first resolved family a head start.
+    /// Enabled by default.
+    pub fn with_happy_eyeballs(mut self, enabled: bool) -> Self {
+        self.connect.happy_eyeballs = if enabled {
+            Some(HAPPY_EYEBALLS_HEAD_START)
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Reuse the address a host was last connected at for `ttl` instead of resolving it again.
+    /// A pinned address is dropped as soon as connecting to it fails.
+    pub fn with_dns_pinning(mut self, ttl: Duration) -> Self {
+        self.connect.dns_pinning = Some(ttl);
+        self
+    }
+
+    /// Give up on a single connection attempt after `timeout`.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Resolve host names with `resolver` instead of the system resolver.
+    pub fn with_resolver(mut self, resolver: Arc<dyn Resolve>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    /// Trust the root certificates of the PEM encoded `pem_bundle` in addition to the
+    /// bundled web PKI roots.
+    pub fn with_root_certificates<T: Into<Vec<u8>>>(mut self, pem_bundle: T) -> Self {
+        self.tls.root_certificates.push(pem_bundle.into());
+        self
+    }
+
+    /// Trust the bundled web PKI roots, enabled by default. Disable it to trust only the
+    /// root certificates given with `with_root_certificates`.
+    pub fn with_default_roots(mut self, enabled: bool) -> Self {
+        self.tls.default_roots = enabled;
+        self
+    }
+
+    /// Accept only servers whose leaf certificate has one of the given SHA-256 hashes of its
+    /// DER encoded SubjectPublicKeyInfo. Every connection is checked, the ones to hosts
+    /// redirected to as well; others fail with `Error::CertificatePinMismatch`.
+    pub fn with_pinned_spki(mut self, hashes: Vec<SpkiHash>) -> Self {
+        self.tls.pinned_spki = hashes;
+        self
+    }
+
+    /// Refuse TLS protocol versions older than `version`.
+    pub fn with_min_tls(mut self, version: TlsVersion) -> Self {
+        self.tls.min_version = version;
+        self
+    }
+
+    /// Start the fetch client.
+    pub fn build(self) -> Result<Client, Error> {
         let (tx_start, rx_start) = std::sync::mpsc::sync_channel(1);
         let (tx_proto, rx_proto) = tokio_mpsc::channel(64);
+        let stats = Arc::new(ConnectStats::default());
+        let connector = Connector::new(self.resolver, self.connect, stats.clone());
+        #[cfg(feature = "tls")]
+        let connector = connector.with_tls(tls::TlsConnector::new(&self.tls).map_err(Error::Tls)?);
 
-        Client::background_thread(tx_start, rx_proto)?;
+        Client::background_thread(tx_start, rx_proto, connector, self.middleware.into())?;
 
         match rx_start.recv_timeout(Duration::from_secs(10)) {
             Err(RecvTimeoutError::Timeout) => {
@@ -190,37 +424,83 @@ impl Client {
         Ok(Client {
             runtime: tx_proto,
             refs: Arc::new(AtomicUsize::new(1)),
+            stats,
         })
     }
+}
+
+impl Client {
+    /// Create a new fetch client.
+    pub fn new() -> Result<Self, Error> {
+        Client::builder().build()
+    }
+
+    /// Create a new fetch client running the given middleware around every request.
+    /// Request hooks run in the given order, response hooks in reverse order.
+    pub fn with_middleware(middleware: Vec<Arc<dyn Middleware>>) -> Result<Self, Error> {
+        Client::builder().with_middleware(middleware).build()
+    }
+
+    /// Configure a new fetch client.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    /// Successful connections per address family.
+    pub fn connect_stats(&self) -> &ConnectStats {
+        &self.stats
+    }
 
     async fn execute_request_with_redirects(
-        client: hyper::Client<HttpConnector>,
+        client: hyper::Client<Connector>,
         mut request: Request,
         abort: Abort,
+        middleware: Chain,
     ) -> Result<Response, Error> {
         let mut redirects = 0;
 
         loop {
             if abort.is_aborted() {
-                debug!(target: "fetch", "fetch of {} aborted", request.url());
-                return Err(Error::Aborted);
+                debug!(target: "fetch", "fetch of {} aborted: {:?}", request.url(), abort.reason());
+                return Err(abort.error());
             }
 
-            let url = request.url().clone();
-            let hyper_request = request.clone().into();
+            let outgoing = middleware::on_request(&middleware, request.clone());
+            let url = outgoing.url().clone();
+            let started = Instant::now();
+
+            // The deadline spans the whole redirect chain, so it bounds each hop as well.
+            let sent = client.request(outgoing.into());
+            let result = match abort.deadline() {
+                Some(deadline) => {
+                    let deadline = tokio::time::Instant::from_std(deadline);
+                    match tokio::time::timeout_at(deadline, sent).await {
+                        Ok(result) => result,
+                        Err(_) => {
+                            debug!(target: "fetch", "deadline of {} reached", request.url());
+                            return Err(Error::Timeout);
+                        }
+                    }
+                }
+                None => sent.await,
+            };
 
-            match client.request(hyper_request).await {
+            match result {
                 Ok(hyper_resp) => {
-                    let resp = Response::new(url, hyper_resp, abort.clone());
+                    middleware::on_response(
+                        &middleware,
+                        &url,
+                        hyper_resp.status(),
+                        started.elapsed(),
+                    );
+                    let resp = Response::new(url.clone(), hyper_resp, abort.clone());
 
                     if abort.is_aborted() {
-                        debug!(target: "fetch", "fetch of {} aborted", request.url());
-                        return Err(Error::Aborted);
+                        debug!(target: "fetch", "fetch of {} aborted: {:?}", request.url(), abort.reason());
+                        return Err(abort.error());
                     }
 
-                    if let Some((next_url, preserve_method)) =
-                        redirect_location(request.url().clone(), &resp)
-                    {
+                    if let Some((next_url, preserve_method)) = redirect_location(url, &resp) {
                         if redirects >= abort.max_redirects() {
                             return Err(Error::TooManyRedirects);
                         }
@@ -250,7 +530,7 @@ impl Client {
                         return Ok(resp);
                     }
                 }
-                Err(e) => return Err(Error::Hyper(e)),
+                Err(e) => return Err(e.into()),
             }
         }
     }
@@ -258,6 +538,8 @@ impl Client {
     fn background_thread(
         tx_start: TxStartup,
         mut rx_proto: tokio_mpsc::Receiver<ChanItem>,
+        connector: Connector,
+        middleware: Chain,
     ) -> io::Result<thread::JoinHandle<()>> {
         thread::Builder::new().name("fetch".into()).spawn(move || {
             let runtime = match tokio::runtime::Runtime::new() {
@@ -265,9 +547,7 @@ impl Client {
                 Err(e) => return tx_start.send(Err(e)).unwrap_or(()),
             };
 
-            // Create a basic HTTP connector using hyper's default connector
-            let https = hyper::client::connect::HttpConnector::new();
-            let hyper = hyper::Client::builder().build::<_, hyper::Body>(https);
+            let hyper = hyper::Client::builder().build::<_, hyper::Body>(connector);
 
             let future = async move {
                 while let Some(item) = rx_proto.recv().await {
@@ -278,16 +558,20 @@ impl Client {
 
                     trace!(target: "fetch", "new request to {}", request.url());
                     if abort.is_aborted() {
-                        sender.send(Err(Error::Aborted)).unwrap_or(());
+                        sender.send(Err(abort.error())).unwrap_or(());
                         continue;
                     }
                     let client = hyper.clone();
-                    let fut = Self::execute_request_with_redirects(client, request, abort).then(
-                        move |result| {
-                            sender.send(result).unwrap_or(());
-                            futures::future::ready(())
-                        },
-                    );
+                    let fut = Self::execute_request_with_redirects(
+                        client,
+                        request,
+                        abort,
+                        middleware.clone(),
+                    )
+                    .then(move |result| {
+                        sender.send(result).unwrap_or(());
+                        futures::future::ready(())
+                    });
                     tokio::spawn(fut);
                     trace!(target: "fetch", "waiting for next request ...");
                 }
@@ -311,10 +595,10 @@ impl Fetch for Client {
     fn fetch(&self, request: Request, abort: Abort) -> Self::Result {
         debug!(target: "fetch", "fetching: {:?}", request.url());
         if abort.is_aborted() {
-            return Box::pin(futures::future::ready(Err(Error::Aborted)));
+            return Box::pin(futures::future::ready(Err(abort.error())));
         }
         let (tx_res, rx_res) = oneshot::channel();
-        let maxdur = abort.max_duration();
+        let maxdur = abort.time_left();
         let sender = self.runtime.clone();
 
         let future = async move {
@@ -386,13 +670,20 @@ fn redirect_location(u: Url, r: &Response) -> Option<(Url, bool)> {
     }
 }
 
+/// Body of a request. Bodies are kept in memory, so that they can be sent again on redirects.
+#[derive(Debug, Clone)]
+enum RequestBody {
+    Bytes(Bytes),
+    Multipart(Multipart),
+}
+
 /// A wrapper for hyper::Request using Url and with methods.
 #[derive(Debug, Clone)]
 pub struct Request {
     url: Url,
     method: Method,
     headers: HeaderMap,
-    body: Bytes,
+    body: RequestBody,
 }
 
 impl Request {
@@ -402,7 +693,7 @@ impl Request {
             url,
             method,
             headers: HeaderMap::new(),
-            body: Default::default(),
+            body: RequestBody::Bytes(Bytes::new()),
         }
     }
 
@@ -431,9 +722,9 @@ impl Request {
         &mut self.headers
     }
 
-    /// Set the body of the request.
+    /// Set the body of the request, leaving the headers untouched.
     pub fn set_body<T: Into<Bytes>>(&mut self, body: T) {
-        self.body = body.into();
+        self.body = RequestBody::Bytes(body.into());
     }
 
     /// Set the url of the request.
@@ -450,22 +741,58 @@ impl Request {
         self
     }
 
-    /// Consume self, and return it with the body.
-    pub fn with_body<T: Into<Bytes>>(mut self, body: T) -> Self {
-        self.body = body.into();
+    /// Consume self, and return it with the body of the given content type.
+    pub fn with_body<T: Into<Bytes>>(mut self, body: T, content_type: HeaderValue) -> Self {
+        self.headers.insert(header::CONTENT_TYPE, content_type);
+        self.body = RequestBody::Bytes(body.into());
+        self
+    }
+
+    /// Consume self, and return it with the JSON serialized value as body.
+    #[cfg(feature = "json")]
+    pub fn with_json<T: serde::Serialize + ?Sized>(
+        self,
+        value: &T,
+    ) -> Result<Self, serde_json::Error> {
+        let body = serde_json::to_vec(value)?;
+        Ok(self.with_body(body, HeaderValue::from_static("application/json")))
+    }
+
+    /// Consume self, and return it with a `multipart/form-data` body of the given parts.
+    /// The parts are streamed in chunks instead of being copied into a single buffer.
+    pub fn with_multipart(mut self, parts: Vec<Part>) -> Self {
+        let multipart = Multipart::new(parts);
+        let content_type = HeaderValue::from_str(&multipart.content_type())
+            .expect("Boundary consists of ASCII alphanumerics; qed");
+        self.headers.insert(header::CONTENT_TYPE, content_type);
+        self.body = RequestBody::Multipart(multipart);
         self
     }
 }
 
 impl From<Request> for hyper::Request<hyper::Body> {
     fn from(req: Request) -> hyper::Request<hyper::Body> {
+        // hyper derives the length of a bytes body itself, a streamed one would be sent chunked.
+        let (body, content_length) = match req.body {
+            RequestBody::Bytes(bytes) => (hyper::Body::from(bytes), None),
+            RequestBody::Multipart(multipart) => {
+                let chunks = multipart.chunks();
+                let content_length = chunks.iter().map(Bytes::len).sum::<usize>();
+                let stream = futures::stream::iter(chunks.into_iter().map(Ok::<_, io::Error>));
+                (hyper::Body::wrap_stream(stream), Some(content_length))
+            }
+        };
         let mut r = hyper::Request::builder()
             .method(req.method)
             .uri(req.url.as_str())
-            .body(hyper::Body::from(req.body))
+            .body(body)
             .expect("Request conversion to hyper is infallible; qed");
 
         *r.headers_mut() = req.headers;
+        if let Some(content_length) = content_length {
+            r.headers_mut()
+                .insert(header::CONTENT_LENGTH, HeaderValue::from(content_length));
+        }
         r
     }
 }
@@ -479,6 +806,9 @@ pub struct Response {
     body: hyper::Body,
     abort: Abort,
     nread: usize,
+    // Wakes the stream at the deadline of `abort` when the body stops sending, created on the
+    // first poll where a runtime is at hand.
+    deadline: Option<Pin<Box<tokio::time::Sleep>>>,
 }
 
 impl Response {
@@ -491,6 +821,7 @@ impl Response {
             body: r.into_body(),
             abort: a,
             nread: 0,
+            deadline: None,
         }
     }
 
@@ -511,6 +842,49 @@ impl Response {
             .and_then(|h| h.to_str().ok())
             .is_some_and(|s| s.contains("text/html"))
     }
+
+    /// Value of the header `name`, looked up case-insensitively.
+    ///
+    /// `None` if the header is missing or its value is not visible ASCII.
+    /// Of a header sent more than once the first value is returned.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|h| h.to_str().ok())
+    }
+
+    /// All response headers, a header sent more than once is yielded once per value.
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &HeaderValue)> {
+        self.headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// The length of the body announced by the `Content-Length` header.
+    pub fn content_length(&self) -> Option<u64> {
+        self.headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|s| s.trim().parse().ok())
+    }
+
+    /// How long the server asks us to wait before retrying, from the `Retry-After` header.
+    ///
+    /// Both the delta-seconds and the HTTP-date form are understood, a date in the
+    /// past yields a zero duration.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.headers
+            .get(header::RETRY_AFTER)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|s| parse_retry_after(s, SystemTime::now()))
+    }
+}
+
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or_default())
 }
 
 impl Stream for Response {
@@ -518,8 +892,20 @@ impl Stream for Response {
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         if self.abort.is_aborted() {
-            debug!(target: "fetch", "fetch of {} aborted", self.url);
-            return Poll::Ready(Some(Err(Error::Aborted)));
+            debug!(target: "fetch", "fetch of {} aborted: {:?}", self.url, self.abort.reason());
+            return Poll::Ready(Some(Err(self.abort.error())));
+        }
+        if let Some(deadline) = self.abort.deadline() {
+            if self.deadline.is_none() && tokio::runtime::Handle::try_current().is_ok() {
+                let deadline = tokio::time::Instant::from_std(deadline);
+                self.deadline = Some(Box::pin(tokio::time::sleep_until(deadline)));
+            }
+        }
+        if let Some(ref mut deadline) = self.deadline {
+            if deadline.as_mut().poll(cx).is_ready() {
+                debug!(target: "fetch", "deadline reached reading the body of {}", self.url);
+                return Poll::Ready(Some(Err(Error::Timeout)));
+            }
         }
         match Pin::new(&mut self.body).poll_data(cx) {
             Poll::Ready(Some(Ok(c))) => {
@@ -567,6 +953,13 @@ impl io::Read for BodyReader {
         let mut n = 0;
 
         while self.body.is_some() {
+            if let Some(reason) = self.abort.reason() {
+                debug!(target: "fetch", "body read aborted: {reason}");
+                return Err(io::Error::new(
+                    io::ErrorKind::Interrupted,
+                    self.abort.error(),
+                ));
+            }
             // Can we still read from the current chunk?
             if self.offset < self.chunk.len() {
                 let k = min(self.chunk.len() - self.offset, buf.len() - n);
@@ -592,41 +985,37 @@ impl io::Read for BodyReader {
                     .take()
                     .expect("loop condition ensures `self.body` is always defined; qed");
 
-                // Bridge async body to sync I/O. We need to handle two contexts:
-                // 1. Inside a tokio runtime: use block_in_place + handle.block_on
-                // 2. Outside any async executor (e.g., inside futures::executor::block_on
-                //    from jsonrpc's handle_request_sync): create a temporary tokio runtime
+                // Bridge async body to sync I/O. `safe_block_on` drives the read in
+                // `block_in_place` inside a multi-thread runtime task (a plain block_on would
+                // deadlock), refuses to block a current-thread runtime and behaves like a plain
+                // block_on elsewhere, e.g. inside jsonrpc's handle_request_sync.
                 //
-                // The async block returns (body, result) so we can put `body` back.
-                let (body_back, result) = match tokio::runtime::Handle::try_current() {
-                    Ok(handle) => {
-                        tokio::task::block_in_place(|| handle.block_on(async move {
-                            use hyper::body::HttpBody;
-                            let result = match body.data().await {
-                                Some(Ok(chunk)) => Ok(Some(chunk)),
-                                Some(Err(e)) => Err(io::Error::other(format!("body read error: {e}"))),
-                                None => Ok(None),
-                            };
-                            (body, result)
-                        }))
-                    }
-                    Err(_) => {
-                        // No tokio runtime available – create a minimal one (handles nested
-                        // futures::executor::block_on contexts from jsonrpc's handle_request_sync)
-                        tokio::runtime::Builder::new_current_thread()
-                            .build()
-                            .expect("tokio runtime for body reader")
-                            .block_on(async move {
-                                use hyper::body::HttpBody;
-                                let result = match body.data().await {
-                                    Some(Ok(chunk)) => Ok(Some(chunk)),
-                                    Some(Err(e)) => Err(io::Error::other(format!("body read error: {e}"))),
-                                    None => Ok(None),
-                                };
-                                (body, result)
-                            })
-                    }
-                };
+                // The async block returns (body, result) so we can put `body` back. A body which
+                // stops sending is given up on at the deadline.
+                let deadline = self.abort.deadline();
+                let (body_back, result) = parity_runtime::safe_block_on(async move {
+                    use hyper::body::HttpBody;
+                    let read = async {
+                        match body.data().await {
+                            Some(Ok(chunk)) => Ok(Some(chunk)),
+                            Some(Err(e)) => Err(io::Error::other(format!("body read error: {e}"))),
+                            None => Ok(None),
+                        }
+                    };
+                    let result = match deadline {
+                        Some(deadline) => {
+                            let deadline = tokio::time::Instant::from_std(deadline);
+                            tokio::time::timeout_at(deadline, read)
+                                .await
+                                .unwrap_or_else(|_| {
+                                    Err(io::Error::new(io::ErrorKind::TimedOut, Error::Timeout))
+                                })
+                        }
+                        None => read.await,
+                    };
+                    (body, result)
+                })
+                .map_err(|e| io::Error::other(format!("body read error: {e}")))?;
 
                 match result {
                     Ok(Some(chunk)) => {
@@ -659,8 +1048,8 @@ pub enum Error {
     Io(io::Error),
     /// Invalid URLs where attempted to parse.
     Url(url::ParseError),
-    /// Calling code invoked `Abort::abort`.
-    Aborted,
+    /// The request was aborted, e.g. by calling code invoking `Abort::abort`.
+    Aborted(AbortReason),
     /// Too many redirects have been encountered.
     TooManyRedirects,
     /// tokio-timer inner future gave us an error.
@@ -675,12 +1064,16 @@ pub enum Error {
     BackgroundThreadDead,
     /// The request queue is full.
     RequestQueueFull,
+    /// The TLS settings are invalid.
+    Tls(String),
+    /// The certificate of the given host matches none of the pinned keys.
+    CertificatePinMismatch(String),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Error::Aborted => write!(fmt, "The request has been aborted."),
+            Error::Aborted(reason) => write!(fmt, "The request has been aborted: {reason}."),
             Error::Hyper(ref e) => write!(fmt, "{e}"),
             Error::HyperHeaderToStrError(ref e) => write!(fmt, "{e}"),
             Error::ParseInt(ref e) => write!(fmt, "{e}"),
@@ -695,6 +1088,10 @@ impl fmt::Display for Error {
             Error::Timeout => write!(fmt, "request timed out"),
             Error::SizeLimit => write!(fmt, "size limit reached"),
             Error::RequestQueueFull => write!(fmt, "request queue full"),
+            Error::Tls(ref e) => write!(fmt, "invalid TLS settings: {e}"),
+            Error::CertificatePinMismatch(ref host) => {
+                write!(fmt, "certificate of {host} matches none of the pinned keys")
+            }
         }
     }
 }
@@ -710,7 +1107,10 @@ impl ::std::error::Error for Error {
 
 impl From<hyper::Error> for Error {
     fn from(e: hyper::Error) -> Self {
-        Error::Hyper(e)
+        match tls::pin_mismatch(&e) {
+            Some(host) => Error::CertificatePinMismatch(host),
+            None => Error::Hyper(e),
+        }
     }
 }
 
@@ -993,6 +1393,606 @@ mod test {
         });
     }
 
+    struct PanickingMiddleware;
+
+    impl Middleware for PanickingMiddleware {
+        fn on_request(&self, _req: super::Request) -> super::Request {
+            panic!("middleware failure")
+        }
+    }
+
+    #[derive(Default)]
+    struct StatusRecorder(std::sync::Mutex<Vec<StatusCode>>);
+
+    impl Middleware for StatusRecorder {
+        fn on_response(&self, _url: &Url, status: StatusCode, _elapsed: Duration) {
+            self.0.lock().unwrap().push(status);
+        }
+    }
+
+    #[test]
+    fn it_should_send_middleware_headers() {
+        let server = TestServer::run();
+        let recorder = Arc::new(StatusRecorder::default());
+        let client = Client::with_middleware(vec![
+            Arc::new(PanickingMiddleware),
+            Arc::new(crate::HeaderMiddleware::new(vec![(
+                header::HeaderName::from_static("x-signature"),
+                HeaderValue::from_static("signed"),
+            )])),
+            recorder.clone(),
+        ])
+        .unwrap();
+        let runtime = Runtime::new().unwrap();
+
+        runtime.block_on(async {
+            let resp = client
+                .get(
+                    &format!("http://{}/redirect?/header?x-signature", server.addr()),
+                    Abort::default(),
+                )
+                .await
+                .expect("Request failed");
+
+            assert!(resp.is_success());
+
+            let mut body = Vec::new();
+            let mut resp_stream = resp;
+            while let Some(chunk) = resp_stream.next().await {
+                body.extend_from_slice(&chunk.expect("Failed to read chunk"));
+            }
+            assert_eq!(&body[..], b"signed");
+        });
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![StatusCode::MOVED_PERMANENTLY, StatusCode::OK]
+        );
+    }
+
+    #[test]
+    fn it_should_abort_redirects_at_the_deadline() {
+        let server = TestServer::run();
+        let recorder = Arc::new(StatusRecorder::default());
+        let client = Client::with_middleware(vec![recorder.clone()]).unwrap();
+        let runtime = Runtime::new().unwrap();
+
+        let abort = Abort::default()
+            .with_max_redirects(100)
+            .with_max_duration(Duration::from_secs(30))
+            .with_deadline(Instant::now() + Duration::from_millis(1000));
+
+        runtime.block_on(async {
+            let started = Instant::now();
+            match client
+                .get(&format!("http://{}/slow-loop", server.addr()), abort)
+                .await
+            {
+                Err(Error::Timeout) => {}
+                other => panic!("expected timeout, got {other:?}"),
+            }
+            assert!(started.elapsed() < Duration::from_secs(5));
+        });
+
+        // Some redirects were followed before the deadline hit.
+        let hops = recorder.0.lock().unwrap().len();
+        assert!(hops > 0 && hops < 100, "followed {hops} redirects");
+    }
+
+    #[test]
+    fn it_should_abort_a_stalled_body_at_the_deadline() {
+        let server = TestServer::run();
+        let client = Client::new().unwrap();
+        let runtime = Runtime::new().unwrap();
+
+        let abort = || Abort::default().with_deadline(Instant::now() + Duration::from_millis(500));
+        let url = format!("http://{}/stall", server.addr());
+
+        runtime.block_on(async {
+            let mut resp = client.get(&url, abort()).await.expect("Request failed");
+            let started = Instant::now();
+            assert_eq!(&resp.next().await.unwrap().unwrap()[..], b"1");
+            match resp.next().await {
+                Some(Err(Error::Timeout)) => {}
+                other => panic!("expected timeout, got {other:?}"),
+            }
+            assert!(started.elapsed() < Duration::from_secs(5));
+        });
+
+        let resp = runtime
+            .block_on(client.get(&url, abort()))
+            .expect("Request failed");
+        let started = Instant::now();
+        let mut body = Vec::new();
+        match io::Read::read_to_end(&mut BodyReader::new(resp), &mut body) {
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+            other => panic!("expected timeout, got {other:?}"),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn it_should_abort_children_with_their_parent() {
+        let parent = Abort::default().with_deadline(Instant::now() + Duration::from_secs(60));
+        let child = parent.child();
+        let sibling = parent.child();
+        assert_eq!(child.deadline(), parent.deadline());
+
+        // A child cannot extend the deadline of its parent.
+        let later = child
+            .clone()
+            .with_deadline(Instant::now() + Duration::from_secs(120));
+        assert_eq!(later.deadline(), parent.deadline());
+
+        sibling.abort_with(AbortReason::SizeLimit);
+        assert!(sibling.is_aborted());
+        assert!(!child.is_aborted());
+        assert!(!parent.is_aborted());
+
+        parent.abort_with(AbortReason::Shutdown);
+        parent.abort();
+        assert_eq!(child.reason(), Some(AbortReason::Shutdown));
+        assert_eq!(sibling.reason(), Some(AbortReason::SizeLimit));
+        assert_eq!(parent.reason(), Some(AbortReason::Shutdown));
+    }
+
+    #[test]
+    fn it_should_report_the_abort_reason() {
+        let server = TestServer::run();
+        let client = Client::new().unwrap();
+        let runtime = Runtime::new().unwrap();
+
+        let abort = Abort::default();
+        abort.abort_with(AbortReason::Shutdown);
+        let expired = Abort::default().with_deadline(Instant::now());
+        assert_eq!(expired.reason(), Some(AbortReason::Timeout));
+
+        runtime.block_on(async {
+            match client
+                .get(&format!("http://{}", server.addr()), abort.child())
+                .await
+            {
+                Err(e @ Error::Aborted(AbortReason::Shutdown)) => assert_eq!(
+                    e.to_string(),
+                    "The request has been aborted: shutting down."
+                ),
+                other => panic!("expected abort, got {other:?}"),
+            }
+            match client
+                .get(&format!("http://{}", server.addr()), expired)
+                .await
+            {
+                Err(Error::Timeout) => {}
+                other => panic!("expected timeout, got {other:?}"),
+            }
+        });
+    }
+
+    async fn read_body(mut resp: Response) -> Vec<u8> {
+        let mut body = Vec::new();
+        while let Some(chunk) = resp.next().await {
+            body.extend_from_slice(&chunk.expect("Failed to read chunk"));
+        }
+        body
+    }
+
+    // Header of the request as returned by the `/echo` endpoint.
+    fn echoed_header(resp: &Response, name: &str) -> Option<String> {
+        resp.headers
+            .get(format!("x-{name}"))
+            .map(|v| v.to_str().unwrap().to_owned())
+    }
+
+    // Split a multipart body into the headers and data of its parts.
+    fn parse_multipart(boundary: &str, body: &[u8]) -> Vec<(String, Vec<u8>)> {
+        fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+            haystack.windows(needle.len()).position(|w| w == needle)
+        }
+
+        let delimiter = format!("\r\n--{boundary}").into_bytes();
+        let body = [&b"\r\n"[..], body].concat();
+        let mut segments = Vec::new();
+        let mut rest = &body[..];
+        while let Some(pos) = find(rest, &delimiter) {
+            segments.push(&rest[..pos]);
+            rest = &rest[pos + delimiter.len()..];
+        }
+        assert_eq!(rest, b"--\r\n");
+        segments[1..]
+            .iter()
+            .map(|segment| {
+                let segment = segment.strip_prefix(&b"\r\n"[..]).unwrap();
+                let split = find(segment, b"\r\n\r\n").unwrap();
+                (
+                    String::from_utf8(segment[..split].to_vec()).unwrap(),
+                    segment[split + 4..].to_vec(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_should_post_body_with_content_type() {
+        let server = TestServer::run();
+        let client = Client::new().unwrap();
+        let runtime = Runtime::new().unwrap();
+
+        runtime.block_on(async {
+            let url = format!("http://{}/echo", server.addr()).parse().unwrap();
+            let request = super::Request::post(url)
+                .with_body("hello", HeaderValue::from_static("text/plain"));
+            let resp = client
+                .fetch(request, Abort::default())
+                .await
+                .expect("Request failed");
+
+            assert_eq!(
+                echoed_header(&resp, "content-type").as_deref(),
+                Some("text/plain")
+            );
+            assert_eq!(echoed_header(&resp, "content-length").as_deref(), Some("5"));
+            assert_eq!(echoed_header(&resp, "transfer-encoding"), None);
+            assert_eq!(read_body(resp).await, b"hello");
+        });
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn it_should_post_json_body() {
+        let server = TestServer::run();
+        let client = Client::new().unwrap();
+        let runtime = Runtime::new().unwrap();
+
+        runtime.block_on(async {
+            let url = format!("http://{}/echo", server.addr()).parse().unwrap();
+            let request = super::Request::post(url)
+                .with_json(&serde_json::json!({ "block": 1 }))
+                .unwrap();
+            let resp = client
+                .fetch(request, Abort::default())
+                .await
+                .expect("Request failed");
+
+            assert_eq!(
+                echoed_header(&resp, "content-type").as_deref(),
+                Some("application/json")
+            );
+            assert_eq!(read_body(resp).await, br#"{"block":1}"#);
+        });
+    }
+
+    #[test]
+    fn it_should_post_multipart_body() {
+        let server = TestServer::run();
+        let client = Client::new().unwrap();
+        let runtime = Runtime::new().unwrap();
+        // larger than a single chunk
+        let file: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+
+        runtime.block_on(async {
+            let url = format!("http://{}/echo", server.addr()).parse().unwrap();
+            let request = super::Request::post(url).with_multipart(vec![
+                Part::text("comment", "crash \"report\""),
+                Part::file("dump", "core.bin", "application/octet-stream", file.clone()),
+                Part::text("empty", ""),
+            ]);
+            let resp = client
+                .fetch(request, Abort::default())
+                .await
+                .expect("Request failed");
+
+            let content_type = echoed_header(&resp, "content-type").unwrap();
+            let boundary = content_type
+                .strip_prefix("multipart/form-data; boundary=")
+                .unwrap();
+            let content_length = echoed_header(&resp, "content-length");
+            assert_eq!(echoed_header(&resp, "transfer-encoding"), None);
+
+            let body = read_body(resp).await;
+            assert_eq!(content_length, Some(body.len().to_string()));
+            assert_eq!(
+                parse_multipart(boundary, &body),
+                vec![
+                    (
+                        r#"Content-Disposition: form-data; name="comment""#.to_owned(),
+                        br#"crash "report""#.to_vec()
+                    ),
+                    (
+                        "Content-Disposition: form-data; name=\"dump\"; filename=\"core.bin\"\r\n\
+                         Content-Type: application/octet-stream"
+                            .to_owned(),
+                        file
+                    ),
+                    (
+                        r#"Content-Disposition: form-data; name="empty""#.to_owned(),
+                        Vec::new()
+                    ),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn it_should_send_body_again_only_on_temporary_redirect() {
+        let server = TestServer::run();
+        let client = Client::new().unwrap();
+        let runtime = Runtime::new().unwrap();
+
+        runtime.block_on(async {
+            let request = |path: &str| {
+                let url = format!("http://{}{}", server.addr(), path).parse().unwrap();
+                super::Request::post(url).with_multipart(vec![Part::text("name", "value")])
+            };
+
+            let resp = client
+                .fetch(request("/temporary?/echo"), Abort::default())
+                .await
+                .expect("Request failed");
+            assert!(echoed_header(&resp, "content-type")
+                .unwrap()
+                .starts_with("multipart/form-data"));
+            let body = read_body(resp).await;
+            assert!(String::from_utf8(body).unwrap().contains("value"));
+
+            // the method changes to GET, the body is dropped
+            let resp = client
+                .fetch(request("/redirect?/echo"), Abort::default())
+                .await
+                .expect("Request failed");
+            assert_eq!(echoed_header(&resp, "content-type"), None);
+            assert!(read_body(resp).await.is_empty());
+        });
+    }
+
+    struct MockResolver {
+        addrs: Vec<SocketAddr>,
+        calls: AtomicUsize,
+    }
+
+    impl MockResolver {
+        fn new(addrs: Vec<SocketAddr>) -> Arc<Self> {
+            Arc::new(MockResolver {
+                addrs,
+                calls: AtomicUsize::new(0),
+            })
+        }
+    }
+
+    impl Resolve for MockResolver {
+        fn resolve(
+            &self,
+            _host: &str,
+            _port: u16,
+        ) -> futures::future::BoxFuture<'static, io::Result<Vec<SocketAddr>>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(futures::future::ready(Ok(self.addrs.clone())))
+        }
+    }
+
+    #[test]
+    fn it_should_race_address_families() {
+        let server = TestServer::run();
+        let runtime = Runtime::new().unwrap();
+        // an address from the IPv6 discard prefix never answers
+        let dead: SocketAddr = format!("[100::1]:{}", server.addr().port())
+            .parse()
+            .unwrap();
+        let dead_timeout = Duration::from_secs(10);
+
+        for addrs in [vec![dead, server.addr()], vec![server.addr(), dead]] {
+            let client = Client::builder()
+                .with_resolver(MockResolver::new(addrs))
+                .with_happy_eyeballs(true)
+                .with_connect_timeout(dead_timeout)
+                .build()
+                .unwrap();
+
+            let started = Instant::now();
+            runtime.block_on(async {
+                let url = format!("http://dual.test:{}/?1", server.addr().port());
+                let resp = client
+                    .get(&url, Abort::default())
+                    .await
+                    .expect("Request failed");
+                assert!(resp.is_success());
+            });
+            assert!(started.elapsed() < dead_timeout / 4);
+            assert_eq!(client.connect_stats().ipv4(), 1);
+            assert_eq!(client.connect_stats().ipv6(), 0);
+        }
+    }
+
+    #[test]
+    fn it_should_try_other_family_once_preferred_failed() {
+        use crate::connector::{ConnectConfig, ConnectStats, Connector};
+
+        assert_eq!(
+            ConnectConfig::default().happy_eyeballs,
+            Some(HAPPY_EYEBALLS_HEAD_START)
+        );
+        let runtime = Runtime::new().unwrap();
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind(ADDRESS).await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            // nothing listens on the IPv6 loopback, the attempt fails right away
+            let refused: SocketAddr = format!("[::1]:{}", addr.port()).parse().unwrap();
+            let head_start = Duration::from_secs(10);
+            let config = ConnectConfig {
+                happy_eyeballs: Some(head_start),
+                ..Default::default()
+            };
+            let mut connector = Connector::new(
+                MockResolver::new(vec![refused, addr]),
+                config,
+                Arc::new(ConnectStats::default()),
+            );
+            let uri: hyper::Uri = format!("http://dual.test:{}/", addr.port())
+                .parse()
+                .unwrap();
+
+            let started = Instant::now();
+            connector.call(uri).await.expect("connect failed");
+            assert!(started.elapsed() < head_start / 4);
+        });
+    }
+
+    #[test]
+    fn it_should_reuse_pinned_address() {
+        use crate::connector::{ConnectConfig, ConnectStats, Connector};
+
+        let runtime = Runtime::new().unwrap();
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind(ADDRESS).await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let resolver = MockResolver::new(vec![addr]);
+            let config = ConnectConfig {
+                dns_pinning: Some(Duration::from_secs(60)),
+                ..Default::default()
+            };
+            let mut connector =
+                Connector::new(resolver.clone(), config, Arc::new(ConnectStats::default()));
+            let uri: hyper::Uri = format!("http://pinned.test:{}/", addr.port())
+                .parse()
+                .unwrap();
+
+            for _ in 0..2 {
+                connector.call(uri.clone()).await.expect("connect failed");
+            }
+            assert_eq!(resolver.calls.load(Ordering::SeqCst), 1);
+
+            // a failing pinned address is resolved again
+            drop(listener);
+            assert!(connector.call(uri.clone()).await.is_err());
+            assert_eq!(resolver.calls.load(Ordering::SeqCst), 2);
+        });
+    }
+
+    fn response_with_headers(headers: &[(&str, &str)]) -> Response {
+        let mut builder = hyper::Response::builder();
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let r = builder.body(Body::empty()).unwrap();
+        Response::new("http://example.com".parse().unwrap(), r, Abort::default())
+    }
+
+    #[test]
+    fn it_should_look_up_headers_case_insensitively() {
+        let response = response_with_headers(&[
+            ("ETag", "\"abc\""),
+            ("content-length", "42"),
+            ("X-RateLimit-Remaining", "7"),
+        ]);
+
+        assert_eq!(response.header("etag"), Some("\"abc\""));
+        assert_eq!(response.header("ETAG"), Some("\"abc\""));
+        assert_eq!(response.header("x-ratelimit-remaining"), Some("7"));
+        assert_eq!(response.header("Last-Modified"), None);
+        assert_eq!(response.content_length(), Some(42));
+        assert_eq!(response.headers().count(), 3);
+        assert!(response
+            .headers()
+            .any(|(name, value)| name == "etag" && value == "\"abc\""));
+    }
+
+    #[test]
+    fn it_should_parse_both_retry_after_forms() {
+        let response = response_with_headers(&[("Retry-After", "120")]);
+        assert_eq!(response.retry_after(), Some(Duration::from_secs(120)));
+
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:30:00 GMT", now),
+            Some(Duration::from_secs(120))
+        );
+        // dates in the past mean retrying right away
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::from_secs(0))
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+
+        let response = response_with_headers(&[("Retry-After", "Thu, 01 Jan 1970 00:00:00 GMT")]);
+        assert_eq!(response.retry_after(), Some(Duration::from_secs(0)));
+        assert_eq!(response_with_headers(&[]).retry_after(), None);
+    }
+
+    #[cfg(feature = "tls")]
+    const TLS_ROOT: &[u8] = include_bytes!("../res/tls/ca.pem");
+
+    #[cfg(feature = "tls")]
+    fn server_spki_hash() -> SpkiHash {
+        let spki = include_bytes!("../res/tls/server-spki.der");
+        let mut hash = [0; 32];
+        hash.copy_from_slice(ring::digest::digest(&ring::digest::SHA256, spki).as_ref());
+        hash
+    }
+
+    // Fetch `url(port)` with `localhost` resolving to a TLS server at `port`.
+    #[cfg(feature = "tls")]
+    fn fetch_tls<F>(builder: ClientBuilder, url: F) -> Result<Response, Error>
+    where
+        F: FnOnce(u16) -> String,
+    {
+        let server = TlsTestServer::run();
+        let client = builder
+            .with_resolver(MockResolver::new(vec![server.addr()]))
+            .build()?;
+        let runtime = Runtime::new().unwrap();
+        runtime.block_on(client.get(&url(server.addr().port()), Abort::default()))
+    }
+
+    #[test]
+    #[cfg(feature = "tls")]
+    fn it_should_trust_custom_root_certificates() {
+        let builder = Client::builder()
+            .with_root_certificates(TLS_ROOT)
+            .with_default_roots(false);
+        let resp = fetch_tls(builder, |port| format!("https://localhost:{port}/"))
+            .expect("Request failed");
+        assert!(resp.is_success());
+    }
+
+    #[test]
+    #[cfg(feature = "tls")]
+    fn it_should_reject_unknown_root_certificates() {
+        let result = fetch_tls(Client::builder(), |port| {
+            format!("https://localhost:{port}/")
+        });
+        assert!(matches!(result, Err(Error::Hyper(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "tls")]
+    fn it_should_accept_pinned_certificate() {
+        let builder = Client::builder()
+            .with_root_certificates(TLS_ROOT)
+            .with_pinned_spki(vec![[0; 32], server_spki_hash()])
+            .with_min_tls(TlsVersion::Tls13);
+        let resp = fetch_tls(builder, |port| format!("https://localhost:{port}/"))
+            .expect("Request failed");
+        assert!(resp.is_success());
+    }
+
+    #[test]
+    #[cfg(feature = "tls")]
+    fn it_should_check_pins_after_redirects() {
+        let server = TestServer::run();
+        let builder = Client::builder()
+            .with_root_certificates(TLS_ROOT)
+            .with_pinned_spki(vec![[0; 32]]);
+        let result = fetch_tls(builder, |port| {
+            format!(
+                "http://{}/redirect?https://localhost:{port}/",
+                server.addr()
+            )
+        });
+        match result.err() {
+            Some(Error::CertificatePinMismatch(host)) => assert_eq!(host, "localhost"),
+            e => panic!("Unexpected result: {e:?}"),
+        }
+    }
+
     struct TestServer;
 
     impl Service<Request<Body>> for TestServer {
@@ -1012,6 +2012,7 @@ mod test {
         fn call(&mut self, req: Request<Body>) -> Self::Future {
             let path = req.uri().path().to_string();
             let query = req.uri().query().unwrap_or("").to_string();
+            let header = req.headers().get(query.as_str()).cloned();
 
             Box::pin(async move {
                 match path.as_str() {
@@ -1028,6 +2029,15 @@ mod test {
                             .expect("Unable to create response");
                         Ok(res)
                     }
+                    "/temporary" => {
+                        let res = HyperResponse::builder()
+                            .status(StatusCode::TEMPORARY_REDIRECT)
+                            .header(hyper::header::LOCATION, query)
+                            .body(Body::empty())
+                            .expect("Unable to create response");
+                        Ok(res)
+                    }
+                    "/echo" => Ok(echo(req).await),
                     "/loop" => {
                         let res = HyperResponse::builder()
                             .status(StatusCode::MOVED_PERMANENTLY)
@@ -1036,6 +2046,29 @@ mod test {
                             .expect("Unable to create response");
                         Ok(res)
                     }
+                    "/slow-loop" => {
+                        tokio::time::sleep(Duration::from_millis(300)).await;
+                        let res = HyperResponse::builder()
+                            .status(StatusCode::MOVED_PERMANENTLY)
+                            .header(hyper::header::LOCATION, "/slow-loop")
+                            .body(Body::empty())
+                            .expect("Unable to create response");
+                        Ok(res)
+                    }
+                    "/header" => {
+                        let value = header.map(|h| h.as_bytes().to_vec()).unwrap_or_default();
+                        Ok(HyperResponse::new(Body::from(value)))
+                    }
+                    "/stall" => {
+                        // Send a chunk of the body, then nothing for a minute.
+                        let (mut sender, body) = Body::channel();
+                        tokio::spawn(async move {
+                            let _ = sender.send_data(Bytes::from_static(b"1")).await;
+                            tokio::time::sleep(Duration::from_secs(60)).await;
+                            drop(sender);
+                        });
+                        Ok(HyperResponse::new(body))
+                    }
                     "/delay" => {
                         let dur = Duration::from_secs(query.parse().unwrap_or(0));
                         tokio::time::sleep(dur).await;
@@ -1054,6 +2087,25 @@ mod test {
         }
     }
 
+    // Respond with the request body, returning its headers with an `x-` prefix.
+    async fn echo(req: Request<Body>) -> HyperResponse<Body> {
+        let mut res = HyperResponse::builder();
+        for name in [
+            header::CONTENT_TYPE,
+            header::CONTENT_LENGTH,
+            header::TRANSFER_ENCODING,
+        ] {
+            if let Some(value) = req.headers().get(&name) {
+                res = res.header(format!("x-{name}"), value);
+            }
+        }
+        let body = hyper::body::to_bytes(req.into_body())
+            .await
+            .expect("Unable to read request body");
+        res.body(Body::from(body))
+            .expect("Unable to create response")
+    }
+
     impl TestServer {
         fn run() -> Handle {
             let (tx_start, rx_start) = std::sync::mpsc::sync_channel(1);
@@ -1087,6 +2139,69 @@ mod test {
         }
     }
 
+    #[cfg(feature = "tls")]
+    struct TlsTestServer;
+
+    #[cfg(feature = "tls")]
+    impl TlsTestServer {
+        // Serve the routes of `TestServer` with a certificate for `localhost` issued by `TLS_ROOT`.
+        fn run() -> Handle {
+            use tokio_rustls::{
+                rustls::{Certificate, PrivateKey, ServerConfig},
+                TlsAcceptor,
+            };
+
+            let certs = rustls_pemfile::certs(&mut &include_bytes!("../res/tls/server.pem")[..])
+                .unwrap()
+                .into_iter()
+                .map(Certificate)
+                .collect();
+            let key = rustls_pemfile::pkcs8_private_keys(
+                &mut &include_bytes!("../res/tls/server.key")[..],
+            )
+            .unwrap()
+            .remove(0);
+            let config = ServerConfig::builder()
+                .with_safe_defaults()
+                .with_no_client_auth()
+                .with_single_cert(certs, PrivateKey(key))
+                .unwrap();
+            let acceptor = TlsAcceptor::from(Arc::new(config));
+
+            let (tx_start, rx_start) = std::sync::mpsc::sync_channel(1);
+            let (tx_end, rx_end) = oneshot::channel();
+
+            std::thread::spawn(move || {
+                let rt = tokio::runtime::Runtime::new().unwrap();
+                rt.block_on(async {
+                    let listener = tokio::net::TcpListener::bind(ADDRESS).await.unwrap();
+                    tx_start.send(listener.local_addr().unwrap()).unwrap_or(());
+
+                    let serve = async {
+                        while let Ok((stream, _)) = listener.accept().await {
+                            let acceptor = acceptor.clone();
+                            tokio::spawn(async move {
+                                // handshakes of clients rejecting the certificate fail
+                                if let Ok(stream) = acceptor.accept(stream).await {
+                                    let _ = hyper::server::conn::Http::new()
+                                        .serve_connection(stream, TestServer)
+                                        .await;
+                                }
+                            });
+                        }
+                    };
+
+                    tokio::select! {
+                        _ = serve => {}
+                        _ = rx_end => {}
+                    }
+                });
+            });
+
+            Handle(rx_start.recv().unwrap(), Some(tx_end))
+        }
+    }
+
     struct Handle(SocketAddr, Option<oneshot::Sender<()>>);
 
     impl Handle {
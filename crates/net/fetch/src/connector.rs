@@ -0,0 +1,400 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+use log::{debug, trace};
+
+use futures::future::BoxFuture;
+use hyper::{
+    client::connect::{Connected, Connection},
+    service::Service,
+    Uri,
+};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+    sync::Notify,
+};
+
+#[cfg(feature = "tls")]
+use crate::tls::TlsConnector;
+
+/// Head start given to the preferred address family before the other one is tried.
+pub const HAPPY_EYEBALLS_HEAD_START: Duration = Duration::from_millis(250);
+
+/// Host name resolution used by the fetch client.
+pub trait Resolve: Send + Sync + 'static {
+    /// Resolve `host` to socket addresses with the given port, most preferred first.
+    fn resolve(&self, host: &str, port: u16) -> BoxFuture<'static, io::Result<Vec<SocketAddr>>>;
+}
+
+/// Resolver backed by the system configuration.
+#[derive(Debug, Default)]
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> BoxFuture<'static, io::Result<Vec<SocketAddr>>> {
+        let host = host.to_owned();
+        Box::pin(async move {
+            Ok(tokio::net::lookup_host((host.as_str(), port))
+                .await?
+                .collect())
+        })
+    }
+}
+
+/// Successful connections per address family.
+#[derive(Debug, Default)]
+pub struct ConnectStats {
+    ipv4: AtomicUsize,
+    ipv6: AtomicUsize,
+}
+
+impl ConnectStats {
+    /// Number of connections established over IPv4.
+    pub fn ipv4(&self) -> usize {
+        self.ipv4.load(Ordering::Relaxed)
+    }
+
+    /// Number of connections established over IPv6.
+    pub fn ipv6(&self) -> usize {
+        self.ipv6.load(Ordering::Relaxed)
+    }
+
+    fn record(&self, addr: &SocketAddr) {
+        let counter = if addr.is_ipv4() {
+            &self.ipv4
+        } else {
+            &self.ipv6
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// How connections are established.
+#[derive(Debug, Clone)]
+pub struct ConnectConfig {
+    /// Race both address families, giving the preferred one this head start.
+    /// Addresses are tried one after another if `None`.
+    pub happy_eyeballs: Option<Duration>,
+    /// Reuse the address a host was last connected at for this long.
+    pub dns_pinning: Option<Duration>,
+    /// Timeout of a single connection attempt.
+    pub connect_timeout: Option<Duration>,
+}
+
+impl Default for ConnectConfig {
+    fn default() -> Self {
+        ConnectConfig {
+            // races the address families like the default connector of hyper
+            happy_eyeballs: Some(HAPPY_EYEBALLS_HEAD_START),
+            dns_pinning: None,
+            connect_timeout: None,
+        }
+    }
+}
+
+type Pins = HashMap<(String, u16), (SocketAddr, Instant)>;
+
+/// Connection established by the connector.
+pub enum Stream {
+    /// Plain TCP connection.
+    Plain(TcpStream),
+    /// TLS session over a TCP connection.
+    #[cfg(feature = "tls")]
+    Tls(Box<tokio_rustls::client::TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+impl Connection for Stream {
+    fn connected(&self) -> Connected {
+        match self {
+            Stream::Plain(stream) => stream.connected(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.get_ref().0.connected(),
+        }
+    }
+}
+
+/// TCP connector of the fetch client.
+#[derive(Clone)]
+pub struct Connector {
+    resolver: Arc<dyn Resolve>,
+    config: ConnectConfig,
+    pins: Arc<Mutex<Pins>>,
+    stats: Arc<ConnectStats>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConnector>,
+}
+
+impl Connector {
+    /// Create a connector resolving hosts with `resolver` and counting connections in `stats`.
+    pub fn new(
+        resolver: Arc<dyn Resolve>,
+        config: ConnectConfig,
+        stats: Arc<ConnectStats>,
+    ) -> Self {
+        Connector {
+            resolver,
+            config,
+            pins: Default::default(),
+            stats,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    /// Establish TLS sessions for `https` URIs with `tls`.
+    #[cfg(feature = "tls")]
+    pub(crate) fn with_tls(mut self, tls: TlsConnector) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    async fn connect(self, dst: Uri) -> io::Result<Stream> {
+        let host = dst
+            .host()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URI without host"))?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let https = dst.scheme_str() == Some("https");
+        let port = dst.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+        let stream = self.connect_tcp(host, port).await?;
+        if https {
+            self.handshake(host, stream).await
+        } else {
+            Ok(Stream::Plain(stream))
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    async fn handshake(&self, host: &str, stream: TcpStream) -> io::Result<Stream> {
+        match self.tls {
+            // every new connection is checked, including the ones to hosts redirected to
+            Some(ref tls) => Ok(Stream::Tls(Box::new(tls.connect(host, stream).await?))),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "TLS is not configured",
+            )),
+        }
+    }
+
+    #[cfg(not(feature = "tls"))]
+    async fn handshake(&self, _host: &str, _stream: TcpStream) -> io::Result<Stream> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "fetch is built without TLS support",
+        ))
+    }
+
+    async fn connect_tcp(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return self.connect_addrs(vec![SocketAddr::new(ip, port)]).await;
+        }
+
+        let key = (host.to_owned(), port);
+        if let Some(addr) = self.pinned(&key) {
+            match attempt(addr, self.config.connect_timeout).await {
+                Ok(stream) => {
+                    self.stats.record(&addr);
+                    return Ok(stream);
+                }
+                Err(e) => {
+                    debug!(target: "fetch", "pinned address {addr} of {host} failed: {e}");
+                    self.pins.lock().expect("pins lock poisoned").remove(&key);
+                }
+            }
+        }
+
+        let addrs = self.resolver.resolve(host, port).await?;
+        let stream = self.connect_addrs(addrs).await?;
+        if let (Some(ttl), Ok(addr)) = (self.config.dns_pinning, stream.peer_addr()) {
+            self.pins
+                .lock()
+                .expect("pins lock poisoned")
+                .insert(key, (addr, Instant::now() + ttl));
+        }
+        Ok(stream)
+    }
+
+    fn pinned(&self, key: &(String, u16)) -> Option<SocketAddr> {
+        self.config.dns_pinning?;
+        let mut pins = self.pins.lock().expect("pins lock poisoned");
+        match pins.get(key) {
+            Some((addr, until)) if *until > Instant::now() => Some(*addr),
+            Some(_) => {
+                pins.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    async fn connect_addrs(&self, addrs: Vec<SocketAddr>) -> io::Result<TcpStream> {
+        let timeout = self.config.connect_timeout;
+        let stream = match self.config.happy_eyeballs {
+            Some(head_start) => race(addrs, head_start, timeout).await,
+            None => sequential(addrs, timeout).await,
+        }?;
+        if let Ok(addr) = stream.peer_addr() {
+            trace!(target: "fetch", "connected to {addr}");
+            self.stats.record(&addr);
+        }
+        Ok(stream)
+    }
+}
+
+impl Service<Uri> for Connector {
+    type Response = Stream;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, io::Result<Stream>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        Box::pin(self.clone().connect(dst))
+    }
+}
+
+async fn attempt(addr: SocketAddr, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, TcpStream::connect(addr))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))?,
+        None => TcpStream::connect(addr).await,
+    }
+}
+
+async fn sequential(addrs: Vec<SocketAddr>, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(
+        io::ErrorKind::AddrNotAvailable,
+        "no addresses to connect to",
+    );
+    for addr in addrs {
+        match attempt(addr, timeout).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                trace!(target: "fetch", "connecting to {addr} failed: {e}");
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// Try the family of the first address, and the other family after `head_start` or as soon as
+/// the first family failed, using the first connection made. The losing attempt is dropped.
+async fn race(
+    addrs: Vec<SocketAddr>,
+    head_start: Duration,
+    timeout: Option<Duration>,
+) -> io::Result<TcpStream> {
+    let preferred_v4 = match addrs.first() {
+        Some(addr) => addr.is_ipv4(),
+        None => return sequential(addrs, timeout).await,
+    };
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv4() == preferred_v4);
+    if other.is_empty() {
+        return sequential(preferred, timeout).await;
+    }
+
+    let preferred_failed = Notify::new();
+    let preferred = async {
+        let result = sequential(preferred, timeout).await;
+        if result.is_err() {
+            preferred_failed.notify_one();
+        }
+        result
+    };
+    let other = async {
+        tokio::select! {
+            _ = tokio::time::sleep(head_start) => (),
+            _ = preferred_failed.notified() => (),
+        }
+        sequential(other, timeout).await
+    };
+    tokio::pin!(preferred, other);
+
+    tokio::select! {
+        result = &mut preferred => match result {
+            Ok(stream) => Ok(stream),
+            Err(_) => other.await,
+        },
+        result = &mut other => match result {
+            Ok(stream) => Ok(stream),
+            Err(_) => preferred.await,
+        },
+    }
+}
@@ -26,16 +26,42 @@ extern crate http;
 extern crate hyper;
 
 extern crate bytes;
+#[cfg(feature = "tls")]
+extern crate ring;
+#[cfg(feature = "tls")]
+extern crate rustls_pemfile;
+#[cfg(feature = "json")]
+extern crate serde;
+#[cfg(feature = "json")]
+extern crate serde_json;
+extern crate stats;
 extern crate tokio;
+#[cfg(feature = "tls")]
+extern crate tokio_rustls;
 extern crate url;
+#[cfg(feature = "tls")]
+extern crate webpki_roots;
 
 /// Fetch client implementation.
 pub mod client;
 #[cfg(feature = "compat")]
 /// Compatibility layer for futures 0.1
 pub mod compat;
+/// Connection establishment.
+pub mod connector;
+/// Request and response hooks.
+pub mod middleware;
+mod multipart;
+/// TLS settings.
+pub mod tls;
 
-pub use self::client::{Abort, BodyReader, Client, Error, Fetch, Request, Response};
+pub use self::client::{
+    Abort, AbortReason, BodyReader, Client, ClientBuilder, Error, Fetch, Request, Response,
+};
+pub use self::connector::{ConnectStats, Resolve};
+pub use self::middleware::{HeaderMiddleware, MetricsMiddleware, Middleware};
+pub use self::multipart::Part;
+pub use self::tls::{SpkiHash, TlsVersion};
 pub use hyper::Method;
 pub use url::Url;
 
@@ -0,0 +1,248 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Hooks run around every HTTP request made by the fetch client.
+//!
+//! Request hooks run in registration order right before a request (or a followed redirect)
+//! is sent, response hooks run in reverse order once its response arrived. A panicking hook
+//! is logged and skipped, it never fails the request.
+
+use log::warn;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use http::header::{HeaderName, HeaderValue};
+use http::StatusCode;
+use stats::prometheus::{Histogram, IntCounter};
+use stats::{Metrics, MetricsError};
+use url::Url;
+
+use crate::client::Request;
+
+/// Hook into the requests made by a `Client`.
+pub trait Middleware: Send + Sync {
+    /// Inspect or modify a request before it is sent.
+    fn on_request(&self, req: Request) -> Request {
+        req
+    }
+
+    /// Observe the status of a response and the time it took to arrive.
+    fn on_response(&self, _url: &Url, _status: StatusCode, _elapsed: Duration) {}
+}
+
+/// Ordered list of middleware shared by all clones of a `Client`.
+pub(crate) type Chain = Arc<[Arc<dyn Middleware>]>;
+
+// Scope of caught hook panics, see `panic_hook::catch_scoped`.
+const PANIC_SCOPE: &str = "fetch_middleware";
+
+// Run request hooks in registration order. A panicking hook leaves the request as it was.
+pub(crate) fn on_request(chain: &[Arc<dyn Middleware>], mut req: Request) -> Request {
+    for middleware in chain {
+        let original = req.clone();
+        req = match panic_hook::catch_scoped(PANIC_SCOPE, || middleware.on_request(req)) {
+            Ok(req) => req,
+            Err(e) => {
+                warn!(target: "fetch", "request middleware failed for {}: {e}", original.url());
+                original
+            }
+        };
+    }
+    req
+}
+
+// Run response hooks in reverse registration order.
+pub(crate) fn on_response(
+    chain: &[Arc<dyn Middleware>],
+    url: &Url,
+    status: StatusCode,
+    elapsed: Duration,
+) {
+    for middleware in chain.iter().rev() {
+        let result =
+            panic_hook::catch_scoped(PANIC_SCOPE, || middleware.on_response(url, status, elapsed));
+        if let Err(e) = result {
+            warn!(target: "fetch", "response middleware failed for {url}: {e}");
+        }
+    }
+}
+
+/// Records request latency and response status classes into a metrics registry.
+pub struct MetricsMiddleware {
+    latency: Histogram,
+    // responses by status class, 1xx to 5xx.
+    statuses: [IntCounter; 5],
+}
+
+impl MetricsMiddleware {
+    /// Register the fetch metrics with `metrics`.
+    pub fn new(metrics: &Metrics) -> Result<Self, MetricsError> {
+        let latency = metrics.histogram(
+            "fetch_request_duration_seconds",
+            "Time until the response headers of a fetch request arrived",
+            vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0],
+        )?;
+        let status = |class: u16| {
+            metrics.int_counter(
+                &format!("fetch_responses_{class}xx"),
+                &format!("Number of fetch responses with a {class}xx status"),
+            )
+        };
+        Ok(MetricsMiddleware {
+            latency,
+            statuses: [status(1)?, status(2)?, status(3)?, status(4)?, status(5)?],
+        })
+    }
+}
+
+impl Middleware for MetricsMiddleware {
+    fn on_response(&self, _url: &Url, status: StatusCode, elapsed: Duration) {
+        self.latency.observe(elapsed.as_secs_f64());
+        let class = usize::from(status.as_u16() / 100);
+        if let Some(counter) = class.checked_sub(1).and_then(|i| self.statuses.get(i)) {
+            counter.inc();
+        }
+    }
+}
+
+/// Adds fixed headers to every request, e.g. for authentication.
+/// Existing headers of the same name are replaced.
+pub struct HeaderMiddleware {
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl HeaderMiddleware {
+    /// Create a middleware setting the given headers.
+    pub fn new(headers: Vec<(HeaderName, HeaderValue)>) -> Self {
+        HeaderMiddleware { headers }
+    }
+}
+
+impl Middleware for HeaderMiddleware {
+    fn on_request(&self, mut req: Request) -> Request {
+        for (name, value) in &self.headers {
+            req.headers_mut().insert(name.clone(), value.clone());
+        }
+        req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct Recorder {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Middleware for Recorder {
+        fn on_request(&self, req: Request) -> Request {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("request {}", self.name));
+            req
+        }
+
+        fn on_response(&self, _url: &Url, _status: StatusCode, _elapsed: Duration) {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("response {}", self.name));
+        }
+    }
+
+    struct Panicking;
+
+    impl Middleware for Panicking {
+        fn on_request(&self, _req: Request) -> Request {
+            panic!("request hook")
+        }
+
+        fn on_response(&self, _url: &Url, _status: StatusCode, _elapsed: Duration) {
+            panic!("response hook")
+        }
+    }
+
+    #[test]
+    fn hooks_run_in_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let chain: Chain = vec![
+            Arc::new(Recorder {
+                name: "a",
+                log: log.clone(),
+            }) as Arc<dyn Middleware>,
+            Arc::new(Recorder {
+                name: "b",
+                log: log.clone(),
+            }),
+        ]
+        .into();
+        let url: Url = "http://localhost/".parse().unwrap();
+
+        on_request(&chain, Request::get(url.clone()));
+        on_response(&chain, &url, StatusCode::OK, Duration::from_millis(1));
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["request a", "request b", "response b", "response a"]
+        );
+    }
+
+    #[test]
+    fn panicking_hook_is_skipped() {
+        // the global hook of the client ends the process
+        static GLOBAL: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        panic_hook::set_with(|msg| GLOBAL.lock().unwrap().push(msg.to_owned()));
+        let chain: Chain = vec![
+            Arc::new(Panicking) as Arc<dyn Middleware>,
+            Arc::new(HeaderMiddleware::new(vec![(
+                HeaderName::from_static("x-test"),
+                HeaderValue::from_static("1"),
+            )])),
+        ]
+        .into();
+        let url: Url = "http://localhost/".parse().unwrap();
+
+        let req = on_request(&chain, Request::get(url.clone()));
+        assert_eq!(req.url(), &url);
+        assert_eq!(req.headers().get("x-test").unwrap(), "1");
+        on_response(&chain, &url, StatusCode::OK, Duration::from_millis(1));
+        assert!(!GLOBAL
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|msg| msg.contains("request hook") || msg.contains("response hook")));
+    }
+
+    #[test]
+    fn metrics_count_status_classes() {
+        let metrics = Metrics::new("test_".into()).unwrap();
+        let middleware = MetricsMiddleware::new(&metrics).unwrap();
+        let url: Url = "http://localhost/".parse().unwrap();
+
+        middleware.on_response(&url, StatusCode::OK, Duration::from_millis(20));
+        middleware.on_response(&url, StatusCode::NOT_FOUND, Duration::from_millis(20));
+        middleware.on_response(&url, StatusCode::NO_CONTENT, Duration::from_millis(20));
+
+        assert_eq!(middleware.statuses[1].get(), 2);
+        assert_eq!(middleware.statuses[3].get(), 1);
+        assert_eq!(middleware.latency.get_sample_count(), 3);
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+use bytes::Bytes;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Size of the chunks the data of a part is sent in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A field of a `multipart/form-data` request body.
+#[derive(Debug, Clone)]
+pub struct Part {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+    data: Bytes,
+}
+
+impl Part {
+    /// Create a plain text field.
+    pub fn text<N: Into<String>, V: Into<String>>(name: N, value: V) -> Part {
+        Part {
+            name: name.into(),
+            filename: None,
+            content_type: None,
+            data: Bytes::from(value.into()),
+        }
+    }
+
+    /// Create a file field with the given file name and content type.
+    pub fn file<N, F, C, D>(name: N, filename: F, content_type: C, data: D) -> Part
+    where
+        N: Into<String>,
+        F: Into<String>,
+        C: Into<String>,
+        D: Into<Bytes>,
+    {
+        Part {
+            name: name.into(),
+            filename: Some(filename.into()),
+            content_type: Some(content_type.into()),
+            data: data.into(),
+        }
+    }
+
+    // Delimiter and headers preceding the part data.
+    fn head(&self, boundary: &str) -> String {
+        let mut head = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"",
+            boundary,
+            escape(&self.name)
+        );
+        if let Some(ref filename) = self.filename {
+            head.push_str(&format!("; filename=\"{}\"", escape(filename)));
+        }
+        head.push_str("\r\n");
+        if let Some(ref content_type) = self.content_type {
+            head.push_str(&format!("Content-Type: {content_type}\r\n"));
+        }
+        head.push_str("\r\n");
+        head
+    }
+}
+
+/// A `multipart/form-data` request body.
+#[derive(Debug, Clone)]
+pub(crate) struct Multipart {
+    boundary: String,
+    parts: Vec<Part>,
+}
+
+impl Multipart {
+    /// Create a body with the given parts and a random boundary.
+    pub fn new(parts: Vec<Part>) -> Multipart {
+        Multipart {
+            boundary: boundary(),
+            parts,
+        }
+    }
+
+    /// Value of the `Content-Type` header for the body.
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// The encoded body. Part data is split into chunks without being copied.
+    pub fn chunks(&self) -> Vec<Bytes> {
+        let mut chunks = Vec::new();
+        for part in &self.parts {
+            chunks.push(Bytes::from(part.head(&self.boundary)));
+            let mut data = part.data.clone();
+            while data.len() > CHUNK_SIZE {
+                chunks.push(data.split_to(CHUNK_SIZE));
+            }
+            if !data.is_empty() {
+                chunks.push(data);
+            }
+            chunks.push(Bytes::from_static(b"\r\n"));
+        }
+        chunks.push(Bytes::from(format!("--{}--\r\n", self.boundary)));
+        chunks
+    }
+}
+
+// Quotes and line breaks are percent-encoded in field and file names, as browsers do.
+fn escape(name: &str) -> String {
+    name.replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default();
+    let random = || {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(nanos);
+        hasher.finish()
+    };
+    format!("----------------{:016x}{:016x}", random(), random())
+}
@@ -0,0 +1,218 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{error, fmt};
+
+/// SHA-256 hash of the DER encoded SubjectPublicKeyInfo of a certificate.
+pub type SpkiHash = [u8; 32];
+
+/// TLS protocol version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    /// TLS 1.2
+    Tls12,
+    /// TLS 1.3
+    Tls13,
+}
+
+/// Which servers TLS connections are made to.
+#[derive(Debug, Clone)]
+pub(crate) struct TlsConfig {
+    /// Trust the bundled web PKI root certificates.
+    pub default_roots: bool,
+    /// PEM bundles of additionally trusted root certificates.
+    pub root_certificates: Vec<Vec<u8>>,
+    /// Accept only leaf certificates with one of these SPKI hashes, any if empty.
+    pub pinned_spki: Vec<SpkiHash>,
+    /// Oldest protocol version accepted.
+    pub min_version: TlsVersion,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            default_roots: true,
+            root_certificates: Vec::new(),
+            pinned_spki: Vec::new(),
+            min_version: TlsVersion::Tls12,
+        }
+    }
+}
+
+/// The leaf certificate presented by a host matched none of the pinned keys.
+#[derive(Debug)]
+#[cfg_attr(not(feature = "tls"), allow(dead_code))]
+pub(crate) struct PinMismatch {
+    pub host: String,
+}
+
+impl fmt::Display for PinMismatch {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "certificate of {} matches none of the pins", self.host)
+    }
+}
+
+impl error::Error for PinMismatch {}
+
+/// Host of the pin mismatch which made the connection of a request fail, if any.
+pub(crate) fn pin_mismatch(e: &hyper::Error) -> Option<String> {
+    let mut source = error::Error::source(e);
+    while let Some(e) = source {
+        // io errors hand out the source of their payload, not the payload itself
+        let payload = e
+            .downcast_ref::<std::io::Error>()
+            .and_then(|e| e.get_ref())
+            .map(|payload| payload as &(dyn error::Error + 'static));
+        if let Some(mismatch) = payload.unwrap_or(e).downcast_ref::<PinMismatch>() {
+            return Some(mismatch.host.clone());
+        }
+        source = e.source();
+    }
+    None
+}
+
+/// DER encoded SubjectPublicKeyInfo of a DER encoded X.509 certificate, the SHA-256 hash of
+/// which is the key to pin.
+#[cfg(feature = "tls")]
+pub fn subject_public_key_info(cert: &[u8]) -> Option<&[u8]> {
+    use x509_parser::{certificate::X509Certificate, prelude::FromDer};
+
+    match X509Certificate::from_der(cert) {
+        Ok((&[], cert)) => Some(cert.tbs_certificate.subject_pki.raw),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "tls")]
+pub(crate) use self::backend::TlsConnector;
+
+#[cfg(feature = "tls")]
+mod backend {
+    use super::{subject_public_key_info, PinMismatch, SpkiHash, TlsConfig, TlsVersion};
+    use ring::digest::{digest, SHA256};
+    use std::{io, sync::Arc};
+    use tokio::net::TcpStream;
+    use tokio_rustls::{
+        client::TlsStream,
+        rustls::{
+            version::{TLS12, TLS13},
+            Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName,
+            SupportedProtocolVersion,
+        },
+    };
+
+    /// Establishes TLS sessions over connected TCP streams.
+    #[derive(Clone)]
+    pub(crate) struct TlsConnector {
+        connector: tokio_rustls::TlsConnector,
+        pinned_spki: Arc<Vec<SpkiHash>>,
+    }
+
+    impl TlsConnector {
+        /// Create a connector with the given trust settings.
+        pub fn new(config: &TlsConfig) -> Result<Self, String> {
+            let mut roots = RootCertStore::empty();
+            if config.default_roots {
+                roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+                    OwnedTrustAnchor::from_subject_spki_name_constraints(
+                        anchor.subject,
+                        anchor.spki,
+                        anchor.name_constraints,
+                    )
+                }));
+            }
+            for bundle in &config.root_certificates {
+                let certs = rustls_pemfile::certs(&mut &bundle[..])
+                    .map_err(|e| format!("invalid PEM bundle: {e}"))?;
+                if certs.is_empty() {
+                    return Err("no certificates in PEM bundle".into());
+                }
+                for cert in certs {
+                    roots
+                        .add(&Certificate(cert))
+                        .map_err(|e| format!("invalid root certificate: {e}"))?;
+                }
+            }
+
+            let versions: &[&SupportedProtocolVersion] = match config.min_version {
+                TlsVersion::Tls12 => &[&TLS13, &TLS12],
+                TlsVersion::Tls13 => &[&TLS13],
+            };
+            let client_config = ClientConfig::builder()
+                .with_safe_default_cipher_suites()
+                .with_safe_default_kx_groups()
+                .with_protocol_versions(versions)
+                .map_err(|e| e.to_string())?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+
+            Ok(TlsConnector {
+                connector: Arc::new(client_config).into(),
+                pinned_spki: Arc::new(config.pinned_spki.clone()),
+            })
+        }
+
+        /// Establish a session with `host`, checking its certificate against the pins.
+        pub async fn connect(
+            &self,
+            host: &str,
+            stream: TcpStream,
+        ) -> io::Result<TlsStream<TcpStream>> {
+            let name = ServerName::try_from(host)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let stream = self.connector.connect(name, stream).await?;
+            if !self.pinned_spki.is_empty() && !self.is_pinned(&stream) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    PinMismatch {
+                        host: host.to_owned(),
+                    },
+                ));
+            }
+            Ok(stream)
+        }
+
+        fn is_pinned(&self, stream: &TlsStream<TcpStream>) -> bool {
+            let (_, session) = stream.get_ref();
+            let spki = session
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|leaf| subject_public_key_info(&leaf.0));
+            match spki {
+                Some(spki) => {
+                    let hash = digest(&SHA256, spki);
+                    self.pinned_spki.iter().any(|pin| pin[..] == *hash.as_ref())
+                }
+                None => false,
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_subject_public_key_info() {
+        let cert = include_bytes!("../res/tls/server.der");
+        let spki = include_bytes!("../res/tls/server-spki.der");
+
+        assert_eq!(subject_public_key_info(cert), Some(&spki[..]));
+        assert_eq!(subject_public_key_info(&cert[..cert.len() / 2]), None);
+        assert_eq!(subject_public_key_info(spki), None);
+    }
+}
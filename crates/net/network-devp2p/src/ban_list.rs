@@ -0,0 +1,414 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+use node_table::NodeId;
+use serde_json;
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    time::{self, Duration, SystemTime},
+};
+
+const MAX_BANNED_NODES: usize = 1024;
+const MAX_REASON_LEN: usize = 256;
+const BANNED_NODES_FILE: &str = "banned_nodes.json";
+/// Number of disables within `DISABLE_WINDOW` after which a node is banned.
+const DISABLES_BEFORE_BAN: u32 = 3;
+const DISABLE_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// A banned node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BanEntry {
+    /// Id of the banned node.
+    pub id: NodeId,
+    /// Why the node was banned.
+    pub reason: String,
+    /// When the ban was issued.
+    pub banned_at: SystemTime,
+    /// When the ban is lifted.
+    pub expires_at: SystemTime,
+}
+
+impl BanEntry {
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at <= now
+    }
+}
+
+/// Disables of a node within the current window.
+struct Disables {
+    count: u32,
+    since: SystemTime,
+}
+
+/// List of banned nodes backed by disk file. Changes are written by `save_if_changed`,
+/// called periodically, and when the list is dropped.
+pub struct BanList {
+    entries: HashMap<NodeId, BanEntry>,
+    disables: HashMap<NodeId, Disables>,
+    path: Option<String>,
+    network_id: Option<u64>,
+    changed: bool,
+}
+
+impl BanList {
+    /// Create a ban list, loading the bans saved under `path` for the same `network_id`.
+    pub fn new(path: Option<String>, network_id: Option<u64>) -> BanList {
+        BanList {
+            entries: BanList::load(path.clone(), network_id),
+            disables: HashMap::new(),
+            path,
+            network_id,
+            changed: false,
+        }
+    }
+
+    /// Ban a node for `duration`. Replaces any existing ban for the same node.
+    pub fn ban(&mut self, id: NodeId, duration: Duration, reason: &str) {
+        let now = SystemTime::now();
+        let reason = reason.chars().take(MAX_REASON_LEN).collect();
+        self.entries.insert(
+            id,
+            BanEntry {
+                id,
+                reason,
+                banned_at: now,
+                expires_at: now + duration,
+            },
+        );
+        self.prune(now);
+        self.changed = true;
+    }
+
+    /// Note that a node was disabled. A node disabled `DISABLES_BEFORE_BAN` times within
+    /// `DISABLE_WINDOW` is banned for `duration`. Returns `true` if the node got banned.
+    pub fn note_disable(&mut self, id: NodeId, duration: Duration) -> bool {
+        let now = SystemTime::now();
+        let in_window = |d: &Disables| d.since + DISABLE_WINDOW > now;
+        if !self.disables.contains_key(&id) && self.disables.len() >= MAX_BANNED_NODES {
+            self.disables.retain(|_, d| in_window(d));
+            if self.disables.len() >= MAX_BANNED_NODES {
+                return false;
+            }
+        }
+        let disables = self.disables.entry(id).or_insert(Disables {
+            count: 0,
+            since: now,
+        });
+        if !in_window(disables) {
+            disables.count = 0;
+            disables.since = now;
+        }
+        disables.count += 1;
+        if disables.count < DISABLES_BEFORE_BAN {
+            return false;
+        }
+        self.disables.remove(&id);
+        let reason = format!(
+            "disabled {} times within {} minutes",
+            DISABLES_BEFORE_BAN,
+            DISABLE_WINDOW.as_secs() / 60
+        );
+        self.ban(id, duration, &reason);
+        true
+    }
+
+    /// Lift the ban on a node. Returns `false` if the node was not banned.
+    pub fn unban(&mut self, id: &NodeId) -> bool {
+        self.disables.remove(id);
+        let removed = self.entries.remove(id).is_some();
+        self.changed |= removed;
+        removed
+    }
+
+    /// Check if a node is currently banned.
+    pub fn is_banned(&self, id: &NodeId) -> bool {
+        self.entries
+            .get(id)
+            .is_some_and(|e| !e.is_expired(SystemTime::now()))
+    }
+
+    /// All bans which have not expired yet.
+    pub fn entries(&self) -> Vec<BanEntry> {
+        let now = SystemTime::now();
+        self.entries
+            .values()
+            .filter(|e| !e.is_expired(now))
+            .cloned()
+            .collect()
+    }
+
+    /// Drop expired bans and, if still over the limit, the bans closest to expiry.
+    fn prune(&mut self, now: SystemTime) {
+        self.entries.retain(|_, e| !e.is_expired(now));
+        if self.entries.len() > MAX_BANNED_NODES {
+            let mut by_expiry: Vec<_> = self
+                .entries
+                .values()
+                .map(|e| (e.expires_at, e.id))
+                .collect();
+            by_expiry.sort();
+            let excess = self.entries.len() - MAX_BANNED_NODES;
+            for (_, id) in by_expiry.into_iter().take(excess) {
+                self.entries.remove(&id);
+            }
+        }
+    }
+
+    /// Save the banned nodes file if bans changed since the last save.
+    pub fn save_if_changed(&mut self) {
+        if self.changed {
+            self.save();
+            self.changed = false;
+        }
+    }
+
+    /// Save the banned nodes file.
+    pub fn save(&self) {
+        let mut path = match self.path {
+            Some(ref path) => PathBuf::from(path),
+            None => return,
+        };
+        if let Err(e) = fs::create_dir_all(&path) {
+            warn!(target: "network", "Error creating banned nodes directory: {e:?}");
+            return;
+        }
+        path.push(BANNED_NODES_FILE);
+        let nodes = self
+            .entries()
+            .iter()
+            .take(MAX_BANNED_NODES)
+            .map(Into::into)
+            .collect();
+        let list = json::BanList {
+            network_id: self.network_id,
+            nodes,
+        };
+
+        match fs::File::create(&path) {
+            Ok(file) => {
+                if let Err(e) = serde_json::to_writer_pretty(file, &list) {
+                    warn!(target: "network", "Error writing banned nodes file: {e:?}");
+                }
+            }
+            Err(e) => {
+                warn!(target: "network", "Error creating banned nodes file: {e:?}");
+            }
+        }
+    }
+
+    fn load(path: Option<String>, network_id: Option<u64>) -> HashMap<NodeId, BanEntry> {
+        let path = match path {
+            Some(path) => PathBuf::from(path).join(BANNED_NODES_FILE),
+            None => return Default::default(),
+        };
+
+        let file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                debug!(target: "network", "Error opening banned nodes file: {e:?}");
+                return Default::default();
+            }
+        };
+        let res: Result<json::BanList, _> = serde_json::from_reader(file);
+        match res {
+            Ok(json::BanList {
+                network_id: Some(recorded),
+                ..
+            }) if network_id.is_some_and(|id| id != recorded) => {
+                warn!(target: "network", "Ignoring banned nodes {}: recorded for network id {}, running network id {}", path.display(), recorded, network_id.unwrap_or_default());
+                Default::default()
+            }
+            Ok(list) => {
+                let now = SystemTime::now();
+                list.nodes
+                    .into_iter()
+                    .map(|n| n.into_ban_entry())
+                    .filter(|e| !e.is_expired(now))
+                    .take(MAX_BANNED_NODES)
+                    .map(|e| (e.id, e))
+                    .collect()
+            }
+            Err(e) => {
+                warn!(target: "network", "Error reading banned nodes file: {e:?}");
+                Default::default()
+            }
+        }
+    }
+}
+
+impl Drop for BanList {
+    fn drop(&mut self) {
+        self.save_if_changed();
+    }
+}
+
+mod json {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct BanList {
+        #[serde(default)]
+        pub network_id: Option<u64>,
+        pub nodes: Vec<BanEntry>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct BanEntry {
+        pub id: NodeId,
+        pub reason: String,
+        pub banned_at: u64,
+        pub expires_at: u64,
+    }
+
+    impl BanEntry {
+        pub fn into_ban_entry(self) -> super::BanEntry {
+            super::BanEntry {
+                id: self.id,
+                reason: self.reason,
+                banned_at: time::UNIX_EPOCH + Duration::from_secs(self.banned_at),
+                expires_at: time::UNIX_EPOCH + Duration::from_secs(self.expires_at),
+            }
+        }
+    }
+
+    fn as_secs(t: SystemTime) -> u64 {
+        t.duration_since(time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+
+    impl<'a> From<&'a super::BanEntry> for BanEntry {
+        fn from(entry: &'a super::BanEntry) -> Self {
+            BanEntry {
+                id: entry.id,
+                reason: entry.reason.clone(),
+                banned_at: as_secs(entry.banned_at),
+                expires_at: as_secs(entry.expires_at),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn ban_and_unban() {
+        let mut list = BanList::new(None, None);
+        let id = NodeId::random();
+
+        list.ban(id, Duration::from_secs(60), "test");
+        assert!(list.is_banned(&id));
+        assert_eq!(list.entries()[0].reason, "test");
+
+        assert!(list.unban(&id));
+        assert!(!list.is_banned(&id));
+        assert!(!list.unban(&id));
+    }
+
+    #[test]
+    fn expired_ban_is_ignored() {
+        let mut list = BanList::new(None, None);
+        let id = NodeId::random();
+
+        list.ban(id, Duration::from_secs(0), "test");
+        assert!(!list.is_banned(&id));
+        assert!(list.entries().is_empty());
+    }
+
+    #[test]
+    fn repeated_disables_ban() {
+        let mut list = BanList::new(None, None);
+        let id = NodeId::random();
+
+        for _ in 1..DISABLES_BEFORE_BAN {
+            assert!(!list.note_disable(id, Duration::from_secs(60)));
+        }
+        assert!(!list.is_banned(&id));
+        assert!(list.note_disable(id, Duration::from_secs(60)));
+        assert!(list.is_banned(&id));
+        assert!(list.changed);
+    }
+
+    #[test]
+    fn ban_list_save_load() {
+        let tempdir = TempDir::new().unwrap();
+        let path = Some(tempdir.path().to_str().unwrap().to_owned());
+        let banned = NodeId::random();
+        let expired = NodeId::random();
+
+        {
+            let mut list = BanList::new(path.clone(), None);
+            list.ban(banned, Duration::from_secs(3600), "useless peer");
+            list.ban(expired, Duration::from_secs(0), "useless peer");
+        }
+
+        let list = BanList::new(path, None);
+        assert!(list.is_banned(&banned));
+        assert!(!list.is_banned(&expired));
+        assert_eq!(list.entries().len(), 1);
+    }
+
+    #[test]
+    fn ban_list_is_saved_when_changed() {
+        let tempdir = TempDir::new().unwrap();
+        let path = Some(tempdir.path().to_str().unwrap().to_owned());
+        let file = tempdir.path().join(BANNED_NODES_FILE);
+        let banned = NodeId::random();
+
+        let mut list = BanList::new(path.clone(), None);
+        list.ban(banned, Duration::from_secs(3600), "test");
+        assert!(!file.exists());
+        list.save_if_changed();
+        assert!(BanList::new(path.clone(), None).is_banned(&banned));
+
+        fs::remove_file(&file).unwrap();
+        list.save_if_changed();
+        assert!(!file.exists());
+        assert!(list.unban(&banned));
+        list.save_if_changed();
+        assert!(!BanList::new(path, None).is_banned(&banned));
+    }
+
+    #[test]
+    fn ban_list_of_other_network_is_not_loaded() {
+        let tempdir = TempDir::new().unwrap();
+        let path = Some(tempdir.path().to_str().unwrap().to_owned());
+        let banned = NodeId::random();
+
+        BanList::new(path.clone(), Some(1)).ban(banned, Duration::from_secs(3600), "test");
+
+        assert!(BanList::new(path.clone(), Some(1)).is_banned(&banned));
+        assert!(!BanList::new(path, Some(5)).is_banned(&banned));
+    }
+
+    #[test]
+    fn ban_list_is_bounded() {
+        let mut list = BanList::new(None, None);
+        let first = NodeId::random();
+        list.ban(first, Duration::from_secs(60), "test");
+        for _ in 0..MAX_BANNED_NODES {
+            list.ban(NodeId::random(), Duration::from_secs(3600), "test");
+        }
+
+        assert_eq!(list.entries().len(), MAX_BANNED_NODES);
+        assert!(!list.is_banned(&first));
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Size-classed pool of byte buffers, reused for socket reads, snappy (de)compression and
+//! outgoing packet assembly to keep allocator churn down under heavy sync.
+//!
+//! Buffers are handed out as `PooledBuffer`, which goes back to the pool on drop. The pool
+//! keeps a bounded number of buffers per size class and a bounded number of bytes overall,
+//! so an idle node does not pin memory used during a burst.
+
+use parking_lot::Mutex;
+use std::{
+    fmt, mem,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+/// Capacities of the pooled buffers.
+const SIZE_CLASSES: [usize; 3] = [4 * 1024, 64 * 1024, 1024 * 1024];
+/// Maximum number of idle buffers kept per size class by the shared pool.
+const MAX_PER_CLASS: usize = 64;
+/// Maximum number of bytes kept in idle buffers by the shared pool.
+const MAX_RESIDENT_BYTES: usize = 16 * 1024 * 1024;
+
+/// Buffer pool usage statistics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BufferPoolStats {
+    /// Number of buffers served from the pool.
+    pub hits: u64,
+    /// Number of buffers which had to be allocated.
+    pub misses: u64,
+    /// Bytes held by idle buffers in the pool.
+    pub resident_bytes: usize,
+}
+
+struct State {
+    free: [Vec<Vec<u8>>; 3],
+    stats: BufferPoolStats,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    max_per_class: usize,
+    max_resident_bytes: usize,
+}
+
+/// Handle to a buffer pool. Cheap to clone.
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<Inner>,
+}
+
+impl Default for BufferPool {
+    /// A pool with the limits used for the sessions of a network service.
+    fn default() -> Self {
+        BufferPool::new(MAX_PER_CLASS, MAX_RESIDENT_BYTES)
+    }
+}
+
+impl BufferPool {
+    /// Create a pool keeping at most `max_per_class` idle buffers per size class
+    /// and at most `max_resident_bytes` in idle buffers overall.
+    pub fn new(max_per_class: usize, max_resident_bytes: usize) -> Self {
+        BufferPool {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State {
+                    free: Default::default(),
+                    stats: Default::default(),
+                }),
+                max_per_class,
+                max_resident_bytes,
+            }),
+        }
+    }
+
+    /// Get an empty buffer with at least `capacity` bytes of capacity.
+    /// Requests larger than the largest size class are allocated and not pooled.
+    pub fn get(&self, capacity: usize) -> PooledBuffer {
+        let class = match SIZE_CLASSES.iter().position(|&size| capacity <= size) {
+            Some(class) => class,
+            None => {
+                self.inner.state.lock().stats.misses += 1;
+                return PooledBuffer::from(Vec::with_capacity(capacity));
+            }
+        };
+
+        let mut state = self.inner.state.lock();
+        let buf = match state.free[class].pop() {
+            Some(buf) => {
+                state.stats.hits += 1;
+                state.stats.resident_bytes -= buf.capacity();
+                buf
+            }
+            None => {
+                state.stats.misses += 1;
+                Vec::with_capacity(SIZE_CLASSES[class])
+            }
+        };
+        PooledBuffer {
+            buf,
+            pool: Some(self.clone()),
+        }
+    }
+
+    /// Usage statistics.
+    pub fn stats(&self) -> BufferPoolStats {
+        self.inner.state.lock().stats
+    }
+
+    fn put(&self, mut buf: Vec<u8>) {
+        let capacity = buf.capacity();
+        // buffers which grew past the next size class are dropped, they would pin
+        // more memory than their class accounts for.
+        let class = match SIZE_CLASSES
+            .iter()
+            .rposition(|&size| size <= capacity && capacity < size * 2)
+        {
+            Some(class) => class,
+            None => return,
+        };
+
+        let mut state = self.inner.state.lock();
+        if state.free[class].len() < self.inner.max_per_class
+            && state.stats.resident_bytes + capacity <= self.inner.max_resident_bytes
+        {
+            buf.clear();
+            state.stats.resident_bytes += capacity;
+            state.free[class].push(buf);
+        }
+    }
+}
+
+/// A buffer, returned to its pool on drop.
+#[derive(Default)]
+pub struct PooledBuffer {
+    buf: Vec<u8>,
+    pool: Option<BufferPool>,
+}
+
+impl PooledBuffer {
+    /// Take the buffer out of the pool's management.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        self.pool = None;
+        mem::take(&mut self.buf)
+    }
+}
+
+impl From<Vec<u8>> for PooledBuffer {
+    fn from(buf: Vec<u8>) -> Self {
+        PooledBuffer { buf, pool: None }
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl Clone for PooledBuffer {
+    fn clone(&self) -> Self {
+        let mut buf = match self.pool {
+            Some(ref pool) => pool.get(self.buf.len()),
+            None => PooledBuffer::default(),
+        };
+        buf.extend_from_slice(&self.buf);
+        buf
+    }
+}
+
+impl fmt::Debug for PooledBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.buf.fmt(f)
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.put(mem::take(&mut self.buf));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_buffers_of_the_same_class() {
+        let pool = BufferPool::new(4, 1 << 20);
+        let mut buf = pool.get(100);
+        assert!(buf.capacity() >= 4 * 1024);
+        buf.extend_from_slice(&[1, 2, 3]);
+        drop(buf);
+        assert_eq!(pool.stats().resident_bytes, 4 * 1024);
+
+        let buf = pool.get(4000);
+        assert!(buf.is_empty());
+        let _big = pool.get(5000);
+        assert_eq!(
+            pool.stats(),
+            BufferPoolStats {
+                hits: 1,
+                misses: 2,
+                resident_bytes: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn caps_idle_buffers() {
+        let pool = BufferPool::new(2, 64 * 1024 + 8 * 1024);
+        let small: Vec<_> = (0..3).map(|_| pool.get(10)).collect();
+        drop(small);
+        assert_eq!(pool.stats().resident_bytes, 8 * 1024);
+
+        drop(pool.get(10_000));
+        assert_eq!(pool.stats().resident_bytes, 72 * 1024);
+        drop(pool.get(100_000));
+        assert_eq!(pool.stats().resident_bytes, 72 * 1024);
+    }
+
+    #[test]
+    fn does_not_pool_oversized_buffers() {
+        let pool = BufferPool::new(4, usize::MAX);
+        drop(pool.get(2 * 1024 * 1024));
+
+        let mut grown = pool.get(10);
+        grown.resize(3 * 1024 * 1024, 0);
+        drop(grown);
+        assert_eq!(pool.stats().resident_bytes, 0);
+
+        let plain = PooledBuffer::from(vec![1; 10]);
+        assert_eq!(plain.clone().into_vec(), vec![1; 10]);
+        drop(plain);
+        assert_eq!(pool.stats().resident_bytes, 0);
+    }
+}
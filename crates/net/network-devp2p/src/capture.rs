@@ -0,0 +1,423 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Capture of the decrypted subprotocol packets of a single session to a file, for debugging
+//! the interoperability with other clients.
+
+use ethereum_types::U64;
+use network::{Error, ErrorKind, PacketId, PeerId, ProtocolId};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Length of a record without its payload.
+const RECORD_HEADER_LEN: usize = 1 + 8 + 8 + 1;
+
+/// Direction of a captured packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureDirection {
+    /// Packet received from the peer.
+    Received,
+    /// Packet sent to the peer.
+    Sent,
+}
+
+/// Packet read back from a capture file.
+///
+/// A capture file is a sequence of records, each of them a big-endian `u32` holding the length
+/// of the rest of the record followed by:
+///
+/// | Bytes | Field                                                              |
+/// |-------|--------------------------------------------------------------------|
+/// | 1     | direction, 0 for a received and 1 for a sent packet                |
+/// | 8     | time of the capture, microseconds since the Unix epoch, big-endian |
+/// | 8     | protocol id, big-endian, e.g. `0x657468` for `eth`                 |
+/// | 1     | packet id within the protocol                                      |
+/// | rest  | payload, decompressed                                              |
+///
+/// Records which would exceed the size limit of the capture end it, the file only holds
+/// complete records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    /// Direction of the packet.
+    pub direction: CaptureDirection,
+    /// Time of the capture, with a precision of microseconds.
+    pub timestamp: SystemTime,
+    /// Subprotocol of the packet.
+    pub protocol: ProtocolId,
+    /// Packet id within the subprotocol.
+    pub packet_id: PacketId,
+    /// Payload of the packet.
+    pub payload: Vec<u8>,
+}
+
+impl CaptureRecord {
+    /// Read the next record of a capture file. `None` at the end of the file.
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<CaptureRecord>> {
+        let mut len = [0u8; 4];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len < RECORD_HEADER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "capture record is too short",
+            ));
+        }
+        let mut record = vec![0u8; len];
+        reader.read_exact(&mut record)?;
+        let direction = match record[0] {
+            0 => CaptureDirection::Received,
+            1 => CaptureDirection::Sent,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid direction of capture record",
+                ))
+            }
+        };
+        let mut word = [0u8; 8];
+        word.copy_from_slice(&record[1..9]);
+        let timestamp = UNIX_EPOCH + Duration::from_micros(u64::from_be_bytes(word));
+        word.copy_from_slice(&record[9..17]);
+        let protocol = U64::from(u64::from_be_bytes(word));
+        Ok(Some(CaptureRecord {
+            direction,
+            timestamp,
+            protocol,
+            packet_id: record[17],
+            payload: record.split_off(RECORD_HEADER_LEN),
+        }))
+    }
+
+    /// Read all records of a capture file.
+    pub fn read_all<R: Read>(mut reader: R) -> io::Result<Vec<CaptureRecord>> {
+        let mut records = Vec::new();
+        while let Some(record) = CaptureRecord::read_from(&mut reader)? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+}
+
+/// File a capture writes to, with the number of bytes written.
+struct Sink {
+    file: BufWriter<File>,
+    written: usize,
+    max_bytes: usize,
+}
+
+impl Sink {
+    /// Write a record. Returns `false` if the record does not fit.
+    fn write(
+        &mut self,
+        direction: CaptureDirection,
+        protocol: ProtocolId,
+        packet_id: PacketId,
+        payload: &[u8],
+    ) -> io::Result<bool> {
+        let len = RECORD_HEADER_LEN + payload.len();
+        if self.written + 4 + len > self.max_bytes {
+            return Ok(false);
+        }
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_micros() as u64);
+        self.file.write_all(&(len as u32).to_be_bytes())?;
+        self.file.write_all(&[match direction {
+            CaptureDirection::Received => 0,
+            CaptureDirection::Sent => 1,
+        }])?;
+        self.file.write_all(&micros.to_be_bytes())?;
+        self.file.write_all(&protocol.as_u64().to_be_bytes())?;
+        self.file.write_all(&[packet_id])?;
+        self.file.write_all(payload)?;
+        self.written += 4 + len;
+        Ok(true)
+    }
+}
+
+/// Capture of the packets of one session. Attached to the session, which drops it once it is
+/// over.
+pub(crate) struct PacketCapture {
+    sink: Mutex<Option<Sink>>,
+}
+
+impl PacketCapture {
+    /// Record a packet of the session. Returns `false` once the capture is over.
+    pub(crate) fn record(
+        &self,
+        direction: CaptureDirection,
+        protocol: ProtocolId,
+        packet_id: PacketId,
+        payload: &[u8],
+    ) -> bool {
+        let mut sink = self.sink.lock();
+        let more = match *sink {
+            Some(ref mut s) => match s.write(direction, protocol, packet_id, payload) {
+                Ok(more) => more,
+                Err(e) => {
+                    warn!(target: "network", "Error writing packet capture: {e}");
+                    false
+                }
+            },
+            None => false,
+        };
+        if !more {
+            Self::close(&mut sink);
+        }
+        more
+    }
+
+    fn close(sink: &mut Option<Sink>) {
+        if let Some(mut sink) = sink.take() {
+            if let Err(e) = sink.file.flush() {
+                warn!(target: "network", "Error writing packet capture: {e}");
+            }
+        }
+    }
+
+    /// Check if packets are still captured.
+    fn is_active(&self) -> bool {
+        self.sink.lock().is_some()
+    }
+}
+
+/// Packet captures of the sessions of a host, limited in number.
+pub(crate) struct PacketCaptures {
+    max_captures: usize,
+    dir: Option<PathBuf>,
+    running: Mutex<HashMap<PeerId, Arc<PacketCapture>>>,
+}
+
+impl PacketCaptures {
+    /// Allow at most `max_captures` captures at once, none if zero, to files in `dir`.
+    pub fn new(max_captures: usize, dir: Option<PathBuf>) -> PacketCaptures {
+        PacketCaptures {
+            max_captures,
+            dir,
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Start capturing the packets of `peer` to a new file `name` in the capture directory, at
+    /// most `max_bytes` of records. Fails if the peer is captured already, too many captures
+    /// are running or the file exists.
+    pub fn start(
+        self: &Arc<Self>,
+        peer: PeerId,
+        max_bytes: usize,
+        name: &str,
+    ) -> Result<CaptureHandle, Error> {
+        let mut components = Path::new(name).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) {
+            bail!(ErrorKind::InvalidCaptureName(name.into()));
+        }
+        let dir = self.dir.as_ref().ok_or(ErrorKind::NoCaptureDirectory)?;
+        let mut running = self.running.lock();
+        running.retain(|_, capture| capture.is_active());
+        if running.contains_key(&peer) {
+            bail!(ErrorKind::CaptureInProgress);
+        }
+        if running.len() >= self.max_captures {
+            bail!(ErrorKind::TooManyCaptures(self.max_captures));
+        }
+        fs::create_dir_all(dir)?;
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dir.join(name))?;
+        let capture = Arc::new(PacketCapture {
+            sink: Mutex::new(Some(Sink {
+                file: BufWriter::new(file),
+                written: 0,
+                max_bytes,
+            })),
+        });
+        running.insert(peer, capture.clone());
+        Ok(CaptureHandle {
+            peer,
+            capture,
+            captures: self.clone(),
+        })
+    }
+
+    /// End the capture of a peer whose session is over.
+    pub fn session_ended(&self, peer: PeerId) {
+        if let Some(capture) = self.running.lock().remove(&peer) {
+            PacketCapture::close(&mut capture.sink.lock());
+        }
+    }
+}
+
+/// Running packet capture, stopped when dropped.
+pub struct CaptureHandle {
+    peer: PeerId,
+    capture: Arc<PacketCapture>,
+    captures: Arc<PacketCaptures>,
+}
+
+impl CaptureHandle {
+    /// Captured peer.
+    pub fn peer(&self) -> PeerId {
+        self.peer
+    }
+
+    /// Check if packets are still captured. A capture ends once it reached its size limit.
+    pub fn is_active(&self) -> bool {
+        self.capture.is_active()
+    }
+
+    pub(crate) fn capture(&self) -> Arc<PacketCapture> {
+        self.capture.clone()
+    }
+}
+
+impl Drop for CaptureHandle {
+    fn drop(&mut self) {
+        PacketCapture::close(&mut self.capture.sink.lock());
+        let mut running = self.captures.running.lock();
+        if running
+            .get(&self.peer)
+            .is_some_and(|capture| Arc::ptr_eq(capture, &self.capture))
+        {
+            running.remove(&self.peer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const ETH: ProtocolId = U64([0x65_7468]);
+
+    #[test]
+    fn records_are_read_back() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("capture");
+        let captures = Arc::new(PacketCaptures::new(1, Some(dir.path().into())));
+        let handle = captures.start(3, 1024, "capture").unwrap();
+        let capture = handle.capture();
+
+        assert!(capture.record(CaptureDirection::Sent, ETH, 0x03, b"request"));
+        assert!(capture.record(CaptureDirection::Received, ETH, 0x04, b""));
+        drop(handle);
+        assert!(!capture.record(CaptureDirection::Sent, ETH, 0x03, b"late"));
+
+        let records = CaptureRecord::read_all(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].direction, CaptureDirection::Sent);
+        assert_eq!(records[0].protocol, ETH);
+        assert_eq!(records[0].packet_id, 0x03);
+        assert_eq!(records[0].payload, b"request".to_vec());
+        assert_eq!(records[1].direction, CaptureDirection::Received);
+        assert_eq!(records[1].packet_id, 0x04);
+        assert!(records[1].payload.is_empty());
+        assert!(records[0].timestamp <= records[1].timestamp);
+    }
+
+    #[test]
+    fn capture_ends_at_size_limit() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("capture");
+        let captures = Arc::new(PacketCaptures::new(1, Some(dir.path().into())));
+        // room for two records with a payload of 10 bytes
+        let handle = captures
+            .start(3, 2 * (4 + RECORD_HEADER_LEN + 10) + 5, "capture")
+            .unwrap();
+        let capture = handle.capture();
+
+        assert!(capture.record(CaptureDirection::Sent, ETH, 1, &[1; 10]));
+        assert!(capture.record(CaptureDirection::Sent, ETH, 2, &[2; 10]));
+        assert!(!capture.record(CaptureDirection::Sent, ETH, 3, &[3; 10]));
+        assert!(!handle.is_active());
+
+        let records = CaptureRecord::read_all(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            fs::metadata(&path).unwrap().len() as usize,
+            2 * (4 + RECORD_HEADER_LEN + 10)
+        );
+    }
+
+    #[test]
+    fn limits_captures() {
+        let dir = TempDir::new().unwrap();
+        let captures = Arc::new(PacketCaptures::new(2, Some(dir.path().into())));
+        let start = |peer: PeerId, name: &str| captures.start(peer, 1024, name);
+
+        let first = start(1, "1").unwrap();
+        let err = start(1, "1b").map(|_| ()).unwrap_err();
+        assert_matches!(err.kind(), ErrorKind::CaptureInProgress);
+        let _second = start(2, "2").unwrap();
+        let err = start(3, "3").map(|_| ()).unwrap_err();
+        assert_matches!(err.kind(), ErrorKind::TooManyCaptures(2));
+
+        drop(first);
+        assert!(start(3, "3").is_ok());
+        let err = Arc::new(PacketCaptures::new(0, Some(dir.path().into())))
+            .start(1, 1024, "0")
+            .map(|_| ())
+            .unwrap_err();
+        assert_matches!(err.kind(), ErrorKind::TooManyCaptures(0));
+    }
+
+    #[test]
+    fn capture_ends_with_session() {
+        let dir = TempDir::new().unwrap();
+        let captures = Arc::new(PacketCaptures::new(1, Some(dir.path().into())));
+        let handle = captures.start(1, 1024, "1").unwrap();
+
+        captures.session_ended(1);
+        assert!(!handle.is_active());
+        assert!(captures.start(2, 1024, "2").is_ok());
+    }
+
+    #[test]
+    fn captures_only_to_new_files_in_capture_directory() {
+        let dir = TempDir::new().unwrap();
+        let captures = Arc::new(PacketCaptures::new(1, Some(dir.path().join("captures"))));
+
+        for name in &["../escape", "/tmp/capture", "sub/capture", "..", ""] {
+            let err = captures.start(1, 1024, name).map(|_| ()).unwrap_err();
+            assert_matches!(err.kind(), ErrorKind::InvalidCaptureName(_));
+        }
+        drop(captures.start(1, 1024, "capture").unwrap());
+        assert!(dir.path().join("captures").join("capture").exists());
+        // existing files are never truncated
+        assert!(captures.start(1, 1024, "capture").is_err());
+
+        let err = Arc::new(PacketCaptures::new(1, None))
+            .start(1, 1024, "capture")
+            .map(|_| ())
+            .unwrap_err();
+        assert_matches!(err.kind(), ErrorKind::NoCaptureDirectory);
+    }
+}
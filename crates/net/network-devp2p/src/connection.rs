@@ -17,6 +17,7 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 
+use buffer_pool::{BufferPool, PooledBuffer};
 use bytes::{Buf, BufMut};
 use crypto::{
     aes::{AesCtr256, AesEcb256},
@@ -31,8 +32,7 @@ use mio::{
     tcp::*,
     PollOpt, Ready, Token,
 };
-use network::{Error, ErrorKind};
-use parity_bytes::*;
+use network::{Error, ErrorKind, MAX_PAYLOAD_SIZE};
 use rlp::{Rlp, RlpStream};
 use std::{
     collections::VecDeque,
@@ -45,12 +45,14 @@ use tiny_keccak::Keccak;
 
 const ENCRYPTED_HEADER_LEN: usize = 32;
 const RECEIVE_PAYLOAD: Duration = Duration::from_secs(30);
-pub const MAX_PAYLOAD_SIZE: usize = (1 << 24) - 1;
 
 /// Network responses should try not to go over this limit.
 /// This should be lower than MAX_PAYLOAD_SIZE
 pub const PAYLOAD_SOFT_LIMIT: usize = (1 << 22) - 1;
 
+/// Protocol packets are refused while a connection has this much data waiting to be sent.
+pub const MAX_SEND_QUEUE_BYTES: usize = 64 * 1024 * 1024;
+
 pub trait GenericSocket: Read + Write {}
 
 impl GenericSocket for TcpStream {}
@@ -61,11 +63,13 @@ pub struct GenericConnection<Socket: GenericSocket> {
     /// Network socket
     pub socket: Socket,
     /// Receive buffer
-    rec_buf: Bytes,
+    rec_buf: PooledBuffer,
     /// Expected size
     rec_size: usize,
     /// Send out packets FIFO
-    send_queue: VecDeque<Cursor<Bytes>>,
+    send_queue: VecDeque<Cursor<PooledBuffer>>,
+    /// Pool of receive and send buffers
+    pool: BufferPool,
     /// Event flags this connection expects
     interest: Ready,
     /// Registered flag
@@ -78,11 +82,19 @@ impl<Socket: GenericSocket> GenericConnection<Socket> {
         if self.rec_size != self.rec_buf.len() {
             warn!(target:"network", "Unexpected connection read start");
         }
+        if self.rec_buf.capacity() == 0 {
+            self.rec_buf = self.pool.get(size);
+        }
         self.rec_size = size;
     }
 
+    /// Pool of receive and send buffers.
+    pub fn pool(&self) -> &BufferPool {
+        &self.pool
+    }
+
     /// Readable IO handler. Called when there is some data to be read.
-    pub fn readable(&mut self) -> io::Result<Option<Bytes>> {
+    pub fn readable(&mut self) -> io::Result<Option<PooledBuffer>> {
         if self.rec_size == 0 || self.rec_buf.len() >= self.rec_size {
             return Ok(None);
         }
@@ -116,10 +128,12 @@ impl<Socket: GenericSocket> GenericConnection<Socket> {
     }
 
     /// Add a packet to send queue.
-    pub fn send<Message>(&mut self, io: &IoContext<Message>, data: Bytes)
+    pub fn send<Message, Data>(&mut self, io: &IoContext<Message>, data: Data)
     where
         Message: Send + Clone + Sync + 'static,
+        Data: Into<PooledBuffer>,
     {
+        let data = data.into();
         if !data.is_empty() {
             trace!(target:"network", "{}: Sending {} bytes", self.token, data.len());
             self.send_queue.push_back(Cursor::new(data));
@@ -130,6 +144,14 @@ impl<Socket: GenericSocket> GenericConnection<Socket> {
         }
     }
 
+    /// Number of bytes waiting to be sent.
+    pub fn queued_bytes(&self) -> usize {
+        self.send_queue
+            .iter()
+            .map(|buf| buf.get_ref().len() - buf.position() as usize)
+            .sum()
+    }
+
     /// Check if this connection has data to be sent.
     pub fn is_sending(&self) -> bool {
         self.interest.is_writable()
@@ -185,14 +207,15 @@ impl<Socket: GenericSocket> GenericConnection<Socket> {
 pub type Connection = GenericConnection<TcpStream>;
 
 impl Connection {
-    /// Create a new connection with given id and socket.
-    pub fn new(token: StreamToken, socket: TcpStream) -> Connection {
+    /// Create a new connection with given id and socket, taking its buffers from `pool`.
+    pub fn new(token: StreamToken, socket: TcpStream, pool: BufferPool) -> Connection {
         Connection {
             token,
             socket,
             send_queue: VecDeque::new(),
-            rec_buf: Bytes::new(),
+            rec_buf: PooledBuffer::default(),
             rec_size: 0,
+            pool,
             interest: Ready::hup() | Ready::readable(),
             registered: AtomicBool::new(false),
         }
@@ -229,9 +252,10 @@ impl Connection {
         Ok(Connection {
             token: self.token,
             socket: self.socket.try_clone()?,
-            rec_buf: Vec::new(),
+            rec_buf: PooledBuffer::default(),
             rec_size: 0,
             send_queue: self.send_queue.clone(),
+            pool: self.pool.clone(),
             interest: Ready::hup(),
             registered: AtomicBool::new(false),
         })
@@ -308,7 +332,7 @@ pub enum WriteStatus {
 /// `RLPx` packet
 pub struct Packet {
     pub protocol: u16,
-    pub data: Bytes,
+    pub data: PooledBuffer,
 }
 
 /// Encrypted connection receiving state.
@@ -434,7 +458,9 @@ impl EncryptedConnection {
         header.append_raw(&[0xc2u8, 0x80u8, 0x80u8], 1);
         let padding = (16 - (len % 16)) % 16;
 
-        let mut packet = vec![0u8; 16 + 16 + len + padding + 16];
+        let packet_len = 16 + 16 + len + padding + 16;
+        let mut packet = self.connection.pool.get(packet_len);
+        packet.resize(packet_len, 0u8);
         let mut header = header.out();
         header.resize(HEADER_LEN, 0u8);
         let _ = &mut packet[..HEADER_LEN].copy_from_slice(&mut header);
@@ -464,7 +490,7 @@ impl EncryptedConnection {
     }
 
     /// Decrypt and authenticate an incoming packet header. Prepare for receiving payload.
-    fn read_header(&mut self, mut header: Bytes) -> Result<(), Error> {
+    fn read_header(&mut self, mut header: PooledBuffer) -> Result<(), Error> {
         if header.len() != ENCRYPTED_HEADER_LEN {
             return Err(ErrorKind::Auth.into());
         }
@@ -498,7 +524,7 @@ impl EncryptedConnection {
     }
 
     /// Decrypt and authenticate packet payload.
-    fn read_payload(&mut self, mut payload: Bytes) -> Result<Packet, Error> {
+    fn read_payload(&mut self, mut payload: PooledBuffer) -> Result<Packet, Error> {
         let padding = (16 - (self.payload_len % 16)) % 16;
         let full_length = self.payload_len + padding + 16;
         if payload.len() != full_length {
@@ -590,7 +616,6 @@ mod tests {
     use super::*;
     use io::*;
     use mio::Ready;
-    use parity_bytes::Bytes;
 
     pub struct TestSocket {
         pub read_buffer: Vec<u8>,
@@ -700,8 +725,9 @@ mod tests {
                 token: 999998888usize,
                 socket: TestSocket::new(),
                 send_queue: VecDeque::new(),
-                rec_buf: Bytes::new(),
+                rec_buf: PooledBuffer::default(),
                 rec_size: 0,
+                pool: BufferPool::new(4, 1 << 20),
                 interest: Ready::hup() | Ready::readable(),
                 registered: AtomicBool::new(false),
             }
@@ -724,8 +750,9 @@ mod tests {
                     error: "test broken socket".to_owned(),
                 },
                 send_queue: VecDeque::new(),
-                rec_buf: Bytes::new(),
+                rec_buf: PooledBuffer::default(),
                 rec_size: 0,
+                pool: BufferPool::new(4, 1 << 20),
                 interest: Ready::hup() | Ready::readable(),
                 registered: AtomicBool::new(false),
             }
@@ -780,7 +807,7 @@ mod tests {
     #[test]
     fn connection_write() {
         let mut connection = TestConnection::new();
-        let data = Cursor::new(vec![0; 10240]);
+        let data = Cursor::new(vec![0; 10240].into());
         connection.send_queue.push_back(data);
 
         let status = connection.writable(&test_io());
@@ -793,7 +820,7 @@ mod tests {
     fn connection_write_is_buffered() {
         let mut connection = TestConnection::new();
         connection.socket = TestSocket::new_buf(1024);
-        let data = Cursor::new(vec![0; 10240]);
+        let data = Cursor::new(vec![0; 10240].into());
         connection.send_queue.push_back(data);
 
         let status = connection.writable(&test_io());
@@ -806,7 +833,7 @@ mod tests {
     #[test]
     fn connection_write_to_broken() {
         let mut connection = TestBrokenConnection::new();
-        let data = Cursor::new(vec![0; 10240]);
+        let data = Cursor::new(vec![0; 10240].into());
         connection.send_queue.push_back(data);
 
         let status = connection.writable(&test_io());
@@ -819,7 +846,7 @@ mod tests {
     fn connection_read() {
         let mut connection = TestConnection::new();
         connection.rec_size = 2048;
-        connection.rec_buf = vec![10; 1024];
+        connection.rec_buf = vec![10; 1024].into();
         connection.socket.read_buffer = vec![99; 2048];
 
         let status = connection.readable();
@@ -853,7 +880,7 @@ mod tests {
     fn connection_read_full() {
         let mut connection = TestConnection::new();
         connection.rec_size = 1024;
-        connection.rec_buf = vec![76; 1024];
+        connection.rec_buf = vec![76; 1024].into();
 
         let status = connection.readable();
 
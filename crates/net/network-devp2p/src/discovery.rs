@@ -18,7 +18,7 @@ use crypto::publickey::{recover, sign, KeyPair, Secret};
 use ethereum_types::{H256, H520};
 use hash::keccak;
 use lru::LruCache;
-use network::{Error, ErrorKind, IpFilter};
+use network::{Error, ErrorKind, IpFilter, NodeRecord};
 use node_table::*;
 use parity_bytes::Bytes;
 use rlp::{Rlp, RlpStream};
@@ -42,6 +42,8 @@ const PACKET_PING: u8 = 1;
 const PACKET_PONG: u8 = 2;
 const PACKET_FIND_NODE: u8 = 3;
 const PACKET_NEIGHBOURS: u8 = 4;
+const PACKET_ENR_REQUEST: u8 = 5;
+const PACKET_ENR_RESPONSE: u8 = 6;
 
 const PING_TIMEOUT: Duration = Duration::from_millis(500);
 const FIND_NODE_TIMEOUT: Duration = Duration::from_secs(2);
@@ -183,6 +185,8 @@ pub struct Discovery<'a> {
     adding_nodes: Vec<NodeEntry>,
     ip_filter: IpFilter,
     request_backoff: &'a [Duration],
+    // Record served to ENR requests, its sequence number is announced in pings and pongs (EIP-868)
+    node_record: Option<NodeRecord>,
 }
 
 pub struct TableUpdates {
@@ -210,9 +214,25 @@ impl<'a> Discovery<'a> {
             adding_nodes: Vec::new(),
             ip_filter,
             request_backoff: &REQUEST_BACKOFF,
+            node_record: None,
         }
     }
 
+    /// Serve `record` to ENR requests from now on.
+    pub fn set_node_record(&mut self, record: NodeRecord) {
+        self.node_record = Some(record);
+    }
+
+    fn append_enr_seq(&self, rlp: &mut RlpStream) {
+        if let Some(ref record) = self.node_record {
+            rlp.append(&record.seq());
+        }
+    }
+
+    fn list_len(&self, len: usize) -> usize {
+        len + usize::from(self.node_record.is_some())
+    }
+
     /// Add a new node to discovery table. Pings the node.
     pub fn add_node(&mut self, e: NodeEntry) {
         // If distance returns None, then we are trying to add ourself.
@@ -400,11 +420,12 @@ impl<'a> Discovery<'a> {
     }
 
     fn ping(&mut self, node: &NodeEntry, reason: PingReason) -> Result<(), Error> {
-        let mut rlp = RlpStream::new_list(4);
+        let mut rlp = RlpStream::new_list(self.list_len(4));
         rlp.append(&PROTOCOL_VERSION);
         self.public_endpoint.to_rlp_list(&mut rlp);
         node.endpoint.to_rlp_list(&mut rlp);
         append_expiration(&mut rlp);
+        self.append_enr_seq(&mut rlp);
         let old_parity_hash = keccak(rlp.as_raw());
         let hash = self.send_packet(PACKET_PING, &node.endpoint.udp_address(), &rlp.drain())?;
 
@@ -534,6 +555,9 @@ impl<'a> Discovery<'a> {
             PACKET_PONG => self.on_pong(&rlp, &node_id, &from),
             PACKET_FIND_NODE => self.on_find_node(&rlp, &node_id, &from),
             PACKET_NEIGHBOURS => self.on_neighbours(&rlp, &node_id, &from),
+            PACKET_ENR_REQUEST => {
+                self.on_enr_request(&rlp, &node_id, &from, hash_signed.as_bytes())
+            }
             _ => {
                 debug!(target: "discovery", "Unknown UDP packet: {packet_id}");
                 Ok(None)
@@ -581,7 +605,7 @@ impl<'a> Discovery<'a> {
         let ping_to = NodeEndpoint::from_rlp(&rlp.at(2)?)?;
         let timestamp: u64 = rlp.val_at(3)?;
         self.check_timestamp(timestamp)?;
-        let mut response = RlpStream::new_list(3);
+        let mut response = RlpStream::new_list(self.list_len(3));
         let pong_to = NodeEndpoint {
             address: *from,
             udp_port: ping_from.udp_port,
@@ -596,6 +620,7 @@ impl<'a> Discovery<'a> {
 
         response.append(&echo_hash);
         append_expiration(&mut response);
+        self.append_enr_seq(&mut response);
         self.send_packet(PACKET_PONG, from, &response.drain())?;
 
         let entry = NodeEntry {
@@ -711,6 +736,41 @@ impl<'a> Discovery<'a> {
         Ok(None)
     }
 
+    fn on_enr_request(
+        &mut self,
+        rlp: &Rlp,
+        node_id: &NodeId,
+        from: &SocketAddr,
+        request_hash: &[u8],
+    ) -> Result<Option<TableUpdates>, Error> {
+        trace!(target: "discovery", "Got EnrRequest from {:?}", &from);
+        let timestamp: u64 = rlp.val_at(0)?;
+        self.check_timestamp(timestamp)?;
+        let record = match self.node_record {
+            Some(ref record) => record.encode(),
+            None => return Ok(None),
+        };
+
+        let node = NodeEntry {
+            id: *node_id,
+            endpoint: NodeEndpoint {
+                address: *from,
+                udp_port: from.port(),
+            },
+        };
+        // The response is larger than the request, only answer nodes which answered our pings
+        if let NodeValidity::ValidNode(_) = self.check_validity(&node) {
+            let mut response = RlpStream::new_list(2);
+            response.append(&request_hash);
+            response.append_raw(&record, 1);
+            self.send_packet(PACKET_ENR_RESPONSE, from, &response.drain())?;
+            trace!(target: "discovery", "Sent EnrResponse to {:?}", &from);
+        } else {
+            debug!(target: "discovery", "Ignoring EnrRequest from unknown node {:?}", &from);
+        }
+        Ok(None)
+    }
+
     fn check_validity(&mut self, node: &NodeEntry) -> NodeValidity {
         let id_hash = keccak(node.id);
         let dist = match Discovery::distance(&self.id_hash, &id_hash) {
@@ -982,6 +1042,7 @@ mod tests {
     use std::net::{IpAddr, Ipv4Addr};
 
     use crypto::publickey::{Generator, Random};
+    use network::LocalNodeRecord;
     use rustc_hex::FromHex;
     use std::str::FromStr;
 
@@ -1495,4 +1556,66 @@ mod tests {
             panic!("Expected no changes to discovery1's table for unexpected pong");
         }
     }
+
+    #[test]
+    fn answers_enr_requests_of_known_nodes() {
+        let key1 = Random.generate();
+        let key2 = Random.generate();
+        let ep1 = NodeEndpoint {
+            address: SocketAddr::from_str("127.0.0.1:40447").unwrap(),
+            udp_port: 40447,
+        };
+        let ep2 = NodeEndpoint {
+            address: SocketAddr::from_str("127.0.0.1:40448").unwrap(),
+            udp_port: 40448,
+        };
+        let mut discovery1 = Discovery::new(&key1, ep1.clone(), IpFilter::default());
+        let mut discovery2 = Discovery::new(&key2, ep2.clone(), IpFilter::default());
+        let mut record = LocalNodeRecord::new(key1.secret().clone(), 1).unwrap();
+        record.set_endpoint(&ep1.address, ep1.udp_port).unwrap();
+        discovery1.set_node_record(record.record().clone());
+
+        let enr_request = |discovery: &Discovery| {
+            let mut rlp = RlpStream::new_list(1);
+            append_expiration(&mut rlp);
+            assemble_packet(PACKET_ENR_REQUEST, &rlp.drain(), &discovery.secret).unwrap()
+        };
+
+        // the endpoint of unknown nodes is not proven, they get no answer
+        discovery1
+            .on_packet(&enr_request(&discovery2), ep2.address)
+            .unwrap();
+        assert!(!discovery1.any_sends_queued());
+
+        discovery1
+            .ping(
+                &NodeEntry {
+                    id: discovery2.id,
+                    endpoint: ep2.clone(),
+                },
+                PingReason::Default,
+            )
+            .unwrap();
+        let ping = discovery1.dequeue_send().unwrap();
+        // pings announce the sequence number of the record
+        let rlp = Rlp::new(&ping.payload[(32 + 65 + 1)..]);
+        assert_eq!(rlp.item_count().unwrap(), 5);
+        assert_eq!(rlp.val_at::<u64>(4).unwrap(), record.record().seq());
+        discovery2.on_packet(&ping.payload, ep1.address).unwrap();
+        let pong = discovery2.dequeue_send().unwrap();
+        discovery1.on_packet(&pong.payload, ep2.address).unwrap();
+        while discovery1.dequeue_send().is_some() {}
+
+        let request = enr_request(&discovery2);
+        discovery1.on_packet(&request, ep2.address).unwrap();
+        let response = discovery1.dequeue_send().unwrap();
+        assert_eq!(response.address, ep2.address);
+        let data = &response.payload[(32 + 65)..];
+        assert_eq!(data[0], PACKET_ENR_RESPONSE);
+        let rlp = Rlp::new(&data[1..]);
+        assert_eq!(rlp.val_at::<Vec<u8>>(0).unwrap(), request[0..32].to_vec());
+        let served = NodeRecord::decode(rlp.at(1).unwrap().as_raw()).unwrap();
+        assert_eq!(served, *record.record());
+        assert_eq!(served.id(), key1.public());
+    }
 }
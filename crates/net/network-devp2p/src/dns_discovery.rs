@@ -0,0 +1,1120 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! DNS based node discovery (EIP-1459).
+//!
+//! Node lists are published as a merkle tree of TXT records below a domain. The root record is
+//! signed by the key embedded in the `enrtree://<key>@<domain>` URL, every other record is
+//! looked up by the hash of its content, so a valid root authenticates the whole tree. Trees are
+//! crawled a few lookups per round; records are cached by hash and once a tree is synced only its
+//! root is checked again for updates.
+
+use crypto::publickey::{recover, Public, Signature};
+use discovery::NodeEntry;
+use ethereum_types::H256;
+use hash::keccak;
+use lru::LruCache;
+use node_table::{NodeEndpoint, NodeId};
+use parking_lot::Mutex;
+use rlp::{Rlp, RlpStream};
+use std::{
+    collections::{HashSet, VecDeque},
+    fs, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    str::FromStr,
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+const ROOT_PREFIX: &str = "enrtree-root:v1";
+const BRANCH_PREFIX: &str = "enrtree-branch:";
+const LINK_PREFIX: &str = "enrtree://";
+const ENR_PREFIX: &str = "enr:";
+
+/// Maximum number of DNS lookups per round.
+const MAX_LOOKUPS_PER_ROUND: usize = 4;
+/// How long a synced tree is left alone before its root is checked for updates.
+const TREE_REFRESH_INTERVAL: Duration = Duration::from_secs(30 * 60);
+/// Maximum number of cached records.
+const MAX_CACHED_RECORDS: usize = 10_000;
+/// Maximum size of an encoded node record (EIP-778).
+const MAX_ENR_SIZE: usize = 300;
+
+const DNS_PORT: u16 = 53;
+const DNS_TIMEOUT: Duration = Duration::from_secs(2);
+const DNS_TYPE_TXT: u16 = 16;
+const DNS_CLASS_IN: u16 = 1;
+const MAX_DNS_MESSAGE_SIZE: usize = 4096;
+
+/// Resolves TXT records.
+pub trait Resolver: Send {
+    /// All TXT records of `name`, each with its character strings concatenated.
+    fn txt(&self, name: &str) -> Result<Vec<String>, String>;
+}
+
+/// Counters of skipped records and lookup failures.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// DNS lookups performed.
+    pub lookups: u64,
+    /// Lookups which failed or returned no record.
+    pub failed_lookups: u64,
+    /// Records which could not be parsed or don't belong in their subtree.
+    pub malformed_records: u64,
+    /// Roots and node records with an invalid signature.
+    pub bad_signatures: u64,
+    /// Records whose content doesn't match the hash they were looked up by.
+    pub hash_mismatches: u64,
+    /// Branch entries pointing to a record already visited during the same sync.
+    pub cycles: u64,
+    /// Distinct nodes discovered.
+    pub nodes: u64,
+}
+
+/// Location and public key of a node tree, parsed from an `enrtree://<key>@<domain>` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeUrl {
+    /// Compressed public key the tree root is signed with.
+    key: [u8; 33],
+    /// Domain of the root record.
+    domain: String,
+}
+
+impl FromStr for TreeUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s
+            .strip_prefix(LINK_PREFIX)
+            .ok_or_else(|| format!("Missing {LINK_PREFIX} scheme"))?;
+        let (key, domain) = rest
+            .split_once('@')
+            .ok_or_else(|| "Missing domain".to_string())?;
+        let key = base32_decode(key).ok_or_else(|| "Invalid public key encoding".to_string())?;
+        if key.len() != 33 || (key[0] != 2 && key[0] != 3) {
+            return Err("Invalid public key".into());
+        }
+        if domain.is_empty() || domain.len() > 253 {
+            return Err("Invalid domain".into());
+        }
+        let mut url = TreeUrl {
+            key: [0u8; 33],
+            domain: domain.trim_end_matches('.').to_ascii_lowercase(),
+        };
+        url.key.copy_from_slice(&key);
+        Ok(url)
+    }
+}
+
+/// Reason a record was skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RecordError {
+    Malformed(&'static str),
+    BadSignature,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Root {
+    enr_root: String,
+    link_root: String,
+    seq: u32,
+}
+
+#[derive(Debug, Clone)]
+enum Entry {
+    Branch(Vec<String>),
+    Link(TreeUrl),
+    Node(Option<NodeEntry>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Subtree {
+    Enr,
+    Link,
+}
+
+struct Tree {
+    url: TreeUrl,
+    // sequence number of the last synced root.
+    seq: Option<u32>,
+    // records still to be visited in the current sync.
+    pending: VecDeque<(String, Subtree)>,
+    // records visited in the current sync.
+    visited: HashSet<String>,
+    // when the root was checked last.
+    checked: Option<Instant>,
+}
+
+impl Tree {
+    fn new(url: TreeUrl) -> Self {
+        Tree {
+            url,
+            seq: None,
+            pending: VecDeque::new(),
+            visited: HashSet::new(),
+            checked: None,
+        }
+    }
+}
+
+// outcome of a single crawl step.
+enum Step {
+    Idle,
+    Cached,
+    Lookup,
+}
+
+/// Crawls the node trees of a set of `enrtree://` URLs.
+pub struct DnsDiscovery {
+    resolver: Box<dyn Resolver>,
+    trees: Vec<Tree>,
+    cache: LruCache<String, Entry>,
+    known: HashSet<NodeId>,
+    stats: Stats,
+    max_lookups: usize,
+    refresh_interval: Duration,
+}
+
+impl DnsDiscovery {
+    /// Create a crawler for the given tree URLs. Invalid URLs are logged and ignored.
+    pub fn new(urls: &[String], resolver: Box<dyn Resolver>) -> Self {
+        let mut trees: Vec<Tree> = Vec::new();
+        for url in urls {
+            match url.parse::<TreeUrl>() {
+                Ok(url) => {
+                    if !trees.iter().any(|t| t.url == url) {
+                        trees.push(Tree::new(url));
+                    }
+                }
+                Err(e) => warn!(target: "dns_discovery", "Ignoring DNS discovery URL {url}: {e}"),
+            }
+        }
+
+        DnsDiscovery {
+            resolver,
+            trees,
+            cache: LruCache::new(MAX_CACHED_RECORDS),
+            known: HashSet::new(),
+            stats: Stats::default(),
+            max_lookups: MAX_LOOKUPS_PER_ROUND,
+            refresh_interval: TREE_REFRESH_INTERVAL,
+        }
+    }
+
+    /// Check if there are no trees to crawl.
+    pub fn is_empty(&self) -> bool {
+        self.trees.is_empty()
+    }
+
+    /// Counters of the crawl so far.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Continue crawling with a limited number of lookups, serving the trees in turn.
+    /// Returns the nodes discovered for the first time.
+    pub fn round(&mut self, now: Instant) -> Vec<NodeEntry> {
+        let mut found = Vec::new();
+        let mut lookups = 0;
+        // roots are checked at most once per round.
+        let mut roots_checked = Vec::new();
+        loop {
+            let mut progress = false;
+            // trees added by links in this pass are served in the next one.
+            roots_checked.resize(self.trees.len(), false);
+            for (i, root_checked) in roots_checked.iter_mut().enumerate() {
+                if lookups >= self.max_lookups {
+                    return found;
+                }
+                match self.step(i, now, root_checked, &mut found) {
+                    Step::Idle => {}
+                    Step::Cached => progress = true,
+                    Step::Lookup => {
+                        progress = true;
+                        lookups += 1;
+                    }
+                }
+            }
+            if !progress {
+                return found;
+            }
+        }
+    }
+
+    // visit the next pending record of tree `i`, or check its root if due.
+    fn step(
+        &mut self,
+        i: usize,
+        now: Instant,
+        root_checked: &mut bool,
+        found: &mut Vec<NodeEntry>,
+    ) -> Step {
+        if let Some((hash, subtree)) = self.trees[i].pending.pop_front() {
+            let (entry, step) = match self.cache.get(&hash).cloned() {
+                Some(entry) => (Some(entry), Step::Cached),
+                None => {
+                    let name = format!("{}.{}", hash, self.trees[i].url.domain);
+                    (self.fetch_entry(&name, &hash), Step::Lookup)
+                }
+            };
+            if let Some(entry) = entry {
+                self.visit(i, subtree, entry, found);
+            }
+            return step;
+        }
+
+        let interval = self.refresh_interval;
+        let tree = &mut self.trees[i];
+        if *root_checked
+            || tree
+                .checked
+                .is_some_and(|checked| now.saturating_duration_since(checked) < interval)
+        {
+            return Step::Idle;
+        }
+        *root_checked = true;
+        tree.checked = Some(now);
+
+        let url = tree.url.clone();
+        if let Some(root) = self.fetch_root(&url) {
+            let tree = &mut self.trees[i];
+            if tree.seq != Some(root.seq) {
+                trace!(target: "dns_discovery", "Syncing tree {} at seq {}", url.domain, root.seq);
+                tree.seq = Some(root.seq);
+                tree.visited.clear();
+                tree.pending.clear();
+                for (hash, subtree) in [
+                    (root.enr_root, Subtree::Enr),
+                    (root.link_root, Subtree::Link),
+                ] {
+                    tree.visited.insert(hash.clone());
+                    tree.pending.push_back((hash, subtree));
+                }
+            }
+        }
+        Step::Lookup
+    }
+
+    fn visit(&mut self, i: usize, subtree: Subtree, entry: Entry, found: &mut Vec<NodeEntry>) {
+        match (entry, subtree) {
+            (Entry::Branch(children), _) => {
+                let tree = &mut self.trees[i];
+                for child in children {
+                    if tree.visited.insert(child.clone()) {
+                        tree.pending.push_back((child, subtree));
+                    } else {
+                        self.stats.cycles += 1;
+                    }
+                }
+            }
+            (Entry::Link(url), Subtree::Link) => {
+                if !self.trees.iter().any(|t| t.url == url) {
+                    debug!(target: "dns_discovery", "Following link to {}", url.domain);
+                    self.trees.push(Tree::new(url));
+                }
+            }
+            (Entry::Node(Some(node)), Subtree::Enr) => {
+                if self.known.insert(node.id) {
+                    self.stats.nodes += 1;
+                    found.push(node);
+                }
+            }
+            // valid record without a reachable endpoint.
+            (Entry::Node(None), Subtree::Enr) => {}
+            (_, subtree) => {
+                debug!(target: "dns_discovery", "Unexpected record in {subtree:?} subtree of {}", self.trees[i].url.domain);
+                self.stats.malformed_records += 1;
+            }
+        }
+    }
+
+    fn lookup(&mut self, name: &str) -> Vec<String> {
+        self.stats.lookups += 1;
+        match self.resolver.txt(name) {
+            Ok(ref records) if records.is_empty() => {
+                trace!(target: "dns_discovery", "No TXT records at {name}");
+                self.stats.failed_lookups += 1;
+                Vec::new()
+            }
+            Ok(records) => records,
+            Err(e) => {
+                debug!(target: "dns_discovery", "Error resolving {name}: {e}");
+                self.stats.failed_lookups += 1;
+                Vec::new()
+            }
+        }
+    }
+
+    fn fetch_root(&mut self, url: &TreeUrl) -> Option<Root> {
+        let record = self
+            .lookup(&url.domain)
+            .into_iter()
+            .find(|r| r.starts_with(ROOT_PREFIX))?;
+        match parse_root(&record, &url.key) {
+            Ok(root) => Some(root),
+            Err(e) => {
+                debug!(target: "dns_discovery", "Skipping root of {}: {e:?}", url.domain);
+                self.note_error(e);
+                None
+            }
+        }
+    }
+
+    fn fetch_entry(&mut self, name: &str, hash: &str) -> Option<Entry> {
+        let records = self.lookup(name);
+        if records.is_empty() {
+            return None;
+        }
+        let record = match records
+            .into_iter()
+            .find(|r| subdomain_hash(r).eq_ignore_ascii_case(hash))
+        {
+            Some(record) => record,
+            None => {
+                debug!(target: "dns_discovery", "Skipping {name}: content doesn't match hash");
+                self.stats.hash_mismatches += 1;
+                return None;
+            }
+        };
+        match parse_entry(&record) {
+            Ok(entry) => {
+                self.cache.put(hash.to_ascii_uppercase(), entry.clone());
+                Some(entry)
+            }
+            Err(e) => {
+                debug!(target: "dns_discovery", "Skipping {name}: {e:?}");
+                self.note_error(e);
+                None
+            }
+        }
+    }
+
+    fn note_error(&mut self, e: RecordError) {
+        match e {
+            RecordError::Malformed(_) => self.stats.malformed_records += 1,
+            RecordError::BadSignature => self.stats.bad_signatures += 1,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Crawled {
+    found: Vec<NodeEntry>,
+    stats: Stats,
+}
+
+/// Runs the rounds of a `DnsDiscovery` on a thread of its own, as the DNS lookups block.
+/// The thread ends when the crawler is dropped.
+pub struct DnsCrawler {
+    rounds: Mutex<mpsc::SyncSender<Instant>>,
+    crawled: Arc<Mutex<Crawled>>,
+}
+
+impl DnsCrawler {
+    /// Start the crawler thread named `name`.
+    pub fn spawn(mut discovery: DnsDiscovery, name: String) -> io::Result<Self> {
+        // a round is only requested while the thread waits for one.
+        let (rounds, requests) = mpsc::sync_channel::<Instant>(0);
+        let crawled = Arc::new(Mutex::new(Crawled::default()));
+        let shared = crawled.clone();
+        thread::Builder::new().name(name).spawn(move || {
+            for now in requests {
+                let found = discovery.round(now);
+                let mut crawled = shared.lock();
+                crawled.found.extend(found);
+                crawled.stats = discovery.stats().clone();
+            }
+        })?;
+        Ok(DnsCrawler {
+            rounds: Mutex::new(rounds),
+            crawled,
+        })
+    }
+
+    /// Start a round, unless the previous one is still running.
+    pub fn request_round(&self, now: Instant) {
+        if let Err(mpsc::TrySendError::Disconnected(_)) = self.rounds.lock().try_send(now) {
+            warn!(target: "dns_discovery", "DNS discovery thread is gone");
+        }
+    }
+
+    /// Take the nodes discovered since the last call.
+    pub fn take_found(&self) -> Vec<NodeEntry> {
+        std::mem::take(&mut self.crawled.lock().found)
+    }
+
+    /// Counters of the crawl as of the end of the last round.
+    pub fn stats(&self) -> Stats {
+        self.crawled.lock().stats.clone()
+    }
+}
+
+/// Subdomain of a record: base32 of the first 16 bytes of the keccak hash of its content.
+fn subdomain_hash(record: &str) -> String {
+    base32_encode(&keccak(record.as_bytes())[..16])
+}
+
+fn compress(public: &Public) -> [u8; 33] {
+    let public = public.as_bytes();
+    let mut compressed = [0u8; 33];
+    compressed[0] = 2 + (public[63] & 1);
+    compressed[1..].copy_from_slice(&public[..32]);
+    compressed
+}
+
+// recover the signer of `message` if it has the compressed public key `key`.
+fn recover_signer(r: &[u8], s: &[u8], v: u8, message: &H256, key: &[u8]) -> Option<Public> {
+    let signature = Signature::from_rsv(&H256::from_slice(r), &H256::from_slice(s), v);
+    recover(&signature, message)
+        .ok()
+        .filter(|public| compress(public)[..] == *key)
+}
+
+fn parse_root(record: &str, key: &[u8; 33]) -> Result<Root, RecordError> {
+    let sig_at = record
+        .find(" sig=")
+        .ok_or(RecordError::Malformed("root without signature"))?;
+    let (mut enr_root, mut link_root, mut seq, mut sig) = (None, None, None, None);
+    for field in record[ROOT_PREFIX.len()..].split_whitespace() {
+        match field.split_once('=') {
+            Some(("e", v)) => enr_root = Some(v),
+            Some(("l", v)) => link_root = Some(v),
+            Some(("seq", v)) => seq = v.parse::<u32>().ok(),
+            Some(("sig", v)) => sig = base64_decode(v),
+            _ => return Err(RecordError::Malformed("unknown root field")),
+        }
+    }
+    let (enr_root, link_root, seq, sig) = match (enr_root, link_root, seq, sig) {
+        (Some(e), Some(l), Some(seq), Some(sig)) => (e, l, seq, sig),
+        _ => return Err(RecordError::Malformed("incomplete root")),
+    };
+    if !is_hash(enr_root) || !is_hash(link_root) {
+        return Err(RecordError::Malformed("invalid root hash"));
+    }
+    if sig.len() != 65 {
+        return Err(RecordError::Malformed("invalid root signature length"));
+    }
+
+    let v = if sig[64] >= 27 { sig[64] - 27 } else { sig[64] };
+    let message = keccak(&record.as_bytes()[..sig_at]);
+    if recover_signer(&sig[..32], &sig[32..64], v, &message, key).is_none() {
+        return Err(RecordError::BadSignature);
+    }
+
+    Ok(Root {
+        enr_root: enr_root.to_ascii_uppercase(),
+        link_root: link_root.to_ascii_uppercase(),
+        seq,
+    })
+}
+
+fn parse_entry(record: &str) -> Result<Entry, RecordError> {
+    if let Some(children) = record.strip_prefix(BRANCH_PREFIX) {
+        let children = children
+            .split(',')
+            .filter(|c| !c.is_empty())
+            .map(|c| {
+                if is_hash(c) {
+                    Ok(c.to_ascii_uppercase())
+                } else {
+                    Err(RecordError::Malformed("invalid branch hash"))
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Entry::Branch(children))
+    } else if record.starts_with(LINK_PREFIX) {
+        record
+            .parse()
+            .map(Entry::Link)
+            .map_err(|_| RecordError::Malformed("invalid link"))
+    } else if let Some(enr) = record.strip_prefix(ENR_PREFIX) {
+        let enr =
+            base64_decode(enr).ok_or(RecordError::Malformed("invalid node record encoding"))?;
+        parse_enr(&enr).map(Entry::Node)
+    } else {
+        Err(RecordError::Malformed("unknown record type"))
+    }
+}
+
+/// Parse an RLP encoded node record (EIP-778) and verify its signature. Returns `None` for
+/// valid records without an IP address and TCP port.
+fn parse_enr(enr: &[u8]) -> Result<Option<NodeEntry>, RecordError> {
+    const MALFORMED: RecordError = RecordError::Malformed("invalid node record");
+
+    if enr.len() > MAX_ENR_SIZE {
+        return Err(RecordError::Malformed("node record too large"));
+    }
+    let rlp = Rlp::new(enr);
+    let count = rlp.item_count().map_err(|_| MALFORMED)?;
+    if count < 2 || count % 2 != 0 {
+        return Err(MALFORMED);
+    }
+
+    let (mut scheme, mut key, mut ip, mut tcp, mut udp) = (None, None, None, None, None);
+    let mut last_key: Option<Vec<u8>> = None;
+    for pair in (2..count).step_by(2) {
+        let k: Vec<u8> = rlp.val_at(pair).map_err(|_| MALFORMED)?;
+        if last_key.as_ref().is_some_and(|last| *last >= k) {
+            return Err(RecordError::Malformed("node record keys not sorted"));
+        }
+        let value = rlp.at(pair + 1).map_err(|_| MALFORMED)?;
+        match &k[..] {
+            b"id" => scheme = Some(value.as_val::<Vec<u8>>().map_err(|_| MALFORMED)?),
+            b"secp256k1" => key = Some(value.data().map_err(|_| MALFORMED)?.to_vec()),
+            b"ip" => {
+                let octets = value.data().map_err(|_| MALFORMED)?;
+                if octets.len() != 4 {
+                    return Err(MALFORMED);
+                }
+                ip = Some(IpAddr::V4(Ipv4Addr::new(
+                    octets[0], octets[1], octets[2], octets[3],
+                )));
+            }
+            b"ip6" if ip.is_none() => {
+                let octets = value.data().map_err(|_| MALFORMED)?;
+                if octets.len() != 16 {
+                    return Err(MALFORMED);
+                }
+                let mut addr = [0u8; 16];
+                addr.copy_from_slice(octets);
+                ip = Some(IpAddr::V6(Ipv6Addr::from(addr)));
+            }
+            b"tcp" => tcp = Some(value.as_val::<u16>().map_err(|_| MALFORMED)?),
+            b"udp" => udp = Some(value.as_val::<u16>().map_err(|_| MALFORMED)?),
+            _ => {}
+        }
+        last_key = Some(k);
+    }
+
+    if scheme.as_deref() != Some(&b"v4"[..]) {
+        return Err(RecordError::Malformed("unsupported identity scheme"));
+    }
+    let key = key.ok_or(RecordError::Malformed("node record without public key"))?;
+    let signature = rlp.at(0).and_then(|s| s.data()).map_err(|_| MALFORMED)?;
+    if key.len() != 33 || signature.len() != 64 {
+        return Err(MALFORMED);
+    }
+
+    let mut content = RlpStream::new_list(count - 1);
+    for i in 1..count {
+        content.append_raw(rlp.at(i).map_err(|_| MALFORMED)?.as_raw(), 1);
+    }
+    let message = keccak(content.out());
+    let id: NodeId = (0..2)
+        .find_map(|v| recover_signer(&signature[..32], &signature[32..], v, &message, &key))
+        .ok_or(RecordError::BadSignature)?;
+
+    Ok(match (ip, tcp) {
+        (Some(ip), Some(tcp)) => Some(NodeEntry {
+            id,
+            endpoint: NodeEndpoint {
+                address: SocketAddr::new(ip, tcp),
+                udp_port: udp.unwrap_or(tcp),
+            },
+        }),
+        _ => None,
+    })
+}
+
+fn is_hash(s: &str) -> bool {
+    !s.is_empty() && s.len() <= 26 && s.bytes().all(|b| base32_value(b).is_some())
+}
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+fn base32_value(b: u8) -> Option<u8> {
+    match b {
+        b'A'..=b'Z' => Some(b - b'A'),
+        b'a'..=b'z' => Some(b - b'a'),
+        b'2'..=b'7' => Some(b - b'2' + 26),
+        _ => None,
+    }
+}
+
+/// Unpadded base32 (RFC 4648).
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u16, 0u8);
+    for byte in data {
+        buffer = (buffer << 8) | u16::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[usize::from((buffer >> bits) & 0x1f)] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[usize::from((buffer << (5 - bits)) & 0x1f)] as char);
+    }
+    out
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u16, 0u8);
+    for b in s.trim_end_matches('=').bytes() {
+        buffer = (buffer << 5) | u16::from(base32_value(b)?);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Base64, URL-safe alphabet and padding are optional.
+fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut buffer, mut bits) = (0u32, 0u8);
+    for b in s.trim_end_matches('=').bytes() {
+        let value = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'-' | b'+' => 62,
+            b'_' | b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Resolver sending TXT queries over UDP to the first name server of the system configuration.
+pub struct SystemResolver {
+    server: SocketAddr,
+}
+
+impl SystemResolver {
+    /// Use the first name server from `/etc/resolv.conf`, falling back to a public resolver.
+    pub fn new() -> Self {
+        let server = fs::read_to_string("/etc/resolv.conf")
+            .ok()
+            .and_then(|conf| {
+                conf.lines()
+                    .filter_map(|line| line.trim().strip_prefix("nameserver"))
+                    .filter_map(|addr| addr.trim().split('%').next()?.parse::<IpAddr>().ok())
+                    .next()
+            })
+            .unwrap_or(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)));
+        SystemResolver::with_server(SocketAddr::new(server, DNS_PORT))
+    }
+
+    /// Send all queries to `server`.
+    pub fn with_server(server: SocketAddr) -> Self {
+        SystemResolver { server }
+    }
+}
+
+impl Default for SystemResolver {
+    fn default() -> Self {
+        SystemResolver::new()
+    }
+}
+
+impl Resolver for SystemResolver {
+    fn txt(&self, name: &str) -> Result<Vec<String>, String> {
+        let local: SocketAddr = if self.server.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(local).map_err(|e| e.to_string())?;
+        socket
+            .set_read_timeout(Some(DNS_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        socket.connect(self.server).map_err(|e| e.to_string())?;
+
+        let id = ::rand::random::<u16>();
+        socket
+            .send(&txt_query(id, name)?)
+            .map_err(|e| e.to_string())?;
+
+        let mut buf = [0u8; MAX_DNS_MESSAGE_SIZE];
+        loop {
+            let len = socket.recv(&mut buf).map_err(|e| e.to_string())?;
+            // ignore stray responses to earlier queries.
+            if len >= 2 && buf[..2] == id.to_be_bytes() {
+                return parse_txt_response(&buf[..len]);
+            }
+        }
+    }
+}
+
+fn txt_query(id: u16, name: &str) -> Result<Vec<u8>, String> {
+    let mut query = Vec::with_capacity(name.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    // recursion desired, one question.
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 || !label.is_ascii() {
+            return Err(format!("Invalid domain name {name}"));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&DNS_TYPE_TXT.to_be_bytes());
+    query.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+fn parse_txt_response(buf: &[u8]) -> Result<Vec<String>, String> {
+    const TRUNCATED: &str = "Truncated DNS response";
+
+    fn u16_at(buf: &[u8], pos: usize) -> Result<u16, String> {
+        buf.get(pos..pos + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or_else(|| TRUNCATED.to_string())
+    }
+
+    fn skip_name(buf: &[u8], mut pos: usize) -> Result<usize, String> {
+        loop {
+            let len = *buf.get(pos).ok_or_else(|| TRUNCATED.to_string())?;
+            if len & 0xc0 == 0xc0 {
+                return Ok(pos + 2);
+            }
+            if len == 0 {
+                return Ok(pos + 1);
+            }
+            pos += 1 + usize::from(len);
+        }
+    }
+
+    let flags = u16_at(buf, 2)?;
+    if flags & 0x8000 == 0 {
+        return Err("Not a DNS response".into());
+    }
+    if flags & 0x0200 != 0 {
+        return Err(TRUNCATED.into());
+    }
+    match flags & 0x000f {
+        0 => {}
+        3 => return Ok(Vec::new()),
+        rcode => return Err(format!("DNS error code {rcode}")),
+    }
+
+    let questions = u16_at(buf, 4)?;
+    let answers = u16_at(buf, 6)?;
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(buf, pos)? + 4;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        pos = skip_name(buf, pos)?;
+        let rtype = u16_at(buf, pos)?;
+        let rdlen = usize::from(u16_at(buf, pos + 8)?);
+        pos += 10;
+        let rdata = buf.get(pos..pos + rdlen).ok_or(TRUNCATED)?;
+        pos += rdlen;
+        if rtype != DNS_TYPE_TXT {
+            continue;
+        }
+
+        let mut record = Vec::with_capacity(rdlen);
+        let mut i = 0;
+        while i < rdata.len() {
+            let len = usize::from(rdata[i]);
+            record.extend_from_slice(rdata.get(i + 1..i + 1 + len).ok_or(TRUNCATED)?);
+            i += 1 + len;
+        }
+        records.push(String::from_utf8(record).map_err(|_| "Invalid TXT record".to_string())?);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::publickey::{sign, Generator, KeyPair, Random};
+    use std::collections::HashMap;
+
+    const DOMAIN: &str = "nodes.example.org";
+    const UNKNOWN_HASH: &str = "C7HRFPF3BLGF3YR4DY5KX3SMBE";
+
+    #[derive(Default)]
+    struct MockResolver {
+        records: HashMap<String, String>,
+    }
+
+    impl MockResolver {
+        // add a record under the subdomain derived from its content.
+        fn add(&mut self, domain: &str, record: String) -> String {
+            let hash = subdomain_hash(&record);
+            self.records.insert(format!("{hash}.{domain}"), record);
+            hash
+        }
+
+        fn add_root(
+            &mut self,
+            domain: &str,
+            key: &KeyPair,
+            enr_root: &str,
+            link_root: &str,
+            seq: u32,
+        ) {
+            let content = format!("{ROOT_PREFIX} e={enr_root} l={link_root} seq={seq}");
+            let sig = sign(key.secret(), &keccak(content.as_bytes())).unwrap();
+            self.records.insert(
+                domain.to_string(),
+                format!("{content} sig={}", base64_encode(&sig[..])),
+            );
+        }
+    }
+
+    impl Resolver for MockResolver {
+        fn txt(&self, name: &str) -> Result<Vec<String>, String> {
+            Ok(self.records.get(name).cloned().into_iter().collect())
+        }
+    }
+
+    fn base64_encode(data: &[u8]) -> String {
+        const ALPHABET: &[u8; 64] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+        let mut out = String::new();
+        let (mut buffer, mut bits) = (0u32, 0u8);
+        for byte in data {
+            buffer = (buffer << 8) | u32::from(*byte);
+            bits += 8;
+            while bits >= 6 {
+                bits -= 6;
+                out.push(ALPHABET[((buffer >> bits) & 0x3f) as usize] as char);
+            }
+        }
+        if bits > 0 {
+            out.push(ALPHABET[((buffer << (6 - bits)) & 0x3f) as usize] as char);
+        }
+        out
+    }
+
+    fn tree_url(key: &KeyPair, domain: &str) -> String {
+        format!(
+            "{LINK_PREFIX}{}@{domain}",
+            base32_encode(&compress(key.public()))
+        )
+    }
+
+    // node record of `key` at 10.0.0.`n`, signed by `signer`.
+    fn enr(key: &KeyPair, signer: &KeyPair, n: u8) -> String {
+        let compressed = compress(key.public());
+        let append_content = |s: &mut RlpStream| {
+            s.append(&1u64);
+            s.append(&"id").append(&"v4");
+            s.append(&"ip").append(&vec![10u8, 0, 0, n]);
+            s.append(&"secp256k1").append(&&compressed[..]);
+            s.append(&"tcp").append(&30303u16);
+            s.append(&"udp").append(&30301u16);
+        };
+        let mut content = RlpStream::new_list(11);
+        append_content(&mut content);
+        let sig = sign(signer.secret(), &keccak(content.out())).unwrap();
+
+        let mut record = RlpStream::new_list(12);
+        record.append(&&sig[..64]);
+        append_content(&mut record);
+        format!("{ENR_PREFIX}{}", base64_encode(&record.out()))
+    }
+
+    fn crawl(discovery: &mut DnsDiscovery) -> HashSet<NodeId> {
+        discovery.max_lookups = usize::MAX;
+        discovery
+            .round(Instant::now())
+            .into_iter()
+            .map(|n| n.id)
+            .collect()
+    }
+
+    #[test]
+    fn tree_url_parsing() {
+        let key = Random.generate();
+        let url: TreeUrl = tree_url(&key, "Nodes.Example.org.").parse().unwrap();
+        assert_eq!(url.key, compress(key.public()));
+        assert_eq!(url.domain, "nodes.example.org");
+
+        assert!("enrtree://@nodes.example.org".parse::<TreeUrl>().is_err());
+        assert!("enr://AAAA@nodes.example.org".parse::<TreeUrl>().is_err());
+        assert!(
+            format!("{LINK_PREFIX}{}", base32_encode(&compress(key.public())))
+                .parse::<TreeUrl>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn base32_round_trip() {
+        for len in 0..40 {
+            let data: Vec<u8> = (0..len).map(|i| (i * 37) as u8).collect();
+            assert_eq!(base32_decode(&base32_encode(&data)).unwrap(), data);
+        }
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base64_decode("Zm9vYmFy").unwrap(), b"foobar");
+        assert_eq!(base64_decode("-_8").unwrap(), vec![0xfb, 0xff]);
+    }
+
+    #[test]
+    fn crawls_tree_skipping_bad_records() {
+        let tree_key = Random.generate();
+        let nodes: Vec<KeyPair> = (0..5).map(|_| Random.generate()).collect();
+        let mut resolver = MockResolver::default();
+
+        let good1 = resolver.add(DOMAIN, enr(&nodes[0], &nodes[0], 1));
+        let good2 = resolver.add(DOMAIN, enr(&nodes[1], &nodes[1], 2));
+        // signed by another key than the one in the record.
+        let bad_signature = resolver.add(DOMAIN, enr(&nodes[2], &nodes[3], 3));
+        let malformed = resolver.add(DOMAIN, format!("{ENR_PREFIX}AAAA"));
+        // content doesn't match the hash it is published under.
+        resolver.records.insert(
+            format!("{UNKNOWN_HASH}.{DOMAIN}"),
+            enr(&nodes[4], &nodes[4], 5),
+        );
+
+        let good3 = resolver.add(DOMAIN, enr(&nodes[3], &nodes[3], 4));
+        let sub_branch = format!("{BRANCH_PREFIX}{good3}");
+        let sub_hash = subdomain_hash(&sub_branch);
+        // refers back to the sub branch, which is referenced by the enr root as well.
+        let cycle = resolver.add(DOMAIN, format!("{BRANCH_PREFIX}{sub_hash}"));
+        resolver.add(DOMAIN, sub_branch);
+
+        let enr_root = resolver.add(
+            DOMAIN,
+            format!(
+                "{BRANCH_PREFIX}{good1},{good2},{bad_signature},{malformed},{UNKNOWN_HASH},{sub_hash},{cycle}"
+            ),
+        );
+        let link_root = resolver.add(DOMAIN, BRANCH_PREFIX.to_string());
+        resolver.add_root(DOMAIN, &tree_key, &enr_root, &link_root, 1);
+
+        let mut discovery = DnsDiscovery::new(&[tree_url(&tree_key, DOMAIN)], Box::new(resolver));
+        let found = crawl(&mut discovery);
+
+        let expected: HashSet<NodeId> = [0, 1, 3].iter().map(|i| *nodes[*i].public()).collect();
+        assert_eq!(found, expected);
+
+        let stats = discovery.stats();
+        assert_eq!(stats.nodes, 3);
+        assert_eq!(stats.bad_signatures, 1);
+        assert_eq!(stats.malformed_records, 1);
+        assert_eq!(stats.hash_mismatches, 1);
+        assert_eq!(stats.cycles, 1);
+
+        // already discovered nodes are not reported again.
+        discovery.refresh_interval = Duration::from_secs(0);
+        assert!(crawl(&mut discovery).is_empty());
+    }
+
+    #[test]
+    fn rejects_root_with_bad_signature() {
+        let tree_key = Random.generate();
+        let node = Random.generate();
+        let mut resolver = MockResolver::default();
+        let enr_root = resolver.add(DOMAIN, enr(&node, &node, 1));
+        let link_root = resolver.add(DOMAIN, BRANCH_PREFIX.to_string());
+        // signed by another key than the one in the URL.
+        resolver.add_root(DOMAIN, &Random.generate(), &enr_root, &link_root, 1);
+
+        let mut discovery = DnsDiscovery::new(&[tree_url(&tree_key, DOMAIN)], Box::new(resolver));
+        assert!(crawl(&mut discovery).is_empty());
+        assert_eq!(discovery.stats().bad_signatures, 1);
+        assert_eq!(discovery.stats().lookups, 1);
+    }
+
+    #[test]
+    fn follows_links_and_rate_limits_lookups() {
+        let (key_a, key_b) = (Random.generate(), Random.generate());
+        let (node_a, node_b) = (Random.generate(), Random.generate());
+        let domain_b = "other.example.org";
+        let mut resolver = MockResolver::default();
+
+        let enr_b = resolver.add(domain_b, enr(&node_b, &node_b, 2));
+        let empty_b = resolver.add(domain_b, BRANCH_PREFIX.to_string());
+        resolver.add_root(domain_b, &key_b, &enr_b, &empty_b, 3);
+
+        let enr_a = resolver.add(DOMAIN, enr(&node_a, &node_a, 1));
+        let link = resolver.add(DOMAIN, tree_url(&key_b, domain_b));
+        resolver.add_root(DOMAIN, &key_a, &enr_a, &link, 7);
+
+        let mut discovery = DnsDiscovery::new(&[tree_url(&key_a, DOMAIN)], Box::new(resolver));
+        let now = Instant::now();
+        let mut found = HashSet::new();
+        let mut rounds = 0;
+        while found.len() < 2 {
+            let lookups = discovery.stats().lookups;
+            found.extend(discovery.round(now).into_iter().map(|n| n.id));
+            assert!(discovery.stats().lookups - lookups <= MAX_LOOKUPS_PER_ROUND as u64);
+            rounds += 1;
+            assert!(rounds < 10, "crawl did not finish");
+        }
+        assert!(found.contains(node_a.public()) && found.contains(node_b.public()));
+
+        // synced trees aren't looked up again until the refresh interval passed.
+        let lookups = discovery.stats().lookups;
+        assert!(discovery.round(now).is_empty());
+        assert_eq!(discovery.stats().lookups, lookups);
+        assert!(discovery.round(now + TREE_REFRESH_INTERVAL).is_empty());
+        assert_eq!(discovery.stats().lookups, lookups + 2);
+    }
+
+    #[test]
+    fn crawler_runs_rounds_on_its_own_thread() {
+        let tree_key = Random.generate();
+        let node = Random.generate();
+        let mut resolver = MockResolver::default();
+        let enr_root = resolver.add(DOMAIN, enr(&node, &node, 1));
+        let link_root = resolver.add(DOMAIN, BRANCH_PREFIX.to_string());
+        resolver.add_root(DOMAIN, &tree_key, &enr_root, &link_root, 1);
+
+        let discovery = DnsDiscovery::new(&[tree_url(&tree_key, DOMAIN)], Box::new(resolver));
+        let crawler = DnsCrawler::spawn(discovery, "dns-discovery-test".into()).unwrap();
+        let mut found = Vec::new();
+        for _ in 0..100 {
+            crawler.request_round(Instant::now());
+            found.extend(crawler.take_found().into_iter().map(|n| n.id));
+            if !found.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(found, vec![*node.public()]);
+        assert_eq!(crawler.stats().nodes, 1);
+    }
+
+    #[test]
+    fn parses_txt_response() {
+        let mut response = txt_query(0x1234, "example.org").unwrap();
+        // response, recursion available, one answer.
+        response[2..4].copy_from_slice(&[0x81, 0x80]);
+        response[6..8].copy_from_slice(&[0, 1]);
+        // answer with a compressed name and a record split into two strings.
+        response.extend_from_slice(&[0xc0, 12, 0, 16, 0, 1, 0, 0, 0, 60, 0, 12]);
+        response.extend_from_slice(b"\x05hello\x05world");
+
+        assert_eq!(parse_txt_response(&response).unwrap(), vec!["helloworld"]);
+
+        response[3] = 0x83;
+        assert_eq!(parse_txt_response(&response).unwrap(), Vec::<String>::new());
+        response[2] = 0x83;
+        assert!(parse_txt_response(&response).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+use io::TimerToken;
+use network::{Error, ErrorKind, ProtocolId, AUTO_TIMER_TOKENS};
+use std::collections::HashSet;
+
+/// Timer tokens registered by the protocol handlers. A handler token is private to its
+/// protocol, the host maps it to a host level token and routes the timer back to the protocol.
+pub struct HandlerTimers {
+    registered: HashSet<(ProtocolId, TimerToken)>,
+    next_auto: TimerToken,
+}
+
+impl Default for HandlerTimers {
+    fn default() -> Self {
+        HandlerTimers {
+            registered: HashSet::new(),
+            next_auto: AUTO_TIMER_TOKENS,
+        }
+    }
+}
+
+impl HandlerTimers {
+    /// Claim `token` for a timer of `protocol`. Fails if the protocol registered the token already.
+    pub fn claim(&mut self, protocol: ProtocolId, token: TimerToken) -> Result<(), Error> {
+        if !self.registered.insert((protocol, token)) {
+            bail!(ErrorKind::TimerTokenInUse(token));
+        }
+        Ok(())
+    }
+
+    /// Allocate a token for a timer of `protocol`. Allocated tokens are distinct over all
+    /// protocols and skip the tokens the protocol claimed explicitly.
+    pub fn allocate(&mut self, protocol: ProtocolId) -> TimerToken {
+        loop {
+            let token = self.next_auto;
+            self.next_auto += 1;
+            if self.registered.insert((protocol, token)) {
+                return token;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_duplicate_tokens_of_a_protocol() {
+        let mut timers = HandlerTimers::default();
+        let (sync, other) = (ProtocolId::from(1), ProtocolId::from(2));
+
+        timers.claim(sync, 0).unwrap();
+        timers.claim(other, 0).unwrap();
+        assert_matches!(
+            *timers.claim(sync, 0).unwrap_err().kind(),
+            ErrorKind::TimerTokenInUse(0)
+        );
+    }
+
+    #[test]
+    fn allocates_distinct_tokens() {
+        let mut timers = HandlerTimers::default();
+        let (sync, other) = (ProtocolId::from(1), ProtocolId::from(2));
+        timers.claim(sync, AUTO_TIMER_TOKENS + 1).unwrap();
+
+        let tokens = [
+            timers.allocate(sync),
+            timers.allocate(sync),
+            timers.allocate(other),
+        ];
+        assert_eq!(
+            tokens,
+            [
+                AUTO_TIMER_TOKENS,
+                AUTO_TIMER_TOKENS + 2,
+                AUTO_TIMER_TOKENS + 3
+            ]
+        );
+        assert!(timers.claim(other, AUTO_TIMER_TOKENS + 3).is_err());
+    }
+}
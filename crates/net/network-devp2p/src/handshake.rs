@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
 
+use buffer_pool::BufferPool;
 use connection::Connection;
 use crypto::publickey::{ecdh, ecies, recover, sign, Generator, KeyPair, Public, Random, Secret};
 use ethereum_types::{H256, H520};
@@ -83,6 +84,7 @@ impl Handshake {
         id: Option<&NodeId>,
         socket: TcpStream,
         nonce: &H256,
+        pool: BufferPool,
     ) -> Result<Handshake, Error> {
         Ok(Handshake {
             id: if let Some(id) = id {
@@ -90,7 +92,7 @@ impl Handshake {
             } else {
                 NodeId::default()
             },
-            connection: Connection::new(token, socket),
+            connection: Connection::new(token, socket, pool),
             originated: false,
             state: HandshakeState::New,
             ecdhe: Random.generate(),
@@ -310,19 +312,7 @@ impl Handshake {
         Message: Send + Clone + Sync + 'static,
     {
         trace!(target: "network", "Sending handshake auth to {:?}", self.connection.remote_addr_str());
-        let mut rlp = RlpStream::new_list(4);
-        let shared = *ecdh::agree(secret, &self.id)?;
-        rlp.append(&sign(self.ecdhe.secret(), &(shared ^ self.nonce))?.to_vec());
-        rlp.append(public);
-        rlp.append(&self.nonce);
-        rlp.append(&PROTOCOL_VERSION);
-        let mut encoded = rlp.out();
-        encoded.resize(encoded.len() + rand::thread_rng().gen_range(100, 301), 0);
-        let len = (encoded.len() + ECIES_OVERHEAD) as u16;
-        let prefix = len.to_be_bytes();
-        let message = ecies::encrypt(&self.id, &prefix, &encoded)?;
-        self.auth_cipher.extend_from_slice(&prefix);
-        self.auth_cipher.extend_from_slice(&message);
+        self.auth_cipher = auth_packet(secret, public, &self.id, &self.ecdhe, &self.nonce)?;
         self.connection.send(io, self.auth_cipher.clone());
         self.connection.expect(V4_ACK_PACKET_SIZE);
         self.state = HandshakeState::ReadingAck;
@@ -378,6 +368,29 @@ impl Handshake {
     }
 }
 
+/// EIP-8 auth packet of the node with `secret` and `public` to the node `remote`.
+pub(crate) fn auth_packet(
+    secret: &Secret,
+    public: &Public,
+    remote: &NodeId,
+    ecdhe: &KeyPair,
+    nonce: &H256,
+) -> Result<Bytes, Error> {
+    let mut rlp = RlpStream::new_list(4);
+    let shared = *ecdh::agree(secret, remote)?;
+    rlp.append(&sign(ecdhe.secret(), &(shared ^ *nonce))?.to_vec());
+    rlp.append(public);
+    rlp.append(nonce);
+    rlp.append(&PROTOCOL_VERSION);
+    let mut encoded = rlp.out();
+    encoded.resize(encoded.len() + rand::thread_rng().gen_range(100, 301), 0);
+    let len = (encoded.len() + ECIES_OVERHEAD) as u16;
+    let prefix = len.to_be_bytes();
+    let mut packet = prefix.to_vec();
+    packet.extend_from_slice(&ecies::encrypt(remote, &prefix, &encoded)?);
+    Ok(packet)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -413,7 +426,7 @@ mod test {
         let addr = "127.0.0.1:50556".parse().unwrap();
         let socket = TcpStream::connect(&addr).unwrap();
         let nonce = H256::default();
-        Handshake::new(0, to, socket, &nonce).unwrap()
+        Handshake::new(0, to, socket, &nonce, BufferPool::default()).unwrap()
     }
 
     fn test_io() -> IoContext<i32> {
@@ -14,7 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
 
-use crypto::publickey::{Generator, KeyPair, Random, Secret};
+use crypto::publickey::{Generator, KeyPair, Public, Random, Secret};
 use ethereum_types::H256;
 use hash::keccak;
 use mio::{deprecated::EventLoop, tcp::*, udp::*, *};
@@ -32,21 +32,32 @@ use std::{
         atomic::{AtomicBool, Ordering as AtomicOrdering},
         Arc,
     },
-    time::Duration,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use ban_list::{BanEntry, BanList};
+use buffer_pool::{BufferPool, PooledBuffer};
+use capture::{CaptureHandle, PacketCaptures};
 use discovery::{Discovery, NodeEntry, TableUpdates, MAX_DATAGRAM_SIZE};
+use dns_discovery::{DnsCrawler, DnsDiscovery, Stats as DnsDiscoveryStats, SystemResolver};
+use handler_timers::HandlerTimers;
 use io::*;
 use ip_utils::{map_external_address, select_public_address};
 use network::{
     client_version::ClientVersion, ConnectionDirection, ConnectionFilter, DisconnectReason, Error,
-    ErrorKind, NetworkConfiguration, NetworkContext as NetworkContextTrait, NetworkIoMessage,
-    NetworkProtocolHandler, NonReservedPeerMode, PacketId, PeerId, ProtocolId, SessionInfo,
+    ErrorKind, LocalNodeRecord, NetworkConfiguration, NetworkContext as NetworkContextTrait,
+    NetworkIoMessage, NetworkProtocolHandler, NodeRecord, NonReservedPeerMode, PacketId, PeerId,
+    ProtocolId, SessionInfo, MAX_PAYLOAD_SIZE,
 };
 use node_table::*;
 use parity_path::restrict_permissions_owner;
 use parking_lot::{Mutex, RwLock};
+use peer_diversity::{client_name, network_prefix, DiversityPolicy, PeerDistribution};
+use reachability::{Reachability, ReachabilityMonitor, ReachabilityProbe};
+use seen_capabilities::{SeenCapabilities, SeenCapability};
 use session::{Session, SessionData};
+use sockets::HostSockets;
 use PROTOCOL_VERSION;
 
 type Slab<T> = ::slab::Slab<T, usize>;
@@ -56,7 +67,9 @@ const MAX_HANDSHAKES: usize = 1024;
 
 const DEFAULT_PORT: u16 = 30303;
 
-// StreamToken/TimerToken
+// StreamToken/TimerToken of the IO event loop: sessions take `FIRST_SESSION..=LAST_SESSION`, the
+// host's own streams and timers `SYS_TIMER..USER_TIMER` and the timers of the protocol handlers
+// `USER_TIMER` and up. Handler tokens are private to their protocol and mapped to the latter.
 const TCP_ACCEPT: StreamToken = SYS_TIMER + 1;
 const IDLE: TimerToken = SYS_TIMER + 2;
 const DISCOVERY: StreamToken = SYS_TIMER + 3;
@@ -64,6 +77,9 @@ const DISCOVERY_REFRESH: TimerToken = SYS_TIMER + 4;
 const FAST_DISCOVERY_REFRESH: TimerToken = SYS_TIMER + 5;
 const DISCOVERY_ROUND: TimerToken = SYS_TIMER + 6;
 const NODE_TABLE: TimerToken = SYS_TIMER + 7;
+const DNS_DISCOVERY: TimerToken = SYS_TIMER + 8;
+const REACHABILITY_PROBE: TimerToken = SYS_TIMER + 9;
+const BAN_LIST: TimerToken = SYS_TIMER + 10;
 const FIRST_SESSION: StreamToken = 0;
 const LAST_SESSION: StreamToken = FIRST_SESSION + MAX_SESSIONS - 1;
 const USER_TIMER: TimerToken = LAST_SESSION + 256;
@@ -80,6 +96,12 @@ const FAST_DISCOVERY_REFRESH_TIMEOUT: Duration = Duration::from_secs(10);
 const DISCOVERY_ROUND_TIMEOUT: Duration = Duration::from_millis(300);
 // for NODE_TABLE TimerToken
 const NODE_TABLE_TIMEOUT: Duration = Duration::from_secs(300);
+// for DNS_DISCOVERY TimerToken
+const DNS_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(1);
+// for the connection and each read of a reachability probe
+const REACHABILITY_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+// for BAN_LIST TimerToken
+const BAN_LIST_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Debug, PartialEq, Eq)]
 /// Protocol info
@@ -108,6 +130,7 @@ pub struct NetworkContext<'s> {
     session: Option<SharedSession>,
     session_id: Option<StreamToken>,
     reserved_peers: &'s HashSet<NodeId>,
+    handler_timers: &'s Mutex<HandlerTimers>,
 }
 
 impl<'s> NetworkContext<'s> {
@@ -118,6 +141,7 @@ impl<'s> NetworkContext<'s> {
         session: Option<SharedSession>,
         sessions: Arc<RwLock<Slab<SharedSession>>>,
         reserved_peers: &'s HashSet<NodeId>,
+        handler_timers: &'s Mutex<HandlerTimers>,
     ) -> NetworkContext<'s> {
         let id = session.as_ref().map(|s| s.lock().token());
         NetworkContext {
@@ -127,6 +151,7 @@ impl<'s> NetworkContext<'s> {
             session,
             sessions,
             reserved_peers,
+            handler_timers,
         }
     }
 
@@ -136,6 +161,16 @@ impl<'s> NetworkContext<'s> {
             _ => self.sessions.read().get(peer).cloned(),
         }
     }
+
+    fn add_timer(&self, token: TimerToken, delay: Duration) {
+        self.io
+            .message(NetworkIoMessage::AddTimer {
+                token,
+                delay,
+                protocol: self.protocol,
+            })
+            .unwrap_or_else(|e| warn!("Error sending network IO message: {e:?}"));
+    }
 }
 
 impl<'s> NetworkContextTrait for NetworkContext<'s> {
@@ -156,7 +191,8 @@ impl<'s> NetworkContextTrait for NetworkContext<'s> {
                 .lock()
                 .send_packet(self.io, Some(protocol), packet_id, &data)?;
         } else {
-            trace!(target: "network", "Send: Peer no longer exist")
+            trace!(target: "network", "Send: Peer no longer exist");
+            bail!(ErrorKind::PeerGone);
         }
         Ok(())
     }
@@ -167,7 +203,7 @@ impl<'s> NetworkContextTrait for NetworkContext<'s> {
             "Respond called without network context"
         );
         self.session_id.map_or_else(
-            || Err(ErrorKind::Expired.into()),
+            || Err(ErrorKind::SessionExpired.into()),
             |id| self.send(id, packet_id, data),
         )
     }
@@ -178,27 +214,40 @@ impl<'s> NetworkContextTrait for NetworkContext<'s> {
             .unwrap_or_else(|e| warn!("Error sending network IO message: {e:?}"));
     }
 
+    fn ban_peer(&self, peer: PeerId, reason: &str) {
+        self.io
+            .message(NetworkIoMessage::BanPeer(peer, reason.to_owned()))
+            .unwrap_or_else(|e| warn!("Error sending network IO message: {e:?}"));
+    }
+
     fn disconnect_peer(&self, peer: PeerId) {
         self.io
             .message(NetworkIoMessage::Disconnect(peer))
             .unwrap_or_else(|e| warn!("Error sending network IO message: {e:?}"));
     }
 
+    fn avoid_peer(&self, peer: PeerId) {
+        self.io
+            .message(NetworkIoMessage::AvoidPeer(peer))
+            .unwrap_or_else(|e| warn!("Error sending network IO message: {e:?}"));
+    }
+
     fn is_expired(&self) -> bool {
         self.session.as_ref().is_some_and(|s| s.lock().expired())
     }
 
     fn register_timer(&self, token: TimerToken, delay: Duration) -> Result<(), Error> {
-        self.io
-            .message(NetworkIoMessage::AddTimer {
-                token,
-                delay,
-                protocol: self.protocol,
-            })
-            .unwrap_or_else(|e| warn!("Error sending network IO message: {e:?}"));
+        self.handler_timers.lock().claim(self.protocol, token)?;
+        self.add_timer(token, delay);
         Ok(())
     }
 
+    fn register_timer_auto(&self, delay: Duration) -> Result<TimerToken, Error> {
+        let token = self.handler_timers.lock().allocate(self.protocol);
+        self.add_timer(token, delay);
+        Ok(token)
+    }
+
     fn peer_client_version(&self, peer: PeerId) -> ClientVersion {
         self.resolve_session(peer)
             .map_or(ClientVersion::from("unknown").to_owned(), |s| {
@@ -243,6 +292,12 @@ pub struct HostInfo {
     pub local_endpoint: NodeEndpoint,
     /// Public address + discovery port
     pub public_endpoint: Option<NodeEndpoint>,
+    /// Node record (EIP-778) advertising the public endpoint
+    node_record: LocalNodeRecord,
+    /// Capabilities advertised by peers
+    pub(crate) seen_capabilities: Mutex<SeenCapabilities>,
+    /// Buffers of the sessions of this host
+    pub(crate) buffer_pool: BufferPool,
 }
 
 impl HostInfo {
@@ -259,6 +314,10 @@ impl HostInfo {
         self.keys.secret()
     }
 
+    pub(crate) fn max_payload_size(&self) -> usize {
+        min(self.config.max_payload_size, MAX_PAYLOAD_SIZE)
+    }
+
     pub(crate) fn id(&self) -> &NodeId {
         self.keys.public()
     }
@@ -272,6 +331,28 @@ struct ProtocolTimer {
     pub token: TimerToken, // Handler level token
 }
 
+/// Statistics of the network host.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NetworkStats {
+    /// Result of the last probe of the public address, if any.
+    pub reachability: Option<ReachabilityProbe>,
+    /// Number of probes which found the public address reachable.
+    pub reachable_probes: u64,
+    /// Number of probes which did not.
+    pub unreachable_probes: u64,
+}
+
+impl NetworkStats {
+    fn note_reachability(&mut self, probe: ReachabilityProbe) {
+        if probe.result == Reachability::Reachable {
+            self.reachable_probes += 1;
+        } else {
+            self.unreachable_probes += 1;
+        }
+        self.reachability = Some(probe);
+    }
+}
+
 /// Root IO handler. Manages protocol handlers, IO timers and network connections.
 ///
 /// NOTE: must keep the lock in order of: reserved_nodes (rwlock) -> session (mutex, from sessions)
@@ -279,15 +360,24 @@ pub struct Host {
     pub info: RwLock<HostInfo>,
     udp_socket: Mutex<Option<UdpSocket>>,
     tcp_listener: Mutex<TcpListener>,
+    sockets: HostSockets,
     sessions: Arc<RwLock<Slab<SharedSession>>>,
     discovery: Mutex<Option<Discovery<'static>>>,
+    dns_discovery: Option<DnsCrawler>,
     nodes: RwLock<NodeTable>,
+    banned_nodes: RwLock<BanList>,
+    /// Nodes of other networks which are not dialed until the given time
+    avoided_nodes: RwLock<HashMap<NodeId, Instant>>,
     handlers: RwLock<HashMap<ProtocolId, Arc<dyn NetworkProtocolHandler + Sync>>>,
     timers: RwLock<HashMap<TimerToken, ProtocolTimer>>,
     timer_counter: RwLock<usize>,
+    handler_timers: Mutex<HandlerTimers>,
     reserved_nodes: RwLock<HashSet<NodeId>>,
     stopping: AtomicBool,
     filter: Option<Arc<dyn ConnectionFilter>>,
+    reachability: Arc<ReachabilityMonitor>,
+    packet_captures: Arc<PacketCaptures>,
+    stats: Arc<Mutex<NetworkStats>>,
 }
 
 impl Host {
@@ -295,7 +385,16 @@ impl Host {
     pub fn new(
         mut config: NetworkConfiguration,
         filter: Option<Arc<dyn ConnectionFilter>>,
+        buffer_pool: BufferPool,
     ) -> Result<Host, Error> {
+        config.config_path = config
+            .config_path
+            .as_deref()
+            .map(|path| config.instance_path(path));
+        config.net_config_path = config
+            .net_config_path
+            .as_deref()
+            .map(|path| config.instance_path(path));
         let mut listen_address = match config.listen_address {
             None => SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), DEFAULT_PORT)),
             Some(addr) => addr,
@@ -319,9 +418,17 @@ impl Host {
                     |s| KeyPair::from_secret(s).expect("Error creating node secret key"),
                 )
         };
+        // the record is not persisted, taking the time keeps sequence numbers growing across restarts
+        let seq = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |since| since.as_secs());
+        let node_record = LocalNodeRecord::new(keys.secret().clone(), seq)?;
         let path = config.net_config_path.clone();
+        let network_id = config.network_id;
         // Setup the server socket
-        let tcp_listener = TcpListener::bind(&listen_address)?;
+        let sockets = HostSockets::new(&config);
+        sockets.check()?;
+        let tcp_listener = sockets.listen(&listen_address)?;
         listen_address = SocketAddr::new(listen_address.ip(), tcp_listener.local_addr()?.port());
         debug!(target: "network", "Listening at {listen_address:?}");
         let udp_port = config.udp_port.unwrap_or_else(|| listen_address.port());
@@ -331,8 +438,24 @@ impl Host {
         };
 
         let boot_nodes = config.boot_nodes.clone();
+        let dns_discovery =
+            DnsDiscovery::new(&config.dns_discovery_urls, Box::new(SystemResolver::new()));
+        let dns_discovery = if dns_discovery.is_empty() {
+            None
+        } else {
+            DnsCrawler::spawn(dns_discovery, config.instance_name("dns-discovery"))
+                .map_err(|e| warn!(target: "network", "Error starting DNS discovery: {e:?}"))
+                .ok()
+        };
         let reserved_nodes = config.reserved_nodes.clone();
         config.max_handshakes = min(config.max_handshakes, MAX_HANDSHAKES as u32);
+        let packet_captures = Arc::new(PacketCaptures::new(
+            config.max_packet_captures,
+            config
+                .net_config_path
+                .as_ref()
+                .map(|path| Path::new(path).join("captures")),
+        ));
 
         let mut host = Host {
             info: RwLock::new(HostInfo {
@@ -342,22 +465,33 @@ impl Host {
                 protocol_version: PROTOCOL_VERSION,
                 capabilities: Vec::new(),
                 public_endpoint: None,
+                node_record,
                 local_endpoint,
+                seen_capabilities: Mutex::new(SeenCapabilities::default()),
+                buffer_pool,
             }),
             discovery: Mutex::new(None),
+            dns_discovery,
             udp_socket: Mutex::new(None),
             tcp_listener: Mutex::new(tcp_listener),
+            sockets,
             sessions: Arc::new(RwLock::new(Slab::new_starting_at(
                 FIRST_SESSION,
                 MAX_SESSIONS,
             ))),
-            nodes: RwLock::new(NodeTable::new(path)),
+            nodes: RwLock::new(NodeTable::new(path.clone(), network_id)),
+            banned_nodes: RwLock::new(BanList::new(path, network_id)),
+            avoided_nodes: RwLock::new(HashMap::new()),
             handlers: RwLock::new(HashMap::new()),
             timers: RwLock::new(HashMap::new()),
             timer_counter: RwLock::new(USER_TIMER),
+            handler_timers: Mutex::new(HandlerTimers::default()),
             reserved_nodes: RwLock::new(HashSet::new()),
             stopping: AtomicBool::new(false),
             filter,
+            reachability: Arc::new(ReachabilityMonitor::default()),
+            packet_captures,
+            stats: Arc::new(Mutex::new(NetworkStats::default())),
         };
 
         for n in boot_nodes {
@@ -451,6 +585,99 @@ impl Host {
         Ok(())
     }
 
+    /// Ban a node for `duration`, disconnecting it if currently connected.
+    pub fn ban_node(
+        &self,
+        id: NodeId,
+        duration: Duration,
+        reason: &str,
+        io: &IoContext<NetworkIoMessage>,
+    ) {
+        debug!(target: "network", "Banning node {id} for {duration:?}: {reason}");
+        self.banned_nodes.write().ban(id, duration, reason);
+
+        let mut to_kill = Vec::new();
+        for e in self.sessions.read().iter() {
+            let mut s = e.lock();
+            if s.id() == Some(&id) {
+                s.disconnect(io, DisconnectReason::DisconnectRequested);
+                to_kill.push(s.token());
+            }
+        }
+        for p in to_kill {
+            trace!(target: "network", "Disconnecting banned node: {p}");
+            self.kill_connection(p, io, false);
+        }
+    }
+
+    /// Do not dial a node of a different network for the configured avoid duration.
+    /// Unlike bans this is not persisted and does not refuse incoming connections.
+    pub fn avoid_node(&self, id: NodeId) {
+        let duration = self.info.read().config.avoid_duration;
+        debug!(target: "network", "Avoiding node {id} of a different network for {duration:?}");
+        let now = Instant::now();
+        let mut avoided = self.avoided_nodes.write();
+        avoided.retain(|_, until| *until > now);
+        avoided.insert(id, now + duration);
+    }
+
+    /// Lift the ban on a node. Returns `false` if the node was not banned.
+    pub fn unban_node(&self, id: &NodeId) -> bool {
+        self.banned_nodes.write().unban(id)
+    }
+
+    /// Get all currently banned nodes.
+    pub fn banned_nodes(&self) -> Vec<BanEntry> {
+        self.banned_nodes.read().entries()
+    }
+
+    /// Get the capabilities advertised by peers, including those not implemented.
+    pub fn seen_capabilities(&self) -> Vec<SeenCapability> {
+        self.info.read().seen_capabilities.lock().entries()
+    }
+
+    /// Connected peers by IP prefix and client name.
+    pub fn peer_distribution(&self) -> PeerDistribution {
+        self.peer_distribution_except(None)
+    }
+
+    // Peers of the ready sessions other than `except`, skipping the sessions busy elsewhere.
+    fn peer_distribution_except(&self, except: Option<StreamToken>) -> PeerDistribution {
+        let mut distribution = PeerDistribution::default();
+        for s in self.sessions.read().iter() {
+            match s.try_lock() {
+                Some(ref s) if s.is_ready() && Some(s.token()) != except => {
+                    distribution.add(
+                        s.remote_addr().ok().and_then(|a| network_prefix(&a)),
+                        client_name(&s.info.client_version),
+                    );
+                }
+                _ => (),
+            }
+        }
+        distribution
+    }
+
+    /// Check if a connection with the given node is allowed by the ban list and connection filter.
+    fn connection_allowed(&self, id: &NodeId, direction: ConnectionDirection) -> bool {
+        if self.banned_nodes.read().is_banned(id) {
+            return false;
+        }
+        if matches!(direction, ConnectionDirection::Outbound)
+            && self
+                .avoided_nodes
+                .read()
+                .get(id)
+                .is_some_and(|until| *until > Instant::now())
+        {
+            return false;
+        }
+        let self_id = *self.info.read().id();
+        self.filter
+            .as_ref()
+            .is_none_or(|f| f.connection_allowed(&self_id, id, direction))
+    }
+
     pub fn external_url(&self) -> Option<String> {
         let info = self.info.read();
         info.public_endpoint
@@ -458,6 +685,85 @@ impl Host {
             .map(|e| format!("{}", Node::new(*info.id(), e.clone())))
     }
 
+    /// Node record (EIP-778) in its text form.
+    pub fn node_record(&self) -> String {
+        self.info.read().node_record.record().to_string()
+    }
+
+    /// Set an entry of the node record, the value being an RLP item. Nodes asking for the record
+    /// through the discovery protocol get the updated one.
+    pub fn set_node_record_entry(&self, key: &[u8], value: Vec<u8>) -> Result<(), Error> {
+        let record = {
+            let mut info = self.info.write();
+            if !info.node_record.set(key, value)? {
+                return Ok(());
+            }
+            info.node_record.record().clone()
+        };
+        self.update_discovery_record(record);
+        Ok(())
+    }
+
+    fn update_discovery_record(&self, record: NodeRecord) {
+        if let Some(ref mut discovery) = *self.discovery.lock() {
+            discovery.set_node_record(record);
+        }
+    }
+
+    /// Probe the reachability of the public address, blocking until the probe is done. `None` if
+    /// the public address is not known yet or another probe is running.
+    pub fn probe_reachability(&self) -> Option<ReachabilityProbe> {
+        let (address, secret, public) = self.reachability_target()?;
+        let probe = self
+            .reachability
+            .run(address, &secret, &public, REACHABILITY_PROBE_TIMEOUT)?;
+        self.stats.lock().note_reachability(probe.clone());
+        Some(probe)
+    }
+
+    /// Counters of the DNS discovery, `None` if it is not enabled.
+    pub fn dns_discovery_stats(&self) -> Option<DnsDiscoveryStats> {
+        self.dns_discovery.as_ref().map(DnsCrawler::stats)
+    }
+
+    /// Result of the last reachability probe.
+    pub fn reachability(&self) -> Option<ReachabilityProbe> {
+        self.stats.lock().reachability.clone()
+    }
+
+    /// Statistics of the host.
+    pub fn stats(&self) -> NetworkStats {
+        self.stats.lock().clone()
+    }
+
+    /// Start capturing the subprotocol packets of a connected peer to a new file `name` in the
+    /// capture directory.
+    pub fn start_capture(
+        &self,
+        peer: PeerId,
+        max_bytes: usize,
+        name: &str,
+    ) -> Result<CaptureHandle, Error> {
+        let session = self.sessions.read().get(peer).cloned();
+        let session = session.ok_or(ErrorKind::PeerNotFound)?;
+        let mut session = session.lock();
+        if !session.is_ready() || session.expired() {
+            bail!(ErrorKind::PeerNotFound);
+        }
+        let handle = self.packet_captures.start(peer, max_bytes, name)?;
+        session.set_capture(handle.capture());
+        Ok(handle)
+    }
+
+    fn reachability_target(&self) -> Option<(SocketAddr, Secret, Public)> {
+        let info = self.info.read();
+        let address = info.public_endpoint.as_ref()?.address;
+        if address.ip().is_unspecified() {
+            return None;
+        }
+        Some((address, info.secret().clone(), *info.id()))
+    }
+
     pub fn local_url(&self) -> String {
         let info = self.info.read();
         format!("{}", Node::new(*info.id(), info.local_endpoint.clone()))
@@ -475,6 +781,7 @@ impl Host {
             trace!(target: "network", "Disconnecting on shutdown: {p}");
             self.kill_connection(p, io, true);
         }
+        self.banned_nodes.write().save_if_changed();
         io.unregister_handler();
     }
 
@@ -499,9 +806,14 @@ impl Host {
         let local_endpoint = self.info.read().local_endpoint.clone();
         let public_address = self.info.read().config.public_address;
         let allow_ips = self.info.read().config.ip_filter.clone();
+        let source_ip = self.sockets.source_ip();
         let public_endpoint = match public_address {
             None => {
-                let public_address = select_public_address(local_endpoint.address.port());
+                // peers see the connections coming from the source address
+                let public_address = match source_ip {
+                    Some(ip) => SocketAddr::new(ip, local_endpoint.address.port()),
+                    None => select_public_address(local_endpoint.address.port()),
+                };
                 let public_endpoint = NodeEndpoint {
                     address: public_address,
                     udp_port: local_endpoint.udp_port,
@@ -524,7 +836,15 @@ impl Host {
             },
         };
 
-        self.info.write().public_endpoint = Some(public_endpoint.clone());
+        {
+            let mut info = self.info.write();
+            info.public_endpoint = Some(public_endpoint.clone());
+            let record = &mut info.node_record;
+            if let Err(e) = record.set_endpoint(&public_endpoint.address, public_endpoint.udp_port)
+            {
+                warn!(target: "network", "Error updating the node record: {e}");
+            }
+        }
 
         if let Some(url) = self.external_url() {
             io.message(NetworkIoMessage::NetworkStarted(url))
@@ -537,7 +857,9 @@ impl Host {
             if info.config.discovery_enabled
                 && info.config.non_reserved_mode == NonReservedPeerMode::Accept
             {
-                Some(Discovery::new(&info.keys, public_endpoint, allow_ips))
+                let mut discovery = Discovery::new(&info.keys, public_endpoint, allow_ips);
+                discovery.set_node_record(info.node_record.record().clone());
+                Some(discovery)
             } else {
                 None
             }
@@ -546,7 +868,7 @@ impl Host {
         if let Some(mut discovery) = discovery {
             let mut udp_addr = local_endpoint.address;
             udp_addr.set_port(local_endpoint.udp_port);
-            let socket = UdpSocket::bind(&udp_addr).expect("Error binding UDP socket");
+            let socket = self.sockets.bind_udp(&udp_addr)?;
             *self.udp_socket.lock() = Some(socket);
 
             discovery.add_node_list(self.nodes.read().entries());
@@ -556,7 +878,17 @@ impl Host {
             io.register_timer(DISCOVERY_REFRESH, DISCOVERY_REFRESH_TIMEOUT)?;
             io.register_timer(DISCOVERY_ROUND, DISCOVERY_ROUND_TIMEOUT)?;
         }
+        // DNS discovery works independently of the discovery protocol and feeds the same tables.
+        if self.dns_discovery.is_some()
+            && self.info.read().config.non_reserved_mode == NonReservedPeerMode::Accept
+        {
+            io.register_timer(DNS_DISCOVERY, DNS_DISCOVERY_TIMEOUT)?;
+        }
         io.register_timer(NODE_TABLE, NODE_TABLE_TIMEOUT)?;
+        io.register_timer(BAN_LIST, BAN_LIST_TIMEOUT)?;
+        if let Some(interval) = self.info.read().config.reachability_probe_interval {
+            io.register_timer(REACHABILITY_PROBE, interval)?;
+        }
         io.register_stream(TCP_ACCEPT)?;
         Ok(())
     }
@@ -623,7 +955,7 @@ impl Host {
     }
 
     fn connect_peers(&self, io: &IoContext<NetworkIoMessage>) {
-        let (min_peers, mut pin, max_handshakes, allow_ips, self_id) = {
+        let (min_peers, mut pin, max_handshakes, allow_ips, self_id, policy) = {
             let info = self.info.read();
             if info.capabilities.is_empty() {
                 return;
@@ -636,6 +968,11 @@ impl Host {
                 config.max_handshakes as usize,
                 config.ip_filter.clone(),
                 *info.id(),
+                DiversityPolicy::new(
+                    config.max_peer_share,
+                    config.min_peers as usize,
+                    max(config.max_peers, config.min_peers) as usize,
+                ),
             )
         };
 
@@ -667,6 +1004,33 @@ impl Host {
             Vec::new()
         });
 
+        // Dials underway count as peers, once they would make up `min_peers` skip the nodes of
+        // the IP prefixes taking their share of the slots already.
+        let mut distribution = self.peer_distribution_except(None);
+        let mut dialing = handshake_count;
+        let mut diverse = |id: &NodeId| {
+            let network = match reserved_nodes.contains(id) {
+                true => None,
+                false => self
+                    .nodes
+                    .read()
+                    .get(id)
+                    .and_then(|node| network_prefix(&node.endpoint.address)),
+            };
+            if !policy.admits(
+                &distribution,
+                distribution.total + dialing,
+                network.as_deref(),
+                None,
+            ) {
+                trace!(target: "network", "Not dialing {id}, too many peers in {network:?}");
+                return false;
+            }
+            distribution.add(network, None);
+            dialing += 1;
+            true
+        };
+
         let max_handshakes_per_round = max_handshakes / 2;
         let mut started: usize = 0;
         for id in nodes
@@ -674,10 +1038,9 @@ impl Host {
                 !self.have_session(id)
                     && !self.connecting_to(id)
                     && *id != self_id
-                    && self.filter.as_ref().is_none_or(|f| {
-                        f.connection_allowed(&self_id, id, ConnectionDirection::Outbound)
-                    })
+                    && self.connection_allowed(id, ConnectionDirection::Outbound)
             })
+            .filter(|id| diverse(id))
             .take(min(
                 max_handshakes_per_round,
                 max_handshakes - handshake_count,
@@ -709,7 +1072,7 @@ impl Host {
                     return;
                 }
             };
-            match TcpStream::connect(&address) {
+            match self.sockets.connect(&address) {
                 Ok(socket) => {
                     trace!(target: "network", "{id}: Connecting to {address:?}");
                     socket
@@ -796,8 +1159,9 @@ impl Host {
 
     fn session_readable(&self, token: StreamToken, io: &IoContext<NetworkIoMessage>) {
         let mut ready_data: Vec<ProtocolId> = Vec::new();
-        let mut packet_data: Vec<(ProtocolId, PacketId, Vec<u8>)> = Vec::new();
+        let mut packet_data: Vec<(ProtocolId, PacketId, PooledBuffer)> = Vec::new();
         let mut kill = false;
+        let mut probe = false;
         let session = { self.sessions.read().get(token).cloned() };
         let mut ready_id = None;
         if let Some(session) = session.clone() {
@@ -827,9 +1191,10 @@ impl Host {
                         }
                         Ok(SessionData::Ready) => {
                             let (_, egress_count, ingress_count) = self.session_count();
+                            let distribution = self.peer_distribution_except(Some(token));
                             let reserved_nodes = self.reserved_nodes.read();
                             let mut s = session.lock();
-                            let (min_peers, mut max_peers, reserved_only, self_id) = {
+                            let (min_peers, mut max_peers, reserved_only, max_peer_share) = {
                                 let info = self.info.read();
                                 let mut max_peers = info.config.max_peers;
                                 for cap in &s.info.capabilities {
@@ -844,7 +1209,7 @@ impl Host {
                                     info.config.min_peers as usize,
                                     max_peers as usize,
                                     info.config.non_reserved_mode == NonReservedPeerMode::Deny,
-                                    *info.id(),
+                                    info.config.max_peer_share,
                                 )
                             };
 
@@ -868,9 +1233,26 @@ impl Host {
                                 break;
                             }
 
-                            if !self.filter.as_ref().is_none_or(|f| {
-                                f.connection_allowed(&self_id, &id, ConnectionDirection::Inbound)
-                            }) {
+                            // Keep inbound peers of one IP prefix or client from taking over.
+                            if !s.info.originated && !reserved_nodes.contains(&id) {
+                                let policy =
+                                    DiversityPolicy::new(max_peer_share, min_peers, max_peers);
+                                let network = s.remote_addr().ok().and_then(|a| network_prefix(&a));
+                                let client = client_name(&s.info.client_version);
+                                if !policy.admits(
+                                    &distribution,
+                                    distribution.total,
+                                    network.as_deref(),
+                                    client.as_deref(),
+                                ) {
+                                    trace!(target: "network", "Disconnecting peer {id:?} of {network:?} running {client:?}, too many peers of either");
+                                    s.disconnect(io, DisconnectReason::TooManyPeers);
+                                    kill = true;
+                                    break;
+                                }
+                            }
+
+                            if !self.connection_allowed(&id, ConnectionDirection::Inbound) {
                                 trace!(target: "network", "Inbound connection not allowed for {id:?}");
                                 s.disconnect(io, DisconnectReason::UnexpectedIdentity);
                                 kill = true;
@@ -920,6 +1302,10 @@ impl Host {
                         },
                         Ok(SessionData::Continue) => (),
                         Ok(SessionData::None) => break,
+                        Ok(SessionData::Probe) => {
+                            probe = true;
+                            break;
+                        }
                     }
                 }
             }
@@ -927,6 +1313,11 @@ impl Host {
             if kill {
                 self.kill_connection(token, io, true);
             }
+            if probe {
+                // never counted as a peer, deregistered as soon as the ack is sent
+                self.kill_connection(token, io, false);
+                return;
+            }
 
             let handlers = self.handlers.read();
             if !ready_data.is_empty() {
@@ -953,6 +1344,7 @@ impl Host {
                                 Some(session.clone()),
                                 self.sessions.clone(),
                                 &reserved,
+                                &self.handler_timers,
                             ),
                             &token,
                         );
@@ -973,6 +1365,7 @@ impl Host {
                             Some(session.clone()),
                             self.sessions.clone(),
                             &reserved,
+                            &self.handler_timers,
                         ),
                         &token,
                         packet_id,
@@ -1081,6 +1474,9 @@ impl Host {
                 self.nodes.write().note_failure(&id);
             }
         }
+        if expired_session.is_some() {
+            self.packet_captures.session_ended(token);
+        }
         for p in to_disconnect {
             let reserved = self.reserved_nodes.read();
             if let Some(h) = self.handlers.read().get(&p) {
@@ -1091,6 +1487,7 @@ impl Host {
                         expired_session.clone(),
                         self.sessions.clone(),
                         &reserved,
+                        &self.handler_timers,
                     ),
                     &token,
                 );
@@ -1128,7 +1525,14 @@ impl Host {
     {
         let reserved = { self.reserved_nodes.read() };
 
-        let context = NetworkContext::new(io, protocol, None, self.sessions.clone(), &reserved);
+        let context = NetworkContext::new(
+            io,
+            protocol,
+            None,
+            self.sessions.clone(),
+            &reserved,
+            &self.handler_timers,
+        );
         action(&context);
     }
 
@@ -1143,7 +1547,14 @@ impl Host {
     {
         let reserved = { self.reserved_nodes.read() };
 
-        let context = NetworkContext::new(io, protocol, None, self.sessions.clone(), &reserved);
+        let context = NetworkContext::new(
+            io,
+            protocol,
+            None,
+            self.sessions.clone(),
+            &reserved,
+            &self.handler_timers,
+        );
         action(&context)
     }
 }
@@ -1225,12 +1636,57 @@ impl IoHandler<NetworkIoMessage> for Host {
                 io.update_registration(DISCOVERY)
                     .unwrap_or_else(|e| debug!("Error updating discovery registration: {e:?}"));
             }
+            DNS_DISCOVERY => {
+                // the lookups block, they run on the crawler thread; this adds what it found.
+                let found = match self.dns_discovery {
+                    Some(ref dns) => {
+                        dns.request_round(Instant::now());
+                        dns.take_found()
+                    }
+                    None => return,
+                };
+                if found.is_empty() {
+                    return;
+                }
+                debug!(target: "network", "DNS discovery found {} new nodes", found.len());
+                let mut nodes = self.nodes.write();
+                let mut discovery = self.discovery.lock();
+                for entry in found {
+                    if !nodes.contains(&entry.id) {
+                        nodes.add_node(Node::new(entry.id, entry.endpoint.clone()));
+                        if let Some(ref mut discovery) = *discovery {
+                            discovery.add_node(entry);
+                        }
+                    }
+                }
+            }
             NODE_TABLE => {
                 trace!(target: "network", "Refreshing node table");
                 let mut nodes = self.nodes.write();
                 nodes.clear_useless();
                 nodes.save();
             }
+            BAN_LIST => self.banned_nodes.write().save_if_changed(),
+            REACHABILITY_PROBE => {
+                // the probe waits for our own listener, it must not block the IO thread
+                let (address, secret, public) = match self.reachability_target() {
+                    Some(target) => target,
+                    None => return,
+                };
+                let monitor = self.reachability.clone();
+                let stats = self.stats.clone();
+                let name = self.info.read().config.instance_name("reachability-probe");
+                let spawned = thread::Builder::new().name(name).spawn(move || {
+                    if let Some(probe) =
+                        monitor.run(address, &secret, &public, REACHABILITY_PROBE_TIMEOUT)
+                    {
+                        stats.lock().note_reachability(probe);
+                    }
+                });
+                if let Err(e) = spawned {
+                    warn!(target: "network", "Error starting reachability probe: {e:?}");
+                }
+            }
             _ => match self.timers.read().get(&token).cloned() {
                 Some(timer) => match self.handlers.read().get(&timer.protocol).cloned() {
                     None => {
@@ -1245,6 +1701,7 @@ impl IoHandler<NetworkIoMessage> for Host {
                                 None,
                                 self.sessions.clone(),
                                 &reserved,
+                                &self.handler_timers,
                             ),
                             timer.token,
                         );
@@ -1275,6 +1732,7 @@ impl IoHandler<NetworkIoMessage> for Host {
                     None,
                     self.sessions.clone(),
                     &reserved,
+                    &self.handler_timers,
                 ));
                 self.handlers.write().insert(*protocol, h);
                 let mut info = self.info.write();
@@ -1324,15 +1782,57 @@ impl IoHandler<NetworkIoMessage> for Host {
                     session
                         .lock()
                         .disconnect(io, DisconnectReason::DisconnectRequested);
-                    if let Some(id) = session.lock().id() {
+                    let id = session.lock().id().cloned();
+                    if let Some(id) = id {
                         let mut nodes = self.nodes.write();
-                        nodes.note_failure(id);
-                        nodes.mark_as_useless(id);
+                        nodes.note_failure(&id);
+                        nodes.mark_as_useless(&id);
+                        // reserved peers are never banned automatically
+                        if !self.reserved_nodes.read().contains(&id) {
+                            let duration = self.info.read().config.peer_ban_duration;
+                            if self.banned_nodes.write().note_disable(id, duration) {
+                                debug!(target: "network", "Banning repeatedly disabled node {id}");
+                            }
+                        }
                     }
                 }
                 trace!(target: "network", "Disabling peer {peer}");
                 self.kill_connection(*peer, io, false);
             }
+            NetworkIoMessage::BanPeer(ref peer, ref reason) => {
+                let session = { self.sessions.read().get(*peer).cloned() };
+                if let Some(session) = session {
+                    session
+                        .lock()
+                        .disconnect(io, DisconnectReason::DisconnectRequested);
+                    let id = session.lock().id().cloned();
+                    if let Some(id) = id {
+                        let mut nodes = self.nodes.write();
+                        nodes.note_failure(&id);
+                        nodes.mark_as_useless(&id);
+                        // reserved peers are never banned automatically
+                        if !self.reserved_nodes.read().contains(&id) {
+                            let duration = self.info.read().config.peer_ban_duration;
+                            self.banned_nodes.write().ban(id, duration, reason);
+                        }
+                    }
+                }
+                debug!(target: "network", "Banning peer {peer}: {reason}");
+                self.kill_connection(*peer, io, false);
+            }
+            NetworkIoMessage::AvoidPeer(ref peer) => {
+                let session = { self.sessions.read().get(*peer).cloned() };
+                if let Some(session) = session {
+                    session.lock().disconnect(io, DisconnectReason::UselessPeer);
+                    let id = session.lock().id().cloned();
+                    // reserved peers are always dialed
+                    if let Some(id) = id.filter(|id| !self.reserved_nodes.read().contains(id)) {
+                        self.avoid_node(id);
+                    }
+                }
+                trace!(target: "network", "Avoiding peer {peer}");
+                self.kill_connection(*peer, io, false);
+            }
             NetworkIoMessage::InitPublicInterface => self
                 .init_public_interface(io)
                 .unwrap_or_else(|e| warn!("Error initializing public interface: {e:?}")),
@@ -1512,6 +2012,67 @@ fn host_client_url() {
         .parse()
         .unwrap();
     config.use_secret = Some(key);
-    let host: Host = Host::new(config, None).unwrap();
+    let host: Host = Host::new(config, None, BufferPool::default()).unwrap();
     assert!(host.local_url().starts_with("enode://101b3ef5a4ea7a1c7928e24c4c75fd053c235d7b80c22ae5c03d145d0ac7396e2a4ffff9adee3133a7b05044a5cee08115fd65145e5165d646bde371010d803c@"));
 }
+
+#[test]
+fn host_banned_node_persists() {
+    use tempfile::TempDir;
+
+    let tempdir = TempDir::new().unwrap();
+    let mut config = NetworkConfiguration::new_local();
+    config.net_config_path = Some(tempdir.path().to_str().unwrap().to_owned());
+    let banned = NodeId::random();
+    let expired = NodeId::random();
+
+    {
+        let host = Host::new(config.clone(), None, BufferPool::default()).unwrap();
+        let io = IoContext::new(IoChannel::disconnected(), 0);
+        host.ban_node(banned, Duration::from_secs(3600), "test", &io);
+        host.ban_node(expired, Duration::from_secs(0), "test", &io);
+    }
+
+    let host = Host::new(config, None, BufferPool::default()).unwrap();
+    assert!(!host.connection_allowed(&banned, ConnectionDirection::Inbound));
+    assert!(!host.connection_allowed(&banned, ConnectionDirection::Outbound));
+    assert!(host.connection_allowed(&expired, ConnectionDirection::Inbound));
+    assert_eq!(host.banned_nodes().len(), 1);
+
+    assert!(host.unban_node(&banned));
+    assert!(host.connection_allowed(&banned, ConnectionDirection::Inbound));
+}
+
+#[test]
+fn host_keeps_files_of_labeled_instances_apart() {
+    use tempfile::TempDir;
+
+    let tempdir = TempDir::new().unwrap();
+    let mut config = NetworkConfiguration::new_local();
+    config.config_path = Some(tempdir.path().to_str().unwrap().to_owned());
+    config.instance_label = "alpha".into();
+    let alpha = Host::new(config.clone(), None, BufferPool::default()).unwrap();
+    config.instance_label = "beta".into();
+    let beta = Host::new(config, None, BufferPool::default()).unwrap();
+
+    assert!(tempdir.path().join("alpha").join("key").exists());
+    assert!(tempdir.path().join("beta").join("key").exists());
+    assert!(!tempdir.path().join("key").exists());
+    assert_ne!(alpha.info.read().id(), beta.info.read().id());
+}
+
+#[test]
+fn host_avoided_node_is_not_dialed() {
+    let mut config = NetworkConfiguration::new_local();
+    config.avoid_duration = Duration::from_secs(3600);
+    let host = Host::new(config, None, BufferPool::default()).unwrap();
+    let avoided = NodeId::random();
+
+    host.avoid_node(avoided);
+    assert!(!host.connection_allowed(&avoided, ConnectionDirection::Outbound));
+    assert!(host.connection_allowed(&avoided, ConnectionDirection::Inbound));
+
+    host.info.write().config.avoid_duration = Duration::from_secs(0);
+    host.avoid_node(avoided);
+    assert!(host.connection_allowed(&avoided, ConnectionDirection::Outbound));
+}
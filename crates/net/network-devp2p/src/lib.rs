@@ -85,6 +85,7 @@ extern crate rustc_hex;
 extern crate serde;
 extern crate serde_json;
 extern crate slab;
+extern crate socket2;
 extern crate tiny_keccak;
 
 #[macro_use]
@@ -102,21 +103,42 @@ extern crate tempfile;
 #[macro_use]
 extern crate assert_matches;
 
+mod ban_list;
+mod buffer_pool;
+mod capture;
 mod connection;
 mod discovery;
+mod dns_discovery;
+mod handler_timers;
 mod handshake;
 mod host;
 mod ip_utils;
 mod node_table;
+mod peer_diversity;
+mod reachability;
+mod seen_capabilities;
 mod service;
 mod session;
+mod sockets;
+#[cfg(any(test, feature = "test-helpers"))]
+pub mod test_net;
 
-pub use host::NetworkContext;
+pub use ban_list::BanEntry;
+pub use buffer_pool::{BufferPool, BufferPoolStats, PooledBuffer};
+pub use capture::{CaptureDirection, CaptureHandle, CaptureRecord};
+pub use host::{NetworkContext, NetworkStats};
 pub use service::NetworkService;
+#[cfg(any(test, feature = "test-helpers"))]
+pub use sockets::RecordingBindDevice;
+pub use sockets::{BindDevice, HostSockets, SystemBindDevice};
 
 pub use connection::PAYLOAD_SOFT_LIMIT;
+pub use dns_discovery::Stats as DnsDiscoveryStats;
 
-pub use io::TimerToken;
+pub use io::{IoStats, TimerToken};
 pub use node_table::{validate_node_url, NodeId};
+pub use peer_diversity::PeerDistribution;
+pub use reachability::{Reachability, ReachabilityProbe};
+pub use seen_capabilities::SeenCapability;
 
 const PROTOCOL_VERSION: u32 = 5;
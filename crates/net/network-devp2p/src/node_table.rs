@@ -295,14 +295,17 @@ pub struct NodeTable {
     nodes: HashMap<NodeId, Node>,
     useless_nodes: HashSet<NodeId>,
     path: Option<String>,
+    network_id: Option<u64>,
 }
 
 impl NodeTable {
-    pub fn new(path: Option<String>) -> NodeTable {
+    /// Create a node table, loading the nodes saved under `path` for the same `network_id`.
+    pub fn new(path: Option<String>, network_id: Option<u64>) -> NodeTable {
         NodeTable {
             path: path.clone(),
-            nodes: NodeTable::load(path),
+            nodes: NodeTable::load(path, network_id),
             useless_nodes: HashSet::new(),
+            network_id,
         }
     }
 
@@ -398,6 +401,11 @@ impl NodeTable {
             .collect()
     }
 
+    /// Get particular node
+    pub fn get(&self, id: &NodeId) -> Option<&Node> {
+        self.nodes.get(id)
+    }
+
     /// Get particular node
     pub fn get_mut(&mut self, id: &NodeId) -> Option<&mut Node> {
         self.nodes.get_mut(id)
@@ -470,7 +478,10 @@ impl NodeTable {
             .take(MAX_NODES)
             .map(Into::into)
             .collect();
-        let table = json::NodeTable { nodes };
+        let table = json::NodeTable {
+            network_id: self.network_id,
+            nodes,
+        };
 
         match fs::File::create(&path) {
             Ok(file) => {
@@ -484,7 +495,7 @@ impl NodeTable {
         }
     }
 
-    fn load(path: Option<String>) -> HashMap<NodeId, Node> {
+    fn load(path: Option<String>, network_id: Option<u64>) -> HashMap<NodeId, Node> {
         let path = match path {
             Some(path) => PathBuf::from(path).join(NODES_FILE),
             None => return Default::default(),
@@ -499,6 +510,13 @@ impl NodeTable {
         };
         let res: Result<json::NodeTable, _> = serde_json::from_reader(file);
         match res {
+            Ok(json::NodeTable {
+                network_id: Some(recorded),
+                ..
+            }) if network_id.is_some_and(|id| id != recorded) => {
+                warn!(target: "network", "Ignoring node table {}: recorded for network id {}, running network id {}", path.display(), recorded, network_id.unwrap_or_default());
+                Default::default()
+            }
             Ok(table) => table
                 .nodes
                 .into_iter()
@@ -529,6 +547,8 @@ mod json {
 
     #[derive(Serialize, Deserialize)]
     pub struct NodeTable {
+        #[serde(default)]
+        pub network_id: Option<u64>,
         pub nodes: Vec<Node>,
     }
 
@@ -678,7 +698,7 @@ mod tests {
         let id4 = H512::from_str("d979fb575495b8d6db44f750317d0f4622bf4c2aa3365d6af7c284339968eef29b69ad0dce72a4d8db5ebb4968de0e3bec910127f134779fbcb0cb6d3331163c").unwrap();
         let id5 = H512::from_str("e979fb575495b8d6db44f750317d0f4622bf4c2aa3365d6af7c284339968eef29b69ad0dce72a4d8db5ebb4968de0e3bec910127f134779fbcb0cb6d3331163c").unwrap();
         let id6 = H512::from_str("f979fb575495b8d6db44f750317d0f4622bf4c2aa3365d6af7c284339968eef29b69ad0dce72a4d8db5ebb4968de0e3bec910127f134779fbcb0cb6d3331163c").unwrap();
-        let mut table = NodeTable::new(None);
+        let mut table = NodeTable::new(None, None);
 
         table.add_node(node1);
         table.add_node(node2);
@@ -727,7 +747,7 @@ mod tests {
         let id3 = H512::from_str("c979fb575495b8d6db44f750317d0f4622bf4c2aa3365d6af7c284339968eef29b69ad0dce72a4d8db5ebb4968de0e3bec910127f134779fbcb0cb6d3331163c").unwrap();
 
         {
-            let mut table = NodeTable::new(Some(tempdir.path().to_str().unwrap().to_owned()));
+            let mut table = NodeTable::new(Some(tempdir.path().to_str().unwrap().to_owned()), None);
             table.add_node(node1);
             table.add_node(node2);
             table.add_node(node3);
@@ -737,7 +757,7 @@ mod tests {
         }
 
         {
-            let table = NodeTable::new(Some(tempdir.path().to_str().unwrap().to_owned()));
+            let table = NodeTable::new(Some(tempdir.path().to_str().unwrap().to_owned()), None);
             let r = table.nodes(&IpFilter::default());
             assert_eq!(r[0][..], id2[..]); // latest success
             assert_eq!(r[1][..], id1[..]); // unknown
@@ -745,6 +765,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn table_of_other_network_is_not_loaded() {
+        let tempdir = TempDir::new().unwrap();
+        let path = Some(tempdir.path().to_str().unwrap().to_owned());
+        let node = Node::from_str("enode://a979fb575495b8d6db44f750317d0f4622bf4c2aa3365d6af7c284339968eef29b69ad0dce72a4d8db5ebb4968de0e3bec910127f134779fbcb0cb6d3331163c@22.99.55.44:7770").unwrap();
+
+        NodeTable::new(path.clone(), Some(1)).add_node(node);
+
+        assert_eq!(NodeTable::new(path.clone(), Some(1)).entries().len(), 1);
+        assert!(NodeTable::new(path, Some(5)).entries().is_empty());
+    }
+
     #[test]
     fn custom_allow() {
         let filter = IpFilter {
@@ -0,0 +1,208 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+use ip_utils::SocketAddrExt;
+use network::client_version::ClientVersion;
+use std::{
+    cmp::max,
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+};
+
+/// Connected peers by network prefix and client name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerDistribution {
+    /// Number of peers counted.
+    pub total: usize,
+    /// Peers with a public address by `/16` IPv4 or `/32` IPv6 prefix, e.g. `203.0.0.0/16`.
+    pub networks: BTreeMap<String, usize>,
+    /// Peers by client name, e.g. `Geth`.
+    pub clients: BTreeMap<String, usize>,
+}
+
+impl PeerDistribution {
+    /// Count a peer in the buckets it belongs to.
+    pub fn add(&mut self, network: Option<String>, client: Option<String>) {
+        self.total += 1;
+        if let Some(network) = network {
+            *self.networks.entry(network).or_insert(0) += 1;
+        }
+        if let Some(client) = client {
+            *self.clients.entry(client).or_insert(0) += 1;
+        }
+    }
+}
+
+/// Network prefix a peer address is counted under. Addresses which are not publicly routable,
+/// e.g. of the nodes of a private chain in one local network, are not counted.
+pub fn network_prefix(address: &SocketAddr) -> Option<String> {
+    let ip = address.ip();
+    if !ip.is_global_s() {
+        return None;
+    }
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            Some(format!("{}.{}.0.0/16", octets[0], octets[1]))
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            Some(format!("{:x}:{:x}::/32", segments[0], segments[1]))
+        }
+    }
+}
+
+/// Client name a peer is counted under.
+pub fn client_name(client_version: &ClientVersion) -> Option<String> {
+    client_version.info().name
+}
+
+/// Keeps peers of one network prefix or one client from taking more than a share of the peer
+/// slots. Below `min_peers` any peer is admitted. Reserved peers are exempt and not checked.
+#[derive(Debug, Clone, Copy)]
+pub struct DiversityPolicy {
+    min_peers: usize,
+    limit: usize,
+}
+
+impl DiversityPolicy {
+    /// Allow `max_share` percent of the `max_peers` slots to each network prefix and client.
+    pub fn new(max_share: u32, min_peers: usize, max_peers: usize) -> Self {
+        DiversityPolicy {
+            min_peers,
+            limit: max(1, max_peers * max_share as usize / 100),
+        }
+    }
+
+    /// Check if a peer of `network` running `client` is admitted next to the `connected` peers
+    /// counted in `distribution`.
+    pub fn admits(
+        &self,
+        distribution: &PeerDistribution,
+        connected: usize,
+        network: Option<&str>,
+        client: Option<&str>,
+    ) -> bool {
+        if connected < self.min_peers {
+            return true;
+        }
+        let below_limit = |buckets: &BTreeMap<String, usize>, key: Option<&str>| {
+            key.is_none_or(|key| buckets.get(key).cloned().unwrap_or(0) < self.limit)
+        };
+        below_limit(&distribution.networks, network) && below_limit(&distribution.clients, client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPENETHEREUM: &str = "OpenEthereum/v3.3.5-stable/x86_64-linux-gnu/rustc1.71.1";
+    const GETH: &str = "Geth/v1.13.5-stable/linux-amd64/go1.21.4";
+    const NETHERMIND: &str = "Nethermind/v1.21.0/linux-x64/dotnet7.0.11";
+
+    fn address(ip: &str) -> SocketAddr {
+        SocketAddr::new(ip.parse().unwrap(), 30303)
+    }
+
+    // Admit the candidates one by one as they come, counting the admitted ones.
+    fn admit(
+        policy: &DiversityPolicy,
+        candidates: &[(&str, &str)],
+    ) -> (PeerDistribution, Vec<usize>) {
+        let mut distribution = PeerDistribution::default();
+        let mut rejected = Vec::new();
+        for (i, (ip, client)) in candidates.iter().enumerate() {
+            let network = network_prefix(&address(ip));
+            let client = client_name(&ClientVersion::from(*client));
+            if policy.admits(
+                &distribution,
+                distribution.total,
+                network.as_deref(),
+                client.as_deref(),
+            ) {
+                distribution.add(network, client);
+            } else {
+                rejected.push(i);
+            }
+        }
+        (distribution, rejected)
+    }
+
+    #[test]
+    fn counts_public_prefixes_only() {
+        assert_eq!(
+            network_prefix(&address("203.7.1.2")),
+            Some("203.7.0.0/16".into())
+        );
+        assert_eq!(
+            network_prefix(&address("2a01:4f8:1:2::1")),
+            Some("2a01:4f8::/32".into())
+        );
+        assert_eq!(network_prefix(&address("127.0.0.1")), None);
+        assert_eq!(network_prefix(&address("10.1.2.3")), None);
+        assert_eq!(network_prefix(&address("192.168.1.1")), None);
+    }
+
+    #[test]
+    fn caps_dominating_prefix_and_client_at_capacity() {
+        // 10 slots, at most 4 per bucket once 5 peers are connected.
+        let policy = DiversityPolicy::new(40, 5, 10);
+        // One client in one prefix, two others spread over many, arriving in turns.
+        let candidates: Vec<(String, &str)> = (0..20)
+            .flat_map(|i| {
+                vec![
+                    (format!("203.7.{}.1", i), OPENETHEREUM),
+                    (format!("{}.9.0.1", 20 + i), GETH),
+                    (format!("{}.9.0.1", 60 + i), NETHERMIND),
+                ]
+            })
+            .collect();
+        let candidates: Vec<(&str, &str)> = candidates
+            .iter()
+            .map(|(ip, client)| (ip.as_str(), *client))
+            .collect();
+
+        let (distribution, _) = admit(&policy, &candidates);
+        assert_eq!(distribution.total, 12);
+        assert!(distribution.networks.values().all(|count| *count <= 4));
+        assert!(distribution.clients.values().all(|count| *count <= 4));
+        assert_eq!(distribution.networks["203.7.0.0/16"], 4);
+        assert_eq!(distribution.clients["Geth"], 4);
+        assert_eq!(distribution.clients["Nethermind"], 4);
+    }
+
+    #[test]
+    fn admits_anything_below_min_peers() {
+        let policy = DiversityPolicy::new(40, 5, 10);
+        let candidates = [("203.7.0.1", GETH); 5];
+        let (distribution, rejected) = admit(&policy, &candidates);
+        assert!(rejected.is_empty());
+        assert_eq!(distribution.networks["203.7.0.0/16"], 5);
+        assert_eq!(distribution.clients["Geth"], 5);
+
+        // The sixth one would make the bucket exceed its share.
+        let (_, rejected) = admit(&policy, &[candidates[0]; 6]);
+        assert_eq!(rejected, vec![5]);
+    }
+
+    #[test]
+    fn full_share_disables_the_limit() {
+        let policy = DiversityPolicy::new(100, 5, 10);
+        let (_, rejected) = admit(&policy, &[("203.7.0.1", GETH); 10]);
+        assert!(rejected.is_empty());
+    }
+}
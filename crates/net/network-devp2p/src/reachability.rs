@@ -0,0 +1,207 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reachability of the advertised address, checked by dialing it and starting a handshake
+//! with ourselves. Our listener only answers the handshake if the connection made it through
+//! the NAT and firewalls in between.
+
+use crypto::publickey::{ecies, Generator, Public, Random, Secret};
+use ethereum_types::H256;
+use handshake::auth_packet;
+use std::{
+    fmt, io,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime},
+};
+
+/// Outcome of a reachability probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reachability {
+    /// Our listener answered the handshake.
+    Reachable,
+    /// The connection was refused.
+    Refused,
+    /// The connection or the handshake timed out.
+    Timeout,
+    /// The probe failed otherwise, e.g. another node answered.
+    Failed(String),
+}
+
+impl fmt::Display for Reachability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Reachability::Reachable => write!(f, "reachable"),
+            Reachability::Refused => write!(f, "connection refused"),
+            Reachability::Timeout => write!(f, "timed out"),
+            Reachability::Failed(ref e) => write!(f, "failed: {e}"),
+        }
+    }
+}
+
+/// Result of a reachability probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReachabilityProbe {
+    /// Address dialed.
+    pub address: SocketAddr,
+    /// Outcome of the probe.
+    pub result: Reachability,
+    /// When the probe finished.
+    pub at: SystemTime,
+}
+
+/// Dial `address` and start a handshake with the node of `secret` and `public`, our own.
+pub fn probe(
+    address: SocketAddr,
+    secret: &Secret,
+    public: &Public,
+    timeout: Duration,
+) -> ReachabilityProbe {
+    let result = match handshake(address, secret, public, timeout) {
+        Ok(()) => Reachability::Reachable,
+        Err(e) => match e.kind() {
+            io::ErrorKind::ConnectionRefused => Reachability::Refused,
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Reachability::Timeout,
+            _ => Reachability::Failed(e.to_string()),
+        },
+    };
+    ReachabilityProbe {
+        address,
+        result,
+        at: SystemTime::now(),
+    }
+}
+
+// Send the auth packet and decrypt the EIP-8 ack, only our listener can encrypt it to us.
+fn handshake(
+    address: SocketAddr,
+    secret: &Secret,
+    public: &Public,
+    timeout: Duration,
+) -> io::Result<()> {
+    let mut stream = TcpStream::connect_timeout(&address, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let auth = auth_packet(secret, public, public, &Random.generate(), &H256::random())
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    stream.write_all(&auth)?;
+    let mut prefix = [0u8; 2];
+    stream.read_exact(&mut prefix)?;
+    let mut ack = vec![0u8; u16::from_be_bytes(prefix) as usize];
+    stream.read_exact(&mut ack)?;
+    ecies::decrypt(secret, &prefix, &ack)
+        .map(|_| ())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("unexpected ack: {e}")))
+}
+
+/// Runs the probes one at a time and logs changes of the reachability.
+#[derive(Default)]
+pub struct ReachabilityMonitor {
+    confirmed: AtomicBool,
+    running: AtomicBool,
+}
+
+impl ReachabilityMonitor {
+    /// Probe `address` unless a probe is running already. Warns if the address is not
+    /// reachable anymore.
+    pub fn run(
+        &self,
+        address: SocketAddr,
+        secret: &Secret,
+        public: &Public,
+        timeout: Duration,
+    ) -> Option<ReachabilityProbe> {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        let probe = probe(address, secret, public, timeout);
+        self.note(&probe);
+        self.running.store(false, Ordering::SeqCst);
+        Some(probe)
+    }
+
+    fn note(&self, probe: &ReachabilityProbe) {
+        if probe.result == Reachability::Reachable {
+            if !self.confirmed.swap(true, Ordering::SeqCst) {
+                info!(target: "network", "Public address {} is reachable", probe.address);
+            }
+        } else if self.confirmed.swap(false, Ordering::SeqCst) {
+            warn!(target: "network", "Public address {} is not reachable anymore: {}", probe.address, probe.result);
+        } else {
+            debug!(target: "network", "Public address {} is not reachable: {}", probe.address, probe.result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::publickey::KeyPair;
+    use std::net::TcpListener;
+
+    fn keys() -> KeyPair {
+        Random.generate()
+    }
+
+    #[test]
+    fn closed_port_is_refused() {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let keys = keys();
+        let probe = probe(
+            address,
+            keys.secret(),
+            keys.public(),
+            Duration::from_secs(1),
+        );
+        assert_eq!(probe.result, Reachability::Refused);
+    }
+
+    #[test]
+    fn silent_listener_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let keys = keys();
+        let probe = probe(
+            listener.local_addr().unwrap(),
+            keys.secret(),
+            keys.public(),
+            Duration::from_millis(200),
+        );
+        assert_eq!(probe.result, Reachability::Timeout);
+    }
+
+    #[test]
+    fn monitor_tracks_confirmed_reachability() {
+        let monitor = ReachabilityMonitor::default();
+        let address = "127.0.0.1:30303".parse().unwrap();
+        let note = |result| {
+            monitor.note(&ReachabilityProbe {
+                address,
+                result,
+                at: SystemTime::now(),
+            })
+        };
+        note(Reachability::Refused);
+        assert!(!monitor.confirmed.load(Ordering::SeqCst));
+        note(Reachability::Reachable);
+        assert!(monitor.confirmed.load(Ordering::SeqCst));
+        note(Reachability::Timeout);
+        assert!(!monitor.confirmed.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,201 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+use network::PeerCapabilityInfo;
+use std::{collections::HashMap, time::SystemTime};
+
+const MAX_SEEN_CAPABILITIES: usize = 256;
+const MAX_CLIENT_VERSION_LEN: usize = 256;
+
+/// A capability advertised in the `Hello` messages of peers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeenCapability {
+    /// Protocol name and version, e.g. `eth/66`.
+    pub capability: String,
+    /// Number of `Hello` messages advertising the capability.
+    pub count: u64,
+    /// When the capability was last advertised.
+    pub last_seen: SystemTime,
+    /// Client version of the peer which last advertised the capability.
+    pub client_version: String,
+}
+
+/// Frequency table of the capabilities advertised by peers, whether implemented or not.
+/// Bounded to `MAX_SEEN_CAPABILITIES`, the least recently advertised entry is evicted first.
+pub struct SeenCapabilities {
+    entries: HashMap<String, (SeenCapability, u64)>,
+    max_entries: usize,
+    tick: u64,
+}
+
+impl Default for SeenCapabilities {
+    fn default() -> Self {
+        SeenCapabilities::new(MAX_SEEN_CAPABILITIES)
+    }
+}
+
+impl SeenCapabilities {
+    fn new(max_entries: usize) -> Self {
+        SeenCapabilities {
+            entries: HashMap::new(),
+            max_entries,
+            tick: 0,
+        }
+    }
+
+    /// Record the capabilities of a `Hello` message sent by a peer running `client_version`.
+    /// Returns the capabilities not present in the table before.
+    pub fn note(
+        &mut self,
+        capabilities: &[PeerCapabilityInfo],
+        client_version: &str,
+    ) -> Vec<String> {
+        let now = SystemTime::now();
+        let client_version: String = client_version
+            .chars()
+            .take(MAX_CLIENT_VERSION_LEN)
+            .collect();
+        let mut first_seen = Vec::new();
+        for capability in capabilities {
+            let name = capability_name(capability);
+            self.tick += 1;
+            if let Some((entry, last_tick)) = self.entries.get_mut(&name) {
+                entry.count += 1;
+                entry.last_seen = now;
+                entry.client_version = client_version.clone();
+                *last_tick = self.tick;
+                continue;
+            }
+            if self.entries.len() >= self.max_entries {
+                self.evict();
+            }
+            self.entries.insert(
+                name.clone(),
+                (
+                    SeenCapability {
+                        capability: name.clone(),
+                        count: 1,
+                        last_seen: now,
+                        client_version: client_version.clone(),
+                    },
+                    self.tick,
+                ),
+            );
+            first_seen.push(name);
+        }
+        first_seen
+    }
+
+    /// All recorded capabilities, most frequently advertised first.
+    pub fn entries(&self) -> Vec<SeenCapability> {
+        let mut entries: Vec<_> = self.entries.values().map(|(e, _)| e.clone()).collect();
+        entries.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a.capability.cmp(&b.capability))
+        });
+        entries
+    }
+
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, (_, tick))| *tick)
+            .map(|(name, _)| name.clone());
+        if let Some(name) = oldest {
+            self.entries.remove(&name);
+        }
+    }
+}
+
+// Protocol ids are the protocol names read as big endian numbers.
+fn capability_name(capability: &PeerCapabilityInfo) -> String {
+    let bytes = capability.protocol.as_u64().to_be_bytes();
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    format!(
+        "{}/{}",
+        String::from_utf8_lossy(&bytes[start..]),
+        capability.version
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rlp::{Rlp, RlpStream};
+
+    // Capabilities as decoded from the `Hello` message.
+    fn hello_capabilities(caps: &[(&str, u8)]) -> Vec<PeerCapabilityInfo> {
+        let mut s = RlpStream::new_list(caps.len());
+        for (name, version) in caps {
+            s.begin_list(2);
+            s.append(&name.as_bytes().to_vec());
+            s.append(version);
+        }
+        let out = s.out();
+        Rlp::new(&out).as_list().unwrap()
+    }
+
+    fn names(table: &SeenCapabilities) -> Vec<String> {
+        table.entries().into_iter().map(|e| e.capability).collect()
+    }
+
+    #[test]
+    fn records_known_and_unknown_capabilities() {
+        let mut table = SeenCapabilities::default();
+        let first = table.note(
+            &hello_capabilities(&[("eth", 66), ("snap", 1)]),
+            "Geth/v1.10.0",
+        );
+        assert_eq!(first, vec!["eth/66".to_owned(), "snap/1".to_owned()]);
+
+        let second = table.note(
+            &hello_capabilities(&[("eth", 66), ("wit", 0)]),
+            "Nethermind/v1.11.0",
+        );
+        assert_eq!(second, vec!["wit/0".to_owned()]);
+
+        let entries = table.entries();
+        assert_eq!(names(&table), vec!["eth/66", "snap/1", "wit/0"]);
+        assert_eq!(entries[0].count, 2);
+        assert_eq!(entries[0].client_version, "Nethermind/v1.11.0");
+        assert_eq!(entries[1].count, 1);
+        assert_eq!(entries[1].client_version, "Geth/v1.10.0");
+    }
+
+    #[test]
+    fn first_seen_is_reported_once() {
+        let mut table = SeenCapabilities::default();
+        let hello = hello_capabilities(&[("snap", 1)]);
+        assert_eq!(table.note(&hello, "Geth"), vec!["snap/1".to_owned()]);
+        assert!(table.note(&hello, "Geth").is_empty());
+        assert!(table.note(&hello, "Erigon").is_empty());
+        assert_eq!(table.entries()[0].count, 3);
+    }
+
+    #[test]
+    fn evicts_least_recently_seen() {
+        let mut table = SeenCapabilities::new(2);
+        table.note(&hello_capabilities(&[("eth", 65), ("eth", 66)]), "Geth");
+        table.note(&hello_capabilities(&[("eth", 65)]), "Geth");
+        assert_eq!(
+            table.note(&hello_capabilities(&[("snap", 1)]), "Geth"),
+            vec!["snap/1".to_owned()]
+        );
+        assert_eq!(names(&table), vec!["eth/65", "snap/1"]);
+    }
+}
@@ -14,14 +14,22 @@
 // You should have received a copy of the GNU General Public License
 // along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
 
-use host::Host;
+use ban_list::BanEntry;
+use buffer_pool::{BufferPool, BufferPoolStats};
+use capture::CaptureHandle;
+use dns_discovery::Stats as DnsDiscoveryStats;
+use host::{Host, NetworkStats};
 use io::*;
 use network::{
-    ConnectionFilter, Error, NetworkConfiguration, NetworkContext, NetworkIoMessage,
+    ConnectionFilter, Error, ErrorKind, NetworkConfiguration, NetworkContext, NetworkIoMessage,
     NetworkProtocolHandler, NonReservedPeerMode, PeerId, ProtocolId,
 };
+use node_table::NodeId;
 use parking_lot::RwLock;
-use std::{net::SocketAddr, ops::RangeInclusive, sync::Arc};
+use peer_diversity::PeerDistribution;
+use reachability::ReachabilityProbe;
+use seen_capabilities::SeenCapability;
+use std::{collections::BTreeMap, net::SocketAddr, ops::RangeInclusive, sync::Arc, time::Duration};
 
 struct HostHandler {
     public_url: RwLock<Option<String>>,
@@ -48,6 +56,9 @@ pub struct NetworkService {
     host_handler: Arc<HostHandler>,
     config: NetworkConfiguration,
     filter: Option<Arc<dyn ConnectionFilter>>,
+    buffer_pool: BufferPool,
+    /// Entries set in the node record, applied again whenever the host is started
+    node_record_entries: RwLock<BTreeMap<Vec<u8>, Vec<u8>>>,
 }
 
 impl NetworkService {
@@ -59,7 +70,7 @@ impl NetworkService {
         let host_handler = Arc::new(HostHandler {
             public_url: RwLock::new(None),
         });
-        let io_service = IoService::<NetworkIoMessage>::start("devp2p")?;
+        let io_service = IoService::<NetworkIoMessage>::start(&config.instance_name("devp2p"))?;
 
         Ok(NetworkService {
             io_service,
@@ -68,6 +79,8 @@ impl NetworkService {
             config,
             host_handler,
             filter,
+            buffer_pool: BufferPool::default(),
+            node_record_entries: RwLock::new(BTreeMap::new()),
         })
     }
 
@@ -114,6 +127,22 @@ impl NetworkService {
         host.as_ref().map(|h| h.local_url())
     }
 
+    /// Returns the node record (EIP-778) in its text form if available.
+    pub fn node_record(&self) -> Option<String> {
+        let host = self.host.read();
+        host.as_ref().map(|h| h.node_record())
+    }
+
+    /// Set an entry of the node record, the value being an RLP item. The entry is kept across
+    /// restarts of the network.
+    pub fn set_node_record_entry(&self, key: &[u8], value: Vec<u8>) -> Result<(), Error> {
+        if let Some(ref host) = *self.host.read() {
+            host.set_node_record_entry(key, value.clone())?;
+        }
+        self.node_record_entries.write().insert(key.to_vec(), value);
+        Ok(())
+    }
+
     /// Start network IO.
     ///
     /// In case of error, also returns the listening address for better error reporting.
@@ -122,9 +151,18 @@ impl NetworkService {
         let listen_addr = self.config.listen_address;
         if host.is_none() {
             let h = Arc::new(
-                Host::new(self.config.clone(), self.filter.clone())
-                    .map_err(|err| (err, listen_addr))?,
+                Host::new(
+                    self.config.clone(),
+                    self.filter.clone(),
+                    self.buffer_pool.clone(),
+                )
+                .map_err(|err| (err, listen_addr))?,
             );
+            for (key, value) in self.node_record_entries.read().iter() {
+                h.set_node_record_entry(key, value.clone()).unwrap_or_else(
+                    |e| warn!(target: "network", "Error setting node record entry: {e}"),
+                );
+            }
             self.io_service
                 .register_handler(h.clone())
                 .map_err(|err| (err.into(), listen_addr))?;
@@ -188,6 +226,108 @@ impl NetworkService {
         }
     }
 
+    /// Get a list of all banned nodes.
+    pub fn banned_peers(&self) -> Vec<BanEntry> {
+        self.host
+            .read()
+            .as_ref()
+            .map(|h| h.banned_nodes())
+            .unwrap_or_default()
+    }
+
+    /// Capabilities advertised in the `Hello` messages of peers, including those not implemented.
+    pub fn seen_capabilities(&self) -> Vec<SeenCapability> {
+        self.host
+            .read()
+            .as_ref()
+            .map(|h| h.seen_capabilities())
+            .unwrap_or_default()
+    }
+
+    /// Connected peers by IP prefix and client name.
+    pub fn peer_distribution(&self) -> PeerDistribution {
+        self.host
+            .read()
+            .as_ref()
+            .map(|h| h.peer_distribution())
+            .unwrap_or_default()
+    }
+
+    /// Result of the last probe of the public address.
+    pub fn reachability(&self) -> Option<ReachabilityProbe> {
+        self.host.read().as_ref().and_then(|h| h.reachability())
+    }
+
+    /// Statistics of the host, empty if the service is not started.
+    pub fn stats(&self) -> NetworkStats {
+        self.host
+            .read()
+            .as_ref()
+            .map(|h| h.stats())
+            .unwrap_or_default()
+    }
+
+    /// Probe the reachability of the public address now, blocking until the probe is done.
+    /// `None` if the service is not started, its public address is not known yet or another
+    /// probe is running.
+    pub fn probe_reachability(&self) -> Option<ReachabilityProbe> {
+        let host = self.host.read().clone();
+        host.and_then(|h| h.probe_reachability())
+    }
+
+    /// Counters of the DNS discovery, `None` if the service is not started or no DNS discovery
+    /// URL is configured.
+    pub fn dns_discovery_stats(&self) -> Option<DnsDiscoveryStats> {
+        self.host
+            .read()
+            .as_ref()
+            .and_then(|h| h.dns_discovery_stats())
+    }
+
+    /// Start capturing the decrypted subprotocol packets of a connected peer to a new file
+    /// `name` in the `captures` directory of `net_config_path`, until `max_bytes` of records are
+    /// written, the session ends or the returned handle is dropped. At most one capture runs
+    /// per peer and at most `max_packet_captures` of the configuration in total. See
+    /// `CaptureRecord` for the format of the file.
+    pub fn start_capture(
+        &self,
+        peer: PeerId,
+        max_bytes: usize,
+        name: &str,
+    ) -> Result<CaptureHandle, Error> {
+        match *self.host.read() {
+            Some(ref host) => host.start_capture(peer, max_bytes, name),
+            None => Err(ErrorKind::PeerNotFound.into()),
+        }
+    }
+
+    /// Usage statistics of the buffer pool shared by the sessions of this service.
+    pub fn buffer_pool_stats(&self) -> BufferPoolStats {
+        self.buffer_pool.stats()
+    }
+
+    /// Queue depth and latencies of the IO workers running the network handlers.
+    pub fn io_stats(&self) -> IoStats {
+        self.io_service.stats()
+    }
+
+    /// Ban a node for `duration`, disconnecting it if currently connected.
+    pub fn ban(&self, node_id: NodeId, duration: Duration, reason: &str) {
+        let host = self.host.read();
+        if let Some(ref host) = *host {
+            let io_ctxt = IoContext::new(self.io_service.channel(), 0);
+            host.ban_node(node_id, duration, reason, &io_ctxt);
+        }
+    }
+
+    /// Lift the ban on a node. Returns `false` if the node was not banned.
+    pub fn unban(&self, node_id: &NodeId) -> bool {
+        self.host
+            .read()
+            .as_ref()
+            .is_some_and(|h| h.unban_node(node_id))
+    }
+
     /// Executes action in the network context
     pub fn with_context<F>(&self, protocol: ProtocolId, action: F)
     where
@@ -19,10 +19,13 @@ use std::{
     io,
     net::SocketAddr,
     str,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
-use connection::{Connection, EncryptedConnection, Packet, MAX_PAYLOAD_SIZE};
+use buffer_pool::PooledBuffer;
+use capture::{CaptureDirection, PacketCapture};
+use connection::{Connection, EncryptedConnection, Packet, MAX_SEND_QUEUE_BYTES};
 use ethereum_types::H256;
 use handshake::Handshake;
 use host::*;
@@ -33,8 +36,9 @@ use mio::{
     *,
 };
 use network::{
-    client_version::ClientVersion, DisconnectReason, Error, ErrorKind, PeerCapabilityInfo,
-    ProtocolId, SessionCapabilityInfo, SessionInfo,
+    client_version::{ClientCapabilities, ClientVersion},
+    DisconnectReason, Error, ErrorKind, PeerCapabilityInfo, ProtocolId, SessionCapabilityInfo,
+    SessionInfo, MAX_PAYLOAD_SIZE,
 };
 use node_table::NodeId;
 use rlp::{Rlp, RlpStream, EMPTY_LIST_RLP};
@@ -49,7 +53,7 @@ const MIN_COMPRESSION_PROTOCOL_VERSION: u32 = 5;
 #[derive(Debug, Clone)]
 enum ProtocolState {
     // Packets pending protocol on_connect event return.
-    Pending(Vec<(Vec<u8>, u8)>),
+    Pending(Vec<(PooledBuffer, u8)>),
     // Protocol connected.
     Connected,
 }
@@ -70,6 +74,8 @@ pub struct Session {
     // Protocol states -- accumulates pending packets until signaled as ready.
     protocol_states: HashMap<ProtocolId, ProtocolState>,
     compression: bool,
+    /// Capture of the subprotocol packets, if running.
+    capture: Option<Arc<PacketCapture>>,
 }
 
 enum State {
@@ -85,7 +91,7 @@ pub enum SessionData {
     /// A packet has been received
     Packet {
         /// Packet data
-        data: Vec<u8>,
+        data: PooledBuffer,
         /// Packet protocol ID
         protocol: ProtocolId,
         /// Zero based packet ID
@@ -93,6 +99,9 @@ pub enum SessionData {
     },
     /// Session has more data to be read
     Continue,
+    /// Handshake of a reachability probe of our own, the ack is sent and the session is to
+    /// be dropped.
+    Probe,
 }
 
 const PACKET_HELLO: u8 = 0x80;
@@ -119,8 +128,8 @@ impl Session {
         Message: Send + Clone + Sync + 'static,
     {
         let originated = id.is_some();
-        let mut handshake =
-            Handshake::new(token, id, socket, nonce).expect("Can't create handshake");
+        let mut handshake = Handshake::new(token, id, socket, nonce, host.buffer_pool.clone())
+            .expect("Can't create handshake");
         let local_addr = handshake.connection.local_addr_str();
         handshake.start(io, host, originated)?;
         Ok(Session {
@@ -136,12 +145,14 @@ impl Session {
                 originated,
                 remote_address: "Handshake".to_owned(),
                 local_address: local_addr,
+                max_payload_size: host.max_payload_size(),
             },
             ping_time: Instant::now(),
             pong_time: None,
             expired: false,
             protocol_states: HashMap::new(),
             compression: false,
+            capture: None,
         })
     }
 
@@ -232,6 +243,14 @@ impl Session {
             return self.read_packet(io, &data, host);
         }
         if create_session {
+            if let State::Handshake(ref mut h) = self.state {
+                if h.id == *host.id() {
+                    // reachability probe of our own, the ack is all it waits for
+                    trace!(target: "network", "{}: Reachability probe handshake complete", h.connection.token);
+                    h.writable(io)?;
+                    return Ok(SessionData::Probe);
+                }
+            }
             self.complete_handshake(io, host)?;
             io.update_registration(self.token())
                 .unwrap_or_else(|e| debug!(target: "network", "Token registration error: {e:?}"));
@@ -325,7 +344,16 @@ impl Session {
             bail!(ErrorKind::BadProtocol);
         }
         if self.expired() {
-            return Err(ErrorKind::Expired.into());
+            return Err(ErrorKind::SessionExpired.into());
+        }
+        if data.len() > self.info.max_payload_size {
+            debug!(target: "network", "Not sending packet {} of {} bytes to {}, limit is {} bytes", packet_id, data.len(), self.token(), self.info.max_payload_size);
+            bail!(ErrorKind::OversizedPacket);
+        }
+        let queued_bytes = self.connection().queued_bytes();
+        if protocol.is_some() && queued_bytes + data.len() > MAX_SEND_QUEUE_BYTES {
+            debug!(target: "network", "Not sending packet {} to {}, {} bytes already queued", packet_id, self.token(), queued_bytes);
+            bail!(ErrorKind::SendQueueFull(queued_bytes));
         }
         let mut i = 0usize;
         let pid = match protocol {
@@ -334,21 +362,25 @@ impl Session {
                     i += 1;
                     if i == self.info.capabilities.len() {
                         debug!(target: "network", "Unknown protocol: {protocol:?}");
-                        return Ok(());
+                        bail!(ErrorKind::ProtocolUnsupported);
                     }
                 }
                 self.info.capabilities[i].id_offset + packet_id
             }
             None => packet_id,
         };
+        if let Some(protocol) = protocol {
+            self.capture_packet(CaptureDirection::Sent, protocol, packet_id, data);
+        }
         let mut rlp = RlpStream::new();
         rlp.append(&(u32::from(pid)));
-        let mut compressed = Vec::new();
+        let mut compressed = PooledBuffer::default();
         let mut payload = data; // create a reference with local lifetime
         if self.compression {
-            if payload.len() > MAX_PAYLOAD_SIZE {
-                bail!(ErrorKind::OversizedPacket);
-            }
+            compressed = self
+                .connection()
+                .pool()
+                .get(snappy::max_compressed_len(payload.len()));
             let len = snappy::compress_into(payload, &mut compressed);
             trace!(target: "network", "compressed {} to {}", payload.len(), len);
             payload = &compressed[0..len];
@@ -385,7 +417,7 @@ impl Session {
 
     /// Signal that a subprotocol has handled the connection successfully and
     /// get all pending packets in order received.
-    pub fn mark_connected(&mut self, protocol: ProtocolId) -> Vec<(ProtocolId, u8, Vec<u8>)> {
+    pub fn mark_connected(&mut self, protocol: ProtocolId) -> Vec<(ProtocolId, u8, PooledBuffer)> {
         match self
             .protocol_states
             .insert(protocol, ProtocolState::Connected)
@@ -402,6 +434,27 @@ impl Session {
         }
     }
 
+    /// Capture all subprotocol packets from now on, replacing a running capture.
+    pub fn set_capture(&mut self, capture: Arc<PacketCapture>) {
+        self.capture = Some(capture);
+    }
+
+    fn capture_packet(
+        &mut self,
+        direction: CaptureDirection,
+        protocol: ProtocolId,
+        packet_id: u8,
+        payload: &[u8],
+    ) {
+        let over = self
+            .capture
+            .as_ref()
+            .is_some_and(|capture| !capture.record(direction, protocol, packet_id, payload));
+        if over {
+            self.capture = None;
+        }
+    }
+
     fn read_packet<Message>(
         &mut self,
         io: &IoContext<Message>,
@@ -420,12 +473,18 @@ impl Session {
         }
         let data = if self.compression {
             let compressed = &packet.data[1..];
-            if snappy::decompressed_len(compressed)? > MAX_PAYLOAD_SIZE {
+            let len = snappy::decompressed_len(compressed)?;
+            if len > MAX_PAYLOAD_SIZE {
                 bail!(ErrorKind::OversizedPacket);
             }
-            snappy::decompress(compressed)?
+            let mut data = self.connection().pool().get(len);
+            let len = snappy::decompress_into(compressed, &mut data)?;
+            data.truncate(len);
+            data
         } else {
-            packet.data[1..].to_owned()
+            let mut data = self.connection().pool().get(packet.data.len() - 1);
+            data.extend_from_slice(&packet.data[1..]);
+            data
         };
         match packet_id {
             PACKET_HELLO => {
@@ -468,6 +527,12 @@ impl Session {
                 // map to protocol
                 let protocol = self.info.capabilities[i].protocol;
                 let protocol_packet_id = packet_id - self.info.capabilities[i].id_offset;
+                self.capture_packet(
+                    CaptureDirection::Received,
+                    protocol,
+                    protocol_packet_id,
+                    &data,
+                );
 
                 match *self
                     .protocol_states
@@ -531,6 +596,14 @@ impl Session {
         let peer_caps: Vec<PeerCapabilityInfo> = rlp.list_at(2)?;
         let id = rlp.val_at::<NodeId>(4)?;
 
+        let first_seen = host
+            .seen_capabilities
+            .lock()
+            .note(&peer_caps, &client_version.to_string());
+        for capability in first_seen {
+            debug!(target: "network", "First seen capability {capability} advertised by {client_version}");
+        }
+
         // Intersect with host capabilities
         // Leave only highset mutually supported capability version
         let mut caps: Vec<SessionCapabilityInfo> = Vec::new();
@@ -578,6 +651,9 @@ impl Session {
         debug!(target: "network", "Hello: {client_version} v{protocol} {id} {caps:?}");
         let protocol = ::std::cmp::min(protocol, host.protocol_version);
         self.info.protocol_version = protocol;
+        if let Some(limit) = client_version.max_payload_size() {
+            self.info.max_payload_size = ::std::cmp::min(self.info.max_payload_size, limit);
+        }
         self.info.client_version = client_version;
         self.info.capabilities = caps;
         self.info.peer_capabilities = peer_caps;
@@ -0,0 +1,281 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Sockets of the host, pinned to a network interface and dialing from a source address
+//! if configured.
+
+use mio::{tcp::*, udp::*};
+use network::{Error, ErrorKind, NetworkConfiguration};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    io,
+    net::{self, IpAddr, SocketAddr},
+};
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Binds sockets to a network interface.
+pub trait BindDevice: Send + Sync {
+    /// Bind `socket` to `interface`, all its traffic goes through that interface.
+    fn bind_device(&self, socket: &Socket, interface: &str) -> io::Result<()>;
+}
+
+/// `SO_BINDTODEVICE` on Linux, nothing elsewhere.
+pub struct SystemBindDevice;
+
+impl BindDevice for SystemBindDevice {
+    #[cfg(target_os = "linux")]
+    fn bind_device(&self, socket: &Socket, interface: &str) -> io::Result<()> {
+        socket.bind_device(Some(interface.as_bytes()))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn bind_device(&self, _socket: &Socket, _interface: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Creates the listener, the outbound connections and the discovery socket of the host.
+pub struct HostSockets {
+    interface: Option<String>,
+    source_address: Option<SocketAddr>,
+    binder: Box<dyn BindDevice>,
+}
+
+impl HostSockets {
+    /// Sockets with the options of `config`.
+    pub fn new(config: &NetworkConfiguration) -> HostSockets {
+        if config.bind_interface.is_some() && !cfg!(target_os = "linux") {
+            warn!(target: "network", "Binding to a network interface is only supported on Linux, the interface setting is ignored");
+        }
+        HostSockets::with_binder(config, Box::new(SystemBindDevice))
+    }
+
+    /// Sockets with the options of `config`, bound to the interface by `binder`.
+    pub fn with_binder(config: &NetworkConfiguration, binder: Box<dyn BindDevice>) -> HostSockets {
+        HostSockets {
+            interface: config.bind_interface.clone(),
+            source_address: config.outbound_source_address,
+            binder,
+        }
+    }
+
+    /// Check that the options can be applied, so that a misconfiguration fails at startup
+    /// rather than with every dial.
+    pub fn check(&self) -> Result<(), Error> {
+        if let Some(source) = self.source_address {
+            let socket = self.socket(source, Type::STREAM, Protocol::TCP)?;
+            socket
+                .bind(&SocketAddr::new(source.ip(), 0).into())
+                .map_err(|e| option_error(format!("outbound source address {source}"), e))?;
+        }
+        Ok(())
+    }
+
+    /// IP address outbound connections are made from, if configured.
+    pub fn source_ip(&self) -> Option<IpAddr> {
+        self.source_address
+            .map(|addr| addr.ip())
+            .filter(|ip| !ip.is_unspecified())
+    }
+
+    /// TCP listener at `address`.
+    pub fn listen(&self, address: &SocketAddr) -> Result<TcpListener, Error> {
+        let socket = self.socket(*address, Type::STREAM, Protocol::TCP)?;
+        if cfg!(unix) {
+            socket.set_reuse_address(true)?;
+        }
+        socket.bind(&(*address).into())?;
+        socket.listen(LISTEN_BACKLOG)?;
+        Ok(TcpListener::from_std(net::TcpListener::from(socket))?)
+    }
+
+    /// Start a connection to `address`. The connection completes in the background.
+    pub fn connect(&self, address: &SocketAddr) -> Result<TcpStream, Error> {
+        let socket = self.socket(*address, Type::STREAM, Protocol::TCP)?;
+        if let Some(source) = self
+            .source_address
+            .filter(|s| s.is_ipv4() == address.is_ipv4())
+        {
+            // Only the address is pinned, every dial needs its own ephemeral port.
+            socket.bind(&SocketAddr::new(source.ip(), 0).into())?;
+        }
+        socket.set_nonblocking(true)?;
+        match socket.connect(&(*address).into()) {
+            Ok(()) => {}
+            Err(ref e) if is_in_progress(e) => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(TcpStream::from_stream(net::TcpStream::from(socket))?)
+    }
+
+    /// UDP socket at `address`. An unspecified address is replaced by the source address
+    /// of the same family, the discovery packets go out from it like the connections.
+    pub fn bind_udp(&self, address: &SocketAddr) -> Result<UdpSocket, Error> {
+        let address = self.udp_address(*address);
+        let socket = self.socket(address, Type::DGRAM, Protocol::UDP)?;
+        socket.bind(&address.into())?;
+        Ok(UdpSocket::from_socket(net::UdpSocket::from(socket))?)
+    }
+
+    fn udp_address(&self, address: SocketAddr) -> SocketAddr {
+        match self.source_address {
+            Some(source)
+                if address.ip().is_unspecified() && source.is_ipv4() == address.is_ipv4() =>
+            {
+                SocketAddr::new(source.ip(), address.port())
+            }
+            _ => address,
+        }
+    }
+
+    fn socket(&self, address: SocketAddr, ty: Type, protocol: Protocol) -> Result<Socket, Error> {
+        let socket = Socket::new(Domain::for_address(address), ty, Some(protocol))?;
+        if let Some(ref interface) = self.interface {
+            self.binder.bind_device(&socket, interface).map_err(|e| {
+                let option = match e.kind() {
+                    io::ErrorKind::PermissionDenied => format!(
+                        "network interface {interface} (SO_BINDTODEVICE requires CAP_NET_RAW)"
+                    ),
+                    _ => format!("network interface {interface}"),
+                };
+                option_error(option, e)
+            })?;
+        }
+        Ok(socket)
+    }
+}
+
+fn option_error(option: String, err: io::Error) -> Error {
+    ErrorKind::SocketOption(option, err).into()
+}
+
+fn is_in_progress(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        if err.raw_os_error() == Some(::libc::EINPROGRESS) {
+            return true;
+        }
+    }
+    err.kind() == io::ErrorKind::WouldBlock
+}
+
+/// Records the interfaces sockets are bound to instead of binding them. Clones share the record.
+#[cfg(any(test, feature = "test-helpers"))]
+#[derive(Default, Clone)]
+pub struct RecordingBindDevice {
+    /// Interfaces passed to `bind_device`, in order.
+    pub interfaces: ::std::sync::Arc<::parking_lot::Mutex<Vec<String>>>,
+    /// Error returned by every call, if any.
+    pub error: Option<io::ErrorKind>,
+}
+
+#[cfg(any(test, feature = "test-helpers"))]
+impl BindDevice for RecordingBindDevice {
+    fn bind_device(&self, _socket: &Socket, interface: &str) -> io::Result<()> {
+        self.interfaces.lock().push(interface.to_owned());
+        match self.error {
+            Some(kind) => Err(kind.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host_sockets(
+        interface: Option<&str>,
+        source: Option<&str>,
+        error: Option<io::ErrorKind>,
+    ) -> (HostSockets, RecordingBindDevice) {
+        let mut config = NetworkConfiguration::new_local();
+        config.bind_interface = interface.map(Into::into);
+        config.outbound_source_address = source.map(|s| s.parse().unwrap());
+        let binder = RecordingBindDevice {
+            error,
+            ..Default::default()
+        };
+        (
+            HostSockets::with_binder(&config, Box::new(binder.clone())),
+            binder,
+        )
+    }
+
+    #[test]
+    fn binds_every_socket_to_the_interface() {
+        let (sockets, binder) = host_sockets(Some("eth1"), None, None);
+        let listener = sockets.listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        sockets.connect(&listener.local_addr().unwrap()).unwrap();
+        sockets.bind_udp(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        assert_eq!(*binder.interfaces.lock(), vec!["eth1"; 3]);
+    }
+
+    #[test]
+    fn sockets_are_not_bound_without_an_interface() {
+        let (sockets, binder) = host_sockets(None, None, None);
+        sockets.listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        assert!(binder.interfaces.lock().is_empty());
+    }
+
+    #[test]
+    fn interface_errors_are_reported() {
+        let (sockets, _) = host_sockets(Some("eth1"), None, Some(io::ErrorKind::PermissionDenied));
+        let err = sockets.listen(&"127.0.0.1:0".parse().unwrap()).unwrap_err();
+        assert_matches!(err.kind(), ErrorKind::SocketOption(..));
+        assert!(err.to_string().contains("CAP_NET_RAW"));
+    }
+
+    #[test]
+    fn unusable_source_address_fails_the_check() {
+        let (sockets, _) = host_sockets(None, Some("127.0.0.1:0"), None);
+        assert!(sockets.check().is_ok());
+        // documentation range, not assigned to a local interface
+        let (sockets, _) = host_sockets(None, Some("192.0.2.1:0"), None);
+        assert_matches!(
+            sockets.check().unwrap_err().kind(),
+            ErrorKind::SocketOption(..)
+        );
+    }
+
+    #[test]
+    fn every_dial_gets_its_own_port_from_the_source_address() {
+        let (sockets, _) = host_sockets(None, Some("127.0.0.1:40404"), None);
+        let listener = sockets.listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let target = listener.local_addr().unwrap();
+        let first = sockets.connect(&target).unwrap();
+        let second = sockets.connect(&target).unwrap();
+        assert_ne!(
+            first.local_addr().unwrap().port(),
+            second.local_addr().unwrap().port()
+        );
+    }
+
+    #[test]
+    fn discovery_uses_the_source_address() {
+        let (sockets, _) = host_sockets(None, Some("127.0.0.1:0"), None);
+        assert_eq!(
+            sockets.udp_address("0.0.0.0:30303".parse().unwrap()),
+            "127.0.0.1:30303".parse().unwrap()
+        );
+        assert_eq!(
+            sockets.udp_address("[::]:30303".parse().unwrap()),
+            "[::]:30303".parse().unwrap()
+        );
+        assert_eq!(sockets.source_ip(), Some("127.0.0.1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,323 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! In-process network of connected nodes for integration tests.
+//!
+//! Every node runs a full `NetworkService` on the loopback interface with a single test
+//! protocol registered. All nodes are reserved peers of each other, so the network is
+//! a full mesh once `TestNet::new` returns.
+
+use crypto::publickey::{Generator, Random};
+use ethereum_types::U64;
+use network::{
+    NetworkConfiguration, NetworkContext, NetworkProtocolHandler, PacketId, PeerId, ProtocolId,
+};
+use node_table::NodeId;
+use parking_lot::{Mutex, RwLock};
+use service::NetworkService;
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Protocol registered by every test node.
+pub const TEST_PROTOCOL: ProtocolId = U64([0x0074_6e65]);
+/// Version and packet count of the test protocol.
+pub const TEST_PROTOCOL_VERSIONS: &[(u8, u8)] = &[(1u8, 16u8)];
+/// Default timeout of the waiting helpers.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+type ConnectedFn = Box<dyn Fn(&dyn NetworkContext, PeerId) + Send + Sync>;
+type ReadFn = Box<dyn Fn(&dyn NetworkContext, PeerId, PacketId, &[u8]) + Send + Sync>;
+
+/// Event observed by the protocol handler of a test node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TestEvent {
+    /// Peer connected.
+    Connected(PeerId),
+    /// Packet received from peer.
+    Packet {
+        peer: PeerId,
+        packet_id: PacketId,
+        data: Vec<u8>,
+    },
+    /// Peer disconnected.
+    Disconnected(PeerId),
+}
+
+/// Protocol handler of a test node. Records all events and passes them on to the
+/// registered closures.
+#[derive(Default)]
+pub struct TestHandler {
+    peers: Mutex<HashSet<PeerId>>,
+    events: Mutex<Vec<TestEvent>>,
+    on_connected: RwLock<Option<ConnectedFn>>,
+    on_read: RwLock<Option<ReadFn>>,
+}
+
+impl TestHandler {
+    /// Call `f` for every newly connected peer.
+    pub fn on_connected<F>(&self, f: F)
+    where
+        F: Fn(&dyn NetworkContext, PeerId) + Send + Sync + 'static,
+    {
+        *self.on_connected.write() = Some(Box::new(f));
+    }
+
+    /// Call `f` for every received packet.
+    pub fn on_read<F>(&self, f: F)
+    where
+        F: Fn(&dyn NetworkContext, PeerId, PacketId, &[u8]) + Send + Sync + 'static,
+    {
+        *self.on_read.write() = Some(Box::new(f));
+    }
+
+    /// Currently connected peers.
+    pub fn peers(&self) -> HashSet<PeerId> {
+        self.peers.lock().clone()
+    }
+
+    /// All events observed so far.
+    pub fn events(&self) -> Vec<TestEvent> {
+        self.events.lock().clone()
+    }
+
+    /// Check if a packet with given id and payload was received from any peer.
+    pub fn got_packet(&self, packet_id: PacketId, data: &[u8]) -> bool {
+        self.events.lock().iter().any(|e| match *e {
+            TestEvent::Packet {
+                packet_id: id,
+                data: ref d,
+                ..
+            } => id == packet_id && &d[..] == data,
+            _ => false,
+        })
+    }
+
+    /// Check if given peer disconnected at any time.
+    pub fn got_disconnect(&self, peer: PeerId) -> bool {
+        self.events.lock().contains(&TestEvent::Disconnected(peer))
+    }
+}
+
+impl NetworkProtocolHandler for TestHandler {
+    fn read(&self, io: &dyn NetworkContext, peer: &PeerId, packet_id: u8, data: &[u8]) {
+        self.events.lock().push(TestEvent::Packet {
+            peer: *peer,
+            packet_id,
+            data: data.to_vec(),
+        });
+        if let Some(ref f) = *self.on_read.read() {
+            f(io, *peer, packet_id, data);
+        }
+    }
+
+    fn connected(&self, io: &dyn NetworkContext, peer: &PeerId) {
+        self.peers.lock().insert(*peer);
+        self.events.lock().push(TestEvent::Connected(*peer));
+        if let Some(ref f) = *self.on_connected.read() {
+            f(io, *peer);
+        }
+    }
+
+    fn disconnected(&self, _io: &dyn NetworkContext, peer: &PeerId) {
+        self.peers.lock().remove(peer);
+        self.events.lock().push(TestEvent::Disconnected(*peer));
+    }
+}
+
+/// A node of the test network.
+pub struct TestNode {
+    /// Network service of the node.
+    pub service: NetworkService,
+    /// Handler of the test protocol.
+    pub handler: Arc<TestHandler>,
+    /// Public key of the node.
+    pub id: NodeId,
+}
+
+impl TestNode {
+    /// Id of the session with given remote node, if connected.
+    pub fn peer_id(&self, remote: &NodeId) -> Option<PeerId> {
+        let peers = self.handler.peers();
+        self.service
+            .with_context_eval(TEST_PROTOCOL, |io| {
+                peers
+                    .into_iter()
+                    .find(|peer| io.session_info(*peer).and_then(|s| s.id) == Some(*remote))
+            })
+            .flatten()
+    }
+}
+
+/// Network of in-process nodes connected to each other.
+pub struct TestNet {
+    nodes: Vec<TestNode>,
+}
+
+impl TestNet {
+    /// Start `n` nodes and wait until all of them are connected to each other.
+    /// `config_fn` may adjust the local configuration of each node before it is started.
+    ///
+    /// Panics if the nodes fail to start or to connect within `DEFAULT_TIMEOUT`.
+    pub fn new<F>(n: usize, config_fn: F) -> TestNet
+    where
+        F: Fn(usize, &mut NetworkConfiguration),
+    {
+        TestNet::with_handlers(n, config_fn, |_, _| {})
+    }
+
+    /// Same as `new`, `handler_fn` may install closures on the handler of each node
+    /// before any peer connects.
+    pub fn with_handlers<F, H>(n: usize, config_fn: F, handler_fn: H) -> TestNet
+    where
+        F: Fn(usize, &mut NetworkConfiguration),
+        H: Fn(usize, &TestHandler),
+    {
+        let mut nodes = Vec::with_capacity(n);
+        for i in 0..n {
+            let key = Random.generate();
+            let mut config = NetworkConfiguration::new_local();
+            config.use_secret = Some(key.secret().clone());
+            config.boot_nodes = vec![];
+            config.discovery_enabled = false;
+            config_fn(i, &mut config);
+
+            let service = NetworkService::new(config, None).expect("Error creating test node");
+            service
+                .start()
+                .unwrap_or_else(|(e, _)| panic!("Error starting test node: {e}"));
+            let handler = Arc::new(TestHandler::default());
+            handler_fn(i, &handler);
+            service
+                .register_protocol(handler.clone(), TEST_PROTOCOL, TEST_PROTOCOL_VERSIONS)
+                .expect("Error registering test protocol");
+            nodes.push(TestNode {
+                service,
+                handler,
+                id: *key.public(),
+            });
+        }
+
+        let urls: Vec<String> = nodes
+            .iter()
+            .map(|node| node.service.local_url().expect("test node is started"))
+            .collect();
+        for (i, node) in nodes.iter().enumerate() {
+            for url in urls.iter().skip(i + 1) {
+                node.service
+                    .add_reserved_peer(url)
+                    .expect("local url is valid");
+            }
+        }
+
+        let net = TestNet { nodes };
+        assert!(
+            net.wait_for_mesh(DEFAULT_TIMEOUT),
+            "Test network of {n} nodes failed to connect"
+        );
+        net
+    }
+
+    /// Number of nodes.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Check if the network has no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Get node by index.
+    pub fn node(&self, i: usize) -> &TestNode {
+        &self.nodes[i]
+    }
+
+    /// Id of the session of node `from` with node `to`.
+    pub fn peer_id(&self, from: usize, to: usize) -> Option<PeerId> {
+        self.nodes[from].peer_id(&self.nodes[to].id)
+    }
+
+    /// Send a test protocol packet from node `from` to node `to`.
+    pub fn send(&self, from: usize, to: usize, packet_id: PacketId, data: &[u8]) {
+        let peer = self
+            .peer_id(from, to)
+            .unwrap_or_else(|| panic!("Node {from} is not connected to node {to}"));
+        self.nodes[from].service.with_context(TEST_PROTOCOL, |io| {
+            io.send(peer, packet_id, data.to_vec())
+                .expect("Error sending test packet")
+        });
+    }
+
+    /// Disconnect node `to` from node `from`.
+    pub fn disconnect(&self, from: usize, to: usize) {
+        if let Some(peer) = self.peer_id(from, to) {
+            self.nodes[from]
+                .service
+                .with_context(TEST_PROTOCOL, |io| io.disconnect_peer(peer));
+        }
+    }
+
+    /// Wait until every node is connected to all other nodes.
+    pub fn wait_for_mesh(&self, timeout: Duration) -> bool {
+        let others = self.nodes.len().saturating_sub(1);
+        wait_for(timeout, || {
+            self.nodes
+                .iter()
+                .all(|node| node.handler.peers().len() >= others)
+        })
+    }
+
+    /// Wait until node `i` received a packet with given id and payload.
+    pub fn wait_for_packet(
+        &self,
+        i: usize,
+        packet_id: PacketId,
+        data: &[u8],
+        timeout: Duration,
+    ) -> bool {
+        wait_for(timeout, || {
+            self.nodes[i].handler.got_packet(packet_id, data)
+        })
+    }
+}
+
+impl Drop for TestNet {
+    fn drop(&mut self) {
+        for node in &self.nodes {
+            node.service.stop();
+        }
+    }
+}
+
+/// Poll `condition` until it holds or `timeout` passes.
+pub fn wait_for<F: Fn() -> bool>(timeout: Duration, condition: F) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if condition() {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
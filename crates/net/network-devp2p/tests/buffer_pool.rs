@@ -0,0 +1,110 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+extern crate ethcore_network_devp2p;
+
+use ethcore_network_devp2p::BufferPool;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    hint::black_box,
+    thread,
+};
+
+const PACKETS: usize = 10_000;
+const PACKET_SIZE: usize = 1500;
+
+// Counts allocations made by the current thread, so tests running in parallel
+// do not disturb each other.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|a| a.set(a.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations<F: FnOnce()>(f: F) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn pooled_buffers_avoid_allocations_per_packet() {
+    let unpooled = allocations(|| {
+        for i in 0..PACKETS {
+            let mut packet = vec![0u8; PACKET_SIZE];
+            packet[0] = i as u8;
+            black_box(&packet);
+        }
+    });
+
+    let pool = BufferPool::new(4, 1024 * 1024);
+    let pooled = allocations(|| {
+        for i in 0..PACKETS {
+            let mut packet = pool.get(PACKET_SIZE);
+            packet.resize(PACKET_SIZE, 0);
+            packet[0] = i as u8;
+            black_box(&packet);
+        }
+    });
+
+    assert!(unpooled >= PACKETS, "{} allocations without pool", unpooled);
+    assert!(pooled <= 2, "{} allocations with pool", pooled);
+    let stats = pool.stats();
+    assert_eq!(stats.misses, 1);
+    assert_eq!(stats.hits, PACKETS as u64 - 1);
+}
+
+#[test]
+fn concurrent_users_never_share_a_buffer() {
+    let pool = BufferPool::new(4, 1024 * 1024);
+    let threads: Vec<_> = (0..8u8)
+        .map(|id| {
+            let pool = pool.clone();
+            thread::spawn(move || {
+                for round in 0..1000usize {
+                    let size = if round % 2 == 0 { 100 } else { 5000 };
+                    let mut buf = pool.get(size);
+                    assert!(buf.is_empty());
+                    buf.resize(size, id);
+                    thread::yield_now();
+                    assert!(buf.iter().all(|&b| b == id), "buffer aliased");
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let stats = pool.stats();
+    assert_eq!(stats.hits + stats.misses, 8 * 1000);
+    assert!(stats.resident_bytes <= 1024 * 1024);
+}
@@ -20,17 +20,24 @@ extern crate ethcore_network;
 extern crate ethcore_network_devp2p;
 extern crate ethereum_types;
 extern crate parity_bytes;
-extern crate parity_crypto as crypto;
 extern crate parking_lot;
+extern crate tempfile;
 
-use crypto::publickey::{Generator, Random};
 use ethcore_network::*;
-use ethcore_network_devp2p::NetworkService;
+use ethcore_network_devp2p::{
+    test_net::{
+        wait_for, TestHandler, TestNet, DEFAULT_TIMEOUT, TEST_PROTOCOL, TEST_PROTOCOL_VERSIONS,
+    },
+    CaptureDirection, CaptureRecord, NetworkService, Reachability,
+};
 use ethereum_types::U64;
 use io::TimerToken;
 use parity_bytes::Bytes;
 use parking_lot::Mutex;
 use std::{
+    collections::HashSet,
+    fs::File,
+    net::{SocketAddr, TcpListener},
     sync::{
         atomic::{AtomicBool, Ordering as AtomicOrdering},
         Arc,
@@ -111,6 +118,33 @@ impl NetworkProtocolHandler for TestProtocol {
     }
 }
 
+/// Protocol registering its timers with host allocated tokens.
+#[derive(Default)]
+struct AutoTimerProtocol {
+    registered: Mutex<Vec<TimerToken>>,
+    fired: Mutex<HashSet<TimerToken>>,
+}
+
+impl NetworkProtocolHandler for AutoTimerProtocol {
+    fn initialize(&self, io: &dyn NetworkContext) {
+        for _ in 0..3 {
+            let token = io.register_timer_auto(Duration::from_millis(10)).unwrap();
+            self.registered.lock().push(token);
+        }
+    }
+
+    fn read(&self, _io: &dyn NetworkContext, _peer: &PeerId, _packet_id: u8, _data: &[u8]) {}
+
+    fn connected(&self, _io: &dyn NetworkContext, _peer: &PeerId) {}
+
+    fn disconnected(&self, _io: &dyn NetworkContext, _peer: &PeerId) {}
+
+    fn timeout(&self, _io: &dyn NetworkContext, timer: TimerToken) {
+        assert!(self.registered.lock().contains(&timer));
+        self.fired.lock().insert(timer);
+    }
+}
+
 #[test]
 fn net_service() {
     let service = NetworkService::new(NetworkConfiguration::new_local(), None)
@@ -134,25 +168,112 @@ fn net_start_stop() {
     service.start().unwrap();
 }
 
+#[test]
+fn net_connect() {
+    let net = TestNet::with_handlers(
+        2,
+        |_, _| {},
+        |_, handler| {
+            handler.on_connected(|io, peer| {
+                assert!(io.peer_client_version(peer).to_string().contains("Parity"));
+                io.respond(33, b"hello".to_vec()).unwrap();
+            })
+        },
+    );
+    assert!(net.wait_for_packet(0, 33, b"hello", DEFAULT_TIMEOUT));
+    assert!(net.wait_for_packet(1, 33, b"hello", DEFAULT_TIMEOUT));
+    assert_eq!(net.node(0).service.connected_peers().len(), 1);
+    assert_eq!(net.node(1).service.connected_peers().len(), 1);
+}
+
 #[test]
 fn net_disconnect() {
-    let key1 = Random.generate();
-    let mut config1 = NetworkConfiguration::new_local();
-    config1.use_secret = Some(key1.secret().clone());
-    config1.boot_nodes = vec![];
-    let mut service1 = NetworkService::new(config1, None).unwrap();
-    service1.start().unwrap();
-    let handler1 = TestProtocol::register(&mut service1, false);
-    let mut config2 = NetworkConfiguration::new_local();
-    config2.boot_nodes = vec![service1.local_url().unwrap()];
-    let mut service2 = NetworkService::new(config2, None).unwrap();
-    service2.start().unwrap();
-    let handler2 = TestProtocol::register(&mut service2, true);
-    while !(handler1.got_disconnect() && handler2.got_disconnect()) {
-        thread::sleep(Duration::from_millis(50));
+    let net = TestNet::new(2, |_, _| {});
+    let peer0 = net.peer_id(1, 0).unwrap();
+    let peer1 = net.peer_id(0, 1).unwrap();
+    net.disconnect(1, 0);
+    assert!(wait_for(DEFAULT_TIMEOUT, || {
+        net.node(0).handler.got_disconnect(peer1) && net.node(1).handler.got_disconnect(peer0)
+    }));
+}
+
+#[test]
+fn net_three_nodes() {
+    let net = TestNet::with_handlers(
+        3,
+        |_, _| {},
+        |_, handler| {
+            handler.on_read(|io, _peer, packet_id, data| {
+                if packet_id == 1 {
+                    let mut reply = data.to_vec();
+                    reply.reverse();
+                    io.respond(2, reply).unwrap();
+                }
+            })
+        },
+    );
+    assert_eq!(net.len(), 3);
+    for i in 0..3 {
+        assert_eq!(net.node(i).handler.peers().len(), 2);
     }
-    assert!(handler1.got_disconnect());
-    assert!(handler2.got_disconnect());
+
+    net.send(0, 1, 1, b"ping");
+    assert!(net.wait_for_packet(1, 1, b"ping", DEFAULT_TIMEOUT));
+    assert!(net.wait_for_packet(0, 2, b"gnip", DEFAULT_TIMEOUT));
+    assert!(!net.node(2).handler.got_packet(1, b"ping"));
+
+    let peer0 = net.peer_id(2, 0).unwrap();
+    let peer2 = net.peer_id(0, 2).unwrap();
+    net.disconnect(0, 2);
+    assert!(wait_for(DEFAULT_TIMEOUT, || {
+        net.node(0).handler.got_disconnect(peer2) && net.node(2).handler.got_disconnect(peer0)
+    }));
+    assert_eq!(net.node(1).handler.peers().len(), 2);
+
+    net.send(2, 1, 1, b"pong");
+    assert!(net.wait_for_packet(2, 2, b"gnop", DEFAULT_TIMEOUT));
+}
+
+#[test]
+fn net_send_over_payload_limit() {
+    let net = TestNet::new(2, |i, config| {
+        if i == 0 {
+            config.max_payload_size = 1024;
+        }
+    });
+    let peer = net.peer_id(0, 1).unwrap();
+    let send = |data: Vec<u8>| {
+        net.node(0)
+            .service
+            .with_context_eval(TEST_PROTOCOL, |io| io.send(peer, 1, data))
+            .expect("test node is started")
+    };
+
+    let err = send(vec![1u8; 1025]).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::OversizedPacket));
+    let session = net
+        .node(0)
+        .service
+        .with_context_eval(TEST_PROTOCOL, |io| io.session_info(peer))
+        .unwrap()
+        .unwrap();
+    assert_eq!(session.max_payload_size, 1024);
+
+    send(vec![2u8; 1024]).unwrap();
+    assert!(net.wait_for_packet(1, 1, &[2u8; 1024], DEFAULT_TIMEOUT));
+    assert!(!net.node(1).handler.got_packet(1, &[1u8; 1025]));
+}
+
+#[test]
+fn net_send_to_gone_peer() {
+    let net = TestNet::new(2, |_, _| {});
+    let err = net
+        .node(0)
+        .service
+        .with_context_eval(TEST_PROTOCOL, |io| io.send(1000, 1, b"ping".to_vec()))
+        .expect("test node is started")
+        .unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::PeerGone));
 }
 
 #[test]
@@ -165,3 +286,305 @@ fn net_timeout() {
         thread::sleep(Duration::from_millis(50));
     }
 }
+
+#[test]
+fn net_auto_timer_tokens() {
+    let service = NetworkService::new(NetworkConfiguration::new_local(), None).unwrap();
+    service.start().unwrap();
+    let handlers = [
+        Arc::new(AutoTimerProtocol::default()),
+        Arc::new(AutoTimerProtocol::default()),
+    ];
+    for (handler, protocol) in handlers.iter().zip(&[0x000aaa00u64, 0x000aff00]) {
+        service
+            .register_protocol(handler.clone(), U64::from(*protocol), &[(1u8, 1u8)])
+            .unwrap();
+    }
+
+    assert!(wait_for(DEFAULT_TIMEOUT, || {
+        handlers.iter().all(|h| h.registered.lock().len() == 3)
+    }));
+    let tokens: HashSet<_> = handlers
+        .iter()
+        .flat_map(|h| h.registered.lock().clone())
+        .collect();
+    assert_eq!(tokens.len(), 6);
+    assert!(wait_for(DEFAULT_TIMEOUT, || {
+        handlers
+            .iter()
+            .all(|h| *h.fired.lock() == h.registered.lock().iter().cloned().collect())
+    }));
+}
+
+#[test]
+fn net_duplicate_timer_token() {
+    let config = NetworkConfiguration::new_local();
+    let mut service = NetworkService::new(config, None).unwrap();
+    service.start().unwrap();
+    let handler = TestProtocol::register(&mut service, false);
+    // timer 0 is registered once the handler is initialized
+    assert!(wait_for(DEFAULT_TIMEOUT, || handler.got_timeout()));
+
+    let err = service
+        .with_context_eval(U64::from(0x000aaa00), |io| {
+            io.register_timer(0, Duration::from_millis(10))
+        })
+        .expect("test node is started")
+        .unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::TimerTokenInUse(0)));
+}
+
+#[test]
+fn net_instances_are_isolated() {
+    fn start(label: &str) -> (TestNet, Arc<Mutex<HashSet<String>>>) {
+        let threads = Arc::new(Mutex::new(HashSet::new()));
+        let net = TestNet::with_handlers(
+            2,
+            |_, config| config.instance_label = label.into(),
+            |_, handler| {
+                let threads = threads.clone();
+                handler.on_connected(move |_, _| {
+                    let name = thread::current().name().unwrap_or_default().to_owned();
+                    threads.lock().insert(name);
+                })
+            },
+        );
+        (net, threads)
+    }
+
+    let (alpha, alpha_threads) = start("alpha");
+    let (beta, beta_threads) = start("beta");
+
+    for (net, other) in [(&alpha, &beta), (&beta, &alpha)] {
+        for i in 0..2 {
+            assert_eq!(net.node(i).handler.peers().len(), 1);
+            assert!(net.peer_id(i, 1 - i).is_some());
+            for j in 0..2 {
+                assert_eq!(net.node(i).peer_id(&other.node(j).id), None);
+            }
+        }
+    }
+    assert!(alpha_threads
+        .lock()
+        .iter()
+        .all(|name| name.starts_with("Worker alpha-devp2p")));
+    assert!(beta_threads
+        .lock()
+        .iter()
+        .all(|name| name.starts_with("Worker beta-devp2p")));
+
+    // stopping one instance leaves the other running
+    drop(beta);
+    alpha.send(0, 1, 1, b"ping");
+    assert!(alpha.wait_for_packet(1, 1, b"ping", DEFAULT_TIMEOUT));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn net_dials_from_the_source_address() {
+    use ethcore_network_devp2p::HostSockets;
+    use std::{io, net::TcpListener};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut config = NetworkConfiguration::new_local();
+    // all of 127/8 is local on Linux
+    config.outbound_source_address = Some("127.0.0.2:0".parse().unwrap());
+    config.bind_interface = Some("lo".into());
+    let sockets = HostSockets::new(&config);
+    let _stream = match sockets.connect(&listener.local_addr().unwrap()) {
+        Ok(stream) => stream,
+        Err(Error(ErrorKind::SocketOption(_, ref e), _))
+            if e.kind() == io::ErrorKind::PermissionDenied =>
+        {
+            // binding to an interface needs CAP_NET_RAW before Linux 5.7
+            return;
+        }
+        Err(e) => panic!("Can't connect: {e}"),
+    };
+    let (_, peer) = listener.accept().unwrap();
+    assert_eq!(peer.ip(), config.outbound_source_address.unwrap().ip());
+}
+
+// Local address of a port nothing listens on.
+fn closed_port() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+#[test]
+fn net_reachability_probe() {
+    // the probe dials the public address, it must be the one listened at
+    let address = closed_port();
+    let net = TestNet::new(1, |_, config| {
+        config.listen_address = Some(address);
+        config.public_address = Some(address);
+    });
+    let node = net.node(0);
+    assert!(wait_for(DEFAULT_TIMEOUT, || node
+        .service
+        .external_url()
+        .is_some()));
+
+    let probe = node.service.probe_reachability().unwrap();
+    assert_eq!(probe.result, Reachability::Reachable);
+    assert_eq!(node.service.reachability(), Some(probe));
+    assert_eq!(node.service.stats().reachable_probes, 1);
+    // the session of the probe is dropped without ever becoming a peer
+    assert!(wait_for(DEFAULT_TIMEOUT, || node
+        .service
+        .connected_peers()
+        .is_empty()));
+    assert!(node.handler.events().is_empty());
+}
+
+#[test]
+fn net_reachability_probe_of_closed_port() {
+    let net = TestNet::new(1, |_, config| {
+        config.public_address = Some(closed_port());
+    });
+    let node = net.node(0);
+    assert!(wait_for(DEFAULT_TIMEOUT, || node
+        .service
+        .external_url()
+        .is_some()));
+    let probe = node.service.probe_reachability().unwrap();
+    assert_eq!(probe.result, Reachability::Refused);
+    assert_eq!(node.service.stats().unreachable_probes, 1);
+}
+
+#[test]
+fn net_packet_capture() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("captures").join("capture");
+    let net = TestNet::new(2, |i, config| {
+        if i == 0 {
+            config.max_packet_captures = 1;
+            config.net_config_path = Some(dir.path().to_str().unwrap().to_owned());
+        }
+    });
+    let peer = net.peer_id(0, 1).unwrap();
+    let capture = net
+        .node(0)
+        .service
+        .start_capture(peer, 1024, "capture")
+        .unwrap();
+    // capturing is disabled on node 1
+    let err = net
+        .node(1)
+        .service
+        .start_capture(net.peer_id(1, 0).unwrap(), 1024, "capture")
+        .map(|_| ())
+        .unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::TooManyCaptures(0)));
+
+    net.send(0, 1, 1, b"request");
+    net.send(0, 1, 2, &[0x80]);
+    assert!(net.wait_for_packet(1, 2, &[0x80], DEFAULT_TIMEOUT));
+    net.send(1, 0, 3, b"response");
+    assert!(net.wait_for_packet(0, 3, b"response", DEFAULT_TIMEOUT));
+    drop(capture);
+    // not captured anymore
+    net.send(0, 1, 4, b"after");
+    assert!(net.wait_for_packet(1, 4, b"after", DEFAULT_TIMEOUT));
+
+    let records = CaptureRecord::read_all(File::open(&path).unwrap()).unwrap();
+    let packets: Vec<_> = records
+        .iter()
+        .map(|r| (r.direction, r.protocol, r.packet_id, &r.payload[..]))
+        .collect();
+    assert_eq!(
+        packets,
+        vec![
+            (CaptureDirection::Sent, TEST_PROTOCOL, 1, &b"request"[..]),
+            (CaptureDirection::Sent, TEST_PROTOCOL, 2, &[0x80][..]),
+            (
+                CaptureDirection::Received,
+                TEST_PROTOCOL,
+                3,
+                &b"response"[..]
+            ),
+        ]
+    );
+    assert!(records.windows(2).all(|r| r[0].timestamp <= r[1].timestamp));
+}
+
+#[test]
+fn net_repeatedly_disabled_peer_is_refused_after_restart() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let start = || {
+        let mut config = NetworkConfiguration::new_local();
+        config.net_config_path = Some(dir.path().to_str().unwrap().to_owned());
+        config.boot_nodes = vec![];
+        config.discovery_enabled = false;
+        let service = NetworkService::new(config, None).unwrap();
+        service
+            .start()
+            .unwrap_or_else(|(e, _)| panic!("Error starting node: {e}"));
+        let handler = Arc::new(TestHandler::default());
+        service
+            .register_protocol(handler.clone(), TEST_PROTOCOL, TEST_PROTOCOL_VERSIONS)
+            .unwrap();
+        (service, handler)
+    };
+    // the other node dials the tested one, all connections are inbound
+    let net = TestNet::new(1, |_, _| {});
+    let dialer = net.node(0);
+
+    let (service, handler) = start();
+    dialer
+        .service
+        .add_reserved_peer(&service.local_url().unwrap())
+        .unwrap();
+    // the third disable bans the node
+    for _ in 0..3 {
+        assert!(wait_for(DEFAULT_TIMEOUT, || !handler.peers().is_empty()));
+        let peer = handler.peers().into_iter().next().unwrap();
+        service.with_context(TEST_PROTOCOL, |io| io.disable_peer(peer));
+        assert!(wait_for(DEFAULT_TIMEOUT, || handler.peers().is_empty()));
+    }
+    let banned = service.banned_peers();
+    assert_eq!(banned.len(), 1);
+    assert_eq!(banned[0].id, dialer.id);
+    service.stop();
+    drop(service);
+
+    let (service, handler) = start();
+    assert_eq!(service.banned_peers()[0].id, dialer.id);
+    dialer
+        .service
+        .add_reserved_peer(&service.local_url().unwrap())
+        .unwrap();
+    assert!(!wait_for(Duration::from_secs(5), || !handler
+        .peers()
+        .is_empty()));
+
+    assert!(service.unban(&dialer.id));
+    assert!(wait_for(DEFAULT_TIMEOUT, || !handler.peers().is_empty()));
+}
+
+#[test]
+fn net_packet_capture_ends_with_session() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let net = TestNet::new(2, |i, config| {
+        if i == 0 {
+            config.max_packet_captures = 1;
+            config.net_config_path = Some(dir.path().to_str().unwrap().to_owned());
+        }
+    });
+    let node = net.node(0);
+    let capture = node
+        .service
+        .start_capture(net.peer_id(0, 1).unwrap(), 1024, "first")
+        .unwrap();
+
+    net.disconnect(0, 1);
+    assert!(wait_for(DEFAULT_TIMEOUT, || !capture.is_active()));
+    // the slot is free again once the peer reconnected
+    assert!(net.wait_for_mesh(DEFAULT_TIMEOUT));
+    assert!(node
+        .service
+        .start_capture(net.peer_id(0, 1).unwrap(), 1024, "second")
+        .is_ok());
+}
@@ -0,0 +1,338 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Splits payloads exceeding the packet size limit of a session into several parts and
+//! reassembles them on the receiving side.
+//!
+//! Every part starts with a continuation header: the big endian sequence number of the part
+//! followed by a flags byte, which is `LAST_PART` for the final part of a payload.
+
+use error::{Error, ErrorKind};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// Length of the continuation header of every part.
+pub const PART_HEADER_LEN: usize = 3;
+
+const LAST_PART: u8 = 0x01;
+const MAX_PARTS: usize = u16::MAX as usize + 1;
+
+/// Split `payload` into parts of at most `max_part_size` bytes, headers included.
+pub fn split(payload: &[u8], max_part_size: usize) -> Result<Vec<Vec<u8>>, Error> {
+    if max_part_size <= PART_HEADER_LEN {
+        bail!(ErrorKind::OversizedPacket);
+    }
+    let chunks: Vec<&[u8]> = match payload.is_empty() {
+        true => vec![payload],
+        false => payload.chunks(max_part_size - PART_HEADER_LEN).collect(),
+    };
+    if chunks.len() > MAX_PARTS {
+        bail!(ErrorKind::OversizedPacket);
+    }
+    let last = chunks.len() - 1;
+    Ok(chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut part = Vec::with_capacity(PART_HEADER_LEN + chunk.len());
+            part.push((i >> 8) as u8);
+            part.push(i as u8);
+            part.push(if i == last { LAST_PART } else { 0 });
+            part.extend_from_slice(chunk);
+            part
+        })
+        .collect())
+}
+
+/// Sequence number, last part flag and data of a part.
+fn parse(part: &[u8]) -> Result<(usize, bool, &[u8]), Error> {
+    if part.len() < PART_HEADER_LEN || part[2] & !LAST_PART != 0 {
+        bail!(ErrorKind::BadProtocol);
+    }
+    let sequence = (usize::from(part[0]) << 8) | usize::from(part[1]);
+    Ok((sequence, part[2] & LAST_PART != 0, &part[PART_HEADER_LEN..]))
+}
+
+struct Partial {
+    next: usize,
+    data: Vec<u8>,
+    /// When the last part arrived
+    updated: Instant,
+}
+
+/// Reassembles split payloads, one at a time per key (e.g. per peer). Parts of a payload have
+/// to arrive in order.
+///
+/// Incomplete payloads are dropped once no part arrived for `timeout`, or to make room for a
+/// new one once `max_partial` of them are buffered. The remaining parts of a dropped payload
+/// are ignored.
+pub struct Reassembler<K> {
+    max_payload_size: usize,
+    max_partial: usize,
+    timeout: Duration,
+    partial: HashMap<K, Partial>,
+    dropped: HashSet<K>,
+}
+
+impl<K: Hash + Eq + Clone> Reassembler<K> {
+    /// Create a reassembler buffering at most `max_partial` incomplete payloads of at most
+    /// `max_payload_size` bytes each, for at most `timeout` between two parts.
+    pub fn new(max_payload_size: usize, max_partial: usize, timeout: Duration) -> Self {
+        Reassembler {
+            max_payload_size,
+            max_partial,
+            timeout,
+            partial: HashMap::new(),
+            dropped: HashSet::new(),
+        }
+    }
+
+    /// Add a part received from `key` at `now`. Returns the payload once its last part arrived.
+    /// Parts out of order or exceeding the limits discard the incomplete payload of `key`.
+    pub fn push(&mut self, key: K, part: &[u8], now: Instant) -> Result<Option<Vec<u8>>, Error> {
+        let (sequence, last, data) = parse(part)?;
+        let mut partial = match self.partial.remove(&key) {
+            Some(partial) => partial,
+            None if sequence != 0 && self.dropped.contains(&key) => {
+                if last {
+                    self.dropped.remove(&key);
+                }
+                return Ok(None);
+            }
+            None => {
+                self.dropped.remove(&key);
+                if self.partial.len() >= self.max_partial {
+                    self.expire(now);
+                }
+                if self.partial.len() >= self.max_partial {
+                    self.drop_oldest();
+                }
+                Partial {
+                    next: 0,
+                    data: Vec::new(),
+                    updated: now,
+                }
+            }
+        };
+        if sequence != partial.next {
+            bail!(ErrorKind::BadProtocol);
+        }
+        if partial.data.len() + data.len() > self.max_payload_size {
+            bail!(ErrorKind::OversizedPacket);
+        }
+        partial.data.extend_from_slice(data);
+        if last {
+            return Ok(Some(partial.data));
+        }
+        if partial.next + 1 == MAX_PARTS {
+            bail!(ErrorKind::OversizedPacket);
+        }
+        partial.next += 1;
+        partial.updated = now;
+        self.partial.insert(key, partial);
+        Ok(None)
+    }
+
+    /// Drop the incomplete payloads which received no part for the timeout.
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        let expired: Vec<K> = self
+            .partial
+            .iter()
+            .filter(|(_, partial)| now.saturating_duration_since(partial.updated) >= timeout)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            self.partial.remove(&key);
+            self.dropped.insert(key);
+        }
+    }
+
+    /// Drop the incomplete payload which received a part least recently.
+    fn drop_oldest(&mut self) {
+        let oldest = self
+            .partial
+            .iter()
+            .min_by_key(|(_, partial)| partial.updated)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.partial.remove(&key);
+            self.dropped.insert(key);
+        }
+    }
+
+    /// Discard the incomplete payload of `key`, e.g. after a disconnect.
+    pub fn remove(&mut self, key: &K) {
+        self.partial.remove(key);
+        self.dropped.remove(key);
+    }
+
+    /// Number of incomplete payloads.
+    pub fn pending(&self) -> usize {
+        self.partial.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn split_payload_is_reassembled() {
+        let payload: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let parts = split(&payload, 103).unwrap();
+        assert_eq!(parts.len(), 10);
+        assert!(parts.iter().all(|part| part.len() <= 103));
+
+        let mut reassembler = Reassembler::new(payload.len(), 1, TIMEOUT);
+        let now = Instant::now();
+        let (last, rest) = parts.split_last().unwrap();
+        for part in rest {
+            assert_eq!(reassembler.push(1, part, now).unwrap(), None);
+        }
+        assert_eq!(reassembler.push(1, last, now).unwrap(), Some(payload));
+        assert_eq!(reassembler.pending(), 0);
+
+        let empty = split(&[], 103).unwrap();
+        assert_eq!(
+            reassembler.push(1, &empty[0], now).unwrap(),
+            Some(Vec::new())
+        );
+    }
+
+    #[test]
+    fn payloads_of_different_keys_are_reassembled_independently() {
+        let first = split(&[1u8; 10], 7).unwrap();
+        let second = split(&[2u8; 10], 7).unwrap();
+        let mut reassembler = Reassembler::new(10, 2, TIMEOUT);
+        let now = Instant::now();
+        assert_eq!(reassembler.push(1, &first[0], now).unwrap(), None);
+        assert_eq!(reassembler.push(2, &second[0], now).unwrap(), None);
+        assert_eq!(reassembler.push(1, &first[1], now).unwrap(), None);
+        assert_eq!(reassembler.push(2, &second[1], now).unwrap(), None);
+        assert_eq!(
+            reassembler.push(2, &second[2], now).unwrap(),
+            Some(vec![2u8; 10])
+        );
+        assert_eq!(
+            reassembler.push(1, &first[2], now).unwrap(),
+            Some(vec![1u8; 10])
+        );
+    }
+
+    #[test]
+    fn part_out_of_order_is_rejected() {
+        let parts = split(&[0u8; 10], 7).unwrap();
+        let mut reassembler = Reassembler::new(10, 1, TIMEOUT);
+        let now = Instant::now();
+        assert_matches!(
+            reassembler.push(1, &parts[1], now).unwrap_err().kind(),
+            ErrorKind::BadProtocol
+        );
+        assert_eq!(reassembler.push(1, &parts[0], now).unwrap(), None);
+        assert_matches!(
+            reassembler.push(1, &parts[2], now).unwrap_err().kind(),
+            ErrorKind::BadProtocol
+        );
+        assert_eq!(reassembler.pending(), 0);
+        assert_matches!(
+            reassembler.push(1, &[0], now).unwrap_err().kind(),
+            ErrorKind::BadProtocol
+        );
+    }
+
+    #[test]
+    fn unbounded_sequence_is_capped() {
+        let mut reassembler = Reassembler::new(1000, 1, TIMEOUT);
+        let now = Instant::now();
+        let mut part = vec![0u8; PART_HEADER_LEN + 100];
+        let mut sequence = 0;
+        let err = loop {
+            part[0] = (sequence >> 8) as u8;
+            part[1] = sequence as u8;
+            match reassembler.push(1, &part, now) {
+                Ok(None) => sequence += 1,
+                Ok(Some(_)) => panic!("payload without last part completed"),
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(sequence, 10);
+        assert_matches!(err.kind(), ErrorKind::OversizedPacket);
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn oldest_partial_payload_is_dropped_beyond_the_cap() {
+        let parts = split(&[0u8; 10], 7).unwrap();
+        let mut reassembler = Reassembler::new(10, 2, TIMEOUT);
+        let now = Instant::now();
+        assert_eq!(reassembler.push(1, &parts[0], now).unwrap(), None);
+        let later = now + Duration::from_secs(1);
+        assert_eq!(reassembler.push(2, &parts[0], later).unwrap(), None);
+        assert_eq!(reassembler.push(3, &parts[0], later).unwrap(), None);
+        assert_eq!(reassembler.pending(), 2);
+
+        // the rest of the dropped payload is ignored, a new one is accepted again
+        assert_eq!(reassembler.push(1, &parts[1], later).unwrap(), None);
+        assert_eq!(reassembler.push(1, &parts[2], later).unwrap(), None);
+        assert_eq!(reassembler.push(2, &parts[1], later).unwrap(), None);
+        assert_eq!(
+            reassembler.push(2, &parts[2], later).unwrap(),
+            Some(vec![0u8; 10])
+        );
+        assert_eq!(reassembler.push(1, &parts[0], later).unwrap(), None);
+        assert_eq!(reassembler.pending(), 2);
+    }
+
+    #[test]
+    fn stale_partial_payloads_expire() {
+        let parts = split(&[0u8; 10], 7).unwrap();
+        let mut reassembler = Reassembler::new(10, 2, TIMEOUT);
+        let now = Instant::now();
+        assert_eq!(reassembler.push(1, &parts[0], now).unwrap(), None);
+        assert_eq!(reassembler.push(2, &parts[0], now).unwrap(), None);
+        let later = now + Duration::from_secs(5);
+        assert_eq!(reassembler.push(2, &parts[1], later).unwrap(), None);
+
+        reassembler.expire(now + TIMEOUT);
+        assert_eq!(reassembler.pending(), 1);
+        assert_eq!(reassembler.push(1, &parts[1], later).unwrap(), None);
+        assert_eq!(
+            reassembler.push(2, &parts[2], later).unwrap(),
+            Some(vec![0u8; 10])
+        );
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn split_requires_room_for_data() {
+        assert_matches!(
+            split(&[0u8; 10], PART_HEADER_LEN).unwrap_err().kind(),
+            ErrorKind::OversizedPacket
+        );
+        assert_matches!(
+            split(&[0u8; MAX_PARTS + 1], PART_HEADER_LEN + 1)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::OversizedPacket
+        );
+    }
+}
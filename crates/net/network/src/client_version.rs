@@ -19,12 +19,16 @@
 //! Parse ethereum client ID strings and provide querying functionality
 
 use semver::Version;
-use std::fmt;
+use std::{collections::BTreeMap, fmt};
 
 /// Parity client string prefix
 const LEGACY_CLIENT_ID_PREFIX: &str = "Parity-Ethereum";
 const CURRENT_CLIENT_ID_PREFIX: &str = "OpenEthereum";
 
+/// Largest packet payload sent to peers which are known to drop packets well below the
+/// RLPx frame limit.
+const SMALL_PAYLOAD_SIZE: usize = (1 << 22) - 1;
+
 lazy_static! {
 /// Parity versions starting from this will accept block bodies requests
 /// of 256 bodies
@@ -131,6 +135,11 @@ pub trait ClientCapabilities {
     /// Service transactions are specific to parity and nethermind. Query if
     /// this version accepts them.
     fn accepts_service_transaction(&self) -> bool;
+
+    /// Parity versions before PARITY_CLIENT_LARGE_REQUESTS_VERSION stall on
+    /// packets approaching the RLPx frame limit. Query the largest payload this
+    /// version is known to handle if it is below that limit.
+    fn max_payload_size(&self) -> Option<usize>;
 }
 
 impl ClientCapabilities for ClientVersion {
@@ -149,6 +158,13 @@ impl ClientCapabilities for ClientVersion {
             ClientVersion::Other(client_id) => is_nethermind(client_id),
         }
     }
+
+    fn max_payload_size(&self) -> Option<usize> {
+        match self.can_handle_large_requests() {
+            true => None,
+            false => Some(SMALL_PAYLOAD_SIZE),
+        }
+    }
 }
 
 fn is_parity(client_id: &str) -> bool {
@@ -248,6 +264,179 @@ impl fmt::Display for ClientVersion {
     }
 }
 
+impl ClientVersion {
+    /// Fields of the client ID string, as far as they could be recognized.
+    pub fn info(&self) -> ClientInfo {
+        ClientInfo::from(self.to_string().as_str())
+    }
+}
+
+/// Fields of a client ID string in the common `Name[/identity]/vX.Y.Z-tag/os-arch/language`
+/// format. Fields which could not be recognized are `None`, the whole string is kept in `raw`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ClientInfo {
+    /// The client ID string as received
+    pub raw: String,
+    /// Client name, e.g. `Geth`
+    pub name: Option<String>,
+    /// Identity set by the node operator
+    pub identity: Option<String>,
+    /// Numeric version without the leading `v`, e.g. `1.13.5`
+    pub version: Option<String>,
+    /// Version suffix after `-` or `+`, e.g. `stable-916d6a44`
+    pub tag: Option<String>,
+    /// Operating system, normalized to `linux`, `windows`, `macos`, ...
+    pub os: Option<String>,
+    /// CPU architecture, normalized to `x86_64`, `aarch64`, `x86` or `arm`
+    pub arch: Option<String>,
+    /// Language or compiler the client was built with, e.g. `go1.21.4`
+    pub language: Option<String>,
+}
+
+impl ClientInfo {
+    /// Major and minor version numbers.
+    pub fn major_minor(&self) -> Option<(u64, u64)> {
+        let mut numbers = self.version.as_ref()?.split('.').map(str::parse);
+        match (numbers.next(), numbers.next()) {
+            (Some(Ok(major)), Some(Ok(minor))) => Some((major, minor)),
+            _ => None,
+        }
+    }
+
+    /// Whether the client is known to run on Windows.
+    pub fn is_windows(&self) -> bool {
+        self.os.as_deref() == Some("windows")
+    }
+}
+
+impl From<&str> for ClientInfo {
+    fn from(raw: &str) -> Self {
+        let mut info = ClientInfo {
+            raw: raw.to_owned(),
+            ..Default::default()
+        };
+        let tokens: Vec<&str> = raw.split('/').collect();
+        info.name = non_empty(tokens[0]);
+
+        let version_index = match tokens
+            .iter()
+            .skip(1)
+            .position(|token| parse_version(token).is_some())
+        {
+            Some(index) => index + 1,
+            None => return info,
+        };
+        let (version, tag) =
+            parse_version(tokens[version_index]).expect("version token was found above; qed");
+        info.version = Some(version.to_owned());
+        info.tag = tag.map(str::to_owned);
+        info.identity = non_empty(&tokens[1..version_index].join("/"));
+
+        if let Some(platform) = tokens.get(version_index + 1) {
+            let (os, arch) = parse_platform(platform);
+            info.os = os.map(str::to_owned);
+            info.arch = arch.map(str::to_owned);
+        }
+        if let Some(language) = tokens.get(version_index + 2..) {
+            info.language = non_empty(&language.join("/"));
+        }
+        info
+    }
+}
+
+fn non_empty(token: &str) -> Option<String> {
+    match token.is_empty() {
+        true => None,
+        false => Some(token.to_owned()),
+    }
+}
+
+/// Split a `vX.Y[.Z][-tag]` token into the numeric version and the tag.
+fn parse_version(token: &str) -> Option<(&str, Option<&str>)> {
+    let token = get_number_from_version(token)?;
+    let (version, tag) = match token.find(&['-', '+'][..]) {
+        Some(index) => (&token[..index], Some(&token[index + 1..])),
+        None => (token, None),
+    };
+    let numbers: Vec<&str> = version.split('.').collect();
+    let numeric = numbers.len() >= 2
+        && numbers
+            .iter()
+            .all(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+    match numeric {
+        true => Some((version, tag.filter(|tag| !tag.is_empty()))),
+        false => None,
+    }
+}
+
+/// Find the operating system and architecture among the dash separated parts of a platform
+/// token like `linux-amd64`, `x86_64-unknown-linux-gnu` or `X64-Linux`.
+fn parse_platform(token: &str) -> (Option<&'static str>, Option<&'static str>) {
+    let mut os = None;
+    let mut arch = None;
+    for part in token.split('-').map(str::to_ascii_lowercase) {
+        let part_os = match part.as_str() {
+            "linux" => Some("linux"),
+            "windows" | "win32" | "win64" => Some("windows"),
+            "darwin" | "macos" | "osx" => Some("macos"),
+            "freebsd" => Some("freebsd"),
+            "openbsd" => Some("openbsd"),
+            "netbsd" => Some("netbsd"),
+            "android" => Some("android"),
+            _ => None,
+        };
+        let part_arch = match part.as_str() {
+            "x86_64" | "amd64" | "x64" => Some("x86_64"),
+            "aarch64" | "aarch_64" | "arm64" => Some("aarch64"),
+            "x86" | "386" | "i386" | "i686" => Some("x86"),
+            "arm" | "armv7" | "armv7l" => Some("arm"),
+            _ => None,
+        };
+        os = os.or(part_os);
+        arch = arch.or(part_arch);
+    }
+    (os, arch)
+}
+
+/// Number of peers by client software.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ClientBreakdown {
+    /// Number of peers
+    pub total: usize,
+    /// Peers whose client name is not known
+    pub unknown: usize,
+    /// Peers by client name
+    pub clients: BTreeMap<String, usize>,
+    /// Peers by client name and `major.minor` version
+    pub versions: BTreeMap<String, BTreeMap<String, usize>>,
+}
+
+/// Count the peers by client name and version.
+pub fn summarize<'a>(peers: impl Iterator<Item = &'a ClientVersion>) -> ClientBreakdown {
+    let mut breakdown = ClientBreakdown::default();
+    for peer in peers {
+        breakdown.total += 1;
+        let info = peer.info();
+        let name = match info.name {
+            Some(ref name) => name.clone(),
+            None => {
+                breakdown.unknown += 1;
+                continue;
+            }
+        };
+        if let Some((major, minor)) = info.major_minor() {
+            *breakdown
+                .versions
+                .entry(name.clone())
+                .or_default()
+                .entry(format!("{major}.{minor}"))
+                .or_insert(0) += 1;
+        }
+        *breakdown.clients.entry(name).or_insert(0) += 1;
+    }
+    breakdown
+}
+
 fn get_number_from_version(version: &str) -> Option<&str> {
     if version.starts_with("v") {
         return version.get(1..);
@@ -491,6 +680,20 @@ pub mod tests {
         assert!(!client_version.can_handle_large_requests());
     }
 
+    #[test]
+    fn client_capabilities_max_payload_size_reduced_for_old_parity_versions() {
+        let old = ClientVersion::from(make_old_semver_version_string().as_str());
+        assert_eq!(old.max_payload_size(), Some(SMALL_PAYLOAD_SIZE));
+        assert_eq!(
+            ClientVersion::from("Parity-Ethereum/unknown").max_payload_size(),
+            Some(SMALL_PAYLOAD_SIZE)
+        );
+
+        let current = ClientVersion::from(make_default_version_string().as_str());
+        assert_eq!(current.max_payload_size(), None);
+        assert_eq!(ClientVersion::from("Geth").max_payload_size(), None);
+    }
+
     #[test]
     pub fn client_version_when_to_owned_then_both_objects_equal() {
         let client_version_string: String = make_old_semver_version_string();
@@ -525,6 +728,107 @@ pub mod tests {
         )
     }
 
+    #[test]
+    fn client_info_parses_real_world_ids() {
+        // raw, name, identity, version, tag, os, arch, language
+        type Expected = (
+            &'static str,
+            &'static str,
+            Option<&'static str>,
+            &'static str,
+            Option<&'static str>,
+            Option<&'static str>,
+            Option<&'static str>,
+            Option<&'static str>,
+        );
+        let corpus: &[Expected] = &[
+            ("Geth/v1.13.5-stable-916d6a44/linux-amd64/go1.21.4", "Geth", None, "1.13.5", Some("stable-916d6a44"), Some("linux"), Some("x86_64"), Some("go1.21.4")),
+            ("Geth/v1.10.26-stable-e5eb32ac/windows-amd64/go1.18.5", "Geth", None, "1.10.26", Some("stable-e5eb32ac"), Some("windows"), Some("x86_64"), Some("go1.18.5")),
+            ("Geth/v1.11.6-stable-ea9e62ca/darwin-arm64/go1.20.3", "Geth", None, "1.11.6", Some("stable-ea9e62ca"), Some("macos"), Some("aarch64"), Some("go1.20.3")),
+            ("Geth/main.jnode.network/v1.8.21-stable-9dc5d1a9/linux", "Geth", Some("main.jnode.network"), "1.8.21", Some("stable-9dc5d1a9"), Some("linux"), None, None),
+            ("Geth/v1.12.0-stable/linux-arm/go1.20.4", "Geth", None, "1.12.0", Some("stable"), Some("linux"), Some("arm"), Some("go1.20.4")),
+            ("Nethermind/v1.10.37-0-068e5c399-20210311/X64-Linux/5.0.4", "Nethermind", None, "1.10.37", Some("0-068e5c399-20210311"), Some("linux"), Some("x86_64"), Some("5.0.4")),
+            ("Nethermind/v1.25.4+20b10b35/linux-x64/dotnet8.0.2", "Nethermind", None, "1.25.4", Some("20b10b35"), Some("linux"), Some("x86_64"), Some("dotnet8.0.2")),
+            ("Nethermind/v1.19.3+e8ac1da4/windows-x64/dotnet7.0.9", "Nethermind", None, "1.19.3", Some("e8ac1da4"), Some("windows"), Some("x86_64"), Some("dotnet7.0.9")),
+            ("besu/v23.10.2/linux-x86_64/openjdk-java-17", "besu", None, "23.10.2", None, Some("linux"), Some("x86_64"), Some("openjdk-java-17")),
+            ("besu/v22.7.4/linux-aarch_64/openjdk-java-11", "besu", None, "22.7.4", None, Some("linux"), Some("aarch64"), Some("openjdk-java-11")),
+            ("besu/v24.1.0/osx-aarch_64/corretto-java-21", "besu", None, "24.1.0", None, Some("macos"), Some("aarch64"), Some("corretto-java-21")),
+            ("erigon/v2.55.1-125509e4/linux-amd64/go1.20.11", "erigon", None, "2.55.1", Some("125509e4"), Some("linux"), Some("x86_64"), Some("go1.20.11")),
+            ("erigon/v2.48.1-stable/windows-amd64/go1.19.3", "erigon", None, "2.48.1", Some("stable"), Some("windows"), Some("x86_64"), Some("go1.19.3")),
+            ("reth/v0.1.0-alpha.13-e3ea3d1b/x86_64-unknown-linux-gnu", "reth", None, "0.1.0", Some("alpha.13-e3ea3d1b"), Some("linux"), Some("x86_64"), None),
+            ("reth/v1.0.3-390f30a/aarch64-apple-darwin", "reth", None, "1.0.3", Some("390f30a"), Some("macos"), Some("aarch64"), None),
+            ("OpenEthereum/v3.3.5-stable-6c2d392d0-20220405/x86_64-linux-gnu/rustc1.58.1", "OpenEthereum", None, "3.3.5", Some("stable-6c2d392d0-20220405"), Some("linux"), Some("x86_64"), Some("rustc1.58.1")),
+            ("OpenEthereum/mynode/v3.2.6-stable-f9f4926-20210514/x86_64-pc-windows-msvc/rustc1.52.1", "OpenEthereum", Some("mynode"), "3.2.6", Some("stable-f9f4926-20210514"), Some("windows"), Some("x86_64"), Some("rustc1.52.1")),
+            ("OpenEthereum//v3.2.0/x86_64-linux-gnu/rustc1.49.0", "OpenEthereum", None, "3.2.0", None, Some("linux"), Some("x86_64"), Some("rustc1.49.0")),
+            ("Parity-Ethereum/v2.7.2-stable-2662d19-20200206/x86_64-unknown-linux-gnu/rustc1.41.0", "Parity-Ethereum", None, "2.7.2", Some("stable-2662d19-20200206"), Some("linux"), Some("x86_64"), Some("rustc1.41.0")),
+            ("Parity-Ethereum/ABCDEFGH/v2.7.3/linux/rustc", "Parity-Ethereum", Some("ABCDEFGH"), "2.7.3", None, Some("linux"), None, Some("rustc")),
+            ("Parity/v1.11.11-stable-4a98ac0-20180831/i686-linux-gnu/rustc1.28.0", "Parity", None, "1.11.11", Some("stable-4a98ac0-20180831"), Some("linux"), Some("x86"), Some("rustc1.28.0")),
+        ];
+
+        for &(raw, name, identity, version, tag, os, arch, language) in corpus {
+            let info = ClientVersion::from(raw).info();
+            assert_eq!(info.raw, raw);
+            assert_eq!(info.name.as_deref(), Some(name), "{raw}");
+            assert_eq!(info.identity.as_deref(), identity, "{raw}");
+            assert_eq!(info.version.as_deref(), Some(version), "{raw}");
+            assert_eq!(info.tag.as_deref(), tag, "{raw}");
+            assert_eq!(info.os.as_deref(), os, "{raw}");
+            assert_eq!(info.arch.as_deref(), arch, "{raw}");
+            assert_eq!(info.language.as_deref(), language, "{raw}");
+        }
+
+        assert!(ClientInfo::from("Geth/v1.10.26-stable/windows-amd64/go1.18.5").is_windows());
+        assert!(!ClientInfo::from("Geth/v1.10.26-stable/linux-amd64/go1.18.5").is_windows());
+    }
+
+    #[test]
+    fn client_info_degrades_gracefully() {
+        let info = ClientInfo::from("");
+        assert_eq!(info, ClientInfo::default());
+
+        let info = ClientInfo::from("Geth");
+        assert_eq!(info.name, Some("Geth".to_owned()));
+        assert_eq!(info.version, None);
+
+        let info = ClientInfo::from("Geth/vnext/linux-amd64");
+        assert_eq!(info.raw, "Geth/vnext/linux-amd64");
+        assert_eq!(info.name, Some("Geth".to_owned()));
+        assert_eq!((info.version, info.os), (None, None));
+
+        let info = ClientInfo::from("besu/v23.1/unknown");
+        assert_eq!(info.major_minor(), Some((23, 1)));
+        assert_eq!((info.os, info.arch, info.language), (None, None, None));
+    }
+
+    #[test]
+    fn summarize_counts_clients_and_versions() {
+        let peers: Vec<ClientVersion> = [
+            "Geth/v1.13.5-stable-916d6a44/linux-amd64/go1.21.4",
+            "Geth/v1.13.4-stable-3f907d6a/linux-amd64/go1.21.3",
+            "Geth/v1.12.0-stable/linux-arm/go1.20.4",
+            "Nethermind/v1.25.4+20b10b35/linux-x64/dotnet8.0.2",
+            "OpenEthereum/v3.3.5-stable-6c2d392d0-20220405/x86_64-linux-gnu/rustc1.58.1",
+            "Geth",
+            "",
+            "/v1.0.0/linux",
+        ]
+        .iter()
+        .map(ClientVersion::from)
+        .collect();
+
+        let breakdown = summarize(peers.iter());
+
+        assert_eq!(breakdown.total, 8);
+        assert_eq!(breakdown.unknown, 2);
+        assert_eq!(breakdown.clients["Geth"], 4);
+        assert_eq!(breakdown.clients["Nethermind"], 1);
+        assert_eq!(breakdown.clients["OpenEthereum"], 1);
+        assert_eq!(breakdown.versions["Geth"]["1.13"], 2);
+        assert_eq!(breakdown.versions["Geth"]["1.12"], 1);
+        assert_eq!(breakdown.versions["Geth"].len(), 2);
+        assert_eq!(breakdown.versions["OpenEthereum"]["3.3"], 1);
+    }
+
     #[test]
     fn is_parity_when_parity_then_true() {
         let client_id = format!("{CURRENT_CLIENT_ID_PREFIX}/");
@@ -132,18 +132,78 @@ error_chain! {
             display("Peer disconnected: {}", reason),
         }
 
+        #[doc = "Peer session does not exist anymore"]
+        PeerGone {
+            description("Peer is gone"),
+            display("Peer is gone"),
+        }
+
+        #[doc = "Packet refused because too much data is already queued for the peer"]
+        SendQueueFull(queued_bytes: usize) {
+            description("Send queue is full"),
+            display("Send queue is full, {} bytes queued", queued_bytes),
+        }
+
+        #[doc = "Peer session has expired"]
+        SessionExpired {
+            description("Session expired"),
+            display("Session expired"),
+        }
+
+        #[doc = "Peer did not negotiate the protocol"]
+        ProtocolUnsupported {
+            description("Protocol not supported by peer"),
+            display("Protocol not supported by peer"),
+        }
+
         #[doc = "Invalid node id"]
         InvalidNodeId {
             description("Invalid node id"),
             display("Invalid node id"),
         }
 
+        #[doc = "Invalid node record"]
+        InvalidNodeRecord(reason: &'static str) {
+            description("Invalid node record"),
+            display("Invalid node record: {}", reason),
+        }
+
+        #[doc = "Timer token registered by the protocol already"]
+        TimerTokenInUse(token: usize) {
+            description("Timer token in use"),
+            display("Timer token {} is registered by the protocol already", token),
+        }
+
         #[doc = "Packet size is over the protocol limit"]
         OversizedPacket {
             description("Packet is too large"),
             display("Packet is too large"),
         }
 
+        #[doc = "Packets of the peer are captured already"]
+        CaptureInProgress {
+            description("Capture in progress"),
+            display("Packets of the peer are captured already"),
+        }
+
+        #[doc = "Capture file name is not a plain file name"]
+        InvalidCaptureName(name: String) {
+            description("Invalid capture file name"),
+            display("Capture file name must be a plain file name: {}", name),
+        }
+
+        #[doc = "No network data directory to keep packet captures in"]
+        NoCaptureDirectory {
+            description("No capture directory"),
+            display("Packet captures need a network data directory"),
+        }
+
+        #[doc = "Maximum number of concurrent packet captures reached"]
+        TooManyCaptures(max: usize) {
+            description("Too many packet captures"),
+            display("At most {} peers may be captured at once", max),
+        }
+
         #[doc = "Reached system resource limits for this process"]
         ProcessTooManyFiles {
             description("Too many open files in process."),
@@ -156,6 +216,12 @@ error_chain! {
             display("Too many open files on system. Consider closing some processes/release some file handlers or increas the system-wide resource limits and restart openethereum."),
         }
 
+        #[doc = "A configured socket option could not be applied"]
+        SocketOption(option: String, err: io::Error) {
+            description("Failed to apply a socket option"),
+            display("Failed to apply {}: {}", option, err),
+        }
+
         #[doc = "An unknown IO error occurred."]
         Io(err: io::Error) {
             description("IO Error"),
@@ -40,14 +40,17 @@ extern crate error_chain;
 #[macro_use]
 extern crate lazy_static;
 
+pub mod chunking;
 pub mod client_version;
 
 mod connection_filter;
 mod error;
+mod node_record;
 
 pub use connection_filter::{ConnectionDirection, ConnectionFilter};
 pub use error::{DisconnectReason, Error, ErrorKind};
 pub use io::TimerToken;
+pub use node_record::{LocalNodeRecord, NodeRecord, MAX_NODE_RECORD_SIZE};
 
 use client_version::ClientVersion;
 use crypto::publickey::Secret;
@@ -58,6 +61,7 @@ use std::{
     cmp::Ordering,
     collections::HashMap,
     net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    path::Path,
     str::{self, FromStr},
     sync::Arc,
     time::Duration,
@@ -74,6 +78,14 @@ pub type NodeId = H512;
 /// Local (temporary) peer session ID.
 pub type PeerId = usize;
 
+/// Largest packet payload the RLPx frame header can describe.
+pub const MAX_PAYLOAD_SIZE: usize = (1 << 24) - 1;
+
+/// First timer token allocated by `NetworkContext::register_timer_auto`. Tokens passed to
+/// `NetworkContext::register_timer` should stay below, those at or above are skipped by the
+/// allocator but may be taken already.
+pub const AUTO_TIMER_TOKENS: TimerToken = 1 << 16;
+
 /// Messages used to communitate with the event loop from other threads.
 #[derive(Clone)]
 pub enum NetworkIoMessage {
@@ -101,6 +113,10 @@ pub enum NetworkIoMessage {
     Disconnect(PeerId),
     /// Disconnect and temporary disable peer.
     DisablePeer(PeerId),
+    /// Disconnect a misbehaving peer and ban it for the configured duration, also across restarts.
+    BanPeer(PeerId, String),
+    /// Disconnect a peer of a different network and do not dial it for a while.
+    AvoidPeer(PeerId),
     /// Network has been started with the host as the given enode.
     NetworkStarted(String),
 }
@@ -126,6 +142,8 @@ pub struct SessionInfo {
     pub remote_address: String,
     /// Local endpoint address of the session
     pub local_address: String,
+    /// Largest packet payload which may be sent to the peer
+    pub max_payload_size: usize,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -190,6 +208,13 @@ pub struct NetworkConfiguration {
     pub listen_address: Option<SocketAddr>,
     /// IP address to advertise. Detected automatically if none.
     pub public_address: Option<SocketAddr>,
+    /// Network interface the TCP and UDP sockets are bound to with `SO_BINDTODEVICE`.
+    /// Only supported on Linux, ignored with a warning elsewhere.
+    pub bind_interface: Option<String>,
+    /// Local address outbound connections are made from, its port is ignored. Dials to the other
+    /// address family use the default source address. Used for the advertised address if none
+    /// is set.
+    pub outbound_source_address: Option<SocketAddr>,
     /// Port for UDP connections, same as TCP by default
     pub udp_port: Option<u16>,
     /// Enable NAT configuration
@@ -198,12 +223,17 @@ pub struct NetworkConfiguration {
     pub discovery_enabled: bool,
     /// List of initial node addresses
     pub boot_nodes: Vec<String>,
+    /// EIP-1459 node tree URLs (`enrtree://<key>@<domain>`) to discover nodes from
+    pub dns_discovery_urls: Vec<String>,
     /// Use provided node key instead of default
     pub use_secret: Option<Secret>,
     /// Minimum number of connected peers to maintain
     pub min_peers: u32,
     /// Maximum allowed number of peers
     pub max_peers: u32,
+    /// Share of the `max_peers` slots, in percent, non-reserved peers of one IP prefix or one
+    /// client may take once `min_peers` are connected. 100, the default, turns the limit off.
+    pub max_peer_share: u32,
     /// Maximum handshakes
     pub max_handshakes: u32,
     /// Reserved protocols. Peers with <key> protocol get additional <value> connection slots.
@@ -216,6 +246,27 @@ pub struct NetworkConfiguration {
     pub ip_filter: IpFilter,
     /// Client identifier
     pub client_version: String,
+    /// How long a disabled peer stays banned
+    pub peer_ban_duration: Duration,
+    /// How long a peer of a different network is not dialed again
+    pub avoid_duration: Duration,
+    /// Network id the persisted node table and ban list belong to. Files recorded under a
+    /// different network id are not loaded.
+    pub network_id: Option<u64>,
+    /// Largest packet payload sent to a peer, capped at `MAX_PAYLOAD_SIZE`. Sessions with
+    /// clients known to drop large packets use a smaller limit.
+    pub max_payload_size: usize,
+    /// Label telling apart several network instances of one process. Thread names of a
+    /// labeled instance start with the label and its files are kept in a subdirectory of
+    /// `config_path` and `net_config_path` named after it. Empty for a single instance.
+    pub instance_label: String,
+    /// How often the public address is probed by dialing it and starting a handshake with
+    /// ourselves. Not probed if `None`.
+    pub reachability_probe_interval: Option<Duration>,
+    /// Maximum number of peers whose packets may be captured to files at once, a debugging
+    /// aid. The files are kept in the `captures` directory of `net_config_path`. Capturing is
+    /// disabled if zero.
+    pub max_packet_captures: usize,
 }
 
 impl Default for NetworkConfiguration {
@@ -232,19 +283,49 @@ impl NetworkConfiguration {
             net_config_path: None,
             listen_address: None,
             public_address: None,
+            bind_interface: None,
+            outbound_source_address: None,
             udp_port: None,
             nat_enabled: true,
             discovery_enabled: true,
             boot_nodes: Vec::new(),
+            dns_discovery_urls: Vec::new(),
             use_secret: None,
             min_peers: 25,
             max_peers: 50,
+            max_peer_share: 100,
             max_handshakes: 64,
             reserved_protocols: HashMap::new(),
             ip_filter: IpFilter::default(),
             reserved_nodes: Vec::new(),
             non_reserved_mode: NonReservedPeerMode::Accept,
             client_version: "Parity-network".into(),
+            peer_ban_duration: Duration::from_secs(24 * 60 * 60),
+            avoid_duration: Duration::from_secs(30 * 60),
+            network_id: None,
+            max_payload_size: MAX_PAYLOAD_SIZE,
+            instance_label: String::new(),
+            reachability_probe_interval: None,
+            max_packet_captures: 0,
+        }
+    }
+
+    /// `name` qualified with the instance label, if any.
+    pub fn instance_name(&self, name: &str) -> String {
+        match self.instance_label.is_empty() {
+            true => name.to_owned(),
+            false => format!("{}-{}", self.instance_label, name),
+        }
+    }
+
+    /// Directory of this instance within `path`.
+    pub fn instance_path(&self, path: &str) -> String {
+        match self.instance_label.is_empty() {
+            true => path.to_owned(),
+            false => Path::new(path)
+                .join(&self.instance_label)
+                .to_string_lossy()
+                .into_owned(),
         }
     }
 
@@ -287,18 +368,31 @@ pub trait NetworkContext {
     /// Respond to a current network message. Panics if no there is no packet in the context. If the session is expired returns nothing.
     fn respond(&self, packet_id: PacketId, data: Vec<u8>) -> Result<(), Error>;
 
-    /// Disconnect a peer and prevent it from connecting again.
+    /// Disconnect a peer and prevent it from connecting again. A peer disabled repeatedly
+    /// is banned like with `ban_peer`.
     fn disable_peer(&self, peer: PeerId);
 
+    /// Disconnect a peer which misbehaved in the way `reason` describes and persistently ban
+    /// its node for the configured duration.
+    fn ban_peer(&self, peer: PeerId, reason: &str);
+
     /// Disconnect peer. Reconnect can be attempted later.
     fn disconnect_peer(&self, peer: PeerId);
 
+    /// Disconnect a peer which belongs to a different network and do not dial it again for a while.
+    fn avoid_peer(&self, peer: PeerId);
+
     /// Check if the session is still active.
     fn is_expired(&self) -> bool;
 
     /// Register a new IO timer. 'IoHandler::timeout' will be called with the token.
+    /// Tokens are private to the protocol, registering a token the protocol uses already fails.
     fn register_timer(&self, token: TimerToken, delay: Duration) -> Result<(), Error>;
 
+    /// Register a new IO timer with a token allocated by the host, distinct from the tokens of
+    /// all other timers. 'IoHandler::timeout' will be called with the returned token.
+    fn register_timer_auto(&self, delay: Duration) -> Result<TimerToken, Error>;
+
     /// Returns peer identification string
     fn peer_client_version(&self, peer: PeerId) -> ClientVersion;
 
@@ -341,10 +435,18 @@ where
         (**self).disable_peer(peer)
     }
 
+    fn ban_peer(&self, peer: PeerId, reason: &str) {
+        (**self).ban_peer(peer, reason)
+    }
+
     fn disconnect_peer(&self, peer: PeerId) {
         (**self).disconnect_peer(peer)
     }
 
+    fn avoid_peer(&self, peer: PeerId) {
+        (**self).avoid_peer(peer)
+    }
+
     fn is_expired(&self) -> bool {
         (**self).is_expired()
     }
@@ -353,6 +455,10 @@ where
         (**self).register_timer(token, delay)
     }
 
+    fn register_timer_auto(&self, delay: Duration) -> Result<TimerToken, Error> {
+        (**self).register_timer_auto(delay)
+    }
+
     fn peer_client_version(&self, peer: PeerId) -> ClientVersion {
         (**self).peer_client_version(peer)
     }
@@ -0,0 +1,493 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Ethereum node records (EIP-778).
+//!
+//! A node record is a list of key/value pairs describing a node, signed with the node key and
+//! versioned by a sequence number, so that newer records of a node replace older ones. Only the
+//! "v4" identity scheme (secp256k1 signatures over the keccak hash of the content) is supported.
+
+use crypto::{
+    publickey::{recover, sign, KeyPair, Public, Secret, Signature},
+    Keccak256,
+};
+use error::{Error, ErrorKind};
+use ethereum_types::H256;
+use rlp::{Rlp, RlpStream};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+use NodeId;
+
+/// Maximum size of an encoded node record.
+pub const MAX_NODE_RECORD_SIZE: usize = 300;
+
+const TEXT_PREFIX: &str = "enr:";
+
+/// Keys describing the identity and the endpoint of a node, maintained by `LocalNodeRecord`.
+const RESERVED_KEYS: [&[u8]; 8] = [
+    b"id",
+    b"secp256k1",
+    b"ip",
+    b"tcp",
+    b"udp",
+    b"ip6",
+    b"tcp6",
+    b"udp6",
+];
+
+/// Signed node record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeRecord {
+    seq: u64,
+    /// Values by key, each a single RLP item.
+    pairs: BTreeMap<Vec<u8>, Vec<u8>>,
+    signature: [u8; 64],
+    id: NodeId,
+}
+
+impl NodeRecord {
+    /// Sign a record with the given sequence number and pairs. The identity scheme and the public
+    /// key are added to the pairs. Values have to be RLP items.
+    pub fn signed(
+        secret: &Secret,
+        seq: u64,
+        mut pairs: BTreeMap<Vec<u8>, Vec<u8>>,
+    ) -> Result<NodeRecord, Error> {
+        let keys = KeyPair::from_secret(secret.clone())?;
+        pairs.insert(b"id".to_vec(), rlp::encode(&"v4"));
+        pairs.insert(
+            b"secp256k1".to_vec(),
+            rlp::encode(&&compress(keys.public())[..]),
+        );
+        if !pairs.values().all(|value| is_item(value)) {
+            bail!(ErrorKind::InvalidNodeRecord("value is not an RLP item"));
+        }
+
+        let message = H256(content(seq, &pairs).keccak256());
+        let signature = sign(secret, &message)?;
+        let mut record = NodeRecord {
+            seq,
+            pairs,
+            signature: [0u8; 64],
+            id: *keys.public(),
+        };
+        record.signature.copy_from_slice(&signature[..64]);
+        if record.encode().len() > MAX_NODE_RECORD_SIZE {
+            bail!(ErrorKind::InvalidNodeRecord("node record too large"));
+        }
+        Ok(record)
+    }
+
+    /// Decode a record, checking its signature.
+    pub fn decode(bytes: &[u8]) -> Result<NodeRecord, Error> {
+        const MALFORMED: ErrorKind = ErrorKind::InvalidNodeRecord("malformed node record");
+
+        if bytes.len() > MAX_NODE_RECORD_SIZE {
+            bail!(ErrorKind::InvalidNodeRecord("node record too large"));
+        }
+        let rlp = Rlp::new(bytes);
+        let count = rlp.item_count()?;
+        if count < 2 || count % 2 != 0 {
+            bail!(MALFORMED);
+        }
+        let signature = rlp.at(0)?.data()?;
+        if signature.len() != 64 {
+            bail!(MALFORMED);
+        }
+        let seq: u64 = rlp.val_at(1)?;
+        let mut pairs = BTreeMap::new();
+        for pair in (2..count).step_by(2) {
+            let key: Vec<u8> = rlp.val_at(pair)?;
+            if pairs.keys().next_back().is_some_and(|last| *last >= key) {
+                bail!(ErrorKind::InvalidNodeRecord("node record keys not sorted"));
+            }
+            pairs.insert(key, rlp.at(pair + 1)?.as_raw().to_vec());
+        }
+
+        if pairs.get(&b"id"[..]) != Some(&rlp::encode(&"v4")) {
+            bail!(ErrorKind::InvalidNodeRecord("unsupported identity scheme"));
+        }
+        let key = match pairs.get(&b"secp256k1"[..]) {
+            Some(key) => Rlp::new(key).data()?.to_vec(),
+            None => bail!(ErrorKind::InvalidNodeRecord(
+                "node record without public key"
+            )),
+        };
+        let message = H256(content(seq, &pairs).keccak256());
+        let (r, s) = (
+            H256::from_slice(&signature[..32]),
+            H256::from_slice(&signature[32..]),
+        );
+        let id = (0..2)
+            .filter_map(|v| recover(&Signature::from_rsv(&r, &s, v), &message).ok())
+            .find(|public| compress(public)[..] == key[..])
+            .ok_or(ErrorKind::InvalidNodeRecord(
+                "invalid node record signature",
+            ))?;
+
+        let mut record = NodeRecord {
+            seq,
+            pairs,
+            signature: [0u8; 64],
+            id,
+        };
+        record.signature.copy_from_slice(signature);
+        Ok(record)
+    }
+
+    /// RLP encoding of the record.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut s = RlpStream::new_list(2 + 2 * self.pairs.len());
+        s.append(&&self.signature[..]);
+        append_pairs(&mut s, self.seq, &self.pairs);
+        s.out()
+    }
+
+    /// Sequence number, higher in newer records of the node.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Id of the node the record was signed by.
+    pub fn id(&self) -> &NodeId {
+        &self.id
+    }
+
+    /// Value of a key as an RLP item.
+    pub fn get(&self, key: &[u8]) -> Option<Rlp<'_>> {
+        self.pairs.get(key).map(|value| Rlp::new(value))
+    }
+
+    /// Advertised TCP endpoint, IPv4 preferred.
+    pub fn tcp_address(&self) -> Option<SocketAddr> {
+        self.address(b"ip", b"tcp")
+            .or_else(|| self.address(b"ip6", b"tcp6"))
+    }
+
+    /// Advertised UDP endpoint, IPv4 preferred.
+    pub fn udp_address(&self) -> Option<SocketAddr> {
+        self.address(b"ip", b"udp")
+            .or_else(|| self.address(b"ip6", b"udp6"))
+    }
+
+    fn address(&self, ip: &[u8], port: &[u8]) -> Option<SocketAddr> {
+        let octets = self.get(ip)?.data().ok()?.to_vec();
+        let ip = match octets.len() {
+            4 => IpAddr::from(<[u8; 4]>::try_from(&octets[..]).ok()?),
+            16 => IpAddr::from(<[u8; 16]>::try_from(&octets[..]).ok()?),
+            _ => return None,
+        };
+        Some(SocketAddr::new(ip, self.get(port)?.as_val().ok()?))
+    }
+}
+
+/// Text form: `enr:` followed by the URL-safe base64 of the encoding, without padding.
+impl fmt::Display for NodeRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", TEXT_PREFIX, base64_encode(&self.encode()))
+    }
+}
+
+impl FromStr for NodeRecord {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = s
+            .strip_prefix(TEXT_PREFIX)
+            .and_then(base64_decode)
+            .ok_or(ErrorKind::InvalidNodeRecord("invalid node record text"))?;
+        NodeRecord::decode(&bytes)
+    }
+}
+
+/// Record of the local node. Keeps the endpoint and the entries set by subsystems, signing a new
+/// record with a higher sequence number whenever they change.
+pub struct LocalNodeRecord {
+    secret: Secret,
+    /// Endpoint entries.
+    endpoint: BTreeMap<Vec<u8>, Vec<u8>>,
+    /// Entries set by subsystems.
+    extensions: BTreeMap<Vec<u8>, Vec<u8>>,
+    record: NodeRecord,
+}
+
+impl LocalNodeRecord {
+    /// Record of the node with key `secret`, without endpoint, starting at sequence number `seq`.
+    pub fn new(secret: Secret, seq: u64) -> Result<LocalNodeRecord, Error> {
+        let record = NodeRecord::signed(&secret, seq, BTreeMap::new())?;
+        Ok(LocalNodeRecord {
+            secret,
+            endpoint: BTreeMap::new(),
+            extensions: BTreeMap::new(),
+            record,
+        })
+    }
+
+    /// The current record.
+    pub fn record(&self) -> &NodeRecord {
+        &self.record
+    }
+
+    /// Advertise the given TCP address and UDP port. The endpoint is removed if the address is
+    /// unspecified. Returns whether the record changed.
+    pub fn set_endpoint(&mut self, address: &SocketAddr, udp_port: u16) -> Result<bool, Error> {
+        let mut endpoint = BTreeMap::new();
+        let (ip_key, ip, tcp_key, udp_key) = match address.ip() {
+            IpAddr::V4(ip) => ("ip", ip.octets().to_vec(), "tcp", "udp"),
+            IpAddr::V6(ip) => ("ip6", ip.octets().to_vec(), "tcp6", "udp6"),
+        };
+        if !address.ip().is_unspecified() {
+            endpoint.insert(ip_key.as_bytes().to_vec(), rlp::encode(&ip));
+            endpoint.insert(tcp_key.as_bytes().to_vec(), rlp::encode(&address.port()));
+            endpoint.insert(udp_key.as_bytes().to_vec(), rlp::encode(&udp_port));
+        }
+        if endpoint == self.endpoint {
+            return Ok(false);
+        }
+        self.update(endpoint, self.extensions.clone())
+    }
+
+    /// Set an entry, the value being an RLP item. The keys of the identity and the endpoint
+    /// can't be set. Returns whether the record changed.
+    pub fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<bool, Error> {
+        if RESERVED_KEYS.contains(&key) {
+            bail!(ErrorKind::InvalidNodeRecord("reserved key"));
+        }
+        if self.extensions.get(key) == Some(&value) {
+            return Ok(false);
+        }
+        let mut extensions = self.extensions.clone();
+        extensions.insert(key.to_vec(), value);
+        self.update(self.endpoint.clone(), extensions)
+    }
+
+    /// Remove an entry set with `set`. Returns whether the record changed.
+    pub fn remove(&mut self, key: &[u8]) -> Result<bool, Error> {
+        if !self.extensions.contains_key(key) {
+            return Ok(false);
+        }
+        let mut extensions = self.extensions.clone();
+        extensions.remove(key);
+        self.update(self.endpoint.clone(), extensions)
+    }
+
+    fn update(
+        &mut self,
+        endpoint: BTreeMap<Vec<u8>, Vec<u8>>,
+        extensions: BTreeMap<Vec<u8>, Vec<u8>>,
+    ) -> Result<bool, Error> {
+        let pairs = endpoint
+            .iter()
+            .chain(extensions.iter())
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        self.record = NodeRecord::signed(&self.secret, self.record.seq + 1, pairs)?;
+        self.endpoint = endpoint;
+        self.extensions = extensions;
+        Ok(true)
+    }
+}
+
+fn content(seq: u64, pairs: &BTreeMap<Vec<u8>, Vec<u8>>) -> Vec<u8> {
+    let mut s = RlpStream::new_list(1 + 2 * pairs.len());
+    append_pairs(&mut s, seq, pairs);
+    s.out()
+}
+
+fn append_pairs(s: &mut RlpStream, seq: u64, pairs: &BTreeMap<Vec<u8>, Vec<u8>>) {
+    s.append(&seq);
+    for (key, value) in pairs {
+        s.append(key);
+        s.append_raw(value, 1);
+    }
+}
+
+fn is_item(value: &[u8]) -> bool {
+    Rlp::new(value)
+        .payload_info()
+        .is_ok_and(|info| info.header_len + info.value_len == value.len())
+}
+
+fn compress(public: &Public) -> [u8; 33] {
+    let public = public.as_bytes();
+    let mut compressed = [0u8; 33];
+    compressed[0] = 2 + (public[63] & 1);
+    compressed[1..].copy_from_slice(&public[..32]);
+    compressed
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len() * 4 / 3 + 1);
+    let (mut buffer, mut bits) = (0u32, 0u8);
+    for byte in data {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 6 {
+            bits -= 6;
+            out.push(BASE64_ALPHABET[((buffer >> bits) & 0x3f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE64_ALPHABET[((buffer << (6 - bits)) & 0x3f) as usize] as char);
+    }
+    out
+}
+
+fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut buffer, mut bits) = (0u32, 0u8);
+    for b in s.trim_end_matches('=').bytes() {
+        let value = BASE64_ALPHABET.iter().position(|c| *c == b)? as u32;
+        buffer = (buffer << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::publickey::{Generator, Random};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn local_record() -> (KeyPair, LocalNodeRecord) {
+        let keys = Random.generate();
+        let record = LocalNodeRecord::new(keys.secret().clone(), 1).unwrap();
+        (keys, record)
+    }
+
+    fn round_trip(record: &NodeRecord) -> NodeRecord {
+        let decoded: NodeRecord = record.to_string().parse().unwrap();
+        assert_eq!(decoded, *record);
+        assert_eq!(NodeRecord::decode(&record.encode()).unwrap(), *record);
+        decoded
+    }
+
+    #[test]
+    fn eip778_example_record() {
+        let text = "enr:-IS4QHCYrYZbAKWCBRlAy5zzaDZXJBGkcnh4MHcBFZntXNFrdvJjX04jRzjzCBOonrkTfj499SZuOh8R33Ls8RRcy5wBgmlkgnY0gmlwhH8AAAGJc2VjcDI1NmsxoQPKY0yuDUmstAHYpMa2_oxVtw0RW_QAdpzBQA8yWM0xOIN1ZHCCdl8";
+        let record: NodeRecord = text.parse().unwrap();
+
+        assert_eq!(record.seq(), 1);
+        assert_eq!(
+            record.udp_address(),
+            Some(SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), 30303))
+        );
+        assert_eq!(record.tcp_address(), None);
+        assert_eq!(record.to_string(), text);
+
+        // signatures are deterministic, signing the same content with the example key gives the same record
+        let secret =
+            Secret::from_str("b71c71a67e1177ad4e901695e1b4b9ee17ae16c6668d313eac2f96dbcda3f291")
+                .unwrap();
+        let mut pairs = BTreeMap::new();
+        pairs.insert(b"ip".to_vec(), rlp::encode(&&[127u8, 0, 0, 1][..]));
+        pairs.insert(b"udp".to_vec(), rlp::encode(&30303u16));
+        assert_eq!(NodeRecord::signed(&secret, 1, pairs).unwrap(), record);
+    }
+
+    #[test]
+    fn rejects_tampered_record() {
+        let (_, mut local) = local_record();
+        local.set(b"foo", rlp::encode(&1u8)).unwrap();
+        let mut encoded = local.record().encode();
+        let last = encoded.len() - 1;
+        encoded[last] ^= 1;
+
+        assert_matches!(
+            *NodeRecord::decode(&encoded).unwrap_err().kind(),
+            ErrorKind::InvalidNodeRecord(_)
+        );
+        assert!("enr:!".parse::<NodeRecord>().is_err());
+    }
+
+    #[test]
+    fn endpoint_changes_bump_sequence() {
+        let (keys, mut local) = local_record();
+        assert_eq!(local.record().seq(), 1);
+        assert_eq!(local.record().id(), keys.public());
+
+        let address = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 1).into(), 30303);
+        assert!(local.set_endpoint(&address, 30301).unwrap());
+        let record = round_trip(local.record());
+        assert_eq!(record.seq(), 2);
+        assert_eq!(record.id(), keys.public());
+        assert_eq!(record.tcp_address(), Some(address));
+        assert_eq!(
+            record.udp_address(),
+            Some(SocketAddr::new(address.ip(), 30301))
+        );
+
+        // unchanged endpoints leave the record alone
+        assert!(!local.set_endpoint(&address, 30301).unwrap());
+        assert_eq!(local.record().seq(), 2);
+
+        let address = SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 30303);
+        assert!(local.set_endpoint(&address, 30303).unwrap());
+        let record = round_trip(local.record());
+        assert_eq!(record.seq(), 3);
+        assert_eq!(record.tcp_address(), Some(address));
+        assert!(record.get(b"ip").is_none());
+
+        let unspecified = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 30303);
+        assert!(local.set_endpoint(&unspecified, 30303).unwrap());
+        assert_eq!(round_trip(local.record()).tcp_address(), None);
+    }
+
+    #[test]
+    fn extension_entries() {
+        let (_, mut local) = local_record();
+        let address = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 1).into(), 30303);
+        local.set_endpoint(&address, 30303).unwrap();
+
+        assert!(local.set(b"eth", rlp::encode_list(&[1u64, 2])).unwrap());
+        assert!(!local.set(b"eth", rlp::encode_list(&[1u64, 2])).unwrap());
+        let record = round_trip(local.record());
+        assert_eq!(record.seq(), 3);
+        assert_eq!(
+            record.get(b"eth").unwrap().as_list::<u64>().unwrap(),
+            vec![1, 2]
+        );
+        assert_eq!(record.tcp_address(), Some(address));
+
+        assert!(local.remove(b"eth").unwrap());
+        assert!(!local.remove(b"eth").unwrap());
+        let record = round_trip(local.record());
+        assert_eq!(record.seq(), 4);
+        assert!(record.get(b"eth").is_none());
+
+        assert_matches!(
+            *local.set(b"tcp", rlp::encode(&1u16)).unwrap_err().kind(),
+            ErrorKind::InvalidNodeRecord("reserved key")
+        );
+        // entries making the record too large are refused, leaving the record as it is
+        assert!(local.set(b"big", rlp::encode(&vec![0u8; 300])).is_err());
+        assert_eq!(local.record().seq(), 4);
+        assert!(local.record().get(b"big").is_none());
+    }
+}
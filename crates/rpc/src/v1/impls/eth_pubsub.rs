@@ -108,12 +108,12 @@ where
     C: EngineInfo,
 {
     fn notify(executor: &Executor, subscriber: &Client, result: pubsub::Result) {
-        executor.spawn(
-            subscriber
-                .notify(Ok(result))
-                .map(|_| ())
-                .map_err(|e| warn!(target: "rpc", "Unable to send notification: {e}")),
-        );
+        let subscriber = subscriber.clone();
+        executor.spawn_03(async move {
+            if let Err(e) = subscriber.notify(Ok(result)) {
+                warn!(target: "rpc", "Unable to send notification: {e}");
+            }
+        });
     }
 
     fn notify_heads(&self, headers: &[(encoded::Header, BTreeMap<String, String>)]) {
@@ -157,6 +157,7 @@ where
             connected: sync_status.num_peers,
             max: sync_status.current_max_peers(*num_peers_range.start(), *num_peers_range.end()),
             peers,
+            clients: sync_status.clients,
         })
     }
 
@@ -297,16 +298,22 @@ where
         Err(errors::deprecated("Use `parity_allTransaction` instead."))
     }
 
-    fn pending_transactions_stats(&self) -> Result<BTreeMap<H256, TransactionStats>> {
-        let stats = self.sync.pending_transactions_stats();
+    fn pending_transactions_stats(
+        &self,
+        verbose: Option<bool>,
+    ) -> Result<BTreeMap<H256, TransactionStats>> {
+        let stats = self.sync.pending_transactions_stats(verbose.unwrap_or(false));
         Ok(stats
             .into_iter()
             .map(|(hash, stats)| (hash, stats.into()))
             .collect())
     }
 
-    fn new_transactions_stats(&self) -> Result<BTreeMap<H256, TransactionStats>> {
-        let stats = self.sync.new_transactions_stats();
+    fn new_transactions_stats(
+        &self,
+        verbose: Option<bool>,
+    ) -> Result<BTreeMap<H256, TransactionStats>> {
+        let stats = self.sync.new_transactions_stats(verbose.unwrap_or(false));
         Ok(stats
             .into_iter()
             .map(|(hash, stats)| (hash, stats.into()))
@@ -337,6 +344,10 @@ where
         self.sync.enode().ok_or_else(errors::network_disabled)
     }
 
+    fn node_record(&self) -> Result<String> {
+        self.sync.node_record().ok_or_else(errors::network_disabled)
+    }
+
     fn chain_status(&self) -> Result<ChainStatus> {
         let chain_info = self.client.chain_info();
 
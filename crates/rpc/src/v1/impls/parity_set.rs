@@ -176,6 +176,19 @@ where
         }
     }
 
+    fn start_peer_capture(&self, node_id: String, max_bytes: usize, name: String) -> Result<bool> {
+        match self.net.start_packet_capture(node_id, max_bytes, name) {
+            Ok(()) => Ok(true),
+            Err(e) => Err(errors::invalid_params("Peer capture", e)),
+        }
+    }
+
+    fn stop_peer_capture(&self, node_id: String) -> Result<bool> {
+        self.net
+            .stop_packet_capture(node_id)
+            .map_err(|e| errors::invalid_params("Node id", e))
+    }
+
     fn drop_non_reserved_peers(&self) -> Result<bool> {
         self.net.deny_unreserved_peers();
         Ok(true)
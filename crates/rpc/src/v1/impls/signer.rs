@@ -75,13 +75,13 @@ impl<D: Dispatcher + 'static> SignerClient<D> {
                     .map(Into::into)
                     .collect::<Vec<ConfirmationRequest>>();
                 for subscription in subs.lock().values() {
-                    let subscription: &Sink<_> = subscription;
-                    executor.spawn(
-                        subscription
-                            .notify(Ok(requests.clone()))
-                            .map(|_| ())
-                            .map_err(|e| warn!(target: "rpc", "Unable to send notification: {e}")),
-                    );
+                    let subscription: Sink<_> = subscription.clone();
+                    let requests = requests.clone();
+                    executor.spawn_03(async move {
+                        if let Err(e) = subscription.notify(Ok(requests)) {
+                            warn!(target: "rpc", "Unable to send notification: {e}");
+                        }
+                    });
                 }
             }
         });
@@ -56,6 +56,9 @@ impl SnapshotService for TestSnapshotService {
     fn chunk(&self, _hash: H256) -> Option<Bytes> {
         None
     }
+    fn is_manifest_complete(&self) -> bool {
+        false
+    }
     fn restoration_status(&self) -> RestorationStatus {
         *self.status.lock()
     }
@@ -16,12 +16,18 @@
 
 //! Test implementation of `SyncProvider`.
 
-use ethereum_types::{H256, H512};
 use crate::network::client_version::ClientVersion;
+use crate::sync::{
+    EthProtocolInfo, ForkConfirmation, PeerInfo, SyncProvider, SyncState, SyncStatus,
+    TransactionDirection, TransactionPeerStats, TransactionStats,
+};
+use ethereum_types::{H256, H512};
 use parking_lot::RwLock;
 use stats::{PrometheusMetrics, PrometheusRegistry};
-use std::collections::BTreeMap;
-use crate::sync::{EthProtocolInfo, PeerInfo, SyncProvider, SyncState, SyncStatus, TransactionStats};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, UNIX_EPOCH},
+};
 
 /// `TestSyncProvider` config.
 pub struct Config {
@@ -48,6 +54,7 @@ impl TestSyncProvider {
                 start_block_number: 0,
                 last_imported_block_number: None,
                 highest_block_number: None,
+                highest_block_hinted: false,
                 blocks_total: 0,
                 blocks_received: 0,
                 num_peers: config.num_peers,
@@ -56,6 +63,33 @@ impl TestSyncProvider {
                 snapshot_chunks_done: 0,
                 last_imported_old_block_number: None,
                 item_sizes: BTreeMap::new(),
+                num_policy_rejected_transactions: 0,
+                num_peer_fault_transactions: 0,
+                num_skipped_new_blocks: 0,
+                num_bad_snapshot_chunks: 0,
+                num_fast_path_new_blocks: 0,
+                new_block_import_latency: None,
+                num_status_timeout_drops: 0,
+                num_rejected_sealed_blocks: 0,
+                num_inactive_peers: 0,
+                num_disabled_peers: 0,
+                num_compressed_packets_sent: 0,
+                compressed_bytes_saved: 0,
+                num_dropped_sync_events: 0,
+                num_delayed_requests: 0,
+                num_evicted_delayed_requests: 0,
+                num_expired_delayed_requests: 0,
+                num_tx_relay_peers: 0,
+                num_sync_only_peers: 0,
+                clients: Default::default(),
+                headers_backfilled: 0,
+                headers_backfill_target: 0,
+                send_errors: Default::default(),
+                packet_stats: Vec::new(),
+                stall_recoveries: Default::default(),
+                branch_probe: Default::default(),
+                deep_reorg: None,
+                observer_mode: false,
             }),
         }
     }
@@ -89,6 +123,16 @@ impl SyncProvider for TestSyncProvider {
                     version: 63,
                     difficulty: Some(40.into()),
                     head: H256::from_low_u64_be(50),
+                    eth_version: 63,
+                    par_version: 0,
+                    confirmation: ForkConfirmation::Confirmed,
+                    network_id: 1,
+                    genesis: H256::zero(),
+                    snapshot_number: None,
+                    snapshot_hash: None,
+                    block_set: None,
+                    client_version: ClientVersion::from("Parity-Ethereum/1/v2.4.0/linux/rustc"),
+                    fee_market: None,
                 }),
             },
             PeerInfo {
@@ -101,6 +145,16 @@ impl SyncProvider for TestSyncProvider {
                     version: 65,
                     difficulty: None,
                     head: H256::from_low_u64_be(60),
+                    eth_version: 65,
+                    par_version: 0,
+                    confirmation: ForkConfirmation::Confirmed,
+                    network_id: 1,
+                    genesis: H256::zero(),
+                    snapshot_number: None,
+                    snapshot_hash: None,
+                    block_set: None,
+                    client_version: ClientVersion::from("Open-Ethereum/2/v2.4.0/linux/rustc"),
+                    fee_market: None,
                 }),
             },
         ]
@@ -110,30 +164,54 @@ impl SyncProvider for TestSyncProvider {
         None
     }
 
-    fn pending_transactions_stats(&self) -> BTreeMap<H256, TransactionStats> {
+    fn node_record(&self) -> Option<String> {
+        None
+    }
+
+    fn pending_transactions_stats(&self, verbose: bool) -> BTreeMap<H256, TransactionStats> {
         map![
             H256::from_low_u64_be(1) => TransactionStats {
                 first_seen: 10,
                 propagated_to: map![
                     H512::from_low_u64_be(128) => 16
                 ],
+                peers: if verbose {
+                    Some(vec![
+                        TransactionPeerStats {
+                            peer_id: 2,
+                            node_id: None,
+                            direction: TransactionDirection::Received,
+                            timestamp: UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+                        },
+                        TransactionPeerStats {
+                            peer_id: 3,
+                            node_id: Some(H512::from_low_u64_be(128)),
+                            direction: TransactionDirection::Sent,
+                            timestamp: UNIX_EPOCH + Duration::from_secs(1_600_000_001),
+                        },
+                    ])
+                } else {
+                    None
+                },
             },
             H256::from_low_u64_be(5) => TransactionStats {
                 first_seen: 16,
                 propagated_to: map![
                     H512::from_low_u64_be(16) => 1
                 ],
+                peers: if verbose { Some(vec![]) } else { None },
             }
         ]
     }
 
-    fn new_transactions_stats(&self) -> BTreeMap<H256, TransactionStats> {
+    fn new_transactions_stats(&self, verbose: bool) -> BTreeMap<H256, TransactionStats> {
         map![
             H256::from_low_u64_be(1) => TransactionStats {
                 first_seen: 10,
                 propagated_to: map![
                     H512::from_low_u64_be(128) => 2
                 ],
+                peers: if verbose { Some(vec![]) } else { None },
             }
         ]
     }
@@ -38,4 +38,10 @@ impl ManageNetwork for TestManageNetwork {
         25..=50
     }
     fn with_proto_context(&self, _: ProtocolId, _: &mut dyn FnMut(&dyn NetworkContext)) {}
+    fn start_packet_capture(&self, _: String, _: usize, _: String) -> Result<(), String> {
+        Err("Peer is not connected".into())
+    }
+    fn stop_packet_capture(&self, _: String) -> Result<bool, String> {
+        Ok(false)
+    }
 }
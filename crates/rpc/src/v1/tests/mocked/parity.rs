@@ -209,13 +209,24 @@ fn rpc_parity_chain() {
     assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
 }
 
+#[test]
+fn rpc_parity_node_record_without_network() {
+    let deps = Dependencies::new();
+    let io = deps.default_client();
+
+    let request = r#"{"jsonrpc": "2.0", "method": "parity_nodeRecord", "params":[], "id": 1}"#;
+    let response = r#"{"jsonrpc":"2.0","error":{"code":-32000,"message":"Network is disabled or not yet up."},"id":1}"#;
+
+    assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
+}
+
 #[test]
 fn rpc_parity_net_peers() {
     let deps = Dependencies::new();
     let io = deps.default_client();
 
     let request = r#"{"jsonrpc": "2.0", "method": "parity_netPeers", "params":[], "id": 1}"#;
-    let response = r#"{"jsonrpc":"2.0","result":{"active":0,"connected":120,"max":50,"peers":[{"caps":["eth/63","eth/64"],"id":"node1","name":{"ParityClient":{"can_handle_large_requests":true,"compiler":"rustc","identity":"1","name":"Parity-Ethereum","os":"linux","semver":"2.4.0"}},"network":{"localAddress":"127.0.0.1:8888","remoteAddress":"127.0.0.1:7777"},"protocols":{"eth":{"difficulty":"0x28","head":"0000000000000000000000000000000000000000000000000000000000000032","version":63}}},{"caps":["eth/64","eth/65"],"id":null,"name":{"Other":"Open-Ethereum/2/v2.4.0/linux/rustc"},"network":{"localAddress":"127.0.0.1:3333","remoteAddress":"Handshake"},"protocols":{"eth":{"difficulty":null,"head":"000000000000000000000000000000000000000000000000000000000000003c","version":65}}}]},"id":1}"#;
+    let response = r#"{"jsonrpc":"2.0","result":{"active":0,"connected":120,"max":50,"peers":[{"caps":["eth/63","eth/64"],"id":"node1","name":{"ParityClient":{"can_handle_large_requests":true,"compiler":"rustc","identity":"1","name":"Parity-Ethereum","os":"linux","semver":"2.4.0"}},"network":{"localAddress":"127.0.0.1:8888","remoteAddress":"127.0.0.1:7777"},"protocols":{"eth":{"blockSet":null,"clientVersion":"Parity-Ethereum/1/v2.4.0/linux/rustc","confirmation":"confirmed","difficulty":"0x28","ethVersion":63,"feeMarket":null,"genesis":"0000000000000000000000000000000000000000000000000000000000000000","head":"0000000000000000000000000000000000000000000000000000000000000032","networkId":1,"parVersion":0,"snapshotHash":null,"snapshotNumber":null,"version":63}}},{"caps":["eth/64","eth/65"],"id":null,"name":{"Other":"Open-Ethereum/2/v2.4.0/linux/rustc"},"network":{"localAddress":"127.0.0.1:3333","remoteAddress":"Handshake"},"protocols":{"eth":{"blockSet":null,"clientVersion":"Open-Ethereum/2/v2.4.0/linux/rustc","confirmation":"confirmed","difficulty":null,"ethVersion":65,"feeMarket":null,"genesis":"0000000000000000000000000000000000000000000000000000000000000000","head":"000000000000000000000000000000000000000000000000000000000000003c","networkId":1,"parVersion":0,"snapshotHash":null,"snapshotNumber":null,"version":65}}}],"clients":{"total":0,"unknown":0,"clients":{},"versions":{}}},"id":1}"#;
 
     assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
 }
@@ -447,6 +458,17 @@ fn rpc_parity_transactions_stats() {
     assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
 }
 
+#[test]
+fn rpc_parity_transactions_stats_verbose() {
+    let deps = Dependencies::new();
+    let io = deps.default_client();
+
+    let request = r#"{"jsonrpc": "2.0", "method": "parity_pendingTransactionsStats", "params":[true], "id": 1}"#;
+    let response = r#"{"jsonrpc":"2.0","result":{"0x0000000000000000000000000000000000000000000000000000000000000001":{"firstSeen":10,"propagatedTo":{"0x00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000080":16},"peers":[{"peerId":2,"nodeId":null,"direction":"received","timestamp":1600000000},{"peerId":3,"nodeId":"0x00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000080","direction":"sent","timestamp":1600000001}]},"0x0000000000000000000000000000000000000000000000000000000000000005":{"firstSeen":16,"propagatedTo":{"0x00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000010":1},"peers":[]}},"id":1}"#;
+
+    assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
+}
+
 #[test]
 fn rpc_parity_local_transactions() {
     use crate::types::transaction::{Transaction, TypedTransaction};
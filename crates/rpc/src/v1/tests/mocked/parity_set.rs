@@ -161,6 +161,37 @@ fn rpc_parity_set_transactions_limit() {
     assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
 }
 
+#[test]
+fn rpc_parity_start_peer_capture_of_unknown_peer() {
+    let miner = miner_service();
+    let client = client_service();
+    let network = network_service();
+
+    let mut io = IoHandler::new();
+    io.extend_with(parity_set_client(&client, &miner, &network).to_delegate());
+
+    let request = r#"{"jsonrpc": "2.0", "method": "parity_startPeerCapture", "params":["0x00", 1048576, "peer.capture"], "id": 1}"#;
+    let response = r#"{"jsonrpc":"2.0","error":{"code":-32602,"message":"Couldn't parse parameters: Peer capture","data":"\"Peer is not connected\""},"id":1}"#;
+
+    assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
+}
+
+#[test]
+fn rpc_parity_stop_peer_capture() {
+    let miner = miner_service();
+    let client = client_service();
+    let network = network_service();
+
+    let mut io = IoHandler::new();
+    io.extend_with(parity_set_client(&client, &miner, &network).to_delegate());
+
+    let request =
+        r#"{"jsonrpc": "2.0", "method": "parity_stopPeerCapture", "params":["0x00"], "id": 1}"#;
+    let response = r#"{"jsonrpc":"2.0","result":false,"id":1}"#;
+
+    assert_eq!(io.handle_request_sync(request), Some(response.to_owned()));
+}
+
 #[test]
 fn rpc_parity_set_hash_content() {
     let miner = miner_service();
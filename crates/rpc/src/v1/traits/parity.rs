@@ -152,12 +152,18 @@ pub trait Parity {
     fn future_transactions(&self) -> Result<Vec<Transaction>>;
 
     /// Returns propagation statistics on transactions pending in the queue.
+    /// Includes the per-peer breakdown if the optional verbosity flag is set.
     #[rpc(name = "parity_pendingTransactionsStats")]
-    fn pending_transactions_stats(&self) -> Result<BTreeMap<H256, TransactionStats>>;
+    fn pending_transactions_stats(
+        &self,
+        _: Option<bool>,
+    ) -> Result<BTreeMap<H256, TransactionStats>>;
 
     /// Returns propagation statistics on transactions recently added into the queue.
+    /// Includes the per-peer breakdown if the optional verbosity flag is set.
     #[rpc(name = "parity_newTransactionsStats")]
-    fn new_transactions_stats(&self) -> Result<BTreeMap<H256, TransactionStats>>;
+    fn new_transactions_stats(&self, _: Option<bool>)
+        -> Result<BTreeMap<H256, TransactionStats>>;
 
     /// Returns a list of current and past local transactions with status details.
     #[rpc(name = "parity_localTransactions")]
@@ -183,6 +189,10 @@ pub trait Parity {
     #[rpc(name = "parity_enode")]
     fn enode(&self) -> Result<String>;
 
+    /// Get the node record (EIP-778) of this node, in its `enr:` text form.
+    #[rpc(name = "parity_nodeRecord")]
+    fn node_record(&self) -> Result<String>;
+
     /// Get the current chain status.
     #[rpc(name = "parity_chainStatus")]
     fn chain_status(&self) -> Result<ChainStatus>;
@@ -77,6 +77,16 @@ pub trait ParitySet {
     #[rpc(name = "parity_removeReservedPeer")]
     fn remove_reserved_peer(&self, _: String) -> Result<bool>;
 
+    /// Start capturing the decrypted packets of a connected peer, given by its node id, to a new
+    /// file of the given name in the capture directory of the network until the given number
+    /// of bytes is written.
+    #[rpc(name = "parity_startPeerCapture")]
+    fn start_peer_capture(&self, _: String, _: usize, _: String) -> Result<bool>;
+
+    /// Stop capturing the packets of a peer. Returns false if they were not captured.
+    #[rpc(name = "parity_stopPeerCapture")]
+    fn stop_peer_capture(&self, _: String) -> Result<bool>;
+
     /// Drop all non-reserved peers.
     #[rpc(name = "parity_dropNonReservedPeers")]
     fn drop_non_reserved_peers(&self) -> Result<bool>;
@@ -14,12 +14,15 @@
 // You should have received a copy of the GNU General Public License
 // along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::network::client_version::ClientVersion;
-use std::collections::BTreeMap;
+use crate::network::client_version::{ClientBreakdown, ClientVersion};
+use std::{collections::BTreeMap, time::UNIX_EPOCH};
 
 use ethereum_types::{H512, U256};
 use serde::{Serialize, Serializer};
-use crate::sync::{self, PeerInfo as SyncPeerInfo, TransactionStats as SyncTransactionStats};
+use crate::sync::{
+    self, PeerInfo as SyncPeerInfo, TransactionPeerStats as SyncTransactionPeerStats,
+    TransactionStats as SyncTransactionStats,
+};
 
 /// Sync info
 #[derive(Default, Debug, Serialize, PartialEq)]
@@ -48,6 +51,8 @@ pub struct Peers {
     pub max: u32,
     /// Detailed information on peers
     pub peers: Vec<PeerInfo>,
+    /// Number of connected peers by client software
+    pub clients: ClientBreakdown,
 }
 
 /// Peer connection information
@@ -84,6 +89,7 @@ pub struct PeerProtocolsInfo {
 
 /// Peer Ethereum protocol information
 #[derive(Default, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct EthProtocolInfo {
     /// Negotiated ethereum protocol version
     pub version: u32,
@@ -91,6 +97,54 @@ pub struct EthProtocolInfo {
     pub difficulty: Option<U256>,
     /// SHA3 of peer best block hash
     pub head: String,
+    /// Negotiated eth protocol version
+    pub eth_version: u32,
+    /// Negotiated par protocol version, 0 if not supported
+    pub par_version: u32,
+    /// Fork block confirmation status
+    pub confirmation: String,
+    /// Network id advertised by the peer
+    pub network_id: u64,
+    /// Genesis hash advertised by the peer
+    pub genesis: String,
+    /// Best snapshot block number if advertised
+    pub snapshot_number: Option<u64>,
+    /// Best snapshot hash if advertised
+    pub snapshot_hash: Option<String>,
+    /// Blocks the peer is currently downloading from, if any
+    pub block_set: Option<String>,
+    /// Version of the software the peer is running
+    pub client_version: String,
+    /// Gas limit and base fee of the highest validated header received from the peer
+    pub fee_market: Option<PeerFeeMarket>,
+}
+
+/// Gas limit and base fee of a header served by a peer
+#[derive(Default, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerFeeMarket {
+    /// Number of the header
+    pub number: u64,
+    /// Hash of the header
+    pub hash: String,
+    /// Gas limit of the header
+    pub gas_limit: U256,
+    /// Base fee of the header, if past the EIP-1559 transition
+    pub base_fee: Option<U256>,
+    /// Whether the values can follow our chain, null if the header is too far ahead to tell
+    pub consistent: Option<bool>,
+}
+
+impl From<sync::PeerFeeMarket> for PeerFeeMarket {
+    fn from(fee_market: sync::PeerFeeMarket) -> Self {
+        PeerFeeMarket {
+            number: fee_market.number,
+            hash: format!("{:x}", fee_market.hash),
+            gas_limit: fee_market.gas_limit,
+            base_fee: fee_market.base_fee,
+            consistent: fee_market.consistent,
+        }
+    }
 }
 
 impl From<sync::EthProtocolInfo> for EthProtocolInfo {
@@ -99,6 +153,16 @@ impl From<sync::EthProtocolInfo> for EthProtocolInfo {
             version: info.version,
             difficulty: info.difficulty,
             head: format!("{:x}", info.head),
+            eth_version: info.eth_version.into(),
+            par_version: info.par_version.into(),
+            confirmation: info.confirmation.to_string().into(),
+            network_id: info.network_id,
+            genesis: format!("{:x}", info.genesis),
+            snapshot_number: info.snapshot_number,
+            snapshot_hash: info.snapshot_hash.map(|hash| format!("{hash:x}")),
+            block_set: info.block_set.map(|set| set.to_string().into()),
+            client_version: info.client_version.to_string(),
+            fee_market: info.fee_market.map(Into::into),
         }
     }
 }
@@ -132,6 +196,33 @@ pub struct TransactionStats {
     pub first_seen: u64,
     /// Peers this transaction was propagated to with count.
     pub propagated_to: BTreeMap<H512, usize>,
+    /// Most recent exchanges of this transaction with peers, oldest first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peers: Option<Vec<TransactionPeerStats>>,
+}
+
+/// Direction of a transaction exchange with a peer.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionDirection {
+    /// Transaction was sent to the peer.
+    Sent,
+    /// Transaction was received from the peer.
+    Received,
+}
+
+/// Single exchange of a transaction with a peer.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionPeerStats {
+    /// Session id of the peer.
+    pub peer_id: usize,
+    /// Public node id of the peer.
+    pub node_id: Option<H512>,
+    /// Whether the transaction was sent or received.
+    pub direction: TransactionDirection,
+    /// Unix timestamp of the exchange in seconds.
+    pub timestamp: u64,
 }
 
 impl From<SyncPeerInfo> for PeerInfo {
@@ -156,6 +247,27 @@ impl From<SyncTransactionStats> for TransactionStats {
         TransactionStats {
             first_seen: s.first_seen,
             propagated_to: s.propagated_to.into_iter().collect(),
+            peers: s
+                .peers
+                .map(|peers| peers.into_iter().map(Into::into).collect()),
+        }
+    }
+}
+
+impl From<SyncTransactionPeerStats> for TransactionPeerStats {
+    fn from(s: SyncTransactionPeerStats) -> Self {
+        TransactionPeerStats {
+            peer_id: s.peer_id,
+            node_id: s.node_id,
+            direction: match s.direction {
+                sync::TransactionDirection::Sent => TransactionDirection::Sent,
+                sync::TransactionDirection::Received => TransactionDirection::Received,
+            },
+            timestamp: s
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
         }
     }
 }
@@ -170,8 +282,10 @@ pub struct ChainStatus {
 
 #[cfg(test)]
 mod tests {
-    use super::{ChainStatus, Peers, SyncInfo, SyncStatus, TransactionStats};
-    use ethereum_types::H512;
+    use super::{ChainStatus, EthProtocolInfo, Peers, SyncInfo, SyncStatus, TransactionStats};
+    use crate::network::client_version::ClientVersion;
+    use crate::sync;
+    use ethereum_types::{H256, H512};
     use serde_json;
     use std::collections::BTreeMap;
 
@@ -191,7 +305,37 @@ mod tests {
         let serialized = serde_json::to_string(&t).unwrap();
         assert_eq!(
             serialized,
-            r#"{"active":0,"connected":0,"max":0,"peers":[]}"#
+            r#"{"active":0,"connected":0,"max":0,"peers":[],"clients":{"total":0,"unknown":0,"clients":{},"versions":{}}}"#
+        );
+    }
+
+    #[test]
+    fn test_serialize_eth_protocol_info() {
+        let t = EthProtocolInfo::from(sync::EthProtocolInfo {
+            version: 66,
+            head: H256::from_low_u64_be(1),
+            difficulty: Some(2.into()),
+            eth_version: 66,
+            par_version: 0,
+            confirmation: sync::ForkConfirmation::Confirmed,
+            network_id: 1,
+            genesis: H256::from_low_u64_be(3),
+            snapshot_number: None,
+            snapshot_hash: None,
+            block_set: Some(sync::BlockSet::NewBlocks),
+            client_version: ClientVersion::from("OpenEthereum/v3.3.0/x86_64-linux-gnu/rustc"),
+            fee_market: Some(sync::PeerFeeMarket {
+                number: 4,
+                hash: H256::from_low_u64_be(4),
+                gas_limit: 30_000_000.into(),
+                base_fee: Some(7.into()),
+                consistent: Some(false),
+            }),
+        });
+        let serialized = serde_json::to_string(&t).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"version":66,"difficulty":"0x2","head":"0000000000000000000000000000000000000000000000000000000000000001","ethVersion":66,"parVersion":0,"confirmation":"confirmed","networkId":1,"genesis":"0000000000000000000000000000000000000000000000000000000000000003","snapshotNumber":null,"snapshotHash":null,"blockSet":"new_blocks","clientVersion":"OpenEthereum/v3.3.0/x86_64-linux-gnu/rustc","feeMarket":{"number":4,"hash":"0000000000000000000000000000000000000000000000000000000000000004","gasLimit":"0x1c9c380","baseFee":"0x7","consistent":false}}"#
         );
     }
 
@@ -228,6 +372,7 @@ mod tests {
             propagated_to: map![
                 H512::from_low_u64_be(10) => 50
             ],
+            peers: None,
         };
 
         let serialized = serde_json::to_string(&stats).unwrap();
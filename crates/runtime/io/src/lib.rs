@@ -86,6 +86,7 @@ extern crate time;
 extern crate timer;
 extern crate tokio;
 
+mod metrics;
 #[cfg(feature = "mio")]
 mod service_mio;
 #[cfg(not(feature = "mio"))]
@@ -203,6 +204,10 @@ where
     }
 }
 
+pub use metrics::{
+    IoStats, SlowHandler, WorkKind, WorkLatency, DEFAULT_SATURATION_PERIOD,
+    DEFAULT_SATURATION_THRESHOLD,
+};
 #[cfg(feature = "mio")]
 pub use service_mio::{
     IoChannel, IoContext, IoManager, IoService, StreamToken, TimerToken, TOKENS_PER_HANDLER,
@@ -216,7 +221,7 @@ mod tests {
     use std::{
         sync::{atomic, Arc},
         thread,
-        time::Duration,
+        time::{Duration, Instant},
     };
 
     // Mio's behaviour is too unstable for this test. Sometimes we have to wait a few milliseconds,
@@ -312,4 +317,57 @@ mod tests {
         thread::sleep(Duration::from_secs(2));
         assert!(handler.0.load(atomic::Ordering::SeqCst) >= 2);
     }
+
+    #[derive(Clone)]
+    struct Flood;
+
+    struct DelayHandler(Duration);
+
+    impl IoHandler<Flood> for DelayHandler {
+        fn message(&self, _io: &IoContext<Flood>, _message: &Flood) {
+            thread::sleep(self.0);
+        }
+    }
+
+    // Send `per_worker` messages per worker at once to a handler taking `delay` for each.
+    fn flood(delay: Duration, per_worker: usize) -> IoStats {
+        let service = IoService::<Flood>::start("Test").expect("Error creating network service");
+        service.set_saturation_warning(Duration::from_millis(20), Duration::from_millis(50));
+        service
+            .register_handler(Arc::new(DelayHandler(delay)))
+            .unwrap();
+
+        let messages = service.stats().workers * per_worker;
+        for _ in 0..messages {
+            service.send_message(Flood).unwrap();
+        }
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while service.stats().message.handled < messages as u64 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        service.stats()
+    }
+
+    #[test]
+    #[cfg_attr(feature = "mio", ignore)]
+    fn slow_handler_saturates_workers() {
+        let stats = flood(Duration::from_millis(10), 30);
+        assert_eq!(stats.message.handled, stats.workers as u64 * 30);
+        assert_eq!(stats.queued, 0);
+        assert!(stats.max_queued > stats.workers);
+        assert!(stats.message.queue_time >= Duration::from_millis(20));
+        assert!(stats.message.execution_time >= Duration::from_millis(5));
+        assert!(stats.saturation_warnings >= 1);
+    }
+
+    #[test]
+    #[cfg_attr(feature = "mio", ignore)]
+    fn fast_handler_keeps_latencies_low() {
+        let stats = flood(Duration::from_millis(0), 30);
+        assert_eq!(stats.message.handled, stats.workers as u64 * 30);
+        assert!(stats.message.queue_time < Duration::from_millis(20));
+        assert!(stats.message.execution_time < Duration::from_millis(5));
+        assert_eq!(stats.saturation_warnings, 0);
+        assert_eq!(stats.timer.handled, 0);
+    }
 }
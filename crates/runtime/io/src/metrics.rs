@@ -0,0 +1,343 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Queue depth and latencies of the work handed to the IO workers.
+
+use parking_lot::Mutex;
+use std::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Average queue time above which the workers are considered saturated.
+pub const DEFAULT_SATURATION_THRESHOLD: Duration = Duration::from_millis(500);
+/// Time the workers have to stay saturated before a warning is logged.
+pub const DEFAULT_SATURATION_PERIOD: Duration = Duration::from_secs(10);
+/// Minimum time between two saturation warnings.
+const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+/// A new sample weighs 1/2^EWMA_SHIFT in the moving averages.
+const EWMA_SHIFT: u32 = 3;
+
+/// Kind of work handed to the IO workers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkKind {
+    /// A timer fired.
+    Timer,
+    /// A message was broadcast.
+    Message,
+    /// A stream can be read from.
+    Readable,
+    /// A stream can be written to.
+    Writable,
+    /// A stream was closed.
+    Hup,
+}
+
+const WORK_KINDS: usize = 5;
+
+/// Latencies of one kind of work, as exponentially weighted moving averages.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WorkLatency {
+    /// Number of handler calls.
+    pub handled: u64,
+    /// Time from queueing the work until a handler starts on it.
+    pub queue_time: Duration,
+    /// Time spent in the handler.
+    pub execution_time: Duration,
+}
+
+/// A handler call which took long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlowHandler {
+    /// Id of the handler.
+    pub handler_id: usize,
+    /// Kind of work handled.
+    pub kind: WorkKind,
+    /// Time spent in the handler.
+    pub duration: Duration,
+}
+
+/// IO worker metrics. The workers take work from one shared queue.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct IoStats {
+    /// Number of worker threads.
+    pub workers: usize,
+    /// Number of workers running a handler right now.
+    pub busy_workers: usize,
+    /// Work items queued and not picked up by a worker yet.
+    pub queued: usize,
+    /// Highest number of queued work items seen.
+    pub max_queued: usize,
+    /// Timer calls.
+    pub timer: WorkLatency,
+    /// Message calls.
+    pub message: WorkLatency,
+    /// Stream read calls.
+    pub readable: WorkLatency,
+    /// Stream write calls.
+    pub writable: WorkLatency,
+    /// Stream close calls.
+    pub hup: WorkLatency,
+    /// The slowest handler call since the last saturation warning.
+    pub slowest_handler: Option<SlowHandler>,
+    /// Number of saturation warnings logged.
+    pub saturation_warnings: u64,
+}
+
+#[derive(Default)]
+struct KindMetrics {
+    handled: AtomicU64,
+    queue_micros: AtomicU64,
+    execution_micros: AtomicU64,
+}
+
+impl KindMetrics {
+    fn latency(&self) -> WorkLatency {
+        WorkLatency {
+            handled: self.handled.load(Ordering::Relaxed),
+            queue_time: Duration::from_micros(self.queue_micros.load(Ordering::Relaxed)),
+            execution_time: Duration::from_micros(self.execution_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_micros().min(u64::MAX as u128) as u64
+}
+
+/// Fold a sample into a moving average, returns the new average.
+fn update_average(average: &AtomicU64, sample: u64) -> u64 {
+    let update = |old: u64| {
+        let new = if sample >= old {
+            old + ((sample - old) >> EWMA_SHIFT)
+        } else {
+            old - ((old - sample) >> EWMA_SHIFT)
+        };
+        Some(new)
+    };
+    let old = average
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, update)
+        .expect("the update never fails; qed");
+    update(old).expect("the update never fails; qed")
+}
+
+/// Collects the metrics of the IO workers. Updates are atomics only, the slowest handler
+/// call is locked for only when a slower one is seen.
+pub(crate) struct IoMetrics {
+    epoch: Instant,
+    workers: usize,
+    busy: AtomicUsize,
+    queued: AtomicUsize,
+    max_queued: AtomicUsize,
+    kinds: [KindMetrics; WORK_KINDS],
+    slowest_micros: AtomicU64,
+    slowest: Mutex<Option<SlowHandler>>,
+    saturation_threshold: AtomicU64,
+    saturation_period: AtomicU64,
+    // the times below are microseconds since `epoch` plus one, zero stands for none
+    saturated_since: AtomicU64,
+    last_warning: AtomicU64,
+    warnings: AtomicU64,
+}
+
+impl IoMetrics {
+    pub fn new(workers: usize) -> Self {
+        IoMetrics {
+            epoch: Instant::now(),
+            workers,
+            busy: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+            max_queued: AtomicUsize::new(0),
+            kinds: Default::default(),
+            slowest_micros: AtomicU64::new(0),
+            slowest: Mutex::new(None),
+            saturation_threshold: AtomicU64::new(micros(DEFAULT_SATURATION_THRESHOLD)),
+            saturation_period: AtomicU64::new(micros(DEFAULT_SATURATION_PERIOD)),
+            saturated_since: AtomicU64::new(0),
+            last_warning: AtomicU64::new(0),
+            warnings: AtomicU64::new(0),
+        }
+    }
+
+    /// Warn once the average queue time stays above `threshold` for `period`.
+    pub fn set_saturation_warning(&self, threshold: Duration, period: Duration) {
+        self.saturation_threshold
+            .store(micros(threshold), Ordering::Relaxed);
+        self.saturation_period
+            .store(micros(period), Ordering::Relaxed);
+    }
+
+    /// A work item was queued, returns the time to pass to `start`.
+    pub fn queued(&self) -> Instant {
+        let queued = self.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max_queued.fetch_max(queued, Ordering::Relaxed);
+        Instant::now()
+    }
+
+    /// A work item was taken from the queue.
+    pub fn dequeued(&self) {
+        let _ = self
+            .queued
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |queued| {
+                Some(queued.saturating_sub(1))
+            });
+    }
+
+    /// A handler starts on work queued at `queued_at`, returns the start time to pass
+    /// to `finish`.
+    pub fn start(&self, kind: WorkKind, queued_at: Instant) -> Instant {
+        let now = Instant::now();
+        self.busy.fetch_add(1, Ordering::Relaxed);
+        let queue_time = micros(now.saturating_duration_since(queued_at));
+        let average = update_average(&self.kinds[kind as usize].queue_micros, queue_time);
+        self.check_saturation(average, now);
+        now
+    }
+
+    /// The handler `handler_id` started at `started` is done.
+    pub fn finish(&self, kind: WorkKind, handler_id: usize, started: Instant) {
+        let duration = started.elapsed();
+        let kind_metrics = &self.kinds[kind as usize];
+        kind_metrics.handled.fetch_add(1, Ordering::Relaxed);
+        update_average(&kind_metrics.execution_micros, micros(duration));
+        self.busy.fetch_sub(1, Ordering::Relaxed);
+
+        if micros(duration) > self.slowest_micros.load(Ordering::Relaxed) {
+            let mut slowest = self.slowest.lock();
+            if slowest.is_none_or(|s| duration > s.duration) {
+                self.slowest_micros
+                    .store(micros(duration), Ordering::Relaxed);
+                *slowest = Some(SlowHandler {
+                    handler_id,
+                    kind,
+                    duration,
+                });
+            }
+        }
+    }
+
+    fn since_epoch(&self, now: Instant) -> u64 {
+        micros(now.saturating_duration_since(self.epoch)) + 1
+    }
+
+    fn check_saturation(&self, average_queue_time: u64, now: Instant) {
+        if average_queue_time <= self.saturation_threshold.load(Ordering::Relaxed) {
+            self.saturated_since.store(0, Ordering::Relaxed);
+            return;
+        }
+        let now = self.since_epoch(now);
+        let since = match self.saturated_since.compare_exchange(
+            0,
+            now,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => return,
+            Err(since) => since,
+        };
+        if now - since < self.saturation_period.load(Ordering::Relaxed) {
+            return;
+        }
+        let last_warning = self.last_warning.load(Ordering::Relaxed);
+        if last_warning != 0 && now - last_warning < micros(WARNING_INTERVAL) {
+            return;
+        }
+        // a single worker logs
+        if self
+            .last_warning
+            .compare_exchange(last_warning, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+        self.warnings.fetch_add(1, Ordering::Relaxed);
+        let slowest = self.slowest.lock().take();
+        self.slowest_micros.store(0, Ordering::Relaxed);
+        let slowest = match slowest {
+            Some(s) => format!(
+                "{:?} work of handler {} taking {:?}",
+                s.kind, s.handler_id, s.duration
+            ),
+            None => "none finished".into(),
+        };
+        warn!(
+            target: "io",
+            "IO workers saturated: work waits {:?} on average before being handled, {} queued. Slowest recent handler call: {}",
+            Duration::from_micros(average_queue_time),
+            self.queued.load(Ordering::Relaxed),
+            slowest
+        );
+    }
+
+    pub fn stats(&self) -> IoStats {
+        let latency = |kind: WorkKind| self.kinds[kind as usize].latency();
+        IoStats {
+            workers: self.workers,
+            busy_workers: self.busy.load(Ordering::Relaxed),
+            queued: self.queued.load(Ordering::Relaxed),
+            max_queued: self.max_queued.load(Ordering::Relaxed),
+            timer: latency(WorkKind::Timer),
+            message: latency(WorkKind::Message),
+            readable: latency(WorkKind::Readable),
+            writable: latency(WorkKind::Writable),
+            hup: latency(WorkKind::Hup),
+            slowest_handler: *self.slowest.lock(),
+            saturation_warnings: self.warnings.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_follow_the_samples() {
+        let average = AtomicU64::new(0);
+        assert_eq!(update_average(&average, 800), 100);
+        for _ in 0..100 {
+            update_average(&average, 800);
+        }
+        assert!(average.load(Ordering::Relaxed) > 790);
+        for _ in 0..100 {
+            update_average(&average, 0);
+        }
+        assert!(average.load(Ordering::Relaxed) < 10);
+    }
+
+    #[test]
+    fn tracks_queue_depth_and_slowest_handler() {
+        let metrics = IoMetrics::new(2);
+        let queued_at = metrics.queued();
+        metrics.queued();
+        assert_eq!(metrics.stats().queued, 2);
+
+        metrics.dequeued();
+        let started = metrics.start(WorkKind::Timer, queued_at);
+        assert_eq!(metrics.stats().busy_workers, 1);
+        metrics.finish(WorkKind::Timer, 3, started - Duration::from_millis(5));
+
+        let stats = metrics.stats();
+        assert_eq!(stats.queued, 1);
+        assert_eq!(stats.max_queued, 2);
+        assert_eq!(stats.busy_workers, 0);
+        assert_eq!(stats.timer.handled, 1);
+        assert_eq!(stats.message.handled, 0);
+        let slowest = stats.slowest_handler.unwrap();
+        assert_eq!((slowest.handler_id, slowest.kind), (3, WorkKind::Timer));
+        assert!(slowest.duration >= Duration::from_millis(5));
+    }
+}
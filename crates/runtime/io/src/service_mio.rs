@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::metrics::{IoMetrics, IoStats};
 use crate::worker::{Work, WorkType, Worker};
 use crate::IoError;
 use crate::IoHandler;
@@ -41,6 +42,7 @@ pub type HandlerId = usize;
 /// Maximum number of tokens a handler can use
 pub const TOKENS_PER_HANDLER: usize = 16384;
 const MAX_HANDLERS: usize = 8;
+const WORKERS: usize = 4;
 
 /// Messages used to communicate with the event loop from other threads.
 #[derive(Clone)]
@@ -198,6 +200,7 @@ where
     workers: Vec<Worker>,
     worker_channel: deque::Worker<Work<Message>>,
     work_ready: Arc<Condvar>,
+    metrics: Arc<IoMetrics>,
 }
 
 impl<Message> IoManager<Message>
@@ -205,17 +208,17 @@ where
     Message: Send + Sync + 'static,
 {
     /// Creates a new instance and registers it with the event loop.
-    pub fn start(
+    pub(crate) fn start(
         symbolic_name: &str,
         event_loop: &mut EventLoop<IoManager<Message>>,
         handlers: Arc<RwLock<Slab<Arc<dyn IoHandler<Message>>>>>,
+        metrics: Arc<IoMetrics>,
     ) -> Result<(), IoError> {
         let worker = deque::Worker::new_fifo();
         let stealer = worker.stealer();
-        let num_workers = 4;
         let work_ready_mutex = Arc::new(Mutex::new(()));
         let work_ready = Arc::new(Condvar::new());
-        let workers = (0..num_workers)
+        let workers = (0..WORKERS)
             .map(|i| {
                 Worker::new(
                     &format!("{symbolic_name}{i}"),
@@ -223,6 +226,7 @@ where
                     IoChannel::new(event_loop.channel(), Arc::downgrade(&handlers)),
                     work_ready.clone(),
                     work_ready_mutex.clone(),
+                    metrics.clone(),
                 )
             })
             .collect();
@@ -233,6 +237,7 @@ where
             worker_channel: worker,
             workers,
             work_ready,
+            metrics,
         };
         event_loop.run(&mut io)?;
         Ok(())
@@ -256,6 +261,7 @@ where
                     token: token_id,
                     handler: handler.clone(),
                     handler_id: handler_index,
+                    queued_at: self.metrics.queued(),
                 });
             } else {
                 if events.is_readable() {
@@ -264,6 +270,7 @@ where
                         token: token_id,
                         handler: handler.clone(),
                         handler_id: handler_index,
+                        queued_at: self.metrics.queued(),
                     });
                 }
                 if events.is_writable() {
@@ -272,6 +279,7 @@ where
                         token: token_id,
                         handler: handler.clone(),
                         handler_id: handler_index,
+                        queued_at: self.metrics.queued(),
                     });
                 }
             }
@@ -298,6 +306,7 @@ where
                     token: token_id,
                     handler: handler.clone(),
                     handler_id: handler_index,
+                    queued_at: self.metrics.queued(),
                 });
                 self.work_ready.notify_all();
             }
@@ -398,6 +407,7 @@ where
                             token: 0,
                             handler,
                             handler_id: id,
+                            queued_at: self.metrics.queued(),
                         });
                     }
                 }
@@ -525,6 +535,7 @@ where
     thread: Option<JoinHandle<()>>,
     host_channel: Mutex<Sender<IoMessage<Message>>>,
     handlers: Arc<RwLock<Slab<Arc<dyn IoHandler<Message>>>>>,
+    metrics: Arc<IoMetrics>,
 }
 
 impl<Message> IoService<Message>
@@ -532,21 +543,25 @@ where
     Message: Send + Sync + 'static,
 {
     /// Starts IO event loop
-    pub fn start(symbolic_name: &'static str) -> Result<IoService<Message>, IoError> {
+    pub fn start(symbolic_name: &str) -> Result<IoService<Message>, IoError> {
+        let symbolic_name = symbolic_name.to_owned();
         let mut config = EventLoopBuilder::new();
         config.messages_per_tick(1024);
         let mut event_loop = config.build().expect("Error creating event loop");
         let channel = event_loop.channel();
         let handlers = Arc::new(RwLock::new(Slab::with_capacity(MAX_HANDLERS)));
         let h = handlers.clone();
+        let metrics = Arc::new(IoMetrics::new(WORKERS));
+        let m = metrics.clone();
         let thread = thread::spawn(move || {
-            IoManager::<Message>::start(symbolic_name, &mut event_loop, h)
+            IoManager::<Message>::start(&symbolic_name, &mut event_loop, h, m)
                 .expect("Error starting IO service");
         });
         Ok(IoService {
             thread: Some(thread),
             host_channel: Mutex::new(channel),
             handlers,
+            metrics,
         })
     }
 
@@ -593,6 +608,17 @@ where
             Arc::downgrade(&self.handlers),
         )
     }
+
+    /// Queue depth and latencies of the work handed to the workers.
+    pub fn stats(&self) -> IoStats {
+        self.metrics.stats()
+    }
+
+    /// Log a warning once work waits on average more than `threshold` for `period` before
+    /// being handled. Defaults to `DEFAULT_SATURATION_THRESHOLD` and `DEFAULT_SATURATION_PERIOD`.
+    pub fn set_saturation_warning(&self, threshold: Duration, period: Duration) {
+        self.metrics.set_saturation_warning(threshold, period);
+    }
 }
 
 impl<Message> Drop for IoService<Message>
@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::metrics::{IoMetrics, IoStats, WorkKind};
 use crate::IoError;
 use crate::IoHandler;
 use chrono::TimeDelta;
@@ -25,7 +26,7 @@ use slab::Slab;
 use std::{
     sync::{Arc, Weak},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use timer::{Guard as TimerGuard, Timer};
 
@@ -104,7 +105,10 @@ where
     /// Broadcast a message to other IO clients
     pub fn message(&self, message: Message) -> Result<(), IoError> {
         if let Some(ref channel) = *self.shared.channel.lock() {
-            channel.push(WorkTask::UserMessage(Arc::new(message)));
+            channel.push((
+                self.shared.metrics.queued(),
+                WorkTask::UserMessage(Arc::new(message)),
+            ));
         }
         for thread in self.shared.threads.read().iter() {
             thread.unpark();
@@ -155,7 +159,10 @@ where
     pub fn send(&self, message: Message) -> Result<(), IoError> {
         if let Some(shared) = self.shared.upgrade() {
             match *shared.channel.lock() {
-                Some(ref channel) => channel.push(WorkTask::UserMessage(Arc::new(message))),
+                Some(ref channel) => channel.push((
+                    shared.metrics.queued(),
+                    WorkTask::UserMessage(Arc::new(message)),
+                )),
                 None => self.send_sync(message)?,
             };
 
@@ -189,7 +196,7 @@ where
     fn send_raw(&self, message: WorkTask<Message>) {
         if let Some(shared) = self.shared.upgrade() {
             if let Some(ref channel) = *shared.channel.lock() {
-                channel.push(message);
+                channel.push((shared.metrics.queued(), message));
             }
 
             for thread in shared.threads.read().iter() {
@@ -230,10 +237,15 @@ where
     // List of created timers. We need to keep them in a data struct so that we can cancel them if
     // necessary.
     timers: Mutex<FnvHashMap<TimerToken, TimerGuard>>,
-    // Channel used to send work to the worker threads.
-    channel: Mutex<Option<deque::Worker<WorkTask<Message>>>>,
+    // Channel used to send work to the worker threads, with the time it was queued at.
+    channel: Mutex<Option<deque::Worker<QueuedTask<Message>>>>,
+    // Queue depth and latencies of the work.
+    metrics: IoMetrics,
 }
 
+// Work and the time it was queued at.
+type QueuedTask<Message> = (Instant, WorkTask<Message>);
+
 // Messages used to communicate with the event loop from other threads.
 enum WorkTask<Message>
 where
@@ -267,11 +279,12 @@ where
     Message: Send + Sync + 'static,
 {
     /// Starts IO event loop
-    pub fn start(_symbolic_name: &'static str) -> Result<IoService<Message>, IoError> {
+    pub fn start(_symbolic_name: &str) -> Result<IoService<Message>, IoError> {
         // This minimal implementation of IoService does have named Workers
         // like the mio-dependent one does, so _symbolic_name is ignored.
         let tx = deque::Worker::new_fifo();
         let rx = tx.stealer();
+        let workers = num_cpus::get();
 
         let shared = Arc::new(Shared {
             handlers: RwLock::new(Slab::with_capacity(MAX_HANDLERS)),
@@ -279,9 +292,10 @@ where
             timer: Mutex::new(Timer::new()),
             timers: Mutex::new(FnvHashMap::default()),
             channel: Mutex::new(Some(tx)),
+            metrics: IoMetrics::new(workers),
         });
 
-        let thread_joins = (0..num_cpus::get())
+        let thread_joins = (0..workers)
             .map(|_| {
                 let rx = rx.clone();
                 let shared = shared.clone();
@@ -307,7 +321,7 @@ where
         let mut thread_joins = self.thread_joins.lock();
         if let Some(channel) = channel {
             for _ in 0..thread_joins.len() {
-                channel.push(WorkTask::Shutdown);
+                channel.push((self.shared.metrics.queued(), WorkTask::Shutdown));
             }
         }
         for thread in thread_joins.drain(..) {
@@ -337,7 +351,10 @@ where
     /// Send a message over the network. Normaly `HostIo::send` should be used. This can be used from non-io threads.
     pub fn send_message(&self, message: Message) -> Result<(), IoError> {
         if let Some(ref channel) = *self.shared.channel.lock() {
-            channel.push(WorkTask::UserMessage(Arc::new(message)));
+            channel.push((
+                self.shared.metrics.queued(),
+                WorkTask::UserMessage(Arc::new(message)),
+            ));
         }
         for thread in self.shared.threads.read().iter() {
             thread.unpark();
@@ -345,6 +362,19 @@ where
         Ok(())
     }
 
+    /// Queue depth and latencies of the work handed to the workers.
+    pub fn stats(&self) -> IoStats {
+        self.shared.metrics.stats()
+    }
+
+    /// Log a warning once work waits on average more than `threshold` for `period` before
+    /// being handled. Defaults to `DEFAULT_SATURATION_THRESHOLD` and `DEFAULT_SATURATION_PERIOD`.
+    pub fn set_saturation_warning(&self, threshold: Duration, period: Duration) {
+        self.shared
+            .metrics
+            .set_saturation_warning(threshold, period);
+    }
+
     /// Create a new message channel
     #[inline]
     pub fn channel(&self) -> IoChannel<Message> {
@@ -363,33 +393,44 @@ where
     }
 }
 
-fn do_work<Message>(shared: &Arc<Shared<Message>>, rx: deque::Stealer<WorkTask<Message>>)
+fn do_work<Message>(shared: &Arc<Shared<Message>>, rx: deque::Stealer<(Instant, WorkTask<Message>)>)
 where
     Message: Send + Sync + 'static,
 {
     loop {
-        match rx.steal() {
+        let (queued_at, task) = match rx.steal() {
             deque::Steal::Retry => continue,
-            deque::Steal::Empty => thread::park(),
-            deque::Steal::Success(WorkTask::Shutdown) => break,
-            deque::Steal::Success(WorkTask::UserMessage(message)) => {
+            deque::Steal::Empty => {
+                thread::park();
+                continue;
+            }
+            deque::Steal::Success(work) => work,
+        };
+        shared.metrics.dequeued();
+        match task {
+            WorkTask::Shutdown => break,
+            WorkTask::UserMessage(message) => {
                 for id in 0..MAX_HANDLERS {
                     if let Some(handler) = shared.handlers.read().get(id) {
                         let ctxt = IoContext {
                             handler: id,
                             shared: shared.clone(),
                         };
+                        let started = shared.metrics.start(WorkKind::Message, queued_at);
                         handler.message(&ctxt, &message);
+                        shared.metrics.finish(WorkKind::Message, id, started);
                     }
                 }
             }
-            deque::Steal::Success(WorkTask::TimerTrigger { handler_id, token }) => {
+            WorkTask::TimerTrigger { handler_id, token } => {
                 if let Some(handler) = shared.handlers.read().get(handler_id) {
                     let ctxt = IoContext {
                         handler: handler_id,
                         shared: shared.clone(),
                     };
+                    let started = shared.metrics.start(WorkKind::Timer, queued_at);
                     handler.timeout(&ctxt, token);
+                    shared.metrics.finish(WorkKind::Timer, handler_id, started);
                 }
             }
         }
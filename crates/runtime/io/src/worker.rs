@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::metrics::{IoMetrics, WorkKind};
 use crate::service_mio::{HandlerId, IoChannel, IoContext};
 use crate::{IoHandler, LOCAL_STACK_SIZE};
 use std::{
@@ -22,6 +23,7 @@ use std::{
         Arc,
     },
     thread::{self, JoinHandle},
+    time::Instant,
 };
 
 use parking_lot::{Condvar, Mutex};
@@ -36,11 +38,24 @@ pub enum WorkType<Message> {
     Message(Arc<Message>),
 }
 
+impl<Message> WorkType<Message> {
+    fn kind(&self) -> WorkKind {
+        match *self {
+            WorkType::Readable => WorkKind::Readable,
+            WorkType::Writable => WorkKind::Writable,
+            WorkType::Hup => WorkKind::Hup,
+            WorkType::Timeout => WorkKind::Timer,
+            WorkType::Message(_) => WorkKind::Message,
+        }
+    }
+}
+
 pub struct Work<Message> {
     pub work_type: WorkType<Message>,
     pub token: usize,
     pub handler_id: HandlerId,
     pub handler: Arc<dyn IoHandler<Message>>,
+    pub queued_at: Instant,
 }
 
 /// An IO worker thread
@@ -60,6 +75,7 @@ impl Worker {
         channel: IoChannel<Message>,
         wait: Arc<Condvar>,
         wait_mutex: Arc<Mutex<()>>,
+        metrics: Arc<IoMetrics>,
     ) -> Worker
     where
         Message: Send + Sync + 'static,
@@ -77,6 +93,7 @@ impl Worker {
                 .name(format!("Worker {name}"))
                 .spawn(move || {
                     LOCAL_STACK_SIZE.with(|val| val.set(STACK_SIZE));
+                    stats::profiler::register_current_thread("io-worker");
                     let runtime = tokio::runtime::Builder::new_current_thread()
                         .enable_all()
                         .build()
@@ -95,7 +112,8 @@ impl Worker {
                             while !deleting.load(AtomicOrdering::SeqCst) {
                                 match stealer.steal() {
                                     deque::Steal::Success(work) => {
-                                        Worker::do_work(work, channel.clone())
+                                        metrics.dequeued();
+                                        Worker::do_work(work, channel.clone(), &metrics)
                                     }
                                     deque::Steal::Retry => {}
                                     deque::Steal::Empty => break,
@@ -116,10 +134,12 @@ impl Worker {
         worker
     }
 
-    fn do_work<Message>(work: Work<Message>, channel: IoChannel<Message>)
+    fn do_work<Message>(work: Work<Message>, channel: IoChannel<Message>, metrics: &IoMetrics)
     where
         Message: Send + Sync + 'static,
     {
+        let kind = work.work_type.kind();
+        let started = metrics.start(kind, work.queued_at);
         match work.work_type {
             WorkType::Readable => {
                 work.handler
@@ -142,6 +162,7 @@ impl Worker {
                     .message(&IoContext::new(channel, work.handler_id), &*message);
             }
         }
+        metrics.finish(kind, work.handler_id, started);
     }
 }
 
@@ -0,0 +1,196 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Source of the current time, so that time-based logic can be driven by hand in tests.
+
+use futures::{future::BoxFuture, Future};
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Source of the current time.
+pub trait Clock: Send + Sync {
+    /// Current monotonic time.
+    fn now(&self) -> Instant;
+
+    /// Current wall-clock time.
+    fn system_now(&self) -> SystemTime;
+
+    /// Resolves once `now()` reached `deadline`.
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()>;
+}
+
+/// The clock of the operating system. Sleeping requires a tokio runtime with the timer enabled.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        // the timer is created on first poll, inside the runtime driving the sleep
+        Box::pin(async move { tokio::time::sleep_until(deadline.into()).await })
+    }
+}
+
+#[derive(Debug)]
+struct TestTime {
+    now: Instant,
+    system_now: SystemTime,
+    /// Tasks sleeping until a time not reached yet.
+    sleepers: Vec<Waker>,
+}
+
+/// Clock standing still until advanced by hand. Clones share the time.
+#[derive(Debug, Clone)]
+pub struct TestClock {
+    time: Arc<Mutex<TestTime>>,
+}
+
+impl Default for TestClock {
+    fn default() -> Self {
+        TestClock::new()
+    }
+}
+
+impl TestClock {
+    /// Clock starting at the current time of the operating system.
+    pub fn new() -> Self {
+        TestClock {
+            time: Arc::new(Mutex::new(TestTime {
+                now: Instant::now(),
+                system_now: SystemTime::now(),
+                sleepers: Vec::new(),
+            })),
+        }
+    }
+
+    fn time(&self) -> MutexGuard<'_, TestTime> {
+        self.time.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Move the time forward by `by`, waking the sleeps whose deadline passed.
+    pub fn advance(&self, by: Duration) {
+        let sleepers = {
+            let mut time = self.time();
+            time.now += by;
+            time.system_now += by;
+            std::mem::take(&mut time.sleepers)
+        };
+        for sleeper in sleepers {
+            sleeper.wake();
+        }
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> Instant {
+        self.time().now
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.time().system_now
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        Box::pin(TestSleep {
+            clock: self.clone(),
+            deadline,
+        })
+    }
+}
+
+/// Sleep of a `TestClock`, polled again whenever the clock is advanced.
+struct TestSleep {
+    clock: TestClock,
+    deadline: Instant,
+}
+
+impl Future for TestSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let mut time = self.clock.time();
+        if time.now >= self.deadline {
+            return Poll::Ready(());
+        }
+        if !time.sleepers.iter().any(|w| w.will_wake(cx.waker())) {
+            time.sleepers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    #[test]
+    fn test_clock_advances_by_hand() {
+        let clock = TestClock::new();
+        let (start, system_start) = (clock.now(), clock.system_now());
+        let shared = clock.clone();
+
+        shared.advance(Duration::from_secs(90));
+
+        assert_eq!(clock.now(), start + Duration::from_secs(90));
+        assert_eq!(clock.system_now(), system_start + Duration::from_secs(90));
+    }
+
+    #[test]
+    fn test_sleep_resolves_once_deadline_passed() {
+        let clock = TestClock::new();
+        let deadline = clock.now() + Duration::from_secs(60);
+        let sleep = clock.sleep_until(deadline).shared();
+
+        assert_eq!(sleep.clone().now_or_never(), None);
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(sleep.clone().now_or_never(), None);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(sleep.now_or_never(), Some(()));
+    }
+
+    #[test]
+    fn advance_wakes_sleeping_task() {
+        let clock = TestClock::new();
+        let sleep = clock.sleep_until(clock.now() + Duration::from_secs(3600));
+        let advancing = clock.clone();
+        let advance = std::thread::spawn(move || advancing.advance(Duration::from_secs(3600)));
+
+        futures::executor::block_on(sleep);
+        advance.join().unwrap();
+    }
+
+    #[test]
+    fn system_sleep_until_past_deadline_resolves() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let clock = SystemClock;
+        runtime.block_on(clock.sleep_until(clock.now()));
+    }
+}
@@ -19,11 +19,30 @@
 pub extern crate futures;
 pub extern crate tokio;
 
+mod clock;
+
+pub use clock::{Clock, SystemClock, TestClock};
+
 // Re-export futures01 for backward compatibility
+#[cfg(feature = "compat")]
 pub use futures01;
 
-use futures::Future;
-use std::{fmt, sync::mpsc, thread};
+use futures::{channel::oneshot, Future};
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    fmt,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU8, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, OnceLock,
+    },
+    task::{Context, Poll},
+    thread,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+pub use futures::channel::oneshot::Canceled;
 pub use tokio::{
     runtime::{Builder as TokioRuntimeBuilder, Handle as TokioHandle, Runtime as TokioRuntime},
     time::{sleep as delay, Sleep as Delay},
@@ -50,6 +69,205 @@ fn get_thread_per_future_runtime() -> &'static TokioRuntime {
             .expect("Failed to create thread-per-future runtime")
     })
 }
+
+/// Source of unique identifiers handed out to every `Executor` flavour.
+static NEXT_RUNTIME_ID: AtomicUsize = AtomicUsize::new(1);
+
+fn next_runtime_id() -> usize {
+    NEXT_RUNTIME_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+thread_local! {
+    /// Identifier of the runtime whose task is currently being polled on this thread.
+    static CURRENT_RUNTIME: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Returns the identifier of the runtime driving the task polled on the current thread, if any.
+pub fn current_runtime_id() -> Option<usize> {
+    CURRENT_RUNTIME.with(|c| c.get())
+}
+
+/// Future wrapper marking the current thread as belonging to a runtime while it is polled.
+struct WithRuntimeMarker<F> {
+    id: usize,
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for WithRuntimeMarker<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let id = self.id;
+        let previous = CURRENT_RUNTIME.with(|c| c.replace(Some(id)));
+        let result = self.inner.as_mut().poll(cx);
+        CURRENT_RUNTIME.with(|c| c.set(previous));
+        result
+    }
+}
+
+/// What `safe_block_on` does when it is called from within a runtime task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockOnPolicy {
+    /// Drive the future on the calling worker of a multi-thread runtime, moving the other
+    /// tasks to the remaining workers meanwhile.
+    Offload,
+    /// Refuse to block and return `BlockOnError::InsideRuntime`.
+    Error,
+}
+
+static BLOCK_ON_POLICY: AtomicU8 = AtomicU8::new(0);
+
+/// Sets the process-wide policy applied by `safe_block_on` inside a runtime.
+///
+/// Debug setups may choose `BlockOnPolicy::Error` to surface offending call sites early.
+pub fn set_block_on_policy(policy: BlockOnPolicy) {
+    let value = match policy {
+        BlockOnPolicy::Offload => 0,
+        BlockOnPolicy::Error => 1,
+    };
+    BLOCK_ON_POLICY.store(value, Ordering::Relaxed);
+}
+
+/// Returns the process-wide policy applied by `safe_block_on` inside a runtime.
+pub fn block_on_policy() -> BlockOnPolicy {
+    match BLOCK_ON_POLICY.load(Ordering::Relaxed) {
+        0 => BlockOnPolicy::Offload,
+        _ => BlockOnPolicy::Error,
+    }
+}
+
+/// Error returned by `safe_block_on`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockOnError {
+    /// Blocking was requested from inside a runtime task and the policy forbids it.
+    InsideRuntime,
+    /// Blocking was requested from inside a current-thread runtime, whose IO and timers are
+    /// driven by the calling thread only.
+    CurrentThreadRuntime,
+}
+
+impl fmt::Display for BlockOnError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlockOnError::InsideRuntime => {
+                write!(fmt, "refusing to block on a future from inside a runtime task")
+            }
+            BlockOnError::CurrentThreadRuntime => {
+                write!(fmt, "refusing to block the thread of a current-thread runtime")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BlockOnError {}
+
+/// Returns true if the current thread is driving a task of any runtime.
+fn inside_runtime() -> bool {
+    current_runtime_id().is_some() || TokioHandle::try_current().is_ok()
+}
+
+/// Blocks the current thread until `fut` completes without deadlocking a runtime.
+///
+/// Outside of any runtime this behaves like a regular `block_on`. When called from within
+/// a runtime task `BlockOnError::InsideRuntime` is returned if `block_on_policy()` forbids
+/// blocking. Otherwise a multi-threaded runtime drives the future itself in
+/// `block_in_place`. A current-thread runtime can not: the future may wait for IO or timers
+/// of that runtime, which nothing drives while its only thread is blocked, so
+/// `BlockOnError::CurrentThreadRuntime` is returned.
+pub fn safe_block_on<F>(fut: F) -> Result<F::Output, BlockOnError>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    if !inside_runtime() {
+        return Ok(create_sync_runtime().block_on(fut));
+    }
+
+    if block_on_policy() == BlockOnPolicy::Error {
+        return Err(BlockOnError::InsideRuntime);
+    }
+
+    match TokioHandle::try_current() {
+        // The scheduler moves other tasks off this worker while it drives `fut`.
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+            Ok(tokio::task::block_in_place(|| handle.block_on(fut)))
+        }
+        Ok(_) => Err(BlockOnError::CurrentThreadRuntime),
+        // Polled by an executor other than tokio, there is no reactor to block.
+        Err(_) => Ok(create_sync_runtime().block_on(fut)),
+    }
+}
+
+/// Number of futures 0.1 spawned through the compatibility layer, by call site.
+fn compat_spawns() -> &'static Mutex<BTreeMap<String, u64>> {
+    static COMPAT_SPAWNS: OnceLock<Mutex<BTreeMap<String, u64>>> = OnceLock::new();
+    COMPAT_SPAWNS.get_or_init(Default::default)
+}
+
+#[cfg(feature = "compat")]
+fn note_compat_spawn(location: &std::panic::Location) {
+    let site = format!("{}:{}", location.file(), location.line());
+    *compat_spawns()
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(site)
+        .or_insert(0) += 1;
+}
+
+/// Blocking tasks an executor runs at once unless configured otherwise.
+pub const DEFAULT_MAX_BLOCKING_TASKS: usize = 64;
+
+static BLOCKING_RUNNING: AtomicUsize = AtomicUsize::new(0);
+static BLOCKING_QUEUED: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of blocking tasks which panicked, by task name.
+fn blocking_panics() -> &'static Mutex<BTreeMap<String, u64>> {
+    static BLOCKING_PANICS: OnceLock<Mutex<BTreeMap<String, u64>>> = OnceLock::new();
+    BLOCKING_PANICS.get_or_init(Default::default)
+}
+
+/// Waits for a free blocking task slot, the wait is counted as queued.
+async fn acquire_blocking_slot(slots: Arc<Semaphore>) -> Option<OwnedSemaphorePermit> {
+    BLOCKING_QUEUED.fetch_add(1, Ordering::Relaxed);
+    let permit = slots.acquire_owned().await.ok();
+    BLOCKING_QUEUED.fetch_sub(1, Ordering::Relaxed);
+    permit
+}
+
+/// Runs a blocking task, a panic is caught and counted under `name`.
+fn run_blocking<F, T>(name: &str, f: F) -> Option<T>
+where
+    F: FnOnce() -> T,
+{
+    BLOCKING_RUNNING.fetch_add(1, Ordering::Relaxed);
+    let result = panic_hook::catch_scoped(name, f);
+    BLOCKING_RUNNING.fetch_sub(1, Ordering::Relaxed);
+    match result {
+        Ok(value) => Some(value),
+        Err(_) => {
+            *blocking_panics()
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(name.to_owned())
+                .or_insert(0) += 1;
+            None
+        }
+    }
+}
+
+/// Executor statistics, shared by all executors of the process.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecutorStats {
+    /// Futures 0.1 spawned through the compatibility layer, by `file:line` of the call site.
+    pub compat_spawns: BTreeMap<String, u64>,
+    /// Blocking tasks running.
+    pub blocking_running: usize,
+    /// Blocking tasks waiting for a free slot.
+    pub blocking_queued: usize,
+    /// Blocking tasks which panicked, by task name.
+    pub blocking_panics: BTreeMap<String, u64>,
+}
+
 /// Runtime for futures.
 ///
 /// Runs in a separate thread.
@@ -60,7 +278,9 @@ pub struct Runtime {
 
 impl Runtime {
     fn new(runtime_bldr: &mut TokioRuntimeBuilder) -> Self {
-        let runtime = runtime_bldr.build().expect(
+        // Futures handed to `safe_block_on` are driven by this runtime, so it needs the
+        // IO and time drivers just like the helper runtimes.
+        let runtime = runtime_bldr.enable_all().build().expect(
             "Building a Tokio runtime will only fail when mio components \
 				cannot be initialized (catastrophic)",
         );
@@ -82,9 +302,7 @@ impl Runtime {
             .expect("tx is transfered to a newly spawned thread.");
 
         Runtime {
-            executor: Executor {
-                inner: Mode::Tokio(executor),
-            },
+            executor: Executor::with_mode(Mode::Tokio(executor)),
             handle: RuntimeHandle {
                 close: Some(stop_tx),
                 handle: Some(thread_handle),
@@ -113,9 +331,7 @@ impl Runtime {
     /// as it avoids deadlocks by running each future in its own thread.
     pub fn with_thread_per_future() -> Self {
         Runtime {
-            executor: Executor {
-                inner: Mode::ThreadPerFuture,
-            },
+            executor: Executor::with_mode(Mode::ThreadPerFuture),
             handle: RuntimeHandle {
                 close: None,
                 handle: None,
@@ -162,37 +378,122 @@ impl fmt::Debug for Mode {
 #[derive(Debug, Clone)]
 pub struct Executor {
     inner: Mode,
+    id: usize,
+    /// Slots of the blocking tasks, shared by the clones of the executor.
+    blocking: Arc<Semaphore>,
 }
 
 impl Executor {
+    fn with_mode(inner: Mode) -> Self {
+        Executor {
+            inner,
+            id: next_runtime_id(),
+            blocking: Arc::new(Semaphore::new(DEFAULT_MAX_BLOCKING_TASKS)),
+        }
+    }
+
     /// Executor for existing runtime.
     ///
     /// Deprecated: Exists only to connect with current JSONRPC implementation.
     pub fn new(executor: TaskExecutor) -> Self {
-        Executor {
-            inner: Mode::Tokio(executor),
-        }
+        Self::with_mode(Mode::Tokio(executor))
     }
 
     /// Synchronous executor, used mostly for tests.
     pub fn new_sync() -> Self {
-        Executor { inner: Mode::Sync }
+        Self::with_mode(Mode::Sync)
     }
 
     /// Spawns a new thread for each future (use only for tests).
     pub fn new_thread_per_future() -> Self {
-        Executor {
-            inner: Mode::ThreadPerFuture,
+        Self::with_mode(Mode::ThreadPerFuture)
+    }
+
+    /// Limit the blocking tasks running at once to `max`, the tasks beyond wait for a free slot.
+    /// The limit is shared with the clones made afterwards.
+    pub fn with_max_blocking_tasks(mut self, max: usize) -> Self {
+        self.blocking = Arc::new(Semaphore::new(max.max(1)));
+        self
+    }
+
+    /// Returns true if the calling code runs inside a task spawned by this executor
+    /// (or one of its clones).
+    pub fn is_current(&self) -> bool {
+        current_runtime_id() == Some(self.id)
+    }
+
+    /// Blocks on `fut` without deadlocking this runtime. See `safe_block_on`.
+    pub fn safe_block_on<F>(&self, fut: F) -> Result<F::Output, BlockOnError>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        safe_block_on(fut)
+    }
+
+    fn mark<F: Future>(&self, fut: F) -> WithRuntimeMarker<F> {
+        WithRuntimeMarker {
+            id: self.id,
+            inner: Box::pin(fut),
         }
     }
 
-    /// Spawn a futures 0.1 future to this runtime (default method for backward compatibility)
-    pub fn spawn<R>(&self, r: R)
+    /// Usage statistics of all executors.
+    pub fn stats(&self) -> ExecutorStats {
+        ExecutorStats {
+            compat_spawns: compat_spawns()
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+            blocking_running: BLOCKING_RUNNING.load(Ordering::Relaxed),
+            blocking_queued: BLOCKING_QUEUED.load(Ordering::Relaxed),
+            blocking_panics: blocking_panics()
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+        }
+    }
+
+    /// Run the blocking closure `f`, e.g. filesystem access or heavy computation, off the
+    /// threads driving futures and resolve to its result. The tokio runtime runs it on its
+    /// blocking thread pool, the synchronous executor in place and the thread per future
+    /// executor on a thread named `name`. Tasks beyond the limit of the executor wait for a
+    /// free slot. Resolves to `Canceled` if the closure panics.
+    pub fn spawn_blocking<F, T>(
+        &self,
+        name: &str,
+        f: F,
+    ) -> impl Future<Output = Result<T, Canceled>>
     where
-        R: futures01::IntoFuture<Item = (), Error = ()> + Send + 'static,
-        R::Future: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
     {
-        self.spawn_01(r);
+        let (tx, rx) = oneshot::channel();
+        let task_name = name.to_owned();
+        let task = move || {
+            if let Some(value) = run_blocking(&task_name, f) {
+                let _ = tx.send(value);
+            }
+        };
+        match self.inner {
+            Mode::Tokio(ref executor) => {
+                let slots = self.blocking.clone();
+                executor.spawn(async move {
+                    let _permit = acquire_blocking_slot(slots).await;
+                    let _ = tokio::task::spawn_blocking(task).await;
+                });
+            }
+            Mode::Sync => task(),
+            Mode::ThreadPerFuture => {
+                let slots = self.blocking.clone();
+                // the task is dropped if the thread cannot be spawned, resolving to `Canceled`
+                let _ = thread::Builder::new().name(name.to_owned()).spawn(move || {
+                    let _permit = futures::executor::block_on(acquire_blocking_slot(slots));
+                    task()
+                });
+            }
+        }
+        rx
     }
 
     /// Spawn a futures 0.3 future to this runtime
@@ -200,6 +501,7 @@ impl Executor {
     where
         R: Future<Output = ()> + Send + 'static,
     {
+        let r = self.mark(r);
         match self.inner {
             Mode::Tokio(ref executor) => {
                 executor.spawn(r);
@@ -216,14 +518,37 @@ impl Executor {
     }
 }
 
-// Compatibility layer for futures 0.1 users
+// Compatibility layer for futures 0.1 users, every use is counted in `ExecutorStats`.
+#[cfg(feature = "compat")]
 impl Executor {
+    /// Spawn a futures 0.1 future to this runtime (default method for backward compatibility)
+    #[deprecated(note = "futures 0.1 support is going away, use `spawn_03`")]
+    #[track_caller]
+    pub fn spawn<R>(&self, r: R)
+    where
+        R: futures01::IntoFuture<Item = (), Error = ()> + Send + 'static,
+        R::Future: Send + 'static,
+    {
+        self.spawn_compat(r, std::panic::Location::caller());
+    }
+
     /// Spawn a futures 0.1 future (for backward compatibility)
+    #[deprecated(note = "futures 0.1 support is going away, use `spawn_03`")]
+    #[track_caller]
     pub fn spawn_01<R>(&self, r: R)
     where
         R: futures01::IntoFuture<Item = (), Error = ()> + Send + 'static,
         R::Future: Send + 'static,
     {
+        self.spawn_compat(r, std::panic::Location::caller());
+    }
+
+    fn spawn_compat<R>(&self, r: R, location: &std::panic::Location)
+    where
+        R: futures01::IntoFuture<Item = (), Error = ()> + Send + 'static,
+        R::Future: Send + 'static,
+    {
+        note_compat_spawn(location);
         // Convert futures 0.1 to futures 0.3
         let future = async move {
             use futures::compat::Future01CompatExt;
@@ -234,34 +559,29 @@ impl Executor {
 }
 
 // Keep the old future::Executor trait implementation for compatibility
+#[cfg(feature = "compat")]
 impl<F> futures01::future::Executor<F> for Executor
 where
     F: futures01::Future<Item = (), Error = ()> + Send + 'static,
 {
+    #[track_caller]
     fn execute(&self, future: F) -> Result<(), futures01::future::ExecuteError<F>> {
+        note_compat_spawn(std::panic::Location::caller());
+        let future_03 = self.mark(async move {
+            use futures::compat::Future01CompatExt;
+            let _ = future.compat().await;
+        });
         match self.inner {
             Mode::Tokio(ref executor) => {
-                let future_03 = async move {
-                    use futures::compat::Future01CompatExt;
-                    let _ = future.compat().await;
-                };
                 executor.spawn(future_03);
                 Ok(())
             }
             Mode::Sync => {
-                let future_03 = async move {
-                    use futures::compat::Future01CompatExt;
-                    let _ = future.compat().await;
-                };
                 create_sync_runtime().block_on(future_03);
                 Ok(())
             }
             Mode::ThreadPerFuture => {
                 thread::spawn(move || {
-                    let future_03 = async move {
-                        use futures::compat::Future01CompatExt;
-                        let _ = future.compat().await;
-                    };
                     get_thread_per_future_runtime().block_on(future_03);
                 });
                 Ok(())
@@ -311,3 +631,158 @@ impl RuntimeHandle {
             .send(());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_block_on_outside_runtime_behaves_like_block_on() {
+        assert_eq!(current_runtime_id(), None);
+        assert_eq!(safe_block_on(async { 42 }), Ok(42));
+    }
+
+    #[test]
+    fn safe_block_on_inside_runtime_task_completes() {
+        let runtime = Runtime::with_default_thread_count();
+        let executor = runtime.executor();
+        let (tx, rx) = mpsc::channel();
+
+        let inner = executor.clone();
+        executor.spawn_03(async move {
+            let is_current = inner.is_current();
+            let result = inner.safe_block_on(async {
+                delay(std::time::Duration::from_millis(10)).await;
+                7
+            });
+            tx.send((is_current, result)).unwrap();
+        });
+
+        let (is_current, result) = rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("safe_block_on must not hang inside the runtime");
+        assert!(is_current);
+        assert_eq!(result, Ok(7));
+        assert!(!executor.is_current());
+    }
+
+    #[test]
+    fn safe_block_on_inside_single_thread_runtime_is_refused() {
+        let runtime = Runtime::with_single_thread();
+        let executor = runtime.executor();
+        let (tx, rx) = mpsc::channel();
+
+        let inner = executor.clone();
+        executor.spawn_03(async move {
+            let result = inner.safe_block_on(async {
+                delay(std::time::Duration::from_millis(10)).await;
+                7
+            });
+            tx.send(result).unwrap();
+        });
+
+        let result = rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("safe_block_on must not hang inside the runtime");
+        assert_eq!(result, Err(BlockOnError::CurrentThreadRuntime));
+    }
+
+    #[cfg(feature = "compat")]
+    #[test]
+    #[allow(deprecated)]
+    fn compat_spawns_are_counted_by_call_site() {
+        let executor = Executor::new_sync();
+        let (tx, rx) = mpsc::channel();
+
+        let site = format!("{}:{}", file!(), line!() + 3);
+        for _ in 0..3 {
+            let tx = tx.clone();
+            executor.spawn_01(futures01::future::lazy(move || tx.send(()).map_err(|_| ())));
+        }
+
+        assert_eq!(rx.try_iter().count(), 3);
+        assert_eq!(executor.stats().compat_spawns.get(&site), Some(&3));
+    }
+
+    #[test]
+    fn spawn_blocking_returns_values() {
+        let runtime = Runtime::with_default_thread_count();
+        let executors = [
+            runtime.executor(),
+            Executor::new_sync(),
+            Executor::new_thread_per_future(),
+        ];
+        for executor in &executors {
+            let value = executor.spawn_blocking("test-value", || 6 * 7);
+            assert_eq!(futures::executor::block_on(value), Ok(42));
+        }
+    }
+
+    #[test]
+    fn spawn_blocking_queues_tasks_over_the_limit() {
+        use std::time::Duration;
+
+        let runtime = Runtime::with_default_thread_count();
+        let executor = runtime.executor().with_max_blocking_tasks(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let most_running = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..6)
+            .map(|i| {
+                let running = running.clone();
+                let most_running = most_running.clone();
+                executor.spawn_blocking("test-limit", move || {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most_running.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    i
+                })
+            })
+            .collect();
+
+        let results = futures::executor::block_on(futures::future::join_all(tasks));
+        assert_eq!(results, (0..6).map(Ok).collect::<Vec<_>>());
+        assert_eq!(most_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn panicking_blocking_task_is_canceled() {
+        static GLOBAL: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        panic_hook::set_with(|msg| GLOBAL.lock().unwrap().push(msg.to_owned()));
+        let runtime = Runtime::with_default_thread_count();
+        let executor = runtime.executor();
+
+        let panicked = executor.spawn_blocking("test-panic", || -> u32 { panic!("blocking task") });
+        assert_eq!(futures::executor::block_on(panicked), Err(Canceled));
+        assert_eq!(executor.stats().blocking_panics.get("test-panic"), Some(&1));
+        // the panic stays out of the process-wide hook
+        assert!(!GLOBAL
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|msg| msg.contains("blocking task")));
+
+        // the runtime keeps running tasks
+        let value = executor.spawn_blocking("test-value", || 1);
+        assert_eq!(futures::executor::block_on(value), Ok(1));
+    }
+
+    #[test]
+    fn is_current_distinguishes_executors() {
+        let first = Runtime::with_single_thread();
+        let second = Executor::new_sync();
+        let (tx, rx) = mpsc::channel();
+
+        let other = second.clone();
+        let own = first.executor();
+        first.executor().spawn_03(async move {
+            tx.send((own.is_current(), other.is_current())).unwrap();
+        });
+
+        assert_eq!(
+            rx.recv_timeout(std::time::Duration::from_secs(10)),
+            Ok((true, false))
+        );
+    }
+}
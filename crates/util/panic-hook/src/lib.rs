@@ -20,10 +20,32 @@ extern crate backtrace;
 
 use backtrace::Backtrace;
 use std::{
-    panic::{self, PanicHookInfo},
-    process, thread,
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    error, fmt,
+    panic::{self, AssertUnwindSafe, PanicHookInfo},
+    process,
+    sync::{Arc, Mutex, RwLock},
+    thread,
 };
 
+type Handler = Arc<dyn Fn(&str) + Send + Sync>;
+type EscalationHandler = Arc<dyn Fn(&CaughtPanic) + Send + Sync>;
+
+/// Closure passed to `set_with`, called for panics outside of any catch scope.
+static HANDLER: RwLock<Option<Handler>> = RwLock::new(None);
+/// Number of caught panics per scope label.
+static CAUGHT: Mutex<BTreeMap<String, usize>> = Mutex::new(BTreeMap::new());
+/// Escalation policy of catch scopes.
+static POLICY: RwLock<Option<ScopePolicy>> = RwLock::new(None);
+
+thread_local! {
+    /// Number of catch scopes the current thread is in.
+    static SCOPE_DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// Details of the last panic caught on the current thread.
+    static LAST_PANIC: RefCell<Option<PanicDetails>> = const { RefCell::new(None) };
+}
+
 /// Set the panic hook to write to stderr and abort the process when a panic happens.
 pub fn set_abort() {
     set_with(|msg| {
@@ -37,50 +59,335 @@ pub fn set_abort() {
 /// Depending on how OpenEthereum was compiled, after the closure has been executed, either the process
 /// aborts or unwinding starts.
 ///
+/// The closure is not called for panics inside `catch_scoped`, unless the scope policy escalates them.
+///
 /// If you panic within the closure, a double panic happens and the process will stop.
 pub fn set_with<F>(f: F)
 where
     F: Fn(&str) + Send + Sync + 'static,
 {
-    panic::set_hook(Box::new(move |info| {
-        let msg = gen_panic_msg(info);
-        f(&msg);
+    *HANDLER.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(f));
+    panic::set_hook(Box::new(|info| {
+        let details = PanicDetails::new(info);
+        let in_scope = SCOPE_DEPTH.try_with(|d| d.get() > 0).unwrap_or(false);
+        if in_scope {
+            let _ = LAST_PANIC.try_with(|p| *p.borrow_mut() = Some(details));
+            return;
+        }
+        call_handler(&details.report());
     }));
 }
 
+fn call_handler(msg: &str) {
+    let handler = HANDLER.read().unwrap_or_else(|e| e.into_inner()).clone();
+    match handler {
+        Some(f) => f(msg),
+        None => {
+            eprintln!("{msg}");
+            process::abort()
+        }
+    }
+}
+
+/// Panic caught by `catch_scoped`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaughtPanic {
+    /// Label of the scope the panic was caught in.
+    pub label: String,
+    /// Panic message.
+    pub message: String,
+    /// Source location of the panic as `file:line`, if known.
+    pub location: Option<String>,
+    /// Name of the panicking thread.
+    pub thread: String,
+    /// Full report including the backtrace, as passed to the `set_with` closure.
+    pub report: String,
+}
+
+impl fmt::Display for CaughtPanic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location {
+            Some(ref location) => write!(
+                f,
+                "Panic in {}: '{}', {}",
+                self.label, self.message, location
+            ),
+            None => write!(f, "Panic in {}: '{}'", self.label, self.message),
+        }
+    }
+}
+
+impl error::Error for CaughtPanic {}
+
+/// When panics caught in a scope are escalated to the `set_with` closure.
+#[derive(Debug, Clone, Default)]
+pub struct EscalationRule {
+    /// Escalate once the number of panics caught in the scope reaches this value.
+    pub max_occurrences: Option<usize>,
+    /// Escalate panics whose message contains any of these patterns.
+    pub patterns: Vec<String>,
+}
+
+impl EscalationRule {
+    fn matches(&self, count: usize, message: &str) -> bool {
+        self.max_occurrences.is_some_and(|max| count >= max)
+            || self.patterns.iter().any(|p| message.contains(p.as_str()))
+    }
+}
+
+/// Escalation policy of catch scopes, keyed by scope label.
+#[derive(Clone, Default)]
+pub struct ScopePolicy {
+    rules: BTreeMap<String, EscalationRule>,
+    on_escalate: Option<EscalationHandler>,
+}
+
+impl ScopePolicy {
+    /// Create a policy which never escalates.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Escalate panics in scope `label` according to `rule`.
+    pub fn rule(mut self, label: &str, rule: EscalationRule) -> Self {
+        self.rules.insert(label.to_owned(), rule);
+        self
+    }
+
+    /// Call `f` for escalated panics instead of the `set_with` closure.
+    pub fn on_escalate<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&CaughtPanic) + Send + Sync + 'static,
+    {
+        self.on_escalate = Some(Arc::new(f));
+        self
+    }
+}
+
+/// Set the escalation policy used by `catch_scoped`.
+pub fn set_scope_policy(policy: ScopePolicy) {
+    *POLICY.write().unwrap_or_else(|e| e.into_inner()) = Some(policy);
+}
+
+/// Number of panics caught in scopes with the given label so far.
+pub fn caught_panics(label: &str) -> usize {
+    CAUGHT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(label)
+        .cloned()
+        .unwrap_or(0)
+}
+
+/// Run `f`, converting a panic into an error instead of passing it to the `set_with` closure.
+///
+/// Only meant for non-fatal panics, e.g. in a single request handler. Panics which indicate
+/// corrupted state can be kept fatal with `set_scope_policy`: escalated panics are passed to the
+/// `set_with` closure (which usually aborts) after being counted.
+pub fn catch_scoped<T, F>(label: &str, f: F) -> Result<T, CaughtPanic>
+where
+    F: FnOnce() -> T,
+{
+    SCOPE_DEPTH.with(|d| d.set(d.get() + 1));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    SCOPE_DEPTH.with(|d| d.set(d.get() - 1));
+
+    let payload = match result {
+        Ok(value) => return Ok(value),
+        Err(payload) => payload,
+    };
+
+    // Without our hook installed only the payload is available.
+    let details = LAST_PANIC
+        .with(|p| p.borrow_mut().take())
+        .unwrap_or_else(|| PanicDetails {
+            message: payload_message(&*payload).to_owned(),
+            location: None,
+            thread: thread_name(),
+            backtrace: Backtrace::new(),
+        });
+    let caught = CaughtPanic {
+        label: label.to_owned(),
+        message: details.message.clone(),
+        location: details
+            .location
+            .as_ref()
+            .map(|(file, line)| format!("{file}:{line}")),
+        thread: details.thread.clone(),
+        report: details.report(),
+    };
+
+    let count = {
+        let mut counters = CAUGHT.lock().unwrap_or_else(|e| e.into_inner());
+        let count = counters.entry(label.to_owned()).or_insert(0);
+        *count += 1;
+        *count
+    };
+
+    let policy = POLICY.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(policy) = policy {
+        let escalate = policy
+            .rules
+            .get(label)
+            .is_some_and(|rule| rule.matches(count, &caught.message));
+        if escalate {
+            match policy.on_escalate {
+                Some(ref f) => f(&caught),
+                None => call_handler(&caught.report),
+            }
+        }
+    }
+
+    Err(caught)
+}
+
 static ABOUT_PANIC: &str = "
 This is a bug. Please report it at:
 
     https://github.com/openethereum/openethereum/issues/new
 ";
 
-fn gen_panic_msg(info: &PanicHookInfo) -> String {
-    let location = info.location();
-    let file = location.as_ref().map(|l| l.file()).unwrap_or("<unknown>");
-    let line = location.as_ref().map(|l| l.line()).unwrap_or(0);
+struct PanicDetails {
+    message: String,
+    location: Option<(String, u32)>,
+    thread: String,
+    backtrace: Backtrace,
+}
+
+impl PanicDetails {
+    fn new(info: &PanicHookInfo) -> Self {
+        PanicDetails {
+            message: payload_message(info.payload()).to_owned(),
+            location: info.location().map(|l| (l.file().to_owned(), l.line())),
+            thread: thread_name(),
+            backtrace: Backtrace::new(),
+        }
+    }
+
+    fn report(&self) -> String {
+        let (file, line) = match self.location {
+            Some((ref file, line)) => (&file[..], line),
+            None => ("<unknown>", 0),
+        };
+        let PanicDetails {
+            ref message,
+            ref thread,
+            ref backtrace,
+            ..
+        } = *self;
+
+        format!(
+            r#"
+
+====================
+
+{backtrace:?}
 
-    let msg = match info.payload().downcast_ref::<&'static str>() {
-        Some(s) => *s,
-        None => match info.payload().downcast_ref::<String>() {
+Thread '{thread}' panicked at '{message}', {file}:{line}
+{ABOUT_PANIC}
+"#
+        )
+    }
+}
+
+fn payload_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    match payload.downcast_ref::<&'static str>() {
+        Some(s) => s,
+        None => match payload.downcast_ref::<String>() {
             Some(s) => &s[..],
             None => "Box<Any>",
         },
+    }
+}
+
+fn thread_name() -> String {
+    thread::current().name().unwrap_or("<unnamed>").to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Once,
     };
 
-    let thread = thread::current();
-    let name = thread.name().unwrap_or("<unnamed>");
+    static INIT: Once = Once::new();
+    static GLOBAL: Mutex<Vec<String>> = Mutex::new(Vec::new());
 
-    let backtrace = Backtrace::new();
+    /// Install a hook which records messages instead of aborting.
+    fn init() {
+        INIT.call_once(|| set_with(|msg| GLOBAL.lock().unwrap().push(msg.to_owned())));
+    }
 
-    format!(
-        r#"
+    fn got_global(pattern: &str) -> bool {
+        GLOBAL.lock().unwrap().iter().any(|m| m.contains(pattern))
+    }
 
-====================
+    #[test]
+    fn catches_panic_with_details() {
+        init();
+        assert_eq!(catch_scoped("test_ok", || 42), Ok(42));
 
-{backtrace:?}
+        let line = line!() + 1;
+        let err = catch_scoped("test_details", || -> () { panic!("handler failed") }).unwrap_err();
+        assert_eq!(err.label, "test_details");
+        assert_eq!(err.message, "handler failed");
+        assert_eq!(err.location, Some(format!("{}:{}", file!(), line)));
+        assert!(err.report.contains(&format!(
+            "panicked at 'handler failed', {}:{}",
+            file!(),
+            line
+        )));
+        assert_eq!(caught_panics("test_details"), 1);
 
-Thread '{name}' panicked at '{msg}', {file}:{line}
-{ABOUT_PANIC}
-"#
-    )
+        let _ = catch_scoped("test_details", || panic!("again"));
+        assert_eq!(caught_panics("test_details"), 2);
+        assert_eq!(caught_panics("test_ok"), 0);
+        assert!(!got_global("handler failed"));
+    }
+
+    #[test]
+    fn escalates_after_threshold_and_on_pattern() {
+        init();
+        static ESCALATED: AtomicUsize = AtomicUsize::new(0);
+        set_scope_policy(
+            ScopePolicy::new()
+                .rule(
+                    "test_escalate",
+                    EscalationRule {
+                        max_occurrences: Some(3),
+                        patterns: vec!["corrupt".into()],
+                    },
+                )
+                .on_escalate(|_| {
+                    ESCALATED.fetch_add(1, Ordering::SeqCst);
+                }),
+        );
+
+        for _ in 0..2 {
+            assert!(catch_scoped("test_escalate", || panic!("transient")).is_err());
+        }
+        assert_eq!(ESCALATED.load(Ordering::SeqCst), 0);
+        assert!(catch_scoped("test_escalate", || panic!("transient")).is_err());
+        assert_eq!(ESCALATED.load(Ordering::SeqCst), 1);
+
+        assert!(catch_scoped("test_escalate_other", || panic!("corrupt state")).is_err());
+        assert_eq!(ESCALATED.load(Ordering::SeqCst), 1);
+        assert!(catch_scoped("test_escalate", || panic!("corrupt state")).is_err());
+        assert_eq!(ESCALATED.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn panics_outside_scope_reach_global_handler() {
+        init();
+        let result = thread::spawn(|| {
+            let _ = catch_scoped("test_outside", || panic!("inside scope"));
+            panic!("outside scope");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(got_global("outside scope"));
+        assert!(!got_global("inside scope"));
+    }
 }
@@ -17,6 +17,7 @@
 //! Statistical functions and helpers.
 
 use std::{
+    collections::HashMap,
     iter::FromIterator,
     ops::{Add, Deref, Div, Sub},
     time::Instant,
@@ -26,18 +27,100 @@ use std::{
 extern crate log;
 pub extern crate prometheus;
 
+mod metrics;
+pub mod profiler;
+
+pub use metrics::{MetricKind, Metrics, MetricsError};
+
+/// Replace the characters not allowed in a metric name (or a label name if `colon` is not
+/// allowed) with `_`, collapse runs of `_` and prefix names starting with a digit with `_`
+fn sanitize_name(name: &str, colon: bool) -> String {
+    let mut sanitized = String::with_capacity(name.len() + 1);
+    for c in name.chars() {
+        let c = match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c,
+            ':' if colon => c,
+            _ => '_',
+        };
+        if c != '_' || !sanitized.ends_with('_') {
+            sanitized.push(c);
+        }
+    }
+    if sanitized.chars().next().is_none_or(|c| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+/// Check that `prefix` is empty or a valid metric name
+pub(crate) fn check_prefix(prefix: &str) -> Result<(), MetricsError> {
+    match prefix.is_empty() || sanitize_name(prefix, true) == prefix {
+        true => Ok(()),
+        false => Err(MetricsError::InvalidPrefix(prefix.into())),
+    }
+}
+
+#[derive(Clone)]
+enum Registered {
+    Counter(prometheus::IntCounter),
+    Gauge(prometheus::IntGauge),
+    LabeledCounter(prometheus::IntCounterVec),
+    LabeledGauge(prometheus::IntGaugeVec),
+}
+
+impl Registered {
+    fn kind(&self) -> MetricKind {
+        match *self {
+            Registered::Counter(_) => MetricKind::IntCounter,
+            Registered::Gauge(_) => MetricKind::IntGauge,
+            Registered::LabeledCounter(_) => MetricKind::LabeledIntCounter,
+            Registered::LabeledGauge(_) => MetricKind::LabeledIntGauge,
+        }
+    }
+}
+
+struct Registration {
+    help: String,
+    label: Option<String>,
+    metric: Registered,
+}
+
+fn set_counter(counter: &prometheus::IntCounter, value: i64) {
+    let value = value as u64;
+    if value < counter.get() {
+        counter.reset();
+    }
+    counter.inc_by(value - counter.get());
+}
+
+/// Registry for metrics collected on demand.
+///
+/// Metric names are sanitized, so names built from dynamic strings (e.g. client versions) are
+/// exported with the invalid characters replaced. A strict registry rejects such names instead.
+/// Registering a metric again under the same name updates its value.
 pub struct PrometheusRegistry {
     prefix: String,
+    strict: bool,
     registry: prometheus::Registry,
+    registered: HashMap<String, Registration>,
 }
 
 impl PrometheusRegistry {
-    /// Create a new instance with the specified prefix
-    pub fn new(prefix: String) -> Self {
-        Self {
+    /// Create a new instance with the specified prefix, which must be a valid metric name or empty
+    pub fn new(prefix: String) -> Result<Self, MetricsError> {
+        check_prefix(&prefix)?;
+        Ok(Self {
             prefix,
+            strict: false,
             registry: prometheus::Registry::new(),
-        }
+            registered: HashMap::new(),
+        })
+    }
+
+    /// Reject invalid metric and label names instead of sanitizing them
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
     }
 
     /// Get internal registry
@@ -46,25 +129,108 @@ impl PrometheusRegistry {
     }
 
     /// Adds a new prometheus counter with the specified value
-    pub fn register_counter(&mut self, name: &str, help: &str, value: i64) {
-        let name = format!("{}{}", self.prefix, name);
-        let c = prometheus::IntCounter::new(name.as_str(), help)
-            .expect("name and help must be non-empty");
-        c.inc_by(value as u64);
-        self.registry
-            .register(Box::new(c))
-            .expect("prometheus identifiers must be unique");
+    pub fn register_counter(
+        &mut self,
+        name: &str,
+        help: &str,
+        value: i64,
+    ) -> Result<(), MetricsError> {
+        match self.register_or_get(name, help, None, MetricKind::IntCounter, |opts, _| {
+            Ok(Registered::Counter(prometheus::IntCounter::with_opts(
+                opts,
+            )?))
+        })? {
+            Registered::Counter(c) => set_counter(&c, value),
+            _ => unreachable!("kind is checked by register_or_get; qed"),
+        }
+        Ok(())
     }
 
     /// Adds a new prometheus gauge with the specified gauge
-    pub fn register_gauge(&mut self, name: &str, help: &str, value: i64) {
-        let name = format!("{}{}", self.prefix, name);
-        let g = prometheus::IntGauge::new(name.as_str(), help)
-            .expect("name and help must be non-empty");
-        g.set(value);
-        self.registry
-            .register(Box::new(g))
-            .expect("prometheus identifiers must be are unique");
+    pub fn register_gauge(
+        &mut self,
+        name: &str,
+        help: &str,
+        value: i64,
+    ) -> Result<(), MetricsError> {
+        match self.register_or_get(name, help, None, MetricKind::IntGauge, |opts, _| {
+            Ok(Registered::Gauge(prometheus::IntGauge::with_opts(opts)?))
+        })? {
+            Registered::Gauge(g) => g.set(value),
+            _ => unreachable!("kind is checked by register_or_get; qed"),
+        }
+        Ok(())
+    }
+
+    /// Adds a new prometheus counter with a value for each of the `label` values
+    pub fn register_labeled_counter(
+        &mut self,
+        name: &str,
+        help: &str,
+        label: &str,
+        values: &[(&str, i64)],
+    ) -> Result<(), MetricsError> {
+        let kind = MetricKind::LabeledIntCounter;
+        match self.register_or_get(name, help, Some(label), kind, |opts, label| {
+            Ok(Registered::LabeledCounter(prometheus::IntCounterVec::new(
+                opts,
+                &[label],
+            )?))
+        })? {
+            Registered::LabeledCounter(c) => {
+                for (label_value, value) in values {
+                    set_counter(&c.with_label_values(&[*label_value]), *value);
+                }
+            }
+            _ => unreachable!("kind is checked by register_or_get; qed"),
+        }
+        Ok(())
+    }
+
+    /// Adds a new prometheus gauge with a value for each of the `label` values
+    pub fn register_labeled_gauge(
+        &mut self,
+        name: &str,
+        help: &str,
+        label: &str,
+        values: &[(&str, i64)],
+    ) -> Result<(), MetricsError> {
+        let kind = MetricKind::LabeledIntGauge;
+        match self.register_or_get(name, help, Some(label), kind, |opts, label| {
+            Ok(Registered::LabeledGauge(prometheus::IntGaugeVec::new(
+                opts,
+                &[label],
+            )?))
+        })? {
+            Registered::LabeledGauge(g) => {
+                for (label_value, value) in values {
+                    g.with_label_values(&[*label_value]).set(*value);
+                }
+            }
+            _ => unreachable!("kind is checked by register_or_get; qed"),
+        }
+        Ok(())
+    }
+
+    /// Runs `f` with `prefix` added to the names of the metrics it registers, e.g. to tell apart
+    /// the metrics of several instances of a source
+    pub fn with_prefix<F: FnOnce(&mut Self) -> R, R>(
+        &mut self,
+        prefix: &str,
+        f: F,
+    ) -> Result<R, MetricsError> {
+        let prefixed = format!("{}{}", self.prefix, prefix);
+        let sanitized = match prefixed.is_empty() {
+            true => prefixed.clone(),
+            false => sanitize_name(&prefixed, true),
+        };
+        if self.strict && sanitized != prefixed {
+            return Err(MetricsError::InvalidPrefix(prefix.into()));
+        }
+        let prefix = std::mem::replace(&mut self.prefix, sanitized);
+        let result = f(self);
+        self.prefix = prefix;
+        Ok(result)
     }
 
     /// Adds a new prometheus counter with the time spent in running the specified function
@@ -72,13 +238,76 @@ impl PrometheusRegistry {
         let start = Instant::now();
         let t = f();
         let elapsed = start.elapsed();
-        self.register_gauge(
+        if let Err(e) = self.register_gauge(
             &format!("optime_{name}"),
             &format!("Time to perform {name}"),
             elapsed.as_millis() as i64,
-        );
+        ) {
+            warn!(target: "stats", "Failed to register optime of {name}: {e}");
+        }
         t
     }
+
+    fn register_or_get<F>(
+        &mut self,
+        name: &str,
+        help: &str,
+        label: Option<&str>,
+        kind: MetricKind,
+        create: F,
+    ) -> Result<Registered, MetricsError>
+    where
+        F: FnOnce(prometheus::Opts, &str) -> Result<Registered, MetricsError>,
+    {
+        let full_name = format!("{}{}", self.prefix, name);
+        let name = sanitize_name(&full_name, true);
+        let label = label.map(|label| (label, sanitize_name(label, false)));
+        if self.strict {
+            if name != full_name {
+                return Err(MetricsError::InvalidName(full_name));
+            }
+            if let Some((label, ref sanitized)) = label {
+                if label != sanitized {
+                    return Err(MetricsError::InvalidName(label.into()));
+                }
+            }
+        }
+        let label = label.map(|(_, sanitized)| sanitized);
+
+        if let Some(registration) = self.registered.get(&name) {
+            let registered = registration.metric.kind();
+            return if registered != kind {
+                Err(MetricsError::KindMismatch {
+                    name,
+                    registered,
+                    requested: kind,
+                })
+            } else if registration.help != help || registration.label != label {
+                Err(MetricsError::DescriptionMismatch(name))
+            } else {
+                Ok(registration.metric.clone())
+            };
+        }
+
+        let opts = prometheus::Opts::new(name.as_str(), help);
+        let metric = create(opts, label.as_deref().unwrap_or_default())?;
+        let collector: Box<dyn prometheus::core::Collector> = match metric {
+            Registered::Counter(ref c) => Box::new(c.clone()),
+            Registered::Gauge(ref g) => Box::new(g.clone()),
+            Registered::LabeledCounter(ref c) => Box::new(c.clone()),
+            Registered::LabeledGauge(ref g) => Box::new(g.clone()),
+        };
+        self.registry.register(collector)?;
+        self.registered.insert(
+            name,
+            Registration {
+                help: help.into(),
+                label,
+                metric: metric.clone(),
+            },
+        );
+        Ok(metric)
+    }
 }
 
 /// Implements a prometheus metrics collector
@@ -87,7 +316,17 @@ pub trait PrometheusMetrics {
 }
 
 /// Sorted corpus of data.
+///
+/// Serializes as its sorted sequence of values; deserialized values are sorted again.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        from = "Vec<T>",
+        bound(deserialize = "T: serde::Deserialize<'de> + Ord")
+    )
+)]
 pub struct Corpus<T>(Vec<T>);
 
 impl<T: Ord> From<Vec<T>> for Corpus<T> {
@@ -138,6 +377,86 @@ impl<T: Ord> Corpus<T> {
     pub fn len(&self) -> usize {
         self.0.len()
     }
+
+    /// Merge another corpus into this one, keeping the data sorted.
+    pub fn merge(&mut self, other: Corpus<T>) {
+        if other.is_empty() {
+            return;
+        }
+        let ours = ::std::mem::take(&mut self.0);
+        let mut merged = Vec::with_capacity(ours.len() + other.len());
+        let mut ours = ours.into_iter().peekable();
+        let mut theirs = other.0.into_iter().peekable();
+        loop {
+            let next = match (ours.peek(), theirs.peek()) {
+                (Some(a), Some(b)) if b < a => theirs.next(),
+                (Some(_), _) => ours.next(),
+                (None, _) => theirs.next(),
+            };
+            match next {
+                Some(v) => merged.push(v),
+                None => break,
+            }
+        }
+        self.0 = merged;
+    }
+
+    /// Reduce the corpus to at most `max_len` evenly spaced elements.
+    /// Minimum and maximum are kept as long as `max_len` is at least 2.
+    pub fn downsample(&mut self, max_len: usize) {
+        let len = self.0.len();
+        if len <= max_len {
+            return;
+        }
+        if max_len < 2 {
+            self.0.truncate(max_len);
+            return;
+        }
+        // Index of the `k`th kept element; strictly increasing since `len > max_len`.
+        let kept = |k: usize| k * (len - 1) / (max_len - 1);
+        let mut index = 0;
+        let mut k = 0;
+        self.0.retain(|_| {
+            let keep = k < max_len && kept(k) == index;
+            if keep {
+                k += 1;
+            }
+            index += 1;
+            keep
+        });
+    }
+}
+
+impl<T: Ord + Clone> Corpus<T> {
+    /// Compact summary of the corpus.
+    pub fn summary(&self) -> Summary<T> {
+        Summary {
+            count: self.len(),
+            min: self.0.first().cloned(),
+            max: self.0.last().cloned(),
+            p50: self.percentile(50).cloned(),
+            p90: self.percentile(90).cloned(),
+            p99: self.percentile(99).cloned(),
+        }
+    }
+}
+
+/// Compact summary of a corpus, see `Corpus::summary`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Summary<T> {
+    /// Number of elements.
+    pub count: usize,
+    /// Smallest element.
+    pub min: Option<T>,
+    /// Largest element.
+    pub max: Option<T>,
+    /// 50th percentile.
+    pub p50: Option<T>,
+    /// 90th percentile.
+    pub p90: Option<T>,
+    /// 99th percentile.
+    pub p99: Option<T>,
 }
 
 impl<T: Ord + Copy + ::std::fmt::Display> Corpus<T>
@@ -155,6 +474,7 @@ where
 
 /// Discretised histogram.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Histogram<T> {
     /// Bounds of each bucket.
     pub bucket_bounds: Vec<T>,
@@ -212,6 +532,152 @@ where
 mod tests {
     use super::*;
 
+    #[test]
+    fn prefixes_metrics_of_instances() {
+        let mut registry = PrometheusRegistry::new("oe_".into()).unwrap();
+        for (prefix, value) in [("alpha_", 1), ("beta_", 2)] {
+            registry
+                .with_prefix(prefix, |r| r.register_gauge("peers", "Peers", value))
+                .unwrap()
+                .unwrap();
+        }
+        registry.register_gauge("peers", "Peers", 3).unwrap();
+
+        let names: Vec<_> = registry
+            .registry()
+            .gather()
+            .iter()
+            .map(|family| family.name().to_owned())
+            .collect();
+        assert_eq!(names, vec!["oe_alpha_peers", "oe_beta_peers", "oe_peers"]);
+    }
+
+    fn encode(registry: &PrometheusRegistry) -> String {
+        use prometheus::Encoder;
+
+        let mut buffer = vec![];
+        prometheus::TextEncoder::new()
+            .encode(&registry.registry().gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn sanitizes_dynamic_names() {
+        let mut registry = PrometheusRegistry::new("oe_".into()).unwrap();
+        registry
+            .register_gauge("peers_Geth/v1.13.5-stable", "Peers", 1)
+            .unwrap();
+        registry
+            .register_gauge("blocks in  queue", "Blocks", 2)
+            .unwrap();
+        registry.register_gauge("größe", "Size", 3).unwrap();
+        registry
+            .register_labeled_gauge("clients", "Clients", "client name", &[("Geth/v1", 4)])
+            .unwrap();
+        registry
+            .with_prefix("node 1/", |r| r.register_gauge("peers", "Peers", 5))
+            .unwrap()
+            .unwrap();
+
+        let mut unprefixed = PrometheusRegistry::new("".into()).unwrap();
+        unprefixed.register_gauge("1st/peer", "Peers", 6).unwrap();
+
+        let output = encode(&registry);
+        assert!(output.contains("oe_peers_Geth_v1_13_5_stable 1\n"));
+        assert!(output.contains("oe_blocks_in_queue 2\n"));
+        assert!(output.contains("oe_gr_e 3\n"));
+        assert!(output.contains("oe_clients{client_name=\"Geth/v1\"} 4\n"));
+        assert!(output.contains("oe_node_1_peers 5\n"));
+        assert!(encode(&unprefixed).contains("_1st_peer 6\n"));
+    }
+
+    #[test]
+    fn repeated_registration_updates_value() {
+        let mut registry = PrometheusRegistry::new("".into()).unwrap();
+        registry
+            .register_counter("imported", "Imported", 5)
+            .unwrap();
+        registry
+            .register_counter("imported", "Imported", 3)
+            .unwrap();
+        registry.register_gauge("peers", "Peers", 5).unwrap();
+        // Both names sanitize to `peers_1`.
+        registry.register_gauge("peers 1", "Peers", 1).unwrap();
+        registry.register_gauge("peers/1", "Peers", 2).unwrap();
+        registry
+            .register_labeled_counter("errors", "Errors", "target", &[("sync", 1), ("net", 2)])
+            .unwrap();
+        registry
+            .register_labeled_counter("errors", "Errors", "target", &[("sync", 4)])
+            .unwrap();
+
+        let output = encode(&registry);
+        assert!(output.contains("imported 3\n"));
+        assert!(output.contains("peers 5\n"));
+        assert!(output.contains("peers_1 2\n"));
+        assert!(output.contains("errors{target=\"sync\"} 4\n"));
+        assert!(output.contains("errors{target=\"net\"} 2\n"));
+    }
+
+    #[test]
+    fn conflicting_registration_is_rejected() {
+        let mut registry = PrometheusRegistry::new("".into()).unwrap();
+        registry.register_gauge("peers", "Peers", 5).unwrap();
+        match registry.register_counter("peers", "Peers", 5) {
+            Err(MetricsError::KindMismatch {
+                registered,
+                requested,
+                ..
+            }) => {
+                assert_eq!(registered, MetricKind::IntGauge);
+                assert_eq!(requested, MetricKind::IntCounter);
+            }
+            _ => panic!("expected kind mismatch"),
+        }
+        match registry.register_gauge("peers", "Connected peers", 5) {
+            Err(MetricsError::DescriptionMismatch(name)) => assert_eq!(name, "peers"),
+            _ => panic!("expected description mismatch"),
+        }
+        registry
+            .register_labeled_gauge("clients", "Clients", "client", &[])
+            .unwrap();
+        assert!(registry
+            .register_labeled_gauge("clients", "Clients", "name", &[])
+            .is_err());
+        assert!(registry.register_gauge("empty_help", "", 1).is_err());
+    }
+
+    #[test]
+    fn invalid_prefix_is_rejected() {
+        for prefix in ["oe-", "1oe_", "oe node_", "ö_"] {
+            match PrometheusRegistry::new(prefix.into()) {
+                Err(MetricsError::InvalidPrefix(p)) => assert_eq!(p, prefix),
+                _ => panic!("expected {prefix} to be rejected"),
+            }
+            assert!(Metrics::new(prefix.into()).is_err());
+        }
+        assert!(PrometheusRegistry::new("oe_".into()).is_ok());
+        assert!(PrometheusRegistry::new("oe:".into()).is_ok());
+    }
+
+    #[test]
+    fn strict_registry_rejects_invalid_names() {
+        let mut registry = PrometheusRegistry::new("oe_".into()).unwrap().strict();
+        registry.register_gauge("peers", "Peers", 1).unwrap();
+        match registry.register_gauge("peers/Geth", "Peers", 1) {
+            Err(MetricsError::InvalidName(name)) => assert_eq!(name, "oe_peers/Geth"),
+            _ => panic!("expected invalid name"),
+        }
+        assert!(registry
+            .register_labeled_gauge("clients", "Clients", "client name", &[])
+            .is_err());
+        assert!(registry
+            .with_prefix("node 1_", |r| r.register_gauge("peers", "Peers", 1))
+            .is_err());
+        assert_eq!(registry.registry().gather().len(), 1);
+    }
+
     #[test]
     fn check_corpus() {
         let corpus = Corpus::from(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
@@ -224,6 +690,76 @@ mod tests {
         assert_eq!(corpus.median(), Some(&6));
     }
 
+    #[test]
+    fn merged_corpus_matches_concatenation() {
+        let a = vec![5, 1, 9, 3, 3, 12, 40, 7];
+        let b = vec![2, 3, 8, 41, 0, 6];
+        let mut merged = Corpus::from(a.clone());
+        merged.merge(Corpus::from(b.clone()));
+        let mut naive = [a, b].concat();
+        naive.sort();
+        let naive = Corpus::from(naive);
+        assert_eq!(merged, naive);
+        for p in 0..=100 {
+            assert_eq!(merged.percentile(p), naive.percentile(p));
+        }
+        assert_eq!(merged.median(), naive.median());
+
+        let mut empty = Corpus::from(Vec::new());
+        empty.merge(naive.clone());
+        assert_eq!(empty, naive);
+    }
+
+    #[test]
+    fn downsample_keeps_extremes() {
+        let mut corpus: Corpus<usize> = (0..100).collect();
+        corpus.downsample(5);
+        assert_eq!(&corpus[..], &[0, 24, 49, 74, 99]);
+        corpus.downsample(10);
+        assert_eq!(corpus.len(), 5);
+        corpus.downsample(2);
+        assert_eq!(&corpus[..], &[0, 99]);
+        corpus.downsample(0);
+        assert!(corpus.is_empty());
+    }
+
+    #[test]
+    fn check_summary() {
+        let summary: Summary<usize> = (1..=10).collect::<Corpus<_>>().summary();
+        assert_eq!(
+            summary,
+            Summary {
+                count: 10,
+                min: Some(1),
+                max: Some(10),
+                p50: Some(5),
+                p90: Some(9),
+                p99: Some(9),
+            }
+        );
+        assert_eq!(Corpus::<usize>::from(vec![]).summary().min, None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_roundtrip() {
+        let corpus = Corpus::from(vec![4u64, 1, 3]);
+        let json = serde_json::to_string(&corpus).unwrap();
+        assert_eq!(json, "[1,3,4]");
+        assert_eq!(serde_json::from_str::<Corpus<u64>>(&json).unwrap(), corpus);
+        assert_eq!(
+            serde_json::from_str::<Corpus<u64>>("[4,1,3]").unwrap(),
+            corpus
+        );
+
+        let hist = Histogram::create(&[1usize, 2, 5], 2).unwrap();
+        let json = serde_json::to_string(&hist).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Histogram<usize>>(&json).unwrap(),
+            hist
+        );
+    }
+
     #[test]
     fn check_histogram() {
         let hist = Histogram::create(
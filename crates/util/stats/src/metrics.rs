@@ -0,0 +1,379 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Long-lived metric registry.
+//!
+//! Components obtain typed metric handles once at startup and update them on the hot path.
+//! Implementors of the legacy `PrometheusMetrics` trait can be registered as well, they are
+//! collected into the same output on every `gather`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::Arc,
+};
+
+use parking_lot::{Mutex, RwLock};
+use prometheus::{proto::MetricFamily, Histogram, HistogramOpts, IntCounter, IntGauge, Opts};
+
+use crate::{check_prefix, PrometheusMetrics, PrometheusRegistry};
+
+/// Type of a registered metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    IntCounter,
+    IntGauge,
+    Histogram,
+    LabeledIntCounter,
+    LabeledIntGauge,
+}
+
+impl fmt::Display for MetricKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MetricKind::IntCounter => write!(f, "int counter"),
+            MetricKind::IntGauge => write!(f, "int gauge"),
+            MetricKind::Histogram => write!(f, "histogram"),
+            MetricKind::LabeledIntCounter => write!(f, "labeled int counter"),
+            MetricKind::LabeledIntGauge => write!(f, "labeled int gauge"),
+        }
+    }
+}
+
+/// Metric registration error.
+#[derive(Debug)]
+pub enum MetricsError {
+    /// A metric with the same name but a different type is already registered.
+    KindMismatch {
+        name: String,
+        registered: MetricKind,
+        requested: MetricKind,
+    },
+    /// A metric with the same name but a different help text or label is already registered.
+    DescriptionMismatch(String),
+    /// The metric or label name is not valid (strict registries only).
+    InvalidName(String),
+    /// The metric name prefix is not valid.
+    InvalidPrefix(String),
+    /// The metric was rejected by prometheus (e.g. invalid name).
+    Prometheus(prometheus::Error),
+}
+
+impl fmt::Display for MetricsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MetricsError::KindMismatch {
+                ref name,
+                registered,
+                requested,
+            } => write!(
+                f,
+                "Metric {name} is registered as {registered}, requested as {requested}"
+            ),
+            MetricsError::DescriptionMismatch(ref name) => write!(
+                f,
+                "Metric {name} is registered with a different help text or label"
+            ),
+            MetricsError::InvalidName(ref name) => write!(f, "Invalid metric name {name}"),
+            MetricsError::InvalidPrefix(ref prefix) => {
+                write!(f, "Invalid metric name prefix {prefix}")
+            }
+            MetricsError::Prometheus(ref e) => write!(f, "Prometheus error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for MetricsError {}
+
+impl From<prometheus::Error> for MetricsError {
+    fn from(err: prometheus::Error) -> Self {
+        MetricsError::Prometheus(err)
+    }
+}
+
+#[derive(Clone)]
+enum Metric {
+    IntCounter(IntCounter),
+    IntGauge(IntGauge),
+    Histogram(Histogram),
+}
+
+impl Metric {
+    fn kind(&self) -> MetricKind {
+        match *self {
+            Metric::IntCounter(_) => MetricKind::IntCounter,
+            Metric::IntGauge(_) => MetricKind::IntGauge,
+            Metric::Histogram(_) => MetricKind::Histogram,
+        }
+    }
+}
+
+type LegacyCollector = Box<dyn Fn(&mut PrometheusRegistry) + Send + Sync>;
+
+struct Inner {
+    prefix: String,
+    registry: prometheus::Registry,
+    metrics: Mutex<HashMap<String, Metric>>,
+    legacy: RwLock<Vec<LegacyCollector>>,
+}
+
+/// Shared handle to a metric registry. Cheap to clone.
+#[derive(Clone)]
+pub struct Metrics {
+    inner: Arc<Inner>,
+}
+
+impl Metrics {
+    /// Create a new registry, all metric names get the specified prefix
+    pub fn new(prefix: String) -> Result<Self, MetricsError> {
+        check_prefix(&prefix)?;
+        Ok(Metrics {
+            inner: Arc::new(Inner {
+                prefix,
+                registry: prometheus::Registry::new(),
+                metrics: Mutex::new(HashMap::new()),
+                legacy: RwLock::new(Vec::new()),
+            }),
+        })
+    }
+
+    /// Get or register an integer counter
+    pub fn int_counter(&self, name: &str, help: &str) -> Result<IntCounter, MetricsError> {
+        let metric = self.get_or_register(name, MetricKind::IntCounter, |name| {
+            Ok(Metric::IntCounter(IntCounter::with_opts(Opts::new(
+                name, help,
+            ))?))
+        })?;
+        match metric {
+            Metric::IntCounter(c) => Ok(c),
+            _ => unreachable!("kind is checked by get_or_register; qed"),
+        }
+    }
+
+    /// Get or register an integer gauge
+    pub fn int_gauge(&self, name: &str, help: &str) -> Result<IntGauge, MetricsError> {
+        let metric = self.get_or_register(name, MetricKind::IntGauge, |name| {
+            Ok(Metric::IntGauge(IntGauge::with_opts(Opts::new(
+                name, help,
+            ))?))
+        })?;
+        match metric {
+            Metric::IntGauge(g) => Ok(g),
+            _ => unreachable!("kind is checked by get_or_register; qed"),
+        }
+    }
+
+    /// Get or register a histogram with the given bucket bounds.
+    /// The buckets of an already registered histogram are not changed.
+    pub fn histogram(
+        &self,
+        name: &str,
+        help: &str,
+        buckets: Vec<f64>,
+    ) -> Result<Histogram, MetricsError> {
+        let metric = self.get_or_register(name, MetricKind::Histogram, |name| {
+            Ok(Metric::Histogram(Histogram::with_opts(
+                HistogramOpts::new(name, help).buckets(buckets),
+            )?))
+        })?;
+        match metric {
+            Metric::Histogram(h) => Ok(h),
+            _ => unreachable!("kind is checked by get_or_register; qed"),
+        }
+    }
+
+    /// Register a legacy metrics source, collected on every `gather`
+    pub fn register_legacy<T>(&self, source: Arc<T>)
+    where
+        T: PrometheusMetrics + Send + Sync + ?Sized + 'static,
+    {
+        self.inner
+            .legacy
+            .write()
+            .push(Box::new(move |r| source.prometheus_metrics(r)));
+    }
+
+    /// Collect all registered and legacy metrics
+    pub fn gather(&self) -> Vec<MetricFamily> {
+        self.gather_with(|_| {})
+    }
+
+    /// Collect all registered and legacy metrics, `f` may add further metrics
+    /// to the legacy registry after all legacy sources were collected.
+    /// A legacy metric with the name of a registered one is skipped
+    pub fn gather_with<F: FnOnce(&mut PrometheusRegistry)>(&self, f: F) -> Vec<MetricFamily> {
+        let mut legacy = PrometheusRegistry::new(self.inner.prefix.clone())
+            .expect("prefix is checked in Metrics::new; qed");
+        for collect in self.inner.legacy.read().iter() {
+            collect(&mut legacy);
+        }
+        f(&mut legacy);
+
+        let mut families = self.inner.registry.gather();
+        let registered: HashSet<String> = families.iter().map(|f| f.name().to_owned()).collect();
+        families.extend(legacy.registry().gather().into_iter().filter(|family| {
+            let duplicate = registered.contains(family.name());
+            if duplicate {
+                debug!(target: "stats", "Skipping legacy metric {}, a metric with the same name is registered", family.name());
+            }
+            !duplicate
+        }));
+        families.sort_by(|a, b| a.name().cmp(b.name()));
+        families
+    }
+
+    fn get_or_register<F>(
+        &self,
+        name: &str,
+        kind: MetricKind,
+        create: F,
+    ) -> Result<Metric, MetricsError>
+    where
+        F: FnOnce(&str) -> Result<Metric, MetricsError>,
+    {
+        let name = format!("{}{}", self.inner.prefix, name);
+        let mut metrics = self.inner.metrics.lock();
+        if let Some(metric) = metrics.get(&name) {
+            return if metric.kind() == kind {
+                Ok(metric.clone())
+            } else {
+                Err(MetricsError::KindMismatch {
+                    name,
+                    registered: metric.kind(),
+                    requested: kind,
+                })
+            };
+        }
+
+        let metric = create(&name)?;
+        let collector: Box<dyn prometheus::core::Collector> = match metric {
+            Metric::IntCounter(ref c) => Box::new(c.clone()),
+            Metric::IntGauge(ref g) => Box::new(g.clone()),
+            Metric::Histogram(ref h) => Box::new(h.clone()),
+        };
+        self.inner.registry.register(collector)?;
+        trace!(target: "stats", "Registered {kind} {name}");
+        metrics.insert(name, metric.clone());
+        Ok(metric)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Encoder, TextEncoder};
+    use std::thread;
+
+    fn encode(families: &[MetricFamily]) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(families, &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    struct Legacy;
+
+    impl PrometheusMetrics for Legacy {
+        fn prometheus_metrics(&self, r: &mut PrometheusRegistry) {
+            r.register_gauge("legacy_gauge", "Legacy gauge", 7).unwrap();
+        }
+    }
+
+    #[test]
+    fn same_name_returns_existing_handle() {
+        let metrics = Metrics::new("oe_".into()).unwrap();
+        let a = metrics.int_counter("blocks", "Blocks").unwrap();
+        let b = metrics.int_counter("blocks", "Blocks").unwrap();
+        a.inc();
+        b.inc_by(2);
+        assert_eq!(a.get(), 3);
+    }
+
+    #[test]
+    fn different_kind_is_rejected() {
+        let metrics = Metrics::new("".into()).unwrap();
+        metrics.int_counter("blocks", "Blocks").unwrap();
+        match metrics.int_gauge("blocks", "Blocks") {
+            Err(MetricsError::KindMismatch {
+                registered,
+                requested,
+                ..
+            }) => {
+                assert_eq!(registered, MetricKind::IntCounter);
+                assert_eq!(requested, MetricKind::IntGauge);
+            }
+            _ => panic!("expected kind mismatch"),
+        }
+    }
+
+    #[test]
+    fn legacy_metric_does_not_duplicate_registered_one() {
+        let metrics = Metrics::new("oe_".into()).unwrap();
+        metrics.register_legacy(Arc::new(Legacy));
+        metrics
+            .int_gauge("legacy_gauge", "Registered gauge")
+            .unwrap()
+            .set(3);
+
+        let output = encode(&metrics.gather());
+        assert_eq!(output.matches("# TYPE oe_legacy_gauge gauge").count(), 1);
+        assert!(output.contains("oe_legacy_gauge 3\n"));
+        assert!(!output.contains("oe_legacy_gauge 7\n"));
+    }
+
+    #[test]
+    fn concurrent_registration_and_gather() {
+        let metrics = Metrics::new("oe_".into()).unwrap();
+        metrics.register_legacy(Arc::new(Legacy));
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let metrics = metrics.clone();
+                thread::spawn(move || {
+                    let counter = metrics
+                        .int_counter("sync_blocks_imported", "Imported blocks")
+                        .unwrap();
+                    let gauge = metrics.int_gauge("sync_peers", "Connected peers").unwrap();
+                    let histogram = metrics
+                        .histogram("import_time", "Import time", vec![1.0, 10.0])
+                        .unwrap();
+                    for _ in 0..100 {
+                        counter.inc();
+                        histogram.observe(5.0);
+                    }
+                    gauge.set(25);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let output = encode(&metrics.gather());
+        assert_eq!(
+            output
+                .matches("# TYPE oe_sync_blocks_imported counter")
+                .count(),
+            1
+        );
+        assert!(output.contains("oe_sync_blocks_imported 200\n"));
+        assert!(output.contains("oe_sync_peers 25\n"));
+        assert!(output.contains("oe_import_time_count 200\n"));
+        assert!(output.contains("oe_legacy_gauge 7\n"));
+
+        // gathering again yields the same output
+        assert_eq!(output, encode(&metrics.gather()));
+    }
+}
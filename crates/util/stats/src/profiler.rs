@@ -0,0 +1,723 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Opt-in sampling profiler of registered threads.
+//!
+//! Long running threads register themselves with [`register_current_thread`]. While a
+//! [`Profiler`] runs, its sampler thread interrupts every registered thread once per interval
+//! with `SIGPROF`. The signal handler only records the return addresses of the interrupted
+//! stack, the sampler thread resolves them to function names. The samples of the last window
+//! are reported as folded stacks, the input format of flamegraph tools.
+//!
+//! The handler must be async-signal-safe, the interrupted thread may hold any lock, e.g. the one
+//! of the dynamic loader taken by unwinders. So instead of unwinding it follows the frame pointer
+//! chain of the interrupted context, reading only the thread's stack between the interrupted
+//! stack pointer and the stack end. Functions built without frame pointers leave gaps in the
+//! stacks, build with `-C force-frame-pointers=yes` for complete ones.
+//!
+//! Nothing is sampled while no profiler runs, registering a thread only takes a lock once.
+//! A running profiler costs every registered thread one stack walk per interval and keeps at
+//! most `window / interval` samples per thread. Blocking system calls of a sampled thread may
+//! return early with `EINTR`. Stacks are only recorded on linux on x86_64 and aarch64.
+
+use parking_lot::{const_mutex, Condvar, Mutex, MutexGuard};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    io, mem, ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Deepest stack recorded by a sample, the frames closest to the root are cut off.
+const MAX_DEPTH: usize = 128;
+/// How long the sampler waits for an interrupted thread to record its stack.
+const SAMPLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Profiler configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfilerConfig {
+    /// Time between two samples of a thread.
+    pub interval: Duration,
+    /// Age of the oldest sample reported.
+    pub window: Duration,
+}
+
+impl Default for ProfilerConfig {
+    fn default() -> Self {
+        ProfilerConfig {
+            interval: Duration::from_millis(100),
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+struct RegisteredThread {
+    id: u64,
+    name: String,
+    thread: sys::Thread,
+    /// Highest address of the thread's stack, 0 if unknown.
+    stack_end: usize,
+}
+
+/// Threads to sample. The sampler holds the lock while it interrupts them, so a thread cannot
+/// exit while it is interrupted.
+static THREADS: Mutex<Vec<RegisteredThread>> = const_mutex(Vec::new());
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(0);
+/// Set while a profiler runs, there is only one signal handler to record the stacks.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Removes the thread from the sampled ones when it exits.
+struct Registration(u64);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        THREADS.lock().retain(|thread| thread.id != self.0);
+    }
+}
+
+thread_local! {
+    static REGISTRATION: RefCell<Option<Registration>> = const { RefCell::new(None) };
+}
+
+/// Sample the current thread under `name` whenever a profiler runs, until the thread exits.
+/// Registering the thread again renames it.
+pub fn register_current_thread(name: &str) {
+    let thread = match sys::current_thread() {
+        Some(thread) => thread,
+        None => return,
+    };
+    REGISTRATION.with(|registration| {
+        let mut registration = registration.borrow_mut();
+        let mut threads = THREADS.lock();
+        match *registration {
+            Some(Registration(id)) => {
+                if let Some(registered) = threads.iter_mut().find(|t| t.id == id) {
+                    registered.name = name.to_owned();
+                }
+            }
+            None => {
+                let id = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+                threads.push(RegisteredThread {
+                    id,
+                    name: name.to_owned(),
+                    thread,
+                    stack_end: sys::current_stack_end(),
+                });
+                *registration = Some(Registration(id));
+            }
+        }
+    });
+}
+
+/// Stack recorded by the signal handler of an interrupted thread.
+struct Slot {
+    /// Number of the last sample requested.
+    requested: AtomicU64,
+    /// Number of the last sample recorded.
+    recorded: AtomicU64,
+    /// Highest address of the stack of the interrupted thread.
+    stack_end: AtomicUsize,
+    depth: AtomicUsize,
+    /// The interrupted instruction followed by the return addresses on the stack.
+    frames: [AtomicUsize; MAX_DEPTH],
+}
+
+static SLOT: Slot = Slot {
+    requested: AtomicU64::new(0),
+    recorded: AtomicU64::new(0),
+    stack_end: AtomicUsize::new(0),
+    depth: AtomicUsize::new(0),
+    frames: [const { AtomicUsize::new(0) }; MAX_DEPTH],
+};
+
+extern "C" fn on_sigprof(_: libc::c_int, _: *mut libc::c_void, context: *mut libc::c_void) {
+    let request = SLOT.requested.load(Ordering::Acquire);
+    let stack_end = SLOT.stack_end.load(Ordering::Relaxed);
+    let mut depth = 0;
+    // Safety: `context` is the interrupted context passed by the kernel.
+    if let Some((pc, sp, mut fp)) = unsafe { sys::registers(context) } {
+        SLOT.frames[0].store(pc, Ordering::Relaxed);
+        depth = 1;
+        // a function interrupted while it sets up or tears down its frame has not linked it yet
+        // Safety: `pc` is the interrupted instruction and `sp` the stack in use.
+        if let Some(return_address) = unsafe { sys::unlinked_return_address(pc, sp) } {
+            SLOT.frames[1].store(return_address, Ordering::Relaxed);
+            depth = 2;
+        }
+        // Every frame holds the frame pointer of its caller followed by the return address.
+        // Callers are further up the stack, so the walk ends at the first frame pointer not
+        // above the previous one or outside of the stack in use.
+        let mut low = sp;
+        while depth < MAX_DEPTH
+            && fp >= low
+            && fp % mem::align_of::<usize>() == 0
+            && fp.saturating_add(2 * mem::size_of::<usize>()) <= stack_end
+        {
+            // Safety: `fp` is aligned and within the mapped stack of the interrupted thread.
+            let (caller_fp, return_address) = unsafe {
+                let frame = fp as *const usize;
+                (ptr::read_volatile(frame), ptr::read_volatile(frame.add(1)))
+            };
+            if return_address == 0 {
+                break;
+            }
+            SLOT.frames[depth].store(return_address, Ordering::Relaxed);
+            depth += 1;
+            low = fp + 1;
+            fp = caller_fp;
+        }
+    }
+    SLOT.depth.store(depth, Ordering::Relaxed);
+    SLOT.recorded.store(request, Ordering::Release);
+}
+
+/// Interrupt `thread` and wait until it recorded its stack.
+fn sample(thread: &RegisteredThread) -> Option<Vec<usize>> {
+    let previous = SLOT.requested.load(Ordering::Relaxed);
+    if SLOT.recorded.load(Ordering::Acquire) != previous {
+        // the thread of a timed out sample did not record its stack yet
+        return None;
+    }
+    let request = previous + 1;
+    SLOT.stack_end.store(thread.stack_end, Ordering::Relaxed);
+    SLOT.requested.store(request, Ordering::Release);
+    if !sys::interrupt(&thread.thread) {
+        SLOT.requested.store(previous, Ordering::Relaxed);
+        return None;
+    }
+    let deadline = Instant::now() + SAMPLE_TIMEOUT;
+    while SLOT.recorded.load(Ordering::Acquire) != request {
+        if Instant::now() > deadline {
+            return None;
+        }
+        thread::yield_now();
+    }
+    let depth = SLOT.depth.load(Ordering::Relaxed);
+    Some(
+        SLOT.frames[..depth]
+            .iter()
+            .enumerate()
+            // a return address follows the call, the call belongs to the calling function
+            .map(|(i, frame)| frame.load(Ordering::Relaxed) - usize::from(i > 0))
+            .collect(),
+    )
+}
+
+/// Function names of addresses, cached as the same functions are seen again and again.
+#[derive(Default)]
+struct Resolver {
+    names: HashMap<usize, Vec<String>>,
+}
+
+impl Resolver {
+    /// Names of the function at `address`, the innermost inlined one first. Functions of a
+    /// binary without symbols are named by their offset in the binary, e.g. `openethereum+0x4f2a0`.
+    fn names(&mut self, address: usize) -> &[String] {
+        self.names.entry(address).or_insert_with(|| {
+            // the same name for all addresses of a function, also if it is named by its offset
+            let address = sys::function_start(address).unwrap_or(address);
+            let mut names = Vec::new();
+            backtrace::resolve(address as *mut _, |symbol| {
+                if let Some(name) = symbol.name() {
+                    names.push(format!("{name:#}"));
+                }
+            });
+            if names.is_empty() {
+                names.push(sys::object_offset(address).unwrap_or_else(|| format!("{address:#x}")));
+            }
+            names
+        })
+    }
+
+    /// Folded stack of `thread`, from the root to the innermost frame.
+    fn fold(&mut self, thread: &str, frames: &[usize]) -> String {
+        let mut stack = thread.replace([';', ' '], "_");
+        for frame in frames.iter().rev() {
+            for name in self.names(*frame).iter().rev() {
+                stack.push(';');
+                stack.push_str(name);
+            }
+        }
+        stack
+    }
+}
+
+/// Samples of the window, the same stacks share their text.
+struct Samples {
+    window: Duration,
+    samples: VecDeque<(Instant, Arc<str>)>,
+    stacks: HashSet<Arc<str>>,
+}
+
+impl Samples {
+    fn push(&mut self, time: Instant, stack: String) {
+        let stack = match self.stacks.get(stack.as_str()) {
+            Some(stack) => stack.clone(),
+            None => {
+                let stack: Arc<str> = stack.into();
+                self.stacks.insert(stack.clone());
+                stack
+            }
+        };
+        self.samples.push_back((time, stack));
+    }
+
+    fn prune(&mut self, now: Instant) {
+        let len = self.samples.len();
+        while let Some((time, _)) = self.samples.front() {
+            if now.saturating_duration_since(*time) <= self.window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        if self.samples.len() != len {
+            self.stacks.retain(|stack| Arc::strong_count(stack) > 1);
+        }
+    }
+
+    fn counts(&self) -> BTreeMap<Arc<str>, usize> {
+        let mut counts = BTreeMap::new();
+        for (_, stack) in &self.samples {
+            *counts.entry(stack.clone()).or_insert(0) += 1;
+        }
+        counts
+    }
+}
+
+struct Shared {
+    samples: Mutex<Samples>,
+    stop: Mutex<bool>,
+    stopped: Condvar,
+}
+
+/// Running sampling profiler, stopped when dropped.
+pub struct Profiler {
+    shared: Arc<Shared>,
+    sampler: Option<thread::JoinHandle<()>>,
+    previous_handler: Option<sys::Handler>,
+}
+
+impl Profiler {
+    /// Start sampling the registered threads. Fails if another profiler is running.
+    pub fn start(config: ProfilerConfig) -> io::Result<Profiler> {
+        if RUNNING.swap(true, Ordering::SeqCst) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "a profiler is already running",
+            ));
+        }
+        let mut profiler = Profiler {
+            shared: Arc::new(Shared {
+                samples: Mutex::new(Samples {
+                    window: config.window,
+                    samples: VecDeque::new(),
+                    stacks: HashSet::new(),
+                }),
+                stop: Mutex::new(false),
+                stopped: Condvar::new(),
+            }),
+            sampler: None,
+            previous_handler: None,
+        };
+        profiler.previous_handler = Some(sys::install_handler(on_sigprof)?);
+        let shared = profiler.shared.clone();
+        profiler.sampler = Some(
+            thread::Builder::new()
+                .name("Profiler".into())
+                .spawn(move || Profiler::run(shared, config.interval))?,
+        );
+        Ok(profiler)
+    }
+
+    fn run(shared: Arc<Shared>, interval: Duration) {
+        let mut resolver = Resolver::default();
+        let mut stop = shared.stop.lock();
+        while !*stop {
+            MutexGuard::unlocked(&mut stop, || {
+                let stacks: Vec<(String, Vec<usize>)> = THREADS
+                    .lock()
+                    .iter()
+                    .filter_map(|t| sample(t).map(|frames| (t.name.clone(), frames)))
+                    .collect();
+                let now = Instant::now();
+                let stacks: Vec<String> = stacks
+                    .iter()
+                    .map(|(thread, frames)| resolver.fold(thread, frames))
+                    .collect();
+                let mut samples = shared.samples.lock();
+                for stack in stacks {
+                    samples.push(now, stack);
+                }
+                samples.prune(now);
+            });
+            shared.stopped.wait_for(&mut stop, interval);
+        }
+    }
+
+    fn counts(&self) -> BTreeMap<Arc<str>, usize> {
+        let mut samples = self.shared.samples.lock();
+        samples.prune(Instant::now());
+        samples.counts()
+    }
+
+    /// Samples of the window as folded stacks: one line per stack, the frames from the thread
+    /// name to the innermost function separated by `;`, followed by the number of samples.
+    pub fn folded(&self) -> String {
+        self.counts()
+            .iter()
+            .map(|(stack, count)| format!("{stack} {count}\n"))
+            .collect()
+    }
+
+    /// The `n` functions most often found running by the samples of the window.
+    pub fn top_functions(&self, n: usize) -> Vec<(String, usize)> {
+        let counts = self.counts();
+        let mut functions: HashMap<&str, usize> = HashMap::new();
+        for (stack, count) in &counts {
+            let function = stack.rsplit(';').next().unwrap_or_default();
+            *functions.entry(function).or_insert(0) += count;
+        }
+        let mut functions: Vec<(String, usize)> = functions
+            .into_iter()
+            .map(|(function, count)| (function.to_owned(), count))
+            .collect();
+        functions.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        functions.truncate(n);
+        functions
+    }
+
+    /// Log the `n` functions most often found running, with their share of the samples.
+    pub fn log_top_functions(&self, n: usize) {
+        let total = self.shared.samples.lock().samples.len();
+        info!(target: "profiler", "Top functions of {total} samples:");
+        for (function, count) in self.top_functions(n) {
+            let share = count as f64 * 100.0 / total.max(1) as f64;
+            info!(target: "profiler", "{share:5.1}% {function}");
+        }
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        *self.shared.stop.lock() = true;
+        self.shared.stopped.notify_all();
+        if let Some(sampler) = self.sampler.take() {
+            let _ = sampler.join();
+        }
+        if let Some(handler) = self.previous_handler.take() {
+            sys::restore_handler(handler);
+        }
+        RUNNING.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::{ffi::CStr, io, mem, path::Path, ptr};
+
+    pub struct Thread(libc::pthread_t);
+
+    // Safety: the thread id is only used to send a signal to the thread, from any thread.
+    unsafe impl Send for Thread {}
+
+    pub struct Handler(libc::sigaction);
+
+    pub fn current_thread() -> Option<Thread> {
+        Some(Thread(unsafe { libc::pthread_self() }))
+    }
+
+    pub fn interrupt(thread: &Thread) -> bool {
+        unsafe { libc::pthread_kill(thread.0, libc::SIGPROF) == 0 }
+    }
+
+    /// Highest address of the current thread's stack, 0 if unknown.
+    #[cfg(target_os = "linux")]
+    pub fn current_stack_end() -> usize {
+        unsafe {
+            let mut attr: libc::pthread_attr_t = mem::zeroed();
+            if libc::pthread_getattr_np(libc::pthread_self(), &mut attr) != 0 {
+                return 0;
+            }
+            let mut address = ptr::null_mut();
+            let mut size = 0;
+            let found = libc::pthread_attr_getstack(&attr, &mut address, &mut size) == 0;
+            libc::pthread_attr_destroy(&mut attr);
+            if found {
+                address as usize + size
+            } else {
+                0
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn current_stack_end() -> usize {
+        0
+    }
+
+    /// Instruction, stack and frame pointer of the interrupted context of a signal handler.
+    ///
+    /// # Safety
+    /// `context` must be the `ucontext_t` passed to an `SA_SIGINFO` signal handler.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    pub unsafe fn registers(context: *mut libc::c_void) -> Option<(usize, usize, usize)> {
+        let gregs = &(*(context as *const libc::ucontext_t)).uc_mcontext.gregs;
+        Some((
+            gregs[libc::REG_RIP as usize] as usize,
+            gregs[libc::REG_RSP as usize] as usize,
+            gregs[libc::REG_RBP as usize] as usize,
+        ))
+    }
+
+    /// Instruction, stack and frame pointer of the interrupted context of a signal handler.
+    ///
+    /// # Safety
+    /// `context` must be the `ucontext_t` passed to an `SA_SIGINFO` signal handler.
+    #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+    pub unsafe fn registers(context: *mut libc::c_void) -> Option<(usize, usize, usize)> {
+        let mcontext = &(*(context as *const libc::ucontext_t)).uc_mcontext;
+        Some((
+            mcontext.pc as usize,
+            mcontext.sp as usize,
+            mcontext.regs[29] as usize,
+        ))
+    }
+
+    /// Return address of a function interrupted at `pc` before it linked its frame or after it
+    /// unlinked it again, in its prologue `push rbp; mov rbp, rsp` or at its final `ret`.
+    ///
+    /// # Safety
+    /// `pc` must be the interrupted instruction and `sp` the interrupted stack pointer.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    pub unsafe fn unlinked_return_address(pc: usize, sp: usize) -> Option<usize> {
+        let code = pc as *const u8;
+        let slot = match ptr::read_volatile(code) {
+            // push rbp, ret
+            0x55 | 0xc3 => sp,
+            // mov rbp, rsp after push rbp
+            0x48 if ptr::read_volatile(code.add(1)) == 0x89
+                && ptr::read_volatile(code.add(2)) == 0xe5 =>
+            {
+                sp + mem::size_of::<usize>()
+            }
+            _ => return None,
+        };
+        Some(ptr::read_volatile(slot as *const usize))
+    }
+
+    /// Frames are assumed to be linked on other platforms.
+    ///
+    /// # Safety
+    /// Always safe, nothing is read.
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+    pub unsafe fn unlinked_return_address(_: usize, _: usize) -> Option<usize> {
+        None
+    }
+
+    /// Registers of interrupted contexts are not read on other platforms.
+    ///
+    /// # Safety
+    /// Always safe, the context is not read.
+    #[cfg(not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )))]
+    pub unsafe fn registers(_: *mut libc::c_void) -> Option<(usize, usize, usize)> {
+        None
+    }
+
+    pub fn install_handler(
+        handler: extern "C" fn(libc::c_int, *mut libc::c_void, *mut libc::c_void),
+    ) -> io::Result<Handler> {
+        unsafe {
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = handler as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART | libc::SA_SIGINFO;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = mem::zeroed();
+            if libc::sigaction(libc::SIGPROF, &action, &mut previous) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Handler(previous))
+        }
+    }
+
+    pub fn restore_handler(handler: Handler) {
+        unsafe {
+            libc::sigaction(libc::SIGPROF, &handler.0, ptr::null_mut());
+        }
+    }
+
+    extern "C" {
+        fn _Unwind_FindEnclosingFunction(pc: *mut libc::c_void) -> *mut libc::c_void;
+    }
+
+    /// Start address of the function containing `address`. Not async-signal-safe.
+    pub fn function_start(address: usize) -> Option<usize> {
+        match unsafe { _Unwind_FindEnclosingFunction(address as *mut _) } as usize {
+            0 => None,
+            start => Some(start),
+        }
+    }
+
+    /// `address` as offset in the binary or library it belongs to.
+    pub fn object_offset(address: usize) -> Option<String> {
+        let mut info: libc::Dl_info = unsafe { mem::zeroed() };
+        if unsafe { libc::dladdr(address as *const _, &mut info) } == 0 || info.dli_fname.is_null()
+        {
+            return None;
+        }
+        let path = unsafe { CStr::from_ptr(info.dli_fname) }.to_string_lossy();
+        let object = Path::new(&*path).file_name()?.to_string_lossy();
+        Some(format!(
+            "{}+{:#x}",
+            object,
+            address.checked_sub(info.dli_fbase as usize)?
+        ))
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::io;
+
+    pub struct Thread;
+
+    pub struct Handler;
+
+    pub fn current_thread() -> Option<Thread> {
+        None
+    }
+
+    pub fn interrupt(_: &Thread) -> bool {
+        false
+    }
+
+    pub fn current_stack_end() -> usize {
+        0
+    }
+
+    /// # Safety
+    /// Always safe, the context is not read.
+    pub unsafe fn registers(_: *mut libc::c_void) -> Option<(usize, usize, usize)> {
+        None
+    }
+
+    /// # Safety
+    /// Always safe, nothing is read.
+    pub unsafe fn unlinked_return_address(_: usize, _: usize) -> Option<usize> {
+        None
+    }
+
+    pub fn install_handler(
+        _: extern "C" fn(libc::c_int, *mut libc::c_void, *mut libc::c_void),
+    ) -> io::Result<Handler> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "sampling is only supported on unix",
+        ))
+    }
+
+    pub fn restore_handler(_: Handler) {}
+
+    pub fn function_start(_: usize) -> Option<usize> {
+        None
+    }
+
+    pub fn object_offset(_: usize) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[inline(never)]
+    fn spin_until_stopped(stop: &AtomicBool) -> u64 {
+        let mut x = 0u64;
+        while !stop.load(Ordering::Relaxed) {
+            x = std::hint::black_box(x.wrapping_mul(31).wrapping_add(7));
+        }
+        x
+    }
+
+    #[test]
+    fn samples_hot_function_of_registered_thread() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let spinner = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                register_current_thread("spinner");
+                spin_until_stopped(&stop)
+            })
+        };
+        let profiler = Profiler::start(ProfilerConfig {
+            interval: Duration::from_millis(5),
+            window: Duration::from_secs(60),
+        })
+        .unwrap();
+        assert!(Profiler::start(ProfilerConfig::default()).is_err());
+
+        thread::sleep(Duration::from_millis(500));
+        let folded = profiler.folded();
+        let top = profiler.top_functions(1);
+        drop(profiler);
+        stop.store(true, Ordering::Relaxed);
+        spinner.join().unwrap();
+
+        // named by symbol or by offset, whatever the test binary allows
+        let mut resolver = Resolver::default();
+        let hot_function = resolver
+            .names(spin_until_stopped as *const () as usize)
+            .last()
+            .unwrap()
+            .clone();
+        let handler = resolver
+            .names(on_sigprof as *const () as usize)
+            .last()
+            .unwrap()
+            .clone();
+
+        let (mut total, mut hot) = (0, 0);
+        for line in folded.lines() {
+            let (stack, count) = line.rsplit_once(' ').unwrap();
+            let count: usize = count.parse().unwrap();
+            let mut frames = stack.split(';');
+            assert_eq!(frames.next(), Some("spinner"), "{folded}");
+            let frames: Vec<&str> = frames.collect();
+            assert!(!frames.contains(&handler.as_str()), "{folded}");
+            total += count;
+            if frames.contains(&hot_function.as_str()) {
+                hot += count;
+            }
+        }
+        assert!(total >= 10, "{folded}");
+        assert!(hot * 10 >= total * 9, "{hot_function}: {folded}");
+        // the function found running most often is the hot one or called by it
+        assert!(
+            folded
+                .lines()
+                .any(|line| line.contains(&hot_function) && line.contains(&top[0].0)),
+            "{top:?}"
+        );
+    }
+}
@@ -18,23 +18,153 @@
 
 extern crate parity_bytes as bytes;
 extern crate rlp;
+extern crate semver;
 extern crate target_info;
 
 use bytes::Bytes;
 use rlp::RlpStream;
+use std::{cmp::Ordering, fmt, str::FromStr, sync::OnceLock};
 use target_info::Target;
 
+pub use semver::Version;
+
 mod generated {
     include!(concat!(env!("OUT_DIR"), "/meta.rs"));
 }
 
 #[cfg(feature = "final")]
-const THIS_TRACK: &'static str = "stable";
+const THIS_TRACK: &str = "stable";
 // ^^^ should be reset in Cargo.toml to "stable"
 
 #[cfg(not(feature = "final"))]
-const THIS_TRACK: &str = "unstable";
-// ^^^ This gets used when we're not building a final release; should stay as "unstable".
+const THIS_TRACK: &str = "nightly";
+// ^^^ This gets used when we're not building a final release; should stay as "nightly".
+
+/// Release channel of a build. Tracks are ordered by stability, custom tracks being the least stable.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ReleaseTrack {
+    /// Final releases.
+    Stable,
+    /// Release candidates.
+    Beta,
+    /// Builds of the development branch.
+    Nightly,
+    /// Any other channel, e.g. builds of a private fork.
+    Custom(String),
+}
+
+impl ReleaseTrack {
+    /// Track the software was compiled for.
+    pub fn compiled() -> Self {
+        THIS_TRACK
+            .parse()
+            .expect("compile-time track is a valid track name; qed")
+    }
+
+    /// Check if a node on this track may update to a release on the `other` track.
+    /// Stable only accepts stable releases, beta accepts beta and stable ones and nightly accepts
+    /// anything. A custom track only accepts releases of the same track.
+    pub fn allows_update_to(&self, other: &ReleaseTrack) -> bool {
+        match *self {
+            ReleaseTrack::Stable => *other == ReleaseTrack::Stable,
+            ReleaseTrack::Beta => matches!(*other, ReleaseTrack::Stable | ReleaseTrack::Beta),
+            ReleaseTrack::Nightly => true,
+            ReleaseTrack::Custom(_) => other == self,
+        }
+    }
+
+    fn stability(&self) -> u8 {
+        match *self {
+            ReleaseTrack::Stable => 3,
+            ReleaseTrack::Beta => 2,
+            ReleaseTrack::Nightly => 1,
+            ReleaseTrack::Custom(_) => 0,
+        }
+    }
+}
+
+impl Ord for ReleaseTrack {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (ReleaseTrack::Custom(a), ReleaseTrack::Custom(b)) => a.cmp(b),
+            _ => self.stability().cmp(&other.stability()),
+        }
+    }
+}
+
+impl PartialOrd for ReleaseTrack {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl FromStr for ReleaseTrack {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stable" => Ok(ReleaseTrack::Stable),
+            "beta" => Ok(ReleaseTrack::Beta),
+            // "unstable" is what non-final builds used to be called
+            "nightly" | "unstable" => Ok(ReleaseTrack::Nightly),
+            name if !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') =>
+            {
+                Ok(ReleaseTrack::Custom(name.into()))
+            }
+            name => Err(format!("Invalid release track: {name:?}")),
+        }
+    }
+}
+
+impl fmt::Display for ReleaseTrack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReleaseTrack::Stable => write!(f, "stable"),
+            ReleaseTrack::Beta => write!(f, "beta"),
+            ReleaseTrack::Nightly => write!(f, "nightly"),
+            ReleaseTrack::Custom(ref name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Release track which can be chosen once, before it is first read.
+struct TrackOverride(OnceLock<ReleaseTrack>);
+
+impl TrackOverride {
+    const fn new() -> Self {
+        TrackOverride(OnceLock::new())
+    }
+
+    fn set(&self, track: ReleaseTrack) -> Result<(), ReleaseTrack> {
+        self.0.set(track)
+    }
+
+    fn get(&self) -> &ReleaseTrack {
+        self.0.get_or_init(ReleaseTrack::compiled)
+    }
+}
+
+static RELEASE_TRACK: TrackOverride = TrackOverride::new();
+
+/// Override the compiled release track. Must be called at most once, at startup before the track
+/// is read; otherwise the track is left unchanged and returned back as an error.
+pub fn set_release_track(track: ReleaseTrack) -> Result<(), ReleaseTrack> {
+    RELEASE_TRACK.set(track)
+}
+
+/// Release track of this node.
+pub fn release_track() -> &'static ReleaseTrack {
+    RELEASE_TRACK.get()
+}
+
+/// Get the version of this software.
+pub fn version() -> Version {
+    Version::parse(env!("CARGO_PKG_VERSION"))
+        .expect("Environment variables are known to be valid; qed")
+}
 
 /// Get the platform identifier.
 pub fn platform() -> String {
@@ -45,10 +175,17 @@ pub fn platform() -> String {
 
 /// Get the standard version string for this software (short information for logging).
 pub fn version_short() -> String {
+    client_version(None)
+}
+
+/// Get the version string identifying this node to peers, with the optional node name.
+pub fn client_version(identity: Option<&str>) -> String {
+    let identity = identity.map(|name| format!("{name}/")).unwrap_or_default();
     format!(
-        "OpenEthereum/v{}-{}/{}/rustc{}",
-        env!("CARGO_PKG_VERSION"),
-        THIS_TRACK,
+        "OpenEthereum/{}v{}-{}/{}/rustc{}",
+        identity,
+        version(),
+        release_track(),
         platform(),
         generated::rustc_version()
     )
@@ -57,20 +194,99 @@ pub fn version_short() -> String {
 /// Get the standard version data for this software.
 pub fn version_data() -> Bytes {
     let mut s = RlpStream::new_list(4);
-    let v = (env!("CARGO_PKG_VERSION_MAJOR")
-        .parse::<u32>()
-        .expect("Environment variables are known to be valid; qed")
-        << 16)
-        + (env!("CARGO_PKG_VERSION_MINOR")
-            .parse::<u32>()
-            .expect("Environment variables are known to be valid; qed")
-            << 8)
-        + env!("CARGO_PKG_VERSION_PATCH")
-            .parse::<u32>()
-            .expect("Environment variables are known to be valid; qed");
+    let version = version();
+    let v = ((version.major as u32) << 16) + ((version.minor as u32) << 8) + version.patch as u32;
     s.append(&v);
     s.append(&"OpenEthereum");
     s.append(&generated::rustc_version());
     s.append(&&Target::os()[0..2]);
     s.out().to_vec()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_orders_tracks() {
+        assert_eq!("stable".parse(), Ok(ReleaseTrack::Stable));
+        assert_eq!("beta".parse(), Ok(ReleaseTrack::Beta));
+        assert_eq!("nightly".parse(), Ok(ReleaseTrack::Nightly));
+        assert_eq!("unstable".parse(), Ok(ReleaseTrack::Nightly));
+        assert_eq!(
+            "my-fork".parse(),
+            Ok(ReleaseTrack::Custom("my-fork".into()))
+        );
+        assert!("".parse::<ReleaseTrack>().is_err());
+        assert!("a/b".parse::<ReleaseTrack>().is_err());
+        assert_eq!(ReleaseTrack::Beta.to_string(), "beta");
+        assert_eq!(ReleaseTrack::compiled().to_string(), THIS_TRACK);
+
+        let mut tracks = vec![
+            ReleaseTrack::Beta,
+            ReleaseTrack::Custom("b".into()),
+            ReleaseTrack::Stable,
+            ReleaseTrack::Nightly,
+            ReleaseTrack::Custom("a".into()),
+        ];
+        tracks.sort();
+        assert_eq!(
+            tracks,
+            vec![
+                ReleaseTrack::Custom("a".into()),
+                ReleaseTrack::Custom("b".into()),
+                ReleaseTrack::Nightly,
+                ReleaseTrack::Beta,
+                ReleaseTrack::Stable,
+            ]
+        );
+    }
+
+    #[test]
+    fn track_override_is_one_shot() {
+        let track = TrackOverride::new();
+        assert_eq!(track.set(ReleaseTrack::Beta), Ok(()));
+        assert_eq!(track.set(ReleaseTrack::Stable), Err(ReleaseTrack::Stable));
+        assert_eq!(*track.get(), ReleaseTrack::Beta);
+
+        let read = TrackOverride::new();
+        assert_eq!(*read.get(), ReleaseTrack::compiled());
+        assert_eq!(read.set(ReleaseTrack::Beta), Err(ReleaseTrack::Beta));
+    }
+
+    #[test]
+    fn update_policy() {
+        let custom = ReleaseTrack::Custom("fork".into());
+        let other_custom = ReleaseTrack::Custom("other".into());
+        let tracks = [
+            ReleaseTrack::Stable,
+            ReleaseTrack::Beta,
+            ReleaseTrack::Nightly,
+            custom.clone(),
+            other_custom.clone(),
+        ];
+        let allowed = |from: &ReleaseTrack| -> Vec<bool> {
+            tracks.iter().map(|to| from.allows_update_to(to)).collect()
+        };
+        assert_eq!(
+            allowed(&ReleaseTrack::Stable),
+            [true, false, false, false, false]
+        );
+        assert_eq!(
+            allowed(&ReleaseTrack::Beta),
+            [true, true, false, false, false]
+        );
+        assert_eq!(
+            allowed(&ReleaseTrack::Nightly),
+            [true, true, true, true, true]
+        );
+        assert_eq!(allowed(&custom), [false, false, false, true, false]);
+    }
+
+    #[test]
+    fn client_version_includes_identity_and_track() {
+        let named = client_version(Some("node"));
+        assert!(named.starts_with(&format!("OpenEthereum/node/v{}-", version())));
+        assert!(version_short().contains(&format!("-{}/", release_track())));
+    }
+}
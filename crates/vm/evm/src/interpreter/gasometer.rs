@@ -22,7 +22,7 @@ use super::stack::VecStack;
 use evm;
 use instructions::{self, Instruction, InstructionInfo};
 use interpreter::stack::Stack;
-use vm::{self, Schedule};
+use vm::{self, schedule::EIP2935_HISTORY_SERVE_WINDOW, Schedule};
 
 macro_rules! overflowing {
     ($x: expr) => {{
@@ -324,7 +324,21 @@ impl<Gas: evm::CostType> Gasometer<Gas> {
                 let gas = Gas::from(schedule.exp_gas + schedule.exp_byte_gas * bytes);
                 Request::Gas(gas)
             }
-            instructions::BLOCKHASH => Request::Gas(Gas::from(schedule.blockhash_gas)),
+            instructions::BLOCKHASH => {
+                let number = *stack.peek(0);
+                let current = U256::from(ext.env_info().number);
+                // only lookups within the window of the history contract read its storage
+                let from_history = schedule.eip2935
+                    && number < current
+                    && current - number > U256::from(256)
+                    && current - number <= U256::from(EIP2935_HISTORY_SERVE_WINDOW);
+                let gas = if from_history {
+                    schedule.eip2935_blockhash_gas
+                } else {
+                    schedule.blockhash_gas
+                };
+                Request::Gas(Gas::from(gas))
+            }
             _ => Request::Gas(default_gas),
         };
 
@@ -20,6 +20,8 @@
 mod informant;
 mod gasometer;
 mod memory;
+mod pool;
+mod profiler;
 mod shared_cache;
 mod stack;
 
@@ -41,6 +43,7 @@ pub use self::shared_cache::SharedCache;
 use self::{
     gasometer::Gasometer,
     memory::Memory,
+    profiler::Profiler,
     stack::{Stack, VecStack},
 };
 
@@ -60,6 +63,7 @@ const TWO_POW_64: U256 = U256([0, 0x1, 0, 0]); // 0x1 00000000 00000000
 const TWO_POW_96: U256 = U256([0, 0x100000000, 0, 0]); //0x1 00000000 00000000 00000000
 const TWO_POW_224: U256 = U256([0, 0, 0, 0x100000000]); //0x1 00000000 00000000 00000000 00000000 00000000 00000000 00000000
 const TWO_POW_248: U256 = U256([0, 0, 0, 0x100000000000000]); //0x1 00000000 00000000 00000000 00000000 00000000 00000000 00000000 000000
+const SIGN_BIT: U256 = U256([0, 0, 0, 0x8000000000000000]);
 
 /// Maximum subroutine stack size as specified in
 /// https://eips.ethereum.org/EIPS/eip-2315.
@@ -187,6 +191,8 @@ pub struct Interpreter<Cost: CostType> {
     return_data: ReturnData,
     informant: informant::EvmInformant,
     do_trace: bool,
+    profiler: Option<Profiler<Cost>>,
+    profile_checked: bool,
     done: bool,
     valid_jump_destinations: Option<Arc<BitSet>>,
     valid_subroutine_destinations: Option<Arc<BitSet>>,
@@ -196,11 +202,23 @@ pub struct Interpreter<Cost: CostType> {
     resume_output_range: Option<(U256, U256)>,
     resume_result: Option<InstructionResult<Cost>>,
     last_stack_ret_len: usize,
+    /// `Schedule::constant_time`. Comparisons `LT`, `GT`, `SLT`, `SGT`, `EQ` and `ISZERO` are
+    /// computed without branching on the operands, along with `ADD`, `SUB`, `NOT`, `AND`, `OR`
+    /// and `XOR`, which never do, these are the covered opcodes. `DIV` skips its power of two
+    /// shortcuts, but the division itself still depends on the operands and is not covered.
+    /// Exceptional halts charge all remaining gas to the failing instruction.
+    constant_time: bool,
     _type: PhantomData<Cost>,
 }
 
 impl<Cost: 'static + CostType> vm::Exec for Interpreter<Cost> {
     fn exec(mut self: Box<Self>, ext: &mut dyn vm::Ext) -> vm::ExecTrapResult<GasLeft> {
+        if !self.profile_checked {
+            self.profile_checked = true;
+            if ext.profile_enabled() {
+                self.profiler = Some(Profiler::new());
+            }
+        }
         loop {
             let result = self.step(ext);
             match result {
@@ -220,6 +238,16 @@ impl<Cost: 'static + CostType> vm::Exec for Interpreter<Cost> {
     }
 }
 
+impl<Cost: CostType> Drop for Interpreter<Cost> {
+    fn drop(&mut self) {
+        pool::restore(pool::Buffers {
+            mem: std::mem::take(&mut self.mem),
+            stack: self.stack.take_buffer(),
+            return_stack: std::mem::take(&mut self.return_stack),
+        });
+    }
+}
+
 impl<Cost: 'static + CostType> vm::ResumeCall for Interpreter<Cost> {
     fn resume_call(mut self: Box<Self>, result: MessageCallResult) -> Box<dyn vm::Exec> {
         {
@@ -302,8 +330,8 @@ impl<Cost: CostType> Interpreter<Cost> {
         let gasometer = Cost::from_u256(params.gas)
             .ok()
             .map(|gas| Gasometer::<Cost>::new(gas));
-        let stack = VecStack::with_capacity(schedule.stack_limit, U256::zero());
-        let return_stack = Vec::with_capacity(MAX_SUB_STACK_SIZE);
+        let buffers = pool::checkout(schedule.stack_limit, MAX_SUB_STACK_SIZE);
+        let stack = VecStack::with_buffer(buffers.stack, U256::zero());
 
         Interpreter {
             cache,
@@ -314,16 +342,19 @@ impl<Cost: CostType> Interpreter<Cost> {
             valid_subroutine_destinations,
             gasometer,
             stack,
-            return_stack,
+            return_stack: buffers.return_stack,
             done: false,
             // Overridden in `step_inner` based on
             // the result of `ext.trace_next_instruction`.
             do_trace: true,
-            mem: Vec::new(),
+            profiler: None,
+            profile_checked: false,
+            mem: buffers.mem,
             return_data: ReturnData::empty(),
             last_stack_ret_len: 0,
             resume_output_range: None,
             resume_result: None,
+            constant_time: schedule.constant_time,
             _type: PhantomData,
         }
     }
@@ -349,9 +380,15 @@ impl<Cost: CostType> Interpreter<Cost> {
             self.step_inner(ext)
         };
 
-        if let &InterpreterResult::Done(_) = &result {
+        if let &InterpreterResult::Done(ref value) = &result {
+            if self.constant_time && value.is_err() {
+                self.charge_remaining_gas();
+            }
             self.done = true;
             self.informant.done();
+            if let Some(profiler) = self.profiler.take() {
+                ext.profile_report(&profiler.report());
+            }
         }
         result
     }
@@ -381,16 +418,19 @@ impl<Cost: CostType> Interpreter<Cost> {
                 let instruction = match instruction {
                     Some(i) => i,
                     None => {
-                        return InterpreterResult::Done(Err(vm::Error::BadInstruction {
-                            instruction: opcode,
-                        }))
+                        return self.halt_uncharged(
+                            opcode,
+                            vm::Error::BadInstruction {
+                                instruction: opcode,
+                            },
+                        )
                     }
                 };
 
                 let info = instruction.info();
                 self.last_stack_ret_len = info.ret;
                 if let Err(e) = self.verify_instruction(ext, instruction, info) {
-                    return InterpreterResult::Done(Err(e));
+                    return self.halt_uncharged(opcode, e);
                 };
 
                 // Calculate gas cost
@@ -407,7 +447,7 @@ impl<Cost: CostType> Interpreter<Cost> {
                         self.mem.size(),
                     ) {
                     Ok(t) => t,
-                    Err(e) => return InterpreterResult::Done(Err(e)),
+                    Err(e) => return self.halt_uncharged(opcode, e),
                 };
                 if self.do_trace {
                     ext.trace_prepare_execute(
@@ -427,7 +467,10 @@ impl<Cost: CostType> Interpreter<Cost> {
                     if self.do_trace {
                         ext.trace_failed();
                     }
-                    return InterpreterResult::Done(Err(e));
+                    return self.halt_uncharged(opcode, e);
+                }
+                if let Some(profiler) = self.profiler.as_mut() {
+                    profiler.executed(opcode, requirements.gas_cost);
                 }
                 self.mem.expand(requirements.memory_required_size);
                 self.gasometer
@@ -472,6 +515,9 @@ impl<Cost: CostType> Interpreter<Cost> {
         if let InstructionResult::UnusedGas(ref gas) = result {
             self.gasometer.as_mut().expect(GASOMETER_PROOF).current_gas =
                 self.gasometer.as_mut().expect(GASOMETER_PROOF).current_gas + *gas;
+            if let Some(profiler) = self.profiler.as_mut() {
+                profiler.refunded(*gas);
+            }
         }
 
         if self.do_trace {
@@ -566,6 +612,29 @@ impl<Cost: CostType> Interpreter<Cost> {
         InterpreterResult::Continue
     }
 
+    // Instructions failing after being charged are accounted as executed. In constant time mode
+    // account the ones failing before the same way, so the profile does not tell them apart.
+    #[cold]
+    fn halt_uncharged(&mut self, opcode: u8, e: vm::Error) -> InterpreterResult {
+        if self.constant_time {
+            if let Some(profiler) = self.profiler.as_mut() {
+                profiler.executed(opcode, Cost::from(0));
+            }
+        }
+        InterpreterResult::Done(Err(e))
+    }
+
+    // An exceptional halt consumes all gas of the frame, charge it to the failing instruction.
+    fn charge_remaining_gas(&mut self) {
+        if let Some(gasometer) = self.gasometer.as_mut() {
+            let remaining = gasometer.current_gas;
+            gasometer.current_gas = Cost::from(0);
+            if let Some(profiler) = self.profiler.as_mut() {
+                profiler.halted(remaining);
+            }
+        }
+    }
+
     fn verify_instruction(
         &self,
         ext: &dyn vm::Ext,
@@ -1131,7 +1200,11 @@ impl<Cost: CostType> Interpreter<Cost> {
             }
             instructions::BLOCKHASH => {
                 let block_number = self.stack.pop_back();
-                let block_hash = ext.blockhash(&block_number);
+                let block_hash = if ext.schedule().eip2935 {
+                    ext.blockhash_extended(&block_number)
+                } else {
+                    ext.blockhash(&block_number)
+                };
                 self.stack.push(block_hash.into_uint());
             }
             instructions::COINBASE => {
@@ -1219,6 +1292,12 @@ impl<Cost: CostType> Interpreter<Cost> {
                 let b = self.stack.pop_back();
                 self.stack.push(a.overflowing_sub(b).0);
             }
+            instructions::DIV if self.constant_time => {
+                let a = self.stack.pop_back();
+                let b = self.stack.pop_back();
+                self.stack
+                    .push(if !b.is_zero() { a / b } else { U256::zero() });
+            }
             instructions::DIV => {
                 let a = self.stack.pop_back();
                 let b = self.stack.pop_back();
@@ -1284,6 +1363,35 @@ impl<Cost: CostType> Interpreter<Cost> {
                 let a = self.stack.pop_back();
                 self.stack.push(!a);
             }
+            instructions::LT if self.constant_time => {
+                let a = self.stack.pop_back();
+                let b = self.stack.pop_back();
+                self.stack.push(ct_lt(&a, &b));
+            }
+            instructions::GT if self.constant_time => {
+                let a = self.stack.pop_back();
+                let b = self.stack.pop_back();
+                self.stack.push(ct_lt(&b, &a));
+            }
+            instructions::SLT if self.constant_time => {
+                let a = self.stack.pop_back();
+                let b = self.stack.pop_back();
+                self.stack.push(ct_lt(&(a ^ SIGN_BIT), &(b ^ SIGN_BIT)));
+            }
+            instructions::SGT if self.constant_time => {
+                let a = self.stack.pop_back();
+                let b = self.stack.pop_back();
+                self.stack.push(ct_lt(&(b ^ SIGN_BIT), &(a ^ SIGN_BIT)));
+            }
+            instructions::EQ if self.constant_time => {
+                let a = self.stack.pop_back();
+                let b = self.stack.pop_back();
+                self.stack.push(ct_eq(&a, &b));
+            }
+            instructions::ISZERO if self.constant_time => {
+                let a = self.stack.pop_back();
+                self.stack.push(ct_eq(&a, &U256::zero()));
+            }
             instructions::LT => {
                 let a = self.stack.pop_back();
                 let b = self.stack.pop_back();
@@ -1504,6 +1612,22 @@ impl<Cost: CostType> Interpreter<Cost> {
     }
 }
 
+// `a < b` as 0 or 1, from the borrow of the subtraction instead of comparing limb by limb.
+#[inline]
+fn ct_lt(a: &U256, b: &U256) -> U256 {
+    U256::from(a.overflowing_sub(*b).1 as u64)
+}
+
+// `a == b` as 0 or 1, looking at all limbs.
+#[inline]
+fn ct_eq(a: &U256, b: &U256) -> U256 {
+    let diff =
+        a.0.iter()
+            .zip(b.0.iter())
+            .fold(0, |acc, (x, y)| acc | (x ^ y));
+    U256::from((diff == 0) as u64)
+}
+
 fn get_and_reset_sign(value: U256) -> (U256, bool) {
     let U256(arr) = value;
     let sign = arr[3].leading_zeros() == 0;
@@ -0,0 +1,178 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Buffers of finished interpreters, reused by the interpreters created next on the same
+//! thread. Nested calls create an interpreter per frame, the pool saves allocating the
+//! stacks and memory again for each of them.
+
+use ethereum_types::U256;
+use std::{cell::RefCell, mem};
+
+/// Maximum number of buffer sets kept per thread.
+const MAX_POOLED_BUFFERS: usize = 64;
+/// Memory of a larger capacity is freed instead of being kept in the pool.
+const MAX_POOLED_MEMORY: usize = 1024 * 1024;
+/// Maximum size of all buffers kept per thread. Enough for the stacks of `MAX_POOLED_BUFFERS`
+/// frames with some memory each, far less than `MAX_POOLED_BUFFERS` times `MAX_POOLED_MEMORY`.
+const MAX_POOLED_BYTES: usize = 4 * 1024 * 1024;
+
+/// Memory, stack and return stack of an interpreter.
+pub struct Buffers {
+    pub mem: Vec<u8>,
+    pub stack: Vec<U256>,
+    pub return_stack: Vec<usize>,
+}
+
+impl Buffers {
+    // Bytes allocated for the buffers.
+    fn size(&self) -> usize {
+        self.mem.capacity()
+            + self.stack.capacity() * mem::size_of::<U256>()
+            + self.return_stack.capacity() * mem::size_of::<usize>()
+    }
+}
+
+struct Pool {
+    buffers: Vec<Buffers>,
+    // Sum of the sizes of `buffers`
+    bytes: usize,
+}
+
+thread_local! {
+    static POOL: RefCell<Pool> = const {
+        RefCell::new(Pool {
+            buffers: Vec::new(),
+            bytes: 0,
+        })
+    };
+}
+
+/// Take empty buffers from the pool of the thread, or allocate new ones if it is empty.
+/// Stacks have room for at least `stack_limit` and `return_stack_limit` items.
+pub fn checkout(stack_limit: usize, return_stack_limit: usize) -> Buffers {
+    let mut buffers = POOL
+        .with(|pool| {
+            let mut pool = pool.borrow_mut();
+            let buffers = pool.buffers.pop()?;
+            pool.bytes -= buffers.size();
+            Some(buffers)
+        })
+        .unwrap_or_else(|| Buffers {
+            mem: Vec::new(),
+            stack: Vec::new(),
+            return_stack: Vec::new(),
+        });
+    // a frame must never observe the data of a previous one, memory is expanded with zeroes
+    buffers.mem.clear();
+    buffers.stack.clear();
+    buffers.return_stack.clear();
+    buffers.stack.reserve(stack_limit);
+    buffers.return_stack.reserve(return_stack_limit);
+    buffers
+}
+
+/// Return buffers to the pool of the thread. They are freed if the pool is full, by count
+/// or by size.
+pub fn restore(mut buffers: Buffers) {
+    if buffers.mem.capacity() > MAX_POOLED_MEMORY {
+        buffers.mem = Vec::new();
+    }
+    buffers.mem.clear();
+    buffers.stack.clear();
+    buffers.return_stack.clear();
+    POOL.with(|pool| {
+        let mut pool = pool.borrow_mut();
+        let size = buffers.size();
+        if pool.buffers.len() < MAX_POOLED_BUFFERS && pool.bytes + size <= MAX_POOLED_BYTES {
+            pool.bytes += size;
+            pool.buffers.push(buffers);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_restored_buffers_empty() {
+        let mut buffers = checkout(1024, 1023);
+        buffers.mem.extend_from_slice(&[0xff; 64]);
+        buffers.stack.push(!U256::zero());
+        buffers.return_stack.push(1);
+        let mem = buffers.mem.as_ptr();
+        restore(buffers);
+
+        let buffers = checkout(1024, 1023);
+        assert_eq!(buffers.mem.as_ptr(), mem);
+        assert!(buffers.mem.is_empty());
+        assert!(buffers.stack.is_empty());
+        assert!(buffers.stack.capacity() >= 1024);
+        assert!(buffers.return_stack.is_empty());
+        assert!(buffers.return_stack.capacity() >= 1023);
+    }
+
+    #[test]
+    fn pool_is_bounded() {
+        let all: Vec<_> = (0..MAX_POOLED_BUFFERS + 10)
+            .map(|_| checkout(16, 16))
+            .collect();
+        for buffers in all {
+            restore(buffers);
+        }
+        assert_eq!(
+            POOL.with(|pool| pool.borrow().buffers.len()),
+            MAX_POOLED_BUFFERS
+        );
+
+        let mut buffers = checkout(16, 16);
+        buffers.mem.resize(MAX_POOLED_MEMORY + 1, 0);
+        restore(buffers);
+        let buffers = POOL.with(|pool| pool.borrow_mut().buffers.pop()).unwrap();
+        assert_eq!(buffers.mem.capacity(), 0);
+    }
+
+    #[test]
+    fn pool_size_is_bounded() {
+        let all: Vec<_> = (0..MAX_POOLED_BUFFERS)
+            .map(|_| {
+                let mut buffers = checkout(1024, 1023);
+                buffers.mem.resize(MAX_POOLED_MEMORY, 0);
+                buffers
+            })
+            .collect();
+        for buffers in all {
+            restore(buffers);
+        }
+        let (count, bytes) = POOL.with(|pool| {
+            let pool = pool.borrow();
+            (pool.buffers.len(), pool.bytes)
+        });
+        assert!(count < MAX_POOLED_BUFFERS);
+        assert!(bytes <= MAX_POOLED_BYTES);
+        assert_eq!(
+            bytes,
+            POOL.with(|pool| pool.borrow().buffers.iter().map(Buffers::size).sum())
+        );
+
+        // checked out buffers are no longer counted
+        let buffers = checkout(1024, 1023);
+        assert_eq!(
+            POOL.with(|pool| pool.borrow().bytes),
+            bytes - buffers.size()
+        );
+    }
+}
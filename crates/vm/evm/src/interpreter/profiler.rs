@@ -0,0 +1,67 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Opcode execution counters of a single call frame.
+
+use evm::CostType;
+use vm::OpProfile;
+
+/// Per-opcode execution count and gas, indexed by opcode.
+pub struct Profiler<Cost: CostType> {
+    ops: Box<[(u64, Cost)]>,
+    last: u8,
+}
+
+impl<Cost: CostType> Profiler<Cost> {
+    pub fn new() -> Self {
+        Profiler {
+            ops: vec![(0, Cost::from(0)); 256].into_boxed_slice(),
+            last: 0,
+        }
+    }
+
+    /// Account an executed opcode.
+    #[inline(always)]
+    pub fn executed(&mut self, opcode: u8, gas: Cost) {
+        let op = &mut self.ops[opcode as usize];
+        op.0 += 1;
+        op.1 = op.1 + gas;
+        self.last = opcode;
+    }
+
+    /// Give back gas charged for the last executed opcode, e.g. gas left by a callee.
+    #[inline(always)]
+    pub fn refunded(&mut self, gas: Cost) {
+        let op = &mut self.ops[self.last as usize];
+        op.1 = op.1 - gas;
+    }
+
+    /// Charge the gas left on an exceptional halt to the last executed opcode.
+    pub fn halted(&mut self, gas: Cost) {
+        let op = &mut self.ops[self.last as usize];
+        op.1 = op.1 + gas;
+    }
+
+    pub fn report(&self) -> Vec<OpProfile> {
+        self.ops
+            .iter()
+            .map(|&(count, gas)| OpProfile {
+                count,
+                gas: gas.as_u256(),
+            })
+            .collect()
+    }
+}
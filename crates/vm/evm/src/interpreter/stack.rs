@@ -43,12 +43,19 @@ pub struct VecStack<S> {
 }
 
 impl<S: Copy> VecStack<S> {
-    pub fn with_capacity(capacity: usize, zero: S) -> Self {
+    /// Empty stack on top of a buffer, which is cleared.
+    pub fn with_buffer(mut stack: Vec<S>, zero: S) -> Self {
+        stack.clear();
         VecStack {
-            stack: Vec::with_capacity(capacity),
+            stack,
             logs: [zero; instructions::MAX_NO_OF_TOPICS],
         }
     }
+
+    /// Take the buffer of the stack, leaving it empty without capacity.
+    pub fn take_buffer(&mut self) -> Vec<S> {
+        std::mem::take(&mut self.stack)
+    }
 }
 
 impl<S: fmt::Display> Stack<S> for VecStack<S> {
@@ -14,7 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::interpreter::MAX_SUB_STACK_SIZE;
+use super::interpreter::{Interpreter, InterpreterResult, SharedCache, MAX_SUB_STACK_SIZE};
 use ethereum_types::{Address, H256, U256};
 use factory::Factory;
 use hex_literal::hex;
@@ -337,6 +337,80 @@ fn test_blockhash(factory: super::Factory) {
     assert_eq!(ext.store.get(&H256::default()).unwrap(), &blockhash);
 }
 
+evm_test! {test_blockhash_history_disabled: test_blockhash_history_disabled_int}
+fn test_blockhash_history_disabled(factory: super::Factory) {
+    let address = Address::from_str("0f572e5295c57f15886f9b263e2f6d2d6c7b5ec6").unwrap();
+    let code = "600040600055".from_hex().unwrap();
+    let blockhash =
+        H256::from_str("123400000000000000000000cd1722f2947def4cf144679da39c4c32bdc35681").unwrap();
+
+    let mut params = ActionParams::default();
+    params.address = address;
+    params.gas = U256::from(100_000);
+    params.code = Some(Arc::new(code));
+    let mut ext = FakeExt::new();
+    ext.info.number = 300;
+    ext.blockhash_history.insert(U256::zero(), blockhash);
+
+    let gas_left = {
+        let vm = factory.create(params, ext.schedule(), ext.depth());
+        test_finalize(vm.exec(&mut ext).ok().unwrap()).unwrap()
+    };
+
+    assert_eq!(gas_left, U256::from(94_974));
+    assert_eq!(ext.store.get(&H256::default()).unwrap(), &H256::zero());
+}
+
+evm_test! {test_blockhash_history: test_blockhash_history_int}
+fn test_blockhash_history(factory: super::Factory) {
+    let address = Address::from_str("0f572e5295c57f15886f9b263e2f6d2d6c7b5ec6").unwrap();
+    let code = "600040600055".from_hex().unwrap();
+    let blockhash =
+        H256::from_str("123400000000000000000000cd1722f2947def4cf144679da39c4c32bdc35681").unwrap();
+
+    let mut params = ActionParams::default();
+    params.address = address;
+    params.gas = U256::from(100_000);
+    params.code = Some(Arc::new(code));
+    let mut ext = FakeExt::new();
+    ext.schedule.eip2935 = true;
+    ext.schedule.eip2935_blockhash_gas = 2_500;
+    ext.info.number = 300;
+    ext.blockhash_history.insert(U256::zero(), blockhash);
+
+    let gas_left = {
+        let vm = factory.create(params, ext.schedule(), ext.depth());
+        test_finalize(vm.exec(&mut ext).ok().unwrap()).unwrap()
+    };
+
+    assert_eq!(gas_left, U256::from(77_494));
+    assert_eq!(ext.store.get(&H256::default()).unwrap(), &blockhash);
+}
+
+evm_test! {test_blockhash_history_out_of_window: test_blockhash_history_out_of_window_int}
+fn test_blockhash_history_out_of_window(factory: super::Factory) {
+    let address = Address::from_str("0f572e5295c57f15886f9b263e2f6d2d6c7b5ec6").unwrap();
+    let code = "600040600055".from_hex().unwrap();
+
+    let mut params = ActionParams::default();
+    params.address = address;
+    params.gas = U256::from(100_000);
+    params.code = Some(Arc::new(code));
+    let mut ext = FakeExt::new();
+    ext.schedule.eip2935 = true;
+    ext.schedule.eip2935_blockhash_gas = 2_500;
+    ext.info.number = 8_192;
+
+    let gas_left = {
+        let vm = factory.create(params, ext.schedule(), ext.depth());
+        test_finalize(vm.exec(&mut ext).ok().unwrap()).unwrap()
+    };
+
+    // block 0 is out of the history window, charged as a regular BLOCKHASH
+    assert_eq!(gas_left, U256::from(94_974));
+    assert_eq!(ext.store.get(&H256::default()).unwrap(), &H256::zero());
+}
+
 evm_test! {test_calldataload: test_calldataload_int}
 fn test_calldataload(factory: super::Factory) {
     let address = Address::from_str("0f572e5295c57f15886f9b263e2f6d2d6c7b5ec6").unwrap();
@@ -1778,6 +1852,218 @@ fn push_two_pop_one_constantinople_test(
     assert_store(&ext, 0, result);
 }
 
+evm_test! {test_profile_opcodes: test_profile_opcodes_int}
+fn test_profile_opcodes(factory: super::Factory) {
+    // PUSH1 ff PUSH1 00 MSTORE PUSH1 00 MLOAD PUSH1 00 SSTORE STOP
+    let code = "60ff600052600051600055600000".from_hex().unwrap();
+    let run = |profiling: bool| {
+        let mut params = ActionParams::default();
+        params.gas = U256::from(100_000);
+        params.code = Some(Arc::new(code.clone()));
+        let mut ext = FakeExt::new();
+        ext.profiling = profiling;
+        let gas_left = {
+            let vm = factory.create(params, ext.schedule(), ext.depth());
+            test_finalize(vm.exec(&mut ext).ok().unwrap()).unwrap()
+        };
+        (gas_left, ext.profile_reports)
+    };
+
+    let (_, reports) = run(false);
+    assert!(reports.is_empty());
+
+    let (gas_left, reports) = run(true);
+    assert_eq!(reports.len(), 1);
+    let profile = &reports[0];
+    assert_eq!(profile.len(), 256);
+
+    let counts: Vec<(usize, u64)> = profile
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| op.count > 0)
+        .map(|(opcode, op)| (opcode, op.count))
+        .collect();
+    assert_eq!(
+        counts,
+        vec![(0x00, 1), (0x51, 1), (0x52, 1), (0x55, 1), (0x60, 5)]
+    );
+    assert_eq!(profile[0x60].gas, U256::from(15));
+    // MSTORE pays for the memory expansion, MLOAD does not.
+    assert_eq!(profile[0x52].gas, U256::from(6));
+    assert_eq!(profile[0x51].gas, U256::from(3));
+    assert_eq!(profile[0x00].gas, U256::zero());
+
+    let total = profile.iter().fold(U256::zero(), |acc, op| acc + op.gas);
+    assert_eq!(total, U256::from(100_000) - gas_left);
+}
+
+// Compare `a` and `b` with LT, GT, SLT, SGT, EQ and DIV, then `a` with ISZERO, storing every
+// result plus one so each SSTORE costs the same.
+fn comparison_code(a: U256, b: U256) -> Vec<u8> {
+    let word = |v: U256| {
+        let mut w = [0u8; 32];
+        v.to_big_endian(&mut w);
+        w
+    };
+    let mut code = Vec::new();
+    for (slot, op) in [0x10u8, 0x11, 0x12, 0x13, 0x14, 0x04].iter().enumerate() {
+        code.push(0x7f);
+        code.extend_from_slice(&word(b));
+        code.push(0x7f);
+        code.extend_from_slice(&word(a));
+        code.extend_from_slice(&[*op, 0x60, 0x01, 0x01, 0x60, slot as u8, 0x55]);
+    }
+    code.push(0x7f);
+    code.extend_from_slice(&word(a));
+    code.extend_from_slice(&[0x15, 0x60, 0x01, 0x01, 0x60, 0x06, 0x55]);
+    code
+}
+
+evm_test! {test_constant_time_comparisons: test_constant_time_comparisons_int}
+fn test_constant_time_comparisons(factory: super::Factory) {
+    let run = |code: Vec<u8>, constant_time: bool| {
+        let mut params = ActionParams::default();
+        params.gas = U256::from(300_000);
+        params.code = Some(Arc::new(code));
+        let mut ext = FakeExt::new();
+        ext.schedule.constant_time = constant_time;
+        ext.profiling = true;
+        let gas_left = {
+            let vm = factory.create(params, ext.schedule(), ext.depth());
+            test_finalize(vm.exec(&mut ext).ok().unwrap()).unwrap()
+        };
+        let counts: Vec<u64> = ext.profile_reports[0].iter().map(|op| op.count).collect();
+        (gas_left, ext.store, counts)
+    };
+
+    let max = !U256::zero();
+    let operands = [
+        (U256::zero(), U256::zero()),
+        (U256::from(5), U256::from(3)),
+        (U256::from(3), U256::from(5)),
+        (U256::one() << 255, U256::one()),
+        (max - 1, U256::from(32)),
+        (U256::from(0x1234) << 64, U256::one() << 64),
+        (max - 1, max),
+        (max, U256::one() << 255),
+    ];
+
+    let (gas_left, _, counts) = run(comparison_code(operands[0].0, operands[0].1), true);
+    for &(a, b) in &operands {
+        let (ct_gas_left, ct_store, ct_counts) = run(comparison_code(a, b), true);
+        let (normal_gas_left, normal_store, _) = run(comparison_code(a, b), false);
+        assert_eq!(ct_store, normal_store);
+        assert_eq!(ct_gas_left, gas_left);
+        assert_eq!(normal_gas_left, gas_left);
+        assert_eq!(ct_counts, counts);
+    }
+
+    // SLT and SGT of -1 and 1.
+    let (_, store, _) = run(comparison_code(max, U256::one()), true);
+    assert_eq!(store[&H256::from_low_u64_be(2)], H256::from_low_u64_be(2));
+    assert_eq!(store[&H256::from_low_u64_be(3)], H256::from_low_u64_be(1));
+}
+
+evm_test! {test_constant_time_exceptional_halt: test_constant_time_exceptional_halt_int}
+fn test_constant_time_exceptional_halt(factory: super::Factory) {
+    // PUSH32 offset MLOAD POP PUSH1 00 JUMP, the jump destination is invalid.
+    let run = |offset: U256, constant_time: bool| {
+        let mut code = vec![0x7f];
+        let mut word = [0u8; 32];
+        offset.to_big_endian(&mut word);
+        code.extend_from_slice(&word);
+        code.extend_from_slice(&[0x51, 0x50, 0x60, 0x00, 0x56]);
+
+        let mut params = ActionParams::default();
+        params.gas = U256::from(100_000);
+        params.code = Some(Arc::new(code));
+        let mut ext = FakeExt::new();
+        ext.schedule.constant_time = constant_time;
+        ext.profiling = true;
+        let err = {
+            let vm = factory.create(params, ext.schedule(), ext.depth());
+            test_finalize(vm.exec(&mut ext).ok().unwrap())
+                .err()
+                .unwrap()
+        };
+        let profile = ext.profile_reports.remove(0);
+        let total = profile.iter().fold(U256::zero(), |acc, op| acc + op.gas);
+        (err, total, profile[0x51].count, profile[0x56].count)
+    };
+
+    // A small offset fails on the jump after it was charged, a huge one runs out of gas on the
+    // memory expansion before MLOAD is charged.
+    let huge = U256::one() << 128;
+    let (err, total, mloads, jumps) = run(U256::zero(), true);
+    assert_eq!(err, vm::Error::BadJumpDestination { destination: 0 });
+    assert_eq!((total, mloads, jumps), (U256::from(100_000), 1, 1));
+    let (err, total, mloads, jumps) = run(huge, true);
+    assert_eq!(err, vm::Error::OutOfGas);
+    assert_eq!((total, mloads, jumps), (U256::from(100_000), 1, 0));
+
+    // Without the flag only the gas of the executed instructions is accounted.
+    let (err, total, mloads, _) = run(U256::zero(), false);
+    assert_eq!(err, vm::Error::BadJumpDestination { destination: 0 });
+    assert_eq!((total, mloads), (U256::from(22), 1));
+    let (err, total, mloads, _) = run(huge, false);
+    assert_eq!(err, vm::Error::OutOfGas);
+    assert_eq!((total, mloads), (U256::from(3), 0));
+}
+
+// Writes all ones to the first two words of memory.
+const WRITE_MEMORY: &str = "7fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff6000527fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff60205200";
+// Stores the first two words of memory, untouched before.
+const READ_MEMORY: &str = "600051600055602051600155";
+const ZERO_WORD: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+fn frame_params(code: &str) -> ActionParams {
+    let mut params = ActionParams::default();
+    params.gas = U256::from(100_000);
+    params.code = Some(Arc::new(code.from_hex().unwrap()));
+    params
+}
+
+fn assert_reads_zeroed_memory(factory: &Factory) {
+    let mut ext = FakeExt::new();
+    let vm = factory.create(frame_params(READ_MEMORY), ext.schedule(), ext.depth());
+    test_finalize(vm.exec(&mut ext).ok().unwrap()).unwrap();
+    assert_store(&ext, 0, ZERO_WORD);
+    assert_store(&ext, 1, ZERO_WORD);
+}
+
+evm_test! {test_frame_cannot_read_memory_of_sibling: test_frame_cannot_read_memory_of_sibling_int}
+fn test_frame_cannot_read_memory_of_sibling(factory: super::Factory) {
+    let mut ext = FakeExt::new();
+    let sibling = factory.create(frame_params(WRITE_MEMORY), ext.schedule(), ext.depth());
+    test_finalize(sibling.exec(&mut ext).ok().unwrap()).unwrap();
+
+    // the buffers of the finished sibling are reused
+    assert_reads_zeroed_memory(&factory);
+}
+
+evm_test! {test_frame_cannot_read_memory_of_parent: test_frame_cannot_read_memory_of_parent_int}
+fn test_frame_cannot_read_memory_of_parent(factory: super::Factory) {
+    let mut ext = FakeExt::new();
+    let mut parent = Interpreter::<usize>::new(
+        frame_params(WRITE_MEMORY),
+        Arc::new(SharedCache::default()),
+        ext.schedule(),
+        ext.depth(),
+    );
+    for _ in 0..6 {
+        assert!(matches!(parent.step(&mut ext), InterpreterResult::Continue));
+    }
+
+    // the child runs while the parent holds its memory, and after the parent finished
+    assert_reads_zeroed_memory(&factory);
+    assert!(matches!(
+        parent.step(&mut ext),
+        InterpreterResult::Done(Ok(_))
+    ));
+    drop(parent);
+    assert_reads_zeroed_memory(&factory);
+}
+
 fn assert_set_contains<T: Debug + Eq + PartialEq + Hash>(set: &HashSet<T>, val: &T) {
     let contains = set.contains(val);
     if !contains {
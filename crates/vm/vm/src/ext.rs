@@ -21,6 +21,7 @@ use call_type::CallType;
 use env_info::EnvInfo;
 use error::{Result, TrapKind};
 use ethereum_types::{Address, H256, U256};
+use profile::OpProfile;
 use return_data::ReturnData;
 use schedule::Schedule;
 use std::sync::Arc;
@@ -89,6 +90,10 @@ pub trait Ext {
     /// Returns the hash of one of the 256 most recent complete blocks.
     fn blockhash(&mut self, number: &U256) -> H256;
 
+    /// Returns the hash of a complete block. Blocks older than the 256 most recent ones
+    /// are looked up in the EIP-2935 history contract, zero if not available.
+    fn blockhash_extended(&mut self, number: &U256) -> H256;
+
     /// Creates new contract.
     ///
     /// Returns gas_left and contract address if contract creation was successful.
@@ -185,6 +190,15 @@ pub trait Ext {
     /// Trace the finalised execution of a single instruction.
     fn trace_executed(&mut self, _gas_used: U256, _stack_push: &[U256], _mem: &[u8]) {}
 
+    /// Decide if opcode execution counters should be collected. Queried once per execution.
+    fn profile_enabled(&self) -> bool {
+        false
+    }
+
+    /// Receive the opcode execution counters of a finished execution, indexed by opcode.
+    /// Only called if `profile_enabled` returned true.
+    fn profile_report(&mut self, _profile: &[OpProfile]) {}
+
     /// Check if running in static context.
     fn is_static(&self) -> bool;
 
@@ -29,6 +29,7 @@ mod call_type;
 mod env_info;
 mod error;
 mod ext;
+mod profile;
 mod return_data;
 pub mod schedule;
 
@@ -40,6 +41,7 @@ pub use call_type::CallType;
 pub use env_info::{EnvInfo, LastHashes};
 pub use error::{Error, ExecTrapError, ExecTrapResult, Result, TrapError, TrapKind, TrapResult};
 pub use ext::{ContractCreateResult, CreateContractAddress, Ext, MessageCallResult};
+pub use profile::OpProfile;
 pub use return_data::{GasLeft, ReturnData};
 pub use schedule::{CleanDustMode, Schedule, WasmCosts};
 
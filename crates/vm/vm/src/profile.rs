@@ -0,0 +1,29 @@
+// Copyright 2015-2020 Parity Technologies (UK) Ltd.
+// This file is part of OpenEthereum.
+
+// OpenEthereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// OpenEthereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with OpenEthereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Opcode-level execution statistics
+
+use ethereum_types::U256;
+
+/// Execution statistics of a single opcode within one call frame.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OpProfile {
+    /// Number of times the opcode was executed.
+    pub count: u64,
+    /// Gas charged for the opcode in total, including memory expansion.
+    /// For calls and creates the gas left by the callee is not included.
+    pub gas: U256,
+}
@@ -34,6 +34,8 @@ pub const EIP3529_MAX_REFUND_QUOTIENT: usize = 5;
 /// Reduced SSTORE refund as by EIP-3529
 pub const EIP3529_SSTORE_CLEARS_SCHEDULE: usize =
     EIP2929_SSTORE_RESET_GAS + EIP2930_ACCESS_LIST_STORAGE_KEY_COST;
+/// Number of block hashes kept by the EIP-2935 history contract
+pub const EIP2935_HISTORY_SERVE_WINDOW: u64 = 8191;
 
 /// Definition of the cost schedule and other parameterisations for the EVM.
 #[derive(Debug)]
@@ -173,6 +175,15 @@ pub struct Schedule {
     pub max_refund_quotient: usize,
     // Enable EIP-3541 rule
     pub eip3541: bool,
+    /// Enable EIP-2935 BLOCKHASH for blocks older than 256, served from the history contract
+    pub eip2935: bool,
+    /// BLOCKHASH gas cost for blocks older than 256, if EIP-2935 is enabled
+    pub eip2935_blockhash_gas: usize,
+    /// Execute arithmetic and comparisons without operand dependent fast paths and charge all
+    /// remaining gas to the failing instruction on exceptional halts. Not a consensus rule, meant
+    /// for private chains running secret dependent code, set with the `constantTimeEvm` spec
+    /// param. See the `evm` interpreter for the covered opcodes.
+    pub constant_time: bool,
 }
 
 /// Wasm cost table
@@ -326,6 +337,9 @@ impl Schedule {
             eip3198: false,
             max_refund_quotient: MAX_REFUND_QUOTIENT,
             eip3541: false,
+            eip2935: false,
+            eip2935_blockhash_gas: EIP2929_COLD_SLOAD_COST,
+            constant_time: false,
         }
     }
 
@@ -469,6 +483,9 @@ impl Schedule {
             eip3198: false,
             max_refund_quotient: MAX_REFUND_QUOTIENT,
             eip3541: false,
+            eip2935: false,
+            eip2935_blockhash_gas: EIP2929_COLD_SLOAD_COST,
+            constant_time: false,
         }
     }
 
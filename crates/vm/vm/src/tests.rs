@@ -31,6 +31,7 @@ use EnvInfo;
 use Ext;
 use GasLeft;
 use MessageCallResult;
+use OpProfile;
 use Result;
 use ReturnData;
 use Schedule;
@@ -70,12 +71,16 @@ pub struct FakeExt {
     pub sstore_clears: i128,
     pub depth: usize,
     pub blockhashes: HashMap<U256, H256>,
+    /// Block hashes served by the EIP-2935 history contract.
+    pub blockhash_history: HashMap<U256, H256>,
     pub codes: HashMap<Address, Arc<Bytes>>,
     pub logs: Vec<FakeLogEntry>,
     pub info: EnvInfo,
     pub schedule: Schedule,
     pub balances: HashMap<Address, U256>,
     pub tracing: bool,
+    pub profiling: bool,
+    pub profile_reports: Vec<Vec<OpProfile>>,
     pub is_static: bool,
     pub access_list: AccessList,
 
@@ -204,6 +209,14 @@ impl Ext for FakeExt {
         *self.blockhashes.get(number).unwrap_or(&H256::default())
     }
 
+    fn blockhash_extended(&mut self, number: &U256) -> H256 {
+        *self
+            .blockhashes
+            .get(number)
+            .or_else(|| self.blockhash_history.get(number))
+            .unwrap_or(&H256::default())
+    }
+
     fn create(
         &mut self,
         gas: &U256,
@@ -316,6 +329,14 @@ impl Ext for FakeExt {
         self.tracing
     }
 
+    fn profile_enabled(&self) -> bool {
+        self.profiling
+    }
+
+    fn profile_report(&mut self, profile: &[OpProfile]) {
+        self.profile_reports.push(profile.to_vec());
+    }
+
     fn al_is_enabled(&self) -> bool {
         self.access_list.is_enabled()
     }
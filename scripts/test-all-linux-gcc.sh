@@ -6,4 +6,5 @@ set -u # treat unset variables as error
 echo "_____ Switch to CGG _____"
 cd ..
 export CC=$(which gcc-12) && export CXX=$(which g++-12) &&\
-time cargo test --all
+time cargo test --all &&\
+time cargo test -p fetch --features tls